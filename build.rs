@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use the vendored protoc unless one is configured, so building needs no system protoc
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_prost_build::configure().compile_protos(
+        &["proto/cubo/v1/cubo.proto", "proto/runtime/v1/api.proto"],
+        &["proto"],
+    )?;
+    Ok(())
+}
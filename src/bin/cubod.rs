@@ -0,0 +1,50 @@
+use clap::Parser;
+use std::path::PathBuf;
+
+use cubo::container::runtime::RuntimeConfig;
+use cubo::daemon::cri::{CriConfig, DEFAULT_PAUSE_IMAGE};
+use cubo::daemon::{default_grpc_socket_path, default_socket_path, Daemon};
+
+#[derive(Parser)]
+#[command(name = "cubod", version, about = "The cubo daemon: serves the cubo HTTP and gRPC APIs (and optionally the Kubernetes CRI) over unix sockets.")]
+struct DaemonCli {
+    /// Socket to listen on (default: $XDG_RUNTIME_DIR/cubo.sock)
+    #[arg(long, env = "CUBO_SOCKET", value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Socket for the gRPC API (default: $XDG_RUNTIME_DIR/cubo-grpc.sock)
+    #[arg(long, env = "CUBO_GRPC_SOCKET", value_name = "PATH")]
+    grpc_socket: Option<PathBuf>,
+
+    /// Also serve the Kubernetes CRI on this socket, for use by a kubelet
+    #[arg(long, env = "CUBO_CRI_SOCKET", value_name = "PATH")]
+    cri_socket: Option<PathBuf>,
+
+    /// Image of the infra container holding each pod sandbox's namespaces
+    #[arg(long, env = "CUBO_PAUSE_IMAGE", value_name = "IMAGE", default_value = DEFAULT_PAUSE_IMAGE)]
+    pause_image: String,
+
+    #[arg(long, env = "CUBO_ROOT", value_name = "PATH")]
+    root_dir: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt::init();
+
+    let cli = DaemonCli::parse();
+
+    if let Some(ref root) = cli.root_dir {
+        std::env::set_var("CUBO_ROOT", root);
+    }
+
+    let socket = cli.socket.unwrap_or_else(default_socket_path);
+    let grpc_socket = cli.grpc_socket.unwrap_or_else(default_grpc_socket_path);
+    let mut daemon = Daemon::new(RuntimeConfig::from_env())?;
+    if let Some(socket) = cli.cri_socket {
+        daemon = daemon.with_cri(CriConfig { socket, pause_image: cli.pause_image });
+    }
+    daemon.serve(&socket, Some(&grpc_socket)).await?;
+
+    Ok(())
+}
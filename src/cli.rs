@@ -1,4 +1,14 @@
 use clap::{Parser, Subcommand};
+use clap_complete::{ArgValueCandidates, Shell};
+use crate::commands::completion::{container_candidates, image_candidates};
+use crate::container::build_progress::ProgressMode;
+use crate::container::image_store::ImageFilter;
+use crate::container::sched::{IoPriority, SchedPolicy};
+use crate::container::{IpcMode, LogDriverKind, NetworkMode, RestartPolicy, UsernsMode};
+use crate::container::systemd::{SdNotifyMode, ServiceType};
+use crate::container::sbom::SbomFormat;
+use crate::container::stats::StatsFormat;
+use crate::container::audit::AuditOperation;
 
 
 #[derive(Parser)]
@@ -10,12 +20,44 @@ pub struct Cli {
 
     #[arg(long, global = true, env = "CUBO_ROOT", value_name = "PATH")]
     pub root_dir: Option<String>,
+
+    /// Configuration file (default: ~/.config/cubo/config.toml)
+    #[arg(long, global = true, env = "CUBO_CONFIG", value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Backend that runs new containers: native, runc, crun or the path to another OCI runtime
+    #[arg(long, global = true, env = "CUBO_EXECUTOR", value_name = "RUNTIME")]
+    pub executor: Option<String>,
+
+    /// Storage of the rootfs of new containers: dir, overlayfs or btrfs
+    #[arg(long, global = true, env = "CUBO_SNAPSHOTTER", value_name = "KIND")]
+    pub snapshotter: Option<String>,
+
+    /// How the dir snapshotter fills new rootfs trees: auto, reflink, hardlink or copy
+    #[arg(long, global = true, env = "CUBO_PROVISION", value_name = "MODE")]
+    pub provision: Option<String>,
+
+    /// Never contact a registry: images missing from the store are an error (pull policy "never")
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// When images are fetched from a registry: missing, always or never
+    #[arg(long, global = true, env = "CUBO_PULL_POLICY", value_name = "POLICY", conflicts_with = "offline")]
+    pub pull_policy: Option<String>,
+
+    /// Send the command to a running cubod instead of acting locally
+    #[arg(long, global = true)]
+    pub remote: bool,
+
+    /// Daemon socket used with --remote (default: $XDG_RUNTIME_DIR/cubo.sock)
+    #[arg(long, global = true, env = "CUBO_SOCKET", value_name = "PATH")]
+    pub socket: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Runs the container from a specified blueprint file.
-    Run(RunArgs),
+    Run(Box<RunArgs>),
     /// Build a blueprint from a Cubofile.
     Build(BuildArgs),
     /// List running containers
@@ -24,6 +66,12 @@ pub enum Commands {
     Blueprint(BlueprintArgs),
     /// Stop a running container
     Stop(StopArgs),
+    /// Update resource limits of containers
+    Update(UpdateArgs),
+    /// Checkpoint a running container with CRIU
+    Checkpoint(CheckpointArgs),
+    /// Restore a checkpointed container
+    Restore(RestoreArgs),
     /// Remove containers
     Rm(RmArgs),
     // Remove blueprints
@@ -32,11 +80,59 @@ pub enum Commands {
     Pull(PullArgs),
     /// Fetch the logs of the container
     Logs(LogsArgs),
+    /// Manage user-defined networks
+    Network(NetworkArgs),
+    /// Manage named volumes
+    Volume(VolumeArgs),
+    /// Run the services of a cubo-compose.toml together
+    Compose(ComposeArgs),
+    /// Generate files that integrate containers with other tools
+    Generate(GenerateArgs),
+    /// Write an OCI runtime bundle config.json for a container
+    ExportBundle(ExportBundleArgs),
+    /// Manage cubo itself
+    System(SystemArgs),
+    /// Sign and verify images
+    Image(ImageArgs),
+    /// List images
+    Images(ImageLsArgs),
+    /// Give an image another name
+    Tag(ImageTagArgs),
+    /// Check that the host can run containers and tell how to fix what's missing
+    Doctor,
+    /// Print the shell script that sets up completion of cubo commands
+    Completion(CompletionArgs),
+    /// Generate man pages
+    Man(ManArgs),
+    /// Show the audit log of operations that changed containers and images
+    Audit(AuditArgs),
+    /// Show the configuration and state of containers as JSON
+    Inspect(InspectArgs),
+    /// Manage containers
+    Container(ContainerArgs),
+    /// Remove exited containers older than the configured cleanup.exited_ttl
+    Gc,
+    /// Mount a busybox toolkit into a running container and run a shell with it
+    Debug(DebugArgs),
+    /// Run a host binary in namespaces of a running container
+    Enter(EnterArgs),
+    /// Run an image with source directories mounted over it, restarting it when they change
+    Dev(DevArgs),
+    /// Block until containers stop and exit with the exit code of the last one
+    Wait(WaitArgs),
+    /// Show the resource usage of containers, or export their recorded history
+    Stats(StatsArgs),
+    /// List the host ports a running container's ports are published on
+    Port(PortArgs),
+    /// Start a container and restart it under its restart policy (run by cubo run --restart)
+    #[command(hide = true)]
+    Supervise(SuperviseArgs),
 }
 
 #[derive(Debug, Parser)]
 pub struct RunArgs {
     /// Blueprint name or ID
+    #[arg(add = ArgValueCandidates::new(image_candidates))]
     pub blueprint: String,
     /// Command to run inside the container
     pub command: Option<Vec<String>>,
@@ -49,18 +145,173 @@ pub struct RunArgs {
     /// Bind mount a volume (host->container)
     #[arg(short,long)]
     pub volume: Vec<String>,
-    /// Publish ports (host->container)
+    /// Publish ports (host->container, or a container port alone for a random host port)
     #[arg(short, long)]
     pub publish : Vec<String>,
+    /// Publish every port the image EXPOSEs to a random host port
+    #[arg(short = 'P', long)]
+    pub publish_all: bool,
     /// Environment variables
     #[arg(short, long)]
     pub env: Vec<String>,
     /// Working directory
     #[arg(short, long)]
     pub workdir: Option<String>,
+    /// Hostname inside the container (default: the short container ID)
+    #[arg(long)]
+    pub hostname: Option<String>,
+    /// Domain name inside the container
+    #[arg(long)]
+    pub domainname: Option<String>,
+    /// User to run the command as (format: <user>[:<group>], names or numeric ids)
+    #[arg(short, long)]
+    pub user: Option<String>,
+    /// Program to exec with the command as arguments, instead of /bin/sh -c (drops the image's CMD)
+    #[arg(long, value_name = "PROGRAM")]
+    pub entrypoint: Option<String>,
+    /// Mount the container's root filesystem read-only
+    #[arg(long)]
+    pub read_only: bool,
+    /// Mount an empty tmpfs at a path inside the container, with options after a colon
+    /// (size=64m,mode=755,exec)
+    #[arg(long, value_name = "PATH[:OPTIONS]")]
+    pub tmpfs: Vec<String>,
+    /// Limit the number of processes in the container (-1 for unlimited)
+    #[arg(long, allow_hyphen_values = true)]
+    pub pids_limit: Option<i64>,
+    /// Memory limit (e.g. 512m, 1g)
+    #[arg(short, long)]
+    pub memory: Option<String>,
+    /// Memory plus swap limit, at least --memory (-1 for unlimited swap)
+    #[arg(long, allow_hyphen_values = true)]
+    pub memory_swap: Option<String>,
+    /// Memory the kernel reclaims from the container last, under memory pressure
+    #[arg(long)]
+    pub memory_reservation: Option<String>,
+    /// Adjust the OOM killer score of the container (-1000 to 1000)
+    #[arg(long, allow_hyphen_values = true, value_parser = clap::value_parser!(i32).range(-1000..=1000))]
+    pub oom_score_adj: Option<i32>,
+    /// Nice value of the container processes (-20 to 19, lower runs first)
+    #[arg(long, allow_hyphen_values = true, value_parser = clap::value_parser!(i32).range(-20..=19))]
+    pub nice: Option<i32>,
+    /// CPU scheduling policy (other, batch, idle, fifo or rr)
+    #[arg(long)]
+    pub sched_policy: Option<SchedPolicy>,
+    /// Priority of the fifo or rr scheduling policy (1 to 99, default 1)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=99))]
+    pub rt_priority: Option<u32>,
+    /// CPU time in microseconds a fifo or rr process may use without blocking
+    #[arg(long)]
+    pub rt_runtime: Option<u64>,
+    /// I/O scheduling class and level (realtime[:0-7], best-effort[:0-7] or idle)
+    #[arg(long, value_name = "CLASS[:LEVEL]")]
+    pub ionice: Option<IoPriority>,
+    /// Limit read rate from a device (format: <device>:<rate>, e.g. /dev/sda:1mb)
+    #[arg(long)]
+    pub device_read_bps: Vec<String>,
+    /// Limit write rate to a device (format: <device>:<rate>, e.g. /dev/sda:1mb)
+    #[arg(long)]
+    pub device_write_bps: Vec<String>,
+    /// Relative block I/O weight (10-1000)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(10..=1000))]
+    pub blkio_weight: Option<u16>,
+    /// CPUs the container may run on (e.g. 0-3,6)
+    #[arg(long, value_name = "CPUS")]
+    pub cpuset_cpus: Option<String>,
+    /// NUMA memory nodes the container may allocate from (e.g. 0,1)
+    #[arg(long, value_name = "MEMS")]
+    pub cpuset_mems: Option<String>,
+    /// Ulimit options (format: <name>=<soft>[:<hard>], e.g. nofile=65535:65535)
+    #[arg(long)]
+    pub ulimit: Vec<String>,
+    /// Shift a clock in a new time namespace (format: <monotonic|boottime>=<offset>[s|m|h|d], e.g. boottime=-1h)
+    #[arg(long, value_name = "CLOCK=OFFSET")]
+    pub time_offset: Vec<String>,
+    /// Namespaced kernel parameters (format: <key>=<value>, e.g. net.ipv4.ip_forward=1)
+    #[arg(long)]
+    pub sysctl: Vec<String>,
+    /// Security options (label=disable, label=user:U, label=role:R, label=type:T, label=level:L,
+    /// apparmor=PROFILE, mask=PATH[:PATH...], unmask=PATH[:PATH...]|ALL, landlock=on|off)
+    #[arg(long)]
+    pub security_opt: Vec<String>,
+    /// Network to connect the container to (bridge, host, none or a network name; default: bridge)
+    #[arg(long)]
+    pub network: Option<NetworkMode>,
+    /// Static IPv4 address on a user-defined network
+    #[arg(long)]
+    pub ip: Option<String>,
+    /// Static MAC address on a user-defined network
+    #[arg(long)]
+    pub mac_address: Option<String>,
+    /// Custom DNS server
+    #[arg(long)]
+    pub dns: Vec<String>,
+    /// Custom DNS search domain
+    #[arg(long)]
+    pub dns_search: Vec<String>,
+    /// Add a custom host-to-IP mapping (format: <host>:<ip>)
+    #[arg(long)]
+    pub add_host: Vec<String>,
+    /// IPC namespace to use (private, host)
+    #[arg(long, default_value = "private")]
+    pub ipc: IpcMode,
+    /// User namespace to use (private, host, keep-id)
+    #[arg(long, default_value = "private")]
+    pub userns: UsernsMode,
+    /// Signal to stop the container (default: the image's STOPSIGNAL, or SIGTERM)
+    #[arg(long)]
+    pub stop_signal: Option<String>,
+    /// Run an init inside the container that forwards signals and reaps processes
+    #[arg(long)]
+    pub init: bool,
+    /// Log driver for the container output (json-file, journald, cri, none; default: json-file)
+    #[arg(long)]
+    pub log_driver: Option<LogDriverKind>,
+    /// Log driver options (format: key=value[,key=value], e.g. max-size=10m,max-file=3)
+    #[arg(long)]
+    pub log_opt: Vec<String>,
+    /// Command run with /bin/sh -c inside the container to check that it is healthy
+    #[arg(long)]
+    pub health_cmd: Option<String>,
+    /// Seconds between two health checks
+    #[arg(long, default_value_t = 30, value_name = "SECONDS")]
+    pub health_interval: u64,
+    /// Seconds a health check may run before it counts as failed
+    #[arg(long, default_value_t = 30, value_name = "SECONDS")]
+    pub health_timeout: u64,
+    /// Consecutive failed health checks after which the container is unhealthy
+    #[arg(long, default_value_t = 3)]
+    pub health_retries: u32,
+    /// Return once the health check passes; fail if the container becomes unhealthy
+    #[arg(long)]
+    pub wait_healthy: bool,
+    /// Who reports readiness to systemd (cubo, container, ignore)
+    #[arg(long, default_value = "cubo")]
+    pub sdnotify: SdNotifyMode,
+    /// Storage options of the rootfs (format: size=10G, the quota on what the container writes)
+    #[arg(long)]
+    pub storage_opt: Vec<String>,
+    /// Check the image against the trust policy before running it
+    #[arg(long)]
+    pub verify: bool,
+    /// Write the container ID to a file, which must not exist yet
+    #[arg(long, value_name = "PATH")]
+    pub cidfile: Option<String>,
+    /// Write the pid of the container's process to a file
+    #[arg(long, value_name = "PATH")]
+    pub pidfile: Option<String>,
+    /// Restart the container when it exits (no, always, unless-stopped, on-failure[:N])
+    #[arg(long, default_value = "no")]
+    pub restart: RestartPolicy,
+    /// Take the blueprint argument as a build context, build it and run the image
+    #[arg(long)]
+    pub build: bool,
+    /// Build argument of --build (format: KEY=VALUE)
+    #[arg(long, value_name = "KEY=VALUE", requires = "build")]
+    pub build_arg: Vec<String>,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 pub struct BuildArgs {
     /// Path to build context
     pub path: String,
@@ -72,6 +323,30 @@ pub struct BuildArgs {
     /// Do not use cache when building the image
     #[arg(long)]
     pub no_cache: bool,
+    /// Variable of RUN steps (format: KEY=VALUE), not kept in the image
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub build_arg: Vec<String>,
+    /// Label of the image (format: KEY=VALUE), set over the labels of the Cubofile
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub label: Vec<String>,
+    /// Build again whenever a file of the context changes, until interrupted
+    #[arg(long)]
+    pub watch: bool,
+    /// Recreate this container from the image after every build that changed it
+    #[arg(long, value_name = "NAME", requires = "watch", add = ArgValueCandidates::new(container_candidates))]
+    pub restart_container: Option<String>,
+    /// Progress output: plain, or json for one BuildKit-style status object per line
+    #[arg(long, default_value = "plain")]
+    pub progress: ProgressMode,
+    /// Kill a RUN step running longer than this (e.g. 90s, 10m), failing the build
+    #[arg(long, value_name = "DURATION")]
+    pub step_timeout: Option<String>,
+    /// Memory limit of each RUN step (e.g. 512m, 2g)
+    #[arg(long, value_name = "SIZE")]
+    pub step_memory: Option<String>,
+    /// CPU limit of each RUN step, in cores
+    #[arg(long, value_name = "CPUS")]
+    pub step_cpus: Option<f32>,
 }
 
 #[derive(Debug, Parser)]
@@ -79,6 +354,16 @@ pub struct PsArgs {
     /// Show all containers (inluding stopped)
     #[arg(short, long)]
     pub all: bool,
+    /// Refresh the table every SECONDS (default 2) until interrupted, highlighting changes
+    #[arg(
+        short,
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "2",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub watch: Option<u64>,
 }
 
 #[derive(Debug, Parser)]
@@ -91,15 +376,50 @@ pub struct BlueprintArgs {
 #[derive(Debug, Parser)]
 pub struct StopArgs {
     /// Container name or IDs
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
     pub containers: Vec<String>,
     /// Force stop running containers
     #[arg(short, long)]
     pub force: bool,
 }
 
+#[derive(Debug, Parser)]
+pub struct UpdateArgs {
+    /// Container names or IDs
+    #[arg(required = true, add = ArgValueCandidates::new(container_candidates))]
+    pub containers: Vec<String>,
+    /// Memory limit (e.g. 512m, 1g)
+    #[arg(long)]
+    pub memory: Option<String>,
+    /// Number of CPUs (e.g. 1.5)
+    #[arg(long)]
+    pub cpus: Option<f32>,
+    /// Maximum number of processes (-1 for unlimited)
+    #[arg(long, allow_hyphen_values = true)]
+    pub pids_limit: Option<i64>,
+}
+
+#[derive(Debug, Parser)]
+pub struct CheckpointArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+    /// Keep the container running after the checkpoint
+    #[arg(long)]
+    pub leave_running: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct RestoreArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct RmArgs {
     /// Container names or IDs
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
     pub containers: Vec<String>,
     /// Force remove running containers
     #[arg(short, long)]
@@ -117,13 +437,23 @@ pub struct RmbArgs {
 
 #[derive(Debug, Parser)]
 pub struct PullArgs {
-    /// Image ref (alpine:latest, ubuntu:22.04)
-    pub image: String,
+    /// Image refs (alpine:latest, ubuntu:22.04)
+    #[arg(required_unless_present = "file")]
+    pub images: Vec<String>,
+
+    /// Also pull the references listed in this file, one per line ("-" for stdin)
+    #[arg(short, long, value_name = "PATH")]
+    pub file: Option<String>,
+
+    /// How many images are pulled at once
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
 }
 
 #[derive(Debug, Parser)]
 pub struct LogsArgs {
     /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
     pub container: String,
     
     /// Follow log output (like tail -f)
@@ -137,51 +467,953 @@ pub struct LogsArgs {
     /// Show timestamps
     #[arg(short, long)]
     pub timestamps: bool,
+
+    /// Only show lines since a timestamp (RFC 3339) or a duration ago (e.g. 10m)
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Only show lines up to a timestamp (RFC 3339) or a duration ago (e.g. 10m)
+    #[arg(long)]
+    pub until: Option<String>,
+
+    /// Show which stream (stdout or stderr) each line came from
+    #[arg(long)]
+    pub details: bool,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serial_test::serial;
+#[derive(Debug, Parser)]
+pub struct NetworkArgs {
+    #[command(subcommand)]
+    pub command: NetworkCommands,
+}
 
-    #[test]
-    #[serial]
-    fn test_root_dir_from_env_in_cli() {
-        std::env::set_var("CUBO_ROOT", "/var/lib/cubo-test");
+#[derive(Debug, Subcommand)]
+pub enum NetworkCommands {
+    /// Create a bridge network
+    Create(NetworkCreateArgs),
+    /// List networks
+    Ls,
+    /// Remove one or more networks
+    Rm(NetworkRmArgs),
+    /// Show details and connected containers of networks
+    Inspect(NetworkInspectArgs),
+    /// Connect a container to a network
+    Connect(NetworkConnectArgs),
+    /// Disconnect a container from a network
+    Disconnect(NetworkDisconnectArgs),
+}
 
-        let cli: Cli = Cli::parse_from(["cubo", "ps"]);
-        assert_eq!(cli.root_dir, Some("/var/lib/cubo-test".to_string()));
-        std::env::remove_var("CUBO_ROOT");
-    }
+#[derive(Debug, Parser)]
+pub struct NetworkCreateArgs {
+    /// Network name
+    pub name: String,
+    /// Subnet in CIDR format (picked from 10.89.0.0/16 if not specified)
+    #[arg(long)]
+    pub subnet: Option<String>,
+    /// Gateway address (first address of the subnet if not specified)
+    #[arg(long)]
+    pub gateway: Option<String>,
+}
 
-    #[test]
-    #[serial]
-    fn test_root_dir_flag_overrides_env() {
-        std::env::set_var("CUBO_ROOT", "/env/path");
-        let cli: Cli = Cli::parse_from(["cubo", "--root-dir", "/flag/path", "ps"]);
-        assert_eq!(cli.root_dir, Some("/flag/path".to_string()));
-        std::env::remove_var("CUBO_ROOT");
-    }
+#[derive(Debug, Parser)]
+pub struct NetworkRmArgs {
+    /// Network names
+    #[arg(required = true)]
+    pub networks: Vec<String>,
+}
 
-    #[test]
-    #[serial]
-    fn test_root_dir_not_set() {
-        std::env::remove_var("CUBO_ROOT");
-        let cli = Cli::parse_from(["cubo", "ps"]);
-        assert_eq!(cli.root_dir, None);
-    }
+#[derive(Debug, Parser)]
+pub struct NetworkInspectArgs {
+    /// Network names
+    #[arg(required = true)]
+    pub networks: Vec<String>,
+}
 
-    // Run command tests
-    #[test]
-    #[serial]
-    fn test_run_command_basic() {
-        std::env::remove_var("CUBO_ROOT");
-        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+#[derive(Debug, Parser)]
+pub struct NetworkConnectArgs {
+    /// Network name
+    pub network: String,
+    /// Container ID or name
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+    /// Static IPv4 address on the network
+    #[arg(long)]
+    pub ip: Option<String>,
+    /// Static MAC address on the network
+    #[arg(long)]
+    pub mac_address: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct NetworkDisconnectArgs {
+    /// Network name
+    pub network: String,
+    /// Container ID or name
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct VolumeArgs {
+    #[command(subcommand)]
+    pub command: VolumeCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VolumeCommands {
+    /// Create a volume
+    Create(VolumeCreateArgs),
+    /// List volumes
+    Ls,
+    /// Remove one or more volumes
+    Rm(VolumeRmArgs),
+    /// Show details of volumes
+    Inspect(VolumeInspectArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct VolumeCreateArgs {
+    /// Volume name
+    pub name: String,
+    /// Volume driver: local, or a plugin provided by a cubo-volume-<driver> executable
+    #[arg(short, long, default_value = "local")]
+    pub driver: String,
+    /// Driver option as KEY=VALUE (repeatable)
+    #[arg(short = 'o', long = "opt", value_name = "KEY=VALUE")]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct VolumeRmArgs {
+    /// Volume names
+    #[arg(required = true)]
+    pub volumes: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct VolumeInspectArgs {
+    /// Volume names
+    #[arg(required = true)]
+    pub volumes: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ComposeArgs {
+    /// Compose file (default: ./cubo-compose.toml)
+    #[arg(short, long, value_name = "FILE")]
+    pub file: Option<String>,
+    /// Project name (default: `name` in the file, or the name of its directory)
+    #[arg(short, long)]
+    pub project_name: Option<String>,
+    #[command(subcommand)]
+    pub command: ComposeCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ComposeCommands {
+    /// Create the project's networks and start its services in dependency order
+    Up(ComposeUpArgs),
+    /// Stop and remove the project's containers and networks
+    Down(ComposeDownArgs),
+    /// Fetch the logs of the services, prefixed with the service name
+    Logs(ComposeLogsArgs),
+    /// List the project's containers
+    Ps(ComposePsArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct ComposeUpArgs {
+    /// Return once the services are started instead of following their logs
+    #[arg(short, long)]
+    pub detach: bool,
+    /// Rebuild the images of services with a build context
+    #[arg(long)]
+    pub build: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ComposeDownArgs {
+    /// Seconds to wait for each container to stop before killing it
+    #[arg(short, long)]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ComposeLogsArgs {
+    /// Services to show (all if none given)
+    pub services: Vec<String>,
+    /// Follow log output until the services exit
+    #[arg(short, long)]
+    pub follow: bool,
+    /// Number of lines to show from the end of each service's logs
+    #[arg(short = 'n', long)]
+    pub tail: Option<usize>,
+    /// Show timestamps
+    #[arg(short, long)]
+    pub timestamps: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ComposePsArgs {
+    /// Show all containers (including stopped)
+    #[arg(short, long)]
+    pub all: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ExportBundleArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+    /// Bundle directory to write config.json to
+    pub dir: String,
+    /// Overwrite an existing config.json
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct GenerateArgs {
+    #[command(subcommand)]
+    pub command: GenerateCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GenerateCommands {
+    /// Generate a systemd service unit that runs a container
+    Systemd(GenerateSystemdArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct GenerateSystemdArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+    /// Service type of the unit (notify or forking)
+    #[arg(long = "type", default_value = "notify")]
+    pub service_type: ServiceType,
+    /// Restart policy to map instead of the container's (no, always, unless-stopped, on-failure[:N])
+    #[arg(long)]
+    pub restart_policy: Option<RestartPolicy>,
+    /// Seconds systemd waits before restarting the service
+    #[arg(long, value_name = "SECONDS")]
+    pub restart_sec: Option<u64>,
+    /// Write the unit to container-<name>.service in the current directory instead of stdout
+    #[arg(long)]
+    pub files: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct SystemArgs {
+    #[command(subcommand)]
+    pub command: SystemCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SystemCommands {
+    /// Show the disk space used by images, containers and volumes
+    Df(SystemDfArgs),
+    /// Show the kernel, cgroup, user namespace and storage setup of the host
+    Info,
+}
+
+#[derive(Debug, Parser)]
+pub struct SystemDfArgs {
+    /// Also list the space used by each image, container and volume
+    #[arg(short, long)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct AuditArgs {
+    /// Only show records since a timestamp (RFC 3339) or a duration ago (e.g. 24h)
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Only show records up to a timestamp (RFC 3339) or a duration ago (e.g. 24h)
+    #[arg(long)]
+    pub until: Option<String>,
+    /// Only show one operation (create, start, stop, remove, pull, build)
+    #[arg(long)]
+    pub operation: Option<AuditOperation>,
+    /// Only show records of a container (ID prefix or name)
+    #[arg(long, add = ArgValueCandidates::new(container_candidates))]
+    pub container: Option<String>,
+    /// Only show records of an image reference
+    #[arg(long, add = ArgValueCandidates::new(image_candidates))]
+    pub image: Option<String>,
+    /// Only show records of a user (name or uid)
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Only show failed operations
+    #[arg(long)]
+    pub failed: bool,
+    /// Print the records as JSON lines
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct DebugArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+    /// Statically linked busybox to mount (default: ROOT/debug/busybox, else busybox from PATH)
+    #[arg(long, value_name = "PATH")]
+    pub toolkit: Option<String>,
+    /// Command to run instead of sh, with the toolkit's applets in PATH
+    #[arg(last = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct EnterArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+    /// Join the mount namespace
+    #[arg(short, long)]
+    pub mount: bool,
+    /// Join the network namespace
+    #[arg(short, long)]
+    pub net: bool,
+    /// Join the PID namespace
+    #[arg(short, long)]
+    pub pid: bool,
+    /// Join the UTS namespace
+    #[arg(short, long)]
+    pub uts: bool,
+    /// Join the IPC namespace
+    #[arg(short, long)]
+    pub ipc: bool,
+    /// Join the user namespace
+    #[arg(short = 'U', long)]
+    pub user: bool,
+    /// Join the cgroup namespace
+    #[arg(short = 'C', long)]
+    pub cgroup: bool,
+    /// Host binary and its arguments; joins every namespace when no flag picks some
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct WaitArgs {
+    /// Container names or IDs
+    #[arg(required = true, add = ArgValueCandidates::new(container_candidates))]
+    pub containers: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct PortArgs {
+    /// Container name or ID
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub container: String,
+
+    /// Only show this container port, e.g. 80 or 53/udp
+    pub port: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct StatsArgs {
+    /// Container names or IDs (default: all running containers)
+    #[arg(add = ArgValueCandidates::new(container_candidates))]
+    pub containers: Vec<String>,
+
+    /// Print one table and exit instead of refreshing it
+    #[arg(long)]
+    pub no_stream: bool,
+
+    /// Print the samples cubod recorded (csv, json) instead of the current usage
+    #[arg(long)]
+    pub export: Option<StatsFormat>,
+
+    /// Only export samples since a timestamp (RFC 3339) or a duration ago (e.g. 1h)
+    #[arg(long, requires = "export")]
+    pub since: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevArgs {
+    /// Blueprint name or ID
+    #[arg(add = ArgValueCandidates::new(image_candidates))]
+    pub blueprint: String,
+    /// Directory or file mounted read-write over the image (format: src=PATH,dst=PATH)
+    #[arg(short, long, required = true, value_name = "src=PATH,dst=PATH")]
+    pub source: Vec<String>,
+    /// Name of the container
+    #[arg(short, long)]
+    pub name: Option<String>,
+    /// Environment variables
+    #[arg(short, long)]
+    pub env: Vec<String>,
+    /// Publish ports (host->container)
+    #[arg(short, long)]
+    pub publish: Vec<String>,
+    /// Command to run instead of the image's CMD
+    #[arg(last = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct InspectArgs {
+    /// Container names or IDs
+    #[arg(required = true, add = ArgValueCandidates::new(container_candidates))]
+    pub containers: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ContainerArgs {
+    #[command(subcommand)]
+    pub command: ContainerCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ContainerCommands {
+    /// Remove exited containers
+    Prune(ContainerPruneArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct ContainerPruneArgs {
+    /// Only remove containers that exited before a timestamp (RFC 3339) or a duration ago (e.g. 72h)
+    #[arg(long)]
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct SuperviseArgs {
+    /// ID of the created container
+    pub container: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct CompletionArgs {
+    /// Shell to complete in (bash, zsh, fish, elvish, powershell)
+    pub shell: Shell,
+}
+
+#[derive(Debug, Parser)]
+pub struct ManArgs {
+    /// Write a page per command to this directory instead of printing cubo(1)
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageArgs {
+    #[command(subcommand)]
+    pub command: ImageCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ImageCommands {
+    /// List images
+    Ls(ImageLsArgs),
+    /// Give an image another name
+    Tag(ImageTagArgs),
+    /// Sign a local image with a private key
+    Sign(ImageSignArgs),
+    /// Check an image against the trust policy
+    Verify(ImageVerifyArgs),
+    /// Create a key pair for signing images
+    GenerateKey(ImageGenerateKeyArgs),
+    /// Export the SBOM or build provenance of a built image
+    Sbom(ImageSbomArgs),
+    /// Show the steps of the last build of an image, with their durations and output
+    History(ImageHistoryArgs),
+    /// Remove images no container was created from
+    Prune(ImagePruneArgs),
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageLsArgs {
+    /// Only list images matching FILTER: label=KEY, or label=KEY=VALUE
+    #[arg(short, long = "filter", value_name = "FILTER")]
+    pub filters: Vec<ImageFilter>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageTagArgs {
+    /// Image to name: a name, digest or image ID
+    pub source: String,
+    /// New name of the image, moved over if another image has it
+    pub target: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageHistoryArgs {
+    /// Image reference
+    #[arg(add = ArgValueCandidates::new(image_candidates))]
+    pub image: String,
+    /// Print what each step printed while it ran
+    #[arg(long)]
+    pub logs: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImagePruneArgs {
+    /// Only remove untagged images that no other image was built from
+    #[arg(long)]
+    pub dangling: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageSignArgs {
+    /// Image reference
+    #[arg(add = ArgValueCandidates::new(image_candidates))]
+    pub image: String,
+    /// PKCS#8 PEM private key (P-256)
+    #[arg(long)]
+    pub key: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageVerifyArgs {
+    /// Image reference
+    #[arg(add = ArgValueCandidates::new(image_candidates))]
+    pub image: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageSbomArgs {
+    /// Image reference
+    #[arg(add = ArgValueCandidates::new(image_candidates))]
+    pub image: String,
+    /// SBOM format (cyclonedx, spdx)
+    #[arg(long, default_value = "cyclonedx")]
+    pub format: SbomFormat,
+    /// Export the provenance attestation of the build instead
+    #[arg(long)]
+    pub provenance: bool,
+    /// Write to a file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImageGenerateKeyArgs {
+    /// Path of the key pair without extension, written as <PATH>.key and <PATH>.pub
+    #[arg(long, default_value = "cubo")]
+    pub output: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::sched::IoClass;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn test_root_dir_from_env_in_cli() {
+        std::env::set_var("CUBO_ROOT", "/var/lib/cubo-test");
+
+        let cli: Cli = Cli::parse_from(["cubo", "ps"]);
+        assert_eq!(cli.root_dir, Some("/var/lib/cubo-test".to_string()));
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_root_dir_flag_overrides_env() {
+        std::env::set_var("CUBO_ROOT", "/env/path");
+        let cli: Cli = Cli::parse_from(["cubo", "--root-dir", "/flag/path", "ps"]);
+        assert_eq!(cli.root_dir, Some("/flag/path".to_string()));
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_root_dir_not_set() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "ps"]);
+        assert_eq!(cli.root_dir, None);
+    }
+
+    #[test]
+    #[serial]
+    fn test_executor_flag() {
+        std::env::remove_var("CUBO_ROOT");
+        std::env::remove_var("CUBO_EXECUTOR");
+        let cli = Cli::parse_from(["cubo", "ps"]);
+        assert_eq!(cli.executor, None);
+
+        let cli = Cli::parse_from(["cubo", "run", "--executor", "crun", "alpine", "true"]);
+        assert_eq!(cli.executor, Some("crun".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_snapshotter_flag() {
+        std::env::remove_var("CUBO_ROOT");
+        std::env::remove_var("CUBO_SNAPSHOTTER");
+        let cli = Cli::parse_from(["cubo", "--snapshotter", "overlayfs", "run", "alpine", "true"]);
+        assert_eq!(cli.snapshotter, Some("overlayfs".to_string()));
+
+        std::env::remove_var("CUBO_PROVISION");
+        let cli = Cli::parse_from(["cubo", "run", "--provision", "reflink", "alpine", "true"]);
+        assert_eq!(cli.provision, Some("reflink".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn test_remote_flags() {
+        std::env::remove_var("CUBO_ROOT");
+        std::env::remove_var("CUBO_SOCKET");
+        let cli = Cli::parse_from(["cubo", "ps"]);
+        assert!(!cli.remote);
+        assert_eq!(cli.socket, None);
+
+        let cli = Cli::parse_from(["cubo", "ps", "--remote", "--socket", "/tmp/cubo.sock"]);
+        assert!(cli.remote);
+        assert_eq!(cli.socket, Some("/tmp/cubo.sock".to_string()));
+    }
+
+    // Run command tests
+    #[test]
+    #[serial]
+    fn test_run_command_basic() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.blueprint, "alpine");
+            assert!(args.command.is_none());
+            assert!(args.name.is_none());
+            assert!(!args.interactive);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_with_all_options() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "run", "ubuntu:22.04",
+            "--name", "cubo-container",
+            "-i",
+            "-v", "/host:/container",
+            "-v", "/tmp:/tmp:ro",
+            "-p", "8080:80",
+            "-e", "FOO=bar",
+            "-e", "BAZ=baz",
+            "-w", "/app",
+            "--", "bash", "-c", "echo hello"
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.blueprint, "ubuntu:22.04");
+            assert_eq!(args.name, Some("cubo-container".to_string()));
+            assert!(args.interactive);
+            assert_eq!(args.volume.len(), 2);
+            assert_eq!(args.volume[0], "/host:/container");
+            assert_eq!(args.volume[1], "/tmp:/tmp:ro");
+            assert_eq!(args.publish.len(), 1);
+            assert_eq!(args.publish[0], "8080:80");
+            assert_eq!(args.env.len(), 2);
+            assert_eq!(args.env.len(), 2);
+            assert_eq!(args.workdir, Some("/app".to_string()));
+            let cmd = args.command.unwrap();
+            assert_eq!(cmd, vec!["bash", "-c", "echo hello"])
+        } else {
+            panic!("Excpected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_id_files() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "--cidfile", "/run/web.cid", "--pidfile", "/run/web.pid", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.cidfile, Some("/run/web.cid".to_string()));
+            assert_eq!(args.pidfile, Some("/run/web.pid".to_string()));
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_pids_limit() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "--pids-limit", "100", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.pids_limit, Some(100));
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "--pids-limit", "-1", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.pids_limit, Some(-1));
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from([
+            "cubo", "run", "-m", "512m", "--memory-swap", "-1", "--memory-reservation", "256m", "--oom-score-adj", "-500", "alpine",
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.memory.as_deref(), Some("512m"));
+            assert_eq!(args.memory_swap.as_deref(), Some("-1"));
+            assert_eq!(args.memory_reservation.as_deref(), Some("256m"));
+            assert_eq!(args.oom_score_adj, Some(-500));
+        } else {
+            panic!("Expected Run command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "run", "--oom-score-adj", "1001", "alpine"]).is_err());
+
+        let cli = Cli::parse_from([
+            "cubo", "run", "--nice", "-5", "--sched-policy", "rr", "--rt-priority", "10", "--rt-runtime", "50000",
+            "--ionice", "best-effort:2", "alpine",
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.nice, Some(-5));
+            assert_eq!(args.sched_policy, Some(SchedPolicy::Rr));
+            assert_eq!(args.rt_priority, Some(10));
+            assert_eq!(args.rt_runtime, Some(50000));
+            assert_eq!(args.ionice, Some(IoPriority { class: IoClass::BestEffort, level: 2 }));
+        } else {
+            panic!("Expected Run command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "run", "--nice", "20", "alpine"]).is_err());
+        assert!(Cli::try_parse_from(["cubo", "run", "--ionice", "idle:1", "alpine"]).is_err());
+
+        let cli = Cli::parse_from(["cubo", "run", "--cpuset-cpus", "0-3", "--cpuset-mems", "0", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.cpuset_cpus.as_deref(), Some("0-3"));
+            assert_eq!(args.cpuset_mems.as_deref(), Some("0"));
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_blkio_options() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "run",
+            "--device-read-bps", "/dev/sda:1mb",
+            "--device-write-bps", "/dev/sda:512kb",
+            "--blkio-weight", "300",
+            "alpine",
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.device_read_bps, vec!["/dev/sda:1mb"]);
+            assert_eq!(args.device_write_bps, vec!["/dev/sda:512kb"]);
+            assert_eq!(args.blkio_weight, Some(300));
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_blkio_weight_out_of_range() {
+        let result = Cli::try_parse_from(["cubo", "run", "--blkio-weight", "5", "alpine"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_ulimits() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "run",
+            "--ulimit", "nofile=65535:65535",
+            "--ulimit", "nproc=512",
+            "alpine",
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.ulimit, vec!["nofile=65535:65535", "nproc=512"]);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_sysctls() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "run",
+            "--sysctl", "net.ipv4.ip_unprivileged_port_start=0",
+            "alpine",
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.sysctl, vec!["net.ipv4.ip_unprivileged_port_start=0"]);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_ipc_mode() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.ipc, IpcMode::Private);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "--ipc", "host", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.ipc, IpcMode::Host);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "run", "--ipc", "bogus", "alpine"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_userns_mode() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.userns, UsernsMode::Private);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "--userns", "keep-id", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.userns, UsernsMode::KeepId);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "run", "--userns", "auto", "alpine"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_stop_signal() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "--stop-signal", "SIGINT", "postgres"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.stop_signal, Some("SIGINT".to_string()));
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_health() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "run", "--health-cmd", "pg_isready -U app", "--health-interval", "2",
+            "--wait-healthy", "--sdnotify", "container", "postgres",
+        ]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.health_cmd.as_deref(), Some("pg_isready -U app"));
+            assert_eq!(args.health_interval, 2);
+            assert_eq!(args.health_timeout, 30);
+            assert_eq!(args.health_retries, 3);
+            assert!(args.wait_healthy);
+            assert_eq!(args.sdnotify, SdNotifyMode::Container);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert!(!args.wait_healthy);
+            assert_eq!(args.sdnotify, SdNotifyMode::Cubo);
+        } else {
+            panic!("Expected Run command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "run", "--sdnotify", "always", "alpine"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_log_driver() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "--log-driver", "journald", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.log_driver, Some(LogDriverKind::Journald));
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.log_driver, None);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "run", "--log-driver", "syslog", "alpine"]).is_err());
+
+        let cli = Cli::parse_from(["cubo", "run", "--log-opt", "max-size=10m,max-file=3", "--log-opt", "max-file=5", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.log_opt, vec!["max-size=10m,max-file=3", "max-file=5"]);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_init() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "--init", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert!(args.init);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert!(!args.init);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_network() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.network, None);
+        } else {
+            panic!("Expected Run command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "--network", "mynet", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.network, Some(NetworkMode::Custom("mynet".to_string())));
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_command_dns_options() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "run",
+            "--dns", "1.1.1.1",
+            "--dns", "8.8.8.8",
+            "--dns-search", "example.com",
+            "--add-host", "db:10.0.0.5",
+            "alpine",
+        ]);
         if let Commands::Run(args) = cli.command {
-            assert_eq!(args.blueprint, "alpine");
-            assert!(args.command.is_none());
-            assert!(args.name.is_none());
-            assert!(!args.interactive);
+            assert_eq!(args.dns, vec!["1.1.1.1", "8.8.8.8"]);
+            assert_eq!(args.dns_search, vec!["example.com"]);
+            assert_eq!(args.add_host, vec!["db:10.0.0.5"]);
         } else {
             panic!("Expected Run command");
         }
@@ -189,36 +1421,20 @@ mod tests {
 
     #[test]
     #[serial]
-    fn test_run_command_with_all_options() {
+    fn test_run_command_static_addressing() {
         std::env::remove_var("CUBO_ROOT");
         let cli = Cli::parse_from([
-            "cubo", "run", "ubuntu:22.04",
-            "--name", "cubo-container",
-            "-i",
-            "-v", "/host:/container",
-            "-v", "/tmp:/tmp:ro",
-            "-p", "8080:80",
-            "-e", "FOO=bar",
-            "-e", "BAZ=baz",
-            "-w", "/app",
-            "--", "bash", "-c", "echo hello"
+            "cubo", "run",
+            "--network", "mynet",
+            "--ip", "10.89.1.20",
+            "--mac-address", "02:42:0a:59:01:14",
+            "alpine",
         ]);
         if let Commands::Run(args) = cli.command {
-            assert_eq!(args.blueprint, "ubuntu:22.04");
-            assert_eq!(args.name, Some("cubo-container".to_string()));
-            assert!(args.interactive);
-            assert_eq!(args.volume.len(), 2);
-            assert_eq!(args.volume[0], "/host:/container");
-            assert_eq!(args.volume[1], "/tmp:/tmp:ro");
-            assert_eq!(args.publish.len(), 1);
-            assert_eq!(args.publish[0], "8080:80");
-            assert_eq!(args.env.len(), 2);
-            assert_eq!(args.env.len(), 2);
-            assert_eq!(args.workdir, Some("/app".to_string()));
-            let cmd = args.command.unwrap();
-            assert_eq!(cmd, vec!["bash", "-c", "echo hello"])
+            assert_eq!(args.ip, Some("10.89.1.20".to_string()));
+            assert_eq!(args.mac_address, Some("02:42:0a:59:01:14".to_string()));
         } else {
-            panic!("Excpected Run command");
+            panic!("Expected Run command");
         }
     }
 
@@ -259,6 +1475,35 @@ mod tests {
         }
     }
 
+    #[test]
+    #[serial]
+    fn test_build_command_with_watch() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "build", ".", "--watch", "--restart-container", "web"]);
+        if let Commands::Build(args) = cli.command {
+            assert!(args.watch);
+            assert_eq!(args.restart_container.as_deref(), Some("web"));
+        } else {
+            panic!("Expected Build command");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "build", ".", "--restart-container", "web"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_build_command_with_step_limits() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "build", ".", "--step-timeout", "10m", "--step-memory", "1g", "--step-cpus", "2"]);
+        if let Commands::Build(args) = cli.command {
+            assert_eq!(args.step_timeout.as_deref(), Some("10m"));
+            assert_eq!(args.step_memory.as_deref(), Some("1g"));
+            assert_eq!(args.step_cpus, Some(2.0));
+        } else {
+            panic!("Expected Build command");
+        }
+    }
+
     #[test]
     #[serial]
     fn test_ps_command_basic() {
@@ -283,6 +1528,28 @@ mod tests {
         }
     }
 
+    #[test]
+    #[serial]
+    fn test_ps_command_with_watch() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "ps", "--watch", "-a"]);
+        if let Commands::Ps(args) = cli.command {
+            assert!(args.all);
+            assert_eq!(args.watch, Some(2));
+        } else {
+            panic!("Expected Ps command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "ps", "--watch", "5"]);
+        if let Commands::Ps(args) = cli.command {
+            assert_eq!(args.watch, Some(5));
+        } else {
+            panic!("Expected Ps command");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "ps", "--watch", "0"]).is_err());
+    }
+
     #[test]
     #[serial]
     fn test_stop_command_single() {
@@ -341,7 +1608,8 @@ mod tests {
         std::env::remove_var("CUBO_ROOT");
         let cli = Cli::parse_from(["cubo", "pull", "alpine:latest"]);
         if let Commands::Pull(args) = cli.command {
-            assert_eq!(args.image, "alpine:latest");
+            assert_eq!(args.images, vec!["alpine:latest"]);
+            assert_eq!(args.jobs, 4);
         } else {
             panic!("Expected Pull command");
         }
@@ -353,12 +1621,28 @@ mod tests {
         std::env::remove_var("CUBO_ROOT");
         let cli = Cli::parse_from(["cubo", "pull", "ghcr.io/owner/image:tag"]);
         if let Commands::Pull(args) = cli.command {
-            assert_eq!(args.image, "ghcr.io/owner/image:tag");
+            assert_eq!(args.images, vec!["ghcr.io/owner/image:tag"]);
         } else {
             panic!("Expected Pull command");
         }
     }
 
+    #[test]
+    #[serial]
+    fn test_pull_command_batch() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "pull", "-f", "images.txt", "-j", "8", "alpine"]);
+        if let Commands::Pull(args) = cli.command {
+            assert_eq!(args.images, vec!["alpine"]);
+            assert_eq!(args.file.as_deref(), Some("images.txt"));
+            assert_eq!(args.jobs, 8);
+        } else {
+            panic!("Expected Pull command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "pull"]).is_err());
+        assert!(Cli::try_parse_from(["cubo", "pull", "-j", "0", "alpine"]).is_err());
+    }
+
     #[test]
     #[serial]
     fn test_blueprint_command() {
@@ -439,4 +1723,404 @@ mod tests {
             panic!("Expected logs command");
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[serial]
+    fn test_logs_command_with_filters() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "logs", "--since", "10m", "--until", "2025-11-24T20:30:00Z", "--details", "web",
+        ]);
+        if let Commands::Logs(args) = cli.command {
+            assert_eq!(args.since.as_deref(), Some("10m"));
+            assert_eq!(args.until.as_deref(), Some("2025-11-24T20:30:00Z"));
+            assert!(args.details);
+        } else {
+            panic!("Expected logs command");
+        }
+    }
+
+    // Network command tests
+    #[test]
+    #[serial]
+    fn test_network_create_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "network", "create", "mynet",
+            "--subnet", "172.30.0.0/24",
+            "--gateway", "172.30.0.1",
+        ]);
+        if let Commands::Network(args) = cli.command {
+            if let NetworkCommands::Create(create) = args.command {
+                assert_eq!(create.name, "mynet");
+                assert_eq!(create.subnet, Some("172.30.0.0/24".to_string()));
+                assert_eq!(create.gateway, Some("172.30.0.1".to_string()));
+            } else {
+                panic!("Expected network create");
+            }
+        } else {
+            panic!("Expected Network command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_network_ls_rm_inspect_commands() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "network", "ls"]);
+        assert!(matches!(cli.command, Commands::Network(NetworkArgs { command: NetworkCommands::Ls })));
+
+        let cli = Cli::parse_from(["cubo", "network", "rm", "a", "b"]);
+        if let Commands::Network(NetworkArgs { command: NetworkCommands::Rm(rm) }) = cli.command {
+            assert_eq!(rm.networks, vec!["a", "b"]);
+        } else {
+            panic!("Expected network rm");
+        }
+
+        let cli = Cli::parse_from(["cubo", "network", "inspect", "a"]);
+        assert!(matches!(cli.command, Commands::Network(NetworkArgs { command: NetworkCommands::Inspect(_) })));
+
+        assert!(Cli::try_parse_from(["cubo", "network", "rm"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_network_connect_disconnect_commands() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "network", "connect", "--ip", "10.89.2.9", "backend", "web"]);
+        if let Commands::Network(NetworkArgs { command: NetworkCommands::Connect(connect) }) = cli.command {
+            assert_eq!(connect.network, "backend");
+            assert_eq!(connect.container, "web");
+            assert_eq!(connect.ip, Some("10.89.2.9".to_string()));
+            assert!(connect.mac_address.is_none());
+        } else {
+            panic!("Expected network connect");
+        }
+
+        let cli = Cli::parse_from(["cubo", "network", "disconnect", "backend", "web"]);
+        if let Commands::Network(NetworkArgs { command: NetworkCommands::Disconnect(disconnect) }) = cli.command {
+            assert_eq!(disconnect.network, "backend");
+            assert_eq!(disconnect.container, "web");
+        } else {
+            panic!("Expected network disconnect");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "network", "connect", "backend"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_volume_create_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::try_parse_from(["cubo", "volume", "create", "-d", "nfs", "-o", "server=10.0.0.1", "-o", "path=/x", "shared"]).unwrap();
+        if let Commands::Volume(VolumeArgs { command: VolumeCommands::Create(create) }) = cli.command {
+            assert_eq!(create.name, "shared");
+            assert_eq!(create.driver, "nfs");
+            assert_eq!(create.options, vec!["server=10.0.0.1", "path=/x"]);
+        } else {
+            panic!("Expected Volume create command");
+        }
+
+        let cli = Cli::try_parse_from(["cubo", "volume", "create", "data"]).unwrap();
+        assert!(matches!(cli.command, Commands::Volume(VolumeArgs { command: VolumeCommands::Create(VolumeCreateArgs { ref driver, .. }) }) if driver == "local"));
+        assert!(Cli::try_parse_from(["cubo", "volume", "rm"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_update_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "update", "--memory", "512m", "--cpus", "1.5", "--pids-limit", "-1", "web", "db"]);
+        if let Commands::Update(args) = cli.command {
+            assert_eq!(args.containers, vec!["web", "db"]);
+            assert_eq!(args.memory, Some("512m".to_string()));
+            assert_eq!(args.cpus, Some(1.5));
+            assert_eq!(args.pids_limit, Some(-1));
+        } else {
+            panic!("Expected Update command");
+        }
+
+        assert!(Cli::try_parse_from(["cubo", "update", "--memory", "1g"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_checkpoint_and_restore_commands() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "checkpoint", "--leave-running", "web"]);
+        if let Commands::Checkpoint(args) = cli.command {
+            assert_eq!(args.container, "web");
+            assert!(args.leave_running);
+        } else {
+            panic!("Expected Checkpoint command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "restore", "web"]);
+        assert!(matches!(cli.command, Commands::Restore(RestoreArgs { ref container }) if container == "web"));
+
+        assert!(Cli::try_parse_from(["cubo", "checkpoint"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_compose_commands() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "compose", "-f", "stack.toml", "-p", "shop", "up", "-d", "--build"]);
+        if let Commands::Compose(args) = cli.command {
+            assert_eq!(args.file.as_deref(), Some("stack.toml"));
+            assert_eq!(args.project_name.as_deref(), Some("shop"));
+            assert!(matches!(args.command, ComposeCommands::Up(ComposeUpArgs { detach: true, build: true })));
+        } else {
+            panic!("Expected Compose command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "compose", "logs", "-f", "-n", "10", "web", "db"]);
+        if let Commands::Compose(ComposeArgs { command: ComposeCommands::Logs(logs), file: None, .. }) = cli.command {
+            assert_eq!(logs.services, vec!["web", "db"]);
+            assert!(logs.follow);
+            assert_eq!(logs.tail, Some(10));
+        } else {
+            panic!("Expected Compose logs command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "compose", "down", "-t", "3"]);
+        assert!(matches!(
+            cli.command,
+            Commands::Compose(ComposeArgs { command: ComposeCommands::Down(ComposeDownArgs { timeout: Some(3) }), .. })
+        ));
+        assert!(Cli::try_parse_from(["cubo", "compose"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_export_bundle_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "export-bundle", "web", "/tmp/web-bundle", "-f"]);
+        if let Commands::ExportBundle(args) = cli.command {
+            assert_eq!(args.container, "web");
+            assert_eq!(args.dir, "/tmp/web-bundle");
+            assert!(args.force);
+        } else {
+            panic!("Expected ExportBundle command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "export-bundle", "web"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_generate_systemd_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from([
+            "cubo", "generate", "systemd", "web",
+            "--type", "forking", "--restart-policy", "on-failure:3", "--restart-sec", "5", "--files",
+        ]);
+        if let Commands::Generate(GenerateArgs { command: GenerateCommands::Systemd(args) }) = cli.command {
+            assert_eq!(args.container, "web");
+            assert_eq!(args.service_type, ServiceType::Forking);
+            assert_eq!(args.restart_policy, Some(RestartPolicy::OnFailure { max_retries: 3 }));
+            assert_eq!(args.restart_sec, Some(5));
+            assert!(args.files);
+        } else {
+            panic!("Expected Generate systemd command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "generate", "systemd", "web"]);
+        if let Commands::Generate(GenerateArgs { command: GenerateCommands::Systemd(args) }) = cli.command {
+            assert_eq!(args.service_type, ServiceType::Notify);
+            assert_eq!(args.restart_policy, None);
+            assert!(!args.files);
+        } else {
+            panic!("Expected Generate systemd command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "generate", "systemd", "web", "--restart-policy", "sometimes"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_system_df_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "system", "df", "-v"]);
+        if let Commands::System(SystemArgs { command: SystemCommands::Df(args) }) = cli.command {
+            assert!(args.verbose);
+        } else {
+            panic!("Expected System df command");
+        }
+        assert!(matches!(
+            Cli::parse_from(["cubo", "system", "info"]).command,
+            Commands::System(SystemArgs { command: SystemCommands::Info })
+        ));
+        assert!(matches!(Cli::parse_from(["cubo", "doctor"]).command, Commands::Doctor));
+    }
+
+    #[test]
+    #[serial]
+    fn test_audit_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "audit", "--since", "24h", "--operation", "rm", "--failed", "--json"]);
+        if let Commands::Audit(args) = cli.command {
+            assert_eq!(args.since, Some("24h".to_string()));
+            assert_eq!(args.operation, Some(AuditOperation::Remove));
+            assert!(args.failed);
+            assert!(args.json);
+            assert!(args.container.is_none());
+        } else {
+            panic!("Expected Audit command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "audit", "--operation", "exec"]).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_completion_and_man_commands() {
+        std::env::remove_var("CUBO_ROOT");
+        if let Commands::Completion(args) = Cli::parse_from(["cubo", "completion", "zsh"]).command {
+            assert_eq!(args.shell, Shell::Zsh);
+        } else {
+            panic!("Expected Completion command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "completion", "tcsh"]).is_err());
+
+        if let Commands::Man(args) = Cli::parse_from(["cubo", "man", "-o", "/tmp/man"]).command {
+            assert_eq!(args.output, Some("/tmp/man".to_string()));
+        } else {
+            panic!("Expected Man command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "--storage-opt", "size=10G", "alpine", "sh"]);
+        if let Commands::Run(args) = cli.command {
+            assert_eq!(args.storage_opt, vec!["size=10G"]);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_image_commands() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "image", "sign", "--key", "cubo.key", "myapp:latest"]);
+        if let Commands::Image(ImageArgs { command: ImageCommands::Sign(args) }) = cli.command {
+            assert_eq!(args.image, "myapp:latest");
+            assert_eq!(args.key, "cubo.key");
+        } else {
+            panic!("Expected Image sign command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "image", "generate-key"]);
+        if let Commands::Image(ImageArgs { command: ImageCommands::GenerateKey(args) }) = cli.command {
+            assert_eq!(args.output, "cubo");
+        } else {
+            panic!("Expected Image generate-key command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "image", "sbom", "--format", "spdx", "-o", "sbom.json", "myapp"]);
+        if let Commands::Image(ImageArgs { command: ImageCommands::Sbom(args) }) = cli.command {
+            assert_eq!(args.format, SbomFormat::Spdx);
+            assert!(!args.provenance);
+            assert_eq!(args.output.as_deref(), Some("sbom.json"));
+        } else {
+            panic!("Expected Image sbom command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "image", "sbom", "--format", "syft", "myapp"]).is_err());
+
+        let cli = Cli::parse_from(["cubo", "image", "history", "--logs", "myapp"]);
+        if let Commands::Image(ImageArgs { command: ImageCommands::History(args) }) = cli.command {
+            assert_eq!(args.image, "myapp");
+            assert!(args.logs);
+        } else {
+            panic!("Expected Image history command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "image", "prune", "--dangling"]);
+        if let Commands::Image(ImageArgs { command: ImageCommands::Prune(args) }) = cli.command {
+            assert!(args.dangling);
+        } else {
+            panic!("Expected Image prune command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "images", "--filter", "label=team=payments", "-f", "label=tier"]);
+        if let Commands::Images(args) = cli.command {
+            assert_eq!(
+                args.filters,
+                vec![
+                    ImageFilter::Label { key: "team".to_string(), value: Some("payments".to_string()) },
+                    ImageFilter::Label { key: "tier".to_string(), value: None },
+                ]
+            );
+        } else {
+            panic!("Expected Images command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "image", "ls", "--filter", "size=1"]).is_err());
+
+        let cli = Cli::parse_from(["cubo", "tag", "myapp", "registry.example.com/myapp:v1"]);
+        if let Commands::Tag(args) = cli.command {
+            assert_eq!(args.source, "myapp");
+            assert_eq!(args.target, "registry.example.com/myapp:v1");
+        } else {
+            panic!("Expected Tag command");
+        }
+        let cli = Cli::parse_from(["cubo", "image", "tag", "sha256:3f2a9c1b7d4e", "myapp:v2"]);
+        assert!(matches!(cli.command, Commands::Image(ImageArgs { command: ImageCommands::Tag(_) })));
+
+        let cli = Cli::parse_from(["cubo", "build", ".", "--label", "team=payments", "--label", "tier=front"]);
+        if let Commands::Build(args) = cli.command {
+            assert_eq!(args.label, vec!["team=payments", "tier=front"]);
+        } else {
+            panic!("Expected Build command");
+        }
+
+        let cli = Cli::parse_from(["cubo", "run", "--verify", "alpine"]);
+        if let Commands::Run(args) = cli.command {
+            assert!(args.verify);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    fn test_wait_command() {
+        let cli = Cli::parse_from(["cubo", "wait", "web", "db"]);
+        if let Commands::Wait(args) = cli.command {
+            assert_eq!(args.containers, ["web", "db"]);
+        } else {
+            panic!("Expected Wait command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "wait"]).is_err());
+    }
+
+    #[test]
+    fn test_run_publish_all() {
+        let cli = Cli::parse_from(["cubo", "run", "-P", "-p", "8080", "nginx:latest"]);
+        if let Commands::Run(args) = cli.command {
+            assert!(args.publish_all);
+            assert_eq!(args.publish, ["8080"]);
+        } else {
+            panic!("Expected Run command");
+        }
+    }
+
+    #[test]
+    fn test_port_command() {
+        let cli = Cli::parse_from(["cubo", "port", "web", "80/tcp"]);
+        if let Commands::Port(args) = cli.command {
+            assert_eq!(args.container, "web");
+            assert_eq!(args.port.as_deref(), Some("80/tcp"));
+        } else {
+            panic!("Expected Port command");
+        }
+    }
+
+    #[test]
+    fn test_stats_command() {
+        let cli = Cli::parse_from(["cubo", "stats", "--export", "csv", "--since", "1h", "web"]);
+        if let Commands::Stats(args) = cli.command {
+            assert_eq!(args.containers, ["web"]);
+            assert_eq!(args.export, Some(StatsFormat::Csv));
+            assert_eq!(args.since.as_deref(), Some("1h"));
+        } else {
+            panic!("Expected Stats command");
+        }
+        assert!(Cli::try_parse_from(["cubo", "stats", "--since", "1h"]).is_err());
+        assert!(Cli::try_parse_from(["cubo", "stats", "--export", "xml"]).is_err());
+    }
+}
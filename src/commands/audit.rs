@@ -0,0 +1,53 @@
+use chrono::{SecondsFormat, Utc};
+
+use crate::cli::AuditArgs;
+use crate::commands::context::CuboContext;
+use crate::commands::logs::parse_time_spec;
+use crate::container::audit::{AuditFilter, AuditLog};
+use crate::error::{CuboError, Result};
+
+pub async fn execute(ctx: &CuboContext, args: AuditArgs) -> Result<()> {
+    let now = Utc::now();
+    let filter = AuditFilter {
+        since: args.since.as_deref().map(|s| parse_time_spec(s, now)).transpose()?,
+        until: args.until.as_deref().map(|s| parse_time_spec(s, now)).transpose()?,
+        operation: args.operation,
+        container: args.container,
+        image: args.image,
+        user: args.user,
+        failed_only: args.failed,
+    };
+
+    let log = AuditLog::new(ctx.root_dir());
+    let records = log.read(&filter)?;
+
+    if args.json {
+        for record in &records {
+            let line = serde_json::to_string(record)
+                .map_err(|e| CuboError::SystemError(format!("Failed to serialize audit record: {}", e)))?;
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+
+    println!("{:<21} {:<10} {:<8} {:<12} {:<25} {:<8} COMMAND", "TIME", "USER", "OP", "CONTAINER", "IMAGE", "RESULT");
+    for record in &records {
+        let user = record.user.clone().unwrap_or_else(|| record.uid.to_string());
+        let container = record.container.as_deref().map(|id| &id[..id.len().min(12)]).unwrap_or("-");
+        let result = if record.success { "ok" } else { "failed" };
+        println!(
+            "{:<21} {:<10} {:<8} {:<12} {:<25} {:<8} {}",
+            record.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            user,
+            record.operation.to_string(),
+            container,
+            record.image.as_deref().unwrap_or("-"),
+            result,
+            record.command
+        );
+        if let Some(ref error) = record.error {
+            println!("{:<21} {}", "", error);
+        }
+    }
+    Ok(())
+}
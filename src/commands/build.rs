@@ -1,12 +1,31 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
 
 use crate::cli::BuildArgs;
+use crate::commands::context::CuboContext;
+use crate::container::audit::{AuditLog, AuditOperation, AuditRecord};
 use crate::container::cubofile::Cubofile;
-use crate::container::cubofile_toml::CubofileToml;
-use crate::container::builder::ImageBuilder;
+use crate::config::{parse_byte_size, parse_duration};
+use crate::container::cubofile_toml::{BuildOptions, CubofileToml};
+use crate::container::build_progress::{ProgressMode, SolveStatus, Steps};
+use crate::container::builder::{instruction_texts, toml_instructions, ImageBuilder, StepLimits};
 use crate::container::image_store::ImageStore;
+use crate::container::runtime::ContainerRuntime;
+use crate::container::supervisor;
+use crate::container::RestartPolicy;
+use crate::container::signature::image_digest;
 use crate::error::{CuboError, Result};
-use tracing::{info, error};
+use tracing::{info, error, warn};
+
+/// How long `--watch` waits for the events of a change to settle before building
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
 
 pub fn  detect_build_file(build_context: &PathBuf, specified_file: Option<&String>) -> Result<(PathBuf, bool)> {
     if let Some(file) = specified_file {
@@ -42,7 +61,119 @@ pub fn resolve_image_tag(path: &str, tag: Option<&String>) -> String {
     }
 }
 
-pub async fn execute(args: BuildArgs) -> Result<()> {
+pub async fn execute(ctx: &CuboContext, args: BuildArgs) -> Result<()> {
+    let image_tag = resolve_image_tag(&args.path, args.tag.as_ref());
+    if args.watch {
+        return watch(ctx, args, &image_tag).await;
+    }
+    build_image(ctx, args, &image_tag, true).await
+}
+
+/// Build, then build again whenever something in the context changes, until interrupted. A
+/// change that leaves the content as it was (a touched file) is a cache hit; a build that
+/// changed the image recreates the container of `--restart-container`.
+async fn watch(ctx: &CuboContext, args: BuildArgs, image_tag: &str) -> Result<()> {
+    let image_store = ctx.image_store()?;
+    let runtime = match args.restart_container {
+        Some(_) => Some(ctx.runtime()?),
+        None => None,
+    };
+
+    let mut watcher = ChangeWatcher::new(&[Path::new(&args.path)])?;
+
+    loop {
+        let before = image_digest(image_store, image_tag).ok();
+        match build_image(ctx, args.clone(), image_tag, true).await {
+            Ok(()) => {
+                let after = image_digest(image_store, image_tag).ok();
+                if let (Some(runtime), Some(name)) = (runtime, &args.restart_container) {
+                    if after != before {
+                        match restart_container(ctx, runtime, name).await {
+                            Ok(id) => println!("Recreated {} ({})", name, &id[..12]),
+                            Err(e) => eprintln!("Error recreating {}: {}", name, e),
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("Error: {}", e),
+        }
+
+        println!("Watching {} for changes...", args.path);
+        if watcher.next().await.is_none() {
+            return Ok(());
+        }
+        println!();
+    }
+}
+
+/// Changes of the files under some paths, for `--watch` and `cubo dev`
+pub(crate) struct ChangeWatcher {
+    _watcher: RecommendedWatcher,
+    changed: mpsc::UnboundedReceiver<PathBuf>,
+}
+
+impl ChangeWatcher {
+    pub(crate) fn new(paths: &[&Path]) -> Result<Self> {
+        let (changes, changed) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                if !matches!(event.kind, notify::EventKind::Access(_)) {
+                    let _ = changes.send(event.paths.into_iter().next().unwrap_or_default());
+                }
+            }
+        })
+        .map_err(|e| CuboError::SystemError(format!("Failed to start watching files: {}", e)))?;
+        for path in paths {
+            watcher
+                .watch(path, RecursiveMode::Recursive)
+                .map_err(|e| CuboError::SystemError(format!("Failed to watch {}: {}", path.display(), e)))?;
+        }
+        Ok(Self { _watcher: watcher, changed })
+    }
+
+    /// Wait for a change and for the events it makes to settle (saving a file is often
+    /// several). Returns the first changed path, None once interrupted with Ctrl-C.
+    pub(crate) async fn next(&mut self) -> Option<PathBuf> {
+        let path = tokio::select! {
+            path = self.changed.recv() => path?,
+            _ = tokio::signal::ctrl_c() => return None,
+        };
+        while let Ok(Some(_)) = tokio::time::timeout(DEBOUNCE, self.changed.recv()).await {}
+        Some(path)
+    }
+}
+
+/// Recreate a container from its image and start it again, under its restart policy
+async fn restart_container(ctx: &CuboContext, runtime: &ContainerRuntime, identifier: &str) -> Result<String> {
+    let container_id = runtime.resolve(identifier).await?;
+    let supervised = runtime.get_container(&container_id).await?.config.restart_policy != RestartPolicy::No;
+    let container_id = runtime.recreate_container(&container_id).await?;
+    if supervised {
+        supervisor::launch(runtime, &container_id, &ctx.global_args()).await?;
+    } else {
+        runtime.start_container(&container_id, true).await?;
+    }
+    Ok(container_id)
+}
+
+/// Build the image as `image_tag` and record it in the audit log. Progress goes to stdout
+/// with `progress`, else only to the log.
+pub async fn build_image(ctx: &CuboContext, args: BuildArgs, image_tag: &str, progress: bool) -> Result<()> {
+    let result = build(ctx, args, image_tag, progress).await;
+    AuditLog::new(ctx.root_dir()).record(AuditRecord::new(AuditOperation::Build, &result).with_image(image_tag));
+    result
+}
+
+async fn build(ctx: &CuboContext, args: BuildArgs, image_tag: &str, progress: bool) -> Result<()> {
+    let json = args.progress == ProgressMode::Json;
+    let progress = progress && !json;
+    let say = |line: &str| {
+        if progress {
+            println!("{}", line);
+        } else {
+            info!("{}", line);
+        }
+    };
     let build_context = PathBuf::from(&args.path);
     let (build_file_path, is_toml) = detect_build_file(&build_context, args.file.as_ref())?;
 
@@ -53,87 +184,246 @@ pub async fn execute(args: BuildArgs) -> Result<()> {
             build_file_path.display()
         )));
     }
-
-    let image_tag = resolve_image_tag(&args.path, args.tag.as_ref());
-
-    let root_dir = std::env::var("CUBO_ROOT")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/var/lib/cubo"));
-
-    let image_store = ImageStore::new(root_dir.join("images"))?;
-
-    let builder = ImageBuilder::new(&image_store, build_context.clone());
+    let build_args = parse_build_args(&args.build_arg)?;
+    let labels = parse_labels(&args.label)?;
+
+    let image_store = ctx.image_store()?;
+    let cache = BuildCache::new(image_store.root());
+
+    let mut builder = ImageBuilder::new(image_store, build_context.clone())
+        .with_config(ctx.config())
+        .with_build_args(build_args.clone())
+        .with_labels(labels.clone());
+    if json {
+        builder = builder.with_progress(&print_status);
+    }
 
     if is_toml {
         info!("Parsing Cubofile.toml...");
         let cubofile = CubofileToml::from_file(&build_file_path)?;
+        builder = builder.with_step_limits(step_limits(&args, &cubofile.build)?);
 
-        println!("Building image: {}", image_tag);
-        println!("Base image: {}", cubofile.base_image());
-        println!("Build context: {}", args.path);
-        println!("Format: TOML");
-        println!();
+        let key = cache_key(&build_context, &build_file_path, image_store, &cubofile.base_image(), &build_args, &labels)?;
+        if !args.no_cache && image_store.has_image(image_tag) && cache.get(image_tag).as_deref() == Some(key.as_str()) {
+            say(&format!("Using cached image: {}", image_tag));
+            if json {
+                print_status(&Steps::new(toml_instructions(&cubofile)).cached());
+            }
+            return Ok(());
+        }
+
+        say(&format!("Building image: {}", image_tag));
+        say(&format!("Base image: {}", cubofile.base_image()));
+        say(&format!("Build context: {}", args.path));
+        say("Format: TOML");
+        say("");
 
-        match builder.build_from_toml(&cubofile, &image_tag).await {
+        match builder.build_from_toml(&cubofile, image_tag).await {
             Ok(_) => {
-                println!("Successfully built: {}", image_tag);
-                println!();
-                println!("Run with: cubo run {}", image_tag);
+                cache.save(image_tag, &key);
+                say(&format!("Successfully built: {}", image_tag));
+                say("");
+                say(&format!("Run with: cubo run {}", image_tag));
                 Ok(())
             }
             Err(e) => {
                 error!("Build failed: {}", e);
-                println!("Build failed: {}", e);
-                println!();
-                println!("Make sure:");
-                println!("  1. Base image is imported: cubo image import <ref> <tar>");
-                println!("  2. You have root privileges (needed for chroot)");
-                println!("  3. All COPY source files exist in build context");
+                say(&format!("Build failed: {}", e));
+                say("");
+                say("Make sure:");
+                say("  1. Base image is imported: cubo image import <ref> <tar>");
+                say("  2. You have root privileges (needed for chroot)");
+                say("  3. All COPY source files exist in build context");
                 Err(e)
             }
         }
     } else {
         info!("Parsing Cubofile...");
         let cubofile = Cubofile::from_file(&build_file_path)?;
+        builder = builder.with_step_limits(step_limits(&args, &BuildOptions::default())?);
 
-        if cubofile.base_image().is_none() {
+        let Some(base_image) = cubofile.base_image() else {
             return Err(CuboError::InvalidConfiguration(
                 "Cubofile must contain a BASE instruction".to_string()
             ));
+        };
+
+        let key = cache_key(&build_context, &build_file_path, image_store, &base_image, &build_args, &labels)?;
+        if !args.no_cache && image_store.has_image(image_tag) && cache.get(image_tag).as_deref() == Some(key.as_str()) {
+            say(&format!("Using cached image: {}", image_tag));
+            if json {
+                print_status(&Steps::new(instruction_texts(&cubofile)).cached());
+            }
+            return Ok(());
         }
 
-        println!("Building image: {}", image_tag);
-        println!("Base image: {}", cubofile.base_image().unwrap());
-        println!("Build context: {}", args.path);
-        println!("Format: Text");
-        println!();
+        say(&format!("Building image: {}", image_tag));
+        say(&format!("Base image: {}", base_image));
+        say(&format!("Build context: {}", args.path));
+        say("Format: Text");
+        say("");
 
-        match builder.build(&cubofile, &image_tag).await {
+        match builder.build(&cubofile, image_tag).await {
             Ok(_) => {
-                println!("Successfully built: {}", image_tag);
-                println!();
-                println!("Run with: cubo run {}", image_tag);
+                cache.save(image_tag, &key);
+                say(&format!("Successfully built: {}", image_tag));
+                say("");
+                say(&format!("Run with: cubo run {}", image_tag));
                 Ok(())
             }
             Err(e) => {
                 error!("Build failed: {}", e);
-                println!("Build failed: {}", e);
-                println!();
-                println!("Make sure:");
-                println!("  1. Base image is imported: cubo image import <ref> <tar>");
-                println!("  2. You have root privileges (needed for chroot)");
-                println!("  3. All COPY source files exist in build context");
+                say(&format!("Build failed: {}", e));
+                say("");
+                say("Make sure:");
+                say("  1. Base image is imported: cubo image import <ref> <tar>");
+                say("  2. You have root privileges (needed for chroot)");
+                say("  3. All COPY source files exist in build context");
                 Err(e)
             }
         }
     }
 }
 
+/// Write a status of `--progress json` as a line of stdout
+fn print_status(status: &SolveStatus) {
+    if let Ok(line) = serde_json::to_string(status) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", line).and_then(|_| stdout.flush());
+    }
+}
+
+/// Limits of the RUN steps: the options of `cubo build`, else the `[build]` table of
+/// Cubofile.toml
+fn step_limits(args: &BuildArgs, options: &BuildOptions) -> Result<StepLimits> {
+    let timeout = match args.step_timeout.as_ref().or(options.step_timeout.as_ref()) {
+        Some(spec) => Some(
+            parse_duration(spec)
+                .and_then(|timeout| timeout.to_std().ok())
+                .filter(|timeout| !timeout.is_zero())
+                .ok_or_else(|| CuboError::InvalidConfiguration(format!(
+                    "Invalid step timeout '{}' (expected e.g. 90s, 10m or 1h)",
+                    spec
+                )))?,
+        ),
+        None => None,
+    };
+    let memory = match args.step_memory.as_ref().or(options.step_memory.as_ref()) {
+        Some(spec) => Some(
+            parse_byte_size(spec)
+                .filter(|bytes| *bytes > 0)
+                .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid step memory limit '{}'", spec)))?,
+        ),
+        None => None,
+    };
+    let cpus = args.step_cpus.or(options.step_cpus);
+    if cpus.is_some_and(|cpus| !cpus.is_finite() || cpus <= 0.0) {
+        return Err(CuboError::InvalidConfiguration("The step CPU limit must be above 0".to_string()));
+    }
+    Ok(StepLimits { timeout, memory, cpus })
+}
+
+/// Parse `--build-arg KEY=VALUE` options
+pub fn parse_build_args(specs: &[String]) -> Result<BTreeMap<String, String>> {
+    parse_key_values(specs, "build argument")
+}
+
+/// Parse `--label KEY=VALUE` options
+pub fn parse_labels(specs: &[String]) -> Result<BTreeMap<String, String>> {
+    parse_key_values(specs, "label")
+}
+
+fn parse_key_values(specs: &[String], what: &str) -> Result<BTreeMap<String, String>> {
+    specs
+        .iter()
+        .map(|spec| match spec.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(CuboError::InvalidConfiguration(format!(
+                "Invalid {} '{}' (expected KEY=VALUE)",
+                what, spec
+            ))),
+        })
+        .collect()
+}
+
+/// Digest of everything a build depends on: the build file, every file of the context, the
+/// build arguments, the labels and the base image. A build with the key of the image already there
+/// would make the same image.
+pub fn cache_key(
+    context: &Path,
+    build_file: &Path,
+    image_store: &ImageStore,
+    base_image: &str,
+    build_args: &BTreeMap<String, String>,
+    labels: &BTreeMap<String, String>,
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(fs::read(build_file)?);
+    for (key, value) in build_args {
+        hasher.update(format!("\0arg {}={}", key, value));
+    }
+    for (key, value) in labels {
+        hasher.update(format!("\0label {}={}", key, value));
+    }
+    let base_digest = image_digest(image_store, base_image).unwrap_or_default();
+    hasher.update(format!("\0base {}={}", base_image, base_digest));
+    hash_dir(&mut hasher, context, Path::new(""))?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+fn hash_dir(hasher: &mut Sha256, dir: &Path, relative: &Path) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            hasher.update(format!("\0dir {}", name.display()));
+            hash_dir(hasher, &path, &name)?;
+        } else if file_type.is_symlink() {
+            hasher.update(format!("\0link {}={}", name.display(), fs::read_link(&path)?.display()));
+        } else {
+            hasher.update(format!("\0file {} {:o}\0", name.display(), entry.metadata()?.permissions().mode()));
+            hasher.update(fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
+
+/// Cache keys of the images built by cubo, under `images/build-cache/`
+struct BuildCache {
+    dir: PathBuf,
+}
+
+impl BuildCache {
+    fn new(image_store_root: &Path) -> Self {
+        Self { dir: image_store_root.join("build-cache") }
+    }
+
+    fn path(&self, image: &str) -> PathBuf {
+        self.dir.join(format!("{}.key", image.replace(['/', ':'], "_")))
+    }
+
+    fn get(&self, image: &str) -> Option<String> {
+        fs::read_to_string(self.path(image)).ok().map(|key| key.trim().to_string())
+    }
+
+    /// Remember the key of a finished build; without it the next build just runs again
+    fn save(&self, image: &str, key: &str) {
+        if let Err(e) = fs::create_dir_all(&self.dir).and_then(|_| fs::write(self.path(image), key)) {
+            warn!("Failed to record the build cache key of {}: {}", image, e);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::runtime::RuntimeConfig;
     use tempfile::TempDir;
     use std::fs;
+    use clap::Parser;
 
     #[test]
     fn test_resolve_tag_with_explicit_tag() {
@@ -225,9 +515,17 @@ mod tests {
             tag: None,
             file: None,
             no_cache: false,
+            build_arg: vec![],
+            label: vec![],
+            watch: false,
+            restart_container: None,
+            progress: ProgressMode::Plain,
+            step_timeout: None,
+            step_memory: None,
+            step_cpus: None,
         };
 
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_err());
     }
 
@@ -239,9 +537,17 @@ mod tests {
             tag: None,
             file: None,
             no_cache: false,
+            build_arg: vec![],
+            label: vec![],
+            watch: false,
+            restart_container: None,
+            progress: ProgressMode::Plain,
+            step_timeout: None,
+            step_memory: None,
+            step_cpus: None,
         };
 
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(err.to_string().contains("No Cubofile"));
@@ -255,12 +561,86 @@ mod tests {
             tag: None,
             file: Some("nonexistent.toml".to_string()),
             no_cache: false,
+            build_arg: vec![],
+            label: vec![],
+            watch: false,
+            restart_container: None,
+            progress: ProgressMode::Plain,
+            step_timeout: None,
+            step_memory: None,
+            step_cpus: None,
         };
 
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(err.to_string().contains("not found"));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_parse_build_args() {
+        let args = parse_build_args(&["VERSION=1.2".to_string(), "EMPTY=".to_string()]).unwrap();
+        assert_eq!(args["VERSION"], "1.2");
+        assert_eq!(args["EMPTY"], "");
+        assert!(parse_build_args(&["VERSION".to_string()]).is_err());
+        assert!(parse_build_args(&["=1".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_step_limits() {
+        let args = |extra: &[&str]| match crate::cli::Cli::parse_from([&["cubo", "build", "."], extra].concat()).command {
+            crate::cli::Commands::Build(args) => args,
+            _ => unreachable!(),
+        };
+        let options = BuildOptions {
+            step_timeout: Some("10m".to_string()),
+            step_memory: Some("1g".to_string()),
+            step_cpus: None,
+        };
+
+        let limits = step_limits(&args(&[]), &options).unwrap();
+        assert_eq!(limits.timeout, Some(std::time::Duration::from_secs(600)));
+        assert_eq!(limits.memory, Some(1024 * 1024 * 1024));
+        assert!(limits.cpus.is_none());
+
+        let limits = step_limits(&args(&["--step-timeout", "90s", "--step-cpus", "0.5"]), &options).unwrap();
+        assert_eq!(limits.timeout, Some(std::time::Duration::from_secs(90)));
+        assert_eq!(limits.cpus, Some(0.5));
+
+        assert_eq!(step_limits(&args(&[]), &BuildOptions::default()).unwrap(), StepLimits::default());
+        assert!(step_limits(&args(&["--step-timeout", "forever"]), &options).is_err());
+        assert!(step_limits(&args(&["--step-timeout", "0s"]), &options).is_err());
+        assert!(step_limits(&args(&["--step-cpus", "0"]), &options).is_err());
+    }
+
+    #[test]
+    fn test_cache_key_follows_the_inputs() {
+        let temp = TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        let context = temp.path().join("app");
+        fs::create_dir_all(context.join("src")).unwrap();
+        fs::write(context.join("Cubofile"), "BASE alpine:latest\nCOPY src /app\n").unwrap();
+        fs::write(context.join("src/main.sh"), "echo hi").unwrap();
+        let build_file = context.join("Cubofile");
+        let no_args = BTreeMap::new();
+
+        let key = cache_key(&context, &build_file, &store, "alpine:latest", &no_args, &no_args).unwrap();
+        assert_eq!(key, cache_key(&context, &build_file, &store, "alpine:latest", &no_args, &no_args).unwrap());
+
+        let args = parse_build_args(&["MODE=dev".to_string()]).unwrap();
+        assert_ne!(key, cache_key(&context, &build_file, &store, "alpine:latest", &args, &no_args).unwrap());
+        let labels = parse_labels(&["MODE=dev".to_string()]).unwrap();
+        let labeled = cache_key(&context, &build_file, &store, "alpine:latest", &no_args, &labels).unwrap();
+        assert_ne!(key, labeled);
+        assert_ne!(labeled, cache_key(&context, &build_file, &store, "alpine:latest", &args, &no_args).unwrap());
+
+        fs::write(context.join("src/main.sh"), "echo bye").unwrap();
+        let changed = cache_key(&context, &build_file, &store, "alpine:latest", &no_args, &no_args).unwrap();
+        assert_ne!(key, changed);
+
+        let cache = BuildCache::new(&temp.path().join("images"));
+        assert_eq!(cache.get("app:latest"), None);
+        cache.save("app:latest", &changed);
+        assert_eq!(cache.get("app:latest"), Some(changed));
+    }
+}
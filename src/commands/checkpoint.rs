@@ -0,0 +1,54 @@
+use crate::cli::CheckpointArgs;
+use crate::commands::context::CuboContext;
+use crate::error::Result;
+use tracing::info;
+
+pub async fn execute(ctx: &CuboContext, args: CheckpointArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+
+    let container_id = runtime.resolve(&args.container).await?;
+    runtime.checkpoint_container(&container_id, args.leave_running).await?;
+
+    info!("Checkpointed container: {}", args.container);
+    println!("{}", args.container);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CuboError;
+    use crate::container::Container;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_missing_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let args = CheckpointArgs { container: "ghost".to_string(), leave_running: false };
+        assert!(matches!(execute(&CuboContext::new(RuntimeConfig::from_env()), args).await, Err(CuboError::ContainerNotFound(_))));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_stopped_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()])
+            .with_name("web".to_string());
+        runtime.create_container(container).await.unwrap();
+
+        let args = CheckpointArgs { container: "web".to_string(), leave_running: true };
+        assert!(matches!(execute(&CuboContext::new(RuntimeConfig::from_env()), args).await, Err(CuboError::ContainerNotRunning(_))));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
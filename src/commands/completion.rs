@@ -0,0 +1,135 @@
+use std::io::Write;
+use std::path::Path;
+
+use clap::CommandFactory;
+use clap_complete::env::Shells;
+use clap_complete::CompletionCandidate;
+
+use crate::cli::{Cli, CompletionArgs, ManArgs};
+use crate::container::api::ImageRepository;
+use crate::container::container_store;
+use crate::container::image_store::ImageStore;
+use crate::container::runtime::RuntimeConfig;
+use crate::error::{CuboError, Result};
+
+/// Variable the shell sets when it calls back into cubo for candidates
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Print the script that registers cubo's completions with the shell. The script calls
+/// back into this binary, so names of containers and images are completed as they are.
+pub async fn execute(args: CompletionArgs) -> Result<()> {
+    let shell = args.shell.to_string();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&shell)
+        .ok_or_else(|| CuboError::InvalidConfiguration(format!("Unsupported shell: {}", shell)))?;
+    let exe = std::env::current_exe()?;
+
+    let mut stdout = std::io::stdout().lock();
+    completer.write_registration(COMPLETE_VAR, "cubo", "cubo", &exe.to_string_lossy(), &mut stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Print the cubo(1) page, or write a page per subcommand to a directory
+pub async fn man(args: ManArgs) -> Result<()> {
+    let cmd = Cli::command();
+    match args.output {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir)?;
+            clap_mangen::generate_to(cmd, &dir)?;
+            eprintln!("Wrote man pages to {}", dir);
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            clap_mangen::Man::new(cmd).render(&mut stdout)?;
+        }
+    }
+    Ok(())
+}
+
+/// Names and short IDs of the containers, for arguments taking a container
+pub fn container_candidates() -> Vec<CompletionCandidate> {
+    let config = RuntimeConfig::from_env();
+    let containers = container_store::load_all(&config.root_dir).unwrap_or_default();
+    let mut candidates = containers_to_candidates(containers.into_values().collect());
+    candidates.sort_by(|a, b| a.get_value().cmp(b.get_value()));
+    candidates
+}
+
+fn containers_to_candidates(containers: Vec<crate::container::Container>) -> Vec<CompletionCandidate> {
+    let mut candidates = Vec::new();
+    for container in containers {
+        let help = format!("{} ({})", container.blueprint, container.status);
+        if let Some(ref name) = container.name {
+            candidates.push(CompletionCandidate::new(name).help(Some(help.clone().into())));
+        }
+        let short_id = &container.id[..container.id.len().min(12)];
+        candidates.push(CompletionCandidate::new(short_id).help(Some(help.into())));
+    }
+    candidates
+}
+
+/// References of the images in the local store, for arguments taking an image
+pub fn image_candidates() -> Vec<CompletionCandidate> {
+    let config = RuntimeConfig::from_env();
+    images_in(&config.root_dir)
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+fn images_in(root_dir: &Path) -> Vec<String> {
+    ImageStore::new(root_dir.join("images")).map(|store| sorted_images(&store)).unwrap_or_default()
+}
+
+/// The image names of any repository, sorted
+fn sorted_images(images: &dyn ImageRepository) -> Vec<String> {
+    let mut names = images.list_images().unwrap_or_default();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+
+    #[test]
+    fn test_container_candidates_include_names_and_short_ids() {
+        let mut named = Container::new("alpine:latest".to_string(), vec!["sh".to_string()]);
+        named.name = Some("web".to_string());
+        let unnamed = Container::new("busybox:latest".to_string(), vec!["true".to_string()]);
+
+        let candidates = containers_to_candidates(vec![named.clone(), unnamed.clone()]);
+        let values: Vec<String> = candidates.iter().map(|c| c.get_value().to_string_lossy().to_string()).collect();
+        assert_eq!(values, vec!["web".to_string(), named.id[..12].to_string(), unnamed.id[..12].to_string()]);
+    }
+
+    #[test]
+    fn test_images_in_empty_root() {
+        let temp = tempfile::TempDir::new().unwrap();
+        assert!(images_in(temp.path()).is_empty());
+    }
+
+    #[test]
+    fn test_sorted_images_of_memory_repository() {
+        use crate::container::image_store::{ImageConfig, ImageManifest};
+        use crate::container::memory::MemoryImageRepository;
+
+        let images = MemoryImageRepository::default();
+        for reference in ["nginx:latest", "alpine:3.19"] {
+            images
+                .save_manifest(&ImageManifest {
+                    reference: reference.to_string(),
+                    parent: None,
+                    layers: vec![],
+                    config: ImageConfig { cmd: None, env: None, working_dir: None, exposed_ports: None, stop_signal: None, labels: None },
+                    annotations: None,
+                    repo_digest: None,
+                })
+                .unwrap();
+        }
+        assert_eq!(sorted_images(&images), ["alpine:3.19", "nginx:latest"]);
+    }
+}
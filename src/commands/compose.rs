@@ -0,0 +1,496 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::{
+    BuildArgs, ComposeArgs, ComposeCommands, ComposeDownArgs, ComposeLogsArgs, ComposePsArgs, ComposeUpArgs,
+};
+use crate::commands::context::CuboContext;
+use crate::commands::logs::{follow_logs, get_log_path, read_lines, LogView, StoredLine};
+use crate::commands::ps::print_containers;
+use crate::commands::run::{parse_port, parse_volume, validate_config};
+use crate::container::build_progress::ProgressMode;
+use crate::container::compose::{
+    container_name, network_name, normalize_project_name, service_image, ComposeFile, ProjectState, Service,
+    COMPOSE_FILE,
+};
+use crate::container::image_store::ImageStore;
+use crate::container::logging::log_segments;
+use crate::container::network::{NetworkStore, Subnet};
+use crate::container::registry::RegistryClient;
+use crate::container::runtime::ContainerRuntime;
+use crate::container::{Container, LogDriverKind, MountType, NetworkMode};
+use crate::error::{CuboError, Result};
+use tracing::{info, warn};
+
+/// A compose file with the project it runs as
+struct Project {
+    name: String,
+    /// Directory of the compose file, which relative paths are resolved against
+    dir: PathBuf,
+    compose: ComposeFile,
+    root_dir: PathBuf,
+    /// Pool the project's networks get their subnets from, None for the default
+    network_pool: Option<Subnet>,
+}
+
+/// Where a service's log is and how its lines are printed
+struct ServiceLog {
+    view: LogView,
+    container: Container,
+    path: PathBuf,
+}
+
+pub async fn execute(ctx: &CuboContext, args: ComposeArgs) -> Result<()> {
+    let file = PathBuf::from(args.file.as_deref().unwrap_or(COMPOSE_FILE));
+    let compose = ComposeFile::from_file(&file)?;
+    let name = match args.project_name {
+        Some(ref name) => normalize_project_name(name)?,
+        None => compose.project_name(&file)?,
+    };
+    let dir = file
+        .canonicalize()
+        .ok()
+        .and_then(|path| path.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    let config = ctx.config();
+    let runtime = ctx.runtime()?;
+    let network_pool = config.network_pool.as_deref().map(Subnet::parse).transpose()?;
+    let project = Project { name, dir, compose, root_dir: config.root_dir.clone(), network_pool };
+
+    match args.command {
+        ComposeCommands::Up(up) => compose_up(ctx, &project, up).await,
+        ComposeCommands::Down(down) => compose_down(runtime, &project, down).await,
+        ComposeCommands::Logs(logs) => compose_logs(runtime, &project, logs).await,
+        ComposeCommands::Ps(ps) => compose_ps(runtime, &project, ps).await,
+    }
+}
+
+async fn compose_up(ctx: &CuboContext, project: &Project, args: ComposeUpArgs) -> Result<()> {
+    let order = project.compose.start_order()?;
+    let mut state = ProjectState::load(&project.root_dir, &project.name)?;
+    create_networks(project, &mut state)?;
+    state.save(&project.root_dir, &project.name)?;
+
+    let runtime = ctx.runtime()?;
+    let image_store = ctx.image_store()?;
+    for service_name in &order {
+        let service = &project.compose.services[service_name];
+        let existing = match state.container(service_name) {
+            Some(id) => runtime.get_container(id).await.ok(),
+            None => None,
+        };
+
+        let id = match existing {
+            Some(container) if container.is_running() => {
+                println!("{} is up-to-date", service_name);
+                continue;
+            }
+            Some(container) => container.id,
+            None => {
+                let image = ensure_image(ctx, project, service_name, service, image_store, args.build).await?;
+                let container = service_container(project, service_name, service, &image, image_store)?;
+                let id = runtime.create_container(container).await?;
+                state.set_container(service_name, id.clone());
+                state.save(&project.root_dir, &project.name)?;
+                for network in service_networks(&project.name, service).iter().skip(1) {
+                    runtime.connect_network(&id, network, None, None).await?;
+                }
+                id
+            }
+        };
+
+        runtime.start_container(&id, true).await?;
+        println!("Started {} ({})", service_name, &id[..12]);
+    }
+
+    if !args.detach {
+        follow_services(runtime, project, &order, false).await?;
+    }
+    Ok(())
+}
+
+/// Create the project's default network and the networks of its `networks` table
+fn create_networks(project: &Project, state: &mut ProjectState) -> Result<()> {
+    let mut store = NetworkStore::new(project.root_dir.join("networks"))?;
+    if let Some(pool) = project.network_pool {
+        store = store.with_pool(pool);
+    }
+    let mut wanted = vec![(network_name(&project.name, None), None)];
+    for (name, spec) in &project.compose.networks {
+        wanted.push((network_name(&project.name, Some(name)), spec.subnet.as_deref()));
+    }
+
+    for (name, subnet) in wanted {
+        if store.exists(&name) {
+            continue;
+        }
+        let network = store.create(&name, subnet, None)?;
+        info!("Created network {} ({})", network.name, network.subnet);
+        if !state.networks.contains(&name) {
+            state.networks.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// Networks a service joins, the one it is created on first
+fn service_networks(project: &str, service: &Service) -> Vec<String> {
+    if service.networks.is_empty() {
+        return vec![network_name(project, None)];
+    }
+    service.networks.iter().map(|network| network_name(project, Some(network))).collect()
+}
+
+/// Build the service's image when asked to or when it is missing, else pull it if missing
+async fn ensure_image(
+    ctx: &CuboContext,
+    project: &Project,
+    service_name: &str,
+    service: &Service,
+    image_store: &ImageStore,
+    rebuild: bool,
+) -> Result<String> {
+    let image = service_image(&project.name, service_name, service);
+    match service.build {
+        Some(ref build) if rebuild || !image_store.has_image(&image) => {
+            println!("Building {} as {}", service_name, image);
+            crate::commands::build::execute(ctx, BuildArgs {
+                path: project.dir.join(build.context()).to_string_lossy().to_string(),
+                tag: Some(image.clone()),
+                file: build.file().map(str::to_string),
+                no_cache: false,
+                build_arg: vec![],
+                label: vec![],
+                watch: false,
+                restart_container: None,
+                progress: ProgressMode::Plain,
+                step_timeout: None,
+                step_memory: None,
+                step_cpus: None,
+            })
+            .await?;
+        }
+        None if !image_store.has_image(&image) => {
+            println!("Pulling {} ({})", service_name, image);
+            RegistryClient::new(image_store)
+                .with_config(ctx.config())
+                .pull(&image)
+                .await?;
+        }
+        _ => {}
+    }
+    Ok(image)
+}
+
+/// The container of a service: named after the project and reachable by the service name
+fn service_container(
+    project: &Project,
+    service_name: &str,
+    service: &Service,
+    image: &str,
+    image_store: &ImageStore,
+) -> Result<Container> {
+    let command = match service.command {
+        Some(ref command) => command.clone(),
+        None => image_store
+            .get_config(image)
+            .ok()
+            .and_then(|config| config.cmd)
+            .unwrap_or_else(|| vec!["/bin/sh".to_string()]),
+    };
+    let network = service_networks(&project.name, service).remove(0);
+
+    let mut container = Container::new(image.to_string(), command)
+        .with_name(container_name(&project.name, service_name))
+        .with_alias(service_name.to_string())
+        .with_network_mode(NetworkMode::Custom(network));
+
+    if let Some(ref workdir) = service.workdir {
+        container = container.with_workdir(workdir.clone());
+    }
+    for (key, value) in &service.env {
+        container = container.with_env(key.clone(), value.clone());
+    }
+    for port in &service.ports {
+        let mapping = parse_port(port).ok_or_else(|| {
+            CuboError::InvalidConfiguration(format!("Invalid port '{}' in service {}", port, service_name))
+        })?;
+        container = container.with_port(mapping);
+    }
+    for volume in &service.volumes {
+        let mut mount = parse_volume(volume).ok_or_else(|| {
+            CuboError::InvalidConfiguration(format!("Invalid volume '{}' in service {}", volume, service_name))
+        })?;
+        if matches!(mount.mount_type, MountType::Bind) && Path::new(&mount.host_path).is_relative() {
+            let relative: PathBuf = Path::new(&mount.host_path)
+                .components()
+                .filter(|c| !matches!(c, Component::CurDir))
+                .collect();
+            mount.host_path = project.dir.join(relative).to_string_lossy().to_string();
+        }
+        container = container.with_volume(mount);
+    }
+
+    validate_config(&container.config)?;
+    Ok(container)
+}
+
+async fn compose_down(runtime: &ContainerRuntime, project: &Project, args: ComposeDownArgs) -> Result<()> {
+    let state = ProjectState::load(&project.root_dir, &project.name)?;
+    let timeout = args.timeout.map(Duration::from_secs);
+
+    // Dependents go first
+    for (service_name, id) in state.containers.iter().rev() {
+        if runtime.get_container(id).await.is_err() {
+            continue;
+        }
+        runtime.stop_container(id, timeout).await?;
+        runtime.remove_container(id, true).await?;
+        println!("Removed {}", service_name);
+    }
+
+    let store = NetworkStore::new(project.root_dir.join("networks"))?;
+    for name in &state.networks {
+        match store.remove(name) {
+            Ok(network) => {
+                if let Err(e) = crate::container::network::delete_bridge(&network) {
+                    warn!("Failed to delete bridge {}: {}", network.bridge, e);
+                }
+                println!("Removed network {}", name);
+            }
+            Err(e) => warn!("Failed to remove network {}: {}", name, e),
+        }
+    }
+
+    ProjectState::remove(&project.root_dir, &project.name);
+    Ok(())
+}
+
+async fn compose_logs(runtime: &ContainerRuntime, project: &Project, args: ComposeLogsArgs) -> Result<()> {
+    if let Some(unknown) = args.services.iter().find(|s| !project.compose.services.contains_key(*s)) {
+        return Err(CuboError::InvalidConfiguration(format!("No such service: {}", unknown)));
+    }
+    let services = match args.services.is_empty() {
+        true => project.compose.start_order()?,
+        false => args.services,
+    };
+
+    if args.follow {
+        return follow_services(runtime, project, &services, args.timestamps).await;
+    }
+
+    // Interleave the services' lines by time, like the output came in
+    let logs = service_logs(runtime, project, &services, args.timestamps).await?;
+    let mut lines = Vec::new();
+    for log in &logs {
+        let stored: Vec<StoredLine> =
+            read_lines(&log_segments(&log.path))?.iter().map(|l| StoredLine::parse(l)).collect();
+        let skip = args.tail.map_or(0, |n| stored.len().saturating_sub(n));
+        lines.extend(stored.into_iter().skip(skip).map(|line| (&log.view, line)));
+    }
+    lines.sort_by_key(|(_, line)| line.timestamp);
+    for (view, line) in &lines {
+        view.emit(line);
+    }
+    Ok(())
+}
+
+/// Follow the logs of `services` until all of them have exited
+async fn follow_services(
+    runtime: &ContainerRuntime,
+    project: &Project,
+    services: &[String],
+    timestamps: bool,
+) -> Result<()> {
+    let mut followers = tokio::task::JoinSet::new();
+    for log in service_logs(runtime, project, services, timestamps).await? {
+        let pid = if log.container.is_running() { log.container.pid } else { None };
+        followers.spawn(async move { follow_logs(&log.path, None, &log.view, pid).await });
+    }
+    while let Some(result) = followers.join_next().await {
+        if let Ok(Err(e)) = result {
+            warn!("Failed to follow logs: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Log view and log file of each service that has a readable log, prefixed with the
+/// service name padded to the longest one
+async fn service_logs(
+    runtime: &ContainerRuntime,
+    project: &Project,
+    services: &[String],
+    timestamps: bool,
+) -> Result<Vec<ServiceLog>> {
+    let state = ProjectState::load(&project.root_dir, &project.name)?;
+    let width = services.iter().map(String::len).max().unwrap_or(0);
+    let mut logs = Vec::new();
+    for service_name in services {
+        let Some(container) = state.container(service_name) else {
+            continue;
+        };
+        let Ok(container) = runtime.get_container(container).await else {
+            continue;
+        };
+        if container.config.log_driver != LogDriverKind::JsonFile {
+            warn!("Service {} uses the {} log driver; skipping its logs", service_name, container.config.log_driver);
+            continue;
+        }
+        let path = get_log_path(&project.root_dir, &container.id);
+        if !path.exists() {
+            continue;
+        }
+        let view = LogView::prefixed(format!("{:<width$} | ", service_name, width = width), timestamps);
+        logs.push(ServiceLog { view, container, path });
+    }
+    Ok(logs)
+}
+
+async fn compose_ps(runtime: &ContainerRuntime, project: &Project, args: ComposePsArgs) -> Result<()> {
+    let state = ProjectState::load(&project.root_dir, &project.name)?;
+    let mut containers = Vec::new();
+    for (_, id) in &state.containers {
+        if let Ok(container) = runtime.get_container(id).await {
+            if args.all || container.is_running() {
+                containers.push(container);
+            }
+        }
+    }
+    print_containers(containers, args.all);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::container::image_store::{ImageConfig, ImageManifest};
+    use tempfile::TempDir;
+
+    const SHOP: &str = r#"
+[services.web]
+image = "nginx:latest"
+env = { MODE = "prod" }
+ports = ["8080:80"]
+volumes = ["./static:/srv/static:ro", "/var/log/web:/var/log/nginx"]
+networks = ["front", "back"]
+
+[services.worker]
+image = "worker:latest"
+command = ["work", "--once"]
+depends_on = ["web"]
+
+[networks.front]
+[networks.back]
+"#;
+
+    fn project(temp_dir: &TempDir) -> Project {
+        Project {
+            name: "shop".to_string(),
+            dir: temp_dir.path().join("shop"),
+            compose: ComposeFile::from_string(SHOP).unwrap(),
+            root_dir: temp_dir.path().join("root"),
+            network_pool: None,
+        }
+    }
+
+    fn image_store(temp_dir: &TempDir) -> ImageStore {
+        let store = ImageStore::new(temp_dir.path().join("root").join("images")).unwrap();
+        store
+            .save_manifest(&ImageManifest {
+                reference: "nginx:latest".to_string(),
+                parent: None,
+                layers: Vec::new(),
+                config: ImageConfig {
+                    cmd: Some(vec!["nginx".to_string(), "-g".to_string(), "daemon off;".to_string()]),
+                    env: None,
+                    working_dir: None,
+                    exposed_ports: None,
+                    stop_signal: None,
+                    labels: None,
+                },
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+        store
+    }
+
+    #[test]
+    fn test_service_container() {
+        let temp_dir = TempDir::new().unwrap();
+        let project = project(&temp_dir);
+        let store = image_store(&temp_dir);
+
+        let web = &project.compose.services["web"];
+        let container = service_container(&project, "web", web, "nginx:latest", &store).unwrap();
+        assert_eq!(container.name.as_deref(), Some("shop-web"));
+        assert_eq!(container.config.aliases, ["web"]);
+        assert_eq!(container.command, ["nginx", "-g", "daemon off;"]);
+        assert_eq!(container.config.network_mode, NetworkMode::Custom("shop_front".to_string()));
+        assert_eq!(container.config.env_vars["MODE"], "prod");
+        assert_eq!(container.config.ports[0].host_port, 8080);
+        let static_dir = temp_dir.path().join("shop").join("static");
+        assert_eq!(container.config.volume_mounts[0].host_path, static_dir.to_string_lossy());
+        assert!(container.config.volume_mounts[0].read_only);
+        assert_eq!(container.config.volume_mounts[1].host_path, "/var/log/web");
+        assert_eq!(service_networks("shop", web), ["shop_front", "shop_back"]);
+
+        let worker = &project.compose.services["worker"];
+        let container = service_container(&project, "worker", worker, "worker:latest", &store).unwrap();
+        assert_eq!(container.command, ["work", "--once"]);
+        assert_eq!(container.config.network_mode, NetworkMode::Custom("shop_default".to_string()));
+
+        let broken = Service { ports: vec!["nope".to_string()], ..Service::default() };
+        let err = service_container(&project, "broken", &broken, "nginx:latest", &store).unwrap_err();
+        assert!(err.to_string().contains("Invalid port 'nope' in service broken"));
+    }
+
+    #[test]
+    fn test_create_networks() {
+        let temp_dir = TempDir::new().unwrap();
+        let project = project(&temp_dir);
+        let store = NetworkStore::new(project.root_dir.join("networks")).unwrap();
+        store.create("shop_default", None, None).unwrap();
+
+        let mut state = ProjectState::default();
+        create_networks(&project, &mut state).unwrap();
+        // The default network already existed, so down leaves it alone
+        assert_eq!(state.networks, ["shop_back", "shop_front"]);
+        assert!(store.exists("shop_front") && store.exists("shop_back"));
+
+        create_networks(&project, &mut state).unwrap();
+        assert_eq!(state.networks.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_down_removes_project() {
+        let temp_dir = TempDir::new().unwrap();
+        let project = project(&temp_dir);
+        let runtime = ContainerRuntime::new(RuntimeConfig {
+            root_dir: project.root_dir.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+        let store = image_store(&temp_dir);
+
+        let mut state = ProjectState::default();
+        create_networks(&project, &mut state).unwrap();
+        let web = &project.compose.services["web"];
+        let container = service_container(&project, "web", web, "nginx:latest", &store).unwrap();
+        let id = runtime.create_container(container).await.unwrap();
+        state.set_container("web", id.clone());
+        state.set_container("gone", "missing".to_string());
+        state.save(&project.root_dir, &project.name).unwrap();
+
+        compose_ps(&runtime, &project, ComposePsArgs { all: true }).await.unwrap();
+        compose_down(&runtime, &project, ComposeDownArgs { timeout: Some(0) }).await.unwrap();
+
+        assert!(runtime.get_container(&id).await.is_err());
+        let networks = NetworkStore::new(project.root_dir.join("networks")).unwrap();
+        assert!(networks.list().unwrap().is_empty());
+        assert_eq!(ProjectState::load(&project.root_dir, &project.name).unwrap(), ProjectState::default());
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::Utc;
+
+use crate::cli::{ContainerArgs, ContainerCommands, ContainerPruneArgs};
+use crate::commands::context::CuboContext;
+use crate::commands::logs::parse_time_spec;
+use crate::container::layer_cache::LayerCache;
+use crate::error::Result;
+
+pub async fn execute(ctx: &CuboContext, args: ContainerArgs) -> Result<()> {
+    match args.command {
+        ContainerCommands::Prune(args) => prune(ctx, args).await,
+    }
+}
+
+async fn prune(ctx: &CuboContext, args: ContainerPruneArgs) -> Result<()> {
+    let until = args.until.as_deref().map(|s| parse_time_spec(s, Utc::now())).transpose()?;
+    let runtime = ctx.runtime()?;
+    print_removed(&runtime.prune_containers(until).await?);
+    Ok(())
+}
+
+/// Remove the exited containers older than `cleanup.exited_ttl` and the extracted layers no
+/// image uses any more, as cubod does when it starts
+pub async fn gc(ctx: &CuboContext) -> Result<()> {
+    let image_store = ctx.image_store()?;
+    let layers = LayerCache::new(image_store).prune(image_store)?;
+    eprintln!("Removed {} extracted layer{}", layers, if layers == 1 { "" } else { "s" });
+
+    if ctx.config().exited_ttl.is_none() {
+        eprintln!("No cleanup.exited_ttl configured, no containers to collect");
+        return Ok(());
+    }
+    let runtime = ctx.runtime()?;
+    print_removed(&runtime.collect_garbage().await?);
+    Ok(())
+}
+
+fn print_removed(removed: &[String]) {
+    for id in removed {
+        println!("{}", id);
+    }
+    eprintln!("Removed {} container{}", removed.len(), if removed.len() == 1 { "" } else { "s" });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use crate::container::{Container, ContainerStatus};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_prune_rejects_invalid_until() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+        let result = prune(&CuboContext::new(RuntimeConfig::from_env()), ContainerPruneArgs { until: Some("last week".to_string()) }).await;
+        std::env::remove_var("CUBO_ROOT");
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_prune_removes_exited_containers() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let mut container = Container::new("test:latest".to_string(), vec!["true".to_string()]);
+        container.update_status(ContainerStatus::Stopped);
+        let stopped = runtime.create_container(container).await.unwrap();
+        let created = runtime
+            .create_container(Container::new("test:latest".to_string(), vec!["true".to_string()]))
+            .await
+            .unwrap();
+
+        prune(&CuboContext::new(RuntimeConfig::from_env()), ContainerPruneArgs { until: None }).await.unwrap();
+        std::env::remove_var("CUBO_ROOT");
+        assert!(!temp.path().join(&stopped).exists());
+        assert!(temp.path().join(&created).exists());
+    }
+}
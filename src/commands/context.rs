@@ -0,0 +1,176 @@
+//! What the commands share: the configuration, read once from the config file, the
+//! environment and the global flags, and the runtime and image store opened from it the
+//! first time a command needs them
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+use crate::cli::Cli;
+use crate::config;
+use crate::container::image_store::ImageStore;
+use crate::container::registry::PullPolicy;
+use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+use crate::error::Result;
+
+pub struct CuboContext {
+    config: RuntimeConfig,
+    /// The config file the configuration was read from, if any
+    config_file: Option<PathBuf>,
+    runtime: OnceLock<ContainerRuntime>,
+    image_store: OnceLock<ImageStore>,
+}
+
+impl CuboContext {
+    pub fn new(config: RuntimeConfig) -> Self {
+        Self { config, config_file: None, runtime: OnceLock::new(), image_store: OnceLock::new() }
+    }
+
+    /// The configuration of a command line: its global flags over the environment over the
+    /// config file
+    pub fn init(cli: &Cli) -> Self {
+        let config_file = cli.config.as_ref().map(PathBuf::from).or_else(config::config_path);
+        let mut config = RuntimeConfig::load(config_file.clone());
+        if let Some(ref root) = cli.root_dir {
+            config.root_dir = PathBuf::from(root);
+        }
+        if let Some(ref executor) = cli.executor {
+            match executor.parse() {
+                Ok(kind) => config.executor = kind,
+                Err(e) => warn!("Ignoring --executor: {}", e),
+            }
+        }
+        if let Some(ref snapshotter) = cli.snapshotter {
+            match snapshotter.parse() {
+                Ok(kind) => config.snapshotter = kind,
+                Err(e) => warn!("Ignoring --snapshotter: {}", e),
+            }
+        }
+        if let Some(ref provision) = cli.provision {
+            match provision.parse() {
+                Ok(mode) => config.provision = mode,
+                Err(e) => warn!("Ignoring --provision: {}", e),
+            }
+        }
+        if cli.offline {
+            config.pull_policy = PullPolicy::Never;
+        } else if let Some(ref policy) = cli.pull_policy {
+            match policy.parse() {
+                Ok(policy) => config.pull_policy = policy,
+                Err(e) => warn!("Ignoring --pull-policy: {}", e),
+            }
+        }
+        Self { config_file: config_file.filter(|file| file.exists()), ..Self::new(config) }
+    }
+
+    /// Global flags giving another cubo process, such as the supervisor of a container, this
+    /// context: the same config file with what the flags and environment resolved on top
+    pub fn global_args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = Vec::new();
+        if let Some(ref file) = self.config_file {
+            args.extend(["--config".into(), file.into()]);
+        }
+        args.extend(["--root-dir".into(), self.config.root_dir.clone().into()]);
+        for (flag, value) in [
+            ("--executor", self.config.executor.to_string()),
+            ("--snapshotter", self.config.snapshotter.to_string()),
+            ("--provision", self.config.provision.to_string()),
+            ("--pull-policy", self.config.pull_policy.to_string()),
+        ] {
+            args.extend([flag.into(), value.into()]);
+        }
+        args
+    }
+
+    pub fn config(&self) -> &RuntimeConfig {
+        &self.config
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.config.root_dir
+    }
+
+    /// The container runtime, loading the containers the first time
+    pub fn runtime(&self) -> Result<&ContainerRuntime> {
+        if let Some(runtime) = self.runtime.get() {
+            return Ok(runtime);
+        }
+        let runtime = ContainerRuntime::new(self.config.clone())?;
+        Ok(self.runtime.get_or_init(|| runtime))
+    }
+
+    /// The image store under the root directory
+    pub fn image_store(&self) -> Result<&ImageStore> {
+        if let Some(store) = self.image_store.get() {
+            return Ok(store);
+        }
+        let store = ImageStore::new(self.config.root_dir.join("images"))?;
+        Ok(self.image_store.get_or_init(|| store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::snapshotter::ProvisionMode;
+    use clap::Parser;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    #[serial]
+    fn test_init_from_flags() {
+        std::env::remove_var("CUBO_ROOT");
+        std::env::remove_var("CUBO_PULL_POLICY");
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        let cli = Cli::parse_from(["cubo", "--root-dir", root.to_str().unwrap(), "--offline", "ps"]);
+        let ctx = CuboContext::init(&cli);
+        assert_eq!(ctx.root_dir(), root);
+        assert_eq!(ctx.config().pull_policy, PullPolicy::Never);
+        // The flag isn't handed on through the environment
+        assert!(std::env::var("CUBO_ROOT").is_err());
+
+        assert_eq!(ctx.runtime().unwrap().root_dir(), root);
+        assert!(std::ptr::eq(ctx.runtime().unwrap(), ctx.runtime().unwrap()));
+        assert_eq!(ctx.image_store().unwrap().root(), root.join("images"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_global_args_carry_the_context() {
+        for var in ["CUBO_ROOT", "CUBO_EXECUTOR", "CUBO_SNAPSHOTTER", "CUBO_PROVISION", "CUBO_PULL_POLICY"] {
+            std::env::remove_var(var);
+        }
+        let temp = TempDir::new().unwrap();
+        let config_file = temp.path().join("config.toml");
+        std::fs::write(&config_file, "snapshotter = \"overlay\"\n").unwrap();
+        let root = temp.path().join("root");
+        let cli = Cli::parse_from([
+            "cubo",
+            "--config",
+            config_file.to_str().unwrap(),
+            "--root-dir",
+            root.to_str().unwrap(),
+            "--provision",
+            "copy",
+            "--offline",
+            "run",
+            "alpine",
+        ]);
+        let ctx = CuboContext::init(&cli);
+
+        let mut argv: Vec<OsString> = vec!["cubo".into()];
+        argv.extend(ctx.global_args());
+        argv.extend(["supervise".into(), "web".into()]);
+        let handed = CuboContext::init(&Cli::parse_from(argv));
+        assert_eq!(handed.config_file.as_deref(), Some(config_file.as_path()));
+        assert_eq!(handed.root_dir(), root);
+        assert_eq!(handed.config().snapshotter, ctx.config().snapshotter);
+        assert_eq!(handed.config().provision, ProvisionMode::Copy);
+        assert_eq!(handed.config().pull_policy, PullPolicy::Never);
+        assert_eq!(handed.config().executor, ctx.config().executor);
+    }
+}
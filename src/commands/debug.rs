@@ -0,0 +1,66 @@
+use crate::cli::DebugArgs;
+use crate::commands::context::CuboContext;
+use crate::container::checkpoint::container_init_pid;
+use crate::container::debug::{self, DEBUG_DIR};
+use crate::container::exec::exec_command;
+use crate::error::{exit_status_code, CuboError, Result};
+use tracing::info;
+
+pub async fn execute(ctx: &CuboContext, args: DebugArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+
+    let container_id = runtime.resolve(&args.container).await?;
+    let container = runtime.get_container(&container_id).await?;
+    let pid = match (container.is_running(), container.pid) {
+        (true, Some(pid)) => pid,
+        _ => return Err(CuboError::ContainerNotRunning(args.container.clone())),
+    };
+    let target = container_init_pid(pid)?;
+
+    let env = debug::debug_env(&container);
+    if !debug::is_installed(target) {
+        let toolkit = debug::find_toolkit(runtime.root_dir(), args.toolkit.as_deref().map(std::path::Path::new))?;
+        info!("Mounting {} at {} in container {}", toolkit.display(), DEBUG_DIR, container_id);
+        debug::install(target, &toolkit)?;
+
+        let status = exec_command(&container, &debug::install_applets_command(), &env)?.status().await?;
+        if !status.success() {
+            return Err(CuboError::SystemError(format!(
+                "Failed to install the toolkit's applets in {}/bin",
+                DEBUG_DIR
+            )));
+        }
+    }
+
+    let command = if args.command.is_empty() { vec!["sh".to_string()] } else { args.command };
+    let status = exec_command(&container, &command, &env)?.status().await?;
+    std::process::exit(exit_status_code(status));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_requires_running_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let args = DebugArgs { container: "ghost".to_string(), toolkit: None, command: vec![] };
+        assert!(matches!(execute(&CuboContext::new(RuntimeConfig::from_env()), args).await, Err(CuboError::ContainerNotFound(_))));
+
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["app".to_string()])
+            .with_name("distroless".to_string());
+        runtime.create_container(container).await.unwrap();
+        let args = DebugArgs { container: "distroless".to_string(), toolkit: None, command: vec![] };
+        assert!(matches!(execute(&CuboContext::new(RuntimeConfig::from_env()), args).await, Err(CuboError::ContainerNotRunning(_))));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::cli::DevArgs;
+use crate::commands::context::CuboContext;
+use crate::commands::build::ChangeWatcher;
+use crate::commands::logs::{follow_logs, get_log_path, LogView};
+use crate::commands::run::{parse_env_var, parse_port, validate_config};
+use crate::container::image_store::ImageStore;
+use crate::container::runtime::ContainerRuntime;
+use crate::container::{Container, LogDriverKind, VolumeMount};
+use crate::error::{CuboError, Result};
+
+/// How long the process gets to exit on its own before a restart kills it
+const STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a started container may take to create its log file
+const LOG_WAIT: Duration = Duration::from_secs(2);
+
+pub async fn execute(ctx: &CuboContext, args: DevArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let image_store = ctx.image_store()?;
+
+    let sources = args.source.iter().map(|spec| parse_source(spec)).collect::<Result<Vec<_>>>()?;
+    let container = dev_container(&args, &sources, image_store)?;
+    let container_id = runtime.create_container(container).await?;
+    info!("Created dev container: {}", container_id);
+
+    let result = dev_loop(runtime, &container_id, &sources).await;
+
+    // The container only lives as long as the session
+    if let Err(e) = runtime.stop_container(&container_id, Some(STOP_TIMEOUT)).await {
+        warn!("Failed to stop {}: {}", container_id, e);
+    }
+    runtime.remove_container(&container_id, true).await?;
+    result
+}
+
+/// Parse `--source src=PATH,dst=PATH`. The source has to exist; it is mounted read-write.
+pub fn parse_source(spec: &str) -> Result<VolumeMount> {
+    let invalid = |reason: &str| {
+        CuboError::InvalidConfiguration(format!("Invalid source '{}': {} (expected src=PATH,dst=PATH)", spec, reason))
+    };
+    let (mut src, mut dst) = (None, None);
+    for field in spec.split(',') {
+        match field.split_once('=') {
+            Some(("src" | "source", value)) => src = Some(value),
+            Some(("dst" | "destination" | "target", value)) => dst = Some(value),
+            _ => return Err(invalid(&format!("unknown field '{}'", field))),
+        }
+    }
+    let src = src.ok_or_else(|| invalid("missing src"))?;
+    let dst = dst.ok_or_else(|| invalid("missing dst"))?;
+    if !dst.starts_with('/') {
+        return Err(invalid("dst must be an absolute path"));
+    }
+    let src = fs::canonicalize(src).map_err(|e| invalid(&format!("{}: {}", src, e)))?;
+
+    Ok(VolumeMount::bind(src.to_string_lossy().to_string(), dst.to_string(), false))
+}
+
+fn dev_container(args: &DevArgs, sources: &[VolumeMount], image_store: &ImageStore) -> Result<Container> {
+    if !image_store.has_image(&args.blueprint) {
+        return Err(CuboError::ImageNotFound { reference: args.blueprint.clone() });
+    }
+    let command = if args.command.is_empty() {
+        image_store
+            .get_config(&args.blueprint)
+            .ok()
+            .and_then(|config| config.cmd)
+            .unwrap_or_else(|| vec!["/bin/sh".to_string()])
+    } else {
+        args.command.clone()
+    };
+
+    let mut container = Container::new(args.blueprint.clone(), command);
+    if let Some(ref name) = args.name {
+        container = container.with_name(name.clone());
+    }
+    for env in &args.env {
+        let (key, value) = parse_env_var(env)
+            .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid environment variable '{}'", env)))?;
+        container = container.with_env(key, value);
+    }
+    for port in &args.publish {
+        let mapping = parse_port(port)
+            .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid port '{}'", port)))?;
+        container = container.with_port(mapping);
+    }
+    for source in sources {
+        container = container.with_volume(source.clone());
+    }
+
+    validate_config(&container.config)?;
+    Ok(container)
+}
+
+/// Start the container, follow its output and restart it whenever a source changes, until
+/// interrupted. A process that exits on its own is started again on the next change.
+async fn dev_loop(runtime: &ContainerRuntime, container_id: &str, sources: &[VolumeMount]) -> Result<()> {
+    let paths: Vec<PathBuf> = sources.iter().map(|source| PathBuf::from(&source.host_path)).collect();
+    let mut watcher = ChangeWatcher::new(&paths.iter().map(PathBuf::as_path).collect::<Vec<_>>())?;
+
+    loop {
+        let started = Utc::now();
+        runtime.start_container(container_id, true).await?;
+        let follower = follow(runtime, container_id, started).await;
+        eprintln!("Watching {} for changes...", describe(&paths));
+
+        let Some(changed) = watcher.next().await else {
+            stop_following(follower).await;
+            return Ok(());
+        };
+        eprintln!("{} changed, restarting", changed.display());
+        runtime.stop_container(container_id, Some(STOP_TIMEOUT)).await?;
+        stop_following(follower).await;
+    }
+}
+
+/// Print what the container logs from `since` on, in the background
+async fn follow(runtime: &ContainerRuntime, container_id: &str, since: DateTime<Utc>) -> Option<JoinHandle<()>> {
+    let container = runtime.get_container(container_id).await.ok()?;
+    if container.config.log_driver != LogDriverKind::JsonFile {
+        warn!("The {} log driver can't be followed", container.config.log_driver);
+        return None;
+    }
+    let path = get_log_path(runtime.root_dir(), container_id);
+    let deadline = tokio::time::Instant::now() + LOG_WAIT;
+    while !path.exists() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let pid = container.pid;
+    Some(tokio::spawn(async move {
+        let view = LogView::prefixed(String::new(), false).with_since(since);
+        if let Err(e) = follow_logs(&path, None, &view, pid).await {
+            warn!("Failed to follow logs: {}", e);
+        }
+    }))
+}
+
+/// Let the follower print the last lines of a stopped process, then drop it
+async fn stop_following(follower: Option<JoinHandle<()>>) {
+    if let Some(mut follower) = follower {
+        if tokio::time::timeout(Duration::from_secs(1), &mut follower).await.is_err() {
+            follower.abort();
+        }
+    }
+}
+
+fn describe(paths: &[PathBuf]) -> String {
+    paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::MountType;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_source() {
+        let temp = TempDir::new().unwrap();
+        let spec = format!("src={},dst=/app", temp.path().display());
+        let mount = parse_source(&spec).unwrap();
+        assert_eq!(Path::new(&mount.host_path), fs::canonicalize(temp.path()).unwrap());
+        assert_eq!(mount.container_path, "/app");
+        assert!(!mount.read_only);
+        assert!(matches!(mount.mount_type, MountType::Bind));
+
+        assert!(parse_source(&format!("src={}", temp.path().display())).is_err());
+        assert!(parse_source(&format!("src={},dst=app", temp.path().display())).is_err());
+        assert!(parse_source("src=/nonexistent/dev/source,dst=/app").is_err());
+        assert!(parse_source(&format!("src={},dst=/app,mode=ro", temp.path().display())).is_err());
+    }
+
+    #[test]
+    fn test_dev_container_needs_the_image() {
+        let temp = TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        let args = DevArgs {
+            blueprint: "missing:latest".to_string(),
+            source: vec![],
+            name: None,
+            env: vec![],
+            publish: vec![],
+            command: vec![],
+        };
+        let err = dev_container(&args, &[], &store).unwrap_err();
+        assert!(matches!(err, CuboError::ImageNotFound { .. }));
+    }
+}
@@ -0,0 +1,29 @@
+use crate::container::diagnostics::{run_checks, CheckStatus, HostInfo};
+use crate::commands::context::CuboContext;
+use crate::error::{CuboError, Result};
+
+pub async fn execute(ctx: &CuboContext) -> Result<()> {
+    let config = ctx.config();
+    let host = HostInfo::probe(&config.cgroup_root);
+    let checks = run_checks(&host, config.snapshotter);
+
+    for check in &checks {
+        println!("{:<10} {:<20} {}", format!("[{}]", check.status), check.name, check.detail);
+        if let Some(ref fix) = check.fix {
+            println!("{:<10} {:<20} fix: {}", "", "", fix);
+        }
+    }
+
+    let errors = checks.iter().filter(|c| c.status == CheckStatus::Error).count();
+    let warnings = checks.iter().filter(|c| c.status == CheckStatus::Warning).count();
+    println!("\n{} errors, {} warnings", errors, warnings);
+
+    if errors > 0 {
+        return Err(CuboError::SystemError(format!(
+            "{} check{} failed; containers won't run until fixed",
+            errors,
+            if errors == 1 { "" } else { "s" }
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::cli::EnterArgs;
+use crate::commands::context::CuboContext;
+use crate::container::checkpoint::container_init_pid;
+use crate::container::exec::{enter_command, Namespace};
+use crate::error::{exit_status_code, CuboError, Result};
+use tracing::info;
+
+pub async fn execute(ctx: &CuboContext, args: EnterArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+
+    let container_id = runtime.resolve(&args.container).await?;
+    let container = runtime.get_container(&container_id).await?;
+    let pid = match (container.is_running(), container.pid) {
+        (true, Some(pid)) => pid,
+        _ => return Err(CuboError::ContainerNotRunning(args.container.clone())),
+    };
+    let target = container_init_pid(pid)?;
+
+    let namespaces = selected_namespaces(&args);
+    info!(
+        "Entering the {} namespaces of container {}",
+        namespaces.iter().map(Namespace::to_string).collect::<Vec<_>>().join(", "),
+        container_id
+    );
+    let status = enter_command(target, &namespaces, &args.command)?.status()?;
+    std::process::exit(exit_status_code(status));
+}
+
+/// The namespaces picked by the flags, all of them when none is
+fn selected_namespaces(args: &EnterArgs) -> Vec<Namespace> {
+    let picked: Vec<Namespace> = [
+        (args.user, Namespace::User),
+        (args.mount, Namespace::Mount),
+        (args.uts, Namespace::Uts),
+        (args.ipc, Namespace::Ipc),
+        (args.net, Namespace::Net),
+        (args.cgroup, Namespace::Cgroup),
+        (args.pid, Namespace::Pid),
+    ]
+    .into_iter()
+    .filter_map(|(set, namespace)| set.then_some(namespace))
+    .collect();
+
+    if picked.is_empty() {
+        Namespace::ALL.to_vec()
+    } else {
+        picked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use clap::Parser;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    fn parse(args: &[&str]) -> EnterArgs {
+        let cli = crate::cli::Cli::parse_from([&["cubo", "enter"], args].concat());
+        match cli.command {
+            crate::cli::Commands::Enter(args) => args,
+            _ => panic!("Expected Enter command"),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_selected_namespaces() {
+        let args = parse(&["web", "--net", "--pid", "--", "ss", "-tlnp"]);
+        assert_eq!(selected_namespaces(&args), vec![Namespace::Net, Namespace::Pid]);
+        assert_eq!(args.command, ["ss", "-tlnp"]);
+
+        let args = parse(&["web", "--", "ip", "addr"]);
+        assert_eq!(selected_namespaces(&args), Namespace::ALL.to_vec());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_requires_running_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["app".to_string()])
+            .with_name("stopped".to_string());
+        runtime.create_container(container).await.unwrap();
+        let args = parse(&["stopped", "--", "ls"]);
+        assert!(matches!(execute(&CuboContext::new(RuntimeConfig::from_env()), args).await, Err(CuboError::ContainerNotRunning(_))));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::cli::ExportBundleArgs;
+use crate::commands::context::CuboContext;
+use crate::container::api::Runtime;
+use crate::container::container_store::atomic_write_json;
+use crate::container::oci_spec::{build_spec, SpecHost};
+use crate::container::{apparmor, selinux};
+use crate::error::{CuboError, Result};
+use std::path::Path;
+use tracing::info;
+
+pub async fn execute(ctx: &CuboContext, args: ExportBundleArgs) -> Result<()> {
+    let runtime: &dyn Runtime = ctx.runtime()?;
+
+    let container_id = runtime.resolve(&args.container).await?;
+    let container = runtime.get_container(&container_id).await?;
+
+    let bundle = std::path::absolute(ctx.root_dir().join(&container_id))?;
+    let dir = std::path::absolute(Path::new(&args.dir))?;
+    if dir == bundle {
+        return Err(CuboError::InvalidConfiguration(
+            "The container's own bundle keeps cubo's config.json; export to another directory".to_string()
+        ));
+    }
+    let path = dir.join("config.json");
+    if path.exists() && !args.force {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        )));
+    }
+
+    let mut host = SpecHost::for_user(
+        nix::unistd::geteuid().as_raw(),
+        nix::unistd::getegid().as_raw(),
+        &container.config.userns_mode,
+    );
+    host.labels = selinux::container_labels(&container);
+    host.apparmor_profile = apparmor::profile_for(&container);
+    if let Some(sandbox) = &container.config.sandbox {
+        host.sandbox_pid = runtime.get_container(sandbox).await.ok()
+            .filter(|s| s.is_running())
+            .and_then(|s| s.pid);
+    }
+
+    let spec = build_spec(&container, &bundle.join("rootfs"), &host)?;
+    atomic_write_json(&path, &spec)?;
+    info!("Exported OCI bundle config of container {}", container_id);
+    println!("{}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_export_bundle_missing_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let args = ExportBundleArgs {
+            container: "ghost".to_string(),
+            dir: temp.path().join("out").to_string_lossy().to_string(),
+            force: false,
+        };
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
+        std::env::remove_var("CUBO_ROOT");
+        assert!(matches!(result, Err(CuboError::ContainerNotFound(_))));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::cli::{GenerateArgs, GenerateCommands, GenerateSystemdArgs};
+use crate::commands::context::CuboContext;
+use crate::container::api::Runtime;
+use crate::container::systemd::{render_unit, unit_file_name, UnitOptions};
+use crate::error::Result;
+use tracing::info;
+
+pub async fn execute(ctx: &CuboContext, args: GenerateArgs) -> Result<()> {
+    match args.command {
+        GenerateCommands::Systemd(args) => generate_systemd(ctx, args).await,
+    }
+}
+
+async fn generate_systemd(ctx: &CuboContext, args: GenerateSystemdArgs) -> Result<()> {
+    let runtime: &dyn Runtime = ctx.runtime()?;
+
+    let container_id = runtime.resolve(&args.container).await?;
+    let container = runtime.get_container(&container_id).await?;
+
+    let options = UnitOptions {
+        service_type: args.service_type,
+        restart_policy: args.restart_policy,
+        restart_sec: args.restart_sec,
+        cubo: std::env::current_exe()?,
+        root_dir: std::path::absolute(ctx.root_dir())?,
+    };
+    let unit = render_unit(&container, &options)?;
+
+    if args.files {
+        let path = std::env::current_dir()?.join(unit_file_name(&container));
+        std::fs::write(&path, unit)?;
+        info!("Wrote systemd unit for container {}", container_id);
+        println!("{}", path.display());
+    } else {
+        print!("{}", unit);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CuboError;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::container::systemd::ServiceType;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_generate_missing_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let args = GenerateSystemdArgs {
+            container: "ghost".to_string(),
+            service_type: ServiceType::Notify,
+            restart_policy: None,
+            restart_sec: None,
+            files: false,
+        };
+        let result = generate_systemd(&CuboContext::new(RuntimeConfig::from_env()), args).await;
+        std::env::remove_var("CUBO_ROOT");
+        assert!(matches!(result, Err(CuboError::ContainerNotFound(_))));
+    }
+}
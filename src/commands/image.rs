@@ -0,0 +1,271 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::cli::{
+    ImageArgs, ImageCommands, ImageGenerateKeyArgs, ImageHistoryArgs, ImageLsArgs, ImagePruneArgs, ImageSbomArgs, ImageSignArgs,
+    ImageTagArgs, ImageVerifyArgs,
+};
+use crate::commands::context::CuboContext;
+use crate::container::api::ImageRepository;
+use crate::container::build_history::HistoryStore;
+use crate::container::disk_usage::format_size;
+use crate::container::image_store::ImageStore;
+use crate::container::sbom::AttestationStore;
+use crate::container::signature::{self, SignatureStore, TrustPolicy};
+use crate::error::{CuboError, Result};
+
+pub async fn execute(ctx: &CuboContext, args: ImageArgs) -> Result<()> {
+    match args.command {
+        ImageCommands::Ls(args) => list(ctx, args),
+        ImageCommands::Tag(args) => tag(ctx, args),
+        ImageCommands::Sign(args) => sign(ctx, args),
+        ImageCommands::Verify(args) => verify(ctx, args),
+        ImageCommands::GenerateKey(args) => generate_key(args),
+        ImageCommands::Sbom(args) => sbom(ctx, args),
+        ImageCommands::History(args) => history(ctx, args),
+        ImageCommands::Prune(args) => prune(ctx, args).await,
+    }
+}
+
+pub fn list(ctx: &CuboContext, args: ImageLsArgs) -> Result<()> {
+    let image_store = ctx.image_store()?;
+    let images = image_store.find_images(&args.filters)?;
+    if images.is_empty() {
+        println!("No images found.");
+        return Ok(());
+    }
+
+    println!("{:<40} {:<14} {:>10}", "REFERENCE", "IMAGE ID", "SIZE");
+    for manifest in &images {
+        let digest = image_store.resolve(&manifest.reference).unwrap_or_default();
+        let id = digest.trim_start_matches("sha256:");
+        let size: u64 = manifest
+            .layers
+            .iter()
+            .filter_map(|layer| std::fs::metadata(layer).ok())
+            .map(|meta| meta.len())
+            .sum();
+        println!("{:<40} {:<14} {:>10}", manifest.reference, &id[..12.min(id.len())], format_size(size));
+    }
+    Ok(())
+}
+
+pub fn tag(ctx: &CuboContext, args: ImageTagArgs) -> Result<()> {
+    let images: &dyn ImageRepository = ctx.image_store()?;
+    images.tag(&args.source, &args.target)
+}
+
+fn sign(ctx: &CuboContext, args: ImageSignArgs) -> Result<()> {
+    let image_store = ctx.image_store()?;
+    if !image_store.has_image(&args.image) {
+        return Err(CuboError::ImageNotFound { reference: args.image });
+    }
+
+    let digest = signature::image_digest(image_store, &args.image)?;
+    signature_store(ctx.root_dir()).sign(Path::new(&args.key), &args.image, &digest)?;
+    println!("Signed {} ({})", args.image, digest);
+    Ok(())
+}
+
+fn verify(ctx: &CuboContext, args: ImageVerifyArgs) -> Result<()> {
+    verify_image(ctx.root_dir(), &args.image)?;
+    println!("{} satisfies the trust policy", args.image);
+    Ok(())
+}
+
+fn generate_key(args: ImageGenerateKeyArgs) -> Result<()> {
+    let (private, public) = signature::generate_key_pair(Path::new(&args.output))?;
+    println!("Private key written to {}", private.display());
+    println!("Public key written to {}", public.display());
+    Ok(())
+}
+
+fn sbom(ctx: &CuboContext, args: ImageSbomArgs) -> Result<()> {
+    let attestations = AttestationStore::new(&ctx.root_dir().join("images"));
+    let document = if args.provenance {
+        attestations.provenance(&args.image)?.statement()
+    } else {
+        attestations.sbom(&args.image)?.export(args.format)
+    };
+    let json = serde_json::to_string_pretty(&document)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize {}: {}", args.image, e)))?;
+
+    match args.output {
+        Some(path) => std::fs::write(&path, json + "\n")
+            .map_err(|e| CuboError::SystemError(format!("Failed to write {}: {}", path, e))),
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+}
+
+fn history(ctx: &CuboContext, args: ImageHistoryArgs) -> Result<()> {
+    let history = HistoryStore::new(&ctx.root_dir().join("images")).load(&args.image, args.logs)?;
+
+    println!("{} {}", history.image, history.digest);
+    println!("{:<48} {:>10}  OUTPUT", "STEP", "DURATION");
+    for step in &history.steps {
+        let output = step.log_digest.as_deref().map_or("-", |digest| &digest[..19.min(digest.len())]);
+        println!("{:<48} {:>9.1}s  {}", step.name, step.duration_ms as f64 / 1000.0, output);
+        if args.logs {
+            for line in String::from_utf8_lossy(&step.log).lines() {
+                println!("    {}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn prune(ctx: &CuboContext, args: ImagePruneArgs) -> Result<()> {
+    let image_store = ctx.image_store()?;
+    let runtime = ctx.runtime()?;
+    // Containers name their image by any of its names, its digest or its ID
+    let in_use: HashSet<String> = runtime
+        .list_containers(true)
+        .await?
+        .into_iter()
+        .filter_map(|container| image_store.resolve(&container.blueprint).ok())
+        .collect();
+
+    let unused = if args.dangling {
+        image_store.dangling_images(&in_use)?
+    } else {
+        let mut images = image_store.list_images()?;
+        images.retain(|image| image_store.resolve(image).is_ok_and(|digest| !in_use.contains(&digest)));
+        images.sort();
+        images
+    };
+    for image in &unused {
+        image_store.remove_image(image)?;
+        println!("{}", image);
+    }
+    eprintln!("Removed {} image{}", unused.len(), if unused.len() == 1 { "" } else { "s" });
+    Ok(())
+}
+
+fn signature_store(root_dir: &Path) -> SignatureStore {
+    SignatureStore::new(root_dir.join("images").join("signatures"))
+}
+
+/// Check a stored image against the trust policy; an explicit check needs a policy to check against
+pub fn verify_image(root_dir: &Path, image: &str) -> Result<()> {
+    let policy_path = signature::policy_path(root_dir);
+    if !policy_path.exists() {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "No trust policy at {}",
+            policy_path.display()
+        )));
+    }
+    enforce_policy(root_dir, image)
+}
+
+/// Check a stored image against the trust policy, if there is one
+pub fn enforce_policy(root_dir: &Path, image: &str) -> Result<()> {
+    let policy = TrustPolicy::load_or_default(&signature::policy_path(root_dir))?;
+    let image_store = ImageStore::new(root_dir.join("images"))?;
+    if !image_store.has_image(image) {
+        return Err(CuboError::ImageNotFound { reference: image.to_string() });
+    }
+    signature::enforce(&policy, &image_store, &signature_store(root_dir), image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::container::image_store::{ImageConfig, ImageManifest};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    #[serial]
+    fn test_sign_then_verify() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+        std::env::remove_var("CUBO_POLICY");
+
+        let image_store = ImageStore::new(temp.path().join("images")).unwrap();
+        let blob = temp.path().join("images/blobs/layer.tar");
+        std::fs::write(&blob, b"layer").unwrap();
+        image_store
+            .save_manifest(&ImageManifest {
+                reference: "myapp:latest".to_string(),
+                parent: None,
+                layers: vec![blob.to_string_lossy().to_string()],
+                config: ImageConfig { cmd: None, env: None, working_dir: None, exposed_ports: None, stop_signal: None, labels: None },
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+
+        // Without a policy nothing is enforced, but an explicit check fails
+        enforce_policy(temp.path(), "myapp:latest").unwrap();
+        assert!(verify_image(temp.path(), "myapp:latest").is_err());
+
+        let prefix = temp.path().join("cubo");
+        generate_key(ImageGenerateKeyArgs { output: prefix.to_string_lossy().to_string() }).unwrap();
+        std::fs::write(
+            temp.path().join("policy.toml"),
+            format!(
+                "default = \"reject\"\n\n[[rules]]\nscope = \"docker.io/library/myapp\"\nkeys = [\"{}\"]\n",
+                prefix.with_extension("pub").display()
+            ),
+        )
+        .unwrap();
+        assert!(verify_image(temp.path(), "myapp:latest").is_err());
+
+        sign(&CuboContext::new(RuntimeConfig::from_env()), ImageSignArgs {
+            image: "myapp:latest".to_string(),
+            key: prefix.with_extension("key").to_string_lossy().to_string(),
+        })
+        .unwrap();
+        verify_image(temp.path(), "myapp:latest").unwrap();
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_prune_dangling_keeps_tagged_and_used_images() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+        let image_store = ImageStore::new(temp.path().join("images")).unwrap();
+        let manifest = |reference: &str| ImageManifest {
+            reference: reference.to_string(),
+            parent: None,
+            layers: vec![],
+            config: ImageConfig {
+                cmd: None,
+                env: Some(vec![format!("NAME={}", reference)]),
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        };
+        for reference in ["myapp:latest", "old:a", "old:b"] {
+            image_store.save_manifest(&manifest(reference)).unwrap();
+        }
+        let unused = image_store.untag("old:a").unwrap();
+        let used = image_store.untag("old:b").unwrap();
+        let ctx = CuboContext::new(RuntimeConfig::from_env());
+        ctx.runtime()
+            .unwrap()
+            .create_container(crate::container::Container::new(used.clone(), vec!["true".to_string()]))
+            .await
+            .unwrap();
+
+        prune(&ctx, ImagePruneArgs { dangling: true }).await.unwrap();
+        assert!(image_store.has_image("myapp:latest"));
+        assert!(!image_store.has_image(&unused));
+        assert!(image_store.has_image(&used));
+
+        prune(&ctx, ImagePruneArgs { dangling: false }).await.unwrap();
+        std::env::remove_var("CUBO_ROOT");
+        assert!(!image_store.has_image("myapp:latest"));
+        assert!(image_store.has_image(&used));
+    }
+}
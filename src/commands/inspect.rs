@@ -0,0 +1,76 @@
+use crate::cli::InspectArgs;
+use crate::commands::context::CuboContext;
+use crate::container::api::Runtime;
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+pub async fn execute(ctx: &CuboContext, args: InspectArgs) -> Result<()> {
+    let containers = inspect(ctx.runtime()?, &args.containers).await?;
+
+    let json = serde_json::to_string_pretty(&containers)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize containers: {}", e)))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// The containers of any runtime that `identifiers` name, in order
+pub async fn inspect(runtime: &dyn Runtime, identifiers: &[String]) -> Result<Vec<Container>> {
+    let mut containers = Vec::new();
+    for identifier in identifiers {
+        let container_id = runtime.resolve(identifier).await?;
+        containers.push(runtime.get_container(&container_id).await?);
+    }
+    Ok(containers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::memory::MemoryRuntime;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use crate::container::RestartPolicy;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_missing_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let args = InspectArgs { containers: vec!["ghost".to_string()] };
+        assert!(matches!(execute(&CuboContext::new(RuntimeConfig::from_env()), args).await, Err(CuboError::ContainerNotFound(_))));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    async fn test_find_container_by_name_shows_restart_policy() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig { root_dir: temp.path().to_path_buf(), ..Default::default() };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()])
+            .with_name("web".to_string())
+            .with_restart_policy(RestartPolicy::Always);
+        let id = runtime.create_container(container).await.unwrap();
+
+        assert_eq!(runtime.resolve("web").await.unwrap(), id);
+        let json = serde_json::to_value(runtime.get_container(&id).await.unwrap()).unwrap();
+        assert_eq!(json["config"]["restart_policy"], "Always");
+        assert_eq!(json["restart_count"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_inspect_memory_runtime() {
+        let runtime = MemoryRuntime::new();
+        let web = Container::new("nginx:latest".to_string(), vec!["nginx".to_string()]).with_name("web".to_string());
+        let db = Container::new("postgres:16".to_string(), vec!["postgres".to_string()]);
+        let web_id = runtime.create_container(web).await.unwrap();
+        let db_id = runtime.create_container(db).await.unwrap();
+
+        let containers = inspect(&runtime, &["web".to_string(), db_id[..12].to_string()]).await.unwrap();
+        let ids: Vec<&str> = containers.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, [web_id.as_str(), db_id.as_str()]);
+        assert!(matches!(inspect(&runtime, &["ghost".to_string()]).await, Err(CuboError::ContainerNotFound(_))));
+    }
+}
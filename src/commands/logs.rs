@@ -1,91 +1,258 @@
 use crate::cli::LogsArgs;
-use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+use crate::commands::context::CuboContext;
+use crate::config::parse_duration;
+use crate::container::container_store::pid_is_alive;
+use crate::container::logging::{log_segments, was_rotated, LogEntry, LogStream, LOG_FILE};
+use crate::container::LogDriverKind;
 use crate::error::Result;
 use crate::CuboError;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use chrono::{DateTime, SecondsFormat, Utc};
 use tracing::warn;
 
-pub async fn execute(args: LogsArgs) -> Result<()> {
-    let config = RuntimeConfig::from_env();
-    let runtime = ContainerRuntime::new(config.clone())?;
-
-    let container = runtime.get_container(&args.container).await?;
-    let log_path = get_log_path(&config.root_dir, &container.id);
+pub async fn execute(ctx: &CuboContext, args: LogsArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+
+    let container = runtime.get_container(&runtime.resolve(&args.container).await?).await?;
+    match container.config.log_driver {
+        LogDriverKind::JsonFile | LogDriverKind::Cri => {}
+        LogDriverKind::Journald => {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "Container {} logs to journald; read them with `journalctl CONTAINER_ID_FULL={}`",
+                args.container, container.id
+            )));
+        }
+        LogDriverKind::None => {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "Container {} uses the none log driver; no logs are kept",
+                args.container
+            )));
+        }
+    }
+    let view = LogView::from_args(&args, Utc::now())?;
+    let log_path = match container.config.log_options.path {
+        Some(ref path) if container.config.log_driver == LogDriverKind::Cri => path.clone(),
+        _ => get_log_path(ctx.root_dir(), &container.id),
+    };
     if !log_path.exists() {
         println!("No logs available for container {}", args.container);
         return Ok(());
     }
     if args.follow {
-        follow_logs(&log_path, args.timestamps).await?;
+        let pid = if container.is_running() { container.pid } else { None };
+        follow_logs(&log_path, args.tail, &view, pid).await?;
     } else {
-        print_logs(&log_path, args.tail, args.timestamps)?;
+        print_logs(&log_path, args.tail, &view)?;
     }
 
     Ok(())
 }
 
-fn get_log_path(root_dir: &PathBuf, container_id: &str) -> PathBuf {
-    root_dir.join(container_id).join("container.log")
+pub(crate) fn get_log_path(root_dir: &Path, container_id: &str) -> PathBuf {
+    root_dir.join(container_id).join(LOG_FILE)
 }
 
+/// A line of the log file: a json-file or cri entry, or raw output written before log
+/// drivers existed, possibly prefixed with a timestamp
+#[derive(Debug, PartialEq)]
+pub(crate) struct StoredLine {
+    pub(crate) stream: LogStream,
+    pub(crate) timestamp: Option<DateTime<Utc>>,
+    pub(crate) message: String,
+}
 
-fn print_logs(log_path: &PathBuf, tail: Option<usize>, timestamps: bool) -> Result<()> {
-    let file = File::open(log_path)
-        .map_err(|e| CuboError::SystemError(format!("Failed to open log file: {}", e)))?;
-    
-    let reader = BufReader::new(file);
-    let lines: Vec<String> = reader.lines().filter_map(|l| l.ok()).collect();
-
-    let lines_to_print = if let Some(n) = tail {
-        if lines.len() > n {
-            &lines[lines.len() - n..]
-        } else {
-            &lines[..]
+impl StoredLine {
+    pub(crate) fn parse(line: &str) -> Self {
+        if let Some(entry) = LogEntry::parse(line) {
+            return Self { stream: entry.stream, timestamp: Some(entry.timestamp), message: entry.message };
         }
-    } else {
-        &lines[..]
-    };
+        if let Some(line) = Self::parse_cri(line) {
+            return line;
+        }
+        let timestamp = line
+            .split_whitespace()
+            .next()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc));
+        let message = match timestamp {
+            Some(_) => strip_timestamp(line).unwrap_or_else(|| line.to_string()),
+            None => line.to_string(),
+        };
+        Self { stream: LogStream::Stdout, timestamp, message }
+    }
 
-    for line in lines_to_print {
-        if timestamps {
-            println!("{}", line);
-        } else {
-            if let Some(msg) = strip_timestamp(&line) {
-                println!("{}", msg);
-            } else {
-                println!("{}", line);
-            }
+    /// `<RFC3339Nano> <stream> <F|P> <message>`, as written by the cri driver
+    fn parse_cri(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, ' ');
+        let timestamp = DateTime::parse_from_rfc3339(fields.next()?).ok()?.with_timezone(&Utc);
+        let stream = match fields.next()? {
+            "stdout" => LogStream::Stdout,
+            "stderr" => LogStream::Stderr,
+            _ => return None,
+        };
+        if !matches!(fields.next()?, "F" | "P") {
+            return None;
         }
+        let message = fields.next().unwrap_or("").to_string();
+        Some(Self { stream, timestamp: Some(timestamp), message })
     }
+}
+
+/// How `cubo logs` filters and renders lines
+#[derive(Debug, Default)]
+pub(crate) struct LogView {
+    /// Printed before every line (the service name with `cubo compose logs`)
+    prefix: String,
+    timestamps: bool,
+    details: bool,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+impl LogView {
+    fn from_args(args: &LogsArgs, now: DateTime<Utc>) -> Result<Self> {
+        Ok(Self {
+            prefix: String::new(),
+            timestamps: args.timestamps,
+            details: args.details,
+            since: args.since.as_deref().map(|s| parse_time_spec(s, now)).transpose()?,
+            until: args.until.as_deref().map(|s| parse_time_spec(s, now)).transpose()?,
+        })
+    }
+
+    pub(crate) fn prefixed(prefix: String, timestamps: bool) -> Self {
+        Self { prefix, timestamps, ..Default::default() }
+    }
+
+    /// Only lines logged from `since` on
+    pub(crate) fn with_since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Lines without a timestamp can't be placed in time and only show when unfiltered
+    fn matches(&self, line: &StoredLine) -> bool {
+        match line.timestamp {
+            Some(t) => self.since.is_none_or(|since| t >= since) && self.until.is_none_or(|until| t <= until),
+            None => self.since.is_none() && self.until.is_none(),
+        }
+    }
+
+    fn render(&self, line: &StoredLine) -> String {
+        let mut out = self.prefix.clone();
+        if let (true, Some(t)) = (self.timestamps, line.timestamp) {
+            out.push_str(&t.to_rfc3339_opts(SecondsFormat::Micros, true));
+            out.push(' ');
+        }
+        if self.details {
+            out.push_str(match line.stream {
+                LogStream::Stdout => "stdout ",
+                LogStream::Stderr => "stderr ",
+            });
+        }
+        out.push_str(&line.message);
+        out
+    }
+
+    /// Print a line on the stream it was captured from
+    pub(crate) fn emit(&self, line: &StoredLine) {
+        if !self.matches(line) {
+            return;
+        }
+        match line.stream {
+            LogStream::Stdout => println!("{}", self.render(line)),
+            LogStream::Stderr => eprintln!("{}", self.render(line)),
+        }
+    }
+}
+
+/// Parse `--since`/`--until`: an RFC 3339 timestamp, or how long ago (30s, 10m, 2h, 1d)
+pub(crate) fn parse_time_spec(spec: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(spec) {
+        return Ok(t.with_timezone(&Utc));
+    }
+
+    let invalid = || CuboError::InvalidConfiguration(format!(
+        "Invalid time '{}', expected an RFC 3339 timestamp or a duration like 10m",
+        spec
+    ));
+    parse_duration(spec)
+        .and_then(|ago| now.checked_sub_signed(ago))
+        .ok_or_else(invalid)
+}
+
+pub(crate) fn read_lines(paths: &[PathBuf]) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for path in paths {
+        let file = File::open(path)
+            .map_err(|e| CuboError::SystemError(format!("Failed to open log file: {}", e)))?;
+        lines.extend(BufReader::new(file).lines().map_while(|l| l.ok()));
+    }
+    Ok(lines)
+}
+
+/// Print the lines `selected_lines` picks
+fn print_lines(lines: &[String], tail: Option<usize>, view: &LogView) {
+    for line in &selected_lines(lines, tail, view) {
+        view.emit(line);
+    }
+}
+
+/// The lines that pass the filters, only the last `tail` of them if given
+fn selected_lines(lines: &[String], tail: Option<usize>, view: &LogView) -> Vec<StoredLine> {
+    let mut matching: Vec<StoredLine> = lines
+        .iter()
+        .map(|line| StoredLine::parse(line))
+        .filter(|line| view.matches(line))
+        .collect();
+    let skip = tail.map_or(0, |n| matching.len().saturating_sub(n));
+    matching.split_off(skip)
+}
+
+/// Print the log, including the segments rotated out of it, oldest first
+fn print_logs(log_path: &Path, tail: Option<usize>, view: &LogView) -> Result<()> {
+    print_lines(&read_lines(&log_segments(log_path))?, tail, view);
     Ok(())
 }
 
-async fn follow_logs(log_path: &PathBuf, timestamps: bool) -> Result<()> {
-    let mut file = File::open(log_path)
+/// Print the log so far, then new lines as they come until the container exits (or the
+/// `--until` time passes). `pid` is None when the container isn't running.
+pub(crate) async fn follow_logs(log_path: &Path, tail: Option<usize>, view: &LogView, pid: Option<u32>) -> Result<()> {
+    let file = File::open(log_path)
         .map_err(|e| CuboError::SystemError(format!("Failed to open log file: {}", e)))?;
+    let mut reader = BufReader::new(file);
 
-    file.seek(SeekFrom::End(0))
-        .map_err(|e| CuboError::SystemError(format!("Failed to seek: {}", e)))?;
+    // Rotated segments, then the live file through the reader we keep following
+    let mut segments = log_segments(log_path);
+    segments.pop();
+    let mut history = read_lines(&segments)?;
+    history.extend((&mut reader).lines().map_while(|l| l.ok()));
+    print_lines(&history, tail, view);
 
-    let mut reader = BufReader::new(file);
     let mut line = String::new();
-
+    let mut exiting = false;
     loop {
         match reader.read_line(&mut line) {
             Ok(0) => {
+                // The driver rotated the log: carry on with the new file from its start
+                if was_rotated(log_path, reader.get_ref()) {
+                    if let Ok(file) = File::open(log_path) {
+                        reader = BufReader::new(file);
+                        continue;
+                    }
+                }
+                if exiting || view.until.is_some_and(|until| Utc::now() > until) {
+                    break;
+                }
+                // Give the logger a moment to flush the last lines once the container is gone
+                exiting = !pid_is_alive(pid);
                 tokio::time::sleep(Duration::from_millis(100)).await;
-                line.clear();
             }
             Ok(_) => {
-                let output = if timestamps {
-                    line.clone()
-                } else {
-                    strip_timestamp(&line).unwrap_or(line.clone())
-                };
-                print!("{}", output);
+                view.emit(&StoredLine::parse(line.trim_end_matches('\n')));
                 line.clear();
             }
             Err(e) => {
@@ -109,6 +276,7 @@ fn strip_timestamp(line: &str) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
     use std::fs;
     use std::io::Write;
     use tempfile::TempDir;
@@ -143,6 +311,92 @@ mod tests {
         assert_eq!(result, None);
     }
 
+    fn at(spec: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(spec).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_stored_line_json_entry() {
+        let line = r#"{"stream":"stderr","timestamp":"2025-11-24T20:30:00.123456Z","message":"Hello world"}"#;
+        let stored = StoredLine::parse(line);
+        assert_eq!(stored.stream, LogStream::Stderr);
+        assert_eq!(stored.timestamp, Some(at("2025-11-24T20:30:00.123456Z")));
+        assert_eq!(stored.message, "Hello world");
+    }
+
+    #[test]
+    fn test_stored_line_cri() {
+        let stored = StoredLine::parse("2025-11-24T20:30:00.123456789Z stderr F  indented message");
+        assert_eq!(stored.stream, LogStream::Stderr);
+        assert_eq!(stored.timestamp, Some(at("2025-11-24T20:30:00.123456789Z")));
+        assert_eq!(stored.message, " indented message");
+
+        let stored = StoredLine::parse("2025-11-24T20:30:00.5Z stdout X not cri");
+        assert_eq!(stored.message, "stdout X not cri");
+    }
+
+    #[test]
+    fn test_stored_line_legacy() {
+        let stored = StoredLine::parse("2025-11-24T20:30:00.123456Z Hello");
+        assert_eq!(stored.stream, LogStream::Stdout);
+        assert_eq!(stored.timestamp, Some(at("2025-11-24T20:30:00.123456Z")));
+        assert_eq!(stored.message, "Hello");
+
+        let stored = StoredLine::parse("plain output");
+        assert_eq!(stored.timestamp, None);
+        assert_eq!(stored.message, "plain output");
+    }
+
+    #[test]
+    fn test_render() {
+        let line = StoredLine::parse(
+            r#"{"stream":"stderr","timestamp":"2025-11-24T20:30:00.123456Z","message":"oops"}"#,
+        );
+        assert_eq!(LogView::default().render(&line), "oops");
+        let view = LogView { timestamps: true, details: true, ..Default::default() };
+        assert_eq!(view.render(&line), "2025-11-24T20:30:00.123456Z stderr oops");
+        assert_eq!(view.render(&StoredLine::parse("plain output")), "stdout plain output");
+        let view = LogView::prefixed("web | ".to_string(), true);
+        assert_eq!(view.render(&line), "web | 2025-11-24T20:30:00.123456Z oops");
+    }
+
+    #[test]
+    fn test_view_matches_time_window() {
+        let view = LogView {
+            since: Some(at("2025-11-24T20:30:00Z")),
+            until: Some(at("2025-11-24T20:31:00Z")),
+            ..Default::default()
+        };
+        assert!(view.matches(&StoredLine::parse("2025-11-24T20:30:30.000000Z inside")));
+        assert!(!view.matches(&StoredLine::parse("2025-11-24T20:29:59.000000Z before")));
+        assert!(!view.matches(&StoredLine::parse("2025-11-24T20:31:01.000000Z after")));
+        assert!(!view.matches(&StoredLine::parse("no timestamp")));
+        assert!(LogView::default().matches(&StoredLine::parse("no timestamp")));
+    }
+
+    #[test]
+    fn test_parse_time_spec() {
+        let now = at("2025-11-24T20:30:00Z");
+        assert_eq!(parse_time_spec("30s", now).unwrap(), at("2025-11-24T20:29:30Z"));
+        assert_eq!(parse_time_spec("10m", now).unwrap(), at("2025-11-24T20:20:00Z"));
+        assert_eq!(parse_time_spec("2h", now).unwrap(), at("2025-11-24T18:30:00Z"));
+        assert_eq!(parse_time_spec("1d", now).unwrap(), at("2025-11-23T20:30:00Z"));
+        assert_eq!(
+            parse_time_spec("2025-11-24T10:00:00+02:00", now).unwrap(),
+            at("2025-11-24T08:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_parse_time_spec_invalid() {
+        let now = Utc::now();
+        assert!(parse_time_spec("", now).is_err());
+        assert!(parse_time_spec("10", now).is_err());
+        assert!(parse_time_spec("10w", now).is_err());
+        assert!(parse_time_spec("m", now).is_err());
+        assert!(parse_time_spec("yesterday", now).is_err());
+    }
+
     #[test]
     fn test_print_logs_basic() -> Result<()> {
         let temp_dir = TempDir::new().unwrap();
@@ -151,7 +405,7 @@ mod tests {
         writeln!(file, "Line 1").unwrap();
         writeln!(file, "Line 2").unwrap();
         writeln!(file, "Line 3").unwrap();
-        print_logs(&log_path, None, false)?;
+        print_logs(&log_path, None, &LogView::default())?;
         Ok(())
     }
 
@@ -163,7 +417,7 @@ mod tests {
         for i in 1..=10 {
             writeln!(file, "Line {}", i).unwrap();
         }
-        print_logs(&log_path, Some(3), false)?;
+        print_logs(&log_path, Some(3), &LogView::default())?;
         Ok(())
     }
 
@@ -175,7 +429,7 @@ mod tests {
         writeln!(file, "2025-11-24T20:30:00.123456Z Line 1").unwrap();
         writeln!(file, "2025-11-24T20:30:01.123456Z Line 2").unwrap();
 
-        print_logs(&log_path, None, true)?;
+        print_logs(&log_path, None, &LogView { timestamps: true, ..Default::default() })?;
         Ok(())
     }
 
@@ -187,16 +441,63 @@ mod tests {
         writeln!(file, "Line 1").unwrap();
         writeln!(file, "Line 2").unwrap();
         writeln!(file, "Line 3").unwrap();
-        print_logs(&log_path, Some(100), false)?;
+        print_logs(&log_path, Some(100), &LogView::default())?;
         Ok(())
     }
 
+    #[test]
+    fn test_print_logs_across_rotated_segments() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("container.log");
+        fs::write(temp_dir.path().join("container.log.2"), "oldest\n").unwrap();
+        fs::write(temp_dir.path().join("container.log.1"), "older\n").unwrap();
+        fs::write(&log_path, "latest\n").unwrap();
+        print_logs(&log_path, Some(2), &LogView::default())?;
+
+        let view = LogView::default();
+        let lines = read_lines(&log_segments(&log_path))?;
+        let rendered = |tail| -> Vec<String> {
+            selected_lines(&lines, tail, &view).iter().map(|line| view.render(line)).collect()
+        };
+        assert_eq!(rendered(None), ["oldest", "older", "latest"]);
+        assert_eq!(rendered(Some(2)), ["older", "latest"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_follow_logs_returns_when_not_running() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("container.log");
+        fs::write(&log_path, "line 1\nline 2\n").unwrap();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            follow_logs(&log_path, None, &LogView::default(), None),
+        )
+        .await
+        .expect("follow should stop once the container is gone")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_was_rotated() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = temp_dir.path().join("container.log");
+        fs::write(&log_path, "line\n").unwrap();
+        let open = File::open(&log_path).unwrap();
+        assert!(!was_rotated(&log_path, &open));
+
+        fs::rename(&log_path, temp_dir.path().join("container.log.1")).unwrap();
+        assert!(!was_rotated(&log_path, &open));
+        fs::write(&log_path, "").unwrap();
+        assert!(was_rotated(&log_path, &open));
+    }
+
     #[test]
     fn test_print_logs_empty_file() -> Result<()> {
         let temp_dir = TempDir::new().unwrap();
         let log_path = temp_dir.path().join("container.log");
         File::create(&log_path).unwrap();
-        print_logs(&log_path, None, false)?;
+        print_logs(&log_path, None, &LogView::default())?;
         Ok(())
     }
 
@@ -210,9 +511,12 @@ mod tests {
             follow: false,
             tail: None,
             timestamps: false,
+            since: None,
+            until: None,
+            details: false,
         };
 
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_err());
         std::env::remove_var("CUBO_ROOT");
     }
@@ -236,9 +540,12 @@ mod tests {
             follow: false,
             tail: None,
             timestamps: false,
+            since: None,
+            until: None,
+            details: false,
         };
 
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_ok());
         std::env::remove_var("CUBO_ROOT");
     }
@@ -256,7 +563,7 @@ mod tests {
             vec!["echo".to_string(), "hello".to_string()],
         );
         let container_id = runtime.create_container(container).await.unwrap();
-        let log_path = get_log_path(&temp_dir.path().to_path_buf(), &container_id);
+        let log_path = get_log_path(temp_dir.path(), &container_id);
         fs::create_dir_all(log_path.parent().unwrap()).unwrap();
         let mut file = File::create(&log_path).unwrap();
         writeln!(file, "Test log line 1").unwrap();
@@ -268,10 +575,40 @@ mod tests {
             follow: false, 
             tail: None,
             timestamps: false,
+            since: None,
+            until: None,
+            details: false,
         };
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_ok());
         std::env::remove_var("CUBO_ROOT");
     }
 
-}
\ No newline at end of file
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_execute_journald_container() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_log_driver(LogDriverKind::Journald);
+        let container_id = runtime.create_container(container).await.unwrap();
+
+        std::env::set_var("CUBO_ROOT", temp_dir.path().to_string_lossy().to_string());
+        let args = LogsArgs {
+            container: container_id.clone(),
+            follow: false,
+            tail: None,
+            timestamps: false,
+            since: None,
+            until: None,
+            details: false,
+        };
+        let err = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await.unwrap_err();
+        assert!(err.to_string().contains("journalctl"));
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
@@ -6,4 +6,28 @@ pub mod build;
 pub mod blueprints;
 pub mod rmb;
 pub mod pull;
-pub mod logs;
\ No newline at end of file
+pub mod logs;
+pub mod network;
+pub mod volume;
+pub mod update;
+pub mod checkpoint;
+pub mod restore;
+pub mod remote;
+pub mod compose;
+pub mod generate;
+pub mod export_bundle;
+pub mod system;
+pub mod doctor;
+pub mod completion;
+pub mod audit;
+pub mod inspect;
+pub mod container;
+pub mod supervise;
+pub mod debug;
+pub mod enter;
+pub mod dev;
+pub mod wait;
+pub mod stats;
+pub mod port;
+pub mod image;
+pub mod context;
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use crate::cli::{
+    NetworkArgs, NetworkCommands, NetworkConnectArgs, NetworkCreateArgs, NetworkDisconnectArgs,
+    NetworkInspectArgs, NetworkRmArgs,
+};
+use crate::commands::context::CuboContext;
+use crate::container::network::{self, Network, NetworkStore};
+use crate::error::{CuboError, Result};
+use tracing::{info, warn, error};
+
+pub async fn execute(ctx: &CuboContext, args: NetworkArgs) -> Result<()> {
+    let store = ctx.config().network_store()?;
+
+    match args.command {
+        NetworkCommands::Create(create) => create_network(&store, create),
+        NetworkCommands::Ls => list_networks(&store),
+        NetworkCommands::Rm(rm) => remove_networks(&store, rm),
+        NetworkCommands::Inspect(inspect) => inspect_networks(ctx, &store, inspect).await,
+        NetworkCommands::Connect(connect) => connect_container(ctx, connect).await,
+        NetworkCommands::Disconnect(disconnect) => disconnect_container(ctx, disconnect).await,
+    }
+}
+
+fn create_network(store: &NetworkStore, args: NetworkCreateArgs) -> Result<()> {
+    let network = store.create(&args.name, args.subnet.as_deref(), args.gateway.as_deref())?;
+    info!("Created network {} ({})", network.name, network.subnet);
+    println!("{}", network.short_id());
+    Ok(())
+}
+
+fn list_networks(store: &NetworkStore) -> Result<()> {
+    let networks = store.list()?;
+    if networks.is_empty() {
+        println!("No networks found.");
+        return Ok(());
+    }
+
+    println!("{:<12} {:<20} {:<8} {:<18} {:<15} {:<10}",
+             "NETWORK ID", "NAME", "DRIVER", "SUBNET", "GATEWAY", "CONTAINERS");
+    for network in &networks {
+        println!("{}", format_network_row(network));
+    }
+    Ok(())
+}
+
+pub fn format_network_row(network: &Network) -> String {
+    format!("{:<12} {:<20} {:<8} {:<18} {:<15} {:<10}",
+            network.short_id(),
+            network.name,
+            network.driver,
+            network.subnet,
+            network.gateway,
+            network.endpoints.len())
+}
+
+fn remove_networks(store: &NetworkStore, args: NetworkRmArgs) -> Result<()> {
+    let mut failed = Vec::new();
+
+    for name in args.networks {
+        match store.remove(&name) {
+            Ok(network) => {
+                if let Err(e) = network::delete_bridge(&network) {
+                    warn!("Failed to delete bridge {}: {}", network.bridge, e);
+                }
+                println!("{}", name);
+            }
+            Err(e) => {
+                error!("Failed to remove network {}: {}", name, e);
+                failed.push((name, e));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        for (name, e) in &failed {
+            eprintln!("Error removing {}: {}", name, e);
+        }
+        return Err(CuboError::NetworkError("Some networks could not be removed".to_string()));
+    }
+    Ok(())
+}
+
+async fn inspect_networks(ctx: &CuboContext, store: &NetworkStore, args: NetworkInspectArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let names: HashMap<String, String> = runtime
+        .list_containers(true)
+        .await?
+        .into_iter()
+        .filter_map(|c| c.name.map(|name| (c.id, name)))
+        .collect();
+
+    let mut output = Vec::new();
+    for name in &args.networks {
+        let network = store.get(name)?;
+        output.push(inspect_value(&network, &names)?);
+    }
+
+    let json = serde_json::to_string_pretty(&output)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize network: {}", e)))?;
+    println!("{}", json);
+    Ok(())
+}
+
+async fn connect_container(ctx: &CuboContext, args: NetworkConnectArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let container_id = runtime.resolve(&args.container).await?;
+    let endpoint = runtime
+        .connect_network(&container_id, &args.network, args.ip.as_deref(), args.mac_address.as_deref())
+        .await?;
+    info!("Container {} joined {} as {} ({})", args.container, args.network, endpoint.ip, endpoint.interface);
+    Ok(())
+}
+
+async fn disconnect_container(ctx: &CuboContext, args: NetworkDisconnectArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let container_id = runtime.resolve(&args.container).await?;
+    runtime.disconnect_network(&container_id, &args.network).await
+}
+
+/// The network as JSON, with the name of each connected container next to its endpoint
+pub fn inspect_value(network: &Network, container_names: &HashMap<String, String>) -> Result<serde_json::Value> {
+    let mut value = serde_json::to_value(network)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize network: {}", e)))?;
+
+    if let Some(endpoints) = value.get_mut("endpoints").and_then(|e| e.as_object_mut()) {
+        for (container_id, endpoint) in endpoints.iter_mut() {
+            if let (Some(name), Some(obj)) = (container_names.get(container_id), endpoint.as_object_mut()) {
+                obj.insert("container_name".to_string(), serde_json::Value::String(name.clone()));
+            }
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_format_network_row() {
+        let temp = TempDir::new().unwrap();
+        let store = NetworkStore::new(temp.path().to_path_buf()).unwrap();
+        let network = store.create("frontend", Some("10.50.0.0/24"), None).unwrap();
+
+        let row = format_network_row(&network);
+        assert!(row.starts_with(&network.short_id()));
+        assert!(row.contains("frontend"));
+        assert!(row.contains("bridge"));
+        assert!(row.contains("10.50.0.0/24"));
+        assert!(row.contains("10.50.0.1"));
+    }
+
+    #[test]
+    fn test_inspect_value_includes_container_names() {
+        let temp = TempDir::new().unwrap();
+        let store = NetworkStore::new(temp.path().to_path_buf()).unwrap();
+        store.create("backend", Some("10.60.0.0/24"), None).unwrap();
+        store.connect("backend", "container-1", None, None).unwrap();
+        let network = store.get("backend").unwrap();
+
+        let mut names = HashMap::new();
+        names.insert("container-1".to_string(), "db".to_string());
+
+        let value = inspect_value(&network, &names).unwrap();
+        assert_eq!(value["name"], "backend");
+        assert_eq!(value["endpoints"]["container-1"]["ip"], "10.60.0.2");
+        assert_eq!(value["endpoints"]["container-1"]["container_name"], "db");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_create_and_remove() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let create = NetworkArgs {
+            command: NetworkCommands::Create(NetworkCreateArgs {
+                name: "testnet".to_string(),
+                subnet: Some("10.70.0.0/24".to_string()),
+                gateway: None,
+            }),
+        };
+        execute(&CuboContext::new(RuntimeConfig::from_env()), create).await.unwrap();
+        assert!(temp.path().join("networks/testnet.json").exists());
+
+        let rm = NetworkArgs {
+            command: NetworkCommands::Rm(NetworkRmArgs { networks: vec!["testnet".to_string()] }),
+        };
+        execute(&CuboContext::new(RuntimeConfig::from_env()), rm).await.unwrap();
+        assert!(!temp.path().join("networks/testnet.json").exists());
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_remove_missing_network() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let rm = NetworkArgs {
+            command: NetworkCommands::Rm(NetworkRmArgs { networks: vec!["missing".to_string()] }),
+        };
+        assert!(execute(&CuboContext::new(RuntimeConfig::from_env()), rm).await.is_err());
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_connect_and_disconnect_by_name() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()])
+            .with_name("web".to_string());
+        let id = runtime.create_container(container).await.unwrap();
+        let store = NetworkStore::new(temp.path().join("networks")).unwrap();
+        store.create("backend", Some("10.80.0.0/24"), None).unwrap();
+
+        let connect = NetworkArgs {
+            command: NetworkCommands::Connect(NetworkConnectArgs {
+                network: "backend".to_string(),
+                container: "web".to_string(),
+                ip: None,
+                mac_address: None,
+            }),
+        };
+        execute(&CuboContext::new(RuntimeConfig::from_env()), connect).await.unwrap();
+        assert!(store.get("backend").unwrap().endpoints.contains_key(&id));
+
+        let disconnect = NetworkArgs {
+            command: NetworkCommands::Disconnect(NetworkDisconnectArgs {
+                network: "backend".to_string(),
+                container: "web".to_string(),
+            }),
+        };
+        execute(&CuboContext::new(RuntimeConfig::from_env()), disconnect).await.unwrap();
+        assert!(store.get("backend").unwrap().endpoints.is_empty());
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_connect_missing_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let connect = NetworkArgs {
+            command: NetworkCommands::Connect(NetworkConnectArgs {
+                network: "backend".to_string(),
+                container: "ghost".to_string(),
+                ip: None,
+                mac_address: None,
+            }),
+        };
+        assert!(execute(&CuboContext::new(RuntimeConfig::from_env()), connect).await.is_err());
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
@@ -0,0 +1,84 @@
+use crate::cli::PortArgs;
+use crate::commands::context::CuboContext;
+use crate::commands::run::parse_port;
+use crate::container::api::Runtime;
+use crate::container::{Container, PortMapping};
+use crate::error::{CuboError, Result};
+
+pub async fn execute(ctx: &CuboContext, args: PortArgs) -> Result<()> {
+    for line in published(ctx.runtime()?, &args.container, args.port.as_deref()).await? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// The published ports of the container `identifier` names in any runtime, as `lines` shows them
+pub async fn published(runtime: &dyn Runtime, identifier: &str, filter: Option<&str>) -> Result<Vec<String>> {
+    let container_id = runtime.resolve(identifier).await?;
+    lines(&runtime.get_container(&container_id).await?, filter)
+}
+
+/// `80/tcp -> 0.0.0.0:32768` for each published port of a running container, or only those
+/// of the container port `filter`
+fn lines(container: &Container, filter: Option<&str>) -> Result<Vec<String>> {
+    let published: &[PortMapping] = if container.is_running() { &container.published_ports } else { &[] };
+    let ports: Vec<&PortMapping> = match filter {
+        Some(spec) => {
+            let wanted = parse_port(spec)
+                .filter(PortMapping::is_ephemeral)
+                .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid port '{}'", spec)))?;
+            let ports: Vec<&PortMapping> = published
+                .iter()
+                .filter(|p| p.container_port == wanted.container_port && p.protocol == wanted.protocol)
+                .collect();
+            if ports.is_empty() {
+                return Err(CuboError::NetworkError(format!(
+                    "No public port '{}/{}' published for {}",
+                    wanted.container_port, wanted.protocol, container.id
+                )));
+            }
+            ports
+        }
+        None => published.iter().collect(),
+    };
+
+    Ok(ports
+        .iter()
+        .map(|p| {
+            format!("{}/{} -> {}:{}", p.container_port, p.protocol, p.host_ip.as_deref().unwrap_or("0.0.0.0"), p.host_port)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::memory::MemoryRuntime;
+    use crate::container::ContainerStatus;
+
+    #[test]
+    fn test_lines_of_published_ports() {
+        let mut container = Container::new("nginx:latest".to_string(), vec!["nginx".to_string()]);
+        container.published_ports = vec![PortMapping::tcp(32768, 80), PortMapping::udp(5353, 53)];
+        assert!(lines(&container, None).unwrap().is_empty());
+
+        container.update_status(ContainerStatus::Running);
+        assert_eq!(lines(&container, None).unwrap(), ["80/tcp -> 0.0.0.0:32768", "53/udp -> 0.0.0.0:5353"]);
+        assert_eq!(lines(&container, Some("53/udp")).unwrap(), ["53/udp -> 0.0.0.0:5353"]);
+        assert!(matches!(lines(&container, Some("53")), Err(CuboError::NetworkError(_))));
+        assert!(lines(&container, Some("8080:80")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_published_by_memory_runtime() {
+        let runtime = MemoryRuntime::new();
+        let mut container = Container::new("nginx:latest".to_string(), vec!["nginx".to_string()]).with_name("web".to_string());
+        container.published_ports = vec![PortMapping::tcp(32768, 80)];
+        let id = runtime.create_container(container).await.unwrap();
+        assert!(published(&runtime, "web", None).await.unwrap().is_empty());
+
+        runtime.start_container(&id, true).await.unwrap();
+        assert_eq!(published(&runtime, "web", Some("80")).await.unwrap(), ["80/tcp -> 0.0.0.0:32768"]);
+        assert!(matches!(published(&runtime, "ghost", None).await, Err(CuboError::ContainerNotFound(_))));
+    }
+}
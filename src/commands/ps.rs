@@ -1,60 +1,127 @@
 use crate::cli::PsArgs;
-use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+use crate::commands::context::CuboContext;
+use crate::container::api::Runtime;
+use crate::container::{Container, ContainerStatus};
 use crate::error::Result;
 use chrono_humanize::{Accuracy, HumanTime, Tense};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
 
-pub async fn execute(args: PsArgs) -> Result<()> {
-    // Instanciate runtime
-    let config = RuntimeConfig::from_env();
-    let runtime = ContainerRuntime::new(config)?;
+/// Bold reverse video, for the rows `--watch` highlights
+const HIGHLIGHT: &str = "\x1b[1;7m";
+const RESET: &str = "\x1b[0m";
+/// Clear the screen and move the cursor home
+const CLEAR: &str = "\x1b[2J\x1b[H";
 
-    // List containers
-    let containers = runtime.list_containers(args.all).await?;
+pub async fn execute(ctx: &CuboContext, args: PsArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
 
-    if containers.is_empty() {
-        if args.all {
-            println!("No containers found.");
-        } else {
-            println!("No running containers found. use --all to see all of the containers.");
-        }
-
-        return Ok(());
+    match args.watch {
+        Some(seconds) => watch(args.all, Duration::from_secs(seconds), || runtime.list_containers(args.all)).await,
+        None => list(runtime, args.all).await,
     }
+}
 
-        // Print header
-    println!("{:<12} {:<20} {:<15} {:<10} {:<20} {:<15}", 
-             "CONTAINER ID", "IMAGE", "COMMAND", "STATUS", "CREATED", "NAMES");
+/// Print the containers of any runtime, running ones only unless `all`
+pub async fn list(runtime: &dyn Runtime, all: bool) -> Result<()> {
+    let containers = runtime.list_containers(all).await?;
+    print_containers(containers, all);
 
-    // print each container
-    for container in containers {
-        let command_str = if container.command.is_empty() {
-            "".to_string()
-        } else if container.command.len() == 1 {
-            container.command[0].clone()
+    Ok(())
+}
+
+pub fn print_containers(containers: Vec<Container>, all: bool) {
+    print!("{}", render(&containers, all, None));
+}
+
+/// The container table, with the rows of containers that are new or changed status since
+/// `previous` highlighted
+fn render(containers: &[Container], all: bool, previous: Option<&HashMap<String, ContainerStatus>>) -> String {
+    if containers.is_empty() {
+        return if all {
+            "No containers found.\n".to_string()
         } else {
-            format!("{} {}", container.command[0], container.command[1..].join(" "))
+            "No running containers found. use --all to see all of the containers.\n".to_string()
         };
+    }
 
-        // Truncate command if too long
-        let command_display = if command_str.len() > 15 {
-            format!("{}...", &command_str[..12])
+    let mut table = format!(
+        "{:<12} {:<20} {:<15} {:<10} {:<20} {:<25} {:<15}\n",
+        "CONTAINER ID", "IMAGE", "COMMAND", "STATUS", "CREATED", "PORTS", "NAMES"
+    );
+    for container in containers {
+        let row = format_row(container);
+        let changed = previous.is_some_and(|previous| previous.get(&container.id) != Some(&container.status));
+        if changed {
+            table.push_str(&format!("{}{}{}\n", HIGHLIGHT, row, RESET));
         } else {
-            command_str
-        };
+            table.push_str(&row);
+            table.push('\n');
+        }
+    }
+    table
+}
 
-        let created_str = format_duration_since(container.created_at);
-        let name = container.name.as_deref().unwrap_or("");
+fn format_row(container: &Container) -> String {
+    format!(
+        "{:<12} {:<20} {:<15} {:<10} {:<20} {:<25} {:<15}",
+        &container.id[..12],
+        container.blueprint,
+        format_command_display(&container.command, 15),
+        container.status,
+        format_duration_since(container.created_at),
+        format_ports(container),
+        container.name.as_deref().unwrap_or("")
+    )
+}
 
-        println!("{:<12} {:<20} {:<15} {:<10} {:<20} {:<15}", 
-                 &container.id[..12], 
-                 container.blueprint, 
-                 command_display, 
-                 container.status, 
-                 created_str, 
-                 name);
+/// The published ports of a running container, as docker ps shows them
+fn format_ports(container: &Container) -> String {
+    if !container.is_running() {
+        return String::new();
     }
+    container.published_ports.iter().map(|port| port.to_string()).collect::<Vec<_>>().join(", ")
+}
 
-    Ok(())
+/// Clear the terminal and print the table every `interval` until interrupted, highlighting
+/// the containers that changed since the last refresh
+pub async fn watch<F, Fut>(all: bool, interval: Duration, mut fetch: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Vec<Container>>>,
+{
+    let terminal = std::io::stdout().is_terminal();
+    let mut previous: Option<HashMap<String, ContainerStatus>> = None;
+    loop {
+        let containers = fetch().await?;
+        let mut table = render(&containers, all, previous.as_ref().filter(|_| terminal));
+        if let Some(previous) = &previous {
+            let gone = previous.keys().filter(|id| !containers.iter().any(|c| &c.id == *id));
+            for id in gone {
+                table.push_str(&format!("{} is gone\n", &id[..12]));
+            }
+        }
+
+        if terminal {
+            print!("{}", CLEAR);
+        }
+        println!(
+            "Every {}s: cubo ps{}    {}\n",
+            interval.as_secs(),
+            if all { " --all" } else { "" },
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+        );
+        print!("{}", table);
+        std::io::stdout().flush()?;
+
+        previous = Some(containers.into_iter().map(|c| (c.id, c.status)).collect());
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
 }
 
 fn format_duration_since(time: chrono::DateTime<chrono::Utc>) -> String {
@@ -81,6 +148,7 @@ pub fn format_command_display(command: &[String], max_len: usize) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
     use tempfile::TempDir;
     use crate::container::Container;
 
@@ -118,13 +186,47 @@ mod tests {
         assert!(!result.is_empty());
     }
 
+    #[test]
+    fn test_render_highlights_changed_containers() {
+        let mut running = Container::new("alpine:latest".to_string(), vec!["sh".to_string()]);
+        running.update_status(ContainerStatus::Running);
+        let mut exited = Container::new("alpine:latest".to_string(), vec!["true".to_string()]);
+        exited.update_status(ContainerStatus::Stopped);
+        let containers = vec![running.clone(), exited.clone()];
+
+        assert!(!render(&containers, true, None).contains(HIGHLIGHT));
+
+        let previous = HashMap::from([
+            (running.id.clone(), ContainerStatus::Running),
+            (exited.id.clone(), ContainerStatus::Running),
+        ]);
+        let table = render(&containers, true, Some(&previous));
+        let highlighted: Vec<&str> = table.lines().filter(|line| line.starts_with(HIGHLIGHT)).collect();
+        assert_eq!(highlighted.len(), 1);
+        assert!(highlighted[0].contains(&exited.id[..12]));
+    }
+
+    #[test]
+    fn test_format_ports_of_running_containers() {
+        let mut container = Container::new("nginx:latest".to_string(), vec!["nginx".to_string()])
+            .with_port(crate::container::PortMapping::tcp(0, 80));
+        container.published_ports = vec![
+            crate::container::PortMapping::tcp(32768, 80),
+            crate::container::PortMapping::udp(5353, 53).with_host_ip("127.0.0.1".to_string()),
+        ];
+        assert_eq!(format_ports(&container), "");
+
+        container.update_status(ContainerStatus::Running);
+        assert_eq!(format_ports(&container), "0.0.0.0:32768->80/tcp, 127.0.0.1:5353->53/udp");
+    }
+
     #[tokio::test]
     async fn test_execute_no_containers() {
         let temp_dir = TempDir::new().unwrap();
         std::env::set_var("CUBO_ROOT", temp_dir.path().to_string_lossy().to_string());
 
-        let args = crate::cli::PsArgs {all: false};
-        let result = execute(args).await;
+        let args = crate::cli::PsArgs {all: false, watch: None};
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_ok());
 
         std::env::remove_var("CUBO_ROOT");
@@ -134,8 +236,8 @@ mod tests {
     async fn test_execute_with_all_flag() {
         let temp_dir = TempDir::new().unwrap();
         std::env::set_var("CUBO_ROOT", temp_dir.path().to_string_lossy().to_string());
-        let args = crate::cli::PsArgs {all: true};
-        let result = execute(args).await;
+        let args = crate::cli::PsArgs {all: true, watch: None};
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_ok());
         std::env::remove_var("CUBO_ROOT");
     }
@@ -154,8 +256,8 @@ mod tests {
         ).with_name("test-ps-container".to_string());
         runtime.create_container(container).await.unwrap();
         std::env::set_var("CUBO_ROOT", temp_dir.path().to_string_lossy().to_string());
-        let args = crate::cli::PsArgs {all:true};
-        let result = execute(args).await;
+        let args = crate::cli::PsArgs {all: true, watch: None};
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_ok());
         std::env::remove_var("CUBO_ROOT");
     }
@@ -1,35 +1,107 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
 use crate::cli::PullArgs;
+use crate::commands::context::CuboContext;
+use crate::container::audit::{AuditLog, AuditOperation, AuditRecord};
 use crate::container::image_store::ImageStore;
-use crate::container::registry::RegistryClient;
-use crate::error::Result;
+use crate::container::registry::{PullProgress, RegistryClient};
+use crate::container::runtime::RuntimeConfig;
+use crate::container::signature::image_digest;
+use crate::container::transport::ImageSource;
+use crate::commands::image::enforce_policy;
+use crate::error::{CuboError, Result};
 use tracing::info;
 
-pub async fn execute(args: PullArgs) -> Result<()> {
-    info!("Pulling image: {}", args.image);
+pub async fn execute(ctx: &CuboContext, args: PullArgs) -> Result<()> {
+    let config = ctx.config();
+    let images = references(&args)?;
+    match images.as_slice() {
+        [image] if args.file.is_none() => pull_one(config, image).await,
+        _ => pull_batch(config.clone(), images, args.jobs as usize).await,
+    }
+}
 
-    // Get root directory from environment
-    let root_dir = std::env::var("CUBO_ROOT")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("/var/lib/cubo"));
+/// The images named on the command line, then those of `--file`, without repeats
+pub(crate) fn references(args: &PullArgs) -> Result<Vec<String>> {
+    let mut images = args.images.clone();
+    if let Some(ref path) = args.file {
+        let mut list = String::new();
+        if path == "-" {
+            std::io::stdin().read_to_string(&mut list)?;
+        } else {
+            list = std::fs::read_to_string(path)
+                .map_err(|e| CuboError::InvalidConfiguration(format!("Failed to read {}: {}", path, e)))?;
+        }
+        images.extend(parse_list(&list));
+    }
+    let mut seen = HashSet::new();
+    images.retain(|image| seen.insert(image.clone()));
+    if images.is_empty() {
+        return Err(CuboError::InvalidConfiguration("No images to pull".to_string()));
+    }
+    Ok(images)
+}
 
+/// One reference per line; blank lines and `#` comments are skipped
+fn parse_list(list: &str) -> Vec<String> {
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Pull an image and check it against the trust policy. A rejected image is removed again
+/// unless it was already stored; that one is only refused at `run --verify`.
+async fn fetch(
+    config: &RuntimeConfig,
+    image: &str,
+    progress: &(dyn Fn(PullProgress) + Send + Sync),
+) -> Result<String> {
+    let root_dir = &config.root_dir;
     let image_store = ImageStore::new(root_dir.join("images"))?;
+    let reference = ImageSource::parse(image).local_reference();
+    let existed = image_store.has_image(&reference);
 
-    let registry_client = RegistryClient::new(image_store);
+    let result = RegistryClient::new(&image_store)
+        .with_config(config)
+        .pull_with_progress(image, progress)
+        .await
+        .and_then(|_| enforce_policy(root_dir, &reference));
+    AuditLog::new(root_dir).record(AuditRecord::new(AuditOperation::Pull, &result).with_image(&reference));
+    if matches!(result, Err(CuboError::PermissionDenied(_))) && !existed {
+        let _ = ImageStore::new(root_dir.join("images"))?.remove_image(&reference);
+    }
+    result.map(|_| reference)
+}
 
-    println!("Pulling image: {}", args.image);
+async fn pull_one(config: &RuntimeConfig, image: &str) -> Result<()> {
+    info!("Pulling image: {}", image);
+    println!("Pulling image: {}", image);
     println!();
 
-    match registry_client.pull(&args.image).await {
-        Ok(_) => {
-            println!("Successfully pulled: {}", args.image);
+    match fetch(config, image, &|_| {}).await {
+        Err(e @ CuboError::PermissionDenied(_)) => {
+            eprintln!("Rejected by the trust policy: {}", e);
+            Err(e)
+        }
+        Ok(reference) => {
+            println!("Successfully pulled: {}", reference);
             println!();
             println!("Use with: ");
-            println!("  cubo run {}", args.image);
-            println!("  cubo build (with BASE {})", args.image);
+            println!("  cubo run {}", reference);
+            println!("  cubo build (with BASE {})", reference);
             Ok(())
         }
+        Err(e) if !matches!(ImageSource::parse(image), ImageSource::Registry(_)) => {
+            eprintln!("Import failed: {}", e);
+            Err(e)
+        }
         Err(e) => {
             eprintln!("Pull failed: {}", e);
             eprintln!();
@@ -42,36 +114,76 @@ pub async fn execute(args: PullArgs) -> Result<()> {
     }
 }
 
-pub fn parse_image_reference(image: &str) -> (Option<&str>, &str, &str) {
-    let (image_part, tag) = if let Some(idx) = image.rfind(':') {
-        let after_colon = &image[idx + 1..];
-        if after_colon.contains('/') {
-            (image, "latest")
-        } else {
-            (&image[..idx], after_colon)
-        }
-    } else {
-        (image, "latest")
-    };
+/// Pull `images`, `jobs` at a time. Progress lines of all pulls share the terminal, each
+/// prefixed with its image, and a summary lists the digest or error of every image.
+async fn pull_batch(config: RuntimeConfig, images: Vec<String>, jobs: usize) -> Result<()> {
+    println!("Pulling {} images, {} at a time", images.len(), jobs.min(images.len()));
+    println!();
+
+    let width = images.iter().map(String::len).max().unwrap_or(0);
+    let config = Arc::new(config);
+    let slots = Arc::new(Semaphore::new(jobs));
+    let mut pulls = JoinSet::new();
+    for (idx, image) in images.iter().cloned().enumerate() {
+        let config = Arc::clone(&config);
+        let slots = Arc::clone(&slots);
+        pulls.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let progress = |step: PullProgress| println!("{}", progress_line(&image, width, &step));
+            let result = match fetch(&config, &image, &progress).await {
+                Ok(reference) => ImageStore::new(config.root_dir.join("images"))
+                    .and_then(|store| image_digest(&store, &reference))
+                    .map(|digest| (reference, digest)),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok((ref reference, _)) => println!("{:width$}  Pulled as {}", image, reference),
+                Err(ref e) => println!("{:width$}  Failed: {}", image, e),
+            }
+            (idx, result)
+        });
+    }
 
-    if let Some(idx) = image_part.find('/') {
-        let potential_registry = &image_part[..idx];
+    let mut results: Vec<Option<Result<(String, String)>>> = images.iter().map(|_| None).collect();
+    while let Some(joined) = pulls.join_next().await {
+        let (idx, result) = joined.map_err(|e| CuboError::SystemError(format!("Pull task failed: {}", e)))?;
+        results[idx] = Some(result);
+    }
 
-        if potential_registry.contains('.') || potential_registry.contains(':') {
-            return (Some(potential_registry), &image_part[idx + 1..], tag);
+    let failed = results.iter().filter(|r| !matches!(r, Some(Ok(_)))).count();
+    println!();
+    println!("Pulled {} of {} images", images.len() - failed, images.len());
+    for (image, result) in images.iter().zip(&results) {
+        match result {
+            Some(Ok((reference, digest))) => println!("  ok      {:width$}  {}  {}", image, digest, reference),
+            Some(Err(e)) => println!("  failed  {:width$}  {}", image, e),
+            None => println!("  failed  {:width$}", image),
         }
     }
-    (None, image_part, tag)
+    if failed > 0 {
+        return Err(CuboError::SystemError(format!("{} of {} images could not be pulled", failed, images.len())));
+    }
+    Ok(())
+}
+
+fn progress_line(image: &str, width: usize, step: &PullProgress) -> String {
+    if step.total == 0 {
+        format!("{:width$}  {}", image, step.status)
+    } else {
+        format!("{:width$}  {} [{}/{}]", image, step.status, step.current, step.total)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::reference::ImageReference;
     use tempfile::TempDir;
 
     #[test]
     fn test_parse_image_reference_simple() {
-        let (registry, repo, tag) = parse_image_reference("alpine");
+        let reference = ImageReference::parse("alpine").unwrap();
+        let (registry, repo, tag) = (reference.registry.as_deref(), reference.repository.as_str(), reference.tag_or_default());
         assert_eq!(registry, None);
         assert_eq!(repo, "alpine");
         assert_eq!(tag, "latest");
@@ -79,7 +191,8 @@ mod tests {
 
     #[test]
     fn test_parse_image_reference_with_tag() {
-        let (registry, repo, tag) = parse_image_reference("ubuntu:22.04");
+        let reference = ImageReference::parse("ubuntu:22.04").unwrap();
+        let (registry, repo, tag) = (reference.registry.as_deref(), reference.repository.as_str(), reference.tag_or_default());
         assert_eq!(registry, None);
         assert_eq!(repo, "ubuntu");
         assert_eq!(tag, "22.04");
@@ -87,7 +200,8 @@ mod tests {
 
     #[test]
     fn test_parse_image_reference_with_namespace() {
-        let (registry, repo, tag) = parse_image_reference("library/nginx:latest");
+        let reference = ImageReference::parse("library/nginx:latest").unwrap();
+        let (registry, repo, tag) = (reference.registry.as_deref(), reference.repository.as_str(), reference.tag_or_default());
         assert_eq!(registry, None);
         assert_eq!(repo, "library/nginx");
         assert_eq!(tag, "latest");
@@ -95,7 +209,8 @@ mod tests {
 
     #[test]
     fn test_parse_image_reference_with_registry() {
-        let (registry, repo, tag) = parse_image_reference("ghcr.io/owner/repo:v1.0");
+        let reference = ImageReference::parse("ghcr.io/owner/repo:v1.0").unwrap();
+        let (registry, repo, tag) = (reference.registry.as_deref(), reference.repository.as_str(), reference.tag_or_default());
         assert_eq!(registry, Some("ghcr.io"));
         assert_eq!(repo, "owner/repo");
         assert_eq!(tag, "v1.0");
@@ -103,7 +218,8 @@ mod tests {
 
     #[test]
     fn test_parse_image_reference_with_registry_port() {
-        let (registry, repo, tag) = parse_image_reference("localhost:5000/theimage:test");
+        let reference = ImageReference::parse("localhost:5000/theimage:test").unwrap();
+        let (registry, repo, tag) = (reference.registry.as_deref(), reference.repository.as_str(), reference.tag_or_default());
         assert_eq!(registry, Some("localhost:5000"));
         assert_eq!(repo, "theimage");
         assert_eq!(tag, "test");
@@ -111,7 +227,8 @@ mod tests {
 
     #[test]
     fn test_parse_image_reference_docker_hub() {
-        let (registry, repo, tag) = parse_image_reference("docker.io/library/alpine:3.18");
+        let reference = ImageReference::parse("docker.io/library/alpine:3.18").unwrap();
+        let (registry, repo, tag) = (reference.registry.as_deref(), reference.repository.as_str(), reference.tag_or_default());
         assert_eq!(registry, Some("docker.io"));
         assert_eq!(repo, "library/alpine");
         assert_eq!(tag, "3.18");
@@ -130,4 +247,46 @@ mod tests {
         assert!(images_path.join("blobs").exists());
         assert!(images_path.join("manifests").exists());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_list() {
+        let list = "# nodes\nalpine:3.19\n\n  nginx  # web\nghcr.io/owner/app@sha256:abc\n";
+        assert_eq!(parse_list(list), vec!["alpine:3.19", "nginx", "ghcr.io/owner/app@sha256:abc"]);
+    }
+
+    #[test]
+    fn test_references_merge_args_and_file() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("images.txt");
+        std::fs::write(&path, "alpine\nredis:7\n").unwrap();
+        let args = PullArgs {
+            images: vec!["nginx".to_string(), "alpine".to_string()],
+            file: Some(path.to_string_lossy().into_owned()),
+            jobs: 4,
+        };
+        assert_eq!(references(&args).unwrap(), vec!["nginx", "alpine", "redis:7"]);
+
+        std::fs::write(&path, "# nothing yet\n").unwrap();
+        let args = PullArgs { images: vec![], ..args };
+        assert!(references(&args).is_err());
+    }
+
+    #[test]
+    fn test_progress_line() {
+        let step = PullProgress { status: "Downloaded layer".to_string(), current: 2, total: 3 };
+        assert_eq!(progress_line("alpine", 8, &step), "alpine    Downloaded layer [2/3]");
+    }
+
+    #[tokio::test]
+    async fn test_pull_batch_reports_failures() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp.path().to_path_buf(),
+            pull_policy: crate::container::registry::PullPolicy::Never,
+            ..RuntimeConfig::default()
+        };
+        let images = vec!["alpine".to_string(), "redis:7".to_string()];
+        let err = pull_batch(config, images, 2).await.unwrap_err();
+        assert!(err.to_string().contains("2 of 2 images could not be pulled"));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::cli::{Commands, RunArgs};
+use crate::commands::context::CuboContext;
+use crate::commands::ps::{print_containers, watch};
+use crate::commands::run::build_container;
+use crate::container::RestartPolicy;
+use crate::daemon::client::DaemonClient;
+use crate::daemon::default_socket_path;
+use crate::error::{CuboError, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Run a command through the daemon listening on `socket`
+pub async fn execute(ctx: &CuboContext, socket: Option<&str>, command: Commands) -> Result<()> {
+    let socket = socket.map(PathBuf::from).unwrap_or_else(default_socket_path);
+    let client = DaemonClient::new(socket);
+    info!("Sending command to daemon at {}", client.socket().display());
+
+    match command {
+        Commands::Run(args) => run(ctx, &client, *args).await,
+        Commands::Ps(args) => match args.watch {
+            Some(seconds) => {
+                watch(args.all, Duration::from_secs(seconds), || client.list_containers(args.all)).await
+            }
+            None => {
+                print_containers(client.list_containers(args.all).await?, args.all);
+                Ok(())
+            }
+        },
+        Commands::Stop(args) => {
+            let mut failed = 0;
+            for container in &args.containers {
+                match client.stop_container(container, None).await {
+                    Ok(_) => println!("{}", container),
+                    Err(e) => {
+                        eprintln!("Error stopping {}: {}", container, e);
+                        failed += 1;
+                    }
+                }
+            }
+            check_failures(failed, "stopped")
+        }
+        Commands::Rm(args) => {
+            let mut failed = 0;
+            for container in &args.containers {
+                match client.remove_container(container, args.force).await {
+                    Ok(_) => println!("{}", container),
+                    Err(e) => {
+                        eprintln!("Error removing {}: {}", container, e);
+                        failed += 1;
+                    }
+                }
+            }
+            check_failures(failed, "removed")
+        }
+        Commands::Pull(args) => {
+            let images = crate::commands::pull::references(&args)?;
+            let mut failed = 0;
+            for image in &images {
+                println!("Pulling image: {}", image);
+                match client.pull(image).await {
+                    Ok(_) => println!("Successfully pulled: {}", image),
+                    Err(e) => {
+                        eprintln!("Error pulling {}: {}", image, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(CuboError::SystemError(format!("{} of {} images could not be pulled", failed, images.len())));
+            }
+            Ok(())
+        }
+        _ => Err(CuboError::InvalidConfiguration(
+            "Only run, ps, stop, rm and pull are available with --remote".to_string(),
+        )),
+    }
+}
+
+/// Create and start a detached container through the daemon. Image defaults (CMD, stop
+/// signal) come from the local image store, which the daemon shares on the same host.
+async fn run(ctx: &CuboContext, client: &DaemonClient, args: RunArgs) -> Result<()> {
+    if args.interactive {
+        return Err(CuboError::InvalidConfiguration(
+            "Interactive containers can't be run with --remote".to_string(),
+        ));
+    }
+    if args.wait_healthy {
+        return Err(CuboError::InvalidConfiguration(
+            "--wait-healthy can't be used with --remote".to_string(),
+        ));
+    }
+    if args.restart != RestartPolicy::No {
+        return Err(CuboError::InvalidConfiguration(
+            "--restart can't be used with --remote".to_string(),
+        ));
+    }
+    if args.build {
+        return Err(CuboError::InvalidConfiguration(
+            "--build can't be used with --remote".to_string(),
+        ));
+    }
+
+    let container = build_container(args, ctx.image_store()?, ctx.config())?;
+
+    let container_id = client.create_container(&container).await?;
+    if let Err(e) = client.start_container(&container_id).await {
+        error!("Failed to start container: {}", e);
+        if let Err(cleanup_err) = client.remove_container(&container_id, true).await {
+            error!("Failed to cleanup container after start failure: {}", cleanup_err);
+        }
+        return Err(e);
+    }
+
+    println!("{}", container_id);
+    Ok(())
+}
+
+fn check_failures(failed: usize, action: &str) -> Result<()> {
+    if failed > 0 {
+        return Err(CuboError::SystemError(format!(
+            "Some containers could not be {}",
+            action
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::cli::Cli;
+    use clap::Parser;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_unsupported_command() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "logs", "web"]);
+        let err = execute(&CuboContext::new(RuntimeConfig::from_env()), Some("/nonexistent/cubo.sock"), cli.command).await.unwrap_err();
+        assert!(err.to_string().contains("available with --remote"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_run_interactive_rejected() {
+        std::env::remove_var("CUBO_ROOT");
+        let cli = Cli::parse_from(["cubo", "run", "-i", "alpine"]);
+        let err = execute(&CuboContext::new(RuntimeConfig::from_env()), Some("/nonexistent/cubo.sock"), cli.command).await.unwrap_err();
+        assert!(err.to_string().contains("Interactive"));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_ps_without_daemon() {
+        std::env::remove_var("CUBO_ROOT");
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("cubo.sock");
+        let cli = Cli::parse_from(["cubo", "ps"]);
+        let err = execute(&CuboContext::new(RuntimeConfig::from_env()), socket.to_str(), cli.command).await.unwrap_err();
+        assert!(matches!(err, CuboError::DaemonError(_)));
+    }
+
+    #[test]
+    fn test_check_failures() {
+        assert!(check_failures(0, "removed").is_ok());
+        let err = check_failures(2, "removed").unwrap_err();
+        assert!(err.to_string().contains("could not be removed"));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::cli::RestoreArgs;
+use crate::commands::context::CuboContext;
+use crate::error::Result;
+use tracing::info;
+
+pub async fn execute(ctx: &CuboContext, args: RestoreArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+
+    let container_id = runtime.resolve(&args.container).await?;
+    runtime.restore_container(&container_id).await?;
+
+    info!("Restored container: {}", args.container);
+    println!("{}", args.container);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_without_checkpoint() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()])
+            .with_name("web".to_string());
+        runtime.create_container(container).await.unwrap();
+
+        let err = execute(&CuboContext::new(RuntimeConfig::from_env()), RestoreArgs { container: "web".to_string() }).await.unwrap_err();
+        assert!(err.to_string().contains("no checkpoint"));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
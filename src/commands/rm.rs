@@ -1,10 +1,11 @@
 use crate::cli::RmArgs;
-use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+use crate::commands::context::CuboContext;
+use crate::container::api::Runtime;
 use crate::error::Result;
 use tracing::{info, warn, error};
 
 
-pub async fn execute(args: RmArgs) -> Result<()> {
+pub async fn execute(ctx: &CuboContext, args: RmArgs) -> Result<()> {
     if args.containers.is_empty() {
         error!("No containers specified");
         return Err(crate::error::CuboError::InvalidConfiguration(
@@ -14,14 +15,13 @@ pub async fn execute(args: RmArgs) -> Result<()> {
 
     info!("Removing {} containers(s)", args.containers.len());
 
-    let config = RuntimeConfig::from_env();
-    let runtime = ContainerRuntime::new(config)?;
+    let runtime = ctx.runtime()?;
 
     let  mut removed_containers = Vec::new();
     let mut failed_containers = Vec::new();
 
     for container_identifier in args.containers {
-        match remove_single_container(&runtime, &container_identifier, args.force).await {
+        match remove_single_container(runtime, &container_identifier, args.force).await {
             Ok(_container_id) => {
                 removed_containers.push(container_identifier.clone());
                 info!("Removed container: {}", container_identifier);
@@ -52,53 +52,38 @@ pub async fn execute(args: RmArgs) -> Result<()> {
 }
 
 async fn remove_single_container(
-    runtime: &ContainerRuntime,
+    runtime: &dyn Runtime,
     identifier: &str,
     force: bool
 ) -> Result<String> {
-    let container_id = find_container_id(runtime, identifier).await?;
+    let container_id = runtime.resolve(identifier).await?;
 
     runtime.remove_container(&container_id, force).await?;
 
     Ok(container_id)
 }
 
-/// Find container ID by partial ID or name
-async fn find_container_id(runtime: &ContainerRuntime, identifier: &str) -> Result<String> {
-    let containers = runtime.list_containers(true).await?;
-    
-    // First, try exact ID match
-    for container in &containers {
-        if container.id == identifier {
-            return Ok(container.id.clone());
-        }
-    }
-    
-    // Then try partial ID match (like Docker)
-    for container in &containers {
-        if container.id.starts_with(identifier) {
-            return Ok(container.id.clone());
-        }
-    }
-    
-    // Finally, try name match
-    for container in &containers {
-        if let Some(ref name) = container.name {
-            if name == identifier {
-                return Ok(container.id.clone());
-            }
-        }
-    }
-    
-    Err(crate::error::CuboError::ContainerNotFound(identifier.to_string()))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use crate::container::memory::MemoryRuntime;
     use crate::container::Container;
     use tempfile::TempDir;
 
+    #[tokio::test]
+    async fn test_remove_from_memory_runtime() {
+        let runtime = MemoryRuntime::new();
+        let container = Container::new("test:latest".to_string(), vec!["sleep".to_string()])
+            .with_name("in-memory".to_string());
+        let id = runtime.create_container(container).await.unwrap();
+        runtime.start_container(&id, true).await.unwrap();
+
+        assert!(remove_single_container(&runtime, "in-memory", false).await.is_err());
+        assert_eq!(remove_single_container(&runtime, "in-memory", true).await.unwrap(), id);
+        assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_remove_single_container() {
         let temp_dir = TempDir::new().unwrap();
@@ -125,7 +110,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_find_container_id() {
+    async fn test_resolve() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
@@ -143,25 +128,25 @@ mod tests {
         
         // Test exact ID match
         assert_eq!(
-            find_container_id(&runtime, &container_id).await.unwrap(),
+            runtime.resolve(&container_id).await.unwrap(),
             container_id
         );
         
         // Test partial ID match
         let partial_id = &container_id[..8];
         assert_eq!(
-            find_container_id(&runtime, partial_id).await.unwrap(),
+            runtime.resolve(partial_id).await.unwrap(),
             container_id
         );
         
         // Test name match
         assert_eq!(
-            find_container_id(&runtime, "test-container").await.unwrap(),
+            runtime.resolve("test-container").await.unwrap(),
             container_id
         );
         
         // Test not found
-        assert!(find_container_id(&runtime, "nonexistent").await.is_err());
+        assert!(runtime.resolve("nonexistent").await.is_err());
     }
 
     #[tokio::test]
@@ -242,7 +227,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_find_container_id_multiple_containers() {
+    async fn test_resolve_multiple_containers() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
@@ -257,29 +242,29 @@ mod tests {
         let id1 = runtime.create_container(c1).await.unwrap();
         let id2 = runtime.create_container(c2).await.unwrap();
         assert_eq!(
-            find_container_id(&runtime, "container-one").await.unwrap(),
+            runtime.resolve("container-one").await.unwrap(),
             id1
         );
         assert_eq!(
-            find_container_id(&runtime, "container-two").await.unwrap(),
+            runtime.resolve("container-two").await.unwrap(),
             id2
         );
     }
 
     #[tokio::test]
-    async fn test_find_container_id_empty_list() {
+    async fn test_resolve_empty_list() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
             ..Default::default()
         };
         let runtime = ContainerRuntime::new(config).unwrap();
-        let result = find_container_id(&runtime, "any-id").await;
+        let result = runtime.resolve("any-id").await;
         assert!(result.is_err());
     }
 
     #[tokio::test]
-    async fn test_find_container_id_no_name() {
+    async fn test_resolve_no_name() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
@@ -295,13 +280,13 @@ mod tests {
         let container_id = runtime.create_container(container).await.unwrap();
 
         assert_eq!(
-            find_container_id(&runtime, &container_id).await.unwrap(),
+            runtime.resolve(&container_id).await.unwrap(),
             container_id
         );
 
         let partial = &container_id[..6];
         assert_eq!(
-            find_container_id(&runtime, partial).await.unwrap(),
+            runtime.resolve(partial).await.unwrap(),
             container_id
         );
     }
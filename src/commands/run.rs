@@ -1,21 +1,186 @@
-use crate::cli::RunArgs;
+use crate::cli::{BuildArgs, RunArgs};
+use crate::commands::context::CuboContext;
+use crate::commands::build::{self, resolve_image_tag};
+use crate::container::build_progress::ProgressMode;
 use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
-use crate::container::{Container, VolumeMount, PortMapping, Protocol};
+use crate::container::{Container, ContainerConfig, LogDriverKind, RestartPolicy, LogOptions, NetworkMode, UsernsMode, VolumeMount, MountType, PortMapping, Protocol, Relabel, ThrottleDevice};
+use crate::container::{sched, selinux};
 use crate::container::image_store::ImageStore;
-use crate::error::Result;
+use crate::commands::image::verify_image;
+use crate::config::parse_byte_size;
+use crate::container::cgroup::parse_cpu_list;
+use crate::container::ulimit::parse_ulimit;
+use crate::container::namespace::{parse_time_offset, TIME_CLOCKS};
+use crate::container::sysctl::{parse_sysctl, validate_sysctls};
+use crate::container::dns::{parse_host_entry, validate_nameserver};
+use crate::container::signal::parse_signal;
+use crate::container::health::{wait_healthy, HealthCheck};
+use crate::container::supervisor;
+use crate::container::volume::validate_volume_name;
+use crate::container::systemd::{self, SdNotifyMode};
+use crate::error::{CuboError, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{info, warn, error};
 
-pub async fn execute(args: RunArgs) -> Result<()> {
+pub async fn execute(ctx: &CuboContext, mut args: RunArgs) -> Result<()> {
+    if args.build {
+        args.blueprint = build_blueprint(ctx, &args).await?;
+    }
     info!("Running container with blueprint: {}", args.blueprint);
 
-    let config = RuntimeConfig::from_env();
-    let runtime = ContainerRuntime::new(config.clone())?;
+    let config = ctx.config();
+    let runtime = ctx.runtime()?;
+    let image_store = ctx.image_store()?;
+
+    let detached = !args.interactive;
+    if args.wait_healthy && !detached {
+        return Err(CuboError::InvalidConfiguration(
+            "--wait-healthy only applies to detached containers".to_string()
+        ));
+    }
+    let supervised = args.restart != RestartPolicy::No;
+    if supervised && !detached {
+        return Err(CuboError::InvalidConfiguration(
+            "--restart only applies to detached containers".to_string()
+        ));
+    }
+    let wait_for_health = args.wait_healthy;
+    let sdnotify = args.sdnotify;
+    let cidfile = args.cidfile.clone().map(PathBuf::from);
+    let pidfile = args.pidfile.clone().map(PathBuf::from);
+    if let Some(ref path) = cidfile {
+        if path.exists() {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "Container ID file {} already exists",
+                path.display()
+            )));
+        }
+    }
+    if args.verify {
+        verify_image(&config.root_dir, &args.blueprint)?;
+        info!("{} satisfies the trust policy", args.blueprint);
+    }
+    let container = build_container(args, image_store, config)?;
+
+    let container_id = runtime.create_container(container).await?;
+    info!("Created container: {}", container_id);
+    if let Some(ref path) = cidfile {
+        if let Err(e) = write_id_file(path, &container_id) {
+            let _ = runtime.remove_container(&container_id, true).await;
+            return Err(e);
+        }
+    }
+
+    info!("Starting container: {}", container_id);
+
+    // Attached containers only return once they exit, so the pid is written alongside
+    let pid_writer = pidfile.map(|path| {
+        let (runtime, container_id) = (runtime.clone(), container_id.clone());
+        tokio::spawn(async move {
+            let pid = runtime.wait_for_pid(&container_id, PID_TIMEOUT).await?;
+            write_id_file(&path, &pid.to_string())
+        })
+    });
+
+    // Restarting needs a process that outlives this one to wait for the container
+    let started = if supervised {
+        supervisor::launch(runtime, &container_id, &ctx.global_args()).await
+    } else {
+        runtime.start_container(&container_id, detached).await
+    };
+    match started {
+        Ok(_) => {
+            if detached {
+                if let Some(writer) = pid_writer {
+                    writer
+                        .await
+                        .map_err(|e| CuboError::SystemError(format!("Failed to write pid file: {}", e)))??;
+                }
+                if wait_for_health {
+                    wait_healthy(runtime, &container_id).await?;
+                }
+                println!("{}", container_id);
+                info!("Container started in detached mode");
+                notify_started(runtime, &container_id, sdnotify).await;
+            } else {
+                let container = runtime.get_container(&container_id).await?;
+                info!("Container finished with status: {}", container.status);
+                let exit_code = container.exit_code.ok_or_else(|| {
+                    CuboError::SystemError(format!("The exit code of container {} is unknown", container_id))
+                })?;
+                info!("Exit code: {}", exit_code);
+                std::process::exit(exit_code);
+            }
+        }
+        Err(e) => {
+            error!("Failed to start container: {}", e);
+            if let Err(cleanup_err) = runtime.remove_container(&container_id, true).await {
+                error!("Failed to cleanup container after start failure: {}", cleanup_err);
+            }
+            if let Some(ref path) = cidfile {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// How long a detached container may take to get a process for --pidfile
+const PID_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Write a container ID or pid for scripts and init systems to pick up
+fn write_id_file(path: &Path, id: &str) -> Result<()> {
+    std::fs::write(path, format!("{}\n", id))
+        .map_err(|e| CuboError::SystemError(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Under a Type=notify unit, hand the service manager the container's process to supervise
+async fn notify_started(runtime: &ContainerRuntime, container_id: &str, mode: SdNotifyMode) {
+    let pid = runtime.get_container(container_id).await.ok().and_then(|c| c.pid);
+    if let Some(state) = systemd::started_state(mode, pid) {
+        if let Err(e) = systemd::notify(&state) {
+            warn!("Failed to notify systemd: {}", e);
+        }
+    }
+}
 
-    let image_store_path = config.root_dir.join("images");
-    let image_store = ImageStore::new(image_store_path)?;
+/// Build the container described by the run flags, filling in defaults from the image and
+/// then from the configuration
+/// Build the context given as blueprint with `--build`, returning the image to run. Its
+/// progress is only printed for attached containers, whose stdout isn't just the ID.
+async fn build_blueprint(ctx: &CuboContext, args: &RunArgs) -> Result<String> {
+    let context = std::fs::canonicalize(&args.blueprint).map_err(|e| {
+        CuboError::InvalidConfiguration(format!("Build context {} not found: {}", args.blueprint, e))
+    })?;
+    let path = context.to_string_lossy().to_string();
+    let image_tag = resolve_image_tag(&path, None);
+    let build_args = BuildArgs {
+        path,
+        tag: None,
+        file: None,
+        no_cache: false,
+        build_arg: args.build_arg.clone(),
+        label: vec![],
+        watch: false,
+        restart_container: None,
+        progress: ProgressMode::Plain,
+        step_timeout: None,
+        step_memory: None,
+        step_cpus: None,
+    };
+    build::build_image(ctx, build_args, &image_tag, args.interactive).await?;
+    Ok(image_tag)
+}
 
+pub fn build_container(args: RunArgs, image_store: &ImageStore, defaults: &RuntimeConfig) -> Result<Container> {
     let command = if let Some(cmd) = args.command {
         cmd
+    } else if args.entrypoint.is_some() {
+        // Like docker, the image's CMD was meant for its own entrypoint
+        Vec::new()
     } else {
         match image_store.get_config(&args.blueprint) {
             Ok(img_config) => {
@@ -44,6 +209,179 @@ pub async fn execute(args: RunArgs) -> Result<()> {
         container = container.with_workdir(workdir);
     }
 
+    if let Some(hostname) = args.hostname {
+        container = container.with_hostname(hostname);
+    }
+
+    if let Some(domainname) = args.domainname {
+        container = container.with_domainname(domainname);
+    }
+
+    if let Some(user) = args.user {
+        container = container.with_user(user);
+    }
+
+    if let Some(entrypoint) = args.entrypoint {
+        container = container.with_entrypoint(entrypoint);
+    }
+
+    container = container.with_read_only_rootfs(args.read_only);
+    for path in args.tmpfs {
+        container = container.with_volume(parse_tmpfs(&path)?);
+    }
+
+    if let Some(limit) = args.pids_limit {
+        container = container.with_pids_limit(limit);
+    }
+
+    if let Some(ref memory) = args.memory {
+        container = container.with_memory_limit(parse_memory(memory, "memory limit")?);
+    }
+
+    match args.memory_swap.as_deref() {
+        Some("-1") => container = container.with_memory_swap(-1),
+        Some(swap) => container = container.with_memory_swap(parse_memory(swap, "memory swap limit")? as i64),
+        None => {}
+    }
+
+    if let Some(ref reservation) = args.memory_reservation {
+        container = container.with_memory_reservation(parse_memory(reservation, "memory reservation")?);
+    }
+
+    if let Some(score) = args.oom_score_adj {
+        container = container.with_oom_score_adj(score);
+    }
+
+    if let Some(nice) = args.nice {
+        container = container.with_nice(nice);
+    }
+
+    match args.sched_policy {
+        Some(policy) => container = container.with_sched_policy(policy, args.rt_priority),
+        None if args.rt_priority.is_some() => {
+            return Err(CuboError::InvalidConfiguration(
+                "A real-time priority needs the fifo or rr scheduling policy (--sched-policy)".to_string(),
+            ));
+        }
+        None => {}
+    }
+
+    if let Some(runtime) = args.rt_runtime {
+        container = container.with_rt_runtime(runtime);
+    }
+
+    if let Some(priority) = args.ionice {
+        container = container.with_io_priority(priority);
+    }
+
+    if let Some(weight) = args.blkio_weight {
+        container = container.with_blkio_weight(weight);
+    }
+
+    if let Some(cpus) = args.cpuset_cpus {
+        container = container.with_cpuset_cpus(cpus);
+    }
+
+    if let Some(mems) = args.cpuset_mems {
+        container = container.with_cpuset_mems(mems);
+    }
+
+    for device in args.device_read_bps {
+        container = container.with_device_read_bps(parse_throttle_device(&device)?);
+    }
+
+    for device in args.device_write_bps {
+        container = container.with_device_write_bps(parse_throttle_device(&device)?);
+    }
+
+    for ulimit in args.ulimit {
+        container = container.with_ulimit(parse_ulimit(&ulimit)?);
+    }
+
+    for offset in args.time_offset {
+        container = container.with_time_offset(parse_time_offset(&offset)?);
+    }
+
+    container = container.with_network_mode(args.network.unwrap_or_else(|| defaults.default_network_mode.clone()));
+    container = container.with_ipc_mode(args.ipc);
+    container = container.with_userns_mode(args.userns);
+    container = container.with_init(args.init);
+    container = container.with_restart_policy(args.restart);
+    // The configured log options only go with the configured driver
+    let log_driver = args.log_driver.or(defaults.log_driver).unwrap_or_default();
+    let mut log_opts = Vec::new();
+    if defaults.log_driver.unwrap_or_default() == log_driver {
+        log_opts.extend(defaults.log_opts.iter().cloned());
+    }
+    log_opts.extend(args.log_opt);
+    container = container.with_log_driver(log_driver);
+    container = container.with_log_options(parse_log_opts(&log_opts)?);
+    if let Some(size) = parse_storage_opts(&args.storage_opt)? {
+        container = container.with_storage_size(size);
+    }
+
+    let stop_signal = args.stop_signal.or_else(|| {
+        image_store.get_config(&args.blueprint).ok().and_then(|c| c.stop_signal)
+    });
+    if let Some(signal) = stop_signal {
+        parse_signal(&signal)?;
+        container = container.with_stop_signal(signal);
+    }
+
+    if let Some(ip) = args.ip {
+        container = container.with_ip_address(ip);
+    }
+
+    if let Some(mac) = args.mac_address {
+        container = container.with_mac_address(mac);
+    }
+
+    for server in args.dns {
+        validate_nameserver(&server)?;
+        container = container.with_dns(server);
+    }
+
+    for domain in args.dns_search {
+        container = container.with_dns_search(domain);
+    }
+
+    for host in args.add_host {
+        container = container.with_extra_host(parse_host_entry(&host)?);
+    }
+
+    for sysctl in args.sysctl {
+        let (key, value) = parse_sysctl(&sysctl)?;
+        container = container.with_sysctl(key, value);
+    }
+
+    for opt in args.security_opt {
+        parse_security_opt(&opt, &mut container.config)?;
+    }
+
+    if let Some(command) = args.health_cmd {
+        container = container.with_health_check(HealthCheck {
+            command,
+            interval: args.health_interval,
+            timeout: args.health_timeout,
+            retries: args.health_retries,
+        });
+    } else if args.wait_healthy {
+        return Err(CuboError::InvalidConfiguration(
+            "--wait-healthy requires --health-cmd".to_string()
+        ));
+    }
+
+    if args.sdnotify == SdNotifyMode::Container {
+        match std::env::var("NOTIFY_SOCKET") {
+            Ok(socket) => {
+                container = container
+                    .with_volume(systemd::notify_socket_mount(&socket)?)
+                    .with_env("NOTIFY_SOCKET".to_string(), systemd::CONTAINER_NOTIFY_SOCKET.to_string());
+            }
+            Err(_) => warn!("NOTIFY_SOCKET is not set, nothing to forward into the container"),
+        }
+    }
+
     for env_var in args.env {
         if let Some((key, value)) = parse_env_var(&env_var) {
             container = container.with_env(key, value);
@@ -68,44 +406,189 @@ pub async fn execute(args: RunArgs) -> Result<()> {
         }
     }
 
-    let container_id = runtime.create_container(container).await?;
-    info!("Created container: {}", container_id);
+    if args.publish_all {
+        match image_store.get_config(&args.blueprint) {
+            Ok(img_config) => {
+                let exposed = img_config.exposed_ports.unwrap_or_default();
+                for port in publish_exposed(&exposed, &container.config.ports) {
+                    container = container.with_port(port);
+                }
+            }
+            Err(e) => warn!("Failed to load image config, no EXPOSE ports to publish: {}", e),
+        }
+    }
 
-    info!("Starting container: {}", container_id);
+    validate_config(&container.config)?;
+    Ok(container)
+}
 
-    let detached = !args.interactive;
+/// Checks a container config has to pass before it is created
+pub fn validate_config(config: &ContainerConfig) -> Result<()> {
+    if let Some(ref hostname) = config.hostname {
+        validate_hostname(hostname)?;
+    }
+    if let Some(ref domainname) = config.domainname {
+        validate_hostname(domainname)
+            .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid domainname '{}'", domainname)))?;
+    }
+    if let Some(ref user) = config.user {
+        validate_user(user)?;
+    }
+    validate_network_options(config)?;
+    validate_sysctls(config)?;
+    validate_cpusets(config)?;
+    validate_memory(config)?;
+    validate_scheduling(config)?;
+    validate_time_offsets(config)?;
+    validate_relabel(config)?;
+    validate_userns_mode(config, nix::unistd::geteuid().is_root())?;
+    validate_sandbox(config)?;
+    validate_log_options(config)
+}
 
-    match runtime.start_container(&container_id, detached).await {
-        Ok(_) => {
-            if detached {
-                println!("{}", container_id);
-                info!("Container started in detached mode");
-            } else {
-                match runtime.get_container(&container_id).await {
-                    Ok(container) => {
-                        info!("Container finished with status: {}", container.status);
-                        if let Some(exit_code) = container.exit_code {
-                            info!("Exit code: {}", exit_code);
-                            std::process::exit(exit_code);
-                        }
-                    }
-                    Err(e) => error!("Failed to get final container status: {}", e),
+fn parse_memory(size: &str, what: &str) -> Result<u64> {
+    parse_byte_size(size)
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid {}: {}", what, size)))
+}
+
+/// Swap is counted with memory like docker does, so it needs a memory limit to add to
+fn validate_memory(config: &ContainerConfig) -> Result<()> {
+    if let Some(swap) = config.memory_swap {
+        let Some(memory) = config.memory_limit else {
+            return Err(CuboError::InvalidConfiguration(
+                "A memory swap limit needs a memory limit (--memory) as well".to_string(),
+            ));
+        };
+        if swap != -1 && (swap < 0 || (swap as u64) < memory) {
+            return Err(CuboError::InvalidConfiguration(
+                "The memory swap limit counts memory too, so it can't be below the memory limit".to_string(),
+            ));
+        }
+    }
+    if let (Some(reservation), Some(memory)) = (config.memory_reservation, config.memory_limit) {
+        if reservation > memory {
+            return Err(CuboError::InvalidConfiguration(
+                "The memory reservation can't be above the memory limit".to_string(),
+            ));
+        }
+    }
+    if config.oom_score_adj.is_some_and(|score| !(-1000..=1000).contains(&score)) {
+        return Err(CuboError::InvalidConfiguration("The OOM score adjustment must be between -1000 and 1000".to_string()));
+    }
+    Ok(())
+}
+
+/// A real-time priority and runtime only mean something under a real-time policy
+fn validate_scheduling(config: &ContainerConfig) -> Result<()> {
+    if config.nice.is_some_and(|nice| !(-20..=19).contains(&nice)) {
+        return Err(CuboError::InvalidConfiguration("The nice value must be between -20 and 19".to_string()));
+    }
+    let realtime = config.sched_policy.is_some_and(|policy| policy.is_realtime());
+    if let Some(priority) = config.rt_priority {
+        if !realtime {
+            return Err(CuboError::InvalidConfiguration(
+                "A real-time priority needs the fifo or rr scheduling policy".to_string(),
+            ));
+        }
+        if !(1..=sched::MAX_RT_PRIORITY).contains(&priority) {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "The real-time priority must be between 1 and {}", sched::MAX_RT_PRIORITY
+            )));
+        }
+    }
+    match config.rt_runtime {
+        Some(_) if !realtime => Err(CuboError::InvalidConfiguration(
+            "A real-time runtime needs the fifo or rr scheduling policy".to_string(),
+        )),
+        Some(0) => Err(CuboError::InvalidConfiguration("The real-time runtime can't be 0".to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Apply a `--security-opt`; label=... sets the SELinux labels, apparmor=... the AppArmor
+/// profile, mask=... and unmask=... the masked and read-only paths, landlock=on|off the
+/// Landlock sandbox
+pub fn parse_security_opt(opt: &str, config: &mut ContainerConfig) -> Result<()> {
+    match opt.split_once('=') {
+        Some(("label", value)) => selinux::parse_label_opt(value, &mut config.label_options),
+        Some(("apparmor", profile)) if !profile.is_empty() => {
+            config.apparmor_profile = Some(profile.to_string());
+            Ok(())
+        }
+        Some(("landlock", "on")) => {
+            config.landlock = true;
+            Ok(())
+        }
+        Some(("landlock", "off")) => {
+            config.landlock = false;
+            Ok(())
+        }
+        Some(("unmask", "ALL")) => {
+            config.masked_paths.clear();
+            config.readonly_paths.clear();
+            Ok(())
+        }
+        Some(("unmask", paths)) => {
+            let paths = parse_paths(paths)?;
+            config.masked_paths.retain(|p| !paths.contains(p));
+            config.readonly_paths.retain(|p| !paths.contains(p));
+            Ok(())
+        }
+        Some(("mask", paths)) => {
+            for path in parse_paths(paths)? {
+                if !config.masked_paths.contains(&path) {
+                    config.masked_paths.push(path);
                 }
             }
+            Ok(())
         }
-        Err(e) => {
-            error!("Failed to start container: {}", e);
-            if let Err(cleanup_err) = runtime.remove_container(&container_id, true).await {
-                error!("Failed to cleanup container after start failure: {}", cleanup_err);
-            }
-            return Err(e);
+        _ => Err(CuboError::InvalidConfiguration(format!("Unknown security option '{}'", opt))),
+    }
+}
+
+/// Colon separated absolute paths
+fn parse_paths(paths: &str) -> Result<Vec<String>> {
+    paths
+        .split(':')
+        .map(|path| match path.starts_with('/') {
+            true => Ok(path.to_string()),
+            false => Err(CuboError::InvalidConfiguration(format!("Path '{}' must be absolute", path))),
+        })
+        .collect()
+}
+
+/// Bind mounts relabeled with :z or :Z can't be system directories
+fn validate_relabel(config: &ContainerConfig) -> Result<()> {
+    for mount in &config.volume_mounts {
+        if mount.relabel.is_some() && matches!(mount.mount_type, MountType::Bind) {
+            selinux::check_relabel(Path::new(&mount.host_path))?;
         }
     }
+    Ok(())
+}
 
+/// Each clock has a single offset in the time namespace
+fn validate_time_offsets(config: &ContainerConfig) -> Result<()> {
+    for (i, offset) in config.time_offsets.iter().enumerate() {
+        if !TIME_CLOCKS.contains(&offset.clock.as_str()) {
+            return Err(CuboError::InvalidConfiguration(format!("Unknown clock for a time offset: {}", offset.clock)));
+        }
+        if config.time_offsets[..i].iter().any(|o| o.clock == offset.clock) {
+            return Err(CuboError::InvalidConfiguration(format!("The {} clock is offset more than once", offset.clock)));
+        }
+    }
+    Ok(())
+}
+
+fn validate_cpusets(config: &ContainerConfig) -> Result<()> {
+    for list in config.cpuset_cpus.iter().chain(&config.cpuset_mems) {
+        parse_cpu_list(list).map_err(CuboError::InvalidConfiguration)?;
+    }
     Ok(())
 }
 
-fn parse_env_var(env_str: &str) -> Option<(String, String)> {
+pub fn parse_env_var(env_str: &str) -> Option<(String, String)> {
     if let Some((key, value)) = env_str.split_once('=') {
         Some((key.to_string(), value.to_string()))
     } else {
@@ -113,30 +596,40 @@ fn parse_env_var(env_str: &str) -> Option<(String, String)> {
     }
 }
 
-fn parse_volume(volume_str: &str) -> Option<VolumeMount> {
+/// `HOST:CONTAINER[:OPTIONS]` where HOST is a path, or the name of a volume when it doesn't
+/// start with '/' or '.'
+pub fn parse_volume(volume_str: &str) -> Option<VolumeMount> {
     let parts: Vec<&str> = volume_str.split(':').collect();
+    let mount = |source: &str, target: &str| {
+        if source.starts_with('/') || source.starts_with('.') {
+            Some(VolumeMount::bind(source.to_string(), target.to_string(), false))
+        } else {
+            validate_volume_name(source).ok()?;
+            Some(VolumeMount::volume(source.to_string(), target.to_string(), false))
+        }
+    };
 
     match parts.len() {
-        2 => {
-            Some(VolumeMount::bind(
-                parts[0].to_string(),
-                parts[1].to_string(),
-                false
-            ))
-        }
+        2 => mount(parts[0], parts[1]),
         3 => {
-            let read_only = parts[2] == "ro";
-            Some(VolumeMount::bind(
-                parts[0].to_string(),
-                parts[1].to_string(),
-                read_only
-            ))
+            // Comma-separated options: ro or rw, z or Z, and a propagation like rshared
+            let mut volume = mount(parts[0], parts[1])?;
+            for option in parts[2].split(',') {
+                match option {
+                    "ro" => volume.read_only = true,
+                    "rw" => volume.read_only = false,
+                    "z" => volume.relabel = Some(Relabel::Shared),
+                    "Z" => volume.relabel = Some(Relabel::Private),
+                    propagation => volume.propagation = propagation.parse().ok()?,
+                }
+            }
+            Some(volume)
         }
         _ => None,
     }
 }
 
-fn parse_port(port_str: &str) -> Option<PortMapping> {
+pub fn parse_port(port_str: &str) -> Option<PortMapping> {
     // Handle protocol suffix (e.g., "8080:80/tcp")
     let (port_part, protocol) = if let Some((ports, proto)) = port_str.split_once('/') {
         let protocol = match proto.to_lowercase().as_str() {
@@ -149,28 +642,267 @@ fn parse_port(port_str: &str) -> Option<PortMapping> {
         (port_str, Protocol::Tcp) // default to TCP
     };
     
-    // Parse host:container ports
-    if let Some((host_port_str, container_port_str)) = port_part.split_once(':') {
-        if let (Ok(host_port), Ok(container_port)) = 
-            (host_port_str.parse::<u16>(), container_port_str.parse::<u16>()) {
-            Some(PortMapping {
-                host_port,
-                container_port,
-                protocol,
-                host_ip: None,
-            })
-        } else {
-            None
-        }
+    // Parse host:container ports; a lone container port gets a host port when it starts
+    let (host_port_str, container_port_str) = port_part.split_once(':').unwrap_or(("0", port_part));
+    if let (Ok(host_port), Ok(container_port)) =
+        (host_port_str.parse::<u16>(), container_port_str.parse::<u16>()) {
+        Some(PortMapping {
+            host_port,
+            container_port,
+            protocol,
+            host_ip: None,
+        })
     } else {
         None
     }
 }
 
+/// Mappings of the image's EXPOSE ports ("80", "53/udp") to ephemeral host ports, except
+/// those `published` already maps
+pub fn publish_exposed(exposed: &[String], published: &[PortMapping]) -> Vec<PortMapping> {
+    let mut ports: Vec<PortMapping> = Vec::new();
+    for port in exposed.iter().filter_map(|spec| parse_port(spec.trim())) {
+        let taken = published.iter().chain(&ports)
+            .any(|p| p.container_port == port.container_port && p.protocol == port.protocol);
+        if !taken {
+            ports.push(PortMapping { host_port: 0, ..port });
+        }
+    }
+    ports
+}
+
+/// A hostname is dot-separated labels of letters, digits and inner hyphens, at most 64
+/// bytes as sethostname(2) takes
+fn validate_hostname(hostname: &str) -> Result<()> {
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if hostname.len() > 64 || !hostname.split('.').all(valid_label) {
+        return Err(CuboError::InvalidConfiguration(format!("Invalid hostname '{}'", hostname)));
+    }
+    Ok(())
+}
+
+/// `user[:group]`, each a name or a numeric id. Names are resolved against the image's
+/// /etc/passwd and /etc/group when the container starts.
+fn validate_user(user: &str) -> Result<()> {
+    let valid_part = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '$'))
+    };
+    let (name, group) = match user.split_once(':') {
+        Some((name, group)) => (name, Some(group)),
+        None => (user, None),
+    };
+    if !valid_part(name) || group.is_some_and(|g| !valid_part(g)) {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "Invalid user '{}', expected <user>[:<group>]",
+            user
+        )));
+    }
+    Ok(())
+}
+
+/// An empty tmpfs mounted at an absolute path inside the container, with the options after
+/// the colon: `size=64m`, `mode=755`, `exec` or `noexec`
+pub fn parse_tmpfs(spec: &str) -> Result<VolumeMount> {
+    let (path, options) = match spec.split_once(':') {
+        Some((path, options)) => (path, Some(options)),
+        None => (spec, None),
+    };
+    if !path.starts_with('/') || path.trim_end_matches('/').is_empty() {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "tmpfs path '{}' must be an absolute path other than /",
+            path
+        )));
+    }
+
+    let mut volume = VolumeMount::tmpfs(path.to_string());
+    let invalid = |option: &str| CuboError::InvalidConfiguration(format!(
+        "Invalid tmpfs option '{}' in '{}' (expected size=SIZE, mode=OCTAL, exec or noexec)",
+        option, spec
+    ));
+    for option in options.into_iter().flat_map(|o| o.split(',')) {
+        match option.split_once('=') {
+            Some(("size", size)) => {
+                volume.tmpfs_options.size = Some(parse_byte_size(size).filter(|s| *s > 0).ok_or_else(|| invalid(option))?);
+            }
+            Some(("mode", mode)) => {
+                let mode = u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o7777).ok_or_else(|| invalid(option))?;
+                volume.tmpfs_options.mode = Some(mode);
+            }
+            None if option == "exec" => volume.tmpfs_options.exec = true,
+            None if option == "noexec" => volume.tmpfs_options.exec = false,
+            _ => return Err(invalid(option)),
+        }
+    }
+    Ok(volume)
+}
+
+/// Namespaces of a sandbox owned by another user namespace can't be joined from a new one
+fn validate_sandbox(config: &ContainerConfig) -> Result<()> {
+    if config.sandbox.is_some() && config.userns_mode != UsernsMode::Host {
+        return Err(CuboError::InvalidConfiguration(
+            "Joining a sandbox requires --userns host".to_string()
+        ));
+    }
+    Ok(())
+}
+
+/// Without a user namespace only root can create the other namespaces
+fn validate_userns_mode(config: &ContainerConfig, is_root: bool) -> Result<()> {
+    if config.userns_mode == UsernsMode::Host && !is_root {
+        return Err(CuboError::InvalidConfiguration(
+            "--userns host requires running cubo as root".to_string()
+        ));
+    }
+    Ok(())
+}
+
+/// Rotation options only apply to the json-file driver, and max-file needs max-size.
+/// The cri driver takes nothing but the path of its log file, which it requires.
+fn validate_log_options(config: &ContainerConfig) -> Result<()> {
+    let options = &config.log_options;
+    let allowed = match config.log_driver {
+        LogDriverKind::JsonFile => options.path.is_none(),
+        LogDriverKind::Cri => options.max_size.is_none() && options.max_file.is_none(),
+        _ => *options == LogOptions::default(),
+    };
+    if !allowed {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "--log-opt is not supported by the {} log driver",
+            config.log_driver
+        )));
+    }
+    if config.log_driver == LogDriverKind::Cri && options.path.is_none() {
+        return Err(CuboError::InvalidConfiguration(
+            "The cri log driver requires --log-opt path=FILE".to_string()
+        ));
+    }
+    if options.max_file.is_some() && options.max_size.is_none() {
+        return Err(CuboError::InvalidConfiguration(
+            "max-file requires max-size".to_string()
+        ));
+    }
+    Ok(())
+}
+
+/// Reject option combinations that make no sense for the chosen network mode
+fn validate_network_options(config: &ContainerConfig) -> Result<()> {
+    if (config.ip_address.is_some() || config.mac_address.is_some())
+        && !matches!(config.network_mode, NetworkMode::Custom(_))
+    {
+        return Err(CuboError::InvalidConfiguration(
+            "--ip and --mac-address require a user-defined network (--network <name>)".to_string()
+        ));
+    }
+
+    if !config.ports.is_empty() {
+        match config.network_mode {
+            NetworkMode::Host => {
+                return Err(CuboError::InvalidConfiguration(
+                    "Port publishing (-p) is not supported with --network host; container ports are already host ports".to_string()
+                ));
+            }
+            NetworkMode::None => {
+                return Err(CuboError::InvalidConfiguration(
+                    "Port publishing (-p) is not supported with --network none".to_string()
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_throttle_device(device_str: &str) -> Result<ThrottleDevice> {
+    let (path, rate) = device_str.rsplit_once(':').ok_or_else(|| {
+        CuboError::InvalidConfiguration(format!(
+            "Invalid device rate '{}', expected <device>:<rate>",
+            device_str
+        ))
+    })?;
+
+    if !path.starts_with('/') {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "Device path must be absolute: {}",
+            path
+        )));
+    }
+
+    let rate = parse_byte_size(rate).ok_or_else(|| {
+        CuboError::InvalidConfiguration(format!("Invalid rate: {}", rate))
+    })?;
+
+    Ok(ThrottleDevice { path: path.to_string(), rate })
+}
+
+/// Parse `--log-opt` values; each may hold several comma separated key=value pairs
+fn parse_log_opts(opts: &[String]) -> Result<LogOptions> {
+    let mut options = LogOptions::default();
+    for opt in opts.iter().flat_map(|o| o.split(',')).filter(|o| !o.is_empty()) {
+        let invalid = || CuboError::InvalidConfiguration(format!("Invalid log option '{}'", opt));
+        let (key, value) = opt.split_once('=').ok_or_else(invalid)?;
+        match key {
+            "max-size" => {
+                let size = parse_byte_size(value).filter(|s| *s > 0).ok_or_else(invalid)?;
+                options.max_size = Some(size);
+            }
+            "max-file" => {
+                let count = value.parse::<u32>().ok().filter(|c| *c > 0).ok_or_else(invalid)?;
+                options.max_file = Some(count);
+            }
+            "path" if !value.is_empty() => options.path = Some(PathBuf::from(value)),
+            _ => {
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "Unknown log option '{}', expected max-size, max-file or path",
+                    key
+                )));
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Parse `--storage-opt` values into the rootfs quota; `size` is the only option
+fn parse_storage_opts(opts: &[String]) -> Result<Option<u64>> {
+    let mut size = None;
+    for opt in opts {
+        let invalid = || CuboError::InvalidConfiguration(format!("Invalid storage option '{}'", opt));
+        match opt.split_once('=').ok_or_else(invalid)? {
+            ("size", value) => size = Some(parse_byte_size(value).filter(|s| *s > 0).ok_or_else(invalid)?),
+            (key, _) => {
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "Unknown storage option '{}', expected size",
+                    key
+                )));
+            }
+        }
+    }
+    Ok(size)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::container::MountType;
+    use crate::container::{Propagation, TimeOffset, TmpfsOptions};
+    use crate::container::sched::SchedPolicy;
+
+    #[test]
+    fn test_write_id_file() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("cid");
+        write_id_file(&path, "abc123").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abc123\n");
+
+        assert!(write_id_file(&temp.path().join("missing/cid"), "abc123").is_err());
+    }
 
     #[test]
     fn test_parse_env_var() {
@@ -237,6 +969,71 @@ mod tests {
         assert!(!volume.read_only); // "rw" != "ro", so read_only is false
     }
 
+    #[test]
+    fn test_parse_volume_propagation() {
+        let volume = parse_volume("/mnt/fuse:/fuse:ro,rshared").unwrap();
+        assert!(volume.read_only);
+        assert_eq!(volume.propagation, Propagation::Rshared);
+        assert_eq!(parse_volume("/a:/b:rslave").unwrap().propagation, Propagation::Rslave);
+        assert_eq!(parse_volume("/a:/b").unwrap().propagation, Propagation::Rprivate);
+        assert!(parse_volume("/a:/b:ro,bogus").is_none());
+    }
+
+    #[test]
+    fn test_parse_volume_relabel() {
+        assert_eq!(parse_volume("/srv/web:/web:Z").unwrap().relabel, Some(Relabel::Private));
+        let volume = parse_volume("cache:/cache:ro,z").unwrap();
+        assert!(volume.read_only);
+        assert_eq!(volume.relabel, Some(Relabel::Shared));
+        assert_eq!(parse_volume("/a:/b").unwrap().relabel, None);
+    }
+
+    #[test]
+    fn test_parse_security_opt() {
+        let mut config = ContainerConfig::default();
+        parse_security_opt("label=type:spc_t", &mut config).unwrap();
+        parse_security_opt("label=disable", &mut config).unwrap();
+        assert_eq!(config.label_options.kind.as_deref(), Some("spc_t"));
+        assert!(config.label_options.disable);
+        assert!(parse_security_opt("label=bogus", &mut config).is_err());
+        parse_security_opt("apparmor=unconfined", &mut config).unwrap();
+        assert_eq!(config.apparmor_profile.as_deref(), Some("unconfined"));
+        assert!(parse_security_opt("apparmor=", &mut config).is_err());
+        assert!(parse_security_opt("seccomp=unconfined", &mut config).is_err());
+
+        parse_security_opt("unmask=/proc/kcore:/proc/sys", &mut config).unwrap();
+        parse_security_opt("mask=/proc/cpuinfo:/proc/keys", &mut config).unwrap();
+        assert!(!config.masked_paths.iter().any(|p| p == "/proc/kcore"));
+        assert!(!config.readonly_paths.iter().any(|p| p == "/proc/sys"));
+        assert_eq!(config.masked_paths.iter().filter(|p| *p == "/proc/keys").count(), 1);
+        assert!(config.masked_paths.iter().any(|p| p == "/proc/cpuinfo"));
+        assert!(parse_security_opt("mask=proc/cpuinfo", &mut config).is_err());
+        assert!(parse_security_opt("unmask=", &mut config).is_err());
+        parse_security_opt("landlock=on", &mut config).unwrap();
+        assert!(config.landlock);
+        assert!(parse_security_opt("landlock=yes", &mut config).is_err());
+        parse_security_opt("unmask=ALL", &mut config).unwrap();
+        assert!(config.masked_paths.is_empty() && config.readonly_paths.is_empty());
+    }
+
+    #[test]
+    fn test_validate_relabel() {
+        let config = |volume: VolumeMount| ContainerConfig { volume_mounts: vec![volume], ..Default::default() };
+        assert!(validate_relabel(&config(parse_volume("/usr:/usr:ro,z").unwrap())).is_err());
+        assert!(validate_relabel(&config(parse_volume("/usr:/usr:ro").unwrap())).is_ok());
+        assert!(validate_relabel(&config(parse_volume("/srv/web:/web:Z").unwrap())).is_ok());
+    }
+
+    #[test]
+    fn test_parse_named_volume() {
+        let volume = parse_volume("pgdata:/var/lib/postgresql/data:ro").unwrap();
+        assert!(matches!(volume.mount_type, MountType::Volume));
+        assert_eq!(volume.host_path, "pgdata");
+        assert!(volume.read_only);
+        assert!(matches!(parse_volume("./data:/data").unwrap().mount_type, MountType::Bind));
+        assert!(parse_volume("bad name:/data").is_none());
+    }
+
     #[test]
     fn test_parse_volume_single_path() {
         let result = parse_volume("/single/path");
@@ -317,8 +1114,18 @@ mod tests {
 
     #[test]
     fn test_parse_port_single_number() {
-        let result = parse_port("8080");
-        assert!(result.is_none());
+        let port = parse_port("8080").unwrap();
+        assert!(port.is_ephemeral());
+        assert_eq!(port.container_port, 8080);
+        assert!(matches!(parse_port("53/udp").unwrap().protocol, Protocol::Udp));
+        assert!(parse_port("http").is_none());
+    }
+
+    #[test]
+    fn test_publish_exposed() {
+        let exposed = ["80/tcp".to_string(), "53/udp".to_string(), "443".to_string(), "80".to_string()];
+        let ports = publish_exposed(&exposed, &[PortMapping::tcp(8443, 443)]);
+        assert_eq!(ports, [PortMapping::tcp(0, 80), PortMapping::udp(0, 53)]);
     }
 
     #[test]
@@ -343,5 +1150,366 @@ mod tests {
         assert_eq!(port.host_port, 1);
         assert_eq!(port.container_port, 1);
     }
-}
 
+    #[test]
+    fn test_parse_throttle_device() {
+        let device = parse_throttle_device("/dev/sda:1mb").unwrap();
+        assert_eq!(device.path, "/dev/sda");
+        assert_eq!(device.rate, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_parse_throttle_device_invalid() {
+        assert!(parse_throttle_device("/dev/sda").is_err());
+        assert!(parse_throttle_device("sda:1mb").is_err());
+        assert!(parse_throttle_device("/dev/sda:fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_storage_opts() {
+        assert_eq!(parse_storage_opts(&[]).unwrap(), None);
+        assert_eq!(parse_storage_opts(&["size=10G".to_string()]).unwrap(), Some(10 * 1024 * 1024 * 1024));
+        assert!(parse_storage_opts(&["size=0".to_string()]).is_err());
+        assert!(parse_storage_opts(&["size".to_string()]).is_err());
+        assert!(parse_storage_opts(&["inodes=100".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_log_opts() {
+        let opts = vec!["max-size=10m,max-file=3".to_string()];
+        let options = parse_log_opts(&opts).unwrap();
+        assert_eq!(options.max_size, Some(10 * 1024 * 1024));
+        assert_eq!(options.max_file, Some(3));
+
+        let opts = vec!["max-size=1k".to_string(), "max-file=2".to_string()];
+        assert_eq!(parse_log_opts(&opts).unwrap().max_file, Some(2));
+        assert_eq!(parse_log_opts(&[]).unwrap(), LogOptions::default());
+    }
+
+    #[test]
+    fn test_parse_log_opts_invalid() {
+        for opt in ["max-size", "max-size=huge", "max-size=0", "max-file=0", "max-file=-1", "compress=true"] {
+            assert!(parse_log_opts(&[opt.to_string()]).is_err(), "{} should be rejected", opt);
+        }
+    }
+
+    #[test]
+    fn test_validate_log_options() {
+        let mut config = ContainerConfig {
+            log_options: LogOptions { max_size: Some(1024), max_file: Some(3), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(validate_log_options(&config).is_ok());
+
+        config.log_driver = LogDriverKind::Journald;
+        assert!(validate_log_options(&config).unwrap_err().to_string().contains("journald"));
+
+        let config = ContainerConfig {
+            log_options: LogOptions { max_size: None, max_file: Some(3), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(validate_log_options(&config).is_err());
+    }
+
+    #[test]
+    fn test_validate_cri_log_options() {
+        let mut config = ContainerConfig { log_driver: LogDriverKind::Cri, ..Default::default() };
+        assert!(validate_log_options(&config).unwrap_err().to_string().contains("path=FILE"));
+
+        config.log_options = parse_log_opts(&["path=/var/log/pods/app/0.log".to_string()]).unwrap();
+        assert!(validate_log_options(&config).is_ok());
+
+        config.log_driver = LogDriverKind::JsonFile;
+        assert!(validate_log_options(&config).is_err());
+    }
+
+    #[test]
+    fn test_validate_memory() {
+        let config = |memory: Option<u64>, swap: Option<i64>, reservation: Option<u64>| ContainerConfig {
+            memory_limit: memory,
+            memory_swap: swap,
+            memory_reservation: reservation,
+            ..Default::default()
+        };
+        assert!(validate_memory(&config(Some(512), Some(1024), Some(256))).is_ok());
+        assert!(validate_memory(&config(Some(512), Some(-1), None)).is_ok());
+        assert!(validate_memory(&config(None, None, Some(256))).is_ok());
+        assert!(validate_memory(&config(None, Some(1024), None)).is_err());
+        assert!(validate_memory(&config(Some(512), Some(256), None)).is_err());
+        assert!(validate_memory(&config(Some(512), Some(-2), None)).is_err());
+        assert!(validate_memory(&config(Some(256), None, Some(512))).is_err());
+        let oom = ContainerConfig { oom_score_adj: Some(1001), ..Default::default() };
+        assert!(validate_memory(&oom).is_err());
+    }
+
+    #[test]
+    fn test_validate_scheduling() {
+        let config = |policy: Option<SchedPolicy>, priority: Option<u32>, runtime: Option<u64>| ContainerConfig {
+            sched_policy: policy,
+            rt_priority: priority,
+            rt_runtime: runtime,
+            ..Default::default()
+        };
+        assert!(validate_scheduling(&config(Some(SchedPolicy::Fifo), Some(50), Some(950000))).is_ok());
+        assert!(validate_scheduling(&config(Some(SchedPolicy::Batch), None, None)).is_ok());
+        assert!(validate_scheduling(&config(Some(SchedPolicy::Batch), Some(10), None)).is_err());
+        assert!(validate_scheduling(&config(Some(SchedPolicy::Rr), Some(100), None)).is_err());
+        assert!(validate_scheduling(&config(None, None, Some(1000))).is_err());
+        assert!(validate_scheduling(&config(Some(SchedPolicy::Rr), None, Some(0))).is_err());
+        let nice = ContainerConfig { nice: Some(-21), ..Default::default() };
+        assert!(validate_scheduling(&nice).is_err());
+    }
+
+    #[test]
+    fn test_validate_time_offsets() {
+        let offset = |clock: &str| TimeOffset { clock: clock.to_string(), secs: 60 };
+        let config = |offsets: Vec<TimeOffset>| ContainerConfig { time_offsets: offsets, ..Default::default() };
+        assert!(validate_time_offsets(&config(vec![offset("monotonic"), offset("boottime")])).is_ok());
+        assert!(validate_time_offsets(&config(vec![offset("monotonic"), offset("monotonic")])).is_err());
+        assert!(validate_time_offsets(&config(vec![offset("realtime")])).is_err());
+    }
+
+    #[test]
+    fn test_validate_cpusets() {
+        let config = ContainerConfig { cpuset_cpus: Some("0-3,8".to_string()), cpuset_mems: Some("0".to_string()), ..Default::default() };
+        assert!(validate_cpusets(&config).is_ok());
+        let config = ContainerConfig { cpuset_mems: Some("0-".to_string()), ..Default::default() };
+        assert!(matches!(validate_cpusets(&config), Err(CuboError::InvalidConfiguration(_))));
+    }
+
+    #[test]
+    fn test_validate_sandbox_requires_host_userns() {
+        let mut config = ContainerConfig { sandbox: Some("infra".to_string()), ..Default::default() };
+        assert!(validate_sandbox(&config).is_err());
+
+        config.userns_mode = UsernsMode::Host;
+        assert!(validate_sandbox(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_network_options_host_with_ports() {
+        let mut config = ContainerConfig {
+            network_mode: NetworkMode::Host,
+            ..Default::default()
+        };
+        assert!(validate_network_options(&config).is_ok());
+
+        config.ports.push(PortMapping::tcp(8080, 80));
+        let err = validate_network_options(&config).unwrap_err();
+        assert!(err.to_string().contains("--network host"));
+    }
+
+    #[test]
+    fn test_validate_network_options_none_with_ports() {
+        let config = ContainerConfig {
+            network_mode: NetworkMode::None,
+            ports: vec![PortMapping::tcp(8080, 80)],
+            ..Default::default()
+        };
+        assert!(validate_network_options(&config).is_err());
+    }
+
+    #[test]
+    fn test_validate_network_options_bridge_with_ports() {
+        let config = ContainerConfig {
+            ports: vec![PortMapping::tcp(8080, 80)],
+            ..Default::default()
+        };
+        assert!(validate_network_options(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_network_options_static_ip_needs_user_network() {
+        let mut config = ContainerConfig {
+            ip_address: Some("10.89.1.5".to_string()),
+            ..Default::default()
+        };
+        assert!(validate_network_options(&config).is_err());
+
+        config.network_mode = NetworkMode::Custom("mynet".to_string());
+        assert!(validate_network_options(&config).is_ok());
+    }
+
+    #[test]
+    fn test_validate_userns_mode() {
+        let config = ContainerConfig {
+            userns_mode: UsernsMode::Host,
+            ..Default::default()
+        };
+        assert!(validate_userns_mode(&config, true).is_ok());
+        assert!(validate_userns_mode(&config, false).is_err());
+        assert!(validate_userns_mode(&ContainerConfig::default(), false).is_ok());
+    }
+
+    #[test]
+    fn test_build_args_require_build() {
+        use clap::Parser;
+        let args = run_args(&["--build", "--build-arg", "MODE=dev", "."]);
+        assert!(args.build);
+        assert_eq!(args.build_arg, ["MODE=dev"]);
+        assert!(crate::cli::Cli::try_parse_from(["cubo", "run", "--build-arg", "MODE=dev", "alpine"]).is_err());
+    }
+
+    #[test]
+    fn test_build_container_entrypoint_drops_the_image_cmd() {
+        use crate::container::image_store::{ImageConfig, ImageManifest};
+        let temp = tempfile::TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        store
+            .save_manifest(&ImageManifest {
+                reference: "app:latest".to_string(),
+                parent: None,
+                layers: vec![],
+                config: ImageConfig {
+                    cmd: Some(vec!["serve".to_string()]),
+                    env: None,
+                    working_dir: None,
+                    exposed_ports: None,
+                    stop_signal: None,
+                    labels: None,
+                },
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+
+        let container = build_container(run_args(&["--entrypoint", "/bin/app", "app:latest"]), &store, &RuntimeConfig::default()).unwrap();
+        assert_eq!(container.config.entrypoint.as_deref(), Some("/bin/app"));
+        assert!(container.command.is_empty());
+
+        let container = build_container(run_args(&["--entrypoint", "/bin/app", "app:latest", "--", "--debug"]), &store, &RuntimeConfig::default()).unwrap();
+        assert_eq!(container.command, ["--debug"]);
+    }
+
+    #[tokio::test]
+    async fn test_build_blueprint_needs_a_context() {
+        let args = run_args(&["--build", "/nonexistent/context"]);
+        let err = build_blueprint(&CuboContext::new(RuntimeConfig::default()), &args).await.unwrap_err();
+        assert!(matches!(err, CuboError::InvalidConfiguration(_)));
+    }
+
+    fn run_args(args: &[&str]) -> RunArgs {
+        use clap::Parser;
+        match crate::cli::Cli::parse_from(["cubo", "run"].iter().chain(args)).command {
+            crate::cli::Commands::Run(args) => *args,
+            _ => panic!("Expected Run command"),
+        }
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_build_container_health_and_sdnotify() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+
+        let args = run_args(&["--health-cmd", "true", "--health-retries", "5", "alpine", "sleep"]);
+        let container = build_container(args, &store, &RuntimeConfig::default()).unwrap();
+        let check = container.config.health_check.unwrap();
+        assert_eq!((check.command.as_str(), check.interval, check.retries), ("true", 30, 5));
+
+        let err = build_container(run_args(&["--wait-healthy", "alpine", "sleep"]), &store, &RuntimeConfig::default()).unwrap_err();
+        assert!(matches!(err, CuboError::InvalidConfiguration(_)));
+
+        std::env::set_var("NOTIFY_SOCKET", "/run/systemd/notify");
+        let container = build_container(run_args(&["--sdnotify", "container", "alpine", "sleep"]), &store, &RuntimeConfig::default()).unwrap();
+        std::env::set_var("NOTIFY_SOCKET", "@abstract");
+        let abstract_socket = build_container(run_args(&["--sdnotify", "container", "alpine", "sleep"]), &store, &RuntimeConfig::default());
+        std::env::remove_var("NOTIFY_SOCKET");
+
+        assert_eq!(container.config.env_vars["NOTIFY_SOCKET"], systemd::CONTAINER_NOTIFY_SOCKET);
+        assert_eq!(container.config.volume_mounts[0].host_path, "/run/systemd/notify");
+        assert!(abstract_socket.is_err());
+    }
+
+    #[test]
+    fn test_validate_hostname() {
+        assert!(validate_hostname("web").is_ok());
+        assert!(validate_hostname("web-1.example.com").is_ok());
+        assert!(validate_hostname("").is_err());
+        assert!(validate_hostname("-web").is_err());
+        assert!(validate_hostname("web..local").is_err());
+        assert!(validate_hostname("web_1").is_err());
+        assert!(validate_hostname(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_validate_user() {
+        assert!(validate_user("1000").is_ok());
+        assert!(validate_user("app:staff").is_ok());
+        assert!(validate_user("1000:1000").is_ok());
+        assert!(validate_user("").is_err());
+        assert!(validate_user("app:").is_err());
+        assert!(validate_user(":staff").is_err());
+        assert!(validate_user("app:staff:x").is_err());
+        assert!(validate_user("app user").is_err());
+    }
+
+    #[test]
+    fn test_parse_tmpfs() {
+        let mount = parse_tmpfs("/run").unwrap();
+        assert!(matches!(mount.mount_type, MountType::Tmpfs));
+        assert_eq!(mount.container_path, "/run");
+        assert!(parse_tmpfs("run").is_err());
+        assert!(parse_tmpfs("/").is_err());
+        assert_eq!(mount.tmpfs_options, TmpfsOptions::default());
+    }
+
+    #[test]
+    fn test_parse_tmpfs_options() {
+        let mount = parse_tmpfs("/run:size=64m,mode=1770,exec").unwrap();
+        assert_eq!(mount.container_path, "/run");
+        assert_eq!(mount.tmpfs_options, TmpfsOptions { size: Some(64 << 20), mode: Some(0o1770), exec: true });
+        assert_eq!(mount.tmpfs_options.data().as_deref(), Some("size=67108864,mode=1770"));
+        assert!(!parse_tmpfs("/tmp:exec,noexec").unwrap().tmpfs_options.exec);
+
+        for spec in ["/run:size=lots", "/run:mode=999", "/run:mode=17777", "/run:uid=0", "/run:size=0", "run:size=1m"] {
+            assert!(parse_tmpfs(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_build_container_hostname_user_read_only_tmpfs() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+
+        let args = run_args(&[
+            "--hostname", "web", "-u", "app:staff", "--read-only", "--tmpfs", "/run", "--tmpfs", "/tmp", "alpine", "sleep",
+        ]);
+        let container = build_container(args, &store, &RuntimeConfig::default()).unwrap();
+        assert_eq!(container.config.hostname.as_deref(), Some("web"));
+        assert_eq!(container.config.user.as_deref(), Some("app:staff"));
+        assert!(container.config.read_only_rootfs);
+        let tmpfs: Vec<&str> = container.config.volume_mounts.iter().map(|m| m.container_path.as_str()).collect();
+        assert_eq!(tmpfs, ["/run", "/tmp"]);
+
+        let args = run_args(&["--hostname", "not_valid", "alpine", "sleep"]);
+        assert!(build_container(args, &store, &RuntimeConfig::default()).is_err());
+
+        let args = run_args(&["--domainname", "example.com", "alpine", "sleep"]);
+        let container = build_container(args, &store, &RuntimeConfig::default()).unwrap();
+        assert_eq!(container.config.domainname.as_deref(), Some("example.com"));
+        let args = run_args(&["--domainname", "example..com", "alpine", "sleep"]);
+        assert!(build_container(args, &store, &RuntimeConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_build_container_restart_policy() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+
+        let container = build_container(run_args(&["alpine", "sleep"]), &store, &RuntimeConfig::default()).unwrap();
+        assert_eq!(container.config.restart_policy, RestartPolicy::No);
+
+        let args = run_args(&["--restart", "on-failure:3", "alpine", "sleep"]);
+        let container = build_container(args, &store, &RuntimeConfig::default()).unwrap();
+        assert_eq!(container.config.restart_policy, RestartPolicy::OnFailure { max_retries: 3 });
+    }
+
+    #[tokio::test]
+    async fn test_restart_requires_detached() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let ctx = CuboContext::new(RuntimeConfig { root_dir: temp.path().to_path_buf(), ..RuntimeConfig::default() });
+        let result = execute(&ctx, run_args(&["--restart", "always", "-i", "alpine", "sleep"])).await;
+        assert!(matches!(result, Err(CuboError::InvalidConfiguration(_))));
+    }
+}
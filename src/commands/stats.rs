@@ -0,0 +1,111 @@
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+use crate::cli::StatsArgs;
+use crate::commands::context::CuboContext;
+use crate::commands::logs::parse_time_spec;
+use crate::container::disk_usage::format_size;
+use crate::container::runtime::{resolve_identifier, ContainerRuntime};
+use crate::container::stats::{self, ExportRow, StatsHistory, StatsSample};
+use crate::container::Container;
+use crate::error::Result;
+
+const CLEAR: &str = "\x1b[2J\x1b[H";
+
+/// Time between the two samples the CPU column is computed from
+const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+pub async fn execute(ctx: &CuboContext, args: StatsArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let containers = selected(runtime, &args.containers).await?;
+
+    if let Some(format) = args.export {
+        let since = args.since.as_deref().map(|spec| parse_time_spec(spec, chrono::Utc::now())).transpose()?;
+        let capacity = runtime.config().stats_history;
+        let histories = containers
+            .iter()
+            .map(|c| Ok((c, StatsHistory::new(&runtime.root_dir().join(&c.id), capacity).load()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let rows: Vec<ExportRow> = histories
+            .iter()
+            .flat_map(|(container, samples)| {
+                samples
+                    .iter()
+                    .filter(|s| since.is_none_or(|since| s.timestamp >= since))
+                    .map(|sample| ExportRow { container: display_name(container), sample })
+            })
+            .collect();
+        print!("{}", stats::export(&rows, format)?);
+        return Ok(());
+    }
+
+    let terminal = std::io::stdout().is_terminal();
+    let mut previous = read_all(runtime, &containers);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(SAMPLE_WINDOW) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+        let current = read_all(runtime, &containers);
+        let mut table = format!(
+            "{:<20} {:>8} {:>22} {:>6} {:>22}\n",
+            "CONTAINER", "CPU %", "MEM USAGE / LIMIT", "PIDS", "BLOCK I/O"
+        );
+        for ((container, sample), before) in containers.iter().zip(&current).zip(&previous) {
+            let line = match (sample, before) {
+                (Some(sample), Some(before)) => row(&sample.clone().since(before)),
+                _ => format!("{:>8} {:>22} {:>6} {:>22}", "-", "-", "-", "-"),
+            };
+            table.push_str(&format!("{:<20} {}\n", display_name(container), line));
+        }
+
+        if terminal && !args.no_stream {
+            print!("{}", CLEAR);
+        }
+        print!("{}", table);
+        std::io::stdout().flush()?;
+        if args.no_stream {
+            return Ok(());
+        }
+        previous = current;
+    }
+}
+
+/// The named containers, or every running one
+async fn selected(runtime: &ContainerRuntime, identifiers: &[String]) -> Result<Vec<Container>> {
+    if identifiers.is_empty() {
+        return runtime.list_containers(false).await;
+    }
+    let containers = runtime.list_containers(true).await?;
+    let mut chosen = Vec::new();
+    for identifier in identifiers {
+        let id = resolve_identifier(&containers, identifier)?;
+        if let Some(container) = containers.iter().find(|c| c.id == id) {
+            chosen.push(container.clone());
+        }
+    }
+    Ok(chosen)
+}
+
+fn read_all(runtime: &ContainerRuntime, containers: &[Container]) -> Vec<Option<StatsSample>> {
+    containers
+        .iter()
+        .map(|c| StatsSample::read(&runtime.config().cgroup(&c.id)).ok())
+        .collect()
+}
+
+fn row(sample: &StatsSample) -> String {
+    let limit = sample.memory_limit.map(format_size).unwrap_or_else(|| "unlimited".to_string());
+    format!(
+        "{:>8} {:>22} {:>6} {:>22}",
+        sample.cpu_percent.map(|p| format!("{:.2}%", p)).unwrap_or_else(|| "-".to_string()),
+        format!("{} / {}", format_size(sample.memory_bytes), limit),
+        sample.pids,
+        format!("{} / {}", format_size(sample.io_read_bytes), format_size(sample.io_write_bytes)),
+    )
+}
+
+fn display_name(container: &Container) -> &str {
+    container.name.as_deref().unwrap_or(&container.id[..12.min(container.id.len())])
+}
+
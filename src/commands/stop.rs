@@ -1,10 +1,10 @@
 use crate::cli::StopArgs;
-use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
-use crate::container::Container;
+use crate::commands::context::CuboContext;
+use crate::container::api::Runtime;
 use crate::error::Result;
 use tracing::{info, warn, error};
 
-pub async fn execute(args: StopArgs) -> Result<()> {
+pub async fn execute(ctx: &CuboContext, args: StopArgs) -> Result<()> {
     if args.containers.is_empty() {
         error!("No contiainers specified");
         return Err(crate::error::CuboError::InvalidConfiguration(
@@ -14,14 +14,13 @@ pub async fn execute(args: StopArgs) -> Result<()> {
 
     info!("Removing {} container(s)", args.containers.len());
 
-    let config = RuntimeConfig::from_env();
-    let runtime = ContainerRuntime::new(config)?;
+    let runtime = ctx.runtime()?;
 
     let mut removed_containers: Vec<String> = Vec::new();
     let mut failed_containers: Vec<(String, crate::error::CuboError)> = Vec::new();
 
     for container_identifier in args.containers {
-        match remove_single_container(&runtime, &container_identifier, args.force).await {
+        match remove_single_container(runtime, &container_identifier, args.force).await {
             Ok(_container_id) => {
                 removed_containers.push(container_identifier.clone());
                 info!("Removed container: {}", container_identifier);
@@ -50,47 +49,21 @@ pub async fn execute(args: StopArgs) -> Result<()> {
 }
 
 async fn remove_single_container(
-    runtime: &ContainerRuntime,
+    runtime: &dyn Runtime,
     identifier: &str,
     force: bool
 ) -> Result<String> {
-    let container_id = find_container_id(runtime, identifier).await?;
+    let container_id = runtime.resolve(identifier).await?;
 
     runtime.remove_container(&container_id, force).await?;
 
     Ok(container_id)
 }
 
-async fn find_container_id(runtime: &ContainerRuntime, identifier: &str) -> Result<String> {
-    let containers: Vec<Container> = runtime.list_containers(true).await?;
-
-    for container in &containers {
-        if container.id == identifier {
-            return Ok(container.id.clone());
-        }
-    }
-
-    for container in &containers {
-        if container.id.starts_with(identifier) {
-            return Ok(container.id.clone())
-        }
-    }
-
-    for container in &containers {
-        if let Some(ref name) = container.name {
-            if name == identifier {
-                return Ok(container.id.clone());
-            }
-        }
-    }
-
-    Err(crate::error::CuboError::ContainerNotFound(identifier.to_string()))
-
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
     use crate::container::Container;
     use tempfile::TempDir;
 
@@ -101,7 +74,7 @@ mod tests {
             force: false,
         };
 
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(err.to_string().contains("At least one container"));
@@ -116,13 +89,13 @@ mod tests {
             containers: vec!["nonexistent".to_string()],
             force: false,
         };
-        let result = execute(args).await;
+        let result = execute(&CuboContext::new(RuntimeConfig::from_env()), args).await;
         assert!(result.is_err());
         std::env::remove_var("CUBO_ROOT");
     }
 
     #[tokio::test]
-    async fn test_find_container_id_exact_match() {
+    async fn test_resolve_exact_match() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
@@ -135,13 +108,13 @@ mod tests {
         ).with_name("stop-test".to_string());
         let container_id = runtime.create_container(container).await.unwrap();
 
-        let result = find_container_id(&runtime, &container_id).await;
+        let result = runtime.resolve(&container_id).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), container_id);
     }
 
     #[tokio::test]
-    async fn test_find_container_id_partial_match() {
+    async fn test_resolve_partial_match() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
@@ -155,7 +128,7 @@ mod tests {
 
         let container_id = runtime.create_container(container).await.unwrap();
         let partial_id = &container_id[..8];
-        let result = find_container_id(&runtime, partial_id).await;
+        let result = runtime.resolve(partial_id).await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), container_id);
     }
@@ -173,20 +146,20 @@ mod tests {
             vec!["echo".to_string()],
         ).with_name("my-named-container".to_string());
         let container_id = runtime.create_container(container).await.unwrap();
-        let result = find_container_id(&runtime, "my-named-container").await;
+        let result = runtime.resolve("my-named-container").await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), container_id);
     }
 
     #[tokio::test]
-    async fn test_find_container_id_not_found() {
+    async fn test_resolve_not_found() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
             ..Default::default()
         };
         let runtime = ContainerRuntime::new(config).unwrap();
-        let result = find_container_id(&runtime, "nonexistent").await;
+        let result = runtime.resolve("nonexistent").await;
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(matches!(err, crate::error::CuboError::ContainerNotFound(_)));
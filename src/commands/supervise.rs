@@ -0,0 +1,9 @@
+use crate::cli::SuperviseArgs;
+use crate::commands::context::CuboContext;
+use crate::container::supervisor;
+use crate::error::Result;
+
+pub async fn execute(ctx: &CuboContext, args: SuperviseArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    supervisor::supervise(runtime, &args.container).await
+}
@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::cli::{SystemArgs, SystemCommands, SystemDfArgs};
+use crate::commands::context::CuboContext;
+use crate::container::diagnostics::HostInfo;
+use crate::container::disk_usage::{format_size, UsageCounter};
+use crate::container::image_store::ImageStore;
+use crate::container::layer_cache::LayerCache;
+use crate::container::reference::normalize;
+use crate::container::quota::LOOP_IMAGE;
+use crate::container::runtime::RuntimeConfig;
+use crate::container::snapshotter::snapshotter_for;
+use crate::container::{Container, MountType};
+use crate::error::Result;
+
+pub async fn execute(ctx: &CuboContext, args: SystemArgs) -> Result<()> {
+    match args.command {
+        SystemCommands::Df(args) => df(ctx, args).await,
+        SystemCommands::Info => info(ctx).await,
+    }
+}
+
+async fn info(ctx: &CuboContext) -> Result<()> {
+    let config = ctx.config();
+    let runtime = ctx.runtime()?;
+    let containers = runtime.list_containers(true).await?;
+    let images = ctx.image_store()?.list_images()?;
+    let host = HostInfo::probe(&config.cgroup_root);
+
+    let or_none = |list: &[String]| if list.is_empty() { "none".to_string() } else { list.join(" ") };
+    let found = |path: &Option<PathBuf>| path.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| "not found".to_string());
+
+    println!("Host:");
+    println!("  Kernel:              {}", host.kernel);
+    println!("  Rootless:            {}", host.rootless());
+    println!("Cgroups:");
+    println!("  Driver:              {}", config.cgroup_driver);
+    println!("  Version:             {}", host.cgroup_mode.map(|m| m.to_string()).unwrap_or_else(|| "none".to_string()));
+    println!("  Controllers:         {}", or_none(&host.controllers));
+    println!("  Delegated:           {}", or_none(&host.delegated));
+    println!("User namespaces:");
+    println!("  Max:                 {}", host.max_user_namespaces.map(|m| m.to_string()).unwrap_or_else(|| "unsupported".to_string()));
+    println!("  newuidmap:           {}", found(&host.newuidmap));
+    println!("  newgidmap:           {}", found(&host.newgidmap));
+    println!("  Subordinate ids:     {}", host.subids);
+    println!("Storage:");
+    println!("  Root:                {}", config.root_dir.display());
+    println!("  Snapshotter:         {}", config.snapshotter);
+    println!("  Provisioning:        {}", config.provision);
+    println!("  Overlayfs:           {}", host.overlayfs);
+    println!("Runtime:");
+    println!("  Executor:            {}", config.executor);
+    println!("  Rootless network:    {}", host.network_driver.as_ref().map(|d| d.name()).unwrap_or("none"));
+    println!("Containers:            {}", containers.len());
+    println!("  Running:             {}", containers.iter().filter(|c| c.is_running()).count());
+    println!("  Stopped:             {}", containers.iter().filter(|c| !c.is_running()).count());
+    println!("Images:                {}", images.len());
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ImageUsage {
+    reference: String,
+    size: u64,
+    containers: usize,
+}
+
+#[derive(Debug)]
+struct ContainerUsage {
+    container: Container,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct VolumeUsage {
+    path: String,
+    size: u64,
+    containers: usize,
+    active: bool,
+}
+
+#[derive(Debug)]
+struct DiskUsage {
+    images: Vec<ImageUsage>,
+    /// Unpacked layers, shared by the images
+    snapshots: u64,
+    containers: Vec<ContainerUsage>,
+    volumes: Vec<VolumeUsage>,
+}
+
+async fn df(ctx: &CuboContext, args: SystemDfArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let containers = runtime.list_containers(true).await?;
+
+    let usage = disk_usage(ctx.config(), containers)?;
+    print_summary(&usage);
+    if args.verbose {
+        print_details(&usage);
+    }
+    Ok(())
+}
+
+fn disk_usage(config: &RuntimeConfig, containers: Vec<Container>) -> Result<DiskUsage> {
+    let root = &config.root_dir;
+    let image_store = ImageStore::new(root.join("images"))?;
+    let mut counter = UsageCounter::new();
+
+    // Blobs shared by several images belong to the first one listed
+    let mut counted_blobs: HashSet<PathBuf> = HashSet::new();
+    let mut images = Vec::new();
+    for reference in image_store.list_images()? {
+        let size = image_store
+            .get_layers(&reference)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|layer| counted_blobs.insert(layer.clone()))
+            .map(|layer| counter.size(&layer))
+            .sum();
+        let users = containers
+            .iter()
+            .filter(|c| normalize(&c.blueprint).is_ok_and(|blueprint| blueprint == reference))
+            .count();
+        images.push(ImageUsage { reference, size, containers: users });
+    }
+    images.sort_by(|a, b| a.reference.cmp(&b.reference));
+
+    // Counted before the containers so hardlink-provisioned rootfs trees only add their own files
+    let snapshots = counter.size(&root.join("snapshots")) + counter.size(LayerCache::new(&image_store).root());
+
+    let mut container_usage = Vec::new();
+    for container in &containers {
+        container_usage.push(ContainerUsage {
+            size: container_size(&mut counter, root, config, container),
+            container: container.clone(),
+        });
+    }
+
+    let mut volumes: BTreeMap<String, VolumeUsage> = BTreeMap::new();
+    for container in &containers {
+        for mount in &container.config.volume_mounts {
+            if !matches!(mount.mount_type, MountType::Bind) {
+                continue;
+            }
+            let volume = volumes.entry(mount.host_path.clone()).or_insert_with(|| VolumeUsage {
+                path: mount.host_path.clone(),
+                size: 0,
+                containers: 0,
+                active: false,
+            });
+            volume.containers += 1;
+            volume.active |= container.is_running();
+        }
+    }
+    for volume in volumes.values_mut() {
+        volume.size = counter.size(Path::new(&volume.path));
+    }
+
+    Ok(DiskUsage {
+        images,
+        snapshots,
+        containers: container_usage,
+        volumes: volumes.into_values().collect(),
+    })
+}
+
+/// Bytes a container adds on top of its image: the writable part of its rootfs, logs and state
+fn container_size(counter: &mut UsageCounter, root: &Path, config: &RuntimeConfig, container: &Container) -> u64 {
+    let dir = root.join(&container.id);
+    let snapshotter = snapshotter_for(container.snapshotter, root, config.provision);
+    let storage = snapshotter.storage_dir(&dir.join("rootfs"));
+    let storage_name = storage.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+    counter.size_excluding(&dir, &["rootfs", &storage_name, LOOP_IMAGE]) + counter.size(&storage)
+}
+
+fn percent(part: u64, total: u64) -> u64 {
+    (part * 100).checked_div(total).unwrap_or(0)
+}
+
+fn print_summary(usage: &DiskUsage) {
+    let image_size = usage.images.iter().map(|i| i.size).sum::<u64>() + usage.snapshots;
+    let unused_images = usage.images.iter().filter(|i| i.containers == 0).map(|i| i.size).sum::<u64>();
+    let container_size = usage.containers.iter().map(|c| c.size).sum::<u64>();
+    let stopped_size = usage.containers.iter().filter(|c| !c.container.is_running()).map(|c| c.size).sum::<u64>();
+    let volume_size = usage.volumes.iter().map(|v| v.size).sum::<u64>();
+
+    println!("{:<15} {:<8} {:<8} {:<12} {:<12}", "TYPE", "TOTAL", "ACTIVE", "SIZE", "RECLAIMABLE");
+    println!(
+        "{:<15} {:<8} {:<8} {:<12} {} ({}%)",
+        "Images",
+        usage.images.len(),
+        usage.images.iter().filter(|i| i.containers > 0).count(),
+        format_size(image_size),
+        format_size(unused_images),
+        percent(unused_images, image_size)
+    );
+    println!(
+        "{:<15} {:<8} {:<8} {:<12} {} ({}%)",
+        "Containers",
+        usage.containers.len(),
+        usage.containers.iter().filter(|c| c.container.is_running()).count(),
+        format_size(container_size),
+        format_size(stopped_size),
+        percent(stopped_size, container_size)
+    );
+    // Bind-mounted host directories are never deleted by cubo
+    println!(
+        "{:<15} {:<8} {:<8} {:<12} {}",
+        "Volumes",
+        usage.volumes.len(),
+        usage.volumes.iter().filter(|v| v.active).count(),
+        format_size(volume_size),
+        format_size(0)
+    );
+}
+
+fn print_details(usage: &DiskUsage) {
+    println!("\nImages space usage:\n");
+    println!("{:<30} {:<12} {:<10}", "IMAGE", "SIZE", "CONTAINERS");
+    for image in &usage.images {
+        println!("{:<30} {:<12} {:<10}", image.reference, format_size(image.size), image.containers);
+    }
+    println!("{:<30} {:<12}", "(unpacked layers)", format_size(usage.snapshots));
+
+    println!("\nContainers space usage:\n");
+    println!("{:<12} {:<20} {:<12} {:<10} {:<10} {:<15}", "CONTAINER ID", "IMAGE", "SIZE", "QUOTA", "STATUS", "NAMES");
+    for entry in &usage.containers {
+        let container = &entry.container;
+        let quota = container.config.storage_size.map(format_size).unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<12} {:<20} {:<12} {:<10} {:<10} {:<15}",
+            &container.id[..12],
+            container.blueprint,
+            format_size(entry.size),
+            quota,
+            container.status,
+            container.name.as_deref().unwrap_or("")
+        );
+    }
+
+    println!("\nVolumes space usage:\n");
+    println!("{:<40} {:<12} {:<10}", "HOST PATH", "SIZE", "CONTAINERS");
+    for volume in &usage.volumes {
+        println!("{:<40} {:<12} {:<10}", volume.path, format_size(volume.size), volume.containers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::VolumeMount;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_disk_usage() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig { root_dir: temp.path().to_path_buf(), ..Default::default() };
+
+        let data = temp.path().join("data");
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(data.join("file"), vec![0u8; 32 * 1024]).unwrap();
+
+        let container = Container::new("alpine:latest".to_string(), vec!["true".to_string()])
+            .with_volume(VolumeMount::bind(data.to_string_lossy().to_string(), "/data".to_string(), false));
+        let rootfs = temp.path().join(&container.id).join("rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        std::fs::write(rootfs.join("written"), vec![0u8; 16 * 1024]).unwrap();
+
+        let usage = disk_usage(&config, vec![container.clone(), container]).unwrap();
+        assert!(usage.images.is_empty());
+        assert_eq!(usage.containers.len(), 2);
+        assert!(usage.containers[0].size >= 16 * 1024);
+        assert_eq!(usage.volumes.len(), 1);
+        assert_eq!(usage.volumes[0].containers, 2);
+        assert!(!usage.volumes[0].active);
+        assert!(usage.volumes[0].size >= 32 * 1024);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::cli::UpdateArgs;
+use crate::commands::context::CuboContext;
+use crate::config::parse_byte_size;
+use crate::container::cgroup::ResourceUpdate;
+use crate::error::{CuboError, Result};
+use tracing::{error, info};
+
+pub async fn execute(ctx: &CuboContext, args: UpdateArgs) -> Result<()> {
+    let update = build_update(&args)?;
+
+    let runtime = ctx.runtime()?;
+
+    let mut failed = Vec::new();
+    for identifier in &args.containers {
+        let result = match runtime.resolve(identifier).await {
+            Ok(container_id) => runtime.update_container(&container_id, &update).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {
+                info!("Updated container: {}", identifier);
+                println!("{}", identifier);
+            }
+            Err(e) => {
+                error!("Failed to update container {}: {}", identifier, e);
+                failed.push((identifier.clone(), e));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        for (identifier, e) in &failed {
+            eprintln!("Error updating {}: {}", identifier, e);
+        }
+        return Err(CuboError::SystemError("Some containers could not be updated".to_string()));
+    }
+    Ok(())
+}
+
+/// The limits given on the command line
+pub fn build_update(args: &UpdateArgs) -> Result<ResourceUpdate> {
+    let memory_limit = match args.memory {
+        Some(ref memory) => Some(parse_byte_size(memory).ok_or_else(|| {
+            CuboError::InvalidConfiguration(format!("Invalid memory limit: {}", memory))
+        })?),
+        None => None,
+    };
+    if let Some(cpus) = args.cpus {
+        if !cpus.is_finite() || cpus < 0.0 {
+            return Err(CuboError::InvalidConfiguration(format!("Invalid number of CPUs: {}", cpus)));
+        }
+    }
+
+    let update = ResourceUpdate {
+        memory_limit,
+        cpu_limit: args.cpus,
+        pids_limit: args.pids_limit,
+    };
+    if update.is_empty() {
+        return Err(CuboError::InvalidConfiguration(
+            "Specify at least one of --memory, --cpus or --pids-limit".to_string(),
+        ));
+    }
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    fn args(memory: Option<&str>, cpus: Option<f32>, pids_limit: Option<i64>) -> UpdateArgs {
+        UpdateArgs {
+            containers: vec!["web".to_string()],
+            memory: memory.map(String::from),
+            cpus,
+            pids_limit,
+        }
+    }
+
+    #[test]
+    fn test_build_update() {
+        let update = build_update(&args(Some("512m"), Some(1.5), None)).unwrap();
+        assert_eq!(update.memory_limit, Some(512 * 1024 * 1024));
+        assert_eq!(update.cpu_limit, Some(1.5));
+        assert!(update.pids_limit.is_none());
+    }
+
+    #[test]
+    fn test_build_update_rejects_invalid_values() {
+        assert!(build_update(&args(None, None, None)).is_err());
+        assert!(build_update(&args(Some("lots"), None, None)).is_err());
+        assert!(build_update(&args(None, Some(-2.0), None)).is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_updates_stopped_container_by_name() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let runtime = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()])
+            .with_name("web".to_string());
+        let id = runtime.create_container(container).await.unwrap();
+
+        execute(&CuboContext::new(RuntimeConfig::from_env()), args(None, None, Some(50))).await.unwrap();
+
+        let reloaded = ContainerRuntime::new(RuntimeConfig::from_env()).unwrap();
+        assert_eq!(reloaded.get_container(&id).await.unwrap().config.pids_limit, Some(50));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_missing_container() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        assert!(execute(&CuboContext::new(RuntimeConfig::from_env()), args(Some("1g"), None, None)).await.is_err());
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
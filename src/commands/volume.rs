@@ -0,0 +1,140 @@
+use crate::cli::{VolumeArgs, VolumeCommands, VolumeCreateArgs, VolumeInspectArgs, VolumeRmArgs};
+use crate::commands::context::CuboContext;
+use crate::container::volume::{self, Volume, VolumeStore};
+use crate::container::{Container, MountType};
+use crate::error::{CuboError, Result};
+use tracing::{info, error};
+
+pub async fn execute(ctx: &CuboContext, args: VolumeArgs) -> Result<()> {
+    let store = VolumeStore::new(ctx.root_dir())?;
+
+    match args.command {
+        VolumeCommands::Create(create) => create_volume(&store, create),
+        VolumeCommands::Ls => list_volumes(&store),
+        VolumeCommands::Rm(rm) => remove_volumes(ctx, &store, rm).await,
+        VolumeCommands::Inspect(inspect) => inspect_volumes(&store, inspect),
+    }
+}
+
+fn create_volume(store: &VolumeStore, args: VolumeCreateArgs) -> Result<()> {
+    let options = volume::parse_options(&args.options)?;
+    let volume = store.create(&args.name, &args.driver, options)?;
+    info!("Created volume {} with the {} driver", volume.name, volume.driver);
+    println!("{}", volume.name);
+    Ok(())
+}
+
+fn list_volumes(store: &VolumeStore) -> Result<()> {
+    let volumes = store.list()?;
+    if volumes.is_empty() {
+        println!("No volumes found.");
+        return Ok(());
+    }
+
+    println!("{:<12} {:<30}", "DRIVER", "VOLUME NAME");
+    for volume in &volumes {
+        println!("{:<12} {:<30}", volume.driver, volume.name);
+    }
+    Ok(())
+}
+
+async fn remove_volumes(ctx: &CuboContext, store: &VolumeStore, args: VolumeRmArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+    let containers = runtime.list_containers(true).await?;
+    let mut failed = Vec::new();
+
+    for name in args.volumes {
+        let result = match users(&containers, &name).first() {
+            Some(user) => Err(CuboError::VolumeError(format!("Volume {} is in use by container {}", name, user))),
+            None => store.remove(&name),
+        };
+        match result {
+            Ok(_) => println!("{}", name),
+            Err(e) => {
+                error!("Failed to remove volume {}: {}", name, e);
+                failed.push((name, e));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        for (name, e) in &failed {
+            eprintln!("Error removing {}: {}", name, e);
+        }
+        return Err(CuboError::VolumeError("Some volumes could not be removed".to_string()));
+    }
+    Ok(())
+}
+
+fn inspect_volumes(store: &VolumeStore, args: VolumeInspectArgs) -> Result<()> {
+    let volumes = args
+        .volumes
+        .iter()
+        .map(|name| store.get(name))
+        .collect::<Result<Vec<Volume>>>()?;
+    let json = serde_json::to_string_pretty(&volumes)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize volume: {}", e)))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Names, or short IDs, of the containers that mount the volume
+fn users(containers: &[Container], volume: &str) -> Vec<String> {
+    containers
+        .iter()
+        .filter(|c| {
+            c.config
+                .volume_mounts
+                .iter()
+                .any(|m| matches!(m.mount_type, MountType::Volume) && m.host_path == volume)
+        })
+        .map(|c| c.name.clone().unwrap_or_else(|| c.id[..12.min(c.id.len())].to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::container::VolumeMount;
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_users_of_volume() {
+        let mut db = Container::new("postgres".to_string(), vec![]).with_volume(VolumeMount::volume(
+            "pgdata".to_string(),
+            "/var/lib/postgresql/data".to_string(),
+            false,
+        ));
+        db.name = Some("db".to_string());
+        let web = Container::new("nginx".to_string(), vec![])
+            .with_volume(VolumeMount::bind("pgdata".to_string(), "/srv".to_string(), false));
+
+        assert_eq!(users(&[db, web], "pgdata"), vec!["db"]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_execute_create_and_remove() {
+        let temp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", temp.path());
+
+        let create = VolumeArgs {
+            command: VolumeCommands::Create(VolumeCreateArgs {
+                name: "cache".to_string(),
+                driver: "local".to_string(),
+                options: Vec::new(),
+            }),
+        };
+        execute(&CuboContext::new(RuntimeConfig::from_env()), create).await.unwrap();
+        let store = VolumeStore::new(temp.path()).unwrap();
+        assert_eq!(store.get("cache").unwrap().driver, "local");
+
+        let rm = VolumeArgs { command: VolumeCommands::Rm(VolumeRmArgs { volumes: vec!["cache".to_string()] }) };
+        execute(&CuboContext::new(RuntimeConfig::from_env()), rm).await.unwrap();
+        assert!(!store.exists("cache"));
+
+        std::env::remove_var("CUBO_ROOT");
+    }
+}
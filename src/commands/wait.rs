@@ -0,0 +1,17 @@
+use crate::cli::WaitArgs;
+use crate::commands::context::CuboContext;
+use crate::error::Result;
+
+pub async fn execute(ctx: &CuboContext, args: WaitArgs) -> Result<()> {
+    let runtime = ctx.runtime()?;
+
+    // Print each code as its container stops, like docker wait, then exit as the last did
+    let mut last = 0;
+    for identifier in &args.containers {
+        let container_id = runtime.resolve(identifier).await?;
+        last = runtime.wait_container(&container_id).await?;
+        println!("{}", last);
+    }
+    std::process::exit(last);
+}
+
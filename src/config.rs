@@ -0,0 +1,425 @@
+//! The configuration file, `~/.config/cubo/config.toml` unless `CUBO_CONFIG` or `--config`
+//! point elsewhere. It is the lowest layer: environment variables override it, flags override both.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use chrono::TimeDelta;
+
+use crate::container::runtime::RuntimeConfig;
+use crate::container::ContainerConfig;
+use crate::error::{CuboError, Result};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Root directory for bundles, images and state
+    pub root_dir: Option<PathBuf>,
+    /// Backend of new containers: native, runc, crun or the path to another OCI runtime
+    pub executor: Option<String>,
+    /// Snapshotter of new containers: dir, overlayfs or btrfs
+    pub snapshotter: Option<String>,
+    /// How the dir snapshotter fills new rootfs trees
+    pub provision: Option<String>,
+    /// Who creates container cgroups: cgroupfs or systemd
+    pub cgroup_driver: Option<String>,
+    pub network: NetworkSection,
+    pub registry: RegistrySection,
+    pub defaults: DefaultsSection,
+    pub log: LogSection,
+    pub proxy: ProxyConfig,
+    pub cleanup: CleanupSection,
+    pub stats: StatsSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSection {
+    /// Network of containers run without --network (bridge, host, none or a network name)
+    pub default_mode: Option<String>,
+    /// Range new networks get a /24 from when created without --subnet
+    pub subnet_pool: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistrySection {
+    /// Mirrors tried in order before the registry itself, keyed by registry (e.g. "docker.io")
+    pub mirrors: HashMap<String, Vec<String>>,
+    /// When images are fetched: missing (default), always or never
+    pub pull_policy: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DefaultsSection {
+    /// Memory limit, e.g. "512m"
+    pub memory: Option<String>,
+    pub cpus: Option<f32>,
+    pub pids_limit: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSection {
+    /// Log driver of containers run without --log-driver
+    pub driver: Option<String>,
+    /// Options as given to --log-opt, e.g. { max-size = "10m", max-file = "3" }
+    pub options: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleanupSection {
+    /// How long exited containers are kept before `cubo gc` removes them, e.g. "72h"
+    pub exited_ttl: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsSection {
+    /// How often cubod records a stats sample of each running container, e.g. "30s"
+    pub interval: Option<String>,
+    /// Samples kept per container, oldest dropped first
+    pub history: Option<usize>,
+}
+
+/// Proxies used to reach registries
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub http: Option<String>,
+    pub https: Option<String>,
+    /// Comma-separated hosts reached directly
+    pub no_proxy: Option<String>,
+}
+
+/// Limits given to containers created without their own
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DefaultLimits {
+    pub memory: Option<u64>,
+    pub cpus: Option<f32>,
+    pub pids_limit: Option<i64>,
+}
+
+impl DefaultLimits {
+    /// Give `config` the limits it has none of, and record them as inherited
+    pub fn apply_to(&self, config: &mut ContainerConfig) {
+        if config.memory_limit.is_none() && self.memory.is_some() {
+            config.memory_limit = self.memory;
+            config.inherited_limits.push("memory".to_string());
+        }
+        if config.cpu_limit.is_none() && self.cpus.is_some() {
+            config.cpu_limit = self.cpus;
+            config.inherited_limits.push("cpus".to_string());
+        }
+        if config.pids_limit.is_none() && self.pids_limit.is_some() {
+            config.pids_limit = self.pids_limit;
+            config.inherited_limits.push("pids_limit".to_string());
+        }
+    }
+}
+
+/// `CUBO_CONFIG`, else config.toml in the XDG config directory
+pub fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("CUBO_CONFIG") {
+        if !path.is_empty() {
+            return Some(PathBuf::from(path));
+        }
+    }
+    let config_home = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok().filter(|h| !h.is_empty())?).join(".config"),
+    };
+    Some(config_home.join("cubo").join("config.toml"))
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| CuboError::InvalidConfiguration(format!("Failed to read {}: {}", path.display(), e)))?;
+        toml::from_str(&data)
+            .map_err(|e| CuboError::InvalidConfiguration(format!("Failed to parse {}: {}", path.display(), e)))
+    }
+
+    /// Layer the file over `config`, leaving it untouched when a value is invalid
+    pub fn apply(&self, config: &mut RuntimeConfig) -> Result<()> {
+        let mut layered = config.clone();
+        let invalid = |key: &str, e: String| CuboError::InvalidConfiguration(format!("{}: {}", key, e));
+
+        if let Some(ref root_dir) = self.root_dir {
+            layered.root_dir = root_dir.clone();
+        }
+        if let Some(ref executor) = self.executor {
+            layered.executor = executor.parse().map_err(|e| invalid("executor", e))?;
+        }
+        if let Some(ref snapshotter) = self.snapshotter {
+            layered.snapshotter = snapshotter.parse().map_err(|e| invalid("snapshotter", e))?;
+        }
+        if let Some(ref provision) = self.provision {
+            layered.provision = provision.parse().map_err(|e| invalid("provision", e))?;
+        }
+        if let Some(ref driver) = self.cgroup_driver {
+            layered.cgroup_driver = driver.parse().map_err(|e| invalid("cgroup_driver", e))?;
+        }
+
+        if let Some(ref mode) = self.network.default_mode {
+            layered.default_network_mode = mode.parse().map_err(|e| invalid("network.default_mode", e))?;
+        }
+        if let Some(ref pool) = self.network.subnet_pool {
+            crate::container::network::Subnet::parse(pool)
+                .map_err(|e| invalid("network.subnet_pool", e.to_string()))?;
+            layered.network_pool = Some(pool.clone());
+        }
+
+        for (registry, mirrors) in &self.registry.mirrors {
+            let mut hosts = Vec::new();
+            for mirror in mirrors {
+                let host = mirror_host(mirror).ok_or_else(|| {
+                    invalid("registry.mirrors", format!("'{}' is not an https mirror", mirror))
+                })?;
+                hosts.push(host);
+            }
+            layered.registry_mirrors.insert(registry.clone(), hosts);
+        }
+        if let Some(ref policy) = self.registry.pull_policy {
+            layered.pull_policy = policy.parse().map_err(|e| invalid("registry.pull_policy", e))?;
+        }
+
+        if let Some(ref memory) = self.defaults.memory {
+            let bytes = parse_byte_size(memory)
+                .ok_or_else(|| invalid("defaults.memory", format!("invalid size '{}'", memory)))?;
+            layered.default_limits.memory = Some(bytes);
+        }
+        if let Some(cpus) = self.defaults.cpus {
+            if cpus <= 0.0 {
+                return Err(invalid("defaults.cpus", format!("must be positive, got {}", cpus)));
+            }
+            layered.default_limits.cpus = Some(cpus);
+        }
+        if let Some(pids) = self.defaults.pids_limit {
+            layered.default_limits.pids_limit = Some(pids);
+        }
+
+        if let Some(ref driver) = self.log.driver {
+            layered.log_driver = Some(driver.parse().map_err(|e| invalid("log.driver", e))?);
+        }
+        if !self.log.options.is_empty() {
+            layered.log_opts = self.log.options.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        }
+
+        if self.proxy != ProxyConfig::default() {
+            layered.proxy = self.proxy.clone();
+        }
+
+        if let Some(ref ttl) = self.cleanup.exited_ttl {
+            let ttl = parse_duration(ttl)
+                .ok_or_else(|| invalid("cleanup.exited_ttl", format!("invalid duration '{}'", ttl)))?;
+            layered.exited_ttl = Some(ttl);
+        }
+
+        if let Some(ref interval) = self.stats.interval {
+            let parsed = parse_duration(interval)
+                .filter(|d| *d > chrono::TimeDelta::zero())
+                .ok_or_else(|| invalid("stats.interval", format!("invalid duration '{}'", interval)))?;
+            layered.stats_interval = Some(parsed);
+        }
+        if let Some(history) = self.stats.history {
+            if history == 0 {
+                return Err(invalid("stats.history", "must keep at least one sample".to_string()));
+            }
+            layered.stats_history = history;
+        }
+
+        *config = layered;
+        Ok(())
+    }
+}
+
+/// Host (and port) of a mirror given as a host or an https URL
+fn mirror_host(mirror: &str) -> Option<String> {
+    let host = mirror.strip_prefix("https://").unwrap_or(mirror).trim_end_matches('/');
+    (!host.is_empty() && !host.contains("://")).then(|| host.to_string())
+}
+
+/// Parse a size such as "512", "10k", "1mb" or "2G" into bytes
+pub fn parse_byte_size(size_str: &str) -> Option<u64> {
+    let lower = size_str.trim().to_lowercase();
+    let number_end = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(number_end);
+    let number: u64 = number.parse().ok()?;
+
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" | "kb" => 1024,
+        "m" | "mb" => 1024 * 1024,
+        "g" | "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    number.checked_mul(multiplier)
+}
+
+/// A duration like 90s, 10m, 24h or 7d
+pub fn parse_duration(spec: &str) -> Option<TimeDelta> {
+    let number_end = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+    let (number, unit) = spec.split_at(number_end);
+    let number: i64 = number.parse().ok()?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.checked_mul(unit_seconds).and_then(TimeDelta::try_seconds)
+}
+
+/// Proxy variables as curl and most tools read them, upper case first
+pub fn proxy_from_env(proxy: &mut ProxyConfig) {
+    let var = |name: &str| {
+        [name.to_uppercase(), name.to_string()]
+            .into_iter()
+            .find_map(|key| std::env::var(key).ok().filter(|v| !v.is_empty()))
+    };
+    if let Some(http) = var("http_proxy") {
+        proxy.http = Some(http);
+    }
+    if let Some(https) = var("https_proxy") {
+        proxy.https = Some(https);
+    }
+    if let Some(no_proxy) = var("no_proxy") {
+        proxy.no_proxy = Some(no_proxy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::cgroup::CgroupDriver;
+    use crate::container::executor::ExecutorKind;
+    use crate::container::registry::PullPolicy;
+    use crate::container::{LogDriverKind, NetworkMode};
+    use serial_test::serial;
+    use tempfile::TempDir;
+
+    const EXAMPLE: &str = r#"
+root_dir = "/srv/cubo"
+executor = "crun"
+cgroup_driver = "systemd"
+
+[network]
+default_mode = "host"
+subnet_pool = "172.30.0.0/16"
+
+[registry]
+mirrors = { "docker.io" = ["https://mirror.gcr.io/", "registry.internal:5000"] }
+pull_policy = "never"
+
+[defaults]
+memory = "512m"
+cpus = 1.5
+pids_limit = 256
+
+[log]
+driver = "journald"
+options = { max-size = "10m" }
+
+[proxy]
+https = "http://proxy.internal:3128"
+no_proxy = "localhost,.internal"
+
+[cleanup]
+exited_ttl = "72h"
+
+[stats]
+interval = "30s"
+history = 2880
+"#;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("512"), Some(512));
+        assert_eq!(parse_byte_size("10k"), Some(10 * 1024));
+        assert_eq!(parse_byte_size("1mb"), Some(1024 * 1024));
+        assert_eq!(parse_byte_size("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1tb"), None);
+        assert_eq!(parse_byte_size("mb"), None);
+        assert_eq!(parse_byte_size(""), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Some(TimeDelta::seconds(90)));
+        assert_eq!(parse_duration("10m"), Some(TimeDelta::minutes(10)));
+        assert_eq!(parse_duration("7d"), Some(TimeDelta::days(7)));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("99999999999999999d"), None);
+    }
+
+    #[test]
+    fn test_apply_config_file() {
+        let file: ConfigFile = toml::from_str(EXAMPLE).unwrap();
+        let mut config = RuntimeConfig::default();
+        file.apply(&mut config).unwrap();
+
+        assert_eq!(config.root_dir, PathBuf::from("/srv/cubo"));
+        assert_eq!(config.executor, ExecutorKind::Oci("crun".to_string()));
+        assert_eq!(config.cgroup_driver, CgroupDriver::Systemd);
+        assert_eq!(config.default_network_mode, NetworkMode::Host);
+        assert_eq!(config.network_pool.as_deref(), Some("172.30.0.0/16"));
+        assert_eq!(config.registry_mirrors["docker.io"], vec!["mirror.gcr.io", "registry.internal:5000"]);
+        assert_eq!(config.pull_policy, PullPolicy::Never);
+        assert_eq!(config.default_limits.memory, Some(512 * 1024 * 1024));
+        assert_eq!(config.default_limits.cpus, Some(1.5));
+        assert_eq!(config.default_limits.pids_limit, Some(256));
+        assert_eq!(config.log_driver, Some(LogDriverKind::Journald));
+        assert_eq!(config.log_opts, vec!["max-size=10m".to_string()]);
+        assert_eq!(config.proxy.https.as_deref(), Some("http://proxy.internal:3128"));
+        assert_eq!(config.exited_ttl, Some(chrono::TimeDelta::hours(72)));
+        assert_eq!(config.stats_interval, Some(chrono::TimeDelta::seconds(30)));
+        assert_eq!(config.stats_history, 2880);
+    }
+
+    #[test]
+    fn test_invalid_config_file_changes_nothing() {
+        let file: ConfigFile = toml::from_str("root_dir = \"/srv/cubo\"\n[defaults]\nmemory = \"lots\"\n").unwrap();
+        let mut config = RuntimeConfig::default();
+        let before = config.root_dir.clone();
+        assert!(file.apply(&mut config).unwrap_err().to_string().contains("defaults.memory"));
+        assert_eq!(config.root_dir, before);
+
+        let file: ConfigFile = toml::from_str("[registry]\nmirrors = { \"docker.io\" = [\"http://mirror\"] }\n").unwrap();
+        assert!(file.apply(&mut config).is_err());
+        let file: ConfigFile = toml::from_str("[registry]\npull_policy = \"sometimes\"\n").unwrap();
+        assert!(file.apply(&mut config).unwrap_err().to_string().contains("registry.pull_policy"));
+        assert!(toml::from_str::<ConfigFile>("[network]\nmode = \"host\"\n").is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_env_overrides_config_file() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("config.toml");
+        fs::write(&path, "root_dir = \"/from/file\"\nsnapshotter = \"overlayfs\"\n").unwrap();
+        std::env::set_var("CUBO_CONFIG", &path);
+        std::env::remove_var("CUBO_SNAPSHOTTER");
+
+        std::env::set_var("CUBO_ROOT", temp.path());
+        let config = RuntimeConfig::from_env();
+        assert_eq!(config.root_dir, temp.path());
+        assert_eq!(config.snapshotter.to_string(), "overlayfs");
+
+        std::env::remove_var("CUBO_ROOT");
+        assert_eq!(RuntimeConfig::from_env().root_dir, PathBuf::from("/from/file"));
+
+        std::env::set_var("CUBO_PULL_POLICY", "never");
+        assert_eq!(RuntimeConfig::from_env().pull_policy, PullPolicy::Never);
+        std::env::remove_var("CUBO_PULL_POLICY");
+        std::env::remove_var("CUBO_CONFIG");
+    }
+}
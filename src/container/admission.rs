@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use tracing::debug;
+
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+/// Policy consulted before images are pulled and containers are created, registered on a
+/// `ContainerRuntime` with `with_admission_hook`. Hooks run in the order they were added;
+/// the first to deny an operation stops it.
+pub trait AdmissionHook: Send + Sync {
+    /// Shown in the error when the hook denies an operation
+    fn name(&self) -> &str;
+
+    /// Called before `reference` is pulled; an error denies the pull
+    fn admit_pull(&self, _reference: &str) -> std::result::Result<(), String> {
+        Ok(())
+    }
+
+    /// Called before a container is created, with its image reference normalized and the
+    /// configured default limits applied. The hook may change the container, e.g. to add
+    /// environment variables or limits; an error denies the creation.
+    fn admit_run(&self, _container: &mut Container) -> std::result::Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Ask every hook whether `reference` may be pulled
+pub fn admit_pull(hooks: &[Arc<dyn AdmissionHook>], reference: &str) -> Result<()> {
+    for hook in hooks {
+        hook.admit_pull(reference).map_err(|reason| CuboError::AdmissionDenied {
+            hook: hook.name().to_string(),
+            operation: format!("pull of {}", reference),
+            reason,
+        })?;
+        debug!("Admission hook {} admitted the pull of {}", hook.name(), reference);
+    }
+    Ok(())
+}
+
+/// Ask every hook whether the container may be created, letting each change it in turn
+pub fn admit_run(hooks: &[Arc<dyn AdmissionHook>], container: &mut Container) -> Result<()> {
+    for hook in hooks {
+        hook.admit_run(container).map_err(|reason| CuboError::AdmissionDenied {
+            hook: hook.name().to_string(),
+            operation: format!("container of {}", container.blueprint),
+            reason,
+        })?;
+        debug!("Admission hook {} admitted container {}", hook.name(), container.id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Denies images outside a registry and sets an environment variable on the rest
+    struct TrustedRegistry;
+
+    impl AdmissionHook for TrustedRegistry {
+        fn name(&self) -> &str {
+            "trusted-registry"
+        }
+
+        fn admit_pull(&self, reference: &str) -> std::result::Result<(), String> {
+            match reference.starts_with("registry.example.com/") {
+                true => Ok(()),
+                false => Err("only registry.example.com is trusted".to_string()),
+            }
+        }
+
+        fn admit_run(&self, container: &mut Container) -> std::result::Result<(), String> {
+            self.admit_pull(&container.blueprint)?;
+            container.config.env_vars.insert("TEAM".to_string(), "payments".to_string());
+            Ok(())
+        }
+    }
+
+    struct AllowAll;
+
+    impl AdmissionHook for AllowAll {
+        fn name(&self) -> &str {
+            "allow-all"
+        }
+    }
+
+    #[test]
+    fn test_admit_pull() {
+        let hooks: Vec<Arc<dyn AdmissionHook>> = vec![Arc::new(AllowAll), Arc::new(TrustedRegistry)];
+        assert!(admit_pull(&hooks, "registry.example.com/app:1").is_ok());
+
+        let err = admit_pull(&hooks, "docker.io/library/alpine:latest").unwrap_err();
+        assert!(matches!(err, CuboError::AdmissionDenied { ref hook, .. } if hook == "trusted-registry"));
+        assert_eq!(
+            err.to_string(),
+            "Admission hook trusted-registry denied the pull of docker.io/library/alpine:latest: only registry.example.com is trusted"
+        );
+        assert!(admit_pull(&[], "anything").is_ok());
+    }
+
+    #[test]
+    fn test_admit_run_mutates_container() {
+        let hooks: Vec<Arc<dyn AdmissionHook>> = vec![Arc::new(TrustedRegistry)];
+        let mut container = Container::new("registry.example.com/app:1".to_string(), vec![]);
+        admit_run(&hooks, &mut container).unwrap();
+        assert_eq!(container.config.env_vars["TEAM"], "payments");
+
+        let mut container = Container::new("alpine:latest".to_string(), vec![]);
+        assert!(admit_run(&hooks, &mut container).is_err());
+        assert!(!container.config.env_vars.contains_key("TEAM"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Traits of the library API, so crates embedding cubo can swap the runtime or its stores
+//! (see `memory` for in-memory ones)
+
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::container::container_store as store;
+use crate::container::image_store::{self, ImageManifest};
+use crate::container::runtime::{self, ContainerRuntime};
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+pub type ApiFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Container lifecycle as the commands use it
+pub trait Runtime: Send + Sync {
+    /// Register a container, returning its id
+    fn create_container(&self, container: Container) -> ApiFuture<'_, String>;
+
+    /// Run a created or stopped container. Attached runs resolve once it has exited.
+    fn start_container<'a>(&'a self, container_id: &'a str, detach: bool) -> ApiFuture<'a, ()>;
+
+    /// Stop a running container, killing it when it is still alive after `timeout`
+    fn stop_container<'a>(&'a self, container_id: &'a str, timeout: Option<Duration>) -> ApiFuture<'a, ()>;
+
+    /// Delete a container; a running one only with `force`
+    fn remove_container<'a>(&'a self, container_id: &'a str, force: bool) -> ApiFuture<'a, ()>;
+
+    /// Running containers, or all of them with `all`
+    fn list_containers(&self, all: bool) -> ApiFuture<'_, Vec<Container>>;
+
+    fn get_container<'a>(&'a self, container_id: &'a str) -> ApiFuture<'a, Container>;
+
+    /// The ID of the container named by its full ID, its name or an unambiguous ID prefix
+    fn resolve<'a>(&'a self, identifier: &'a str) -> ApiFuture<'a, String> {
+        Box::pin(async move { runtime::resolve_identifier(&self.list_containers(true).await?, identifier) })
+    }
+}
+
+/// Where containers are persisted between cubo invocations
+pub trait ContainerStore: Send + Sync {
+    /// Save the whole container
+    fn save_config(&self, container: &Container) -> Result<()>;
+
+    /// Save what changes while the container runs (status, pid)
+    fn save_state(&self, container: &Container) -> Result<()>;
+
+    fn load_all(&self) -> Result<HashMap<String, Container>>;
+
+    /// The container, None when there is no such container
+    fn load_one(&self, container_id: &str) -> Result<Option<Container>>;
+
+    /// Forget the container and whatever is kept with it
+    fn remove(&self, container_id: &str) -> Result<()>;
+}
+
+/// Where images are kept
+pub trait ImageRepository: Send + Sync {
+    fn get_manifest(&self, image_ref: &str) -> Result<ImageManifest>;
+
+    fn has_image(&self, image_ref: &str) -> bool;
+
+    fn list_images(&self) -> Result<Vec<String>>;
+
+    fn save_manifest(&self, manifest: &ImageManifest) -> Result<()>;
+
+    fn remove_image(&self, image_ref: &str) -> Result<()>;
+
+    /// Give the image `source` names the name `target` as well
+    fn tag(&self, source: &str, target: &str) -> Result<()>;
+}
+
+impl Runtime for ContainerRuntime {
+    fn create_container(&self, container: Container) -> ApiFuture<'_, String> {
+        Box::pin(ContainerRuntime::create_container(self, container))
+    }
+
+    fn start_container<'a>(&'a self, container_id: &'a str, detach: bool) -> ApiFuture<'a, ()> {
+        Box::pin(ContainerRuntime::start_container(self, container_id, detach))
+    }
+
+    fn stop_container<'a>(&'a self, container_id: &'a str, timeout: Option<Duration>) -> ApiFuture<'a, ()> {
+        Box::pin(ContainerRuntime::stop_container(self, container_id, timeout))
+    }
+
+    fn remove_container<'a>(&'a self, container_id: &'a str, force: bool) -> ApiFuture<'a, ()> {
+        Box::pin(ContainerRuntime::remove_container(self, container_id, force))
+    }
+
+    fn list_containers(&self, all: bool) -> ApiFuture<'_, Vec<Container>> {
+        Box::pin(ContainerRuntime::list_containers(self, all))
+    }
+
+    fn get_container<'a>(&'a self, container_id: &'a str) -> ApiFuture<'a, Container> {
+        Box::pin(ContainerRuntime::get_container(self, container_id))
+    }
+
+    fn resolve<'a>(&'a self, identifier: &'a str) -> ApiFuture<'a, String> {
+        Box::pin(ContainerRuntime::resolve(self, identifier))
+    }
+}
+
+/// Bundles under a root directory, the store of `ContainerRuntime::new`
+#[derive(Debug, Clone)]
+pub struct FsContainerStore {
+    root_dir: PathBuf,
+}
+
+impl FsContainerStore {
+    pub fn new(root_dir: &Path) -> Self {
+        Self { root_dir: root_dir.to_path_buf() }
+    }
+}
+
+impl ContainerStore for FsContainerStore {
+    fn save_config(&self, container: &Container) -> Result<()> {
+        store::save_config(&self.root_dir, container)
+    }
+
+    fn save_state(&self, container: &Container) -> Result<()> {
+        store::save_state(&self.root_dir, container)
+    }
+
+    fn load_all(&self) -> Result<HashMap<String, Container>> {
+        store::load_all(&self.root_dir)
+    }
+
+    fn load_one(&self, container_id: &str) -> Result<Option<Container>> {
+        store::load_one(&self.root_dir, container_id)
+    }
+
+    /// Deletes the whole bundle, rootfs included
+    fn remove(&self, container_id: &str) -> Result<()> {
+        let bundle = self.root_dir.join(container_id);
+        if bundle.exists() {
+            fs::remove_dir_all(&bundle)
+                .map_err(|e| CuboError::SystemError(format!("Failed to remove container directory: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl ImageRepository for image_store::ImageStore {
+    fn get_manifest(&self, image_ref: &str) -> Result<ImageManifest> {
+        image_store::ImageStore::get_manifest(self, image_ref)
+    }
+
+    fn has_image(&self, image_ref: &str) -> bool {
+        image_store::ImageStore::has_image(self, image_ref)
+    }
+
+    fn list_images(&self) -> Result<Vec<String>> {
+        image_store::ImageStore::list_images(self)
+    }
+
+    fn save_manifest(&self, manifest: &ImageManifest) -> Result<()> {
+        image_store::ImageStore::save_manifest(self, manifest)
+    }
+
+    fn remove_image(&self, image_ref: &str) -> Result<()> {
+        image_store::ImageStore::remove_image(self, image_ref)
+    }
+
+    fn tag(&self, source: &str, target: &str) -> Result<()> {
+        image_store::ImageStore::tag(self, source, target)
+    }
+}
@@ -0,0 +1,161 @@
+//! AppArmor confinement of container processes, on hosts (Ubuntu, Debian...) where
+//! AppArmor is enabled
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use tracing::{debug, warn};
+
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+/// Profile containers run under unless they name another one
+pub const DEFAULT_PROFILE: &str = "cubo-default";
+
+/// Profile name that leaves the container unconfined
+pub const UNCONFINED: &str = "unconfined";
+
+/// Kernel parameter telling whether the AppArmor module is enabled
+const ENABLED_PARAMETER: &str = "/sys/module/apparmor/parameters/enabled";
+
+/// Profiles loaded into the kernel, one "name (mode)" per line
+const PROFILES: &str = "/sys/kernel/security/apparmor/profiles";
+
+/// Parser loading profiles into the kernel
+pub const APPARMOR_PARSER: &str = "apparmor_parser";
+
+/// The default profile, like Docker's: everything a container usually does is allowed,
+/// but mounting, writing kernel settings under /proc and /sys and tracing processes
+/// outside the profile are not
+const DEFAULT_PROFILE_TEMPLATE: &str = r#"#include <tunables/global>
+
+profile {name} flags=(attach_disconnected,mediate_deleted) {
+  #include <abstractions/base>
+
+  network,
+  capability,
+  file,
+  umount,
+  signal (receive) peer=unconfined,
+  signal (send,receive) peer={name},
+
+  deny @{PROC}/* w,
+  deny @{PROC}/{[^1-9],[^1-9][^0-9],[^1-9s][^0-9y][^0-9s],[^1-9][^0-9][^0-9][^0-9/]*}/** w,
+  deny @{PROC}/sys/[^k]** w,
+  deny @{PROC}/sys/kernel/{?,??,[^s][^h][^m]**} w,
+  deny @{PROC}/sysrq-trigger rwklx,
+  deny @{PROC}/kcore rwklx,
+
+  deny mount,
+
+  deny /sys/[^f]*/** wklx,
+  deny /sys/f[^s]*/** wklx,
+  deny /sys/fs/[^c]*/** wklx,
+  deny /sys/fs/c[^g]*/** wklx,
+  deny /sys/fs/cg[^r]*/** wklx,
+  deny /sys/firmware/** rwklx,
+  deny /sys/kernel/security/** rwklx,
+
+  ptrace (trace,read,tracedby,readby) peer={name},
+}
+"#;
+
+/// Whether AppArmor is enabled on the host
+pub fn enabled() -> bool {
+    fs::read_to_string(ENABLED_PARAMETER).is_ok_and(|v| v.trim() == "Y")
+}
+
+/// Source of the default profile
+pub fn default_profile() -> String {
+    DEFAULT_PROFILE_TEMPLATE.replace("{name}", DEFAULT_PROFILE)
+}
+
+/// Whether the kernel has a profile called `name`
+pub fn is_loaded(name: &str) -> bool {
+    fs::read_to_string(PROFILES).is_ok_and(|profiles| loaded_in(&profiles, name))
+}
+
+fn loaded_in(profiles: &str, name: &str) -> bool {
+    profiles
+        .lines()
+        .any(|line| line.rsplit_once(" (").map_or(line, |(profile, _)| profile) == name)
+}
+
+/// The profile the container runs under: its own, else the default one once it is loaded.
+/// None when AppArmor is off or the container is unconfined.
+pub fn profile_for(container: &Container) -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+    match container.config.apparmor_profile.as_deref() {
+        Some(UNCONFINED) => None,
+        Some(profile) => Some(profile.to_string()),
+        None if is_loaded(DEFAULT_PROFILE) => Some(DEFAULT_PROFILE.to_string()),
+        None => None,
+    }
+}
+
+/// Load the default profile unless the kernel has it, writing it to `dir` for
+/// apparmor_parser. Loading needs root, rootless containers without it run unconfined.
+pub fn ensure_default_profile(dir: &Path) -> Result<()> {
+    if !enabled() || is_loaded(DEFAULT_PROFILE) {
+        return Ok(());
+    }
+    if !nix::unistd::geteuid().is_root() {
+        warn!("The AppArmor profile {} is not loaded and loading it needs root; running unconfined", DEFAULT_PROFILE);
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    let path = dir.join(DEFAULT_PROFILE);
+    fs::write(&path, default_profile())?;
+
+    debug!("Loading AppArmor profile {}", path.display());
+    let output = Command::new(APPARMOR_PARSER)
+        .arg("-Kr")
+        .arg(&path)
+        .output()
+        .map_err(|e| CuboError::SystemError(format!("Failed to run {}: {}", APPARMOR_PARSER, e)))?;
+    if !output.status.success() {
+        return Err(CuboError::SystemError(format!(
+            "Failed to load the AppArmor profile {}: {}",
+            DEFAULT_PROFILE,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Have the next execve of the calling thread switch to `profile`, like aa_change_onexec(2)
+pub fn change_onexec(profile: &str) -> Result<()> {
+    // The per-LSM interface exists where several LSMs are stacked
+    let attr = match Path::new("/proc/thread-self/attr/apparmor/exec").exists() {
+        true => "/proc/thread-self/attr/apparmor/exec",
+        false => "/proc/thread-self/attr/exec",
+    };
+    fs::write(attr, format!("exec {}", profile))
+        .map_err(|e| CuboError::SystemError(format!("Failed to apply the AppArmor profile {}: {}", profile, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_profile() {
+        let profile = default_profile();
+        assert!(profile.contains("profile cubo-default flags=(attach_disconnected,mediate_deleted) {"));
+        assert!(profile.contains("peer=cubo-default"));
+        assert!(profile.contains("deny mount,"));
+        assert!(!profile.contains("{name}"));
+    }
+
+    #[test]
+    fn test_loaded_in() {
+        let profiles = "cubo-default (enforce)\n/usr/sbin/cupsd (enforce)\nnvidia_modprobe//kmod (complain)\n";
+        assert!(loaded_in(profiles, "cubo-default"));
+        assert!(loaded_in(profiles, "/usr/sbin/cupsd"));
+        assert!(!loaded_in(profiles, "cubo"));
+        assert!(!loaded_in("", "cubo-default"));
+    }
+}
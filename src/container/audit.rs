@@ -0,0 +1,282 @@
+//! Append-only audit trail of the operations that change containers and images,
+//! `audit.jsonl` under the root directory
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use nix::unistd::{getuid, User};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::{CuboError, Result};
+
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditOperation {
+    Create,
+    Start,
+    Stop,
+    Remove,
+    Pull,
+    Build,
+}
+
+impl std::str::FromStr for AuditOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(AuditOperation::Create),
+            "start" => Ok(AuditOperation::Start),
+            "stop" => Ok(AuditOperation::Stop),
+            "remove" | "rm" => Ok(AuditOperation::Remove),
+            "pull" => Ok(AuditOperation::Pull),
+            "build" => Ok(AuditOperation::Build),
+            other => Err(format!(
+                "unknown operation '{}' (expected create, start, stop, remove, pull or build)",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for AuditOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AuditOperation::Create => "create",
+            AuditOperation::Start => "start",
+            AuditOperation::Stop => "stop",
+            AuditOperation::Remove => "remove",
+            AuditOperation::Pull => "pull",
+            AuditOperation::Build => "build",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub time: DateTime<Utc>,
+    pub uid: u32,
+    /// Name of the user, when the uid has a passwd entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Command line of the cubo process (or cubod) that made the change
+    pub command: String,
+    pub operation: AuditOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    pub success: bool,
+    /// Why the operation failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    /// A record of `operation` by the current process, finished with `result`
+    pub fn new<T>(operation: AuditOperation, result: &Result<T>) -> Self {
+        let uid = getuid();
+        Self {
+            time: Utc::now(),
+            uid: uid.as_raw(),
+            user: User::from_uid(uid).ok().flatten().map(|u| u.name),
+            command: std::env::args().collect::<Vec<_>>().join(" "),
+            operation,
+            container: None,
+            image: None,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+
+    pub fn with_container(mut self, container: &str) -> Self {
+        self.container = Some(container.to_string());
+        self
+    }
+
+    pub fn with_image(mut self, image: &str) -> Self {
+        self.image = Some(image.to_string());
+        self
+    }
+}
+
+/// Which records `cubo audit` shows
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub operation: Option<AuditOperation>,
+    /// Prefix of the container ID, or its name as recorded in the command line
+    pub container: Option<String>,
+    pub image: Option<String>,
+    pub user: Option<String>,
+    pub failed_only: bool,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        if self.since.is_some_and(|since| record.time < since)
+            || self.until.is_some_and(|until| record.time > until)
+        {
+            return false;
+        }
+        if self.operation.is_some_and(|op| record.operation != op) {
+            return false;
+        }
+        if let Some(ref container) = self.container {
+            let by_id = record.container.as_deref().is_some_and(|id| id.starts_with(container.as_str()));
+            if !by_id && !record.command.split_whitespace().any(|arg| arg == container) {
+                return false;
+            }
+        }
+        if let Some(ref image) = self.image {
+            if record.image.as_deref() != Some(image.as_str()) {
+                return false;
+            }
+        }
+        if let Some(ref user) = self.user {
+            if record.user.as_deref() != Some(user.as_str()) && record.uid.to_string() != *user {
+                return false;
+            }
+        }
+        !(self.failed_only && record.success)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn new(root_dir: &Path) -> Self {
+        Self { path: root_dir.join(AUDIT_FILE) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record. Each record is a single O_APPEND write, so concurrent cubo
+    /// processes don't interleave lines.
+    pub fn append(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)
+            .map_err(|e| CuboError::SystemError(format!("Failed to serialize audit record: {}", e)))?;
+        line.push(b'\n');
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.path)
+            .map_err(|e| CuboError::SystemError(format!("Failed to open {}: {}", self.path.display(), e)))?;
+        file.write_all(&line)
+            .map_err(|e| CuboError::SystemError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+
+    /// Append a record; an audit log that can't be written doesn't fail the operation
+    pub fn record(&self, record: AuditRecord) {
+        if let Err(e) = self.append(&record) {
+            warn!("Failed to record {} in the audit log: {}", record.operation, e);
+        }
+    }
+
+    /// Records matching `filter`, oldest first. Lines that don't parse are skipped.
+    pub fn read(&self, filter: &AuditFilter) -> Result<Vec<AuditRecord>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(CuboError::SystemError(format!("Failed to open {}: {}", self.path.display(), e))),
+        };
+        Ok(BufReader::new(file)
+            .lines()
+            .map_while(|l| l.ok())
+            .filter_map(|line| serde_json::from_str::<AuditRecord>(&line).ok())
+            .filter(|record| filter.matches(record))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use tempfile::TempDir;
+
+    fn record(operation: AuditOperation, container: &str, success: bool) -> AuditRecord {
+        let result: Result<()> = if success { Ok(()) } else { Err(CuboError::ContainerNotFound(container.to_string())) };
+        AuditRecord::new(operation, &result).with_container(container)
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let temp = TempDir::new().unwrap();
+        let log = AuditLog::new(temp.path());
+        assert!(log.read(&AuditFilter::default()).unwrap().is_empty());
+
+        log.append(&record(AuditOperation::Create, "abc123", true)).unwrap();
+        log.append(&record(AuditOperation::Start, "abc123", false)).unwrap();
+        log.append(&AuditRecord::new(AuditOperation::Pull, &Ok(())).with_image("alpine:latest")).unwrap();
+
+        let records = log.read(&AuditFilter::default()).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].operation, AuditOperation::Create);
+        assert_eq!(records[1].error.as_deref(), Some("Container not found: abc123"));
+        assert_eq!(records[2].image.as_deref(), Some("alpine:latest"));
+        assert_eq!(records[2].uid, getuid().as_raw());
+
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(fs::metadata(log.path()).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_filters() {
+        let create = record(AuditOperation::Create, "abc123", true);
+        let failed_stop = record(AuditOperation::Stop, "def456", false);
+        let pull = AuditRecord::new(AuditOperation::Pull, &Ok(())).with_image("alpine:latest");
+
+        let filter = AuditFilter { operation: Some(AuditOperation::Stop), ..Default::default() };
+        assert!(!filter.matches(&create));
+        assert!(filter.matches(&failed_stop));
+
+        let filter = AuditFilter { container: Some("abc".to_string()), ..Default::default() };
+        assert!(filter.matches(&create));
+        assert!(!filter.matches(&failed_stop));
+
+        let filter = AuditFilter { failed_only: true, ..Default::default() };
+        assert!(!filter.matches(&create));
+        assert!(filter.matches(&failed_stop));
+
+        let filter = AuditFilter { image: Some("alpine:latest".to_string()), ..Default::default() };
+        assert!(filter.matches(&pull));
+        assert!(!filter.matches(&create));
+
+        let filter = AuditFilter { user: Some(create.uid.to_string()), ..Default::default() };
+        assert!(filter.matches(&create));
+        let filter = AuditFilter { user: Some("nobody-at-all".to_string()), ..Default::default() };
+        assert!(!filter.matches(&create));
+
+        let filter = AuditFilter { since: Some(Utc::now() + TimeDelta::hours(1)), ..Default::default() };
+        assert!(!filter.matches(&create));
+        let filter = AuditFilter { until: Some(Utc::now() + TimeDelta::hours(1)), ..Default::default() };
+        assert!(filter.matches(&create));
+    }
+
+    #[test]
+    fn test_operation_parse() {
+        assert_eq!("rm".parse::<AuditOperation>().unwrap(), AuditOperation::Remove);
+        assert_eq!("build".parse::<AuditOperation>().unwrap().to_string(), "build");
+        assert!("exec".parse::<AuditOperation>().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! History of the builds of images: when each step ran, how long it took and what it
+//! printed, kept in the image store for `cubo image history`
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::container::build_progress::Vertex;
+use crate::container::reference::ImageReference;
+use crate::error::{CuboError, Result};
+
+/// A step of a build as it ran
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryStep {
+    /// Name of the step, e.g. "[2/4] RUN make"
+    pub name: String,
+    /// Digest of the step's vertex, the same in the build progress
+    pub digest: String,
+    pub started: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Digest of the output of the step, when it printed anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_digest: Option<String>,
+    /// The output, stored in a file of its own next to the history
+    #[serde(skip)]
+    pub log: Vec<u8>,
+}
+
+impl HistoryStep {
+    /// The step of a completed vertex, which printed `log`
+    pub fn new(vertex: &Vertex, log: Vec<u8>) -> Self {
+        let started = vertex.started.unwrap_or_else(Utc::now);
+        let completed = vertex.completed.unwrap_or(started);
+        let log_digest = match log.is_empty() {
+            true => None,
+            false => Some(format!("sha256:{:x}", Sha256::digest(&log))),
+        };
+        Self {
+            name: vertex.name.clone(),
+            digest: vertex.digest.clone(),
+            started,
+            duration_ms: (completed - started).num_milliseconds().max(0) as u64,
+            log_digest,
+            log,
+        }
+    }
+}
+
+/// The steps of the build of an image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildHistory {
+    pub image: String,
+    pub digest: String,
+    pub steps: Vec<HistoryStep>,
+}
+
+/// Build histories, under `images/history/<image>/`: `history.json` and the output of each
+/// step that printed anything as `<step>.log`
+pub struct HistoryStore {
+    dir: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn new(image_store_root: &Path) -> Self {
+        Self { dir: image_store_root.join("history") }
+    }
+
+    fn image_dir(&self, image: &str) -> Result<PathBuf> {
+        let reference = ImageReference::parse(image)?.normalized();
+        Ok(self.dir.join(reference.file_name()))
+    }
+
+    /// Store `history`, replacing the one of a previous build of the image
+    pub fn save(&self, history: &BuildHistory) -> Result<()> {
+        let dir = self.image_dir(&history.image)?;
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        for (idx, step) in history.steps.iter().enumerate() {
+            if !step.log.is_empty() {
+                fs::write(dir.join(format!("{}.log", idx + 1)), &step.log)?;
+            }
+        }
+        let json = serde_json::to_string_pretty(history)
+            .map_err(|e| CuboError::SystemError(format!("Failed to serialize the build history: {}", e)))?;
+        fs::write(dir.join("history.json"), json)?;
+        debug!("Stored the build history of {}", history.image);
+        Ok(())
+    }
+
+    /// The history of the last build of `image`, with the output of the steps when `logs`
+    pub fn load(&self, image: &str, logs: bool) -> Result<BuildHistory> {
+        let dir = self.image_dir(image)?;
+        let path = dir.join("history.json");
+        let data = fs::read(&path).map_err(|_| {
+            CuboError::InvalidConfiguration(format!("{} has no build history (only images built by cubo do)", image))
+        })?;
+        let mut history: BuildHistory = serde_json::from_slice(&data)
+            .map_err(|e| CuboError::SystemError(format!("Failed to parse {}: {}", path.display(), e)))?;
+        if logs {
+            for (idx, step) in history.steps.iter_mut().enumerate() {
+                if step.log_digest.is_some() {
+                    step.log = fs::read(dir.join(format!("{}.log", idx + 1))).unwrap_or_default();
+                }
+            }
+        }
+        Ok(history)
+    }
+
+    pub fn remove(&self, image: &str) {
+        if let Ok(dir) = self.image_dir(image) {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::build_progress::Steps;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_history_store_roundtrip() {
+        let temp = TempDir::new().unwrap();
+        let store = HistoryStore::new(temp.path());
+        let mut steps = Steps::new(vec!["BASE alpine:3.18".to_string(), "RUN echo hi".to_string()]);
+        let base = steps.start().complete(None);
+        let run = steps.start().complete(None);
+        let history = BuildHistory {
+            image: "myapp:v1".to_string(),
+            digest: "sha256:aa".to_string(),
+            steps: vec![HistoryStep::new(&base, Vec::new()), HistoryStep::new(&run, b"hi\n".to_vec())],
+        };
+        store.save(&history).unwrap();
+
+        let without_logs = store.load("myapp:v1", false).unwrap();
+        assert_eq!(without_logs.steps[1].name, "[2/2] RUN echo hi");
+        assert!(without_logs.steps[1].log.is_empty());
+        assert_eq!(without_logs.steps[1].log_digest, Some(format!("sha256:{:x}", Sha256::digest(b"hi\n"))));
+        assert_eq!(without_logs.steps[0].log_digest, None);
+        assert_eq!(store.load("myapp:v1", true).unwrap(), history);
+
+        store.remove("myapp:v1");
+        assert!(matches!(store.load("myapp:v1", false), Err(CuboError::InvalidConfiguration(_))));
+    }
+}
@@ -0,0 +1,217 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// How `cubo build --progress` reports the build
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Human readable lines
+    #[default]
+    Plain,
+    /// One JSON solve status per line, shaped like BuildKit's
+    Json,
+}
+
+impl std::str::FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(ProgressMode::Plain),
+            "json" => Ok(ProgressMode::Json),
+            other => Err(format!("unknown progress mode '{}' (expected plain or json)", other)),
+        }
+    }
+}
+
+/// Receives the solve statuses of a build as they happen
+pub type ProgressSink<'a> = &'a (dyn Fn(&SolveStatus) + Send + Sync);
+
+/// An update of a build: steps that started or ended, and output they wrote. The same shape
+/// as the status BuildKit reports, without its `statuses`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SolveStatus {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vertexes: Vec<Vertex>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<VertexLog>,
+}
+
+impl SolveStatus {
+    pub fn vertex(vertex: Vertex) -> Self {
+        Self { vertexes: vec![vertex], logs: Vec::new() }
+    }
+
+    /// Output of a step; `stream` is 1 for stdout and 2 for stderr
+    pub fn log(vertex: &Vertex, stream: u8, data: &[u8]) -> Self {
+        let log = VertexLog {
+            vertex: vertex.digest.clone(),
+            stream,
+            data: STANDARD.encode(data),
+            timestamp: Utc::now(),
+        };
+        Self { vertexes: Vec::new(), logs: vec![log] }
+    }
+}
+
+/// A step of the build
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Vertex {
+    /// Identifies the step: the digest of its instruction and of the steps before it
+    pub digest: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Vertex {
+    /// The step finished now, failed with `error` if there is one
+    pub fn complete(mut self, error: Option<String>) -> Self {
+        self.completed = Some(Utc::now());
+        self.error = error;
+        self
+    }
+}
+
+/// Output of a step, base64 encoded
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VertexLog {
+    pub vertex: String,
+    pub stream: u8,
+    pub data: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The vertexes of the instructions of a build, handed out in order. Each one's digest
+/// covers the ones before it, so a step keeps its digest as long as it and everything
+/// before it stay the same.
+pub struct Steps {
+    instructions: Vec<String>,
+    next: usize,
+    digest: Option<String>,
+}
+
+impl Steps {
+    pub fn new(instructions: Vec<String>) -> Self {
+        Self { instructions, next: 0, digest: None }
+    }
+
+    /// The vertex of the next instruction, started now
+    pub fn start(&mut self) -> Vertex {
+        let text = self.instructions.get(self.next).cloned().unwrap_or_default();
+        self.next += 1;
+        let name = format!("[{}/{}] {}", self.next, self.instructions.len(), text);
+        self.vertex(name, &text)
+    }
+
+    /// The vertex of storing the built image, after the instructions
+    pub fn export(&mut self) -> Vertex {
+        self.vertex("exporting to image".to_string(), "export")
+    }
+
+    /// Every vertex of a build whose image was taken from the cache
+    pub fn cached(mut self) -> SolveStatus {
+        let vertexes = (0..self.instructions.len())
+            .map(|_| {
+                let mut vertex = self.start().complete(None);
+                vertex.started = vertex.completed;
+                vertex.cached = true;
+                vertex
+            })
+            .collect();
+        SolveStatus { vertexes, logs: Vec::new() }
+    }
+
+    fn vertex(&mut self, name: String, text: &str) -> Vertex {
+        let mut hasher = Sha256::new();
+        if let Some(ref input) = self.digest {
+            hasher.update(input);
+            hasher.update("\0");
+        }
+        hasher.update(text);
+        let digest = format!("sha256:{:x}", hasher.finalize());
+        let inputs = self.digest.replace(digest.clone()).into_iter().collect();
+        Vertex {
+            digest,
+            inputs,
+            name,
+            started: Some(Utc::now()),
+            completed: None,
+            cached: false,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps() -> Steps {
+        Steps::new(vec!["BASE alpine:3.20".to_string(), "RUN echo hi".to_string()])
+    }
+
+    #[test]
+    fn test_steps_chain_digests() {
+        let mut first = steps();
+        let base = first.start();
+        let run = first.start();
+        assert_eq!(base.name, "[1/2] BASE alpine:3.20");
+        assert_eq!(run.name, "[2/2] RUN echo hi");
+        assert!(base.inputs.is_empty());
+        assert_eq!(run.inputs, vec![base.digest.clone()]);
+        assert_eq!(first.export().inputs, vec![run.digest.clone()]);
+
+        // Same instructions, same digests; a changed earlier step changes the later ones
+        let mut again = steps();
+        assert_eq!(again.start().digest, base.digest);
+        assert_eq!(again.start().digest, run.digest);
+        let mut changed = Steps::new(vec!["BASE alpine:3.21".to_string(), "RUN echo hi".to_string()]);
+        changed.start();
+        assert_ne!(changed.start().digest, run.digest);
+    }
+
+    #[test]
+    fn test_solve_status_json() {
+        let mut steps = steps();
+        let vertex = steps.start();
+        let value = serde_json::to_value(SolveStatus::vertex(vertex.clone())).unwrap();
+        assert_eq!(value["vertexes"][0]["name"], "[1/2] BASE alpine:3.20");
+        assert!(value["vertexes"][0].get("completed").is_none());
+        assert!(value["vertexes"][0].get("cached").is_none());
+        assert!(value.get("logs").is_none());
+
+        let failed = serde_json::to_value(SolveStatus::vertex(vertex.clone().complete(Some("boom".to_string())))).unwrap();
+        assert_eq!(failed["vertexes"][0]["error"], "boom");
+        assert!(failed["vertexes"][0]["completed"].is_string());
+
+        let log = serde_json::to_value(SolveStatus::log(&vertex, 2, b"oops\n")).unwrap();
+        assert_eq!(log["logs"][0]["vertex"], vertex.digest.as_str());
+        assert_eq!(log["logs"][0]["stream"], 2);
+        assert_eq!(log["logs"][0]["data"], "b29wcwo=");
+    }
+
+    #[test]
+    fn test_cached_build() {
+        let status = steps().cached();
+        assert_eq!(status.vertexes.len(), 2);
+        assert!(status.vertexes.iter().all(|v| v.cached && v.started == v.completed));
+    }
+
+    #[test]
+    fn test_progress_mode_from_str() {
+        assert_eq!("json".parse::<ProgressMode>().unwrap(), ProgressMode::Json);
+        assert_eq!("plain".parse::<ProgressMode>().unwrap(), ProgressMode::Plain);
+        assert!("tty".parse::<ProgressMode>().is_err());
+    }
+}
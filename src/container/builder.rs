@@ -1,17 +1,72 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 
 use crate::error::{CuboError, Result};
-use super::cubofile::{Cubofile, Instruction};
-use super::cubofile_toml::CubofileToml;
+use super::cubofile::{glob_match, has_wildcard, parse_chmod, Cubofile, Instruction};
+use super::cubofile_toml::{CopyStep, CubofileToml, RunStep};
+use super::build_history::{BuildHistory, HistoryStep, HistoryStore};
+use super::build_progress::{ProgressSink, SolveStatus, Steps, Vertex};
+use super::cgroup::CgroupManager;
 use super::image_store::{ImageStore, ImageManifest, ImageConfig};
+use super::reference::ImageReference;
 use super::rootfs::RootfsBuilder;
+use super::runtime::RuntimeConfig;
+use super::signal::parse_signal;
+use super::sbom::{AttestationStore, Provenance, Sbom};
+use super::secure_join::secure_join;
+use super::signature::image_digest;
+use super::user;
+use super::ContainerConfig;
+use chrono::{DateTime, Utc};
+
+/// How often a RUN step with a timeout is checked for having exited
+const STEP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Limits of every RUN step, so a hung download or an endless loop fails the build instead
+/// of blocking it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StepLimits {
+    /// Time after which the step is killed, with everything it started
+    pub timeout: Option<Duration>,
+    /// Memory limit in bytes of the step's cgroup
+    pub memory: Option<u64>,
+    /// CPU limit in cores of the step's cgroup
+    pub cpus: Option<f32>,
+}
+
+impl StepLimits {
+    /// Whether the step runs in a cgroup of its own
+    fn needs_cgroup(&self) -> bool {
+        self.memory.is_some() || self.cpus.is_some()
+    }
+}
 
 pub struct ImageBuilder<'a> {
     image_store: &'a ImageStore,
     build_context: PathBuf,
+    /// Environment of RUN steps from --build-arg
+    build_args: BTreeMap<String, String>,
+    /// Where `--progress json` sends the status of each step
+    progress: Option<ProgressSink<'a>>,
+    /// Labels from --label, set over those of the Cubofile
+    labels: BTreeMap<String, String>,
+    /// Timeout and resource limits of RUN steps
+    step_limits: StepLimits,
+    /// Output of the running step
+    step_log: Mutex<Vec<u8>>,
+    /// Steps of the build so far, stored with the image
+    history: Mutex<Vec<HistoryStep>>,
+    /// Pull policy and mirrors of base images, and the cgroup root of RUN steps
+    config: RuntimeConfig,
 }
 
 impl<'a> ImageBuilder<'a> {
@@ -19,11 +74,92 @@ impl<'a> ImageBuilder<'a> {
         Self {
             image_store,
             build_context,
+            build_args: BTreeMap::new(),
+            progress: None,
+            labels: BTreeMap::new(),
+            step_limits: StepLimits::default(),
+            step_log: Mutex::new(Vec::new()),
+            history: Mutex::new(Vec::new()),
+            config: RuntimeConfig::from_env(),
+        }
+    }
+
+    pub fn with_config(mut self, config: &RuntimeConfig) -> Self {
+        self.config = config.clone();
+        self
+    }
+
+    pub fn with_build_args(mut self, build_args: BTreeMap<String, String>) -> Self {
+        self.build_args = build_args;
+        self
+    }
+
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_step_limits(mut self, step_limits: StepLimits) -> Self {
+        self.step_limits = step_limits;
+        self
+    }
+
+    /// Report the start and end of each step, and the output of RUN steps, to `progress`
+    /// instead of printing them
+    pub fn with_progress(mut self, progress: ProgressSink<'a>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn report(&self, status: SolveStatus) {
+        if let Some(progress) = self.progress {
+            progress(&status);
+        }
+    }
+
+    /// Report the end of the step of `vertex`, failed with `error` if there is one, and add
+    /// it to the build history with what it printed
+    fn finish(&self, vertex: Vertex, error: Option<&CuboError>) {
+        let vertex = vertex.complete(error.map(ToString::to_string));
+        let log = std::mem::take(&mut *self.step_log.lock().unwrap());
+        self.history.lock().unwrap().push(HistoryStep::new(&vertex, log));
+        self.report(SolveStatus::vertex(vertex));
+    }
+
+    /// Run the next step of `steps`, reporting when it starts and ends
+    fn step(&self, steps: &mut Steps, run: impl FnOnce(&Vertex) -> Result<()>) -> Result<()> {
+        let vertex = steps.start();
+        self.report(SolveStatus::vertex(vertex.clone()));
+        let result = run(&vertex);
+        self.finish(vertex, result.as_ref().err());
+        result
+    }
+
+    /// Store the built rootfs as `image_ref` in the step that exports it
+    fn export(&self, steps: &mut Steps, image_ref: &str, base_image: &str, work_rootfs: &Path, mut config: ImageConfig) -> Result<()> {
+        if !self.labels.is_empty() {
+            config.labels.get_or_insert_with(BTreeMap::new).extend(self.labels.clone());
         }
+        let vertex = steps.export();
+        self.report(SolveStatus::vertex(vertex.clone()));
+        let result = (|| {
+            info!("Creating image layer from built rootfs");
+            let layer_tar = work_rootfs.with_file_name("layer.tar");
+            self.create_layer_tar(work_rootfs, &layer_tar)?;
+            self.store_image(image_ref, base_image, &layer_tar, config)?;
+            let digest = self.image_store.resolve(image_ref)?;
+            self.report(SolveStatus::log(&vertex, 1, format!("writing image {}\nnaming to {}\n", digest, image_ref).as_bytes()));
+            Ok(())
+        })();
+        self.finish(vertex, result.as_ref().err());
+        result
     }
 
     pub async fn build(&self, cubofile: &Cubofile, image_ref: &str) -> Result<()> {
+        ImageReference::parse(image_ref)?;
         info!("Building image: {}", image_ref);
+        let started = Utc::now();
+        self.history.lock().unwrap().clear();
 
         let base_image = cubofile.base_image().ok_or_else(|| {
             CuboError::InvalidConfiguration("Cubofile must start with BASE instruction".to_string())
@@ -31,77 +167,73 @@ impl<'a> ImageBuilder<'a> {
 
         info!("Base image: {}", base_image);
 
-        self.ensure_image_available(&base_image).await?;
-
         let temp_dir = tempfile::tempdir()
             .map_err(|e| CuboError::SystemError(format!("Failed to create temp dir: {}", e)))?;
         let work_rootfs = temp_dir.path().join("rootfs");
+        let mut steps = Steps::new(instruction_texts(cubofile));
+        let mut image_config = self.prepare_base(&mut steps, &base_image, &work_rootfs).await?;
 
-        info!("Extracting base image into working directory");
-        let rootfs_builder = RootfsBuilder::new(self.image_store);
-        rootfs_builder.build_from_image(&base_image, &work_rootfs)?;
-
-        let base_config = self.image_store.get_config(&base_image)?;
-        let mut image_config = base_config;
-
+        let first_base = cubofile.instructions.iter().position(|i| matches!(i, Instruction::Base { .. }));
         for (idx, instruction) in cubofile.instructions.iter().enumerate() {
-            match instruction {
-                Instruction::Base { .. } => {
-                    debug!("Step {}: BASE (already applied)", idx + 1);
-                }
-
-                Instruction::Run { command } => {
-                    info!("Step {}: RUN {}", idx + 1, command);
-                    self.execute_run(&work_rootfs, command)?;
-                }
-
-                Instruction::Copy { src, dest } => {
-                    info!("Step {}: COPY {} {}", idx + 1, src, dest);
-                    self.execute_copy(&work_rootfs, src, dest)?;
-                }
-
-                Instruction::Env { key, value } => {
-                    info!("Step {}: ENV {}={}", idx + 1, key, value);
-                    let mut env_vars = image_config.env.unwrap_or_default();
-                    env_vars.push(format!("{}={}", key, value));
-                    image_config.env = Some(env_vars);
-                }
-
-                Instruction::Workdir { path } => {
-                    info!("Step {}: WORKDIR {}", idx + 1, path);
-                    image_config.working_dir = Some(path.clone());
-                }
-
-                Instruction::Cmd { command } => {
-                    info!("Step {}: CMD {:?}", idx + 1, command);
-                    image_config.cmd = Some(command.clone());
-                }
-
-                Instruction::Comment => {
-                    // Ignore comments
-                }
+            if matches!(instruction, Instruction::Comment) || Some(idx) == first_base {
+                continue;
             }
+            self.step(&mut steps, |vertex| {
+                match instruction {
+                    Instruction::Base { .. } => {
+                        debug!("Step {}: BASE (already applied)", idx + 1);
+                    }
+
+                    Instruction::Run { command } => {
+                        info!("Step {}: RUN {}", idx + 1, command);
+                        self.execute_run(&work_rootfs, &RunStep { command: command.clone(), ..Default::default() }, vertex)?;
+                    }
+
+                    Instruction::Copy { srcs, dest, chown, chmod } => {
+                        info!("Step {}: COPY {} {}", idx + 1, srcs.join(" "), dest);
+                        let attrs = copy_attrs(&work_rootfs, chown.as_deref(), *chmod)?;
+                        self.execute_copy_sources(&work_rootfs, srcs, dest, attrs)?;
+                    }
+
+                    Instruction::Env { key, value } => {
+                        info!("Step {}: ENV {}={}", idx + 1, key, value);
+                        image_config.env.get_or_insert_with(Vec::new).push(format!("{}={}", key, value));
+                    }
+
+                    Instruction::Workdir { path } => {
+                        info!("Step {}: WORKDIR {}", idx + 1, path);
+                        image_config.working_dir = Some(path.clone());
+                    }
+
+                    Instruction::Cmd { command } => {
+                        info!("Step {}: CMD {:?}", idx + 1, command);
+                        image_config.cmd = Some(command.clone());
+                    }
+
+                    Instruction::StopSignal { signal } => {
+                        info!("Step {}: STOPSIGNAL {}", idx + 1, signal);
+                        image_config.stop_signal = Some(signal.clone());
+                    }
+
+                    Instruction::Label { labels } => {
+                        info!("Step {}: {}", idx + 1, label_text(labels));
+                        image_config.labels.get_or_insert_with(BTreeMap::new).extend(labels.iter().cloned());
+                    }
+
+                    Instruction::Comment => {}
+                }
+                Ok(())
+            })?;
         }
 
-        info!("Creating image layer from built rootfs");
-        let layer_tar = temp_dir.path().join("layer.tar");
-        self.create_layer_tar(&work_rootfs, &layer_tar)?;
-
-        let safe_name = image_ref.replace(':', "_");
-        let final_layer_path = self.image_store_root().join("blobs").join(format!("{}.tar", safe_name));
-
-        fs::create_dir_all(final_layer_path.parent().unwrap())
-            .map_err(|e| CuboError::SystemError(format!("Failed to create blobs dir: {}", e)))?;
-        fs::copy(&layer_tar, &final_layer_path)
-            .map_err(|e| CuboError::SystemError(format!("Failed to copy layer: {}", e)))?;
-
-        let manifest = ImageManifest {
-            reference: image_ref.to_string(),
-            layers: vec![final_layer_path.to_string_lossy().to_string()],
-            config: image_config,
-        };
-
-        self.save_manifest(&manifest)?;
+        self.export(&mut steps, image_ref, &base_image, &work_rootfs, image_config)?;
+        let instructions = cubofile.instructions.iter().filter_map(instruction_text).collect();
+        if let Err(e) = self.attest(image_ref, &base_image, &work_rootfs, instructions, started) {
+            warn!("Failed to record the SBOM and provenance of {}: {}", image_ref, e);
+        }
+        if let Err(e) = self.save_history(image_ref) {
+            warn!("Failed to record the build history of {}: {}", image_ref, e);
+        }
 
         info!("Successfully built image: {}", image_ref);
         Ok(())
@@ -109,103 +241,213 @@ impl<'a> ImageBuilder<'a> {
 
 
     pub async fn build_from_toml(&self, cubofile: &CubofileToml, image_ref: &str) -> Result<()> {
+        ImageReference::parse(image_ref)?;
         info!("BUilding image from TOML: {}", image_ref);
+        let started = Utc::now();
+        self.history.lock().unwrap().clear();
 
         let base_image = &cubofile.image.base;
         info!("Base image: {}", base_image);
 
-        self.ensure_image_available(base_image).await?;
-
         let temp_dir = tempfile::tempdir()
             .map_err(|e| CuboError::SystemError(format!("Failed to create temp dir: {}", e)))?;
         let work_rootfs = temp_dir.path().join("rootfs");
-
-        info!("extracting base image into working directory");
-        let rootfs_builder = RootfsBuilder::new(self.image_store);
-        rootfs_builder.build_from_image(base_image, &work_rootfs)?;
-
-        let base_config = self.image_store.get_config(base_image)?;
-        let mut image_config = base_config;
+        let mut steps = Steps::new(toml_instructions(cubofile));
+        let mut image_config = self.prepare_base(&mut steps, base_image, &work_rootfs).await?;
 
         for (idx, run_step) in cubofile.run.iter().enumerate() {
-            info!("Step {}: Run {}", idx + 1, run_step.command);
-            self.execute_run(&work_rootfs, &run_step.command)?;
+            self.step(&mut steps, |vertex| {
+                info!("Step {}: Run {}", idx + 1, run_step.command);
+                self.execute_run(&work_rootfs, run_step, vertex)
+            })?;
         }
 
         for (idx, copy_step) in cubofile.copy.iter().enumerate() {
-            info!("Step {}: Copy {} {}", idx + 1, copy_step.src, copy_step.dest);
-            self.execute_copy(&work_rootfs, &copy_step.src, &copy_step.dest)?;
+            self.step(&mut steps, |_| {
+                info!("Step {}: Copy {} {}", idx + 1, copy_step.src, copy_step.dest);
+                self.execute_copy_step(&work_rootfs, copy_step)
+            })?;
         }
 
         if let Some(ref workdir) = &cubofile.config.workdir {
-            info!("Setting WORKDIR to {}", workdir);
-            image_config.working_dir = Some(workdir.clone());
+            self.step(&mut steps, |_| {
+                info!("Setting WORKDIR to {}", workdir);
+                image_config.working_dir = Some(workdir.clone());
+                Ok(())
+            })?;
         }
 
         if let Some(ref cmd ) = &cubofile.config.cmd {
-            info!("Setting CMD: {:?}", cmd);
-            image_config.cmd = Some(cmd.clone());
+            self.step(&mut steps, |_| {
+                info!("Setting CMD: {:?}", cmd);
+                image_config.cmd = Some(cmd.clone());
+                Ok(())
+            })?;
         }
 
-        if !cubofile.config.env.is_empty() {
-            let mut env_vars = image_config.env.unwrap_or_default();
-            for (key, value) in &cubofile.config.env {
+        if let Some(ref signal) = cubofile.config.stop_signal {
+            self.step(&mut steps, |_| {
+                parse_signal(signal)?;
+                info!("Setting STOPSIGNAL {}", signal);
+                image_config.stop_signal = Some(signal.clone());
+                Ok(())
+            })?;
+        }
+
+        // Sorted so the config, and the image digest, don't depend on hash map order
+        let mut env: Vec<_> = cubofile.config.env.iter().collect();
+        env.sort();
+        for (key, value) in env {
+            self.step(&mut steps, |_| {
                 info!("Settings ENV {}={}", key, value);
-                env_vars.push(format!("{}={}", key, value));
-            }
-            image_config.env = Some(env_vars);
+                image_config.env.get_or_insert_with(Vec::new).push(format!("{}={}", key, value));
+                Ok(())
+            })?;
         }
 
+        // The ports of the Cubofile replace those of the base image
         if !cubofile.config.expose.is_empty() {
-            info!("Settings EXPOSE: {:?}", cubofile.config.expose);
-            image_config.exposed_ports = Some(cubofile.config.expose.clone());
+            image_config.exposed_ports = Some(Vec::new());
+        }
+        for port in &cubofile.config.expose {
+            self.step(&mut steps, |_| {
+                info!("Settings EXPOSE {}", port);
+                image_config.exposed_ports.get_or_insert_with(Vec::new).push(port.clone());
+                Ok(())
+            })?;
         }
 
-        info!("Creating image layer from built rootfs");
-        let layer_tar = temp_dir.path().join("layer.tar");
-        self.create_layer_tar(&work_rootfs, &layer_tar)?;
+        for (key, value) in &cubofile.config.labels {
+            self.step(&mut steps, |_| {
+                info!("Setting LABEL {}={}", key, value);
+                image_config.labels.get_or_insert_with(BTreeMap::new).insert(key.clone(), value.clone());
+                Ok(())
+            })?;
+        }
 
-        let safe_name = image_ref.replace(":", "_");
-        let final_layer_path = self.image_store_root().join("blobs").join(format!("{}.tar", safe_name));
+        self.export(&mut steps, image_ref, base_image, &work_rootfs, image_config)?;
+        if let Err(e) = self.attest(image_ref, base_image, &work_rootfs, toml_instructions(cubofile), started) {
+            warn!("Failed to record the SBOM and provenance of {}: {}", image_ref, e);
+        }
+        if let Err(e) = self.save_history(image_ref) {
+            warn!("Failed to record the build history of {}: {}", image_ref, e);
+        }
 
-        fs::create_dir_all(final_layer_path.parent().unwrap())
-            .map_err(|e| CuboError::SystemError(format!("Failed to create blobs dir: {}", e)))?;
+        info!("Successfully built image: {}", image_ref);
+        Ok(())
+    }
 
-        fs::copy(&layer_tar, &final_layer_path)
-            .map_err(|e| CuboError::SystemError(format!("Failed to copy layer: {}", e)))?;
+    /// Pull the base image if needed and extract it into `work_rootfs`, as the first step.
+    /// Returns its config, which the other steps build on.
+    async fn prepare_base(&self, steps: &mut Steps, base_image: &str, work_rootfs: &Path) -> Result<ImageConfig> {
+        let vertex = steps.start();
+        self.report(SolveStatus::vertex(vertex.clone()));
+        let result = match self.ensure_image_available(base_image).await {
+            Ok(()) => {
+                info!("Extracting base image into working directory");
+                RootfsBuilder::new(self.image_store)
+                    .build_from_image(base_image, work_rootfs)
+                    .and_then(|_| self.image_store.get_config(base_image))
+            }
+            Err(e) => Err(e),
+        };
+        self.finish(vertex, result.as_ref().err());
+        result
+    }
 
+    /// Save the built layer under its digest and tag it `image_ref`. An image the tag pointed
+    /// to before stays in the store untagged, until `cubo image prune` removes it.
+    fn store_image(&self, image_ref: &str, base_image: &str, layer_tar: &Path, config: ImageConfig) -> Result<()> {
+        let final_layer_path = self.image_store.write_layer(layer_tar)?;
+
+        let base_digest = self.image_store.resolve(base_image)?;
+        let annotations = BTreeMap::from([
+            ("org.opencontainers.image.created".to_string(), Utc::now().to_rfc3339()),
+            ("org.opencontainers.image.base.name".to_string(), base_image.to_string()),
+            ("org.opencontainers.image.base.digest".to_string(), base_digest.clone()),
+        ]);
         let manifest = ImageManifest {
             reference: image_ref.to_string(),
+            parent: Some(base_digest),
             layers: vec![final_layer_path.to_string_lossy().to_string()],
-            config: image_config,
+            config,
+            annotations: Some(annotations),
+            repo_digest: None,
         };
+        let previous = self.image_store.get_manifest(image_ref).ok();
+        let replaced = self.image_store.resolve(image_ref).ok();
+        self.image_store.save_manifest(&manifest)?;
 
-        self.save_manifest(&manifest)?;
-
-        info!("Successfully built image: {}", image_ref);
+        // A rebuild of the same layers replaces the image instead of leaving it untagged
+        let (Some(previous), Some(replaced)) = (previous, replaced) else {
+            return Ok(());
+        };
+        if previous.layers != manifest.layers {
+            info!("{} now untagged as {}", image_ref, replaced);
+        } else if self.image_store.dangling_images(&HashSet::new())?.contains(&replaced) {
+            self.image_store.remove_image(&replaced)?;
+        }
         Ok(())
     }
 
+    /// Store the SBOM of the built rootfs and the provenance of the build next to the manifest
+    fn attest(&self, image_ref: &str, base_image: &str, rootfs: &Path, instructions: Vec<String>, started: DateTime<Utc>) -> Result<()> {
+        let sbom = Sbom::scan(image_ref, rootfs)?;
+        let provenance = Provenance {
+            image: image_ref.to_string(),
+            digest: image_digest(self.image_store, image_ref)?,
+            base_image: base_image.to_string(),
+            base_digest: image_digest(self.image_store, base_image)?,
+            instructions,
+            started,
+            finished: Utc::now(),
+        };
+        AttestationStore::new(self.image_store.root()).save(&sbom, &provenance)
+    }
+
+    /// Store the steps of the build of `image_ref`, for `cubo image history`
+    fn save_history(&self, image_ref: &str) -> Result<()> {
+        let history = BuildHistory {
+            image: image_ref.to_string(),
+            digest: self.image_store.resolve(image_ref)?,
+            steps: std::mem::take(&mut *self.history.lock().unwrap()),
+        };
+        HistoryStore::new(self.image_store.root()).save(&history)
+    }
+
     async fn ensure_image_available(&self, image_ref: &str) -> Result<()> {
-        if self.image_store.has_image(image_ref) {
+        use super::registry::{PullPolicy, RegistryClient};
+        let config = &self.config;
+        if self.image_store.has_image(image_ref) && config.pull_policy != PullPolicy::Always {
             debug!("Image {} already available locally", image_ref);
             return Ok(());
         }
+        if config.pull_policy == PullPolicy::Never {
+            return Err(CuboError::PullDisabled { reference: image_ref.to_string() });
+        }
 
         info!("Base image {} not found locally, pulling from registry...", image_ref);
-        println!("Pulling base image: {}", image_ref);
+        self.say(&format!("Pulling base image: {}", image_ref));
 
-        use super::registry::RegistryClient;
-        let registry_client = RegistryClient::new(ImageStore::new(self.image_store_root())?);
+        let registry_client = RegistryClient::new(self.image_store).with_config(config);
 
         registry_client.pull(image_ref).await?;
 
-        println!("Base image ready: {}", image_ref);
+        self.say(&format!("Base image ready: {}", image_ref));
         Ok(())
     }
 
-    /// Execute a RUN instruction
-    fn execute_run(&self, rootfs: &Path, command: &str) -> Result<()> {
+    /// Print a progress line, unless progress goes to the sink
+    fn say(&self, line: &str) {
+        match self.progress {
+            Some(_) => info!("{}", line),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Execute a RUN step, in its working directory, with its environment and as its user,
+    /// reporting its output as the log of `vertex`
+    fn execute_run(&self, rootfs: &Path, step: &RunStep, vertex: &Vertex) -> Result<()> {
         // Use chroot to run command in the rootfs
         // For simplicity, we'll use /bin/sh from the rootfs
         let sh_path = rootfs.join("bin/sh");
@@ -220,7 +462,8 @@ impl<'a> ImageBuilder<'a> {
             }
         }
 
-        let resolv_conf_dest = rootfs.join("etc/resolv.conf");
+        // The image's /etc/resolv.conf is often a symlink, which must not be written through
+        let resolv_conf_dest = secure_join(rootfs, "/etc/resolv.conf")?;
         if let Err(e) = fs::copy("/etc/resolv.conf", &resolv_conf_dest) {
             warn!("Failed to copy /etc/resolv.conf: {} - network may not work", e);
         }
@@ -252,13 +495,29 @@ impl<'a> ImageBuilder<'a> {
             .output();
         let proc_mounted = proc_mount_result.is_ok() && proc_mount_result.as_ref().unwrap().status.success();
 
-        let output = Command::new("chroot")
-            .arg(rootfs)
-            .arg("/bin/sh")
-            .arg("-c")
-            .arg(command)
-            .output()
-            .map_err(|e| CuboError::SystemError(format!("Failed to execute chroot: {}", e)));
+        let mut chroot = Command::new("chroot");
+        chroot.envs(&self.build_args);
+        if let Some(ref spec) = step.user {
+            // Names are looked up in the image, not on the host chroot would use
+            let user = user::lookup(&secure_join(rootfs, "/etc")?, spec)?;
+            let groups: Vec<String> = std::iter::once(user.gid).chain(user.additional_gids).map(|g| g.to_string()).collect();
+            chroot.arg(format!("--userspec={}:{}", user.uid, user.gid))
+                .arg(format!("--groups={}", groups.join(",")))
+                .env("HOME", &user.home);
+        }
+        chroot.envs(&step.env).arg(rootfs).arg("/bin/sh").arg("-c");
+        match step.workdir {
+            Some(ref workdir) => {
+                fs::create_dir_all(secure_join(rootfs, workdir)?)
+                    .map_err(|e| CuboError::SystemError(format!("Failed to create workdir {}: {}", workdir, e)))?;
+                // chroot starts in /; the paths are arguments so they need no quoting
+                chroot.arg("cd \"$0\" && exec /bin/sh -c \"$1\"").arg(workdir).arg(&step.command);
+            }
+            None => {
+                chroot.arg(&step.command);
+            }
+        }
+        let output = self.run_limited(chroot);
 
         if proc_mounted {
             let _ = Command::new("umount").arg(&proc_dir).output();
@@ -268,6 +527,12 @@ impl<'a> ImageBuilder<'a> {
         }
 
         let output = output?;
+        for (stream, data) in [(1, &output.stdout), (2, &output.stderr)] {
+            if !data.is_empty() {
+                self.report(SolveStatus::log(vertex, stream, data));
+                self.step_log.lock().unwrap().extend_from_slice(data);
+            }
+        }
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -285,9 +550,162 @@ impl<'a> ImageBuilder<'a> {
         Ok(())
     }
 
-    /// Execute a COPY instruction
-    fn execute_copy(&self, rootfs: &Path, src: &str, dest: &str) -> Result<()> {
-        let src_path = self.build_context.join(src);
+    /// Run the command of a RUN step within the step limits: in a cgroup of its own when
+    /// memory or CPU are capped, and killed along with the processes it started once past
+    /// the timeout
+    fn run_limited(&self, mut command: Command) -> Result<Output> {
+        let limits = self.step_limits;
+        let cgroup = match limits.needs_cgroup() {
+            true => Some(self.step_cgroup()?),
+            false => None,
+        };
+        if let Some(cgroup) = &cgroup {
+            let procs = cgroup.open_procs()?;
+            // Only a write between fork and exec: the step is in the cgroup before it can
+            // start anything
+            unsafe {
+                command.pre_exec(move || {
+                    for file in &procs {
+                        if libc::write(file.as_raw_fd(), b"0".as_ptr().cast(), 1) < 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        if limits.timeout.is_some() {
+            // A group of its own, to kill what the shell started along with it
+            command.process_group(0);
+        }
+
+        let output = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| CuboError::SystemError(format!("Failed to execute chroot: {}", e)))
+            .and_then(|child| wait_step(child, limits.timeout));
+
+        if let Some(cgroup) = cgroup {
+            if let Err(e) = cgroup.destroy() {
+                warn!("Failed to remove the cgroup of the RUN step: {}", e);
+            }
+        }
+        output
+    }
+
+    /// A cgroup for the RUN step, holding its memory and CPU limits
+    fn step_cgroup(&self) -> Result<CgroupManager> {
+        let cgroup = CgroupManager::new(&self.config.cgroup_root, &format!("build-{}", std::process::id()));
+        cgroup.create()?;
+        let config = ContainerConfig {
+            memory_limit: self.step_limits.memory,
+            cpu_limit: self.step_limits.cpus,
+            ..Default::default()
+        };
+        if let Err(e) = cgroup.apply(&config) {
+            let _ = cgroup.destroy();
+            return Err(e);
+        }
+        Ok(cgroup)
+    }
+
+    /// Execute a COPY step of Cubofile.toml: a relative destination is resolved against the
+    /// step's workdir, and the files get its chown owner and chmod mode
+    fn execute_copy_step(&self, rootfs: &Path, step: &CopyStep) -> Result<()> {
+        let dest = match step.workdir {
+            Some(ref workdir) if !step.dest.starts_with('/') => {
+                format!("{}/{}", workdir.trim_end_matches('/'), step.dest)
+            }
+            _ => step.dest.clone(),
+        };
+        let mode = match step.chmod {
+            Some(ref mode) => Some(parse_chmod(mode).ok_or_else(|| {
+                CuboError::InvalidConfiguration(format!("Invalid COPY mode '{}' (expected octal, e.g. 644)", mode))
+            })?),
+            None => None,
+        };
+        let attrs = copy_attrs(rootfs, step.chown.as_deref(), mode)?;
+        self.execute_copy_sources(rootfs, std::slice::from_ref(&step.src), &dest, attrs)
+    }
+
+    /// Execute a COPY of one or more sources, each of which may hold wildcards. A destination
+    /// ending in `/` is a directory: files are copied into it under their own names and
+    /// directories have their contents copied into it. Sources are copied in the order given
+    /// and the matches of a wildcard in sorted order, so a later file wins a name whatever
+    /// order the directory lists them in.
+    fn execute_copy_sources(&self, rootfs: &Path, srcs: &[String], dest: &str, attrs: CopyAttrs) -> Result<()> {
+        let into_dir = dest.ends_with('/');
+        if !into_dir && (srcs.len() > 1 || srcs.iter().any(|src| has_wildcard(src))) {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "COPY with several sources or wildcards needs a directory destination ending in '/', got {}",
+                dest
+            )));
+        }
+
+        for src in srcs {
+            for path in self.expand_source(src)? {
+                let is_file = secure_join(&self.build_context, &path)?.is_file();
+                let target = match path.rsplit('/').find(|name| !name.is_empty() && *name != ".") {
+                    Some(name) if into_dir && is_file => format!("{}{}", dest, name),
+                    _ => dest.to_string(),
+                };
+                self.execute_copy(rootfs, &path, &target, attrs)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The paths in the build context a COPY source names: the source itself, or the sorted
+    /// matches of its wildcards, which may appear in any component
+    fn expand_source(&self, src: &str) -> Result<Vec<String>> {
+        if !has_wildcard(src) {
+            return Ok(vec![src.to_string()]);
+        }
+
+        let mut matches = vec![String::new()];
+        for component in src.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            let mut next = Vec::new();
+            for base in &matches {
+                let join = |name: &str| match base.is_empty() {
+                    true => name.to_string(),
+                    false => format!("{}/{}", base, name),
+                };
+                if !has_wildcard(component) {
+                    next.push(join(component));
+                    continue;
+                }
+                let Ok(entries) = fs::read_dir(secure_join(&self.build_context, base)?) else {
+                    continue;
+                };
+                let mut names: Vec<String> = entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .filter(|name| glob_match(component, name))
+                    .collect();
+                names.sort();
+                next.extend(names.iter().map(|name| join(name)));
+            }
+            matches = next;
+        }
+
+        let mut found = Vec::new();
+        for path in matches {
+            if secure_join(&self.build_context, &path)?.exists() {
+                found.push(path);
+            }
+        }
+        if found.is_empty() {
+            return Err(CuboError::SystemError(format!("No files in the build context match {}", src)));
+        }
+        Ok(found)
+    }
+
+    /// Execute a COPY instruction. The source is looked up in the build context and the
+    /// destination in the rootfs, neither of which `..` or a symlink leads out of. The files
+    /// and directories copied get the owner and mode of `attrs` when given.
+    fn execute_copy(&self, rootfs: &Path, src: &str, dest: &str, attrs: CopyAttrs) -> Result<()> {
+        let src_path = secure_join(&self.build_context, src)?;
 
         if !src_path.exists() {
             return Err(CuboError::SystemError(format!(
@@ -297,11 +715,7 @@ impl<'a> ImageBuilder<'a> {
         }
 
         // Destination is relative to rootfs
-        let dest_path = if dest.starts_with('/') {
-            rootfs.join(dest.trim_start_matches('/'))
-        } else {
-            rootfs.join(dest)
-        };
+        let dest_path = secure_join(rootfs, dest)?;
 
         // Create parent directory
         if let Some(parent) = dest_path.parent() {
@@ -313,17 +727,20 @@ impl<'a> ImageBuilder<'a> {
         if src_path.is_file() {
             fs::copy(&src_path, &dest_path)
                 .map_err(|e| CuboError::SystemError(format!("Failed to copy file: {}", e)))?;
+            set_attrs(&dest_path, attrs)?;
         } else if src_path.is_dir() {
-            self.copy_dir_recursive(&src_path, &dest_path)?;
+            self.copy_dir_recursive(&src_path, rootfs, dest, attrs)?;
         }
 
         debug!("Copied {} to {}", src_path.display(), dest_path.display());
         Ok(())
     }
 
-    /// Recursively copy a directory
-    fn copy_dir_recursive(&self, src: &Path, dest: &Path) -> Result<()> {
-        fs::create_dir_all(dest)
+    /// Recursively copy a directory to `dest` in `rootfs`, resolving each entry in the rootfs
+    /// since the image may have symlinks where the files go
+    fn copy_dir_recursive(&self, src: &Path, rootfs: &Path, dest: &str, attrs: CopyAttrs) -> Result<()> {
+        let dest_dir = secure_join(rootfs, dest)?;
+        fs::create_dir_all(&dest_dir)
             .map_err(|e| CuboError::SystemError(format!("Failed to create directory: {}", e)))?;
 
         for entry in fs::read_dir(src)
@@ -332,75 +749,330 @@ impl<'a> ImageBuilder<'a> {
             let entry = entry
                 .map_err(|e| CuboError::SystemError(format!("Failed to read entry: {}", e)))?;
             let src_path = entry.path();
-            let dest_path = dest.join(entry.file_name());
+            let dest = format!("{}/{}", dest.trim_end_matches('/'), entry.file_name().to_string_lossy());
 
             if src_path.is_file() {
+                let dest_path = secure_join(rootfs, &dest)?;
                 fs::copy(&src_path, &dest_path)
                     .map_err(|e| CuboError::SystemError(format!("Failed to copy file: {}", e)))?;
+                set_attrs(&dest_path, attrs)?;
             } else if src_path.is_dir() {
-                self.copy_dir_recursive(&src_path, &dest_path)?;
+                self.copy_dir_recursive(&src_path, rootfs, &dest, attrs)?;
             }
         }
 
-        Ok(())
+        // Last, as a mode without write or search permission would keep the entries out
+        set_attrs(&dest_dir, attrs)
     }
 
-    /// Create a tar archive from a rootfs directory
+    /// Create a tar archive from a rootfs directory. The archive only depends on the content:
+    /// entries are sorted, mtimes are clamped to SOURCE_DATE_EPOCH (0 when unset), files of the
+    /// building user belong to root and owner names are left out.
     fn create_layer_tar(&self, rootfs: &Path, output: &Path) -> Result<()> {
-        let output_cmd = Command::new("tar")
-            .arg("-cf")
-            .arg(output)
-            .arg("-C")
-            .arg(rootfs)
-            .arg(".")
-            .output()
+        let file = fs::File::create(output)
             .map_err(|e| CuboError::SystemError(format!("Failed to create tar: {}", e)))?;
+        let mut layer = LayerWriter {
+            builder: tar::Builder::new(file),
+            epoch: source_date_epoch(),
+            uid: nix::unistd::geteuid().as_raw(),
+            gid: nix::unistd::getegid().as_raw(),
+            links: HashMap::new(),
+        };
+        layer.append_dir(rootfs, Path::new(""))?;
+        layer.builder.into_inner()
+            .and_then(|mut file| file.flush())
+            .map_err(|e| CuboError::SystemError(format!("Failed to create layer tar: {}", e)))
+    }
+}
 
-        if !output_cmd.status.success() {
-            let stderr = String::from_utf8_lossy(&output_cmd.stderr);
-            return Err(CuboError::SystemError(format!(
-                "Failed to create layer tar: {}",
-                stderr
-            )));
+/// Seconds since the epoch in `SOURCE_DATE_EPOCH`, 0 when unset
+/// Owner of copied files, ids resolved in the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileOwner {
+    uid: u32,
+    gid: u32,
+}
+
+/// Owner and mode of the files and directories a COPY creates, from --chown and --chmod
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct CopyAttrs {
+    owner: Option<FileOwner>,
+    /// Permission bits, e.g. 0o644
+    mode: Option<u32>,
+}
+
+/// Resolve the --chown user against the image's /etc/passwd and /etc/group
+fn copy_attrs(rootfs: &Path, chown: Option<&str>, mode: Option<u32>) -> Result<CopyAttrs> {
+    let owner = match chown {
+        Some(spec) => {
+            let user = user::lookup(&secure_join(rootfs, "/etc")?, spec)?;
+            Some(FileOwner { uid: user.uid, gid: user.gid })
         }
+        None => None,
+    };
+    Ok(CopyAttrs { owner, mode })
+}
 
+/// Give `path` the owner and mode of `attrs`. The layer stores the files of the building
+/// user as root's, so root in the image is the building user on disk.
+fn set_attrs(path: &Path, attrs: CopyAttrs) -> Result<()> {
+    if let Some(owner) = attrs.owner {
+        let on_disk = |id: u32, builder: u32| if id == 0 { builder } else { id };
+        let uid = on_disk(owner.uid, nix::unistd::geteuid().as_raw());
+        let gid = on_disk(owner.gid, nix::unistd::getegid().as_raw());
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid)).map_err(|e| {
+            let hint = match e.kind() {
+                std::io::ErrorKind::PermissionDenied if !nix::unistd::geteuid().is_root() => {
+                    " (only root can give copied files an owner other than root)"
+                }
+                _ => "",
+            };
+            CuboError::SystemError(format!("Failed to change the owner of {}: {}{}", path.display(), e, hint))
+        })?;
+    }
+    if let Some(mode) = attrs.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .map_err(|e| CuboError::SystemError(format!("Failed to change the mode of {}: {}", path.display(), e)))?;
+    }
+    Ok(())
+}
+
+/// Wait for a RUN step and collect its output. Past `timeout`, the step's process group is
+/// killed and the step fails.
+fn wait_step(mut child: Child, timeout: Option<Duration>) -> Result<Output> {
+    let Some(timeout) = timeout else {
+        return child.wait_with_output()
+            .map_err(|e| CuboError::SystemError(format!("Failed to wait for the RUN step: {}", e)));
+    };
+
+    // Read on threads so a step writing more than a pipe holds doesn't block
+    let read = |stream: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut data = Vec::new();
+            if let Some(mut stream) = stream {
+                let _ = stream.read_to_end(&mut data);
+            }
+            data
+        })
+    };
+    let stdout = read(child.stdout.take().map(|s| Box::new(s) as Box<dyn Read + Send>));
+    let stderr = read(child.stderr.take().map(|s| Box::new(s) as Box<dyn Read + Send>));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        let status = child.try_wait()
+            .map_err(|e| CuboError::SystemError(format!("Failed to wait for the RUN step: {}", e)))?;
+        match status {
+            Some(status) => break status,
+            None if Instant::now() >= deadline => {
+                let _ = nix::sys::signal::killpg(nix::unistd::Pid::from_raw(child.id() as i32), nix::sys::signal::Signal::SIGKILL);
+                let _ = child.wait();
+                return Err(CuboError::SystemError(format!(
+                    "RUN step timed out after {}s and was killed", timeout.as_secs()
+                )));
+            }
+            None => std::thread::sleep(STEP_POLL_INTERVAL),
+        }
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn source_date_epoch() -> u64 {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            warn!("Ignoring invalid SOURCE_DATE_EPOCH '{}'", value);
+            0
+        }),
+        Err(_) => 0,
+    }
+}
+
+/// Writes a rootfs as a tar archive with normalized metadata
+struct LayerWriter {
+    builder: tar::Builder<fs::File>,
+    epoch: u64,
+    /// Ids of the building user, stored as root
+    uid: u32,
+    gid: u32,
+    /// First path archived for each hard linked inode
+    links: HashMap<(u64, u64), PathBuf>,
+}
+
+impl LayerWriter {
+    fn append_dir(&mut self, dir: &Path, relative: &Path) -> Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", dir.display(), e)))?
+            .collect::<std::io::Result<_>>()
+            .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", dir.display(), e)))?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let name = relative.join(entry.file_name());
+            let meta = fs::symlink_metadata(&path)?;
+            self.append(&path, &name, &meta)
+                .map_err(|e| CuboError::SystemError(format!("Failed to add {} to the layer: {}", name.display(), e)))?;
+            if meta.is_dir() {
+                self.append_dir(&path, &name)?;
+            }
+        }
         Ok(())
     }
 
-    /// Get the image store root directory
-    fn image_store_root(&self) -> PathBuf {
-        // This is a bit hacky - we need access to the image store's root
-        // In a real implementation, we'd expose this via ImageStore
-        // For now, we'll assume it's in the same structure
-        std::env::var("CUBO_ROOT")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("/var/lib/cubo"))
-            .join("images")
+    fn append(&mut self, path: &Path, name: &Path, meta: &fs::Metadata) -> std::io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(meta.mode() & 0o7777);
+        header.set_mtime(self.epoch.min(meta.mtime().max(0) as u64));
+        header.set_uid(if meta.uid() == self.uid { 0 } else { meta.uid() } as u64);
+        header.set_gid(if meta.gid() == self.gid { 0 } else { meta.gid() } as u64);
+        header.set_size(0);
+
+        let file_type = meta.file_type();
+        if !file_type.is_dir() && meta.nlink() > 1 {
+            if let Some(target) = self.links.get(&(meta.dev(), meta.ino())) {
+                header.set_entry_type(tar::EntryType::Link);
+                return self.builder.append_link(&mut header, name, target);
+            }
+            self.links.insert((meta.dev(), meta.ino()), name.to_path_buf());
+        }
+
+        if file_type.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            self.builder.append_data(&mut header, name, std::io::empty())
+        } else if file_type.is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            self.builder.append_link(&mut header, name, fs::read_link(path)?)
+        } else if file_type.is_file() {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(meta.len());
+            self.builder.append_data(&mut header, name, fs::File::open(path)?)
+        } else if file_type.is_char_device() || file_type.is_block_device() {
+            header.set_entry_type(if file_type.is_char_device() {
+                tar::EntryType::Char
+            } else {
+                tar::EntryType::Block
+            });
+            let rdev = meta.rdev();
+            header.set_device_major(nix::sys::stat::major(rdev) as u32)?;
+            header.set_device_minor(nix::sys::stat::minor(rdev) as u32)?;
+            self.builder.append_data(&mut header, name, std::io::empty())
+        } else if file_type.is_fifo() {
+            header.set_entry_type(tar::EntryType::Fifo);
+            self.builder.append_data(&mut header, name, std::io::empty())
+        } else {
+            // Sockets can't be archived
+            debug!("Skipping socket {}", path.display());
+            Ok(())
+        }
     }
+}
 
-    /// Save manifest (duplicated from ImageStore for now)
-    fn save_manifest(&self, manifest: &ImageManifest) -> Result<()> {
-        let safe_name = manifest.reference.replace(':', "_");
-        let manifest_path = self.image_store_root()
-            .join("manifests")
-            .join(format!("{}.json", safe_name));
+/// An instruction as written in a Cubofile, for the build provenance
+fn instruction_text(instruction: &Instruction) -> Option<String> {
+    Some(match instruction {
+        Instruction::Base { image } => format!("BASE {}", image),
+        Instruction::Run { command } => format!("RUN {}", command),
+        Instruction::Copy { srcs, dest, chown, chmod } => {
+            copy_text(&srcs.join(" "), dest, chown.as_deref(), *chmod, None)
+        }
+        Instruction::Env { key, value } => format!("ENV {}={}", key, value),
+        Instruction::Workdir { path } => format!("WORKDIR {}", path),
+        Instruction::Cmd { command } => format!("CMD {:?}", command),
+        Instruction::StopSignal { signal } => format!("STOPSIGNAL {}", signal),
+        Instruction::Label { labels } => label_text(labels),
+        Instruction::Comment => return None,
+    })
+}
 
-        fs::create_dir_all(manifest_path.parent().unwrap())
-            .map_err(|e| CuboError::SystemError(format!("Failed to create manifests dir: {}", e)))?;
+/// A LABEL instruction, with the values that hold spaces or quotes quoted
+fn label_text(labels: &[(String, String)]) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| match value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+            true => format!("{}={:?}", key, value),
+            false => format!("{}={}", key, value),
+        })
+        .collect();
+    format!("LABEL {}", pairs.join(" "))
+}
 
-        let json = serde_json::to_string_pretty(manifest)
-            .map_err(|e| CuboError::SystemError(format!("Failed to serialize manifest: {}", e)))?;
+/// The steps of a Cubofile in the order they are applied: its first BASE, then the other
+/// instructions
+pub fn instruction_texts(cubofile: &Cubofile) -> Vec<String> {
+    let base = cubofile.instructions.iter().position(|i| matches!(i, Instruction::Base { .. }));
+    let rest = cubofile.instructions.iter().enumerate().filter(|(idx, _)| Some(*idx) != base);
+    base.map(|idx| &cubofile.instructions[idx])
+        .into_iter()
+        .chain(rest.map(|(_, instruction)| instruction))
+        .filter_map(instruction_text)
+        .collect()
+}
 
-        fs::write(&manifest_path, json)
-            .map_err(|e| CuboError::SystemError(format!("Failed to write manifest: {}", e)))?;
+/// The steps of a Cubofile.toml in the order they are applied, written as Cubofile instructions
+pub fn toml_instructions(cubofile: &CubofileToml) -> Vec<String> {
+    let mut instructions = vec![format!("BASE {}", cubofile.image.base)];
+    instructions.extend(cubofile.run.iter().map(run_step_text));
+    instructions.extend(cubofile.copy.iter().map(copy_step_text));
+    let config = &cubofile.config;
+    if let Some(ref workdir) = config.workdir {
+        instructions.push(format!("WORKDIR {}", workdir));
+    }
+    if let Some(ref cmd) = config.cmd {
+        instructions.push(format!("CMD {:?}", cmd));
+    }
+    if let Some(ref signal) = config.stop_signal {
+        instructions.push(format!("STOPSIGNAL {}", signal));
+    }
+    let mut env: Vec<_> = config.env.iter().collect();
+    env.sort();
+    instructions.extend(env.into_iter().map(|(key, value)| format!("ENV {}={}", key, value)));
+    instructions.extend(config.expose.iter().map(|port| format!("EXPOSE {}", port)));
+    instructions.extend(config.labels.iter().map(|(key, value)| label_text(&[(key.clone(), value.clone())])));
+    instructions
+}
 
-        Ok(())
+/// A RUN step of Cubofile.toml as an instruction, its options as flags before the command
+fn run_step_text(step: &RunStep) -> String {
+    let mut text = String::from("RUN");
+    if let Some(ref workdir) = step.workdir {
+        text.push_str(&format!(" --workdir={}", workdir));
+    }
+    if let Some(ref user) = step.user {
+        text.push_str(&format!(" --user={}", user));
+    }
+    for (key, value) in &step.env {
+        text.push_str(&format!(" --env={}={}", key, value));
     }
+    format!("{} {}", text, step.command)
+}
+
+fn copy_step_text(step: &CopyStep) -> String {
+    copy_text(&step.src, &step.dest, step.chown.as_deref(), step.chmod.as_deref().and_then(parse_chmod), step.workdir.as_deref())
+}
+
+fn copy_text(src: &str, dest: &str, chown: Option<&str>, chmod: Option<u32>, workdir: Option<&str>) -> String {
+    let mut text = String::from("COPY");
+    if let Some(chown) = chown {
+        text.push_str(&format!(" --chown={}", chown));
+    }
+    if let Some(chmod) = chmod {
+        text.push_str(&format!(" --chmod={:o}", chmod));
+    }
+    if let Some(workdir) = workdir {
+        text.push_str(&format!(" --workdir={}", workdir));
+    }
+    format!("{} {} {}", text, src, dest)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serial_test::serial;
     use tempfile::TempDir;
 
     #[test]
@@ -427,7 +1099,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
 
-        builder.copy_dir_recursive(&src, &dest).unwrap();
+        builder.copy_dir_recursive(&src, tmp.path(), &dest.strip_prefix(tmp.path()).unwrap().to_string_lossy(), CopyAttrs::default()).unwrap();
 
         assert!(dest.join("file1.txt").exists());
         assert!(dest.join("subdir/file2.txt").exists());
@@ -443,7 +1115,7 @@ mod tests {
         fs::write(src.join("test.txt"), "test content").unwrap();
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
-        builder.copy_dir_recursive(&src, &dest).unwrap();
+        builder.copy_dir_recursive(&src, tmp.path(), &dest.strip_prefix(tmp.path()).unwrap().to_string_lossy(), CopyAttrs::default()).unwrap();
         assert!(dest.exists());
         assert!(dest.join("test.txt").exists());
     }
@@ -499,7 +1171,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, context);
 
-        let result = builder.execute_copy(&rootfs, "app.txt", "/app/app.txt");
+        let result = builder.execute_copy(&rootfs, "app.txt", "/app/app.txt", CopyAttrs::default());
         assert!(result.is_ok());
         assert!(rootfs.join("app/app.txt").exists());
         assert_eq!(fs::read_to_string(rootfs.join("app/app.txt")).unwrap(), "application data");
@@ -519,7 +1191,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, context);
 
-        let result = builder.execute_copy(&rootfs, "src", "/app/src");
+        let result = builder.execute_copy(&rootfs, "src", "/app/src", CopyAttrs::default());
         assert!(result.is_ok());
         assert!(rootfs.join("app/src/main.rs").exists());
         assert!(rootfs.join("app/src/lib.rs").exists());
@@ -537,7 +1209,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, context);
 
-        let result = builder.execute_copy(&rootfs, "nonexistent.txt", "/app/file.txt");
+        let result = builder.execute_copy(&rootfs, "nonexistent.txt", "/app/file.txt", CopyAttrs::default());
         assert!(result.is_err());
     }
 
@@ -554,7 +1226,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, context);
 
-        let result = builder.execute_copy(&rootfs, "config.json", "/etc/app/config.json");
+        let result = builder.execute_copy(&rootfs, "config.json", "/etc/app/config.json", CopyAttrs::default());
         assert!(result.is_ok());
         assert!(rootfs.join("etc/app/config.json").exists());
     }
@@ -572,88 +1244,174 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, context);
 
-        let result = builder.execute_copy(&rootfs, "data.txt", "app/data.txt");
+        let result = builder.execute_copy(&rootfs, "data.txt", "app/data.txt", CopyAttrs::default());
         assert!(result.is_ok());
         assert!(rootfs.join("app/data.txt").exists());
     }
 
     #[test]
-    fn test_create_layer_tar() {
+    fn test_execute_copy_sources_with_wildcards() {
         let tmp = TempDir::new().unwrap();
+        let context = tmp.path().join("context");
         let rootfs = tmp.path().join("rootfs");
-        let output = tmp.path().join("layer.tar");
 
+        fs::create_dir_all(context.join("configs/nested")).unwrap();
         fs::create_dir_all(&rootfs).unwrap();
-        fs::write(rootfs.join("file.txt"), "content").unwrap();
-        fs::create_dir_all(rootfs.join("subdir")).unwrap();
-        fs::write(rootfs.join("subdir/nested.txt"), "nested content").unwrap();
+        fs::write(context.join("configs/app.yaml"), "app").unwrap();
+        fs::write(context.join("configs/db.yaml"), "db").unwrap();
+        fs::write(context.join("configs/notes.txt"), "notes").unwrap();
+        fs::write(context.join("configs/nested/cache.yaml"), "cache").unwrap();
+        fs::write(context.join("README.md"), "readme").unwrap();
 
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
-        let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
+        let builder = ImageBuilder::new(&image_store, context);
 
-        let result = builder.create_layer_tar(&rootfs, &output);
-        assert!(result.is_ok());
-        assert!(output.exists());
-        // Verify tar file is not empty
-        let metadata = fs::metadata(&output).unwrap();
-        assert!(metadata.len() > 0);
+        assert_eq!(
+            builder.expand_source("./configs/*.yaml").unwrap(),
+            vec!["configs/app.yaml".to_string(), "configs/db.yaml".to_string()]
+        );
+        assert_eq!(builder.expand_source("c*/*/*.yaml").unwrap(), vec!["configs/nested/cache.yaml".to_string()]);
+        assert!(builder.expand_source("configs/*.json").is_err());
+
+        let srcs = vec!["./configs/*.yaml".to_string(), "README.md".to_string()];
+        builder.execute_copy_sources(&rootfs, &srcs, "/etc/app/", CopyAttrs::default()).unwrap();
+        assert_eq!(fs::read_to_string(rootfs.join("etc/app/app.yaml")).unwrap(), "app");
+        assert_eq!(fs::read_to_string(rootfs.join("etc/app/db.yaml")).unwrap(), "db");
+        assert_eq!(fs::read_to_string(rootfs.join("etc/app/README.md")).unwrap(), "readme");
+        assert!(!rootfs.join("etc/app/notes.txt").exists());
+
+        assert!(builder.execute_copy_sources(&rootfs, &srcs, "/etc/app", CopyAttrs::default()).is_err());
     }
 
     #[test]
-    #[serial_test::serial]
-    fn test_image_store_root() {
+    fn test_execute_copy_step_with_workdir_and_chown() {
         let tmp = TempDir::new().unwrap();
-        std::env::set_var("CUBO_ROOT", tmp.path());
+        let context = tmp.path().join("context");
+        let rootfs = tmp.path().join("rootfs");
+
+        fs::create_dir_all(context.join("conf")).unwrap();
+        fs::write(context.join("conf/app.toml"), "port = 80").unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        let (uid, gid) = (nix::unistd::geteuid().as_raw(), nix::unistd::getegid().as_raw());
+        fs::write(rootfs.join("etc/passwd"), format!("app:x:{}:{}::/home/app:/bin/sh\n", uid, gid)).unwrap();
 
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
-        let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
+        let builder = ImageBuilder::new(&image_store, context);
+
+        let step = CopyStep {
+            src: "conf".to_string(),
+            dest: "conf".to_string(),
+            workdir: Some("/app/".to_string()),
+            chown: Some("app".to_string()),
+            chmod: Some("750".to_string()),
+        };
+        builder.execute_copy_step(&rootfs, &step).unwrap();
+        let meta = fs::metadata(rootfs.join("app/conf/app.toml")).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (uid, gid));
+        assert_eq!(meta.mode() & 0o7777, 0o750);
+        assert_eq!(fs::metadata(rootfs.join("app/conf")).unwrap().mode() & 0o7777, 0o750);
 
-        let root = builder.image_store_root();
-        assert_eq!(root, tmp.path().join("images"));
+        let step = CopyStep { chmod: Some("rwx".to_string()), ..step };
+        assert!(builder.execute_copy_step(&rootfs, &step).is_err());
 
-        std::env::remove_var("CUBO_ROOT");
+        let step = CopyStep { chown: Some("nobody".to_string()), ..step };
+        assert!(builder.execute_copy_step(&rootfs, &step).is_err());
     }
 
     #[test]
-    #[serial_test::serial]
-    fn test_image_store_root_default() {
-        std::env::remove_var("CUBO_ROOT");
-
+    fn test_execute_copy_stays_inside_rootfs() {
         let tmp = TempDir::new().unwrap();
+        let context = tmp.path().join("context");
+        let rootfs = tmp.path().join("rootfs");
+
+        fs::create_dir_all(context.join("conf")).unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(context.join("data.txt"), "some data").unwrap();
+        fs::write(context.join("conf/app.conf"), "x").unwrap();
+        // An image symlink to an absolute host path, and a source outside the context
+        std::os::unix::fs::symlink(tmp.path(), rootfs.join("escape")).unwrap();
+        fs::write(tmp.path().join("secret"), "host").unwrap();
+
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
-        let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
+        let builder = ImageBuilder::new(&image_store, context);
+
+        builder.execute_copy(&rootfs, "data.txt", "../../outside.txt", CopyAttrs::default()).unwrap();
+        assert!(rootfs.join("outside.txt").exists());
+        assert!(!tmp.path().join("outside.txt").exists());
 
-        let root = builder.image_store_root();
-        assert_eq!(root, PathBuf::from("/var/lib/cubo/images"));
+        let escape = format!("/escape{}", tmp.path().display());
+        builder.execute_copy(&rootfs, "conf", &escape, CopyAttrs::default()).unwrap();
+        assert!(!tmp.path().join("app.conf").exists());
+        assert!(secure_join(&rootfs, &escape).unwrap().join("app.conf").exists());
+
+        assert!(builder.execute_copy(&rootfs, "../secret", "/secret", CopyAttrs::default()).is_err());
+        assert!(!rootfs.join("secret").exists());
     }
 
     #[test]
-    #[serial_test::serial]
-    fn test_save_manifest() {
+    fn test_create_layer_tar() {
         let tmp = TempDir::new().unwrap();
-        std::env::set_var("CUBO_ROOT", tmp.path());
+        let rootfs = tmp.path().join("rootfs");
+        let output = tmp.path().join("layer.tar");
+
+        fs::create_dir_all(&rootfs).unwrap();
+        fs::write(rootfs.join("file.txt"), "content").unwrap();
+        fs::create_dir_all(rootfs.join("subdir")).unwrap();
+        fs::write(rootfs.join("subdir/nested.txt"), "nested content").unwrap();
 
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
 
-        let manifest = ImageManifest {
-            reference: "test:v1".to_string(),
-            layers: vec!["layer1.tar".to_string()],
-            config: ImageConfig {
-                cmd: Some(vec!["/bin/sh".to_string()]),
-                env: None,
-                working_dir: None,
-                exposed_ports: None,
-            },
-        };
-
-        let result = builder.save_manifest(&manifest);
+        let result = builder.create_layer_tar(&rootfs, &output);
         assert!(result.is_ok());
+        assert!(output.exists());
+        // Verify tar file is not empty
+        let metadata = fs::metadata(&output).unwrap();
+        assert!(metadata.len() > 0);
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_create_layer_tar_is_reproducible() {
+        let tmp = TempDir::new().unwrap();
+        let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
+        let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
 
-        let manifest_path = tmp.path().join("images/manifests/test_v1.json");
-        assert!(manifest_path.exists());
+        // Same content created in a different order, at different times
+        let first = tmp.path().join("first");
+        let second = tmp.path().join("second");
+        for (rootfs, names) in [(&first, ["a", "b", "c"]), (&second, ["c", "a", "b"])] {
+            fs::create_dir_all(rootfs.join("dir")).unwrap();
+            for name in names {
+                fs::write(rootfs.join("dir").join(name), name).unwrap();
+            }
+            fs::hard_link(rootfs.join("dir/a"), rootfs.join("a-link")).unwrap();
+            std::os::unix::fs::symlink("dir/b", rootfs.join("b-link")).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(1100));
+        }
 
-        std::env::remove_var("CUBO_ROOT");
+        std::env::set_var("SOURCE_DATE_EPOCH", "1700000000");
+        builder.create_layer_tar(&first, &tmp.path().join("first.tar")).unwrap();
+        builder.create_layer_tar(&second, &tmp.path().join("second.tar")).unwrap();
+        std::env::remove_var("SOURCE_DATE_EPOCH");
+        let first_tar = fs::read(tmp.path().join("first.tar")).unwrap();
+        assert_eq!(first_tar, fs::read(tmp.path().join("second.tar")).unwrap());
+
+        let mut archive = tar::Archive::new(first_tar.as_slice());
+        let entries: Vec<(String, u64, u64, tar::EntryType)> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                let h = e.header();
+                (e.path().unwrap().display().to_string(), h.mtime().unwrap(), h.uid().unwrap(), h.entry_type())
+            })
+            .collect();
+        let names: Vec<&str> = entries.iter().map(|e| e.0.as_str()).collect();
+        assert_eq!(names, ["a-link", "b-link", "dir", "dir/a", "dir/b", "dir/c"]);
+        assert!(entries.iter().all(|e| e.1 == 1700000000 && e.2 == 0));
+        assert_eq!(entries[3].3, tar::EntryType::Link);
+        assert_eq!(entries[1].3, tar::EntryType::Symlink);
     }
 
     #[test]
@@ -667,7 +1425,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
 
-        let result = builder.copy_dir_recursive(&src, &dest);
+        let result = builder.copy_dir_recursive(&src, tmp.path(), &dest.strip_prefix(tmp.path()).unwrap().to_string_lossy(), CopyAttrs::default());
         assert!(result.is_ok());
         assert!(dest.exists());
         assert!(dest.is_dir());
@@ -685,7 +1443,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = ImageBuilder::new(&image_store, tmp.path().to_path_buf());
 
-        let result = builder.copy_dir_recursive(&src, &dest);
+        let result = builder.copy_dir_recursive(&src, tmp.path(), &dest.strip_prefix(tmp.path()).unwrap().to_string_lossy(), CopyAttrs::default());
         assert!(result.is_ok());
         assert!(dest.join("a/b/c/d/e/deep.txt").exists());
         assert_eq!(fs::read_to_string(dest.join("a/b/c/d/e/deep.txt")).unwrap(), "deep content");
@@ -709,4 +1467,183 @@ mod tests {
         let err = result.unwrap_err();
         assert!(err.to_string().contains("BASE"));
     }
+
+    /// Store `base:1`, a single layer image with `etc/os`
+    fn save_base_image(image_store: &ImageStore, dir: &Path, labels: Option<BTreeMap<String, String>>) {
+        let layer = dir.join("base.tar");
+        let mut tar = tar::Builder::new(fs::File::create(&layer).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(3);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "etc/os", &b"os\n"[..]).unwrap();
+        tar.finish().unwrap();
+        image_store
+            .save_manifest(&ImageManifest {
+                reference: "base:1".to_string(),
+                parent: None,
+                layers: vec![layer.to_string_lossy().to_string()],
+                config: ImageConfig { cmd: None, env: None, working_dir: None, exposed_ports: None, stop_signal: None, labels },
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_build_writes_to_the_given_store() {
+        let tmp = TempDir::new().unwrap();
+        // CUBO_ROOT names another root: the build goes to the store the builder was given
+        std::env::set_var("CUBO_ROOT", tmp.path().join("elsewhere"));
+        let context = tmp.path().join("context");
+        fs::create_dir_all(&context).unwrap();
+        let image_store = ImageStore::new(tmp.path().join("store")).unwrap();
+        save_base_image(&image_store, tmp.path(), None);
+
+        let builder = ImageBuilder::new(&image_store, context);
+        let cubofile = Cubofile::from_string("BASE base:1\nENV A=1\n").unwrap();
+        builder.build(&cubofile, "app:1").await.unwrap();
+
+        let layers = image_store.get_manifest("app:1").unwrap().layers;
+        assert!(layers.iter().all(|layer| Path::new(layer).starts_with(tmp.path().join("store/blobs"))));
+        assert!(AttestationStore::new(image_store.root()).provenance("app:1").is_ok());
+        assert!(HistoryStore::new(image_store.root()).load("app:1", false).is_ok());
+        assert!(!tmp.path().join("elsewhere").exists());
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_build_sets_labels_and_annotations() {
+        let tmp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", tmp.path());
+        let context = tmp.path().join("context");
+        fs::create_dir_all(&context).unwrap();
+        let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
+        let base_labels = [("maintainer", "core"), ("team", "core")];
+        save_base_image(
+            &image_store,
+            tmp.path(),
+            Some(base_labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+        );
+
+        // Labels of the base image are inherited, LABEL overrides them and --label overrides both
+        let cubofile = Cubofile::from_string("BASE base:1\nLABEL team=payments description=\"web app\" version=1\n").unwrap();
+        let builder = ImageBuilder::new(&image_store, context)
+            .with_labels(BTreeMap::from([("version".to_string(), "2".to_string())]));
+        builder.build(&cubofile, "app:1").await.unwrap();
+
+        let manifest = ImageStore::new(tmp.path().join("images")).unwrap().get_manifest("app:1").unwrap();
+        let labels = manifest.config.labels.unwrap();
+        assert_eq!(labels["maintainer"], "core");
+        assert_eq!(labels["team"], "payments");
+        assert_eq!(labels["description"], "web app");
+        assert_eq!(labels["version"], "2");
+
+        let annotations = manifest.annotations.unwrap();
+        assert_eq!(annotations["org.opencontainers.image.base.name"], "base:1");
+        assert_eq!(annotations["org.opencontainers.image.base.digest"], image_store.resolve("base:1").unwrap());
+        assert!(DateTime::parse_from_rfc3339(&annotations["org.opencontainers.image.created"]).is_ok());
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_build_reports_json_progress() {
+        use crate::container::build_progress::SolveStatus;
+        use base64::Engine;
+        use std::sync::Mutex;
+
+        let tmp = TempDir::new().unwrap();
+        // The built image is stored under the root directory
+        std::env::set_var("CUBO_ROOT", tmp.path());
+        let context = tmp.path().join("context");
+        fs::create_dir_all(&context).unwrap();
+        fs::write(context.join("app.txt"), "hello").unwrap();
+        let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
+        save_base_image(&image_store, tmp.path(), None);
+
+        let statuses = Mutex::new(Vec::new());
+        let sink = |status: &SolveStatus| statuses.lock().unwrap().push(status.clone());
+        let builder = ImageBuilder::new(&image_store, context).with_progress(&sink);
+        let cubofile = Cubofile::from_string("ENV A=1\nBASE base:1\n# app\nCOPY app.txt /app.txt\n").unwrap();
+        assert_eq!(instruction_texts(&cubofile), ["BASE base:1", "ENV A=1", "COPY app.txt /app.txt"]);
+        builder.build(&cubofile, "app:1").await.unwrap();
+
+        let statuses = statuses.into_inner().unwrap();
+        let vertexes: Vec<_> = statuses.iter().flat_map(|s| &s.vertexes).collect();
+        let names: Vec<&str> = vertexes.iter().filter(|v| v.completed.is_some()).map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["[1/3] BASE base:1", "[2/3] ENV A=1", "[3/3] COPY app.txt /app.txt", "exporting to image"]);
+        assert!(vertexes.iter().all(|v| v.error.is_none() && !v.cached));
+
+        let export = &vertexes.last().unwrap().digest;
+        let log = statuses.iter().flat_map(|s| &s.logs).find(|l| &l.vertex == export).unwrap();
+        let text = String::from_utf8(base64::engine::general_purpose::STANDARD.decode(&log.data).unwrap()).unwrap();
+        let digest = ImageStore::new(tmp.path().join("images")).unwrap().resolve("app:1").unwrap();
+        assert_eq!(text, format!("writing image {}\nnaming to app:1\n", digest));
+
+        let history = HistoryStore::new(&tmp.path().join("images")).load("app:1", true).unwrap();
+        assert_eq!(history.digest, digest);
+        let steps: Vec<&str> = history.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(steps, names);
+        let completed = vertexes.iter().filter(|v| v.completed.is_some());
+        assert!(history.steps.iter().zip(completed).all(|(step, vertex)| step.digest == vertex.digest));
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[test]
+    fn test_wait_step_collects_output() {
+        let child = Command::new("sh").args(["-c", "echo out; echo err >&2"])
+            .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        let output = wait_step(child, Some(Duration::from_secs(30))).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn test_wait_step_kills_after_timeout() {
+        let child = Command::new("sh").args(["-c", "sleep 30 & wait"]).process_group(0)
+            .stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+        let started = Instant::now();
+        let err = wait_step(child, Some(Duration::from_millis(200))).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_provenance_instructions() {
+        let cubofile = Cubofile::from_string("BASE alpine:3.18\n# comment\nRUN apk add curl\nCMD [\"curl\"]\n").unwrap();
+        let instructions: Vec<String> = cubofile.instructions.iter().filter_map(instruction_text).collect();
+        assert_eq!(instructions, ["BASE alpine:3.18", "RUN apk add curl", "CMD [\"curl\"]"]);
+
+        let cubofile = Cubofile::from_string("COPY --chmod=0755 --chown=app run.sh /usr/bin/run\n").unwrap();
+        assert_eq!(instruction_text(&cubofile.instructions[0]).unwrap(), "COPY --chown=app --chmod=755 run.sh /usr/bin/run");
+
+        let cubofile = Cubofile::from_string("LABEL version=1 description=\"web app\" empty=\"\"\n").unwrap();
+        assert_eq!(
+            instruction_text(&cubofile.instructions[0]).unwrap(),
+            "LABEL version=1 description=\"web app\" empty=\"\""
+        );
+
+        let cubofile = CubofileToml::from_string(
+            "[image]\nbase = \"alpine\"\n\n[[run]]\ncommand = \"apk add curl\"\n\n[config]\nworkdir = \"/app\"\n",
+        )
+        .unwrap();
+        assert_eq!(toml_instructions(&cubofile), ["BASE alpine", "RUN apk add curl", "WORKDIR /app"]);
+
+        let cubofile = CubofileToml::from_string(
+            "[image]\nbase = \"alpine\"\n\n[[run]]\ncommand = \"make\"\nworkdir = \"/src\"\nuser = \"dev\"\nenv = { CC = \"clang\" }\n\n\
+             [[copy]]\nsrc = \"app\"\ndest = \"bin/\"\nworkdir = \"/opt\"\nchown = \"app:app\"\nchmod = \"0644\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            toml_instructions(&cubofile),
+            ["BASE alpine", "RUN --workdir=/src --user=dev --env=CC=clang make", "COPY --chown=app:app --chmod=644 --workdir=/opt app bin/"]
+        );
+
+        let cubofile = CubofileToml::from_string("[image]\nbase = \"alpine\"\n\n[config.labels]\nteam = \"payments\"\n").unwrap();
+        assert_eq!(toml_instructions(&cubofile), ["BASE alpine", "LABEL team=payments"]);
+    }
 }
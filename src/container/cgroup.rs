@@ -0,0 +1,1002 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::container::systemd::{self, PropertyValue, UnitProperty};
+use crate::container::{ContainerConfig, ThrottleDevice};
+use crate::error::{CuboError, Result};
+
+/// Mount point of the cgroup hierarchies
+pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Name of the parent cgroup that holds every container cgroup
+pub const CUBO_CGROUP_PARENT: &str = "cubo";
+
+/// Controllers cubo needs delegated to the container cgroups
+pub(crate) const CONTROLLERS: &[&str] = &["pids", "io", "memory", "cpu", "cpuset"];
+
+/// v1 hierarchies cubo applies limits on, each mounted at <root>/<controller>
+pub(crate) const V1_CONTROLLERS: &[&str] = &["memory", "cpu", "pids"];
+
+/// cpu.max period in microseconds
+pub const CPU_PERIOD: u64 = 100_000;
+
+/// Who creates the cgroups of containers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupDriver {
+    /// cubo writes the cgroup filesystem itself
+    #[default]
+    Cgroupfs,
+    /// Each container is a transient scope started over systemd's D-Bus API, so
+    /// `systemctl status` and systemd's accounting see it
+    Systemd,
+}
+
+impl std::str::FromStr for CgroupDriver {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cgroupfs" => Ok(CgroupDriver::Cgroupfs),
+            "systemd" => Ok(CgroupDriver::Systemd),
+            other => Err(format!("unknown cgroup driver '{}' (expected cgroupfs or systemd)", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for CgroupDriver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CgroupDriver::Cgroupfs => write!(f, "cgroupfs"),
+            CgroupDriver::Systemd => write!(f, "systemd"),
+        }
+    }
+}
+
+/// How the cgroup hierarchies are mounted at the cgroup root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupMode {
+    /// A single v2 hierarchy holding every controller
+    Unified,
+    /// v1 hierarchies for the controllers, and a v2 one without controllers at unified/
+    /// (systemd's default on older distros)
+    Hybrid,
+    /// v1 hierarchies only
+    Legacy,
+}
+
+impl CgroupMode {
+    /// Whether limits are written to the v1 controller hierarchies
+    pub fn is_v1(self) -> bool {
+        self != CgroupMode::Unified
+    }
+}
+
+impl std::fmt::Display for CgroupMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CgroupMode::Unified => write!(f, "v2"),
+            CgroupMode::Hybrid => write!(f, "v1 (hybrid)"),
+            CgroupMode::Legacy => write!(f, "v1"),
+        }
+    }
+}
+
+/// The mode of the hierarchies mounted at `root`: v2 has cgroup.controllers at its root,
+/// v1 a directory per controller with a tasks file. None when neither is mounted.
+pub fn detect_mode(root: &Path) -> Option<CgroupMode> {
+    if root.join("cgroup.controllers").exists() {
+        Some(CgroupMode::Unified)
+    } else if v1_controllers(root).is_empty() {
+        None
+    } else if root.join("unified/cgroup.controllers").exists() {
+        Some(CgroupMode::Hybrid)
+    } else {
+        Some(CgroupMode::Legacy)
+    }
+}
+
+/// The controllers cubo uses that have a v1 hierarchy at `root`
+pub fn v1_controllers(root: &Path) -> Vec<String> {
+    V1_CONTROLLERS
+        .iter()
+        .filter(|c| root.join(c).join("tasks").exists())
+        .map(|c| c.to_string())
+        .collect()
+}
+
+/// Manages the cgroup of a single container: <root>/cubo/<container_id> on cgroup v2, and
+/// <root>/<controller>/cubo/<container_id> in each controller hierarchy on cgroup v1
+#[derive(Debug, Clone)]
+pub struct CgroupManager {
+    root: PathBuf,
+    path: PathBuf,
+    mode: CgroupMode,
+    driver: CgroupDriver,
+    container_id: String,
+}
+
+impl CgroupManager {
+    /// The manager for the hierarchies mounted at `root`; v2 when nothing is mounted there yet
+    pub fn new(root: &Path, container_id: &str) -> Self {
+        let mode = detect_mode(root).unwrap_or(CgroupMode::Unified);
+        let path = match mode {
+            CgroupMode::Unified => root.join(CUBO_CGROUP_PARENT).join(container_id),
+            _ => root.join(V1_CONTROLLERS[0]).join(CUBO_CGROUP_PARENT).join(container_id),
+        };
+        Self { root: root.to_path_buf(), path, mode, driver: CgroupDriver::Cgroupfs, container_id: container_id.to_string() }
+    }
+
+    /// The manager for `driver`. The systemd driver needs systemd as init and cgroup v2;
+    /// without them the cgroupfs driver is used.
+    pub fn with_driver(root: &Path, driver: CgroupDriver, container_id: &str) -> Self {
+        let mut manager = Self::new(root, container_id);
+        if driver == CgroupDriver::Systemd && manager.mode == CgroupMode::Unified && systemd::booted() {
+            manager.path = root.join(systemd::scope_cgroup(container_id, nix::unistd::geteuid().as_raw()));
+            manager.driver = CgroupDriver::Systemd;
+        }
+        manager
+    }
+
+    /// The container's cgroup; on cgroup v1 the one in the memory hierarchy
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mode(&self) -> CgroupMode {
+        self.mode
+    }
+
+    pub fn driver(&self) -> CgroupDriver {
+        self.driver
+    }
+
+    /// Put the container's process `pid` in its cgroup. With the systemd driver this starts
+    /// the container's scope, holding the limits of `config`.
+    pub fn join(&self, pid: u32, config: &ContainerConfig) -> Result<()> {
+        match self.driver {
+            CgroupDriver::Cgroupfs => self.add_process(pid),
+            CgroupDriver::Systemd => {
+                systemd::start_scope(&systemd::scope_unit(&self.container_id), pid, &scope_properties(config)?)
+            }
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.dirs().iter().any(|dir| dir.exists())
+    }
+
+    /// The container's cgroup directories: one on v2, one per mounted controller on v1
+    fn dirs(&self) -> Vec<PathBuf> {
+        match self.mode {
+            CgroupMode::Unified => vec![self.path.clone()],
+            _ => v1_controllers(&self.root).iter().map(|c| self.v1_dir(c)).collect(),
+        }
+    }
+
+    fn v1_dir(&self, controller: &str) -> PathBuf {
+        self.root.join(controller).join(CUBO_CGROUP_PARENT).join(&self.container_id)
+    }
+
+    /// Create the container cgroup, enabling the controllers we need on the way down.
+    pub fn create(&self) -> Result<()> {
+        if self.mode.is_v1() {
+            return self.create_v1();
+        }
+        let parent = self.root.join(CUBO_CGROUP_PARENT);
+        fs::create_dir_all(&parent)
+            .map_err(|e| CuboError::CgroupError { path: parent.clone(), message: format!("Failed to create: {}", e) })?;
+
+        // Delegation is best effort: the controller may already be enabled, or the
+        // kernel may refuse it, in which case writing the limit below reports the error.
+        self.enable_controllers(&self.root);
+        self.enable_controllers(&parent);
+
+        fs::create_dir_all(&self.path)
+            .map_err(|e| CuboError::CgroupError { path: self.path.clone(), message: format!("Failed to create: {}", e) })?;
+
+        debug!("Created cgroup {:?}", self.path);
+        Ok(())
+    }
+
+    /// Create the container cgroup in every controller hierarchy that is mounted
+    fn create_v1(&self) -> Result<()> {
+        let dirs = self.dirs();
+        if dirs.is_empty() {
+            return Err(CuboError::CgroupError {
+                path: self.root.clone(),
+                message: format!("None of the {} hierarchies is mounted", V1_CONTROLLERS.join(", ")),
+            });
+        }
+        for dir in &dirs {
+            fs::create_dir_all(dir)
+                .map_err(|e| CuboError::CgroupError { path: dir.clone(), message: format!("Failed to create: {}", e) })?;
+        }
+        debug!("Created cgroup v1 {:?}", dirs);
+        Ok(())
+    }
+
+    /// Write the resource limits from the container config into the cgroup
+    pub fn apply(&self, config: &ContainerConfig) -> Result<()> {
+        if self.mode.is_v1() {
+            return self.apply_v1(config);
+        }
+        self.update(&ResourceUpdate {
+            memory_limit: config.memory_limit,
+            cpu_limit: config.cpu_limit,
+            pids_limit: config.pids_limit,
+        })?;
+        if let Some(swap) = config.memory_swap {
+            self.write("memory.swap.max", &format_swap_max(swap, config.memory_limit))?;
+        }
+        if let Some(reservation) = config.memory_reservation {
+            self.write("memory.low", &reservation.to_string())?;
+        }
+        if let Some(cpus) = &config.cpuset_cpus {
+            self.check_available("cpuset.cpus.effective", "CPUs", cpus)?;
+            self.write("cpuset.cpus", cpus)?;
+        }
+        if let Some(mems) = &config.cpuset_mems {
+            self.check_available("cpuset.mems.effective", "memory nodes", mems)?;
+            self.write("cpuset.mems", mems)?;
+        }
+        if let Some(weight) = config.blkio_weight {
+            self.write("io.weight", &format!("default {}", weight))?;
+        }
+        for device in &config.device_read_bps {
+            self.write("io.max", &format_io_max(device, "rbps")?)?;
+        }
+        for device in &config.device_write_bps {
+            self.write("io.max", &format_io_max(device, "wbps")?)?;
+        }
+        Ok(())
+    }
+
+    /// The limits of `apply` on the v1 memory, cpu and pids controllers. The cpuset and
+    /// I/O limits are only implemented on cgroup v2.
+    fn apply_v1(&self, config: &ContainerConfig) -> Result<()> {
+        let unsupported = [
+            ("--cpuset-cpus", config.cpuset_cpus.is_some()),
+            ("--cpuset-mems", config.cpuset_mems.is_some()),
+            ("--blkio-weight", config.blkio_weight.is_some()),
+            ("--device-read-bps", !config.device_read_bps.is_empty()),
+            ("--device-write-bps", !config.device_write_bps.is_empty()),
+        ];
+        if let Some((flag, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(CuboError::CgroupError {
+                path: self.root.clone(),
+                message: format!("{} needs cgroup v2, the host runs cgroup {}", flag, self.mode),
+            });
+        }
+
+        self.update(&ResourceUpdate {
+            memory_limit: config.memory_limit,
+            cpu_limit: config.cpu_limit,
+            pids_limit: config.pids_limit,
+        })?;
+        // memory.memsw.limit_in_bytes counts memory and swap like --memory-swap
+        if let Some(swap) = config.memory_swap {
+            self.write("memory.memsw.limit_in_bytes", &swap.to_string())?;
+        }
+        if let Some(reservation) = config.memory_reservation {
+            self.write("memory.soft_limit_in_bytes", &reservation.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the limits set in `update`, leaving the others alone
+    pub fn update(&self, update: &ResourceUpdate) -> Result<()> {
+        if self.driver == CgroupDriver::Systemd {
+            return systemd::set_unit_properties(&systemd::scope_unit(&self.container_id), &update_properties(update));
+        }
+        if self.mode.is_v1() {
+            return self.update_v1(update);
+        }
+        if let Some(limit) = update.memory_limit {
+            self.write("memory.max", &limit.to_string())?;
+        }
+        if let Some(cpus) = update.cpu_limit {
+            self.write("cpu.max", &format_cpu_max(cpus))?;
+        }
+        if let Some(limit) = update.pids_limit {
+            self.write("pids.max", &format_pids_max(limit))?;
+        }
+        Ok(())
+    }
+
+    fn update_v1(&self, update: &ResourceUpdate) -> Result<()> {
+        if let Some(limit) = update.memory_limit {
+            self.write("memory.limit_in_bytes", &limit.to_string())?;
+        }
+        if let Some(cpus) = update.cpu_limit {
+            self.write("cpu.cfs_period_us", &CPU_PERIOD.to_string())?;
+            self.write("cpu.cfs_quota_us", &format_cfs_quota(cpus))?;
+        }
+        if let Some(limit) = update.pids_limit {
+            self.write("pids.max", &format_pids_max(limit))?;
+        }
+        Ok(())
+    }
+
+    /// Refuse a cpuset list with CPUs or nodes outside those the parent cgroup has. The kernel
+    /// would only fail the write with EINVAL.
+    fn check_available(&self, file: &str, what: &str, requested: &str) -> Result<()> {
+        let Ok(available) = fs::read_to_string(self.root.join(CUBO_CGROUP_PARENT).join(file)) else {
+            return Ok(());
+        };
+        let (Ok(wanted), Ok(have)) = (parse_cpu_list(requested), parse_cpu_list(available.trim())) else {
+            return Ok(());
+        };
+        if !wanted.is_subset(&have) {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "Requested {} are not available: requested {}, available {}",
+                what,
+                requested,
+                available.trim()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Move a process into the cgroup, in every hierarchy on cgroup v1
+    pub fn add_process(&self, pid: u32) -> Result<()> {
+        for dir in self.dirs() {
+            write_file(&dir.join("cgroup.procs"), &pid.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Open the cgroup.procs files of the cgroup, one per hierarchy on cgroup v1. Writing
+    /// "0" to them moves the writer in, which a child can do between fork and exec.
+    pub fn open_procs(&self) -> Result<Vec<fs::File>> {
+        self.dirs()
+            .into_iter()
+            .map(|dir| dir.join("cgroup.procs"))
+            .map(|path| {
+                fs::OpenOptions::new().write(true).open(&path)
+                    .map_err(|e| CuboError::CgroupError { message: format!("Failed to open: {}", e), path })
+            })
+            .collect()
+    }
+
+    /// Remove the cgroup directories. The cgroup must not contain any process. systemd
+    /// removes a scope once it is empty, unless it still holds delegated child cgroups.
+    pub fn destroy(&self) -> Result<()> {
+        if self.driver == CgroupDriver::Systemd {
+            return match self.path.exists() {
+                true => systemd::stop_unit(&systemd::scope_unit(&self.container_id)),
+                false => Ok(()),
+            };
+        }
+        for dir in self.dirs().into_iter().filter(|dir| dir.exists()) {
+            fs::remove_dir(&dir)
+                .map_err(|e| CuboError::CgroupError { path: dir, message: format!("Failed to remove: {}", e) })?;
+        }
+        Ok(())
+    }
+
+    /// Whether a process is left in the cgroup or below it; false when there is no cgroup.
+    /// cgroup v1 has no cgroup.events, so only the cgroup's own processes count there.
+    pub fn is_populated(&self) -> bool {
+        if self.mode.is_v1() {
+            return self.dirs().iter().any(|dir| fs::read_to_string(dir.join("cgroup.procs")).is_ok_and(|procs| !procs.trim().is_empty()));
+        }
+        self.read("cgroup.events")
+            .map(|events| events.lines().any(|line| line == "populated 1"))
+            .unwrap_or(false)
+    }
+
+    pub fn read(&self, file: &str) -> Result<String> {
+        let path = self.file(file);
+        fs::read_to_string(&path)
+            .map(|s| s.trim().to_string())
+            .map_err(|e| CuboError::CgroupError { message: format!("Failed to read: {}", e), path })
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<()> {
+        write_file(&self.file(file), value)
+    }
+
+    /// Path of a cgroup file; on cgroup v1 in the hierarchy of the controller it is named after
+    fn file(&self, file: &str) -> PathBuf {
+        match file.split('.').next() {
+            Some(controller) if self.mode.is_v1() && V1_CONTROLLERS.contains(&controller) => self.v1_dir(controller).join(file),
+            _ => self.path.join(file),
+        }
+    }
+
+    fn enable_controllers(&self, dir: &Path) {
+        let subtree = dir.join("cgroup.subtree_control");
+        for controller in CONTROLLERS {
+            if let Err(e) = fs::write(&subtree, format!("+{}", controller)) {
+                warn!("Failed to enable {} controller in {:?}: {}", controller, dir, e);
+            }
+        }
+    }
+}
+
+fn write_file(path: &Path, value: &str) -> Result<()> {
+    fs::write(path, value)
+        .map_err(|e| CuboError::CgroupError { message: format!("Failed to write: {}", e), path: path.to_path_buf() })
+}
+
+/// Limits changed by `cubo update`; unset fields keep their current value
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceUpdate {
+    pub memory_limit: Option<u64>,
+    pub cpu_limit: Option<f32>,
+    pub pids_limit: Option<i64>,
+}
+
+impl ResourceUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.memory_limit.is_none() && self.cpu_limit.is_none() && self.pids_limit.is_none()
+    }
+
+    /// Record the new limits in a container config
+    pub fn apply_to(&self, config: &mut ContainerConfig) {
+        let mut set = Vec::new();
+        if let Some(limit) = self.memory_limit {
+            config.memory_limit = Some(limit);
+            set.push("memory");
+        }
+        if let Some(cpus) = self.cpu_limit {
+            config.cpu_limit = Some(cpus);
+            set.push("cpus");
+        }
+        if let Some(limit) = self.pids_limit {
+            config.pids_limit = Some(limit);
+            set.push("pids_limit");
+        }
+        // An updated limit is the container's own from now on
+        config.inherited_limits.retain(|limit| !set.contains(&limit.as_str()));
+    }
+}
+
+/// Returns true if the config asks for a limit only a cgroup can enforce
+pub fn has_limits(config: &ContainerConfig) -> bool {
+    config.memory_limit.is_some()
+        || config.memory_swap.is_some()
+        || config.memory_reservation.is_some()
+        || config.cpu_limit.is_some()
+        || config.pids_limit.is_some()
+        || config.cpuset_cpus.is_some()
+        || config.cpuset_mems.is_some()
+        || config.blkio_weight.is_some()
+        || !config.device_read_bps.is_empty()
+        || !config.device_write_bps.is_empty()
+}
+
+/// The numbers of a cpuset list like "0-3,6": comma-separated numbers and inclusive ranges
+pub fn parse_cpu_list(list: &str) -> std::result::Result<BTreeSet<u32>, String> {
+    let invalid = || format!("Invalid cpuset list '{}', expected numbers and ranges like 0-3,6", list);
+    let mut numbers = BTreeSet::new();
+    for part in list.split(',') {
+        let (first, last) = part.split_once('-').unwrap_or((part, part));
+        let first: u32 = first.parse().map_err(|_| invalid())?;
+        let last: u32 = last.parse().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+        numbers.extend(first..=last);
+    }
+    Ok(numbers)
+}
+
+/// memory.swap.max only counts swap, docker's --memory-swap counts memory and swap: the
+/// difference, or "max" for -1
+fn format_swap_max(memory_swap: i64, memory_limit: Option<u64>) -> String {
+    match u64::try_from(memory_swap) {
+        Ok(total) => total.saturating_sub(memory_limit.unwrap_or(0)).to_string(),
+        Err(_) => "max".to_string(),
+    }
+}
+
+/// pids.max takes "max" for unlimited; zero or negative limits mean unlimited.
+fn format_pids_max(limit: i64) -> String {
+    if limit <= 0 {
+        "max".to_string()
+    } else {
+        limit.to_string()
+    }
+}
+
+/// cpu.max is "<quota> <period>"; a quota of zero or less means unlimited
+fn format_cpu_max(cpus: f32) -> String {
+    if cpus <= 0.0 {
+        format!("max {}", CPU_PERIOD)
+    } else {
+        format!("{} {}", (cpus as f64 * CPU_PERIOD as f64).round() as u64, CPU_PERIOD)
+    }
+}
+
+/// systemd's value for no limit ("infinity")
+const INFINITY: u64 = u64::MAX;
+
+/// The scope properties of the limits in `update`
+fn update_properties(update: &ResourceUpdate) -> Vec<UnitProperty> {
+    let mut properties = Vec::new();
+    if let Some(limit) = update.memory_limit {
+        properties.push(UnitProperty::new("MemoryMax", PropertyValue::U64(limit)));
+    }
+    if let Some(cpus) = update.cpu_limit {
+        let quota = match cpus <= 0.0 {
+            true => INFINITY,
+            false => (cpus as f64 * 1_000_000.0).round() as u64,
+        };
+        properties.push(UnitProperty::new("CPUQuotaPerSecUSec", PropertyValue::U64(quota)));
+    }
+    if let Some(limit) = update.pids_limit {
+        let max = u64::try_from(limit).ok().filter(|l| *l > 0).unwrap_or(INFINITY);
+        properties.push(UnitProperty::new("TasksMax", PropertyValue::U64(max)));
+    }
+    properties
+}
+
+/// The scope properties of every limit `apply` writes, as systemd sets them on the
+/// scope's cgroup
+fn scope_properties(config: &ContainerConfig) -> Result<Vec<UnitProperty>> {
+    let mut properties = update_properties(&ResourceUpdate {
+        memory_limit: config.memory_limit,
+        cpu_limit: config.cpu_limit,
+        pids_limit: config.pids_limit,
+    });
+    if let Some(swap) = config.memory_swap {
+        let max = match swap {
+            -1 => INFINITY,
+            _ => format_swap_max(swap, config.memory_limit).parse().unwrap_or(0),
+        };
+        properties.push(UnitProperty::new("MemorySwapMax", PropertyValue::U64(max)));
+    }
+    if let Some(reservation) = config.memory_reservation {
+        properties.push(UnitProperty::new("MemoryLow", PropertyValue::U64(reservation)));
+    }
+    for (name, list) in [("AllowedCPUs", &config.cpuset_cpus), ("AllowedMemoryNodes", &config.cpuset_mems)] {
+        if let Some(list) = list {
+            let numbers = parse_cpu_list(list).map_err(CuboError::InvalidConfiguration)?;
+            properties.push(UnitProperty::new(name, PropertyValue::Bytes(cpu_mask(&numbers))));
+        }
+    }
+    if let Some(weight) = config.blkio_weight {
+        properties.push(UnitProperty::new("IOWeight", PropertyValue::U64(weight as u64)));
+    }
+    for (name, devices) in [("IOReadBandwidthMax", &config.device_read_bps), ("IOWriteBandwidthMax", &config.device_write_bps)] {
+        if !devices.is_empty() {
+            let limits = devices.iter().map(|d| (d.path.clone(), d.rate)).collect();
+            properties.push(UnitProperty::new(name, PropertyValue::DeviceLimits(limits)));
+        }
+    }
+    Ok(properties)
+}
+
+/// A CPU or node list as the bitmask systemd takes, lowest numbers in the first byte
+fn cpu_mask(numbers: &BTreeSet<u32>) -> Vec<u8> {
+    let mut mask = vec![0u8; numbers.last().map_or(0, |n| *n as usize / 8 + 1)];
+    for n in numbers {
+        mask[*n as usize / 8] |= 1 << (n % 8);
+    }
+    mask
+}
+
+/// cpu.cfs_quota_us is the quota per CPU_PERIOD in microseconds, -1 for unlimited
+fn format_cfs_quota(cpus: f32) -> String {
+    if cpus <= 0.0 {
+        "-1".to_string()
+    } else {
+        ((cpus as f64 * CPU_PERIOD as f64).round() as u64).to_string()
+    }
+}
+
+/// io.max is keyed by the device number: "MAJ:MIN rbps=N"
+fn format_io_max(device: &ThrottleDevice, key: &str) -> Result<String> {
+    let (major, minor) = block_device_number(Path::new(&device.path))?;
+    Ok(format!("{}:{} {}={}", major, minor, key, device.rate))
+}
+
+/// Resolve the major and minor numbers of a block device
+pub fn block_device_number(path: &Path) -> Result<(u64, u64)> {
+    use nix::sys::stat::{stat, SFlag};
+
+    let st = stat(path)
+        .map_err(|e| CuboError::CgroupError { path: path.to_path_buf(), message: format!("Failed to stat device: {}", e) })?;
+
+    if SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+        return Err(CuboError::CgroupError { path: path.to_path_buf(), message: "not a block device".to_string() });
+    }
+
+    Ok((libc::major(st.st_rdev) as u64, libc::minor(st.st_rdev) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_cgroup_path() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("cgroup.controllers"), "").unwrap();
+        let manager = CgroupManager::new(temp.path(), "abc123");
+        assert_eq!(manager.mode(), CgroupMode::Unified);
+        assert_eq!(manager.path(), temp.path().join("cubo/abc123"));
+    }
+
+    #[test]
+    fn test_create_and_destroy() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "test-container");
+
+        manager.create().unwrap();
+        assert!(manager.exists());
+
+        manager.destroy().unwrap();
+        assert!(!manager.exists());
+    }
+
+    #[test]
+    fn test_is_populated() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "test-container");
+        assert!(!manager.is_populated());
+
+        manager.create().unwrap();
+        fs::write(manager.path().join("cgroup.events"), "populated 1\nfrozen 0\n").unwrap();
+        assert!(manager.is_populated());
+        fs::write(manager.path().join("cgroup.events"), "populated 0\nfrozen 0\n").unwrap();
+        assert!(!manager.is_populated());
+    }
+
+    #[test]
+    fn test_destroy_missing_cgroup_is_ok() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "missing");
+        assert!(manager.destroy().is_ok());
+    }
+
+    #[test]
+    fn test_apply_pids_limit() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "pids");
+        manager.create().unwrap();
+
+        let config = ContainerConfig {
+            pids_limit: Some(100),
+            ..Default::default()
+        };
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("pids.max").unwrap(), "100");
+    }
+
+    #[test]
+    fn test_apply_unlimited_pids() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "pids");
+        manager.create().unwrap();
+
+        let config = ContainerConfig {
+            pids_limit: Some(-1),
+            ..Default::default()
+        };
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("pids.max").unwrap(), "max");
+    }
+
+    #[test]
+    fn test_apply_without_limits_writes_nothing() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "none");
+        manager.create().unwrap();
+
+        manager.apply(&ContainerConfig::default()).unwrap();
+        assert!(!manager.path().join("pids.max").exists());
+    }
+
+    #[test]
+    fn test_add_process() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "procs");
+        manager.create().unwrap();
+
+        manager.add_process(4242).unwrap();
+        assert_eq!(manager.read("cgroup.procs").unwrap(), "4242");
+    }
+
+    #[test]
+    fn test_apply_blkio_weight() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "io");
+        manager.create().unwrap();
+
+        let config = ContainerConfig {
+            blkio_weight: Some(500),
+            ..Default::default()
+        };
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("io.weight").unwrap(), "default 500");
+    }
+
+    #[test]
+    fn test_apply_throttle_rejects_non_block_device() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "io");
+        manager.create().unwrap();
+
+        let not_a_device = temp.path().join("file");
+        fs::write(&not_a_device, "").unwrap();
+        let config = ContainerConfig {
+            device_read_bps: vec![ThrottleDevice {
+                path: not_a_device.to_string_lossy().to_string(),
+                rate: 1024,
+            }],
+            ..Default::default()
+        };
+        let err = manager.apply(&config).unwrap_err();
+        assert!(err.to_string().contains("not a block device"));
+    }
+
+    #[test]
+    fn test_block_device_number_missing_device() {
+        assert!(block_device_number(Path::new("/nonexistent/device")).is_err());
+    }
+
+    #[test]
+    fn test_has_limits() {
+        assert!(!has_limits(&ContainerConfig::default()));
+        let config = ContainerConfig {
+            pids_limit: Some(10),
+            ..Default::default()
+        };
+        assert!(has_limits(&config));
+        let config = ContainerConfig {
+            blkio_weight: Some(100),
+            ..Default::default()
+        };
+        assert!(has_limits(&config));
+    }
+
+    #[test]
+    fn test_apply_memory_and_cpu() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "mem");
+        manager.create().unwrap();
+
+        let config = ContainerConfig {
+            memory_limit: Some(256 * 1024 * 1024),
+            cpu_limit: Some(1.5),
+            ..Default::default()
+        };
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("memory.max").unwrap(), "268435456");
+        assert_eq!(manager.read("cpu.max").unwrap(), "150000 100000");
+    }
+
+    #[test]
+    fn test_apply_memory_swap_and_reservation() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "swap");
+        manager.create().unwrap();
+
+        let config = ContainerConfig {
+            memory_limit: Some(512),
+            memory_swap: Some(1024),
+            memory_reservation: Some(256),
+            ..Default::default()
+        };
+        assert!(has_limits(&ContainerConfig { memory_reservation: Some(256), ..Default::default() }));
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("memory.max").unwrap(), "512");
+        assert_eq!(manager.read("memory.swap.max").unwrap(), "512");
+        assert_eq!(manager.read("memory.low").unwrap(), "256");
+    }
+
+    #[test]
+    fn test_format_swap_max() {
+        assert_eq!(format_swap_max(1024, Some(1024)), "0");
+        assert_eq!(format_swap_max(3072, Some(1024)), "2048");
+        assert_eq!(format_swap_max(-1, Some(1024)), "max");
+    }
+
+    #[test]
+    fn test_apply_cpuset() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "pinned");
+        manager.create().unwrap();
+        fs::write(temp.path().join(CUBO_CGROUP_PARENT).join("cpuset.cpus.effective"), "0-3\n").unwrap();
+
+        let config = ContainerConfig {
+            cpuset_cpus: Some("0-1,3".to_string()),
+            cpuset_mems: Some("0".to_string()),
+            ..Default::default()
+        };
+        assert!(has_limits(&config));
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("cpuset.cpus").unwrap(), "0-1,3");
+        assert_eq!(manager.read("cpuset.mems").unwrap(), "0");
+
+        let config = ContainerConfig { cpuset_cpus: Some("2-5".to_string()), ..Default::default() };
+        let err = manager.apply(&config).unwrap_err();
+        assert!(err.to_string().contains("requested 2-5, available 0-3"));
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,6").unwrap(), BTreeSet::from([0, 1, 2, 3, 6]));
+        assert_eq!(parse_cpu_list("5").unwrap(), BTreeSet::from([5]));
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("0-a").is_err());
+        assert!(parse_cpu_list("0,,1").is_err());
+    }
+
+    #[test]
+    fn test_update_only_writes_given_limits() {
+        let temp = TempDir::new().unwrap();
+        let manager = CgroupManager::new(temp.path(), "update");
+        manager.create().unwrap();
+
+        manager.update(&ResourceUpdate { pids_limit: Some(64), ..Default::default() }).unwrap();
+        assert_eq!(manager.read("pids.max").unwrap(), "64");
+        assert!(!manager.path().join("memory.max").exists());
+        assert!(!manager.path().join("cpu.max").exists());
+    }
+
+    #[test]
+    fn test_resource_update_apply_to() {
+        let mut config = ContainerConfig {
+            memory_limit: Some(1024),
+            pids_limit: Some(10),
+            inherited_limits: vec!["memory".to_string(), "pids_limit".to_string()],
+            ..Default::default()
+        };
+        let update = ResourceUpdate { cpu_limit: Some(2.0), pids_limit: Some(20), ..Default::default() };
+        assert!(!update.is_empty());
+        assert!(ResourceUpdate::default().is_empty());
+
+        update.apply_to(&mut config);
+        assert_eq!(config.memory_limit, Some(1024));
+        assert_eq!(config.cpu_limit, Some(2.0));
+        assert_eq!(config.pids_limit, Some(20));
+        assert_eq!(config.inherited_limits, ["memory"]);
+    }
+
+    #[test]
+    fn test_format_cpu_max() {
+        assert_eq!(format_cpu_max(0.5), "50000 100000");
+        assert_eq!(format_cpu_max(2.0), "200000 100000");
+        assert_eq!(format_cpu_max(0.0), "max 100000");
+    }
+
+    /// A cgroup root with the v1 hierarchies of `controllers`
+    fn v1_root(controllers: &[&str]) -> TempDir {
+        let temp = TempDir::new().unwrap();
+        for controller in controllers {
+            fs::create_dir(temp.path().join(controller)).unwrap();
+            fs::write(temp.path().join(controller).join("tasks"), "").unwrap();
+        }
+        temp
+    }
+
+    #[test]
+    fn test_detect_mode() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(detect_mode(temp.path()), None);
+        fs::create_dir(temp.path().join(CUBO_CGROUP_PARENT)).unwrap();
+        assert_eq!(detect_mode(temp.path()), None);
+        fs::write(temp.path().join("cgroup.controllers"), "cpu memory\n").unwrap();
+        assert_eq!(detect_mode(temp.path()), Some(CgroupMode::Unified));
+
+        let temp = v1_root(&["memory", "pids"]);
+        assert_eq!(detect_mode(temp.path()), Some(CgroupMode::Legacy));
+        assert_eq!(v1_controllers(temp.path()), ["memory", "pids"]);
+        fs::create_dir(temp.path().join("unified")).unwrap();
+        fs::write(temp.path().join("unified/cgroup.controllers"), "").unwrap();
+        assert_eq!(detect_mode(temp.path()), Some(CgroupMode::Hybrid));
+        assert!(CgroupMode::Hybrid.is_v1() && !CgroupMode::Unified.is_v1());
+    }
+
+    #[test]
+    fn test_v1_create_apply_and_destroy() {
+        let temp = v1_root(&["memory", "cpu", "pids"]);
+        let manager = CgroupManager::new(temp.path(), "legacy");
+        assert_eq!(manager.mode(), CgroupMode::Legacy);
+        assert_eq!(manager.path(), temp.path().join("memory/cubo/legacy"));
+        manager.create().unwrap();
+        assert!(temp.path().join("cpu/cubo/legacy").is_dir());
+
+        let config = ContainerConfig {
+            memory_limit: Some(512),
+            memory_swap: Some(1024),
+            cpu_limit: Some(1.5),
+            pids_limit: Some(-1),
+            ..Default::default()
+        };
+        manager.apply(&config).unwrap();
+        assert_eq!(manager.read("memory.limit_in_bytes").unwrap(), "512");
+        assert_eq!(manager.read("memory.memsw.limit_in_bytes").unwrap(), "1024");
+        assert_eq!(fs::read_to_string(temp.path().join("cpu/cubo/legacy/cpu.cfs_quota_us")).unwrap(), "150000");
+        assert_eq!(fs::read_to_string(temp.path().join("pids/cubo/legacy/pids.max")).unwrap(), "max");
+
+        let config = ContainerConfig { cpuset_cpus: Some("0".to_string()), ..Default::default() };
+        assert!(manager.apply(&config).unwrap_err().to_string().contains("--cpuset-cpus needs cgroup v2"));
+
+        assert!(!manager.is_populated());
+        manager.add_process(4242).unwrap();
+        assert!(manager.is_populated());
+        assert_eq!(fs::read_to_string(temp.path().join("pids/cubo/legacy/cgroup.procs")).unwrap(), "4242");
+
+        // A plain directory keeps the files the kernel would drop with the cgroup
+        for controller in ["memory", "cpu", "pids"] {
+            let dir = temp.path().join(controller).join("cubo/legacy");
+            for entry in fs::read_dir(&dir).unwrap() {
+                fs::remove_file(entry.unwrap().path()).unwrap();
+            }
+        }
+        manager.destroy().unwrap();
+        assert!(!manager.exists());
+    }
+
+    #[test]
+    fn test_v1_without_hierarchies() {
+        let temp = v1_root(&["memory"]);
+        let manager = CgroupManager::new(temp.path(), "c");
+        fs::remove_file(temp.path().join("memory/tasks")).unwrap();
+        assert!(manager.create().is_err());
+    }
+
+    #[test]
+    fn test_cgroup_driver() {
+        assert_eq!("systemd".parse::<CgroupDriver>().unwrap(), CgroupDriver::Systemd);
+        assert_eq!(CgroupDriver::default().to_string(), "cgroupfs");
+        assert!("cgroupv2".parse::<CgroupDriver>().is_err());
+
+        // Without a v2 hierarchy the systemd driver falls back to cgroupfs
+        let temp = v1_root(&["memory"]);
+        let manager = CgroupManager::with_driver(temp.path(), CgroupDriver::Systemd, "c");
+        assert_eq!(manager.driver(), CgroupDriver::Cgroupfs);
+    }
+
+    #[test]
+    fn test_scope_properties() {
+        let config = ContainerConfig {
+            memory_limit: Some(512),
+            memory_swap: Some(-1),
+            cpu_limit: Some(0.5),
+            pids_limit: Some(0),
+            cpuset_cpus: Some("0-1,9".to_string()),
+            blkio_weight: Some(300),
+            device_write_bps: vec![ThrottleDevice { path: "/dev/sda".to_string(), rate: 1024 }],
+            ..Default::default()
+        };
+        let properties = scope_properties(&config).unwrap();
+        let value = |name: &str| properties.iter().find(|p| p.name == name).map(|p| p.value.clone());
+        assert_eq!(value("MemoryMax"), Some(PropertyValue::U64(512)));
+        assert_eq!(value("MemorySwapMax"), Some(PropertyValue::U64(INFINITY)));
+        assert_eq!(value("CPUQuotaPerSecUSec"), Some(PropertyValue::U64(500_000)));
+        assert_eq!(value("TasksMax"), Some(PropertyValue::U64(INFINITY)));
+        assert_eq!(value("AllowedCPUs"), Some(PropertyValue::Bytes(vec![0b11, 0b10])));
+        assert_eq!(value("IOWeight"), Some(PropertyValue::U64(300)));
+        assert_eq!(value("IOWriteBandwidthMax"), Some(PropertyValue::DeviceLimits(vec![("/dev/sda".to_string(), 1024)])));
+        assert_eq!(value("MemoryLow"), None);
+
+        assert!(update_properties(&ResourceUpdate::default()).is_empty());
+        let config = ContainerConfig { cpuset_mems: Some("x".to_string()), ..Default::default() };
+        assert!(scope_properties(&config).is_err());
+    }
+
+    #[test]
+    fn test_format_cfs_quota() {
+        assert_eq!(format_cfs_quota(0.5), "50000");
+        assert_eq!(format_cfs_quota(0.0), "-1");
+    }
+
+    #[test]
+    fn test_format_pids_max() {
+        assert_eq!(format_pids_max(512), "512");
+        assert_eq!(format_pids_max(0), "max");
+        assert_eq!(format_pids_max(-1), "max");
+    }
+}
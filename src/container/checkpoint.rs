@@ -0,0 +1,206 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::debug;
+
+use crate::container::rootless_net::find_in_path;
+use crate::error::{CuboError, Result};
+
+/// Directory of the container bundle holding the CRIU images
+pub const CHECKPOINT_DIR: &str = "checkpoint";
+
+const DUMP_LOG: &str = "dump.log";
+const RESTORE_LOG: &str = "restore.log";
+const RESTORE_PIDFILE: &str = "restore.pid";
+
+// Options shared by dump and restore so both sides agree on the process state
+const COMMON_OPTS: &[&str] = &[
+    "--tcp-established",
+    "--ext-unix-sk",
+    "--file-locks",
+    "--manage-cgroups",
+    "--ext-mount-map",
+    "auto",
+];
+
+pub fn checkpoint_dir(bundle: &Path) -> PathBuf {
+    bundle.join(CHECKPOINT_DIR)
+}
+
+/// True if the bundle holds a complete dump (CRIU writes inventory.img last)
+pub fn has_checkpoint(bundle: &Path) -> bool {
+    checkpoint_dir(bundle).join("inventory.img").exists()
+}
+
+pub fn find_criu() -> Result<PathBuf> {
+    let path = std::env::var("PATH").unwrap_or_default();
+    find_in_path("criu", &path)
+        .ok_or_else(|| CuboError::SystemError("criu not found in PATH; install CRIU to checkpoint containers".to_string()))
+}
+
+/// Pids listed in /proc/<pid>/task/<tid>/children
+pub fn parse_children(content: &str) -> Vec<u32> {
+    content.split_whitespace().filter_map(|pid| pid.parse().ok()).collect()
+}
+
+/// The container init: the process cubo forked into the new pid namespace, below the
+/// recorded container pid
+pub fn container_init_pid(pid: u32) -> Result<u32> {
+    let path = format!("/proc/{}/task/{}/children", pid, pid);
+    let content = fs::read_to_string(&path)
+        .map_err(|e| CuboError::ProcessError(format!("Failed to read {}: {}", path, e)))?;
+    parse_children(&content)
+        .first()
+        .copied()
+        .ok_or_else(|| CuboError::ProcessError(format!("Process {} has no container init", pid)))
+}
+
+pub fn dump_args(init_pid: u32, images_dir: &Path, leave_running: bool) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "dump".into(),
+        "--tree".into(),
+        init_pid.to_string(),
+        "--images-dir".into(),
+        images_dir.to_string_lossy().into_owned(),
+        "--log-file".into(),
+        DUMP_LOG.into(),
+    ];
+    args.extend(COMMON_OPTS.iter().map(|s| s.to_string()));
+    if leave_running {
+        args.push("--leave-running".into());
+    }
+    args
+}
+
+/// `veth_pairs` maps each interface inside the container to its host side veth
+pub fn restore_args(images_dir: &Path, rootfs: &Path, veth_pairs: &[(String, String)]) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "restore".into(),
+        "--images-dir".into(),
+        images_dir.to_string_lossy().into_owned(),
+        "--log-file".into(),
+        RESTORE_LOG.into(),
+        "--restore-detached".into(),
+        "--pidfile".into(),
+        images_dir.join(RESTORE_PIDFILE).to_string_lossy().into_owned(),
+        "--root".into(),
+        rootfs.to_string_lossy().into_owned(),
+    ];
+    args.extend(COMMON_OPTS.iter().map(|s| s.to_string()));
+    for (inside, host) in veth_pairs {
+        args.push("--veth-pair".into());
+        args.push(format!("{}={}", inside, host));
+    }
+    args
+}
+
+/// Dump the process tree of `init_pid` into the bundle, replacing any earlier checkpoint.
+/// Unless `leave_running`, CRIU kills the tree once the dump is complete.
+pub fn dump(criu: &Path, bundle: &Path, init_pid: u32, leave_running: bool) -> Result<()> {
+    let images_dir = checkpoint_dir(bundle);
+    if images_dir.exists() {
+        fs::remove_dir_all(&images_dir)
+            .map_err(|e| CuboError::SystemError(format!("Failed to clear {:?}: {}", images_dir, e)))?;
+    }
+    fs::create_dir_all(&images_dir)
+        .map_err(|e| CuboError::SystemError(format!("Failed to create {:?}: {}", images_dir, e)))?;
+
+    run_criu(criu, &dump_args(init_pid, &images_dir, leave_running), &images_dir.join(DUMP_LOG))
+}
+
+/// Restore the checkpoint of the bundle and return the pid of the restored container init
+pub fn restore(criu: &Path, bundle: &Path, veth_pairs: &[(String, String)]) -> Result<u32> {
+    let images_dir = checkpoint_dir(bundle);
+    let pidfile = images_dir.join(RESTORE_PIDFILE);
+    let _ = fs::remove_file(&pidfile);
+
+    let rootfs = bundle.join("rootfs");
+    run_criu(criu, &restore_args(&images_dir, &rootfs, veth_pairs), &images_dir.join(RESTORE_LOG))?;
+
+    fs::read_to_string(&pidfile)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok())
+        .ok_or_else(|| CuboError::ProcessError(format!("criu did not write a valid pid to {:?}", pidfile)))
+}
+
+fn run_criu(criu: &Path, args: &[String], log: &Path) -> Result<()> {
+    debug!("{} {}", criu.display(), args.join(" "));
+    let output = Command::new(criu)
+        .args(args)
+        .output()
+        .map_err(|e| CuboError::SystemError(format!("Failed to run {}: {}", criu.display(), e)))?;
+    if !output.status.success() {
+        return Err(CuboError::SystemError(format!(
+            "criu {} failed (see {}): {}",
+            args[0],
+            log.display(),
+            last_error_line(&fs::read_to_string(log).unwrap_or_default())
+                .unwrap_or_else(|| String::from_utf8_lossy(&output.stderr).trim().to_string())
+        )));
+    }
+    Ok(())
+}
+
+/// The last "Error" line of a CRIU log, which usually names the cause
+fn last_error_line(log: &str) -> Option<String> {
+    log.lines().rev().find(|line| line.contains("Error")).map(|line| line.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_children() {
+        assert_eq!(parse_children("1234 1240 \n"), vec![1234, 1240]);
+        assert!(parse_children("").is_empty());
+    }
+
+    #[test]
+    fn test_dump_args() {
+        let args = dump_args(42, Path::new("/var/lib/cubo/c1/checkpoint"), false);
+        assert_eq!(&args[..5], ["dump", "--tree", "42", "--images-dir", "/var/lib/cubo/c1/checkpoint"]);
+        assert!(args.contains(&"--tcp-established".to_string()));
+        assert!(!args.contains(&"--leave-running".to_string()));
+
+        let args = dump_args(42, Path::new("/tmp/cp"), true);
+        assert_eq!(args.last().unwrap(), "--leave-running");
+    }
+
+    #[test]
+    fn test_restore_args() {
+        let pairs = vec![("eth0".to_string(), "vcabc123net456".to_string())];
+        let args = restore_args(Path::new("/b/checkpoint"), Path::new("/b/rootfs"), &pairs);
+        assert_eq!(args[0], "restore");
+        assert!(args.windows(2).any(|w| w == ["--root", "/b/rootfs"]));
+        assert!(args.windows(2).any(|w| w == ["--pidfile", "/b/checkpoint/restore.pid"]));
+        assert!(args.windows(2).any(|w| w == ["--veth-pair", "eth0=vcabc123net456"]));
+        assert!(args.contains(&"--restore-detached".to_string()));
+    }
+
+    #[test]
+    fn test_has_checkpoint() {
+        let temp = TempDir::new().unwrap();
+        assert!(!has_checkpoint(temp.path()));
+
+        fs::create_dir_all(checkpoint_dir(temp.path())).unwrap();
+        assert!(!has_checkpoint(temp.path()));
+
+        fs::write(checkpoint_dir(temp.path()).join("inventory.img"), b"").unwrap();
+        assert!(has_checkpoint(temp.path()));
+    }
+
+    #[test]
+    fn test_last_error_line() {
+        let log = "(00.001) Dumping\n(00.002) Error (criu/cr-dump.c:1): first\n(00.003) Error (criu/tun.c:2): last\n(00.004) Done\n";
+        assert_eq!(last_error_line(log).unwrap(), "(00.003) Error (criu/tun.c:2): last");
+        assert!(last_error_line("all good").is_none());
+    }
+
+    #[test]
+    fn test_container_init_pid_missing_process() {
+        assert!(container_init_pid(u32::MAX).is_err());
+    }
+}
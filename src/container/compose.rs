@@ -0,0 +1,392 @@
+//! `cubo-compose.toml`: services started together on a shared network, in dependency order.
+//! The containers and networks `cubo compose up` created are recorded per project so
+//! `down`, `logs` and `ps` find them again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::container_store::{atomic_write_json, read_json};
+use crate::error::{CuboError, Result};
+
+/// File `cubo compose` reads when no `--file` is given
+pub const COMPOSE_FILE: &str = "cubo-compose.toml";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ComposeFile {
+    /// Project name (the name of the file's directory if unset)
+    pub name: Option<String>,
+    /// Services keyed by name
+    #[serde(default)]
+    pub services: BTreeMap<String, Service>,
+    /// User-defined networks services can join, keyed by name
+    #[serde(default)]
+    pub networks: BTreeMap<String, NetworkSpec>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Service {
+    /// Image to run, or the tag to build `build` as
+    pub image: Option<String>,
+    /// Build context to build the image from
+    pub build: Option<BuildSpec>,
+    /// Command (the image's CMD if unset)
+    pub command: Option<Vec<String>>,
+    /// Working directory
+    pub workdir: Option<String>,
+    /// Environment variables
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Published ports, as with `cubo run -p`
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// Volumes, as with `cubo run -v`; relative host paths are relative to the file
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    /// Services started before this one
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Networks of the `networks` table to join (the project's default network if empty)
+    #[serde(default)]
+    pub networks: Vec<String>,
+}
+
+/// `build = "./dir"` or `build = { context = "./dir", file = "Cubofile.toml" }`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BuildSpec {
+    Context(String),
+    Detailed {
+        context: String,
+        file: Option<String>,
+    },
+}
+
+impl BuildSpec {
+    pub fn context(&self) -> &str {
+        match self {
+            BuildSpec::Context(context) | BuildSpec::Detailed { context, .. } => context,
+        }
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        match self {
+            BuildSpec::Context(_) => None,
+            BuildSpec::Detailed { file, .. } => file.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NetworkSpec {
+    /// Subnet in CIDR format (picked automatically if unset)
+    pub subnet: Option<String>,
+}
+
+impl ComposeFile {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            CuboError::InvalidConfiguration(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        Self::from_string(&content)
+    }
+
+    pub fn from_string(content: &str) -> Result<Self> {
+        let compose: Self = toml::from_str(content)
+            .map_err(|e| CuboError::InvalidConfiguration(format!("Failed to parse {}: {}", COMPOSE_FILE, e)))?;
+        compose.validate()?;
+        Ok(compose)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.services.is_empty() {
+            return Err(CuboError::InvalidConfiguration("No services defined".to_string()));
+        }
+        for (name, service) in &self.services {
+            validate_name(name, "service")?;
+            if service.image.is_none() && service.build.is_none() {
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "Service {} needs an image or a build context",
+                    name
+                )));
+            }
+            if let Some(missing) = service.depends_on.iter().find(|dep| !self.services.contains_key(*dep)) {
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "Service {} depends on undefined service {}",
+                    name, missing
+                )));
+            }
+            if let Some(missing) = service.networks.iter().find(|net| !self.networks.contains_key(*net)) {
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "Service {} uses undefined network {}",
+                    name, missing
+                )));
+            }
+        }
+        for name in self.networks.keys() {
+            validate_name(name, "network")?;
+        }
+        Ok(())
+    }
+
+    /// Service names with every service after the ones it depends on
+    pub fn start_order(&self) -> Result<Vec<String>> {
+        fn visit<'a>(
+            compose: &'a ComposeFile,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            order: &mut Vec<String>,
+        ) -> Result<()> {
+            if order.iter().any(|done| done == name) {
+                return Ok(());
+            }
+            if let Some(start) = path.iter().position(|seen| *seen == name) {
+                let cycle: Vec<&str> = path[start..].iter().copied().chain([name]).collect();
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "Dependency cycle between services: {}",
+                    cycle.join(" -> ")
+                )));
+            }
+            path.push(name);
+            for dependency in &compose.services[name].depends_on {
+                visit(compose, dependency, path, order)?;
+            }
+            path.pop();
+            order.push(name.to_string());
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        for name in self.services.keys() {
+            visit(self, name, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    /// `name` from the file, else the name of the directory holding it
+    pub fn project_name(&self, file: &Path) -> Result<String> {
+        let name = match self.name {
+            Some(ref name) => name.clone(),
+            None => {
+                let dir = fs::canonicalize(file)
+                    .ok()
+                    .and_then(|path| path.parent().map(Path::to_path_buf))
+                    .unwrap_or_default();
+                dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+            }
+        };
+        normalize_project_name(&name)
+    }
+}
+
+/// Lowercase the name and drop characters that can't appear in container or network names
+pub fn normalize_project_name(name: &str) -> Result<String> {
+    let normalized: String = name
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    let normalized = normalized.trim_start_matches(['-', '_']).to_string();
+    if normalized.is_empty() {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "Invalid project name '{}'; set one with --project-name",
+            name
+        )));
+    }
+    Ok(normalized)
+}
+
+fn validate_name(name: &str, kind: &str) -> Result<()> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "Invalid {} name '{}': use letters, digits, '_' and '-'",
+            kind, name
+        )));
+    }
+    Ok(())
+}
+
+/// Name of the container running `service`
+pub fn container_name(project: &str, service: &str) -> String {
+    format!("{}-{}", project, service)
+}
+
+/// Name of a network of the project; `None` is the default network every service without
+/// `networks` joins
+pub fn network_name(project: &str, network: Option<&str>) -> String {
+    format!("{}_{}", project, network.unwrap_or("default"))
+}
+
+/// Image a service runs: its `image`, or the tag its build context is built as
+pub fn service_image(project: &str, service_name: &str, service: &Service) -> String {
+    service
+        .image
+        .clone()
+        .unwrap_or_else(|| format!("{}:latest", container_name(project, service_name)))
+}
+
+/// What `cubo compose up` created for a project
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ProjectState {
+    /// Container ID per service, in start order
+    pub containers: Vec<(String, String)>,
+    /// Networks created for the project
+    pub networks: Vec<String>,
+}
+
+impl ProjectState {
+    fn path(root_dir: &Path, project: &str) -> PathBuf {
+        root_dir.join("compose").join(format!("{}.json", project))
+    }
+
+    pub fn load(root_dir: &Path, project: &str) -> Result<Self> {
+        let path = Self::path(root_dir, project);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        read_json(&path)
+    }
+
+    pub fn save(&self, root_dir: &Path, project: &str) -> Result<()> {
+        let path = Self::path(root_dir, project);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CuboError::SystemError(format!("Failed to create compose state directory: {}", e)))?;
+        }
+        atomic_write_json(&path, self)
+    }
+
+    pub fn remove(root_dir: &Path, project: &str) {
+        let _ = fs::remove_file(Self::path(root_dir, project));
+    }
+
+    pub fn container(&self, service: &str) -> Option<&str> {
+        self.containers.iter().find(|(name, _)| name == service).map(|(_, id)| id.as_str())
+    }
+
+    pub fn set_container(&mut self, service: &str, id: String) {
+        match self.containers.iter_mut().find(|(name, _)| name == service) {
+            Some(entry) => entry.1 = id,
+            None => self.containers.push((service.to_string(), id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const SHOP: &str = r#"
+name = "Shop"
+
+[services.db]
+image = "postgres:16"
+env = { POSTGRES_PASSWORD = "secret" }
+networks = ["back"]
+
+[services.web]
+build = { context = "./web", file = "Cubofile.toml" }
+ports = ["8080:80"]
+volumes = ["./static:/srv/static:ro"]
+depends_on = ["api"]
+
+[services.api]
+build = "./api"
+command = ["api", "--port", "9000"]
+depends_on = ["db"]
+networks = ["back"]
+
+[networks.back]
+subnet = "10.90.0.0/24"
+"#;
+
+    #[test]
+    fn test_parse_compose_file() {
+        let compose = ComposeFile::from_string(SHOP).unwrap();
+        assert_eq!(compose.services.len(), 3);
+        assert_eq!(compose.services["db"].env["POSTGRES_PASSWORD"], "secret");
+        assert_eq!(compose.services["api"].build, Some(BuildSpec::Context("./api".to_string())));
+        let web = compose.services["web"].build.as_ref().unwrap();
+        assert_eq!((web.context(), web.file()), ("./web", Some("Cubofile.toml")));
+        assert_eq!(compose.networks["back"].subnet.as_deref(), Some("10.90.0.0/24"));
+    }
+
+    #[test]
+    fn test_start_order() {
+        let compose = ComposeFile::from_string(SHOP).unwrap();
+        assert_eq!(compose.start_order().unwrap(), ["db", "api", "web"]);
+
+        let cyclic = r#"
+[services.a]
+image = "alpine"
+depends_on = ["b"]
+
+[services.b]
+image = "alpine"
+depends_on = ["a"]
+"#;
+        let err = ComposeFile::from_string(cyclic).unwrap().start_order().unwrap_err();
+        assert!(err.to_string().contains("a -> b -> a"));
+    }
+
+    #[test]
+    fn test_invalid_compose_files() {
+        let cases = [
+            ("", "No services"),
+            ("[services.web]\n", "needs an image or a build context"),
+            ("[services.web]\nimage = \"nginx\"\ndepends_on = [\"db\"]\n", "undefined service db"),
+            ("[services.web]\nimage = \"nginx\"\nnetworks = [\"front\"]\n", "undefined network front"),
+            ("[services.\"web app\"]\nimage = \"nginx\"\n", "Invalid service name"),
+        ];
+        for (content, message) in cases {
+            let err = ComposeFile::from_string(content).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", message, err);
+        }
+    }
+
+    #[test]
+    fn test_project_names() {
+        let compose = ComposeFile::from_string(SHOP).unwrap();
+        assert_eq!(compose.project_name(Path::new(COMPOSE_FILE)).unwrap(), "shop");
+        assert_eq!(normalize_project_name("My App!").unwrap(), "myapp");
+        assert!(normalize_project_name("!!").is_err());
+
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("Blog_Site");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join(COMPOSE_FILE), "[services.web]\nimage = \"nginx\"\n").unwrap();
+        let compose = ComposeFile::from_file(&dir.join(COMPOSE_FILE)).unwrap();
+        assert_eq!(compose.project_name(&dir.join(COMPOSE_FILE)).unwrap(), "blog_site");
+
+        assert_eq!(container_name("shop", "web"), "shop-web");
+        assert_eq!(network_name("shop", None), "shop_default");
+        assert_eq!(network_name("shop", Some("back")), "shop_back");
+        assert_eq!(service_image("shop", "api", &compose.services["web"]), "nginx");
+        assert_eq!(service_image("shop", "api", &Service::default()), "shop-api:latest");
+    }
+
+    #[test]
+    fn test_project_state() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(ProjectState::load(temp_dir.path(), "shop").unwrap(), ProjectState::default());
+
+        let mut state = ProjectState { networks: vec!["shop_default".to_string()], ..Default::default() };
+        state.set_container("db", "abc".to_string());
+        state.set_container("web", "def".to_string());
+        state.set_container("db", "ghi".to_string());
+        state.save(temp_dir.path(), "shop").unwrap();
+
+        let loaded = ProjectState::load(temp_dir.path(), "shop").unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.container("db"), Some("ghi"));
+        assert_eq!(loaded.container("api"), None);
+
+        ProjectState::remove(temp_dir.path(), "shop");
+        assert_eq!(ProjectState::load(temp_dir.path(), "shop").unwrap(), ProjectState::default());
+    }
+}
@@ -5,10 +5,58 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
 
 use crate::container::{Container, ContainerStatus};
 use crate::error::{CuboError, Result};
 
+/// Version of the config.json written by this build. Bump it with every change that older
+/// bundles can't be read with as they are, and add the migration that upgrades them.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` turns a version n config.json into a version n + 1 one
+const MIGRATIONS: [fn(&mut Value) -> Result<()>; SCHEMA_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Bundles written before config.json was versioned. Every field added since then has a
+/// default, so there is nothing to change but the version.
+fn migrate_v0_to_v1(_config: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Bring a config.json of any earlier version up to `SCHEMA_VERSION`, returning the version
+/// it was written with
+pub fn migrate(config: &mut Value) -> Result<u32> {
+    let object = config
+        .as_object_mut()
+        .ok_or_else(|| CuboError::SystemError("Container config is not a JSON object".to_string()))?;
+    let version = match object.remove("schema_version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| CuboError::SystemError(format!("Invalid schema_version {}", v)))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(CuboError::SystemError(format!(
+            "Container config has schema version {}, this cubo only reads up to {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+    for step in &MIGRATIONS[version as usize..] {
+        step(config)?;
+    }
+    Ok(version)
+}
+
+/// config.json as written: the container with the version of its layout
+#[derive(Serialize)]
+struct VersionedConfig<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    container: &'a Container,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OciState {
     #[serde(rename = "ociVersion")]
@@ -68,7 +116,7 @@ pub fn save_config(root_dir: &Path, container: &Container) -> Result<()> {
     fs::create_dir_all(&bundle_dir)
         .map_err(|e| CuboError::SystemError(format!("Failed to create bundle dir: {}", e)))?;
     let cfg_path = bundle_dir.join("config.json");
-    atomic_write_json(&cfg_path, container)
+    atomic_write_json(&cfg_path, &VersionedConfig { schema_version: SCHEMA_VERSION, container })
 }
 
 pub fn save_state(root_dir: &Path, container: &Container) -> Result<()> {
@@ -95,25 +143,44 @@ pub fn load_all(root_dir: &Path) -> Result<HashMap<String, Container>> {
             if !path.is_dir() {
                 continue;
             }
-            let config_path = path.join("config.json");
-            if !config_path.exists() {
-                continue;
-            }
-            let mut container: Container = read_json(&config_path)?;
-            let state_path = path.join("state.json");
-            if state_path.exists() {
-                if let Ok(state) = read_json::<OciState>(&state_path) {
-                    if let Some(s) = container_status_from_oci(&state.status) {
-                        container.update_status(s);
-                    } 
-                    container.pid = state.pid;
-                }
+            if let Some(container) = load_bundle(&path)? {
+                loaded.insert(container.id.clone(), container);
             }
-            loaded.insert(container.id.clone(), container);
         }
         Ok(loaded)
 }
 
+/// The container of one bundle, None when there is no such container
+pub fn load_one(root_dir: &Path, container_id: &str) -> Result<Option<Container>> {
+    load_bundle(&root_dir.join(container_id))
+}
+
+fn load_bundle(path: &Path) -> Result<Option<Container>> {
+    let config_path = path.join("config.json");
+    if !config_path.exists() {
+        return Ok(None);
+    }
+    let mut config: Value = read_json(&config_path)?;
+    let version = migrate(&mut config)?;
+    let mut container: Container = serde_json::from_value(config).map_err(|e| {
+        CuboError::SystemError(format!("Failed to parse JSON from {}: {}", config_path.display(), e))
+    })?;
+    if version < SCHEMA_VERSION {
+        info!("Upgrading {} from schema version {} to {}", config_path.display(), version, SCHEMA_VERSION);
+        atomic_write_json(&config_path, &VersionedConfig { schema_version: SCHEMA_VERSION, container: &container })?;
+    }
+    let state_path = path.join("state.json");
+    if state_path.exists() {
+        if let Ok(state) = read_json::<OciState>(&state_path) {
+            if let Some(s) = container_status_from_oci(&state.status) {
+                container.update_status(s);
+            }
+            container.pid = state.pid;
+        }
+    }
+    Ok(Some(container))
+}
+
 pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T>{
     let data = fs::read_to_string(path)
         .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", path.display(), e)))?;
@@ -183,6 +250,24 @@ pub fn pid_is_alive(pid: Option<u32>) -> bool {
     }
 }
 
+/// Where the process waiting for a container's init leaves its exit code, for when no cubo
+/// process is around to record it
+pub const EXIT_CODE_FILE: &str = "exit-code";
+
+pub fn write_exit_code(bundle: &Path, code: i32) -> Result<()> {
+    fs::write(bundle.join(EXIT_CODE_FILE), code.to_string())
+        .map_err(|e| CuboError::SystemError(format!("Failed to write the exit code: {}", e)))
+}
+
+/// The exit code left in `bundle` by the last run of its container, if it got to leave one
+pub fn read_exit_code(bundle: &Path) -> Option<i32> {
+    fs::read_to_string(bundle.join(EXIT_CODE_FILE)).ok()?.trim().parse().ok()
+}
+
+pub fn clear_exit_code(bundle: &Path) {
+    let _ = fs::remove_file(bundle.join(EXIT_CODE_FILE));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -270,7 +355,75 @@ mod tests {
         assert_eq!(c2.id, c.id);
         assert_eq!(c2.status, ContainerStatus::Running);
         assert_eq!(c2.pid, Some(12345));
+
+        let one = load_one(tmp.path(), &c.id).unwrap().unwrap();
+        assert_eq!(one.status, ContainerStatus::Running);
+        assert!(load_one(tmp.path(), "missing").unwrap().is_none());
+
+        let saved: Value = read_json(&bundle.join("config.json")).unwrap();
+        assert_eq!(saved["schema_version"], SCHEMA_VERSION);
+    }
+
+    /// Copy the bundles of a fixture written by an older cubo into a scratch root
+    fn fixture_root(version: &str) -> TempDir {
+        let tmp = TempDir::new().unwrap();
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/bundles").join(version);
+        for bundle in fs::read_dir(fixtures).unwrap() {
+            let bundle = bundle.unwrap().path();
+            let target = tmp.path().join(bundle.file_name().unwrap());
+            fs::create_dir_all(&target).unwrap();
+            for file in fs::read_dir(&bundle).unwrap() {
+                let file = file.unwrap().path();
+                fs::copy(&file, target.join(file.file_name().unwrap())).unwrap();
+            }
+        }
+        tmp
+    }
+
+    #[test]
+    fn test_load_unversioned_bundle_fixture() {
+        let tmp = fixture_root("v0");
+        let id = "3f2a9c1e-7b44-4d0e-9a51-2c8e6d0b7f13";
+
+        let loaded = load_all(tmp.path()).unwrap();
+        let c = &loaded[id];
+        assert_eq!(c.name.as_deref(), Some("legacy-web"));
+        assert_eq!(c.status, ContainerStatus::Stopped);
+        assert_eq!(c.config.memory_limit, Some(128 * 1024 * 1024));
+        assert_eq!(c.config.volume_mounts.len(), 1);
+        assert!(c.config.networks.is_empty());
+        assert!(!c.config.init);
+
+        // The bundle is upgraded in place and loads the same way again
+        let config: Value = read_json(&tmp.path().join(id).join("config.json")).unwrap();
+        assert_eq!(config["schema_version"], SCHEMA_VERSION);
+        assert_eq!(config["config"]["ports"][0]["host_port"], 8080);
+        let reloaded = load_one(tmp.path(), id).unwrap().unwrap();
+        assert_eq!(reloaded.config.hostname.as_deref(), Some("web"));
     }
 
+    #[test]
+    fn test_migrate_rejects_newer_and_invalid_versions() {
+        let mut config = serde_json::json!({"schema_version": SCHEMA_VERSION + 1});
+        assert!(migrate(&mut config).is_err());
+
+        let mut config = serde_json::json!({"schema_version": "one"});
+        assert!(migrate(&mut config).is_err());
 
-}
\ No newline at end of file
+        let mut config = serde_json::json!({"schema_version": SCHEMA_VERSION, "id": "x"});
+        assert_eq!(migrate(&mut config).unwrap(), SCHEMA_VERSION);
+        assert!(config.get("schema_version").is_none());
+    }
+
+
+
+    #[test]
+    fn test_exit_code_file() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(read_exit_code(temp.path()), None);
+        write_exit_code(temp.path(), 137).unwrap();
+        assert_eq!(read_exit_code(temp.path()), Some(137));
+        clear_exit_code(temp.path());
+        assert_eq!(read_exit_code(temp.path()), None);
+    }
+}
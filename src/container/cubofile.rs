@@ -1,6 +1,7 @@
 use std::fs;
 use std::path::Path;
 
+use crate::container::signal::parse_signal;
 use crate::error::{CuboError, Result};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -8,14 +9,19 @@ pub enum Instruction {
     Base { image: String },
     /// RUN <command> - execute command in container
     Run { command: String },
-    /// COPY <src> <dest> - copy files from build context to container
-    Copy { src: String, dest: String },
+    /// COPY [--chown=<user>[:<group>]] [--chmod=<mode>] <src>... <dest> - copy files from build
+    /// context to container. Sources may hold `*`, `?` and `[...]` wildcards.
+    Copy { srcs: Vec<String>, dest: String, chown: Option<String>, chmod: Option<u32> },
     /// ENV <key>=<value> - set environment variable
     Env { key: String, value: String },
     /// WORKDIR <path> - set working directory
     Workdir { path: String },
     /// CMD <command> - default command to run
     Cmd { command: Vec<String> },
+    /// STOPSIGNAL <signal> - signal sent to stop the container
+    StopSignal { signal: String },
+    /// LABEL <key>=<value> ... - metadata stored in the image config
+    Label { labels: Vec<(String, String)> },
     /// Comment or empty line (ignored)
     Comment,
 }
@@ -61,11 +67,12 @@ impl Cubofile {
         let args = if parts.len() > 1 { parts[1].trim() } else { "" };
 
         match directive.as_str() {
-            "BASE" => {
+            // FROM is accepted so Dockerfiles sent to the Docker API build as-is
+            "BASE" | "FROM" => {
                 if args.is_empty() {
                     return Err(CuboError::InvalidConfiguration(format!(
-                        "Line {}: BASE requires an image argument",
-                        line_num
+                        "Line {}: {} requires an image argument",
+                        line_num, directive
                     )));
                 }
                 Ok(Instruction::Base {
@@ -86,16 +93,43 @@ impl Cubofile {
             }
 
             "COPY" => {
-                let copy_parts: Vec<&str> = args.split_whitespace().collect();
-                if copy_parts.len() != 2 {
+                let mut copy_parts: Vec<&str> = args.split_whitespace().collect();
+                let flags = copy_parts.iter().take_while(|part| part.starts_with("--")).count();
+                let (mut chown, mut chmod) = (None, None);
+                for flag in copy_parts.drain(..flags) {
+                    match flag.split_once('=') {
+                        Some(("--chown", owner)) if !owner.is_empty() => chown = Some(owner.to_string()),
+                        Some(("--chmod", mode)) => {
+                            chmod = Some(parse_chmod(mode).ok_or_else(|| CuboError::InvalidConfiguration(format!(
+                                "Line {}: invalid COPY mode '{}' (expected octal, e.g. 644)",
+                                line_num, mode
+                            )))?);
+                        }
+                        _ => {
+                            return Err(CuboError::InvalidConfiguration(format!(
+                                "Line {}: unknown COPY option '{}' (expected --chown or --chmod)",
+                                line_num, flag
+                            )));
+                        }
+                    }
+                }
+                let Some((dest, srcs)) = copy_parts.split_last().filter(|(_, srcs)| !srcs.is_empty()) else {
+                    return Err(CuboError::InvalidConfiguration(format!(
+                        "Line {}: COPY requires at least 2 arguments: <src>... <dest>",
+                        line_num
+                    )));
+                };
+                if (srcs.len() > 1 || srcs.iter().any(|src| has_wildcard(src))) && !dest.ends_with('/') {
                     return Err(CuboError::InvalidConfiguration(format!(
-                        "Line {}: COPY requires exactly 2 arguments: <src> <dest>",
+                        "Line {}: COPY with several sources or wildcards needs a directory destination ending in '/'",
                         line_num
                     )));
                 }
                 Ok(Instruction::Copy {
-                    src: copy_parts[0].to_string(),
-                    dest: copy_parts[1].to_string(),
+                    srcs: srcs.iter().map(|src| src.to_string()).collect(),
+                    dest: dest.to_string(),
+                    chown,
+                    chmod,
                 })
             }
 
@@ -137,11 +171,40 @@ impl Cubofile {
                         line_num
                     )));
                 }
+                // Exec form (["cmd", "arg"]) as written in Dockerfiles
+                if args.starts_with('[') {
+                    let command: Vec<String> = serde_json::from_str(args).map_err(|_| {
+                        CuboError::InvalidConfiguration(format!(
+                            "Line {}: CMD exec form must be a JSON array of strings",
+                            line_num
+                        ))
+                    })?;
+                    return Ok(Instruction::Cmd { command });
+                }
                 // Parse as shell command (split by whitespace)
                 let cmd_parts: Vec<String> = args.split_whitespace().map(|s| s.to_string()).collect();
                 Ok(Instruction::Cmd { command: cmd_parts })
             }
 
+            "STOPSIGNAL" => {
+                parse_signal(args).map_err(|_| {
+                    CuboError::InvalidConfiguration(format!(
+                        "Line {}: STOPSIGNAL requires a valid signal name or number",
+                        line_num
+                    ))
+                })?;
+                Ok(Instruction::StopSignal {
+                    signal: args.to_string(),
+                })
+            }
+
+            "LABEL" => {
+                let labels = parse_labels(args).map_err(|e| {
+                    CuboError::InvalidConfiguration(format!("Line {}: LABEL {}", line_num, e))
+                })?;
+                Ok(Instruction::Label { labels })
+            }
+
             _ => Err(CuboError::InvalidConfiguration(format!(
                 "Line {}: Unknown directive: {}",
                 line_num, directive
@@ -174,6 +237,110 @@ impl Cubofile {
     }
 }
 
+/// Parse the octal mode of `COPY --chmod`, e.g. 644 or 0755
+pub fn parse_chmod(mode: &str) -> Option<u32> {
+    if mode.is_empty() || mode.len() > 4 || !mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return None;
+    }
+    u32::from_str_radix(mode, 8).ok()
+}
+
+/// Whether a COPY source holds wildcards to expand against the build context
+pub fn has_wildcard(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Match a file name against a wildcard pattern: `*` for any run of characters, `?` for
+/// one, and `[...]` for one of a set, with ranges like `a-z` and `!` or `^` to negate it
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_match_chars(&pattern, &name)
+}
+
+fn glob_match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| glob_match_chars(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && glob_match_chars(&pattern[1..], &name[1..]),
+        // An unclosed `[` is taken literally
+        Some('[') if pattern.iter().skip(2).any(|c| *c == ']') => {
+            let end = 2 + pattern[2..].iter().position(|c| *c == ']').unwrap_or(0);
+            match name.first() {
+                Some(c) if class_matches(&pattern[1..end], *c) => glob_match_chars(&pattern[end + 1..], &name[1..]),
+                _ => false,
+            }
+        }
+        Some(c) => name.first() == Some(c) && glob_match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Whether `c` is in the set of a `[...]` wildcard, given without the brackets
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+/// Parse the `key=value` pairs of a LABEL. Values may be double quoted to hold spaces,
+/// e.g. `LABEL description="web frontend" version=1.2`.
+fn parse_labels(args: &str) -> std::result::Result<Vec<(String, String)>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => word.extend(chars.next()),
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quoted {
+        return Err("has an unterminated quote".to_string());
+    }
+    if in_word {
+        words.push(word);
+    }
+    if words.is_empty() {
+        return Err("requires at least one key=value pair".to_string());
+    }
+
+    words
+        .into_iter()
+        .map(|word| match word.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(format!("must be in format key=value, got '{}'", word)),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -210,10 +377,62 @@ mod tests {
         assert_eq!(
             cubofile.instructions[0],
             Instruction::Copy {
-                src: "./app".to_string(),
-                dest: "/usr/bin/app".to_string()
+                srcs: vec!["./app".to_string()],
+                dest: "/usr/bin/app".to_string(),
+                chown: None,
+                chmod: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_copy_chown_chmod() {
+        let content = "COPY --chown=app:app --chmod=0640 config.toml /etc/app/config.toml";
+        let cubofile = Cubofile::from_string(content).unwrap();
+        assert_eq!(
+            cubofile.instructions[0],
+            Instruction::Copy {
+                srcs: vec!["config.toml".to_string()],
+                dest: "/etc/app/config.toml".to_string(),
+                chown: Some("app:app".to_string()),
+                chmod: Some(0o640),
+            }
+        );
+        assert!(Cubofile::from_string("COPY --chmod=999 a b").is_err());
+        assert!(Cubofile::from_string("COPY --chown= a b").is_err());
+        assert!(Cubofile::from_string("COPY --from=build a b").is_err());
+        assert!(Cubofile::from_string("COPY --chown=app a").is_err());
+    }
+
+    #[test]
+    fn test_parse_copy_multiple_sources() {
+        let cubofile = Cubofile::from_string("COPY ./configs/*.yaml README.md /etc/app/").unwrap();
+        assert_eq!(
+            cubofile.instructions[0],
+            Instruction::Copy {
+                srcs: vec!["./configs/*.yaml".to_string(), "README.md".to_string()],
+                dest: "/etc/app/".to_string(),
+                chown: None,
+                chmod: None,
             }
         );
+        assert!(Cubofile::from_string("COPY a b /etc/app").is_err());
+        assert!(Cubofile::from_string("COPY *.yaml /etc/app").is_err());
+        assert!(Cubofile::from_string("COPY /etc/app/").is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.yaml", "app.yaml"));
+        assert!(glob_match("*.yaml", ".yaml"));
+        assert!(!glob_match("*.yaml", "app.yml"));
+        assert!(glob_match("app-?.conf", "app-1.conf"));
+        assert!(!glob_match("app-?.conf", "app-10.conf"));
+        assert!(glob_match("[a-c]*", "beta"));
+        assert!(!glob_match("[!a-c]*", "beta"));
+        assert!(glob_match("[^x]", "y"));
+        assert!(glob_match("file[", "file["));
+        assert!(glob_match("*", "anything"));
     }
 
     #[test]
@@ -253,6 +472,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_cmd_exec_form() {
+        let cubofile = Cubofile::from_string(r#"CMD ["echo", "hello world"]"#).unwrap();
+        assert_eq!(
+            cubofile.instructions[0],
+            Instruction::Cmd {
+                command: vec!["echo".to_string(), "hello world".to_string()]
+            }
+        );
+        assert!(Cubofile::from_string(r#"CMD ["echo", 1]"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_from_alias() {
+        let cubofile = Cubofile::from_string("FROM alpine:3.19").unwrap();
+        assert_eq!(cubofile.base_image(), Some("alpine:3.19".to_string()));
+        assert!(Cubofile::from_string("FROM").is_err());
+    }
+
+    #[test]
+    fn test_parse_stopsignal() {
+        let cubofile = Cubofile::from_string("STOPSIGNAL SIGQUIT").unwrap();
+        assert_eq!(
+            cubofile.instructions[0],
+            Instruction::StopSignal {
+                signal: "SIGQUIT".to_string()
+            }
+        );
+        assert!(Cubofile::from_string("STOPSIGNAL").is_err());
+        assert!(Cubofile::from_string("STOPSIGNAL SIGBOGUS").is_err());
+    }
+
+    #[test]
+    fn test_parse_label() {
+        let content = r#"LABEL org.opencontainers.image.title=web description="web \"frontend\"" empty="""#;
+        let cubofile = Cubofile::from_string(content).unwrap();
+        assert_eq!(
+            cubofile.instructions[0],
+            Instruction::Label {
+                labels: vec![
+                    ("org.opencontainers.image.title".to_string(), "web".to_string()),
+                    ("description".to_string(), "web \"frontend\"".to_string()),
+                    ("empty".to_string(), String::new()),
+                ]
+            }
+        );
+        assert!(Cubofile::from_string("LABEL").is_err());
+        assert!(Cubofile::from_string("LABEL version").is_err());
+        assert!(Cubofile::from_string("LABEL =1").is_err());
+        assert!(Cubofile::from_string(r#"LABEL a="b"#).is_err());
+    }
+
     #[test]
     fn test_parse_full_cubofile() {
         let content = r#"
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
 
@@ -19,6 +19,9 @@ pub struct CubofileToml {
     /// Image configuration line env, workdir, cmd
     #[serde(default)]
     pub config: Config,
+    /// Options of the build itself, not kept in the image
+    #[serde(default)]
+    pub build: BuildOptions,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,18 +30,50 @@ pub struct ImageSpec {
     pub base: String,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct RunStep {
     /// Command to execute
     pub command: String,
+    /// Directory the command runs in, created when missing
+    #[serde(default)]
+    pub workdir: Option<String>,
+    /// Environment of the command, over the build arguments
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// User the command runs as (user[:group], names looked up in the image)
+    #[serde(default)]
+    pub user: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct CopyStep {
-    /// Source path in build context
+    /// Source path in build context, which may hold `*`, `?` and `[...]` wildcards
     pub src: String,
-    /// Destination path in container
+    /// Destination path in container, a directory when it ends in `/`
     pub dest: String,
+    /// Directory a relative destination is resolved against
+    #[serde(default)]
+    pub workdir: Option<String>,
+    /// Owner of the copied files (user[:group], names looked up in the image)
+    #[serde(default)]
+    pub chown: Option<String>,
+    /// Octal mode of the copied files and directories (e.g. "644")
+    #[serde(default)]
+    pub chmod: Option<String>,
+}
+
+/// Limits of the RUN steps, which `cubo build` options override
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildOptions {
+    /// Time after which a RUN step is killed (e.g. 90s, 10m, 1h)
+    #[serde(default)]
+    pub step_timeout: Option<String>,
+    /// Memory limit of each RUN step (e.g. 512m, 2g)
+    #[serde(default)]
+    pub step_memory: Option<String>,
+    /// CPU limit of each RUN step, in cores
+    #[serde(default)]
+    pub step_cpus: Option<f32>,
 }
 
 /// Container config
@@ -51,10 +86,16 @@ pub struct Config {
     pub workdir: Option<String>,
     /// Default command
     pub cmd: Option<Vec<String>>,
+    /// Signal sent to stop the container
+    #[serde(default)]
+    pub stop_signal: Option<String>,
     
     /// Exposed ports
     #[serde(default)]
     pub expose: Vec<String>,
+    /// Labels stored in the image config
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
 }
 
 impl CubofileToml {
@@ -127,6 +168,7 @@ dest = "/etc/app/config.toml"
 workdir = "/app"
 cmd = ["/usr/local/bin/myapp", "serve"]
 expose = ["8080", "9090"]
+stop_signal = "SIGQUIT"
 
 [config.env]
 PATH = "/usr/local/bin:/usr/bin:/bin"
@@ -146,6 +188,56 @@ LOG_LEVEL = "info"
         assert_eq!(cubofile.config.cmd, Some(vec!["/usr/local/bin/myapp".to_string(), "serve".to_string()]));
         assert_eq!(cubofile.config.env.get("APP_ENV"), Some(&"production".to_string()));
         assert_eq!(cubofile.config.expose.len(), 2);
+        assert_eq!(cubofile.config.stop_signal, Some("SIGQUIT".to_string()));
+    }
+
+    #[test]
+    fn test_parse_step_options() {
+        let content = r#"
+[image]
+base = "alpine:latest"
+
+[[run]]
+command = "make install"
+workdir = "/src"
+user = "builder:builder"
+env = { CFLAGS = "-O2" }
+
+[[copy]]
+src = "app.py"
+dest = "app.py"
+workdir = "/app"
+chown = "app"
+chmod = "640"
+"#;
+
+        let cubofile = CubofileToml::from_string(content).unwrap();
+        let run = &cubofile.run[0];
+        assert_eq!(run.workdir.as_deref(), Some("/src"));
+        assert_eq!(run.user.as_deref(), Some("builder:builder"));
+        assert_eq!(run.env["CFLAGS"], "-O2");
+        let copy = &cubofile.copy[0];
+        assert_eq!(copy.workdir.as_deref(), Some("/app"));
+        assert_eq!(copy.chown.as_deref(), Some("app"));
+        assert_eq!(copy.chmod.as_deref(), Some("640"));
+    }
+
+    #[test]
+    fn test_parse_build_options() {
+        let content = r#"
+[image]
+base = "alpine:latest"
+
+[build]
+step_timeout = "10m"
+step_memory = "1g"
+step_cpus = 1.5
+"#;
+
+        let cubofile = CubofileToml::from_string(content).unwrap();
+        assert_eq!(cubofile.build.step_timeout.as_deref(), Some("10m"));
+        assert_eq!(cubofile.build.step_memory.as_deref(), Some("1g"));
+        assert_eq!(cubofile.build.step_cpus, Some(1.5));
     }
 
     #[test]
@@ -306,17 +398,22 @@ base = "scratch"
             },
             run: vec![RunStep {
                 command: "echo hello".to_string(),
+                ..Default::default()
             }],
             copy: vec![CopyStep {
                 src: "./src".to_string(),
                 dest: "/app/src".to_string(),
+                ..Default::default()
             }],
             config: Config {
                 env: HashMap::from([("KEY".to_string(), "value".to_string())]),
                 workdir: Some("/app".to_string()),
                 cmd: Some(vec!["/app/start".to_string()]),
+                stop_signal: None,
                 expose: vec!["8080".to_string()],
+                labels: BTreeMap::from([("version".to_string(), "1.2".to_string())]),
             },
+            build: BuildOptions::default(),
         };
 
         let toml_str = toml::to_string(&cubofile).unwrap();
@@ -325,6 +422,7 @@ base = "scratch"
 
         let parsed: CubofileToml = toml::from_str(&toml_str).unwrap();
         assert_eq!(parsed.base_image(), "alpine:3.18");
+        assert_eq!(parsed.config.labels["version"], "1.2");
     }
 
     #[test]
@@ -335,9 +433,11 @@ base = "scratch"
             },
             run: vec![RunStep {
                 command: "apt update".to_string(),
+                ..Default::default()
             }],
             copy: vec![],
             config: Config::default(),
+            build: BuildOptions::default(),
         };
 
         let cloned = cubofile.clone();
@@ -354,6 +454,7 @@ base = "scratch"
             run: vec![],
             copy: vec![],
             config: Config::default(),
+            build: BuildOptions::default(),
         };
 
         let debug_str = format!("{:?}", cubofile);
@@ -410,6 +511,7 @@ expose = ["80", "443", "8080", "9000"]
     fn test_run_step_clone_and_debug() {
         let step = RunStep {
             command: "test command".to_string(),
+            ..Default::default()
         };
         let cloned = step.clone();
         assert_eq!(cloned.command, "test command");
@@ -423,6 +525,7 @@ expose = ["80", "443", "8080", "9000"]
         let step = CopyStep {
             src: "./source".to_string(),
             dest: "/dest".to_string(),
+            ..Default::default()
         };
         let cloned = step.clone();
         assert_eq!(cloned.src, "./source");
@@ -0,0 +1,251 @@
+//! `cubo debug`: a statically linked busybox mounted into a running container, for images
+//! that come without a shell or any tools
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::fd::AsFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use nix::fcntl::{open, OFlag};
+use nix::mount::{mount, MsFlags};
+use nix::sched::{setns, CloneFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{mkdir, write};
+
+use crate::container::exec::exec_env;
+use crate::container::rootless_net::find_in_path;
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+/// Where the toolkit is mounted inside the container
+pub const DEBUG_DIR: &str = "/.cubo-debug";
+
+pub const TOOLKIT: &str = "busybox";
+
+/// Program header type of the dynamic loader a binary needs
+const PT_INTERP: u32 = 3;
+
+/// The toolkit to inject: `given`, else the one installed under the root directory, else
+/// busybox from PATH. It has to be statically linked since the container has no libc to
+/// offer.
+pub fn find_toolkit(root_dir: &Path, given: Option<&Path>) -> Result<PathBuf> {
+    let installed = root_dir.join("debug").join(TOOLKIT);
+    let path = match given {
+        Some(path) => path.to_path_buf(),
+        None if installed.is_file() => installed,
+        None => find_in_path(TOOLKIT, &std::env::var("PATH").unwrap_or_default()).ok_or_else(|| {
+            CuboError::CommandNotFound(format!(
+                "{} (install a static one at {} or pass --toolkit)",
+                TOOLKIT,
+                installed.display()
+            ))
+        })?,
+    };
+
+    let data = fs::read(&path)
+        .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", path.display(), e)))?;
+    if !is_static_elf(&data) {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "{} is not a statically linked executable",
+            path.display()
+        )));
+    }
+    Ok(path)
+}
+
+/// Whether `data` is an ELF executable that doesn't ask for a dynamic loader
+pub fn is_static_elf(data: &[u8]) -> bool {
+    if data.len() < 52 || &data[..4] != b"\x7fELF" {
+        return false;
+    }
+    let is_64 = data[4] == 2;
+    let little = data[5] == 1;
+    let read = |at: u64, len: usize| -> Option<u64> {
+        let at = usize::try_from(at).ok()?;
+        let bytes = data.get(at..at.checked_add(len)?)?;
+        let mut value = 0u64;
+        for i in 0..len {
+            let byte = if little { bytes[len - 1 - i] } else { bytes[i] };
+            value = value << 8 | byte as u64;
+        }
+        Some(value)
+    };
+    let header = if is_64 {
+        (read(0x20, 8), read(0x36, 2), read(0x38, 2))
+    } else {
+        (read(0x1c, 4), read(0x2a, 2), read(0x2c, 2))
+    };
+    let (Some(phoff), Some(phentsize), Some(phnum)) = header else {
+        return false;
+    };
+
+    // The offsets come from the file, a crafted one must not overflow them
+    (0..phnum).all(|i| {
+        let at = i.checked_mul(phentsize).and_then(|offset| phoff.checked_add(offset));
+        at.and_then(|at| read(at, 4)).is_some_and(|kind| kind != PT_INTERP as u64)
+    })
+}
+
+/// Whether the toolkit is already in the container with process `target`, from an earlier
+/// `cubo debug`
+pub fn is_installed(target: u32) -> bool {
+    Path::new(&format!("/proc/{}/root{}/{}", target, DEBUG_DIR, TOOLKIT)).is_file()
+}
+
+/// Mount a tmpfs at DEBUG_DIR in the mount namespace of `target` and copy the toolkit into
+/// it. The container's processes see it until the container stops.
+pub fn install(target: u32, toolkit: &Path) -> Result<()> {
+    let data = fs::read(toolkit)
+        .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", toolkit.display(), e)))?;
+    let open_ns = |name: &str| {
+        let path = format!("/proc/{}/ns/{}", target, name);
+        File::open(&path).map_err(|e| CuboError::NamespaceError(format!("Failed to open {}: {}", path, e)))
+    };
+    // Mounting in the namespace of a rootless container needs its user namespace too
+    let userns = match fs::read_link(format!("/proc/{}/ns/user", target)) {
+        Ok(theirs) if fs::read_link("/proc/self/ns/user").ok().as_ref() != Some(&theirs) => Some(open_ns("user")?),
+        _ => None,
+    };
+    let mntns = open_ns("mnt")?;
+    let binary = format!("{}/{}", DEBUG_DIR, TOOLKIT);
+
+    // The forked child is single threaded, so it may join the mount namespace; it then checks
+    // that the toolkit runs there
+    let mut command = Command::new(&binary);
+    command.arg("true").stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::piped());
+    let target_path = binary.clone();
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(userns) = &userns {
+                setns(userns.as_fd(), CloneFlags::CLONE_NEWUSER)?;
+            }
+            setns(mntns.as_fd(), CloneFlags::CLONE_NEWNS)?;
+            match mkdir(DEBUG_DIR, Mode::from_bits_truncate(0o755)) {
+                Ok(()) | Err(nix::errno::Errno::EEXIST) => {}
+                Err(e) => return Err(e.into()),
+            }
+            mount(
+                Some("tmpfs"),
+                DEBUG_DIR,
+                Some("tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                Some("mode=755"),
+            )?;
+            let fd = open(
+                target_path.as_str(),
+                OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC | OFlag::O_CLOEXEC,
+                Mode::from_bits_truncate(0o755),
+            )?;
+            let mut written = 0;
+            while written < data.len() {
+                written += write(&fd, &data[written..])?;
+            }
+            Ok(())
+        });
+    }
+
+    let output = command.output().map_err(|e| {
+        CuboError::SystemError(format!(
+            "Failed to mount the toolkit at {} in the container: {} (a read-only rootfs needs {} in the image)",
+            DEBUG_DIR, e, DEBUG_DIR
+        ))
+    })?;
+    if !output.status.success() {
+        return Err(CuboError::SystemError(format!(
+            "{} doesn't run in the container: {}",
+            binary,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The command that links the toolkit's applets into DEBUG_DIR/bin, run inside the container
+pub fn install_applets_command() -> Vec<String> {
+    vec![
+        format!("{}/{}", DEBUG_DIR, TOOLKIT),
+        "--install".to_string(),
+        "-s".to_string(),
+        format!("{}/bin", DEBUG_DIR),
+    ]
+}
+
+/// Environment of debug shells: the container's, with the toolkit's applets after the
+/// container's own PATH so the container's binaries still win
+pub fn debug_env(container: &Container) -> HashMap<String, String> {
+    let mut env = exec_env(container, &HashMap::new());
+    let path = env.get("PATH").cloned().unwrap_or_default();
+    env.insert("PATH".to_string(), format!("{}:{}/bin", path, DEBUG_DIR));
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A minimal 64-bit little endian ELF header with the given program header types
+    fn elf64(program_headers: &[u32]) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        data[0x38..0x3a].copy_from_slice(&(program_headers.len() as u16).to_le_bytes());
+        for kind in program_headers {
+            let mut header = vec![0u8; 56];
+            header[..4].copy_from_slice(&kind.to_le_bytes());
+            data.extend(header);
+        }
+        data
+    }
+
+    #[test]
+    fn test_is_static_elf() {
+        assert!(is_static_elf(&elf64(&[1, 1])));
+        assert!(!is_static_elf(&elf64(&[6, PT_INTERP, 1])));
+        assert!(!is_static_elf(b"#!/bin/sh\nexit 0\n"));
+
+        // Program headers past the end of the file
+        let mut truncated = elf64(&[1]);
+        truncated.truncate(66);
+        assert!(!is_static_elf(&truncated));
+
+        // Offsets that overflow, rather than wrap around to a harmless header
+        let mut crafted = elf64(&[1, 1]);
+        crafted[0x20..0x28].copy_from_slice(&(u64::MAX - 55).to_le_bytes());
+        assert!(!is_static_elf(&crafted));
+        let mut crafted = elf64(&[1, 1]);
+        crafted[0x36..0x38].copy_from_slice(&u16::MAX.to_le_bytes());
+        crafted[0x20..0x28].copy_from_slice(&(u64::MAX - 0xfffe).to_le_bytes());
+        assert!(!is_static_elf(&crafted));
+    }
+
+    #[test]
+    fn test_find_toolkit_prefers_the_given_one() {
+        let temp = TempDir::new().unwrap();
+        let given = temp.path().join("my-busybox");
+        fs::write(&given, elf64(&[1])).unwrap();
+        assert_eq!(find_toolkit(temp.path(), Some(&given)).unwrap(), given);
+
+        let installed = temp.path().join("debug").join(TOOLKIT);
+        fs::create_dir_all(installed.parent().unwrap()).unwrap();
+        fs::write(&installed, elf64(&[PT_INTERP])).unwrap();
+        let err = find_toolkit(temp.path(), None).unwrap_err();
+        assert!(matches!(err, CuboError::InvalidConfiguration(_)));
+
+        fs::write(&installed, elf64(&[1])).unwrap();
+        assert_eq!(find_toolkit(temp.path(), None).unwrap(), installed);
+    }
+
+    #[test]
+    fn test_debug_env_appends_the_toolkit() {
+        let container = Container::new("test:latest".to_string(), vec!["app".to_string()])
+            .with_env("PATH".to_string(), "/app/bin".to_string());
+        assert_eq!(debug_env(&container)["PATH"], "/app/bin:/.cubo-debug/bin");
+        assert_eq!(install_applets_command()[0], "/.cubo-debug/busybox");
+    }
+}
@@ -0,0 +1,381 @@
+//! Host capabilities cubo relies on, for `cubo system info` and `cubo doctor`
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{getuid, Uid, User};
+
+use crate::container::cgroup::{self, CgroupMode, CONTROLLERS, V1_CONTROLLERS};
+use crate::container::idmap::{parse_subid, SUBGID_FILE, SUBUID_FILE};
+use crate::container::rootless_net::{self, find_in_path, UsermodeDriver};
+use crate::container::snapshotter::SnapshotterKind;
+
+const OSRELEASE_FILE: &str = "/proc/sys/kernel/osrelease";
+const MAX_USER_NAMESPACES_FILE: &str = "/proc/sys/user/max_user_namespaces";
+const FILESYSTEMS_FILE: &str = "/proc/filesystems";
+
+/// What the host offers, as seen by the current user
+#[derive(Debug, Clone)]
+pub struct HostInfo {
+    pub kernel: String,
+    pub uid: u32,
+    /// None when no cgroup hierarchy is mounted at the cgroup root
+    pub cgroup_mode: Option<CgroupMode>,
+    /// Controllers the kernel provides at the cgroup root; on cgroup v1 those cubo uses
+    /// that have a hierarchy
+    pub controllers: Vec<String>,
+    /// Controllers enabled for the children of the cgroup root; none on cgroup v1
+    pub delegated: Vec<String>,
+    /// None when the kernel has no user namespaces at all
+    pub max_user_namespaces: Option<u64>,
+    pub newuidmap: Option<PathBuf>,
+    pub newgidmap: Option<PathBuf>,
+    /// The user has ranges in both /etc/subuid and /etc/subgid
+    pub subids: bool,
+    pub overlayfs: bool,
+    pub network_driver: Option<UsermodeDriver>,
+}
+
+impl HostInfo {
+    pub fn probe(cgroup_root: &Path) -> Self {
+        let uid = getuid().as_raw();
+        let path = std::env::var("PATH").unwrap_or_default();
+        let name = User::from_uid(Uid::from_raw(uid)).ok().flatten().map(|u| u.name).unwrap_or_default();
+        let has_subids = |file: &str| {
+            !parse_subid(&fs::read_to_string(file).unwrap_or_default(), &name, uid).is_empty()
+        };
+        let cgroup_mode = cgroup::detect_mode(cgroup_root);
+
+        Self {
+            kernel: fs::read_to_string(OSRELEASE_FILE)
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            uid,
+            cgroup_mode,
+            controllers: match cgroup_mode {
+                Some(mode) if mode.is_v1() => cgroup::v1_controllers(cgroup_root),
+                _ => read_controllers(&cgroup_root.join("cgroup.controllers")),
+            },
+            delegated: read_controllers(&cgroup_root.join("cgroup.subtree_control")),
+            max_user_namespaces: fs::read_to_string(MAX_USER_NAMESPACES_FILE)
+                .ok()
+                .and_then(|s| s.trim().parse().ok()),
+            newuidmap: find_in_path("newuidmap", &path),
+            newgidmap: find_in_path("newgidmap", &path),
+            subids: has_subids(SUBUID_FILE) && has_subids(SUBGID_FILE),
+            overlayfs: has_filesystem(&fs::read_to_string(FILESYSTEMS_FILE).unwrap_or_default(), "overlay"),
+            network_driver: rootless_net::detect_driver(),
+        }
+    }
+
+    pub fn rootless(&self) -> bool {
+        self.uid != 0
+    }
+}
+
+fn read_controllers(path: &Path) -> Vec<String> {
+    parse_controllers(&fs::read_to_string(path).unwrap_or_default())
+}
+
+pub fn parse_controllers(content: &str) -> Vec<String> {
+    content.split_whitespace().map(str::to_string).collect()
+}
+
+/// Whether /proc/filesystems lists `fstype`, with or without the nodev column
+pub fn has_filesystem(proc_filesystems: &str, fstype: &str) -> bool {
+    proc_filesystems
+        .lines()
+        .any(|line| line.split_whitespace().last() == Some(fstype))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Works, with a feature missing
+    Warning,
+    /// Containers won't run as configured
+    Error,
+}
+
+impl std::fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "ok"),
+            CheckStatus::Warning => write!(f, "warning"),
+            CheckStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about it, for anything but Ok
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: CheckStatus::Ok, detail: detail.into(), fix: None }
+    }
+
+    fn failed(name: &'static str, status: CheckStatus, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into(), fix: Some(fix.into()) }
+    }
+}
+
+/// Preflight checks of `cubo doctor` for running containers with `snapshotter`
+pub fn run_checks(info: &HostInfo, snapshotter: SnapshotterKind) -> Vec<Check> {
+    let mut checks = vec![check_cgroups(info)];
+    match info.cgroup_mode {
+        Some(CgroupMode::Unified) => checks.push(check_controllers(info)),
+        Some(_) => checks.push(check_v1_controllers(info)),
+        None => {}
+    }
+    checks.push(check_userns(info));
+    if info.rootless() {
+        checks.push(check_idmap(info));
+        checks.push(check_network(info));
+    }
+    checks.push(check_overlayfs(info, snapshotter));
+    checks
+}
+
+fn check_cgroups(info: &HostInfo) -> Check {
+    const NAME: &str = "cgroups";
+    let v1 = |mode: &str| Check::failed(
+        NAME,
+        CheckStatus::Warning,
+        format!("cgroup v1 ({}): memory, cpu and pids limits are applied, cpuset and I/O limits are not", mode),
+        "Boot with systemd.unified_cgroup_hierarchy=1 on the kernel command line to switch to cgroup v2",
+    );
+    match info.cgroup_mode {
+        Some(CgroupMode::Unified) => Check::ok(NAME, "cgroup v2 (unified hierarchy)"),
+        Some(CgroupMode::Hybrid) => v1("hybrid hierarchy"),
+        Some(CgroupMode::Legacy) => v1("legacy hierarchies"),
+        None => Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            "no cgroup hierarchy mounted: resource limits are not applied",
+            "Mount cgroup2: mount -t cgroup2 none /sys/fs/cgroup",
+        ),
+    }
+}
+
+fn check_controllers(info: &HostInfo) -> Check {
+    const NAME: &str = "cgroup controllers";
+    let unavailable: Vec<&str> = CONTROLLERS
+        .iter()
+        .copied()
+        .filter(|c| !info.controllers.iter().any(|a| a == c))
+        .collect();
+    if !unavailable.is_empty() {
+        return Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            format!("not provided by the kernel: {}", unavailable.join(", ")),
+            "Enable them in the kernel configuration or remove cgroup_disable= from the kernel command line",
+        );
+    }
+
+    // Root enables them itself when creating the cubo cgroup
+    let undelegated: Vec<&str> = CONTROLLERS
+        .iter()
+        .copied()
+        .filter(|c| !info.delegated.iter().any(|d| d == c))
+        .collect();
+    if info.rootless() && !undelegated.is_empty() {
+        return Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            format!("not delegated: {}", undelegated.join(", ")),
+            "Delegate them to users: add Delegate=yes to a drop-in of user@.service (systemctl edit user@.service) and log in again",
+        );
+    }
+    Check::ok(NAME, CONTROLLERS.join(", "))
+}
+
+/// On cgroup v1 only root can create cgroups in the controller hierarchies
+fn check_v1_controllers(info: &HostInfo) -> Check {
+    const NAME: &str = "cgroup controllers";
+    let unmounted: Vec<&str> = V1_CONTROLLERS
+        .iter()
+        .copied()
+        .filter(|c| !info.controllers.iter().any(|a| a == c))
+        .collect();
+    if !unmounted.is_empty() {
+        return Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            format!("no v1 hierarchy: {}", unmounted.join(", ")),
+            "Mount them, e.g. mount -t cgroup -o memory none /sys/fs/cgroup/memory",
+        );
+    }
+    if info.rootless() {
+        return Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            "cgroup v1 can't be delegated: rootless containers run without limits",
+            "Switch to cgroup v2, or run containers as root",
+        );
+    }
+    Check::ok(NAME, V1_CONTROLLERS.join(", "))
+}
+
+fn check_userns(info: &HostInfo) -> Check {
+    const NAME: &str = "user namespaces";
+    let status = if info.rootless() { CheckStatus::Error } else { CheckStatus::Warning };
+    match info.max_user_namespaces {
+        Some(0) => Check::failed(
+            NAME,
+            status,
+            "disabled (user.max_user_namespaces = 0)",
+            "Enable them: sysctl -w user.max_user_namespaces=15000, and persist it in /etc/sysctl.d",
+        ),
+        Some(max) => Check::ok(NAME, format!("max_user_namespaces = {}", max)),
+        None => Check::failed(
+            NAME,
+            status,
+            "not supported by the kernel",
+            "Use a kernel built with CONFIG_USER_NS=y",
+        ),
+    }
+}
+
+fn check_idmap(info: &HostInfo) -> Check {
+    const NAME: &str = "id mapping";
+    if info.newuidmap.is_none() || info.newgidmap.is_none() {
+        return Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            "newuidmap/newgidmap not found: containers only see a single uid and gid",
+            "Install the uidmap package (shadow-utils on Fedora)",
+        );
+    }
+    if !info.subids {
+        return Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            "no subordinate ids: containers only see a single uid and gid",
+            format!("Add ranges for the user: usermod --add-subuids 100000-165535 --add-subgids 100000-165535 $(id -un) (uid {})", info.uid),
+        );
+    }
+    Check::ok(NAME, "newuidmap, newgidmap and subordinate ids")
+}
+
+fn check_network(info: &HostInfo) -> Check {
+    const NAME: &str = "rootless network";
+    match info.network_driver {
+        Some(ref driver) => Check::ok(NAME, driver.name()),
+        None => Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            "no usermode network stack: bridge containers only get loopback",
+            "Install pasta (passt) or slirp4netns",
+        ),
+    }
+}
+
+fn check_overlayfs(info: &HostInfo, snapshotter: SnapshotterKind) -> Check {
+    const NAME: &str = "overlayfs";
+    if info.overlayfs {
+        return Check::ok(NAME, "supported");
+    }
+    if snapshotter == SnapshotterKind::Overlayfs {
+        Check::failed(
+            NAME,
+            CheckStatus::Error,
+            "not supported, but the overlayfs snapshotter is selected",
+            "Load the module (modprobe overlay) or use --snapshotter dir",
+        )
+    } else {
+        Check::failed(
+            NAME,
+            CheckStatus::Warning,
+            "not supported: only the dir and btrfs snapshotters are available",
+            "Load the module: modprobe overlay",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(uid: u32) -> HostInfo {
+        HostInfo {
+            kernel: "6.8.0".to_string(),
+            uid,
+            cgroup_mode: Some(CgroupMode::Unified),
+            controllers: parse_controllers("cpuset cpu io memory hugetlb pids rdma misc\n"),
+            delegated: parse_controllers("cpuset cpu io memory pids\n"),
+            max_user_namespaces: Some(63000),
+            newuidmap: Some(PathBuf::from("/usr/bin/newuidmap")),
+            newgidmap: Some(PathBuf::from("/usr/bin/newgidmap")),
+            subids: true,
+            overlayfs: true,
+            network_driver: Some(UsermodeDriver::Pasta(PathBuf::from("/usr/bin/pasta"))),
+        }
+    }
+
+    #[test]
+    fn test_has_filesystem() {
+        let filesystems = "nodev\tsysfs\nnodev\ttmpfs\n\text4\nnodev\toverlay\n";
+        assert!(has_filesystem(filesystems, "overlay"));
+        assert!(has_filesystem(filesystems, "ext4"));
+        assert!(!has_filesystem(filesystems, "btrfs"));
+    }
+
+    #[test]
+    fn test_checks_pass_on_capable_host() {
+        let checks = run_checks(&host(1000), SnapshotterKind::Dir);
+        assert_eq!(checks.len(), 6);
+        assert!(checks.iter().all(|c| c.status == CheckStatus::Ok), "{:?}", checks);
+
+        // Root doesn't need id mapping or a usermode network stack
+        assert_eq!(run_checks(&host(0), SnapshotterKind::Dir).len(), 4);
+    }
+
+    #[test]
+    fn test_checks_report_fixes() {
+        let mut info = host(1000);
+        info.delegated = parse_controllers("memory pids");
+        info.max_user_namespaces = Some(0);
+        info.newuidmap = None;
+        info.network_driver = None;
+        info.overlayfs = false;
+
+        let checks = run_checks(&info, SnapshotterKind::Overlayfs);
+        let by_name = |name: &str| checks.iter().find(|c| c.name == name).unwrap();
+        assert_eq!(by_name("cgroup controllers").status, CheckStatus::Warning);
+        assert!(by_name("cgroup controllers").detail.contains("io, cpu"));
+        assert_eq!(by_name("user namespaces").status, CheckStatus::Error);
+        assert_eq!(by_name("id mapping").status, CheckStatus::Warning);
+        assert_eq!(by_name("rootless network").status, CheckStatus::Warning);
+        assert_eq!(by_name("overlayfs").status, CheckStatus::Error);
+        assert!(checks.iter().filter(|c| c.status != CheckStatus::Ok).all(|c| c.fix.is_some()));
+    }
+
+    #[test]
+    fn test_cgroup_v1_controllers() {
+        let mut info = host(0);
+        info.cgroup_mode = Some(CgroupMode::Hybrid);
+        info.controllers = parse_controllers("memory cpu pids");
+        let checks = run_checks(&info, SnapshotterKind::Dir);
+        assert_eq!(checks[0].status, CheckStatus::Warning);
+        assert!(checks[0].detail.contains("hybrid"));
+        assert_eq!(checks[1], Check::ok("cgroup controllers", "memory, cpu, pids"));
+
+        info.controllers = parse_controllers("memory");
+        let checks = run_checks(&info, SnapshotterKind::Dir);
+        assert!(checks[1].detail.contains("cpu, pids"));
+
+        let mut info = host(1000);
+        info.cgroup_mode = Some(CgroupMode::Legacy);
+        info.controllers = parse_controllers("memory cpu pids");
+        let checks = run_checks(&info, SnapshotterKind::Dir);
+        assert_eq!(checks[1].status, CheckStatus::Warning);
+    }
+}
@@ -0,0 +1,114 @@
+//! Space used on disk by directory trees, for `cubo system df`
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Sums the blocks allocated to trees of files. A file hard linked into several trees
+/// (hardlink-provisioned rootfs) is only counted the first time it is seen.
+#[derive(Debug, Default)]
+pub struct UsageCounter {
+    seen: HashSet<(u64, u64)>,
+}
+
+impl UsageCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes used by `path` and everything below it on the same filesystem
+    pub fn size(&mut self, path: &Path) -> u64 {
+        self.size_excluding(path, &[])
+    }
+
+    /// Like `size`, skipping the direct children of `path` named in `skip`
+    pub fn size_excluding(&mut self, path: &Path, skip: &[&str]) -> u64 {
+        match fs::symlink_metadata(path) {
+            Ok(meta) => self.walk(path, &meta, meta.dev(), skip),
+            Err(_) => 0,
+        }
+    }
+
+    fn walk(&mut self, path: &Path, meta: &fs::Metadata, dev: u64, skip: &[&str]) -> u64 {
+        let mut total = 0;
+        if meta.nlink() <= 1 || meta.is_dir() || self.seen.insert((meta.dev(), meta.ino())) {
+            total += meta.blocks() * 512;
+        }
+        if !meta.is_dir() {
+            return total;
+        }
+
+        let Ok(entries) = fs::read_dir(path) else {
+            return total;
+        };
+        for entry in entries.flatten() {
+            if skip.iter().any(|name| entry.file_name() == *name) {
+                continue;
+            }
+            let Ok(child) = entry.metadata() else {
+                continue;
+            };
+            // Don't descend into filesystems mounted below, such as a container's rootfs
+            if child.is_dir() && child.dev() != dev {
+                continue;
+            }
+            total += self.walk(&entry.path(), &child, dev, &[]);
+        }
+        total
+    }
+}
+
+/// Human readable size in powers of 1024, e.g. "1.5GB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_size_counts_hardlinks_once() {
+        let temp = TempDir::new().unwrap();
+        let data = temp.path().join("data");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join("sub/big"), vec![1u8; 64 * 1024]).unwrap();
+        fs::write(data.join("skipped"), vec![1u8; 64 * 1024]).unwrap();
+
+        let mut counter = UsageCounter::new();
+        let all = counter.size(&data);
+        assert!(all >= 128 * 1024);
+        let without = UsageCounter::new().size_excluding(&data, &["skipped"]);
+        assert!(without >= 64 * 1024 && without < all);
+
+        let copy = temp.path().join("copy");
+        fs::create_dir_all(&copy).unwrap();
+        fs::hard_link(data.join("sub/big"), copy.join("big")).unwrap();
+        let mut counter = UsageCounter::new();
+        counter.size(&data);
+        assert!(counter.size(&copy) < 64 * 1024);
+
+        assert_eq!(UsageCounter::new().size(&temp.path().join("missing")), 0);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0B");
+        assert_eq!(format_size(1023), "1023B");
+        assert_eq!(format_size(1536), "1.5kB");
+        assert_eq!(format_size(10 * 1024 * 1024 * 1024), "10.0GB");
+    }
+}
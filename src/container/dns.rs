@@ -0,0 +1,265 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::container::{Container, HostEntry};
+use crate::error::{CuboError, Result};
+
+/// Used when the host only lists loopback resolvers, which are unreachable from the container
+const FALLBACK_NAMESERVERS: &[&str] = &["8.8.8.8", "8.8.4.4"];
+
+/// resolv.conf used by systemd-resolved behind its 127.0.0.53 stub
+const SYSTEMD_RESOLV_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// Parse "--add-host" values of the form "host:ip"
+pub fn parse_host_entry(spec: &str) -> Result<HostEntry> {
+    let invalid = || CuboError::InvalidConfiguration(format!("Invalid host entry '{}', expected <host>:<ip>", spec));
+
+    let (hostname, ip) = spec.split_once(':').ok_or_else(invalid)?;
+    if hostname.is_empty() || hostname.contains(char::is_whitespace) {
+        return Err(invalid());
+    }
+    ip.parse::<IpAddr>().map_err(|_| invalid())?;
+
+    Ok(HostEntry { hostname: hostname.to_string(), ip: ip.to_string() })
+}
+
+pub fn validate_nameserver(server: &str) -> Result<()> {
+    server
+        .parse::<IpAddr>()
+        .map(|_| ())
+        .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid DNS server address: {}", server)))
+}
+
+/// Read the host resolver configuration, looking past the systemd-resolved stub
+pub fn host_resolv_conf() -> String {
+    let primary = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    if nameservers(&primary).iter().all(|ns| is_loopback(ns)) {
+        if let Ok(upstream) = fs::read_to_string(SYSTEMD_RESOLV_CONF) {
+            return upstream;
+        }
+    }
+    primary
+}
+
+fn nameservers(resolv_conf: &str) -> Vec<String> {
+    resolv_conf
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some("nameserver"), Some(server)) => Some(server.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn is_loopback(server: &str) -> bool {
+    server.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+/// Build the container's resolv.conf. Explicit --dns/--dns-search win over the host values;
+/// loopback resolvers from the host are dropped.
+pub fn build_resolv_conf(container: &Container, host_resolv: &str) -> String {
+    let config = &container.config;
+
+    let servers: Vec<String> = if !config.dns.is_empty() {
+        config.dns.clone()
+    } else {
+        let host: Vec<String> = nameservers(host_resolv).into_iter().filter(|s| !is_loopback(s)).collect();
+        if host.is_empty() {
+            FALLBACK_NAMESERVERS.iter().map(|s| s.to_string()).collect()
+        } else {
+            host
+        }
+    };
+
+    let search: Vec<String> = if !config.dns_search.is_empty() {
+        config.dns_search.clone()
+    } else {
+        host_resolv
+            .lines()
+            .filter_map(|line| line.strip_prefix("search "))
+            .flat_map(|domains| domains.split_whitespace().map(String::from))
+            .collect()
+    };
+
+    let mut out = String::new();
+    if !search.is_empty() {
+        out.push_str(&format!("search {}\n", search.join(" ")));
+    }
+    for server in servers {
+        out.push_str(&format!("nameserver {}\n", server));
+    }
+    out
+}
+
+/// Build the container's /etc/hosts: localhost, the container itself (by its fully qualified
+/// name first when it has a domain name), --add-host entries, then the named containers
+/// sharing a network with it
+pub fn build_hosts(container: &Container, ip: Option<&str>, peers: &[HostEntry]) -> String {
+    let mut out = String::from("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n");
+
+    if let Some(hostname) = container.hostname() {
+        let names = match container.config.domainname {
+            Some(ref domainname) => format!("{}.{} {}", hostname, domainname, hostname),
+            None => hostname,
+        };
+        out.push_str(&format!("{}\t{}\n", ip.unwrap_or("127.0.1.1"), names));
+    }
+
+    for entry in container.config.extra_hosts.iter().chain(peers) {
+        out.push_str(&format!("{}\t{}\n", entry.ip, entry.hostname));
+    }
+    out
+}
+
+/// Write /etc/resolv.conf, /etc/hostname and /etc/hosts into the rootfs, replacing whatever
+/// the image shipped
+pub fn write_network_files(rootfs: &Path, container: &Container, ip: Option<&str>, peers: &[HostEntry]) -> Result<()> {
+    let etc = rootfs.join("etc");
+    fs::create_dir_all(&etc)
+        .map_err(|e| CuboError::SystemError(format!("Failed to create {:?}: {}", etc, e)))?;
+
+    replace_file(&etc.join("resolv.conf"), &build_resolv_conf(container, &host_resolv_conf()))?;
+    if let Some(hostname) = container.hostname() {
+        replace_file(&etc.join("hostname"), &format!("{}\n", hostname))?;
+    }
+    write_hosts(rootfs, container, ip, peers)
+}
+
+/// Rewrite only /etc/hosts, e.g. after the container joined or left a network
+pub fn write_hosts(rootfs: &Path, container: &Container, ip: Option<&str>, peers: &[HostEntry]) -> Result<()> {
+    replace_file(&rootfs.join("etc/hosts"), &build_hosts(container, ip, peers))
+}
+
+/// Images often ship these as symlinks; unlink first so we never write through
+/// an absolute link onto the host.
+fn replace_file(path: &Path, content: &str) -> Result<()> {
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)
+            .map_err(|e| CuboError::SystemError(format!("Failed to remove {:?}: {}", path, e)))?;
+    }
+    fs::write(path, content)
+        .map_err(|e| CuboError::SystemError(format!("Failed to write {:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn container() -> Container {
+        Container::new("test:latest".to_string(), vec!["sh".to_string()])
+    }
+
+    #[test]
+    fn test_parse_host_entry() {
+        let entry = parse_host_entry("db.local:10.0.0.5").unwrap();
+        assert_eq!(entry.hostname, "db.local");
+        assert_eq!(entry.ip, "10.0.0.5");
+    }
+
+    #[test]
+    fn test_parse_host_entry_ipv6() {
+        let entry = parse_host_entry("v6host:::1").unwrap();
+        assert_eq!(entry.hostname, "v6host");
+        assert_eq!(entry.ip, "::1");
+    }
+
+    #[test]
+    fn test_parse_host_entry_invalid() {
+        assert!(parse_host_entry("nohost").is_err());
+        assert!(parse_host_entry(":10.0.0.1").is_err());
+        assert!(parse_host_entry("host:not-an-ip").is_err());
+    }
+
+    #[test]
+    fn test_validate_nameserver() {
+        assert!(validate_nameserver("1.1.1.1").is_ok());
+        assert!(validate_nameserver("2001:4860:4860::8888").is_ok());
+        assert!(validate_nameserver("dns.google").is_err());
+    }
+
+    #[test]
+    fn test_resolv_conf_from_host() {
+        let host = "search corp.example\nnameserver 10.0.0.2\nnameserver 127.0.0.53\n";
+        let out = build_resolv_conf(&container(), host);
+        assert_eq!(out, "search corp.example\nnameserver 10.0.0.2\n");
+    }
+
+    #[test]
+    fn test_resolv_conf_loopback_only_host_falls_back() {
+        let out = build_resolv_conf(&container(), "nameserver 127.0.0.53\n");
+        assert!(out.contains("nameserver 8.8.8.8"));
+        assert!(!out.contains("127.0.0.53"));
+    }
+
+    #[test]
+    fn test_resolv_conf_explicit_dns_overrides_host() {
+        let mut c = container();
+        c.config.dns = vec!["1.1.1.1".to_string()];
+        c.config.dns_search = vec!["svc.local".to_string()];
+        let out = build_resolv_conf(&c, "search corp.example\nnameserver 10.0.0.2\n");
+        assert_eq!(out, "search svc.local\nnameserver 1.1.1.1\n");
+    }
+
+    #[test]
+    fn test_build_hosts() {
+        let mut c = container();
+        c.config.hostname = Some("web".to_string());
+        c.config.extra_hosts.push(HostEntry { hostname: "db".to_string(), ip: "10.0.0.5".to_string() });
+
+        let out = build_hosts(&c, Some("10.89.1.2"), &[]);
+        assert!(out.starts_with("127.0.0.1\tlocalhost\n"));
+        assert!(out.contains("10.89.1.2\tweb\n"));
+        assert!(out.contains("10.0.0.5\tdb\n"));
+    }
+
+    #[test]
+    fn test_build_hosts_defaults_to_short_id() {
+        let c = container();
+        let out = build_hosts(&c, None, &[]);
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.ends_with(&format!("127.0.1.1\t{}\n", c.short_id())));
+    }
+
+    #[test]
+    fn test_build_hosts_domainname() {
+        let c = container().with_hostname("web".to_string()).with_domainname("example.com".to_string());
+        let out = build_hosts(&c, Some("10.89.1.2"), &[]);
+        assert!(out.contains("10.89.1.2\tweb.example.com web\n"));
+    }
+
+    #[test]
+    fn test_build_hosts_peers_after_extra_hosts() {
+        let mut c = container();
+        c.config.extra_hosts.push(HostEntry { hostname: "db".to_string(), ip: "10.0.0.5".to_string() });
+        let peers = vec![HostEntry { hostname: "db".to_string(), ip: "10.89.1.3".to_string() }];
+
+        let out = build_hosts(&c, None, &peers);
+        let explicit = out.find("10.0.0.5\tdb").unwrap();
+        let peer = out.find("10.89.1.3\tdb").unwrap();
+        assert!(explicit < peer);
+    }
+
+    #[test]
+    fn test_write_network_files_replaces_symlinks() {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        let outside = temp.path().join("outside");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(&outside, "untouched").unwrap();
+        std::os::unix::fs::symlink(&outside, rootfs.join("etc/resolv.conf")).unwrap();
+
+        write_network_files(&rootfs, &container().with_hostname("web".to_string()), None, &[]).unwrap();
+
+        assert_eq!(fs::read_to_string(&outside).unwrap(), "untouched");
+        let resolv = rootfs.join("etc/resolv.conf");
+        assert!(!resolv.symlink_metadata().unwrap().file_type().is_symlink());
+        assert!(fs::read_to_string(&resolv).unwrap().contains("nameserver"));
+        assert!(rootfs.join("etc/hosts").exists());
+        assert_eq!(fs::read_to_string(rootfs.join("etc/hostname")).unwrap(), "web\n");
+    }
+}
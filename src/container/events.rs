@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Events a slow subscriber can fall behind by before it starts missing some
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Create,
+    Start,
+    Stop,
+    /// The container's process was found dead without its exit being recorded
+    Die,
+    Remove,
+    Update,
+    Checkpoint,
+    Restore,
+    Pull,
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            EventKind::Create => "create",
+            EventKind::Start => "start",
+            EventKind::Stop => "stop",
+            EventKind::Die => "die",
+            EventKind::Remove => "remove",
+            EventKind::Update => "update",
+            EventKind::Checkpoint => "checkpoint",
+            EventKind::Restore => "restore",
+            EventKind::Pull => "pull",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something that happened to a container (or, for pulls, an image)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeEvent {
+    pub kind: EventKind,
+    /// Container ID, or the image reference for pulls
+    pub id: String,
+    pub time: DateTime<Utc>,
+}
+
+/// Fan-out of runtime events to whoever is watching; publishing never blocks
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<RuntimeEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, kind: EventKind, id: &str) {
+        // No subscribers is not an error
+        let _ = self.sender.send(RuntimeEvent { kind, id: id.to_string(), time: Utc::now() });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RuntimeEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_reaches_subscribers() {
+        let bus = EventBus::default();
+        let mut first = bus.subscribe();
+        let mut second = bus.clone().subscribe();
+
+        bus.publish(EventKind::Start, "abc");
+        for receiver in [&mut first, &mut second] {
+            let event = receiver.recv().await.unwrap();
+            assert_eq!(event.kind, EventKind::Start);
+            assert_eq!(event.id, "abc");
+        }
+    }
+
+    #[test]
+    fn test_publish_without_subscribers() {
+        EventBus::default().publish(EventKind::Create, "abc");
+    }
+
+    #[test]
+    fn test_event_kind_display() {
+        assert_eq!(EventKind::Checkpoint.to_string(), "checkpoint");
+        assert_eq!(serde_json::to_string(&EventKind::Pull).unwrap(), "\"pull\"");
+    }
+}
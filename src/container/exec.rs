@@ -0,0 +1,262 @@
+//! Running extra processes in a running container by entering its namespaces with nsenter,
+//! or host binaries in some of them for `cubo enter`
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::os::fd::AsFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use nix::sched::{setns, CloneFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fexecve, fork, ForkResult};
+
+use crate::container::checkpoint::container_init_pid;
+use crate::container::rootless_net::find_in_path;
+use crate::container::{selinux, Container};
+use crate::error::{CuboError, Result};
+
+pub const NSENTER: &str = "nsenter";
+
+/// PATH of exec'd processes unless the container sets its own
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Enter every namespace of `target`, its root and its working directory, then run `command`
+pub fn nsenter_args(target: u32, command: &[String]) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "--target".into(),
+        target.to_string(),
+        "--all".into(),
+        "--root".into(),
+        "--wd".into(),
+        "--".into(),
+    ];
+    args.extend(command.iter().cloned());
+    args
+}
+
+/// The container's environment with `extra` on top
+pub fn exec_env(container: &Container, extra: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut env = HashMap::from([("PATH".to_string(), DEFAULT_PATH.to_string())]);
+    env.extend(container.config.env_vars.clone());
+    env.extend(extra.clone());
+    env
+}
+
+/// Prepare `command` to run inside `container`, which has to be running
+pub fn exec_command(
+    container: &Container,
+    command: &[String],
+    env: &HashMap<String, String>,
+) -> Result<tokio::process::Command> {
+    if command.is_empty() {
+        return Err(CuboError::InvalidConfiguration("Exec needs a command".to_string()));
+    }
+    let pid = match (container.is_running(), container.pid) {
+        (true, Some(pid)) => pid,
+        _ => return Err(CuboError::ContainerNotRunning(container.id.clone())),
+    };
+    let target = container_init_pid(pid)?;
+
+    let mut cmd = tokio::process::Command::new(NSENTER);
+    // Run with the SELinux label of the container process, not the one of cubo
+    if selinux::container_labels(container).is_some() {
+        cmd.arg("--follow-context");
+    }
+    cmd.args(nsenter_args(target, command))
+        .env_clear()
+        .envs(exec_env(container, env))
+        .kill_on_drop(true);
+    Ok(cmd)
+}
+
+/// A namespace `cubo enter` can join
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    User,
+    Mount,
+    Uts,
+    Ipc,
+    Net,
+    Cgroup,
+    Pid,
+}
+
+impl Namespace {
+    /// In the order they are joined: the user namespace first since it grants the rights to
+    /// join the others, the mount namespace before the ones that would hide its /proc
+    pub const ALL: [Namespace; 7] = [
+        Namespace::User,
+        Namespace::Mount,
+        Namespace::Uts,
+        Namespace::Ipc,
+        Namespace::Net,
+        Namespace::Cgroup,
+        Namespace::Pid,
+    ];
+
+    /// Name of the namespace under /proc/PID/ns
+    pub fn proc_name(&self) -> &'static str {
+        match self {
+            Namespace::User => "user",
+            Namespace::Mount => "mnt",
+            Namespace::Uts => "uts",
+            Namespace::Ipc => "ipc",
+            Namespace::Net => "net",
+            Namespace::Cgroup => "cgroup",
+            Namespace::Pid => "pid",
+        }
+    }
+
+    fn flag(&self) -> CloneFlags {
+        match self {
+            Namespace::User => CloneFlags::CLONE_NEWUSER,
+            Namespace::Mount => CloneFlags::CLONE_NEWNS,
+            Namespace::Uts => CloneFlags::CLONE_NEWUTS,
+            Namespace::Ipc => CloneFlags::CLONE_NEWIPC,
+            Namespace::Net => CloneFlags::CLONE_NEWNET,
+            Namespace::Cgroup => CloneFlags::CLONE_NEWCGROUP,
+            Namespace::Pid => CloneFlags::CLONE_NEWPID,
+        }
+    }
+}
+
+impl fmt::Display for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.proc_name())
+    }
+}
+
+impl FromStr for Namespace {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Namespace::ALL
+            .into_iter()
+            .find(|ns| ns.proc_name() == s || (*ns == Namespace::Mount && s == "mount"))
+            .ok_or_else(|| format!("Unknown namespace: {}", s))
+    }
+}
+
+/// Prepare a host binary to run in the `namespaces` of process `target`. The binary is
+/// opened on the host before anything is joined, so the container's filesystem needn't have
+/// it; with the mount namespace joined, a dynamically linked one still needs its loader and
+/// libraries in there. Namespaces `target` shares with cubo are left alone.
+pub fn enter_command(target: u32, namespaces: &[Namespace], command: &[String]) -> Result<std::process::Command> {
+    let Some(program) = command.first() else {
+        return Err(CuboError::InvalidConfiguration("Enter needs a command".to_string()));
+    };
+    let path = if program.contains('/') {
+        PathBuf::from(program)
+    } else {
+        find_in_path(program, &std::env::var("PATH").unwrap_or_default())
+            .ok_or_else(|| CuboError::CommandNotFound(program.clone()))?
+    };
+    let binary = File::open(&path)
+        .map_err(|e| CuboError::CommandNotInvocable { command: program.clone(), reason: e.to_string() })?;
+
+    let mut joined = Vec::new();
+    for namespace in Namespace::ALL.into_iter().filter(|ns| namespaces.contains(ns)) {
+        let theirs = format!("/proc/{}/ns/{}", target, namespace.proc_name());
+        let ours = format!("/proc/self/ns/{}", namespace.proc_name());
+        if fs::read_link(&theirs).ok() == fs::read_link(&ours).ok() {
+            continue;
+        }
+        let file = File::open(&theirs)
+            .map_err(|e| CuboError::NamespaceError(format!("Failed to open {}: {}", theirs, e)))?;
+        joined.push((namespace, file));
+    }
+
+    let args = to_cstrings(command.iter().cloned())?;
+    let env = to_cstrings(std::env::vars().map(|(key, value)| format!("{}={}", key, value)))?;
+    let mut cmd = std::process::Command::new(Path::new(program));
+    unsafe {
+        // The forked child is single threaded, which joining a mount or user namespace needs
+        cmd.pre_exec(move || {
+            for (namespace, file) in &joined {
+                setns(file.as_fd(), namespace.flag())?;
+            }
+            // Only children are put in a joined PID namespace
+            if joined.iter().any(|(namespace, _)| *namespace == Namespace::Pid) {
+                if let ForkResult::Parent { child } = fork()? {
+                    let code = match waitpid(child, None) {
+                        Ok(WaitStatus::Exited(_, code)) => code,
+                        Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+                        _ => 1,
+                    };
+                    libc::_exit(code);
+                }
+            }
+            fexecve(binary.as_fd(), &args, &env)?;
+            Ok(())
+        });
+    }
+    Ok(cmd)
+}
+
+fn to_cstrings(strings: impl Iterator<Item = String>) -> Result<Vec<CString>> {
+    strings
+        .map(|s| CString::new(s).map_err(|e| CuboError::InvalidConfiguration(e.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerStatus;
+
+    #[test]
+    fn test_nsenter_args() {
+        let args = nsenter_args(42, &["ls".to_string(), "-l".to_string()]);
+        assert_eq!(args, ["--target", "42", "--all", "--root", "--wd", "--", "ls", "-l"]);
+    }
+
+    #[test]
+    fn test_exec_env_layers() {
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()])
+            .with_env("PATH".to_string(), "/custom".to_string())
+            .with_env("A".to_string(), "1".to_string());
+        let extra = HashMap::from([("A".to_string(), "2".to_string())]);
+        let env = exec_env(&container, &extra);
+        assert_eq!(env["PATH"], "/custom");
+        assert_eq!(env["A"], "2");
+
+        let plain = Container::new("test:latest".to_string(), vec!["sh".to_string()]);
+        assert_eq!(exec_env(&plain, &HashMap::new())["PATH"], DEFAULT_PATH);
+    }
+
+    #[test]
+    fn test_exec_command_requires_running_container() {
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()]);
+        let err = exec_command(&container, &["ls".to_string()], &HashMap::new()).unwrap_err();
+        assert!(matches!(err, CuboError::ContainerNotRunning(_)));
+
+        let mut running = container.clone();
+        running.update_status(ContainerStatus::Running);
+        let err = exec_command(&running, &[], &HashMap::new()).unwrap_err();
+        assert!(matches!(err, CuboError::InvalidConfiguration(_)));
+    }
+
+    #[test]
+    fn test_namespace_parse() {
+        assert_eq!("net".parse::<Namespace>().unwrap(), Namespace::Net);
+        assert_eq!("mount".parse::<Namespace>().unwrap(), Namespace::Mount);
+        assert_eq!(Namespace::Mount.to_string(), "mnt");
+        assert!("time".parse::<Namespace>().is_err());
+    }
+
+    #[test]
+    fn test_enter_command_runs_host_binary() {
+        // Every namespace of our own process is shared, so nothing is joined
+        let target = std::process::id();
+        let command = ["sh".to_string(), "-c".to_string(), "exit 7".to_string()];
+        let status = enter_command(target, &Namespace::ALL, &command).unwrap().status().unwrap();
+        assert_eq!(status.code(), Some(7));
+
+        let err = enter_command(target, &[], &["no-such-binary-here".to_string()]).unwrap_err();
+        assert!(matches!(err, CuboError::CommandNotFound(_)));
+    }
+}
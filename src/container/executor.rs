@@ -0,0 +1,308 @@
+//! Backends that create and signal container processes: cubo's own namespace setup, or an
+//! OCI runtime such as runc or crun driven through the exported bundle
+
+use std::fs;
+use std::future::Future;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Command, Stdio};
+
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::container::container_store::atomic_write_json;
+use crate::container::oci_spec::{build_spec, SpecHost};
+use crate::container::runtime::{ContainerRuntime, ExecutionContext};
+use crate::container::{dns, logging, network, rootless_net, apparmor, selinux, Container, NetworkMode};
+use crate::error::{CuboError, Result};
+
+/// Which backend runs the processes of a container
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExecutorKind {
+    /// cubo's own fork/unshare/pivot_root implementation
+    #[default]
+    Native,
+    /// An OCI runtime binary (runc, crun or a path to one)
+    Oci(String),
+}
+
+impl std::str::FromStr for ExecutorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(ExecutorKind::Native),
+            "" => Err("executor name is empty".to_string()),
+            runtime => Ok(ExecutorKind::Oci(runtime.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutorKind::Native => write!(f, "native"),
+            ExecutorKind::Oci(runtime) => write!(f, "{}", runtime),
+        }
+    }
+}
+
+pub type ExecFuture<'a> = Pin<Box<dyn Future<Output = Result<i32>> + Send + 'a>>;
+
+pub trait Executor: Send + Sync {
+    /// Start the container process. Attached runs resolve to its exit code, detached
+    /// runs to 0 once it is running.
+    fn start<'a>(&'a self, runtime: &'a ContainerRuntime, ctx: &'a ExecutionContext) -> ExecFuture<'a>;
+
+    /// Deliver `signal` to the container's init process
+    fn signal(&self, container: &Container, signal: Signal) -> Result<()>;
+
+    /// Drop what the backend keeps about a container that is being removed
+    fn delete(&self, container: &Container) -> Result<()>;
+}
+
+/// The backend recorded for `container`, with backend state kept under `root_dir`
+pub fn executor_for(kind: &ExecutorKind, root_dir: &Path) -> Box<dyn Executor> {
+    match kind {
+        ExecutorKind::Native => Box::new(NativeExecutor),
+        ExecutorKind::Oci(runtime) => Box::new(OciExecutor::new(runtime, root_dir)),
+    }
+}
+
+pub struct NativeExecutor;
+
+impl Executor for NativeExecutor {
+    fn start<'a>(&'a self, runtime: &'a ContainerRuntime, ctx: &'a ExecutionContext) -> ExecFuture<'a> {
+        Box::pin(runtime.create_isolated_process(ctx))
+    }
+
+    fn signal(&self, container: &Container, signal: Signal) -> Result<()> {
+        let pid = container.pid.ok_or_else(|| CuboError::ContainerNotRunning(container.id.clone()))?;
+        kill(Pid::from_raw(pid as i32), signal)
+            .map_err(|e| CuboError::SystemError(format!("Failed to send {} to container {}: {}", signal, container.id, e)))
+    }
+
+    fn delete(&self, _container: &Container) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs containers with `<runtime> create` and `start` on the bundle written to
+/// `<container dir>/oci`. cubo still wires the networks in between, once the
+/// namespaces exist but before the process runs.
+pub struct OciExecutor {
+    runtime: String,
+    /// --root of the runtime, so rootless runs don't need /run/runc
+    state_dir: PathBuf,
+}
+
+impl OciExecutor {
+    pub fn new(runtime: &str, root_dir: &Path) -> Self {
+        Self { runtime: runtime.to_string(), state_dir: root_dir.join("oci-state") }
+    }
+
+    /// Arguments of a runtime invocation, global options first
+    pub fn args(&self, command: &[&str]) -> Vec<String> {
+        let mut args = vec!["--root".to_string(), self.state_dir.to_string_lossy().to_string()];
+        args.extend(command.iter().map(|a| a.to_string()));
+        args
+    }
+
+    fn command(&self, command: &[&str]) -> Command {
+        let mut cmd = Command::new(&self.runtime);
+        cmd.args(self.args(command));
+        cmd
+    }
+
+    fn run(&self, command: &[&str]) -> Result<()> {
+        let output = self.command(command).stdin(Stdio::null()).output().map_err(|e| {
+            CuboError::SystemError(format!("Failed to run {}: {}", self.runtime, e))
+        })?;
+        if !output.status.success() {
+            return Err(CuboError::SystemError(format!(
+                "{} {} failed: {}",
+                self.runtime,
+                command[0],
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    async fn run_container(&self, runtime: &ContainerRuntime, ctx: &ExecutionContext) -> Result<i32> {
+        let container = &ctx.container;
+        let id = container.id.as_str();
+        let container_dir = runtime.root_dir().join(id);
+        let bundle = container_dir.join("oci");
+        if container.config.init {
+            warn!("--init is not supported by {}, running the command as pid 1", self.runtime);
+        }
+        if container.config.landlock {
+            warn!("Landlock is not supported by {}, running the container without it", self.runtime);
+        }
+
+        let sandbox_pid = runtime.sandbox_pid(container).await?;
+        let mut host = SpecHost::for_user(
+            nix::unistd::geteuid().as_raw(),
+            nix::unistd::getegid().as_raw(),
+            &container.config.userns_mode,
+        );
+        host.sandbox_pid = sandbox_pid;
+        host.labels = selinux::container_labels(container);
+        host.apparmor_profile = apparmor::profile_for(container);
+        atomic_write_json(&bundle.join("config.json"), &build_spec(container, &ctx.rootfs_path, &host)?)?;
+
+        let attachments = match sandbox_pid {
+            Some(_) => Vec::new(),
+            None => runtime.network_attachments(container)?,
+        };
+        if !matches!(container.config.network_mode, NetworkMode::Host) {
+            let ip = attachments.first().map(|(_, endpoint)| endpoint.ip.as_str());
+            let peers = runtime.peer_hosts(container).await;
+            dns::write_network_files(&ctx.rootfs_path, container, ip, &peers)?;
+        }
+        let usermode = match sandbox_pid {
+            Some(_) => None,
+            None => ContainerRuntime::usermode_driver(container),
+        };
+
+        // A crashed earlier run may have left the id registered with the runtime
+        let _ = self.run(&["delete", "--force", id]);
+
+        let pid_file = bundle.join("init.pid");
+        let mut create = self.command(&["create", "--bundle", &bundle.to_string_lossy(), "--pid-file", &pid_file.to_string_lossy(), id]);
+        if ctx.detach {
+            // In pass-through mode the container keeps these as its stdio
+            let (container, dir) = (container.clone(), container_dir.clone());
+            unsafe {
+                create.pre_exec(move || {
+                    logging::redirect_output(&container, &dir).map_err(std::io::Error::other)?;
+                    let devnull = fs::File::open("/dev/null")?;
+                    if libc::dup2(std::os::fd::AsRawFd::as_raw_fd(&devnull), 0) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        } else {
+            // The container init is reparented to us once `create` exits, so it can be waited for
+            if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        let status = create.status().map_err(|e| CuboError::SystemError(format!("Failed to run {}: {}", self.runtime, e)))?;
+        if !status.success() {
+            return Err(CuboError::SystemError(format!("{} create failed for container {}", self.runtime, id)));
+        }
+
+        let pid: u32 = fs::read_to_string(&pid_file)?.trim().parse().map_err(|_| {
+            CuboError::SystemError(format!("Invalid pid file {}", pid_file.display()))
+        })?;
+        runtime.set_container_pid(id, pid).await;
+
+        let primary_custom = matches!(container.config.network_mode, NetworkMode::Custom(_));
+        let wired = attachments.iter().enumerate().try_for_each(|(i, (network, endpoint))| {
+            network::attach_endpoint(network, endpoint, pid, primary_custom && i == 0)
+        }).and_then(|_| match usermode {
+            Some(ref driver) => rootless_net::start(driver, pid, &container.published_ports, &container_dir),
+            None => Ok(()),
+        });
+        if let Err(e) = wired.and_then(|_| self.run(&["start", id])) {
+            let _ = self.run(&["delete", "--force", id]);
+            return Err(e);
+        }
+        info!("Container {} started by {} with PID {}", id, self.runtime, pid);
+
+        if ctx.detach {
+            return Ok(0);
+        }
+        let status = tokio::task::spawn_blocking(move || waitpid(Pid::from_raw(pid as i32), None))
+            .await
+            .map_err(|e| CuboError::SystemError(format!("Failed to wait for container: {}", e)))?;
+        let _ = self.run(&["delete", "--force", id]);
+        match status {
+            Ok(WaitStatus::Exited(_, code)) => Ok(code),
+            Ok(WaitStatus::Signaled(_, signal, _)) => Ok(128 + signal as i32),
+            Ok(status) => {
+                warn!("Container {} exited with status: {:?}", id, status);
+                Ok(1)
+            }
+            Err(e) => Err(CuboError::SystemError(format!("Failed to wait for container: {}", e))),
+        }
+    }
+}
+
+impl Executor for OciExecutor {
+    fn start<'a>(&'a self, runtime: &'a ContainerRuntime, ctx: &'a ExecutionContext) -> ExecFuture<'a> {
+        Box::pin(self.run_container(runtime, ctx))
+    }
+
+    fn signal(&self, container: &Container, signal: Signal) -> Result<()> {
+        self.run(&["kill", &container.id, signal.as_str()])
+    }
+
+    fn delete(&self, container: &Container) -> Result<()> {
+        self.run(&["delete", "--force", &container.id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_executor_kind() {
+        assert_eq!("native".parse::<ExecutorKind>(), Ok(ExecutorKind::Native));
+        assert_eq!("crun".parse::<ExecutorKind>(), Ok(ExecutorKind::Oci("crun".to_string())));
+        assert!("".parse::<ExecutorKind>().is_err());
+        assert_eq!(ExecutorKind::Oci("/usr/bin/runc".to_string()).to_string(), "/usr/bin/runc");
+        assert_eq!(ExecutorKind::default().to_string(), "native");
+    }
+
+    #[test]
+    fn test_oci_args() {
+        let executor = OciExecutor::new("runc", Path::new("/var/lib/cubo"));
+        assert_eq!(executor.args(&["kill", "abc", "SIGTERM"]), ["--root", "/var/lib/cubo/oci-state", "kill", "abc", "SIGTERM"]);
+    }
+
+    #[test]
+    fn test_oci_signal_and_delete_invoke_runtime() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("calls");
+        let fake = temp.path().join("fake-runc");
+        fs::write(&fake, format!("#!/bin/sh\necho \"$@\" >> {}\n", log.display())).unwrap();
+        fs::set_permissions(&fake, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let executor = OciExecutor::new(&fake.to_string_lossy(), temp.path());
+        let container = Container::new("alpine".to_string(), vec![]);
+        executor.signal(&container, Signal::SIGTERM).unwrap();
+        executor.delete(&container).unwrap();
+
+        let state = temp.path().join("oci-state");
+        let calls = fs::read_to_string(&log).unwrap();
+        assert_eq!(calls, format!(
+            "--root {0} kill {1} SIGTERM\n--root {0} delete --force {1}\n",
+            state.display(),
+            container.id
+        ));
+    }
+
+    #[test]
+    fn test_oci_failure_is_reported() {
+        let executor = OciExecutor::new("/nonexistent/runc", Path::new("/tmp"));
+        let container = Container::new("alpine".to_string(), vec![]);
+        assert!(matches!(executor.delete(&container), Err(CuboError::SystemError(_))));
+    }
+
+    #[test]
+    fn test_native_signal_needs_pid() {
+        let container = Container::new("alpine".to_string(), vec![]);
+        assert!(matches!(NativeExecutor.signal(&container, Signal::SIGTERM), Err(CuboError::ContainerNotRunning(_))));
+    }
+}
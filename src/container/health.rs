@@ -0,0 +1,157 @@
+//! Health checks: a shell command exec'd in the running container whose exit status
+//! tells whether the service inside is ready
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::container::exec::exec_command;
+use crate::container::runtime::ContainerRuntime;
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthCheck {
+    /// Command run with /bin/sh -c inside the container; exit status 0 means healthy
+    pub command: String,
+    /// Seconds between two checks
+    pub interval: u64,
+    /// Seconds a check may run before it counts as failed
+    pub timeout: u64,
+    /// Consecutive failures after which the container is unhealthy
+    pub retries: u32,
+}
+
+impl HealthCheck {
+    pub fn new(command: String) -> Self {
+        Self {
+            command,
+            interval: 30,
+            timeout: 30,
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+/// Health of a container from the outcome of its checks so far
+#[derive(Debug)]
+pub struct HealthTracker {
+    retries: u32,
+    failures: u32,
+    status: HealthStatus,
+}
+
+impl HealthTracker {
+    pub fn new(check: &HealthCheck) -> Self {
+        Self { retries: check.retries.max(1), failures: 0, status: HealthStatus::Starting }
+    }
+
+    /// Record one check; a success resets the failure streak
+    pub fn record(&mut self, passed: bool) -> HealthStatus {
+        if passed {
+            self.failures = 0;
+            self.status = HealthStatus::Healthy;
+        } else {
+            self.failures += 1;
+            if self.failures >= self.retries {
+                self.status = HealthStatus::Unhealthy;
+            }
+        }
+        self.status
+    }
+}
+
+/// Run the check once in the running container
+pub async fn run_check(container: &Container, check: &HealthCheck) -> Result<bool> {
+    let command = ["/bin/sh".to_string(), "-c".to_string(), check.command.clone()];
+    let mut cmd = exec_command(container, &command, &HashMap::new())?;
+    cmd.stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+
+    let mut child = cmd.spawn()
+        .map_err(|e| CuboError::SystemError(format!("Failed to run health check: {}", e)))?;
+    match tokio::time::timeout(Duration::from_secs(check.timeout), child.wait()).await {
+        Ok(status) => Ok(status?.success()),
+        Err(_) => {
+            debug!("Health check of {} timed out after {}s", container.id, check.timeout);
+            Ok(false)
+        }
+    }
+}
+
+/// Run the container's health check until it passes. Fails once the container turns
+/// unhealthy or stops running.
+pub async fn wait_healthy(runtime: &ContainerRuntime, container_id: &str) -> Result<()> {
+    let container = runtime.get_container(container_id).await?;
+    let check = container.config.health_check.clone().ok_or_else(|| {
+        CuboError::InvalidConfiguration(format!("Container {} has no health check", container_id))
+    })?;
+    let mut tracker = HealthTracker::new(&check);
+
+    loop {
+        let container = runtime.get_container(container_id).await?;
+        if !container.is_running() {
+            return Err(CuboError::SystemError(format!(
+                "Container {} exited before becoming healthy",
+                container_id
+            )));
+        }
+
+        match tracker.record(run_check(&container, &check).await?) {
+            HealthStatus::Healthy => {
+                info!("Container {} is healthy", container_id);
+                return Ok(());
+            }
+            HealthStatus::Unhealthy => {
+                return Err(CuboError::SystemError(format!(
+                    "Container {} is unhealthy: health check failed {} times in a row",
+                    container_id, check.retries
+                )));
+            }
+            HealthStatus::Starting => tokio::time::sleep(Duration::from_secs(check.interval)).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker() {
+        let mut check = HealthCheck::new("true".to_string());
+        check.retries = 2;
+
+        let mut tracker = HealthTracker::new(&check);
+        assert_eq!(tracker.record(false), HealthStatus::Starting);
+        assert_eq!(tracker.record(true), HealthStatus::Healthy);
+
+        let mut tracker = HealthTracker::new(&check);
+        assert_eq!(tracker.record(false), HealthStatus::Starting);
+        assert_eq!(tracker.record(false), HealthStatus::Unhealthy);
+    }
+
+    #[test]
+    fn test_zero_retries_fails_on_first_check() {
+        let mut check = HealthCheck::new("true".to_string());
+        check.retries = 0;
+        assert_eq!(HealthTracker::new(&check).record(false), HealthStatus::Unhealthy);
+    }
+
+    #[tokio::test]
+    async fn test_run_check_requires_running_container() {
+        let container = Container::new("test:latest".to_string(), vec!["sh".to_string()]);
+        let check = HealthCheck::new("true".to_string());
+        assert!(matches!(run_check(&container, &check).await, Err(CuboError::ContainerNotRunning(_))));
+    }
+}
@@ -0,0 +1,236 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use nix::unistd::{Uid, User};
+use tracing::debug;
+
+use crate::container::rootless_net::find_in_path;
+use crate::container::UsernsMode;
+use crate::error::{CuboError, Result};
+
+pub(crate) const SUBUID_FILE: &str = "/etc/subuid";
+pub(crate) const SUBGID_FILE: &str = "/etc/subgid";
+
+/// One line of a uid_map/gid_map: `size` ids starting at `container_id` map to `host_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdRange {
+    pub container_id: u32,
+    pub host_id: u32,
+    pub size: u32,
+}
+
+/// Multi-range mapping for a rootless container, written by the setuid newuidmap/newgidmap helpers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdMapping {
+    pub uids: Vec<IdRange>,
+    pub gids: Vec<IdRange>,
+    pub newuidmap: PathBuf,
+    pub newgidmap: PathBuf,
+}
+
+/// Subordinate id ranges (start, count) granted to a user in /etc/subuid or /etc/subgid format.
+/// Entries may name the user or give the numeric id.
+pub fn parse_subid(content: &str, user: &str, id: u32) -> Vec<(u32, u32)> {
+    let id = id.to_string();
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.split(':');
+            let owner = parts.next()?;
+            let start = parts.next()?.parse().ok()?;
+            let count = parts.next()?.parse().ok()?;
+            (owner == user || owner == id).then_some((start, count))
+        })
+        .filter(|(_, count)| *count > 0)
+        .collect()
+}
+
+/// Map container root to the invoking id and the subordinate ranges right after it (1, 2, ...)
+pub fn build_ranges(id: u32, subids: &[(u32, u32)]) -> Vec<IdRange> {
+    let mut ranges = vec![IdRange { container_id: 0, host_id: id, size: 1 }];
+    let mut next = 1u32;
+    for &(start, count) in subids {
+        ranges.push(IdRange { container_id: next, host_id: start, size: count });
+        next = next.saturating_add(count);
+    }
+    ranges
+}
+
+/// Map the invoking id to itself and fill the ids around it from the subordinate ranges
+pub fn build_keep_id_ranges(id: u32, subids: &[(u32, u32)]) -> Vec<IdRange> {
+    let mut ranges = vec![IdRange { container_id: id, host_id: id, size: 1 }];
+    let mut next = 0u32;
+    for &(start, count) in subids {
+        let (mut host, mut left) = (start, count);
+        while left > 0 {
+            if next == id {
+                next = next.saturating_add(1);
+            }
+            let size = if next < id { left.min(id - next) } else { left };
+            ranges.push(IdRange { container_id: next, host_id: host, size });
+            next = next.saturating_add(size);
+            host = host.saturating_add(size);
+            left -= size;
+        }
+    }
+    ranges
+}
+
+/// Ranges for the given user namespace mode
+pub fn ranges_for(mode: &UsernsMode, id: u32, subids: &[(u32, u32)]) -> Vec<IdRange> {
+    match mode {
+        UsernsMode::KeepId => build_keep_id_ranges(id, subids),
+        _ => build_ranges(id, subids),
+    }
+}
+
+/// Contents for /proc/<pid>/uid_map or gid_map
+pub fn format_map(ranges: &[IdRange]) -> String {
+    ranges
+        .iter()
+        .map(|r| format!("{} {} {}\n", r.container_id, r.host_id, r.size))
+        .collect()
+}
+
+/// Arguments for newuidmap/newgidmap: `<pid> <inside> <outside> <count> ...`
+pub fn helper_args(pid: u32, ranges: &[IdRange]) -> Vec<String> {
+    let mut args = vec![pid.to_string()];
+    for r in ranges {
+        args.push(r.container_id.to_string());
+        args.push(r.host_id.to_string());
+        args.push(r.size.to_string());
+    }
+    args
+}
+
+/// Build a multi-range mapping for the given user if it has subordinate ids and the
+/// helpers are installed. Returns None for root, for the host user namespace, or when
+/// falling back to a single id.
+pub fn detect(uid: u32, gid: u32, mode: &UsernsMode) -> Option<IdMapping> {
+    if uid == 0 || *mode == UsernsMode::Host {
+        return None;
+    }
+
+    let path = std::env::var("PATH").unwrap_or_default();
+    let newuidmap = find_in_path("newuidmap", &path)?;
+    let newgidmap = find_in_path("newgidmap", &path)?;
+
+    let name = User::from_uid(Uid::from_raw(uid)).ok().flatten().map(|u| u.name).unwrap_or_default();
+    let subuids = parse_subid(&fs::read_to_string(SUBUID_FILE).unwrap_or_default(), &name, uid);
+    let subgids = parse_subid(&fs::read_to_string(SUBGID_FILE).unwrap_or_default(), &name, uid);
+    if subuids.is_empty() || subgids.is_empty() {
+        debug!("No subordinate ids for {} ({}), mapping a single id", name, uid);
+        return None;
+    }
+
+    Some(IdMapping {
+        uids: ranges_for(mode, uid, &subuids),
+        gids: ranges_for(mode, gid, &subgids),
+        newuidmap,
+        newgidmap,
+    })
+}
+
+/// Write the mapping of the user namespace of `pid` through newuidmap/newgidmap
+pub fn apply(mapping: &IdMapping, pid: u32) -> Result<()> {
+    run_helper(&mapping.newuidmap, &helper_args(pid, &mapping.uids))?;
+    run_helper(&mapping.newgidmap, &helper_args(pid, &mapping.gids))
+}
+
+fn run_helper(binary: &PathBuf, args: &[String]) -> Result<()> {
+    debug!("{} {}", binary.display(), args.join(" "));
+    let output = Command::new(binary)
+        .args(args)
+        .output()
+        .map_err(|e| CuboError::NamespaceError(format!("Failed to run {}: {}", binary.display(), e)))?;
+    if !output.status.success() {
+        return Err(CuboError::NamespaceError(format!(
+            "{} failed: {}",
+            binary.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Map only the invoking `uid`/`gid` from outside the namespace of `pid`. Used when the
+/// helpers refuse the subordinate ranges.
+pub fn write_single_mapping(pid: u32, uid: u32, gid: u32, mode: &UsernsMode) -> Result<()> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    let write = |file: &str, content: String| {
+        fs::write(proc_dir.join(file), content)
+            .map_err(|e| CuboError::NamespaceError(format!("Failed to write {}: {}", file, e)))
+    };
+    write("setgroups", "deny".to_string())?;
+    write("uid_map", format_map(&ranges_for(mode, uid, &[])))?;
+    write("gid_map", format_map(&ranges_for(mode, gid, &[])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBUID: &str = "# comment\nalice:100000:65536\n1001:200000:65536\nbob:300000:0\nalice:500000:1000\n";
+
+    #[test]
+    fn test_parse_subid_by_name_and_uid() {
+        assert_eq!(parse_subid(SUBUID, "alice", 1000), vec![(100000, 65536), (500000, 1000)]);
+        assert_eq!(parse_subid(SUBUID, "carol", 1001), vec![(200000, 65536)]);
+    }
+
+    #[test]
+    fn test_parse_subid_skips_empty_and_malformed() {
+        assert!(parse_subid(SUBUID, "bob", 1002).is_empty());
+        assert!(parse_subid("alice:abc:10\nalice\n", "alice", 1000).is_empty());
+    }
+
+    #[test]
+    fn test_build_ranges() {
+        let ranges = build_ranges(1000, &[(100000, 65536), (500000, 1000)]);
+        assert_eq!(ranges[0], IdRange { container_id: 0, host_id: 1000, size: 1 });
+        assert_eq!(ranges[1], IdRange { container_id: 1, host_id: 100000, size: 65536 });
+        assert_eq!(ranges[2], IdRange { container_id: 65537, host_id: 500000, size: 1000 });
+    }
+
+    #[test]
+    fn test_format_map() {
+        let ranges = build_ranges(1000, &[(100000, 65536)]);
+        assert_eq!(format_map(&ranges), "0 1000 1\n1 100000 65536\n");
+    }
+
+    #[test]
+    fn test_helper_args() {
+        let ranges = build_ranges(1000, &[(100000, 65536)]);
+        assert_eq!(helper_args(42, &ranges), vec!["42", "0", "1000", "1", "1", "100000", "65536"]);
+    }
+
+    #[test]
+    fn test_build_keep_id_ranges() {
+        let ranges = build_keep_id_ranges(1000, &[(100000, 65536)]);
+        assert_eq!(
+            ranges,
+            vec![
+                IdRange { container_id: 1000, host_id: 1000, size: 1 },
+                IdRange { container_id: 0, host_id: 100000, size: 1000 },
+                IdRange { container_id: 1001, host_id: 101000, size: 64536 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_keep_id_ranges_small_range() {
+        let ranges = build_keep_id_ranges(1000, &[(100000, 10)]);
+        assert_eq!(ranges[1], IdRange { container_id: 0, host_id: 100000, size: 10 });
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(format_map(&build_keep_id_ranges(1000, &[])), "1000 1000 1\n");
+    }
+
+    #[test]
+    fn test_detect_root_or_host_is_none() {
+        assert!(detect(0, 0, &UsernsMode::Private).is_none());
+        assert!(detect(1000, 1000, &UsernsMode::Host).is_none());
+    }
+}
@@ -1,8 +1,15 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 
 use crate::error::{CuboError, Result};
+use super::lock::FileLock;
+use super::reference::{normalize, ImageReference};
+use super::build_history::HistoryStore;
+use super::sbom::AttestationStore;
+use super::container_store::{atomic_write_json, read_json};
+use sha2::{Digest, Sha256};
 
 pub struct ImageStore {
     root: PathBuf,
@@ -10,12 +17,24 @@ pub struct ImageStore {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageManifest {
-    /// Image reference (e.g., "ubuntu:latest")
+    /// Image reference (e.g., "ubuntu:latest"): the name the manifest was looked up by, or
+    /// its digest. Not part of the stored manifest.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub reference: String,
     /// List of layer blob paths
     pub layers: Vec<String>,
     /// Image configuration
     pub config: ImageConfig,
+    /// Digest of the image this one was built from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// OCI annotations of the image manifest, e.g. `org.opencontainers.image.created`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<BTreeMap<String, String>>,
+    /// Digest of the registry manifest the image was pulled by, which registry signatures
+    /// are made for. None for images built or imported from elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo_digest: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +47,26 @@ pub struct ImageConfig {
     pub working_dir: Option<String>,
     /// Exposed ports
     pub exposed_ports: Option<Vec<String>>,
+    /// Signal used to stop containers of this image
+    #[serde(default)]
+    pub stop_signal: Option<String>,
+    /// Labels set with LABEL or `cubo build --label`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+/// File of the store with the names of its images
+const REPOSITORIES_FILE: &str = "repositories.json";
+
+/// Shortest prefix of a digest taken as an image ID, the length `cubo images` shows
+const MIN_ID_LENGTH: usize = 12;
+
+/// The names of the stored images. Manifests are kept in `manifests/` under the digest of
+/// their content and each name, a normalized reference, points to one of them. An image can
+/// have any number of names, and moving a tag leaves the image it named in the store.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Repositories {
+    names: BTreeMap<String, String>,
 }
 
 impl ImageStore {
@@ -44,7 +83,9 @@ impl ImageStore {
         fs::create_dir_all(&manifests_dir)
             .map_err(|e| CuboError::SystemError(format!("Failed to create manifests directory: {}", e)))?;
 
-        Ok(Self {root})
+        let store = Self { root };
+        store.migrate()?;
+        Ok(store)
     }
 
     /// Import an image from a tar file
@@ -56,22 +97,24 @@ impl ImageStore {
             )))
         }
 
-        let safe_name = image_ref.replace(":", "_");
-        let blob_path = self.root.join("blobs").join(format!("{}.tar", safe_name));
-
-        fs::copy(tar_path, &blob_path)
-            .map_err(|e| CuboError::SystemError(format!("Failed to copy image tar: {}", e)))?;
+        ImageReference::parse(image_ref)?;
+        let blob_path = self.write_layer(tar_path)?;
 
         // Create manifest
         let manifest = ImageManifest {
             reference: image_ref.to_string(),
+            parent: None,
             layers: vec![blob_path.to_string_lossy().to_string()],
             config: ImageConfig {
                 cmd: Some(vec!["/bin/sh".to_string()]),
                 env: Some(vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string()]),
                 working_dir: Some("/".to_string()),
                 exposed_ports: None,
-            }
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
         };
 
         self.save_manifest(&manifest)?;
@@ -79,49 +122,154 @@ impl ImageStore {
     }
 
     pub fn get_manifest(&self, image_ref: &str) -> Result<ImageManifest> {
-        let safe_name = image_ref.replace(":", "_");
-        let manifest_path = self.root.join("manifests").join(format!("{}.json", safe_name));
+        let _lock = FileLock::shared(&self.lock_path())?;
+        self.read_manifest(image_ref)
+    }
 
-        if !manifest_path.exists() {
-            return Err(CuboError::BlueprintNotFound(image_ref.to_string()));
-        }
-        let data = fs::read_to_string(&manifest_path)
-            .map_err(|e| CuboError::SystemError(format!("Failed to read manifest file: {}", e)))?;
+    /// Directory the store keeps its manifests and blobs in
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Copy the layer tar at `layer` into the store's blobs, named by the digest of its
+    /// content so no other image's layer is ever overwritten. Returns the path manifests list
+    /// the layer by.
+    pub fn write_layer(&self, layer: &Path) -> Result<PathBuf> {
+        let copy_error = |e: std::io::Error| CuboError::SystemError(format!("Failed to copy layer {}: {}", layer.display(), e));
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut fs::File::open(layer).map_err(copy_error)?, &mut hasher).map_err(copy_error)?;
+        let blob_path = self.root.join("blobs").join(format!("sha256_{:x}.tar", hasher.finalize()));
 
-        let manifest: ImageManifest = serde_json::from_str(&data)
-            .map_err(|e| CuboError::SystemError(format!("Failed to parse manifest JSON: {}", e)))?;
-        Ok(manifest)
+        if !blob_path.exists() {
+            let tmp_path = blob_path.with_extension("tar.tmp");
+            fs::copy(layer, &tmp_path).and_then(|_| fs::rename(&tmp_path, &blob_path)).map_err(copy_error)?;
+        }
+        Ok(blob_path)
     }
 
     pub fn has_image(&self, image_ref: &str) -> bool {
-        let safe_name = image_ref.replace(":", "_");
-        let manifest_path = self.root.join("manifests").join(format!("{}.json", safe_name));
-        manifest_path.exists()
+        self.resolve(image_ref).is_ok()
     }
 
+    /// Digest of the manifest `image_ref` names. Besides the names of the images, this is
+    /// `sha256:<hex>` or `name@sha256:<hex>` with the digest of a manifest, or an image ID:
+    /// at least 12 leading hex digits of one.
+    pub fn resolve(&self, image_ref: &str) -> Result<String> {
+        let _lock = FileLock::shared(&self.lock_path())?;
+        self.lookup(&self.read_repositories()?, image_ref)
+    }
+
+    /// Give the image `source` names the name `target` as well, moving `target` if it named
+    /// another image
+    pub fn tag(&self, source: &str, target: &str) -> Result<()> {
+        let _lock = FileLock::exclusive(&self.lock_path())?;
+        let mut repositories = self.read_repositories()?;
+        let digest = self.lookup(&repositories, source)?;
+        let reference = ImageReference::parse(target)?;
+        if reference.digest.is_some() || is_untagged(target) {
+            return Err(CuboError::InvalidImageReference {
+                reference: target.to_string(),
+                reason: "a name can't be a digest".to_string(),
+            });
+        }
+        repositories.names.insert(reference.normalized().to_string(), digest);
+        self.write_repositories(&repositories)
+    }
+
+    /// The names of the images, and the digests of the images without one
     pub fn list_images(&self) -> Result<Vec<String>> {
-        let manifests_dir = self.root.join("manifests");
-        let mut images = Vec::new();
+        let _lock = FileLock::shared(&self.lock_path())?;
+        Ok(self.read_manifests()?.into_iter().map(|m| m.reference).collect())
+    }
 
-        if !manifests_dir.exists() {
-            return Ok(images);
+    /// Remove an image. A name only goes away itself while other names still point to its
+    /// image; the manifest and the layer blobs kept inside the store that no other image
+    /// shares are deleted with the last name, or right away when the image is given by
+    /// digest.
+    pub fn remove_image(&self, image_ref: &str) -> Result<()> {
+        let _lock = FileLock::exclusive(&self.lock_path())?;
+        let mut repositories = self.read_repositories()?;
+        let digest = self.lookup(&repositories, image_ref)?;
+        let removed: Vec<String> = match self.name_of(&repositories, image_ref) {
+            Some(name) => vec![name],
+            None => repositories.names.iter().filter(|(_, d)| **d == digest).map(|(n, _)| n.clone()).collect(),
+        };
+        for name in &removed {
+            repositories.names.remove(name);
+            AttestationStore::new(&self.root).remove(name);
+            HistoryStore::new(&self.root).remove(name);
+        }
+        AttestationStore::new(&self.root).remove(image_ref);
+        HistoryStore::new(&self.root).remove(image_ref);
+        if repositories.names.values().any(|d| *d == digest) {
+            return self.write_repositories(&repositories);
         }
 
-        for entry in fs::read_dir(&manifests_dir)
-            .map_err(|e| CuboError::SystemError(format!("Failed to read manifests dir: {}", e)))?
-            {
-                let entry = entry
-                    .map_err(|e| CuboError::SystemError(format!("Failed to read dir entry: {}", e)))?;
-                let path = entry.path();
-
-                if path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    if let Ok(manifest) = self.get_manifest_by_path(&path) {
-                        images.push(manifest.reference);
-                    }
-                }
+        let manifest = self.read_digest(&digest)?;
+        // Blobs are named by their digest, the same layer is the same file name
+        let blob_name = |layer: &str| Path::new(layer).file_name().map(|name| name.to_os_string());
+        let shared: HashSet<_> = self
+            .digests()?
+            .into_iter()
+            .filter(|other| *other != digest)
+            .filter_map(|other| self.read_digest(&other).ok())
+            .flat_map(|other| other.layers)
+            .filter_map(|layer| blob_name(&layer))
+            .collect();
+        let blobs_dir = self.root.join("blobs");
+        for layer in &manifest.layers {
+            let shared = blob_name(layer).is_some_and(|name| shared.contains(&name));
+            if Path::new(layer).starts_with(&blobs_dir) && !shared {
+                let _ = fs::remove_file(layer);
             }
+        }
+
+        fs::remove_file(self.manifest_file(&digest))
+            .map_err(|e| CuboError::SystemError(format!("Failed to remove manifest file: {}", e)))?;
+        self.write_repositories(&repositories)
+    }
+
+    /// Take the name `image_ref` off its image, so the name can go to another image. The image
+    /// stays in the store, under its digest once it has no name left. Returns the digest.
+    pub fn untag(&self, image_ref: &str) -> Result<String> {
+        let _lock = FileLock::exclusive(&self.lock_path())?;
+        let mut repositories = self.read_repositories()?;
+        let name = self
+            .name_of(&repositories, image_ref)
+            .ok_or_else(|| CuboError::ImageNotFound { reference: image_ref.to_string() })?;
+        let digest = repositories.names.remove(&name).unwrap_or_default();
+        self.write_repositories(&repositories)?;
+        AttestationStore::new(&self.root).remove(&name);
+        HistoryStore::new(&self.root).remove(&name);
+        Ok(digest)
+    }
+
+    /// Untagged images that no other image was built from and no container in `in_use` was
+    /// created from
+    pub fn dangling_images(&self, in_use: &HashSet<String>) -> Result<Vec<String>> {
+        let _lock = FileLock::shared(&self.lock_path())?;
+        let manifests = self.read_manifests()?;
+        let parents: HashSet<&str> = manifests.iter().filter_map(|m| m.parent.as_deref()).collect();
+        let mut dangling: Vec<String> = manifests
+            .iter()
+            .map(|m| m.reference.clone())
+            .filter(|reference| is_untagged(reference))
+            .filter(|reference| !parents.contains(reference.as_str()) && !in_use.contains(reference))
+            .collect();
+        dangling.sort();
+        Ok(dangling)
+    }
 
-        Ok(images)
+    /// Manifests of the stored images that match every filter, sorted by reference
+    pub fn find_images(&self, filters: &[ImageFilter]) -> Result<Vec<ImageManifest>> {
+        let _lock = FileLock::shared(&self.lock_path())?;
+        let mut manifests: Vec<ImageManifest> = self
+            .read_manifests()?
+            .into_iter()
+            .filter(|manifest| filters.iter().all(|filter| filter.matches(manifest)))
+            .collect();
+        manifests.sort_by(|a, b| a.reference.cmp(&b.reference));
+        Ok(manifests)
     }
 
     pub fn get_layers(&self, image_ref: &str) -> Result<Vec<PathBuf>> {
@@ -136,26 +284,250 @@ impl ImageStore {
 
 
     // Helpers
-    fn get_manifest_by_path(&self, path: &Path) -> Result<ImageManifest> {
-        let data = fs::read_to_string(path)
-            .map_err(|e| CuboError::SystemError(format!("Failed to read manifest file: {}", e)))?;
+    /// Taken shared to read manifests and exclusively to change them, so another cubo process
+    /// never sees a half-written or half-removed image
+    fn lock_path(&self) -> PathBuf {
+        self.root.join(".lock")
+    }
+
+    fn repositories_path(&self) -> PathBuf {
+        self.root.join(REPOSITORIES_FILE)
+    }
+
+    fn read_repositories(&self) -> Result<Repositories> {
+        let path = self.repositories_path();
+        match path.exists() {
+            true => read_json(&path),
+            false => Ok(Repositories::default()),
+        }
+    }
+
+    fn write_repositories(&self, repositories: &Repositories) -> Result<()> {
+        atomic_write_json(&self.repositories_path(), repositories)
+    }
+
+    /// The name in the index `image_ref` is written as, if it is one. Every way of writing a
+    /// reference finds the name of its normalized form.
+    fn name_of(&self, repositories: &Repositories, image_ref: &str) -> Option<String> {
+        normalize(image_ref).ok().filter(|name| repositories.names.contains_key(name))
+    }
+
+    fn lookup(&self, repositories: &Repositories, image_ref: &str) -> Result<String> {
+        let not_found = || CuboError::ImageNotFound { reference: image_ref.to_string() };
+        if let Some(hex) = image_ref.strip_prefix("sha256:") {
+            return self.find_id(image_ref, hex)?.ok_or_else(not_found);
+        }
+        let reference = ImageReference::parse(image_ref)?.normalized();
+        if let Some(digest) = repositories.names.get(&reference.to_string()) {
+            return Ok(digest.clone());
+        }
+        // A pinned digest finds the image whatever name it was stored under
+        if let Some(digest) = reference.digest {
+            return match self.manifest_file(&digest).exists() {
+                true => Ok(digest),
+                false => Err(not_found()),
+            };
+        }
+        // An image ID, normalized like a name with the default tag
+        let is_id = reference.registry.is_none()
+            && reference.tag.as_deref() == Some(super::reference::DEFAULT_TAG)
+            && reference.repository.len() >= MIN_ID_LENGTH
+            && reference.repository.chars().all(|c| c.is_ascii_hexdigit());
+        match is_id {
+            true => self.find_id(image_ref, &reference.repository)?.ok_or_else(not_found),
+            false => Err(not_found()),
+        }
+    }
+
+    /// The digest that starts with `hex`, if exactly one does
+    fn find_id(&self, image_ref: &str, hex: &str) -> Result<Option<String>> {
+        let prefix = format!("sha256:{}", hex);
+        let matches: Vec<String> = self.digests()?.into_iter().filter(|d| d.starts_with(&prefix)).collect();
+        match matches.len() {
+            0 | 1 => Ok(matches.into_iter().next()),
+            _ => Err(CuboError::InvalidImageReference {
+                reference: image_ref.to_string(),
+                reason: "it is the start of the IDs of several images".to_string(),
+            }),
+        }
+    }
+
+    /// Where the manifest with `digest` is kept
+    fn manifest_file(&self, digest: &str) -> PathBuf {
+        self.root.join("manifests").join(format!("{}.json", digest.replace(':', "_")))
+    }
+
+    /// Digests of the stored manifests
+    fn digests(&self) -> Result<Vec<String>> {
+        let entries = fs::read_dir(self.root.join("manifests"))
+            .map_err(|e| CuboError::SystemError(format!("Failed to read manifests dir: {}", e)))?;
+        Ok(entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.file_name().to_string_lossy().to_string()))
+            .filter_map(|name| name.strip_suffix(".json")?.strip_prefix("sha256_").map(|hex| format!("sha256:{}", hex)))
+            .collect())
+    }
 
-        let manifest: ImageManifest = serde_json::from_str(&data)
-            .map_err(|e| CuboError::SystemError(format!("Failed to parse manifest: {}", e)))?;
+    /// A manifest per name, with the name as its reference, and one for each image without
+    /// a name, with its digest as the reference
+    fn read_manifests(&self) -> Result<Vec<ImageManifest>> {
+        let repositories = self.read_repositories()?;
+        let mut manifests: Vec<ImageManifest> = repositories
+            .names
+            .iter()
+            .filter_map(|(name, digest)| {
+                let manifest = self.read_digest(digest).ok()?;
+                Some(ImageManifest { reference: name.clone(), ..manifest })
+            })
+            .collect();
+        let named: HashSet<&String> = repositories.names.values().collect();
+        for digest in self.digests()? {
+            if !named.contains(&digest) {
+                if let Ok(manifest) = self.read_digest(&digest) {
+                    manifests.push(manifest);
+                }
+            }
+        }
+        Ok(manifests)
+    }
+
+    /// The manifest `image_ref` names, with the name as its reference, or the digest when
+    /// the image was found by digest or ID
+    fn read_manifest(&self, image_ref: &str) -> Result<ImageManifest> {
+        let repositories = self.read_repositories()?;
+        let digest = self.lookup(&repositories, image_ref)?;
+        let manifest = self.read_digest(&digest)?;
+        match self.name_of(&repositories, image_ref) {
+            Some(name) => Ok(ImageManifest { reference: name, ..manifest }),
+            None => Ok(manifest),
+        }
+    }
+
+    fn read_digest(&self, digest: &str) -> Result<ImageManifest> {
+        let path = self.manifest_file(digest);
+        if !path.exists() {
+            return Err(CuboError::ImageNotFound { reference: digest.to_string() });
+        }
+        let manifest: ImageManifest = read_json(&path)?;
+        Ok(ImageManifest { reference: digest.to_string(), ..manifest })
+    }
 
-        Ok(manifest)
-    } 
     pub fn save_manifest(&self, manifest: &ImageManifest) -> Result<()> {
-        let safe_name = manifest.reference.replace(":", "_");
-        let manifest_path = self.root.join("manifests").join(format!("{}.json", safe_name));
+        let _lock = FileLock::exclusive(&self.lock_path())?;
+        let mut repositories = self.read_repositories()?;
+        // An untagged reference is the digest of an image, not a name
+        let name = match is_untagged(&manifest.reference) {
+            true => None,
+            false => Some(normalize(&manifest.reference)?),
+        };
+        let digest = self.write_manifest(manifest)?;
+        if let Some(name) = name {
+            repositories.names.insert(name, digest);
+            self.write_repositories(&repositories)?;
+        }
+        Ok(())
+    }
 
-        let json = serde_json::to_string_pretty(manifest)
+    /// Store the manifest under the digest of its content, without its name. Returns the digest.
+    fn write_manifest(&self, manifest: &ImageManifest) -> Result<String> {
+        let manifest = ImageManifest { reference: String::new(), ..manifest.clone() };
+        let json = serde_json::to_vec_pretty(&manifest)
             .map_err(|e| CuboError::SystemError(format!("Failed to write manifest: {}", e)))?;
+        let digest = format!("sha256:{:x}", Sha256::digest(&json));
 
-        fs::write(&manifest_path, json)
-            .map_err(|e| CuboError::SystemError(format!("Failed to write manifest file:: {}", e)))?;
+        let manifest_path = self.manifest_file(&digest);
+        if !manifest_path.exists() {
+            let tmp_path = manifest_path.with_extension("json.tmp");
+            fs::write(&tmp_path, &json)
+                .and_then(|_| fs::rename(&tmp_path, &manifest_path))
+                .map_err(|e| CuboError::SystemError(format!("Failed to write manifest file: {}", e)))?;
+        }
+        Ok(digest)
+    }
 
-        Ok(())
+    /// Stores written before the names index kept each manifest in a file named after its
+    /// reference, with the reference inside. Move them under their digests and index their
+    /// names.
+    fn migrate(&self) -> Result<()> {
+        if self.repositories_path().exists() {
+            return Ok(());
+        }
+        let _lock = FileLock::exclusive(&self.lock_path())?;
+        if self.repositories_path().exists() {
+            return Ok(());
+        }
+
+        let mut repositories = Repositories::default();
+        let entries = fs::read_dir(self.root.join("manifests"))
+            .map_err(|e| CuboError::SystemError(format!("Failed to read manifests dir: {}", e)))?;
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            let Ok(manifest) = read_json::<ImageManifest>(&path) else {
+                continue;
+            };
+            if manifest.reference.is_empty() {
+                continue;
+            }
+            let digest = self.write_manifest(&manifest)?;
+            if !is_untagged(&manifest.reference) {
+                if let Ok(name) = normalize(&manifest.reference) {
+                    repositories.names.insert(name, digest.clone());
+                }
+            }
+            if path != self.manifest_file(&digest) {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        self.write_repositories(&repositories)
+    }
+}
+
+/// Whether `reference` names an image by digest only, as kept when its tag moved on
+pub fn is_untagged(reference: &str) -> bool {
+    reference.starts_with("sha256:")
+}
+
+/// A `--filter` of `cubo images`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageFilter {
+    /// `label=KEY` matches images with the label, `label=KEY=VALUE` those where it has
+    /// that value
+    Label { key: String, value: Option<String> },
+}
+
+impl ImageFilter {
+    pub fn matches(&self, manifest: &ImageManifest) -> bool {
+        match self {
+            ImageFilter::Label { key, value } => {
+                let label = manifest.config.labels.as_ref().and_then(|labels| labels.get(key));
+                match value {
+                    Some(value) => label == Some(value),
+                    None => label.is_some(),
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, arg) = s.split_once('=').ok_or_else(|| format!("filter '{}' must be NAME=VALUE", s))?;
+        match name {
+            "label" => {
+                let (key, value) = match arg.split_once('=') {
+                    Some((key, value)) => (key, Some(value.to_string())),
+                    None => (arg, None),
+                };
+                if key.is_empty() {
+                    return Err(format!("filter '{}' names no label", s));
+                }
+                Ok(ImageFilter::Label { key: key.to_string(), value })
+            }
+            other => Err(format!("unknown filter '{}' (expected label)", other)),
+        }
     }
 }
 
@@ -180,13 +552,18 @@ mod tests {
 
         let manifest = ImageManifest {
             reference: "alpine:latest".to_string(),
+            parent: None,
             layers: vec!["/path/to/layer.tar".to_string()],
             config: ImageConfig {
                 cmd: Some(vec!["/bin/sh".to_string()]),
                 env: None,
                 working_dir: Some("/".to_string()),
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
 
         store.save_manifest(&manifest).unwrap();
@@ -205,13 +582,18 @@ mod tests {
 
         let manifest = ImageManifest {
             reference: "alpine:latest".to_string(),
+            parent: None,
             layers: vec![],
             config: ImageConfig {
                 cmd: None,
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
 
         store.save_manifest(&manifest).unwrap();
@@ -233,13 +615,18 @@ mod tests {
         for name in &["alpine:latest", "ubuntu:22.04", "nginx:1.25"] {
             let manifest = ImageManifest {
             reference: name.to_string(),
+            parent: None,
             layers: vec![],
             config: ImageConfig {
                 cmd: None,
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         store.save_manifest(&manifest).unwrap();
         }
@@ -256,6 +643,7 @@ mod tests {
         let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
         let manifest = ImageManifest {
             reference: "test:layers".to_string(),
+            parent: None,
             layers: vec![
                 "/path/to/layer1.tar".to_string(),
                 "/path/to/layer2.tar".to_string(),
@@ -266,7 +654,11 @@ mod tests {
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         store.save_manifest(&manifest).unwrap();
         let layers = store.get_layers("test:layers").unwrap();
@@ -279,13 +671,18 @@ mod tests {
         let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
         let manifest = ImageManifest {
             reference: "test:config".to_string(),
+            parent: None,
             layers: vec![],
             config: ImageConfig {
                 cmd: Some(vec!["/entrypoint.sh".to_string()]),
                 env: Some(vec!["ENV=prod".to_string(), "DEBUG=false".to_string()]),
                 working_dir: Some("/app".to_string()),
                 exposed_ports: Some(vec!["8080/tcp".to_string()]),
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         store.save_manifest(&manifest).unwrap();
         let config = store.get_config("test:config").unwrap();
@@ -295,6 +692,98 @@ mod tests {
         assert_eq!(config.exposed_ports.as_ref().unwrap().len(), 1);
     }
 
+    #[test]
+    fn test_remove_image() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        let blob = tmp.path().join("blobs").join("app_latest_0.tar");
+        fs::write(&blob, b"layer").unwrap();
+        let outside = tmp.path().join("elsewhere.tar");
+        fs::write(&outside, b"layer").unwrap();
+
+        store.save_manifest(&ImageManifest {
+            reference: "app:latest".to_string(),
+            parent: None,
+            layers: vec![blob.to_string_lossy().to_string(), outside.to_string_lossy().to_string()],
+            config: ImageConfig {
+                cmd: None,
+                env: None,
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        }).unwrap();
+
+        store.remove_image("app:latest").unwrap();
+        assert!(!store.has_image("app:latest"));
+        assert!(!blob.exists());
+        assert!(outside.exists());
+        assert!(matches!(store.remove_image("app:latest"), Err(CuboError::ImageNotFound { .. })));
+    }
+
+    #[test]
+    fn test_invalid_references_never_become_paths() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().join("images")).unwrap();
+        let manifest = ImageManifest {
+            reference: "../../escape".to_string(),
+            parent: None,
+            layers: vec![],
+            config: ImageConfig {
+                cmd: None,
+                env: None,
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        };
+
+        assert!(matches!(store.save_manifest(&manifest), Err(CuboError::InvalidImageReference { .. })));
+        assert!(!tmp.path().join("escape.json").exists());
+        assert!(!store.has_image("../../escape"));
+        assert!(matches!(store.get_manifest("../images/x"), Err(CuboError::InvalidImageReference { .. })));
+
+        // Names only go into the index, manifests are kept under their digest
+        store.save_manifest(&ImageManifest { reference: "localhost:5000/team/app:v1".to_string(), ..manifest }).unwrap();
+        let digest = store.resolve("localhost:5000/team/app:v1").unwrap();
+        let file = format!("images/manifests/{}.json", digest.replace(':', "_"));
+        assert!(tmp.path().join(file).exists());
+        assert_eq!(fs::read_dir(tmp.path().join("images/manifests")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_references_resolve_to_the_normalized_image() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        store.save_manifest(&ImageManifest {
+            reference: "docker.io/library/alpine".to_string(),
+            parent: None,
+            layers: vec![],
+            config: ImageConfig {
+                cmd: None,
+                env: None,
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        }).unwrap();
+
+        assert_eq!(store.list_images().unwrap(), vec!["alpine:latest".to_string()]);
+        for reference in ["alpine", "alpine:latest", "library/alpine", "docker.io/library/alpine:latest"] {
+            assert_eq!(store.get_manifest(reference).unwrap().reference, "alpine:latest");
+        }
+        assert!(!store.has_image("alpine:3.18"));
+    }
+
     #[test]
     fn test_import_tar_file_not_found() {
         let tmp = TempDir::new().unwrap();
@@ -304,6 +793,40 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("does not exist"));
     }
 
+    #[test]
+    fn test_layers_are_stored_by_content() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().join("store")).unwrap();
+        let import = |reference: &str, content: &[u8]| {
+            let tar = tmp.path().join("layer.tar");
+            fs::write(&tar, content).unwrap();
+            store.import_tar(reference, &tar).unwrap();
+            store.get_manifest(reference).unwrap().layers.remove(0)
+        };
+
+        // Pulled again: the image the name pointed to before keeps its own layer
+        let old = import("app:latest", b"old");
+        let old_digest = store.resolve("app:latest").unwrap();
+        let new = import("app:latest", b"new");
+        assert_ne!(old, new);
+        assert_eq!(store.get_manifest(&old_digest).unwrap().layers, [old.clone()]);
+        assert_eq!(fs::read(&old).unwrap(), b"old");
+
+        // Names that used to map to the same file
+        let nested = import("foo/bar:latest", b"nested");
+        let flat = import("foo_bar:latest", b"flat");
+        assert_ne!(nested, flat);
+        assert_eq!(fs::read(&nested).unwrap(), b"nested");
+
+        // The same content is one blob, kept until its last image goes
+        let same = import("copy:latest", b"flat");
+        assert_eq!(same, flat);
+        store.remove_image("foo_bar:latest").unwrap();
+        assert!(Path::new(&same).exists());
+        store.remove_image("copy:latest").unwrap();
+        assert!(!Path::new(&same).exists());
+    }
+
     #[test]
     fn test_image_config_defaults() {
         let config = ImageConfig {
@@ -311,6 +834,8 @@ mod tests {
             env: None,
             working_dir: None,
             exposed_ports: None,
+            stop_signal: None,
+            labels: None,
         };
         assert!(config.cmd.is_none());
         assert!(config.env.is_none());
@@ -322,13 +847,18 @@ mod tests {
     fn test_image_manifest_debut() {
         let manifest = ImageManifest {
             reference: "debug:test".to_string(),
+            parent: None,
             layers: vec!["layer.tar".to_string()],
             config: ImageConfig {
                 cmd: Some(vec!["test".to_string()]),
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         let debug_str = format!("{:?}", manifest);
         assert!(debug_str.contains("ImageManifest"));
@@ -342,10 +872,195 @@ mod tests {
             env: Some(vec!["PATH=/bin".to_string()]),
             working_dir: Some("/".to_string()),
             exposed_ports: None,
+            stop_signal: None,
+            labels: None,
         };
         let cloned = config.clone();
         assert_eq!(cloned.cmd, config.cmd);
         assert_eq!(cloned.env, config.env);
         assert_eq!(cloned.working_dir, config.working_dir);
     }
+
+    fn manifest_with(reference: &str, layers: Vec<String>, parent: Option<String>) -> ImageManifest {
+        ImageManifest {
+            reference: reference.to_string(),
+            parent,
+            layers,
+            config: ImageConfig {
+                cmd: None,
+                env: None,
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        }
+    }
+
+    #[test]
+    fn test_find_images_by_label() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        let mut web = manifest_with("web:1", vec![], None);
+        web.config.labels = Some(BTreeMap::from([
+            ("team".to_string(), "payments".to_string()),
+            ("tier".to_string(), "front".to_string()),
+        ]));
+        let mut api = manifest_with("api:1", vec![], None);
+        api.config.labels = Some(BTreeMap::from([("team".to_string(), "payments".to_string())]));
+        for manifest in [web, api, manifest_with("alpine:latest", vec![], None)] {
+            store.save_manifest(&manifest).unwrap();
+        }
+
+        let references = |filters: &[&str]| -> Vec<String> {
+            let filters: Vec<ImageFilter> = filters.iter().map(|f| f.parse().unwrap()).collect();
+            store.find_images(&filters).unwrap().into_iter().map(|m| m.reference).collect()
+        };
+        assert_eq!(references(&[]), vec!["alpine:latest", "api:1", "web:1"]);
+        assert_eq!(references(&["label=team"]), vec!["api:1", "web:1"]);
+        assert_eq!(references(&["label=team=payments", "label=tier=front"]), vec!["web:1"]);
+        assert!(references(&["label=team=search"]).is_empty());
+    }
+
+    #[test]
+    fn test_image_filter_from_str() {
+        assert_eq!(
+            "label=a=b=c".parse::<ImageFilter>().unwrap(),
+            ImageFilter::Label { key: "a".to_string(), value: Some("b=c".to_string()) }
+        );
+        assert!("label".parse::<ImageFilter>().is_err());
+        assert!("label=".parse::<ImageFilter>().is_err());
+        assert!("dangling=true".parse::<ImageFilter>().is_err());
+    }
+
+    #[test]
+    fn test_untag_and_dangling_images() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        let old_layer = tmp.path().join("blobs/old.tar");
+        fs::write(&old_layer, b"old").unwrap();
+        store.save_manifest(&manifest_with("app:latest", vec![old_layer.to_string_lossy().to_string()], None)).unwrap();
+
+        let digest = store.untag("app:latest").unwrap();
+        assert!(is_untagged(&digest));
+        assert!(!store.has_image("app:latest"));
+        assert!(store.has_image(&digest));
+        assert_eq!(store.dangling_images(&HashSet::new()).unwrap(), vec![digest.clone()]);
+
+        // In use by a container
+        let in_use = HashSet::from([digest.clone()]);
+        assert!(store.dangling_images(&in_use).unwrap().is_empty());
+
+        // Parent of another image
+        store.save_manifest(&manifest_with("child:latest", vec![], Some(digest.clone()))).unwrap();
+        assert!(store.dangling_images(&HashSet::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remove_image_keeps_shared_blobs() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        let layer = tmp.path().join("blobs/shared.tar");
+        fs::write(&layer, b"layer").unwrap();
+        let layers = vec![layer.to_string_lossy().to_string()];
+        store.save_manifest(&manifest_with("one:latest", layers.clone(), None)).unwrap();
+        store.save_manifest(&manifest_with("two:latest", layers, None)).unwrap();
+
+        store.remove_image("one:latest").unwrap();
+        assert!(layer.exists());
+        store.remove_image("two:latest").unwrap();
+        assert!(!layer.exists());
+    }
+
+    #[test]
+    fn test_tag_names_the_same_image() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        let layer = tmp.path().join("blobs/app.tar");
+        fs::write(&layer, b"app").unwrap();
+        store.save_manifest(&manifest_with("app:1", vec![layer.to_string_lossy().to_string()], None)).unwrap();
+
+        store.tag("app:1", "registry.example.com/app:stable").unwrap();
+        let digest = store.resolve("app:1").unwrap();
+        assert_eq!(store.resolve("registry.example.com/app:stable").unwrap(), digest);
+        assert_eq!(store.get_manifest("registry.example.com/app:stable").unwrap().reference, "registry.example.com/app:stable");
+        assert_eq!(store.list_images().unwrap().len(), 2);
+        assert!(matches!(store.tag("app:1", "app@sha256:0123"), Err(CuboError::InvalidImageReference { .. })));
+        assert!(matches!(store.tag("missing", "app:2"), Err(CuboError::ImageNotFound { .. })));
+
+        // Removing one name keeps the image for the other
+        store.remove_image("app:1").unwrap();
+        assert!(!store.has_image("app:1"));
+        assert!(layer.exists());
+        assert!(store.has_image(&digest));
+
+        // Moving the last name leaves the image behind untagged
+        store.save_manifest(&manifest_with("other:1", vec![], None)).unwrap();
+        store.tag("other:1", "registry.example.com/app:stable").unwrap();
+        assert_eq!(store.dangling_images(&HashSet::new()).unwrap(), vec![digest]);
+    }
+
+    #[test]
+    fn test_digest_and_id_references() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        store.save_manifest(&manifest_with("app:1", vec![], None)).unwrap();
+        store.tag("app:1", "app:latest").unwrap();
+        let digest = store.resolve("app:1").unwrap();
+        let hex = digest.trim_start_matches("sha256:");
+
+        assert_eq!(store.get_manifest(&digest).unwrap().reference, digest);
+        assert_eq!(store.resolve(&format!("sha256:{}", &hex[..6])).unwrap(), digest);
+        assert_eq!(store.resolve(&format!("other@{}", digest)).unwrap(), digest);
+        assert_eq!(store.resolve(&hex[..12]).unwrap(), digest);
+        // How the runtime writes an ID when it normalizes the image of a container
+        assert_eq!(store.resolve(&format!("{}:latest", &hex[..12])).unwrap(), digest);
+        assert!(matches!(store.resolve(&hex[..6]), Err(CuboError::ImageNotFound { .. })));
+
+        // By digest every name of the image goes with it
+        store.remove_image(&digest).unwrap();
+        assert!(store.list_images().unwrap().is_empty());
+        assert!(!store.has_image("app:latest"));
+    }
+
+    #[test]
+    fn test_ambiguous_id_prefix() {
+        let tmp = TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        for n in 0..40 {
+            let mut manifest = manifest_with(&format!("app:{}", n), vec![], None);
+            manifest.config.env = Some(vec![format!("N={}", n)]);
+            store.save_manifest(&manifest).unwrap();
+        }
+        // 40 digests can't all start with different hex digits
+        let ambiguous = (0..16)
+            .map(|digit| format!("sha256:{:x}", digit))
+            .find(|prefix| store.list_images().unwrap().iter().filter(|r| store.resolve(r).unwrap().starts_with(prefix.as_str())).count() > 1)
+            .unwrap();
+        assert!(matches!(store.resolve(&ambiguous), Err(CuboError::InvalidImageReference { .. })));
+    }
+
+    #[test]
+    fn test_migrates_manifests_named_after_references() {
+        let tmp = TempDir::new().unwrap();
+        let manifests = tmp.path().join("manifests");
+        fs::create_dir_all(&manifests).unwrap();
+        let legacy = |file: &str, reference: &str| {
+            let json = serde_json::to_string(&manifest_with(reference, vec![], None)).unwrap();
+            fs::write(manifests.join(file), json).unwrap();
+        };
+        legacy("docker.io_library_alpine_latest.json", "docker.io/library/alpine:latest");
+        legacy("app_1.json", "app:1");
+        legacy("sha256_0123.json", "sha256:0123");
+
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        assert!(tmp.path().join(REPOSITORIES_FILE).exists());
+        assert!(!manifests.join("app_1.json").exists());
+        assert_eq!(store.resolve("alpine").unwrap(), store.resolve("app:1").unwrap());
+        // The same content under three files is one image now
+        assert_eq!(fs::read_dir(&manifests).unwrap().count(), 1);
+        assert_eq!(store.list_images().unwrap(), vec!["alpine:latest", "app:1"]);
+    }
 }
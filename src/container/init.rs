@@ -0,0 +1,148 @@
+use std::process;
+
+use nix::errno::Errno;
+use nix::sys::signal::{kill, sigprocmask, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use tracing::{debug, error, warn};
+
+use crate::error::{CuboError, Result};
+
+// Uncatchable and synchronous signals stay with the process they hit, SIGCHLD drives reaping
+const NOT_FORWARDED: [Signal; 12] = [
+    Signal::SIGCHLD,
+    Signal::SIGKILL,
+    Signal::SIGSTOP,
+    Signal::SIGFPE,
+    Signal::SIGILL,
+    Signal::SIGSEGV,
+    Signal::SIGBUS,
+    Signal::SIGABRT,
+    Signal::SIGTRAP,
+    Signal::SIGSYS,
+    Signal::SIGTTIN,
+    Signal::SIGTTOU,
+];
+
+/// Signals the init relays to the container process
+pub fn forwarded_signals() -> Vec<Signal> {
+    Signal::iterator().filter(|s| !NOT_FORWARDED.contains(s)).collect()
+}
+
+fn init_sigset() -> SigSet {
+    let mut set = SigSet::empty();
+    for signal in forwarded_signals() {
+        set.add(signal);
+    }
+    set.add(Signal::SIGCHLD);
+    set
+}
+
+/// Block the signals the init waits for. Call before forking the container process and
+/// restore the returned mask in the child before exec.
+pub fn block_signals() -> Result<SigSet> {
+    let mut previous = SigSet::empty();
+    sigprocmask(SigmaskHow::SIG_BLOCK, Some(&init_sigset()), Some(&mut previous))
+        .map_err(|e| CuboError::SystemError(format!("Failed to block signals: {}", e)))?;
+    Ok(previous)
+}
+
+pub fn restore_signals(mask: &SigSet) -> Result<()> {
+    sigprocmask(SigmaskHow::SIG_SETMASK, Some(mask), None)
+        .map_err(|e| CuboError::SystemError(format!("Failed to restore signal mask: {}", e)))
+}
+
+/// Exit code of a finished process: its own code, or 128 + the signal that killed it
+pub fn exit_code(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_, code) => Some(code),
+        WaitStatus::Signaled(_, signal, _) => Some(128 + signal as i32),
+        _ => None,
+    }
+}
+
+/// Act as the container's pid 1: relay every forwarded signal to `child`, reap any process
+/// that exits, and exit with `child`'s status once it is gone. Expects `block_signals` to
+/// have been called.
+pub fn supervise(child: Pid) -> ! {
+    // Orphans get reparented here even when we're not the namespace's pid 1
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        warn!("Failed to become a child subreaper: {}", Errno::last());
+    }
+
+    let set = init_sigset();
+    loop {
+        match set.wait() {
+            Ok(Signal::SIGCHLD) => {}
+            Ok(signal) => {
+                debug!("init: forwarding {} to {}", signal, child);
+                match kill(child, signal) {
+                    Ok(()) | Err(Errno::ESRCH) => {}
+                    Err(e) => warn!("init: failed to forward {}: {}", signal, e),
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("init: sigwait failed: {}", e);
+                process::exit(1);
+            }
+        }
+
+        let mut child_exit = None;
+        loop {
+            match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => break,
+                Ok(status) => {
+                    if status.pid() == Some(child) {
+                        child_exit = exit_code(status);
+                    } else {
+                        debug!("init: reaped {:?}", status);
+                    }
+                }
+                Err(Errno::ECHILD) => break,
+                Err(e) => {
+                    error!("init: waitpid failed: {}", e);
+                    break;
+                }
+            }
+        }
+        if let Some(code) = child_exit {
+            process::exit(code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_signals() {
+        let signals = forwarded_signals();
+        for signal in [Signal::SIGTERM, Signal::SIGINT, Signal::SIGUSR1, Signal::SIGHUP, Signal::SIGWINCH] {
+            assert!(signals.contains(&signal), "{} should be forwarded", signal);
+        }
+        for signal in [Signal::SIGCHLD, Signal::SIGKILL, Signal::SIGSTOP, Signal::SIGSEGV] {
+            assert!(!signals.contains(&signal), "{} should not be forwarded", signal);
+        }
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let pid = Pid::from_raw(42);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 3)), Some(3));
+        assert_eq!(exit_code(WaitStatus::Signaled(pid, Signal::SIGTERM, false)), Some(143));
+        assert_eq!(exit_code(WaitStatus::StillAlive), None);
+    }
+
+    #[test]
+    fn test_block_and_restore_signals() {
+        let previous = block_signals().unwrap();
+        let blocked = SigSet::thread_get_mask().unwrap();
+        assert!(blocked.contains(Signal::SIGUSR1));
+        assert!(blocked.contains(Signal::SIGCHLD));
+
+        restore_signals(&previous).unwrap();
+        assert_eq!(SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1), previous.contains(Signal::SIGUSR1));
+    }
+}
@@ -0,0 +1,225 @@
+//! Checking that what a container's command is exec'd with exists in its rootfs, so an image
+//! without a shell or a script without its interpreter fails with a name instead of an errno
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::container::exec::DEFAULT_PATH;
+use crate::container::secure_join::secure_join;
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+
+/// What commands run with unless the container has an entrypoint
+pub const SHELL: &str = "/bin/sh";
+
+/// The argv a container's process is exec'd with: its command as arguments of the
+/// entrypoint, or as a shell command line
+pub fn command_args(container: &Container) -> Vec<String> {
+    match container.config.entrypoint {
+        Some(ref entrypoint) => std::iter::once(entrypoint.clone()).chain(container.command.iter().cloned()).collect(),
+        None => vec![SHELL.to_string(), "-c".to_string(), container.command.join(" ")],
+    }
+}
+
+/// `command_args`, after checking in the container's rootfs that the program exists and that
+/// a script's interpreter does too. A program given by name is replaced by its path.
+pub fn check_command(rootfs: &Path, container: &Container) -> Result<Vec<String>> {
+    let search_path = container.config.env_vars.get("PATH").map(String::as_str).unwrap_or(DEFAULT_PATH);
+    let mut args = command_args(container);
+
+    if let Some(ref entrypoint) = container.config.entrypoint {
+        match find_program(rootfs, &in_working_dir(container, entrypoint), search_path) {
+            Ok(path) => {
+                check_shebang(rootfs, &path, entrypoint, search_path)?;
+                if !entrypoint.contains('/') {
+                    args[0] = path;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(CuboError::CommandNotFound(entrypoint.clone()))
+            }
+            Err(_) => {}
+        }
+        return Ok(args);
+    }
+
+    let command = container.command.join(" ");
+    if is_missing(&resolve_in_rootfs(rootfs, SHELL)) {
+        return Err(CuboError::MissingInterpreter { path: SHELL.to_string(), command });
+    }
+    // A first word that isn't a file may be a builtin; the shell reports it if it isn't
+    if let Some(program) = container.command.first().and_then(|line| line.split_whitespace().next()) {
+        if let Ok(path) = find_program(rootfs, &in_working_dir(container, program), search_path) {
+            check_shebang(rootfs, &path, program, search_path)?;
+        }
+    }
+    Ok(args)
+}
+
+/// Host path of `path` as the container sees it: symlinks are followed inside `rootfs`, and
+/// neither absolute targets nor `..` lead out of it. Fails with NotFound if nothing is there.
+pub fn resolve_in_rootfs(rootfs: &Path, path: &str) -> io::Result<PathBuf> {
+    let host = secure_join(rootfs, path).map_err(|e| match e {
+        CuboError::InvalidConfiguration(msg) => io::Error::new(io::ErrorKind::InvalidInput, msg),
+        e => io::Error::other(e.to_string()),
+    })?;
+    fs::symlink_metadata(&host)?;
+    Ok(host)
+}
+
+/// A relative path to a program as seen from the directory the container's process starts in
+fn in_working_dir(container: &Container, program: &str) -> String {
+    match container.config.working_dir {
+        Some(ref dir) if program.contains('/') && !program.starts_with('/') => {
+            format!("{}/{}", dir.trim_end_matches('/'), program)
+        }
+        _ => program.to_string(),
+    }
+}
+
+/// Container path of the program `name`: as given when it has a slash, else the first match
+/// in `search_path`
+pub fn find_program(rootfs: &Path, name: &str, search_path: &str) -> io::Result<String> {
+    if name.contains('/') {
+        let host = resolve_in_rootfs(rootfs, name)?;
+        return if host.is_file() { Ok(name.to_string()) } else { Err(io::ErrorKind::NotFound.into()) };
+    }
+
+    // Only a missing file counts as missing; one that can't be looked at may still be there
+    let mut unknown = None;
+    for dir in search_path.split(':').filter(|dir| dir.starts_with('/')) {
+        let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+        match resolve_in_rootfs(rootfs, &path) {
+            Ok(host) if host.is_file() => return Ok(path),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => unknown = Some(e),
+        }
+    }
+    Err(unknown.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+}
+
+/// Interpreter and optional argument of the `#!` line of a script
+pub fn shebang(file: &Path) -> Option<(String, Option<String>)> {
+    let mut head = [0u8; 256];
+    let len = File::open(file).and_then(|mut f| f.read(&mut head)).ok()?;
+    let line = head[..len].strip_prefix(b"#!")?;
+    let line = String::from_utf8_lossy(line.split(|b| *b == b'\n').next()?).to_string();
+    let mut words = line.split_whitespace();
+    let interpreter = words.next()?.to_string();
+    Some((interpreter, words.next().map(str::to_string)))
+}
+
+/// Fail when `program` is a script whose interpreter isn't in the container, including the
+/// program `#!/usr/bin/env NAME` runs
+fn check_shebang(rootfs: &Path, program: &str, command: &str, search_path: &str) -> Result<()> {
+    let Ok(host) = resolve_in_rootfs(rootfs, program) else {
+        return Ok(());
+    };
+    let Some((interpreter, arg)) = shebang(&host) else {
+        return Ok(());
+    };
+    let missing = |path: &str| CuboError::MissingInterpreter { path: path.to_string(), command: command.to_string() };
+
+    if is_missing(&resolve_in_rootfs(rootfs, &interpreter)) {
+        return Err(missing(&interpreter));
+    }
+    if let Some(name) = arg.filter(|arg| interpreter.ends_with("/env") && !arg.starts_with('-')) {
+        if is_missing(&find_program(rootfs, &name, search_path)) {
+            return Err(missing(&name));
+        }
+    }
+    Ok(())
+}
+
+fn is_missing<T>(lookup: &io::Result<T>) -> bool {
+    matches!(lookup, Err(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    fn container(command: &str, entrypoint: Option<&str>) -> Container {
+        let mut container = Container::new("test:latest".to_string(), vec![command.to_string()]);
+        container.config.entrypoint = entrypoint.map(str::to_string);
+        container
+    }
+
+    fn write_file(rootfs: &Path, path: &str, content: &str) {
+        let host = rootfs.join(path.trim_start_matches('/'));
+        fs::create_dir_all(host.parent().unwrap()).unwrap();
+        fs::write(host, content).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_in_rootfs_stays_inside() {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path();
+        write_file(rootfs, "/usr/bin/sh", "");
+        symlink("usr/bin", rootfs.join("bin")).unwrap();
+        symlink("/usr/bin/sh", rootfs.join("usr/bin/dash")).unwrap();
+        symlink("../../../../etc", rootfs.join("usr/escape")).unwrap();
+
+        assert_eq!(resolve_in_rootfs(rootfs, "/bin/sh").unwrap(), rootfs.join("usr/bin/sh"));
+        assert_eq!(resolve_in_rootfs(rootfs, "/bin/dash").unwrap(), rootfs.join("usr/bin/sh"));
+        assert_eq!(resolve_in_rootfs(rootfs, "/usr/escape").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        symlink("loop", rootfs.join("loop")).unwrap();
+        assert_eq!(resolve_in_rootfs(rootfs, "/loop").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_missing_shell() {
+        let temp = TempDir::new().unwrap();
+        let err = check_command(temp.path(), &container("app --serve", None)).unwrap_err();
+        assert!(matches!(err, CuboError::MissingInterpreter { ref path, .. } if path == SHELL));
+
+        write_file(temp.path(), SHELL, "");
+        assert_eq!(check_command(temp.path(), &container("app --serve", None)).unwrap(), [SHELL, "-c", "app --serve"]);
+    }
+
+    #[test]
+    fn test_script_interpreters() {
+        let temp = TempDir::new().unwrap();
+        write_file(temp.path(), SHELL, "");
+        write_file(temp.path(), "/app/run.sh", "#!/bin/bash -e\necho hi\n");
+        write_file(temp.path(), "/usr/local/bin/serve", "#!/usr/bin/env python3\n");
+        write_file(temp.path(), "/usr/bin/env", "");
+
+        let err = check_command(temp.path(), &container("/app/run.sh", None)).unwrap_err();
+        assert!(matches!(err, CuboError::MissingInterpreter { ref path, .. } if path == "/bin/bash"));
+        let err = check_command(temp.path(), &container("serve", None)).unwrap_err();
+        assert!(matches!(err, CuboError::MissingInterpreter { ref path, .. } if path == "python3"));
+
+        write_file(temp.path(), "/bin/bash", "");
+        write_file(temp.path(), "/usr/bin/python3", "");
+        check_command(temp.path(), &container("/app/run.sh", None)).unwrap();
+        check_command(temp.path(), &container("serve", None)).unwrap();
+    }
+
+    #[test]
+    fn test_entrypoint_skips_the_shell() {
+        let temp = TempDir::new().unwrap();
+        write_file(temp.path(), "/usr/bin/app", "\x7fELF");
+        let args = check_command(temp.path(), &container("--serve", Some("app"))).unwrap();
+        assert_eq!(args, ["/usr/bin/app", "--serve"]);
+
+        let err = check_command(temp.path(), &container("--serve", Some("/missing"))).unwrap_err();
+        assert!(matches!(err, CuboError::CommandNotFound(_)));
+    }
+
+    #[test]
+    fn test_relative_entrypoint_starts_in_the_working_dir() {
+        let temp = TempDir::new().unwrap();
+        write_file(temp.path(), "/srv/app", "\x7fELF");
+        let mut relative = container("--serve", Some("./app"));
+        assert!(matches!(check_command(temp.path(), &relative).unwrap_err(), CuboError::CommandNotFound(_)));
+
+        relative.config.working_dir = Some("/srv".to_string());
+        assert_eq!(check_command(temp.path(), &relative).unwrap(), ["./app", "--serve"]);
+    }
+}
@@ -0,0 +1,316 @@
+//! Extracted image layers kept under the image store, so trees built from an image are
+//! copied (or reflinked) from them instead of unpacking every layer again
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::container::image_store::ImageStore;
+use crate::container::snapshotter::{self, ProvisionMode};
+use crate::container::unpack;
+use crate::error::{CuboError, Result};
+
+/// Directory of the cache inside the image store
+const CACHE_DIR: &str = "extracted";
+
+/// Directory of the remembered layer digests inside the cache
+const DIGESTS_DIR: &str = "digests";
+
+/// Each entry is the tree of one layer applied over all the layers below it, named after the
+/// digests of those layers. Images sharing base layers share their entries, whatever their
+/// blobs are called.
+pub struct LayerCache {
+    root: PathBuf,
+}
+
+impl LayerCache {
+    pub fn new(image_store: &ImageStore) -> Self {
+        Self { root: image_store.root().join(CACHE_DIR) }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// sha256 of a layer blob. It is remembered by the blob's path, size and modification
+    /// time, so each blob is only read once.
+    pub fn layer_digest(&self, layer: &Path) -> Result<String> {
+        let memo = self.digest_memo(layer)?;
+        if let Ok(digest) = fs::read_to_string(&memo) {
+            return Ok(digest.trim().to_string());
+        }
+
+        let mut file = fs::File::open(layer).map_err(|e| CuboError::LayerExtractionError {
+            layer: layer.to_path_buf(),
+            message: format!("Failed to read layer: {}", e),
+        })?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        let digest = format!("sha256:{:x}", hasher.finalize());
+
+        if let Err(e) = fs::create_dir_all(self.root.join(DIGESTS_DIR)).and_then(|_| fs::write(&memo, &digest)) {
+            debug!("Failed to remember the digest of {}: {}", layer.display(), e);
+        }
+        Ok(digest)
+    }
+
+    /// Names of the entries of `layers`, bottom first. Blobs not hashed yet are hashed in
+    /// parallel.
+    pub fn chain(&self, layers: &[PathBuf]) -> Result<Vec<String>> {
+        let mut names: Vec<String> = Vec::with_capacity(layers.len());
+        unpack::pipelined(layers, unpack::scan_workers(), |layer| self.layer_digest(layer), |_, digest| {
+            names.push(Self::entry_name(names.last(), &digest));
+            Ok(())
+        })?;
+        Ok(names)
+    }
+
+    /// Fill `target` with `layers` applied in order. Only the layers above the topmost
+    /// entry already cached are extracted: `prepare` runs ahead on worker threads, and
+    /// `extract` applies each prepared layer over a copy of the entry below it.
+    pub fn materialize<T: Send>(
+        &self,
+        layers: &[PathBuf],
+        target: &Path,
+        prepare: impl Fn(&Path) -> Result<T> + Sync,
+        extract: impl Fn(T, &Path) -> Result<()>,
+    ) -> Result<()> {
+        let names = self.chain(layers)?;
+        let start = names.iter().rposition(|name| self.root.join(name).is_dir()).map_or(0, |idx| idx + 1);
+        let parent = |idx: usize| idx.checked_sub(1).map(|parent| self.root.join(&names[parent]));
+
+        unpack::pipelined(&layers[start..], unpack::scan_workers(), prepare, |n, layer| {
+            let idx = start + n;
+            debug!("Extracting layer {}/{}: {}", idx + 1, layers.len(), layers[idx].display());
+            let scratch = self.root.join(format!("tmp-{}", uuid::Uuid::new_v4()));
+            let committed = self
+                .provision(parent(idx).as_deref(), &scratch)
+                .and_then(|_| extract(layer, &scratch))
+                .and_then(|_| self.commit(&names[idx], &scratch));
+            if committed.is_err() {
+                let _ = fs::remove_dir_all(&scratch);
+            }
+            committed
+        })?;
+
+        self.provision(parent(layers.len()).as_deref(), target)
+    }
+
+    /// Remove the entries and digests that no image in `image_store` uses. Returns how many
+    /// entries went.
+    pub fn prune(&self, image_store: &ImageStore) -> Result<usize> {
+        if !self.root.exists() {
+            return Ok(0);
+        }
+
+        let mut live_entries = HashSet::new();
+        let mut live_memos = HashSet::new();
+        for reference in image_store.list_images()? {
+            let mut parent: Option<String> = None;
+            for layer in image_store.get_layers(&reference).unwrap_or_default() {
+                let Ok(memo) = self.digest_memo(&layer) else {
+                    break;
+                };
+                live_memos.insert(memo.clone());
+                // Layers never extracted have no digest yet, and nothing above them is cached
+                let Ok(digest) = fs::read_to_string(&memo) else {
+                    break;
+                };
+                let name = Self::entry_name(parent.as_ref(), digest.trim());
+                live_entries.insert(name.clone());
+                parent = Some(name);
+            }
+        }
+
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            // Scratch trees belong to extractions still running
+            if name == DIGESTS_DIR || name.starts_with("tmp-") || live_entries.contains(&name) {
+                continue;
+            }
+            match fs::remove_dir_all(&path) {
+                Ok(_) => removed += 1,
+                Err(e) => warn!("Failed to remove extracted layer {}: {}", path.display(), e),
+            }
+        }
+
+        if let Ok(memos) = fs::read_dir(self.root.join(DIGESTS_DIR)) {
+            for memo in memos.flatten() {
+                if !live_memos.contains(&memo.path()) {
+                    let _ = fs::remove_file(memo.path());
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entry_name(parent: Option<&String>, digest: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(parent.map(String::as_str).unwrap_or("").as_bytes());
+        hasher.update(b"\n");
+        hasher.update(digest.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn digest_memo(&self, layer: &Path) -> Result<PathBuf> {
+        Ok(self.root.join(DIGESTS_DIR).join(snapshotter::chain_id(None, layer)?))
+    }
+
+    /// Create `target` as a copy of `parent`, sharing blocks where the filesystem can. Hard
+    /// links are never used: builds change files in place.
+    fn provision(&self, parent: Option<&Path>, target: &Path) -> Result<()> {
+        fs::create_dir_all(target)
+            .map_err(|e| CuboError::SystemError(format!("Failed to create directory {}: {}", target.display(), e)))?;
+        match parent {
+            Some(parent) => snapshotter::run(
+                Command::new("cp").args(ProvisionMode::Auto.cp_args()).arg(parent.join(".")).arg(target),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Move the extracted `scratch` tree to entry `name`, unless another extraction got there first
+    fn commit(&self, name: &str, scratch: &Path) -> Result<()> {
+        let entry = self.root.join(name);
+        if entry.exists() {
+            return Ok(fs::remove_dir_all(scratch)?);
+        }
+        if let Err(e) = fs::rename(scratch, &entry) {
+            if entry.exists() {
+                return Ok(fs::remove_dir_all(scratch)?);
+            }
+            return Err(CuboError::SystemError(format!("Failed to cache extracted layer {}: {}", name, e)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::image_store::{ImageConfig, ImageManifest};
+    use tempfile::TempDir;
+
+    fn image(store: &ImageStore, reference: &str, layers: &[&Path]) {
+        store
+            .save_manifest(&ImageManifest {
+                reference: reference.to_string(),
+                layers: layers.iter().map(|l| l.to_string_lossy().to_string()).collect(),
+                config: ImageConfig {
+                    cmd: None,
+                    env: None,
+                    working_dir: None,
+                    exposed_ports: None,
+                    stop_signal: None,
+                    labels: None,
+                },
+                parent: None,
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+    }
+
+    fn prepare(layer: &Path) -> Result<PathBuf> {
+        Ok(layer.to_path_buf())
+    }
+
+    /// Stands in for unpacking: writes a file named after the layer
+    fn extract(layer: PathBuf, target: &Path) -> Result<()> {
+        fs::write(target.join(layer.file_name().unwrap()), fs::read(layer)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_depends_on_content_only() {
+        let temp = TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        let cache = LayerCache::new(&store);
+        for (name, content) in [("a.tar", "base"), ("b.tar", "base"), ("c.tar", "app")] {
+            fs::write(temp.path().join(name), content).unwrap();
+        }
+
+        let a = cache.chain(&[temp.path().join("a.tar"), temp.path().join("c.tar")]).unwrap();
+        let b = cache.chain(&[temp.path().join("b.tar"), temp.path().join("c.tar")]).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a[0], a[1]);
+        assert!(cache.layer_digest(&temp.path().join("a.tar")).unwrap().starts_with("sha256:"));
+    }
+
+    #[test]
+    fn test_materialize_extracts_each_layer_once() {
+        let temp = TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        let cache = LayerCache::new(&store);
+        let layers = vec![temp.path().join("base.tar"), temp.path().join("app.tar")];
+        fs::write(&layers[0], "base").unwrap();
+        fs::write(&layers[1], "app").unwrap();
+
+        cache.materialize(&layers, &temp.path().join("one"), prepare, extract).unwrap();
+        assert_eq!(fs::read_to_string(temp.path().join("one/base.tar")).unwrap(), "base");
+        assert_eq!(fs::read_to_string(temp.path().join("one/app.tar")).unwrap(), "app");
+
+        let extracted = std::cell::Cell::new(0);
+        let counting = |layer: PathBuf, target: &Path| {
+            extracted.set(extracted.get() + 1);
+            extract(layer, target)
+        };
+        cache.materialize(&layers, &temp.path().join("two"), prepare, counting).unwrap();
+        assert_eq!(extracted.get(), 0);
+        assert!(temp.path().join("two/app.tar").exists());
+
+        // Changing a copy leaves the cache alone
+        fs::write(temp.path().join("two/base.tar"), "changed").unwrap();
+        cache.materialize(&layers[..1], &temp.path().join("three"), prepare, counting).unwrap();
+        assert_eq!(fs::read_to_string(temp.path().join("three/base.tar")).unwrap(), "base");
+        assert_eq!(extracted.get(), 0);
+    }
+
+    #[test]
+    fn test_materialize_failure_caches_nothing() {
+        let temp = TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        let cache = LayerCache::new(&store);
+        let layers = vec![temp.path().join("layer.tar")];
+        fs::write(&layers[0], "data").unwrap();
+
+        let failing = |_: PathBuf, _: &Path| Err(CuboError::SystemError("corrupt".to_string()));
+        assert!(cache.materialize(&layers, &temp.path().join("rootfs"), prepare, failing).is_err());
+        let entries: Vec<_> = fs::read_dir(temp.path().join("images/extracted"))
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name() != DIGESTS_DIR)
+            .collect();
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn test_prune_keeps_layers_of_stored_images() {
+        let temp = TempDir::new().unwrap();
+        let store = ImageStore::new(temp.path().join("images")).unwrap();
+        let cache = LayerCache::new(&store);
+        let (base, app, old) = (temp.path().join("base.tar"), temp.path().join("app.tar"), temp.path().join("old.tar"));
+        for (layer, content) in [(&base, "base"), (&app, "app"), (&old, "old")] {
+            fs::write(layer, content).unwrap();
+        }
+        image(&store, "app:latest", &[&base, &app]);
+        image(&store, "old:latest", &[&base, &old]);
+        cache.materialize(&[base.clone(), app.clone()], &temp.path().join("a"), prepare, extract).unwrap();
+        cache.materialize(&[base.clone(), old.clone()], &temp.path().join("b"), prepare, extract).unwrap();
+
+        assert_eq!(cache.prune(&store).unwrap(), 0);
+        store.remove_image("old:latest").unwrap();
+        assert_eq!(cache.prune(&store).unwrap(), 1);
+
+        let names = cache.chain(&[base.clone(), app.clone()]).unwrap();
+        assert!(names.iter().all(|name| temp.path().join("images/extracted").join(name).is_dir()));
+        assert!(fs::read_dir(temp.path().join("images/extracted/digests")).unwrap().count() == 2);
+    }
+}
@@ -0,0 +1,141 @@
+//! Advisory flock(2) locks shared by every cubo process using the same root directory
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use tracing::debug;
+
+use crate::error::{CuboError, Result};
+
+/// Held until dropped. flock locks belong to the open file, so two locks on the same path
+/// exclude each other even inside one process.
+#[derive(Debug)]
+pub struct FileLock {
+    _flock: Flock<File>,
+}
+
+impl FileLock {
+    pub fn exclusive(path: &Path) -> Result<Self> {
+        Self::blocking(path, FlockArg::LockExclusive)
+    }
+
+    pub fn shared(path: &Path) -> Result<Self> {
+        Self::blocking(path, FlockArg::LockShared)
+    }
+
+    /// The exclusive lock, or None right away when someone else holds the path
+    pub fn try_exclusive(path: &Path) -> Result<Option<Self>> {
+        Self::acquire(path, FlockArg::LockExclusiveNonblock)
+    }
+
+    fn blocking(path: &Path, arg: FlockArg) -> Result<Self> {
+        Self::acquire(path, arg)?
+            .ok_or_else(|| CuboError::SystemError(format!("Failed to lock {}", path.display())))
+    }
+
+    fn acquire(path: &Path, arg: FlockArg) -> Result<Option<Self>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| CuboError::SystemError(format!("Failed to create lock dir: {}", e)))?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|e| CuboError::SystemError(format!("Failed to open lock {}: {}", path.display(), e)))?;
+        match Flock::lock(file, arg) {
+            Ok(flock) => {
+                debug!("Locked {}", path.display());
+                Ok(Some(Self { _flock: flock }))
+            }
+            Err((_, Errno::EWOULDBLOCK)) => Ok(None),
+            Err((_, errno)) => Err(CuboError::SystemError(format!("Failed to lock {}: {}", path.display(), errno))),
+        }
+    }
+}
+
+/// Lock files of the containers and the store. They are kept apart from the bundles and
+/// never deleted while in use: a process waiting on a deleted lock file would lock nothing.
+pub fn locks_dir(root_dir: &Path) -> PathBuf {
+    root_dir.join("locks")
+}
+
+fn store_lock_path(root_dir: &Path) -> PathBuf {
+    locks_dir(root_dir).join("store.lock")
+}
+
+/// Lock held while a container's bundle changes: the container exclusively, the store shared
+#[derive(Debug)]
+pub struct ContainerLock {
+    _container: FileLock,
+    _store: FileLock,
+}
+
+impl ContainerLock {
+    pub fn acquire(root_dir: &Path, container_id: &str) -> Result<Self> {
+        let store = FileLock::shared(&store_lock_path(root_dir))?;
+        let container = FileLock::exclusive(&locks_dir(root_dir).join(format!("{}.lock", container_id)))?;
+        Ok(Self { _container: container, _store: store })
+    }
+}
+
+/// The whole store to oneself, e.g. to collect garbage: waits for every container lock
+pub fn lock_store(root_dir: &Path) -> Result<FileLock> {
+    FileLock::exclusive(&store_lock_path(root_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_exclusive_and_shared_locks() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("locks/a.lock");
+
+        let held = FileLock::exclusive(&path).unwrap();
+        assert!(FileLock::try_exclusive(&path).unwrap().is_none());
+        drop(held);
+        let held = FileLock::try_exclusive(&path).unwrap();
+        assert!(held.is_some());
+        drop(held);
+
+        let _first = FileLock::shared(&path).unwrap();
+        let _second = FileLock::shared(&path).unwrap();
+        assert!(FileLock::try_exclusive(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_container_lock_blocks_store_lock() {
+        let temp = TempDir::new().unwrap();
+        let container = ContainerLock::acquire(temp.path(), "c1").unwrap();
+        // Other containers can still be locked, the whole store can't
+        let _other = ContainerLock::acquire(temp.path(), "c2").unwrap();
+        assert!(FileLock::try_exclusive(&store_lock_path(temp.path())).unwrap().is_none());
+        assert!(FileLock::try_exclusive(&locks_dir(temp.path()).join("c1.lock")).unwrap().is_none());
+        drop(container);
+        drop(_other);
+        let _store = lock_store(temp.path()).unwrap();
+    }
+
+    #[test]
+    fn test_lock_waits_for_other_holder() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("wait.lock");
+        let held = FileLock::exclusive(&path).unwrap();
+
+        let waiter_path = path.clone();
+        let waiter = std::thread::spawn(move || {
+            let started = std::time::Instant::now();
+            let _lock = FileLock::exclusive(&waiter_path).unwrap();
+            started.elapsed()
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        drop(held);
+        assert!(waiter.join().unwrap() >= std::time::Duration::from_millis(150));
+    }
+}
@@ -0,0 +1,601 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use nix::errno::Errno;
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::unistd::{fork, pipe, ForkResult};
+use serde::{Deserialize, Serialize};
+
+use crate::container::{Container, LogDriverKind, LogOptions};
+use crate::error::{CuboError, Result};
+
+/// Log file of the json-file driver, inside the container bundle
+pub const LOG_FILE: &str = "container.log";
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+/// One line of container output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub stream: LogStream,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+impl LogEntry {
+    pub fn new(stream: LogStream, message: String) -> Self {
+        Self { stream, timestamp: Utc::now(), message }
+    }
+
+    /// Parse a line written by the json-file driver
+    pub fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str(line).ok()
+    }
+}
+
+impl std::fmt::Display for LogStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogStream::Stdout => write!(f, "stdout"),
+            LogStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Destination of a container's output
+pub trait LogDriver {
+    fn write(&mut self, entry: &LogEntry) -> Result<()>;
+}
+
+/// One JSON object per line in the bundle's container.log, rotated to container.log.1,
+/// container.log.2, ... when `max_size` is set
+pub struct JsonFileDriver {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    max_file: u32,
+}
+
+impl JsonFileDriver {
+    pub fn open(path: &Path, options: &LogOptions) -> Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size: options.max_size,
+            max_file: options.max_file.unwrap_or(1).max(1),
+        })
+    }
+
+    /// Shift the rotated segments up by one, dropping the oldest, and start a new file.
+    /// Renames are atomic, so readers see either the old or the new layout.
+    fn rotate(&mut self) -> Result<()> {
+        if self.max_file > 1 {
+            for index in (1..self.max_file - 1).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl LogDriver for JsonFileDriver {
+    fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)
+            .map_err(|e| CuboError::SystemError(format!("Failed to serialize log entry: {}", e)))?;
+        line.push('\n');
+
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + line.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// The Kubernetes CRI log format the kubelet reads: `<RFC3339Nano> <stream> F <message>`.
+/// The kubelet rotates the file by renaming it; writes then go to a fresh file at `path`.
+pub struct CriFileDriver {
+    path: PathBuf,
+    file: File,
+}
+
+impl CriFileDriver {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Self { path: path.to_path_buf(), file: open_append(path)? })
+    }
+}
+
+impl LogDriver for CriFileDriver {
+    fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        if !self.path.exists() || was_rotated(&self.path, &self.file) {
+            self.file = open_append(&self.path)?;
+        }
+        self.file.write_all(cri_line(entry).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// One full (`F`) line; the logger only hands over complete lines
+pub fn cri_line(entry: &LogEntry) -> String {
+    format!(
+        "{} {} F {}\n",
+        entry.timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        entry.stream,
+        entry.message
+    )
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| CuboError::SystemError(format!("Failed to open log file {:?}: {}", path, e)))
+}
+
+/// Path of a rotated segment; 1 is the most recent
+pub fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Existing segments of a log, oldest first and ending with the live file
+pub fn log_segments(path: &Path) -> Vec<PathBuf> {
+    let mut segments: Vec<PathBuf> = (1..)
+        .map(|index| rotated_path(path, index))
+        .take_while(|segment| segment.exists())
+        .collect();
+    segments.reverse();
+    if path.exists() {
+        segments.push(path.to_path_buf());
+    }
+    segments
+}
+
+/// True once `path` names a different file than the one being read
+pub fn was_rotated(path: &Path, current: &File) -> bool {
+    match (fs::metadata(path), current.metadata()) {
+        (Ok(latest), Ok(open)) => latest.ino() != open.ino() || latest.dev() != open.dev(),
+        _ => false,
+    }
+}
+
+/// Entries sent to journald over its native protocol, tagged with the container
+pub struct JournaldDriver {
+    socket: UnixDatagram,
+    fields: Vec<(String, String)>,
+}
+
+impl JournaldDriver {
+    pub fn connect(container: &Container) -> Result<Self> {
+        Self::connect_to(Path::new(JOURNAL_SOCKET), container)
+    }
+
+    pub fn connect_to(socket_path: &Path, container: &Container) -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(socket_path)
+            .map_err(|e| CuboError::SystemError(format!("Failed to connect to journald at {:?}: {}", socket_path, e)))?;
+        Ok(Self { socket, fields: journal_fields(container) })
+    }
+}
+
+impl LogDriver for JournaldDriver {
+    fn write(&mut self, entry: &LogEntry) -> Result<()> {
+        let priority = match entry.stream {
+            LogStream::Stdout => "6",
+            LogStream::Stderr => "3",
+        };
+        let mut fields: Vec<(&str, &str)> = self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        fields.push(("PRIORITY", priority));
+        fields.push(("MESSAGE", &entry.message));
+        self.socket.send(&journal_payload(&fields))?;
+        Ok(())
+    }
+}
+
+/// Discards everything
+pub struct NoneDriver;
+
+impl LogDriver for NoneDriver {
+    fn write(&mut self, _entry: &LogEntry) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The driver selected in the container config
+pub fn open_driver(container: &Container, bundle: &Path) -> Result<Box<dyn LogDriver>> {
+    Ok(match container.config.log_driver {
+        LogDriverKind::JsonFile => Box::new(JsonFileDriver::open(&bundle.join(LOG_FILE), &container.config.log_options)?),
+        LogDriverKind::Journald => Box::new(JournaldDriver::connect(container)?),
+        LogDriverKind::Cri => {
+            let path = container.config.log_options.path.as_deref().ok_or_else(|| {
+                CuboError::InvalidConfiguration("The cri log driver requires a path".to_string())
+            })?;
+            Box::new(CriFileDriver::open(path)?)
+        }
+        LogDriverKind::None => Box::new(NoneDriver),
+    })
+}
+
+/// Fields attached to every journal entry of the container
+pub fn journal_fields(container: &Container) -> Vec<(String, String)> {
+    let short_id: String = container.id.chars().take(12).collect();
+    let name = container.name.clone().unwrap_or_else(|| short_id.clone());
+    vec![
+        ("CONTAINER_ID".to_string(), short_id),
+        ("CONTAINER_ID_FULL".to_string(), container.id.clone()),
+        ("CONTAINER_NAME".to_string(), name.clone()),
+        ("SYSLOG_IDENTIFIER".to_string(), name),
+    ]
+}
+
+/// Serialize fields for the journal socket. Values containing a newline use the binary
+/// form: name, newline, little-endian u64 length, value.
+pub fn journal_payload(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (name, value) in fields {
+        payload.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            payload.push(b'\n');
+            payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            payload.push(b'=');
+        }
+        payload.extend_from_slice(value.as_bytes());
+        payload.push(b'\n');
+    }
+    payload
+}
+
+/// Splits a byte stream into lines without their newline
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=pos).collect();
+            lines.push(String::from_utf8_lossy(&line[..line.len() - 1]).into_owned());
+        }
+        lines
+    }
+
+    /// The trailing partial line, if any
+    pub fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        Some(line)
+    }
+}
+
+/// Send the stdout and stderr of the calling process to the container's log driver.
+/// With `none` they go to /dev/null; otherwise a logger process reads them through pipes
+/// and hands each line to the driver until every writer has exited.
+pub fn redirect_output(container: &Container, bundle: &Path) -> Result<()> {
+    if container.config.log_driver == LogDriverKind::None {
+        let devnull = File::options().write(true).open("/dev/null")?;
+        return dup_onto(devnull.as_raw_fd(), &[1, 2]);
+    }
+
+    let mut driver = open_driver(container, bundle)?;
+    let (out_r, out_w) = pipe().map_err(|e| CuboError::SystemError(format!("Failed to create log pipe: {}", e)))?;
+    let (err_r, err_w) = pipe().map_err(|e| CuboError::SystemError(format!("Failed to create log pipe: {}", e)))?;
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { .. }) => {
+            dup_onto(out_w.as_raw_fd(), &[1])?;
+            dup_onto(err_w.as_raw_fd(), &[2])
+        }
+        Ok(ForkResult::Child) => {
+            drop((out_w, err_w));
+            // Keep only the log pipes so setup pipes of the container still see EOF
+            if let Ok(devnull) = File::options().read(true).write(true).open("/dev/null") {
+                let _ = dup_onto(devnull.as_raw_fd(), &[0, 1, 2]);
+            }
+            close_other_fds(&[0, 1, 2, out_r.as_raw_fd(), err_r.as_raw_fd()]);
+            for sig in [Signal::SIGINT, Signal::SIGHUP] {
+                let _ = unsafe { signal(sig, SigHandler::SigIgn) };
+            }
+
+            pump(driver.as_mut(), out_r, err_r);
+            std::process::exit(0);
+        }
+        Err(e) => Err(CuboError::SystemError(format!("Failed to fork logger: {}", e))),
+    }
+}
+
+fn dup_onto(fd: RawFd, targets: &[RawFd]) -> Result<()> {
+    for target in targets {
+        if unsafe { libc::dup2(fd, *target) } < 0 {
+            return Err(CuboError::SystemError(format!("Failed to redirect fd {}: {}", target, Errno::last())));
+        }
+    }
+    Ok(())
+}
+
+fn close_other_fds(keep: &[RawFd]) {
+    let Ok(entries) = fs::read_dir("/proc/self/fd") else {
+        return;
+    };
+    let open: Vec<RawFd> = entries
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in open.into_iter().filter(|fd| !keep.contains(fd)) {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Read both streams until they are closed, writing every line to the driver
+fn pump(driver: &mut dyn LogDriver, stdout: OwnedFd, stderr: OwnedFd) {
+    let mut streams = vec![
+        (stdout, LogStream::Stdout, LineBuffer::default()),
+        (stderr, LogStream::Stderr, LineBuffer::default()),
+    ];
+    let mut buf = [0u8; 8192];
+
+    while !streams.is_empty() {
+        let mut fds: Vec<libc::pollfd> = streams
+            .iter()
+            .map(|(fd, _, _)| libc::pollfd { fd: fd.as_raw_fd(), events: libc::POLLIN, revents: 0 })
+            .collect();
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            if Errno::last() == Errno::EINTR {
+                continue;
+            }
+            break;
+        }
+
+        let mut closed = Vec::new();
+        for (i, pollfd) in fds.iter().enumerate() {
+            if pollfd.revents == 0 {
+                continue;
+            }
+            let (fd, stream, lines) = &mut streams[i];
+            match nix::unistd::read(&*fd, &mut buf) {
+                Ok(0) => closed.push(i),
+                Ok(n) => {
+                    for line in lines.push(&buf[..n]) {
+                        let _ = driver.write(&LogEntry::new(*stream, line));
+                    }
+                }
+                Err(Errno::EINTR) | Err(Errno::EAGAIN) => {}
+                Err(_) => closed.push(i),
+            }
+        }
+
+        for i in closed.into_iter().rev() {
+            let (_, stream, mut lines) = streams.remove(i);
+            if let Some(rest) = lines.flush() {
+                let _ = driver.write(&LogEntry::new(stream, rest));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_log_entry_roundtrip() {
+        let entry = LogEntry::new(LogStream::Stderr, "boom".to_string());
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.contains("\"stream\":\"stderr\""));
+        assert!(line.contains("\"message\":\"boom\""));
+        assert_eq!(LogEntry::parse(&line).unwrap(), entry);
+        assert!(LogEntry::parse("plain text").is_none());
+    }
+
+    #[test]
+    fn test_json_file_driver_appends_lines() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(LOG_FILE);
+        let mut driver = JsonFileDriver::open(&path, &LogOptions::default()).unwrap();
+        driver.write(&LogEntry::new(LogStream::Stdout, "one".to_string())).unwrap();
+        driver.write(&LogEntry::new(LogStream::Stderr, "two".to_string())).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let entries: Vec<LogEntry> = content.lines().filter_map(LogEntry::parse).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].message, "one");
+        assert_eq!(entries[1].stream, LogStream::Stderr);
+    }
+
+    // Fixed timestamp so every serialized line has the same length
+    fn entry(message: &str) -> LogEntry {
+        LogEntry {
+            stream: LogStream::Stdout,
+            timestamp: "2025-11-24T20:30:00.123456Z".parse().unwrap(),
+            message: message.to_string(),
+        }
+    }
+
+    fn messages(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter_map(LogEntry::parse)
+            .map(|e| e.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_json_file_driver_rotates() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(LOG_FILE);
+        let line_len = serde_json::to_string(&entry("line-0")).unwrap().len() as u64 + 1;
+        let options = LogOptions { max_size: Some(line_len * 2), max_file: Some(3), ..Default::default() };
+
+        let mut driver = JsonFileDriver::open(&path, &options).unwrap();
+        for i in 0..7 {
+            driver.write(&entry(&format!("line-{}", i))).unwrap();
+        }
+
+        assert_eq!(messages(&path), vec!["line-6"]);
+        assert_eq!(messages(&rotated_path(&path, 1)), vec!["line-4", "line-5"]);
+        assert_eq!(messages(&rotated_path(&path, 2)), vec!["line-2", "line-3"]);
+        assert!(!rotated_path(&path, 3).exists());
+        assert_eq!(
+            log_segments(&path),
+            vec![rotated_path(&path, 2), rotated_path(&path, 1), path.clone()]
+        );
+    }
+
+    #[test]
+    fn test_json_file_driver_single_file_truncates() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(LOG_FILE);
+        let options = LogOptions { max_size: Some(10), max_file: None, ..Default::default() };
+
+        let mut driver = JsonFileDriver::open(&path, &options).unwrap();
+        driver.write(&entry("first")).unwrap();
+        driver.write(&entry("second")).unwrap();
+
+        assert_eq!(messages(&path), vec!["second"]);
+        assert!(!rotated_path(&path, 1).exists());
+    }
+
+    #[test]
+    fn test_log_segments() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join(LOG_FILE);
+        assert!(log_segments(&path).is_empty());
+
+        fs::write(&path, "").unwrap();
+        fs::write(rotated_path(&path, 1), "").unwrap();
+        assert_eq!(log_segments(&path), vec![rotated_path(&path, 1), path.clone()]);
+        assert_eq!(rotated_path(&path, 1).file_name().unwrap(), "container.log.1");
+    }
+
+    #[test]
+    fn test_line_buffer() {
+        let mut buffer = LineBuffer::default();
+        assert!(buffer.push(b"hel").is_empty());
+        assert_eq!(buffer.push(b"lo\nwor"), vec!["hello"]);
+        assert_eq!(buffer.push(b"ld\n\n"), vec!["world", ""]);
+        assert!(buffer.flush().is_none());
+
+        buffer.push(b"partial");
+        assert_eq!(buffer.flush().unwrap(), "partial");
+    }
+
+    #[test]
+    fn test_journal_payload() {
+        let payload = journal_payload(&[("PRIORITY", "6"), ("MESSAGE", "hello")]);
+        assert_eq!(payload, b"PRIORITY=6\nMESSAGE=hello\n");
+
+        let payload = journal_payload(&[("MESSAGE", "a\nb")]);
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn test_journal_fields() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_name("web".to_string());
+        let fields = journal_fields(&container);
+        assert!(fields.contains(&("CONTAINER_NAME".to_string(), "web".to_string())));
+        assert!(fields.contains(&("CONTAINER_ID_FULL".to_string(), container.id.clone())));
+        assert_eq!(fields[0].1.len(), 12);
+    }
+
+    #[test]
+    fn test_journald_driver_sends_datagrams() {
+        let temp = TempDir::new().unwrap();
+        let socket_path = temp.path().join("journal.sock");
+        let server = UnixDatagram::bind(&socket_path).unwrap();
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let mut driver = JournaldDriver::connect_to(&socket_path, &container).unwrap();
+        driver.write(&LogEntry::new(LogStream::Stderr, "failed".to_string())).unwrap();
+
+        let mut buf = [0u8; 1024];
+        let n = server.recv(&mut buf).unwrap();
+        let payload = String::from_utf8_lossy(&buf[..n]);
+        assert!(payload.contains("PRIORITY=3\n"));
+        assert!(payload.contains("MESSAGE=failed\n"));
+        assert!(payload.contains(&format!("CONTAINER_ID_FULL={}\n", container.id)));
+    }
+
+    #[test]
+    fn test_journald_driver_missing_socket() {
+        let temp = TempDir::new().unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        assert!(JournaldDriver::connect_to(&temp.path().join("missing"), &container).is_err());
+    }
+
+    #[test]
+    fn test_open_driver_none() {
+        let temp = TempDir::new().unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_log_driver(LogDriverKind::None);
+        let mut driver = open_driver(&container, temp.path()).unwrap();
+        driver.write(&LogEntry::new(LogStream::Stdout, "dropped".to_string())).unwrap();
+        assert!(!temp.path().join(LOG_FILE).exists());
+    }
+
+    #[test]
+    fn test_cri_driver_writes_kubelet_format() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("pods/app/0.log");
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_log_driver(LogDriverKind::Cri)
+            .with_log_options(LogOptions { path: Some(path.clone()), ..Default::default() });
+        let mut driver = open_driver(&container, temp.path()).unwrap();
+        driver.write(&LogEntry::new(LogStream::Stderr, "oops".to_string())).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        let (timestamp, rest) = written.split_once(' ').unwrap();
+        assert!(DateTime::parse_from_rfc3339(timestamp).is_ok());
+        assert_eq!(rest, "stderr F oops\n");
+
+        // Rotated away by the kubelet: the next line starts a new file
+        fs::rename(&path, temp.path().join("pods/app/0.log.1")).unwrap();
+        driver.write(&LogEntry::new(LogStream::Stdout, "again".to_string())).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().ends_with(" stdout F again\n"));
+    }
+}
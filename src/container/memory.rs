@@ -0,0 +1,237 @@
+//! In-memory runtime and stores that never touch the filesystem, for tests of code built on
+//! the `api` traits. Containers don't run: an attached start exits with 0 right away, a
+//! detached one keeps running until it is stopped.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::container::api::{ApiFuture, ContainerStore, ImageRepository, Runtime};
+use crate::container::image_store::ImageManifest;
+use crate::container::{Container, ContainerStatus};
+use crate::error::{CuboError, Result};
+
+#[derive(Debug, Default)]
+pub struct MemoryContainerStore {
+    containers: Mutex<HashMap<String, Container>>,
+}
+
+impl ContainerStore for MemoryContainerStore {
+    fn save_config(&self, container: &Container) -> Result<()> {
+        self.containers.lock().unwrap().insert(container.id.clone(), container.clone());
+        Ok(())
+    }
+
+    fn save_state(&self, container: &Container) -> Result<()> {
+        let mut containers = self.containers.lock().unwrap();
+        let saved = containers.entry(container.id.clone()).or_insert_with(|| container.clone());
+        saved.status = container.status.clone();
+        saved.pid = container.pid;
+        Ok(())
+    }
+
+    fn load_all(&self) -> Result<HashMap<String, Container>> {
+        Ok(self.containers.lock().unwrap().clone())
+    }
+
+    fn load_one(&self, container_id: &str) -> Result<Option<Container>> {
+        Ok(self.containers.lock().unwrap().get(container_id).cloned())
+    }
+
+    fn remove(&self, container_id: &str) -> Result<()> {
+        self.containers.lock().unwrap().remove(container_id);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryImageRepository {
+    manifests: Mutex<HashMap<String, ImageManifest>>,
+}
+
+impl ImageRepository for MemoryImageRepository {
+    fn get_manifest(&self, image_ref: &str) -> Result<ImageManifest> {
+        self.manifests
+            .lock()
+            .unwrap()
+            .get(image_ref)
+            .cloned()
+            .ok_or_else(|| CuboError::ImageNotFound { reference: image_ref.to_string() })
+    }
+
+    fn has_image(&self, image_ref: &str) -> bool {
+        self.manifests.lock().unwrap().contains_key(image_ref)
+    }
+
+    fn list_images(&self) -> Result<Vec<String>> {
+        Ok(self.manifests.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn save_manifest(&self, manifest: &ImageManifest) -> Result<()> {
+        self.manifests.lock().unwrap().insert(manifest.reference.clone(), manifest.clone());
+        Ok(())
+    }
+
+    fn remove_image(&self, image_ref: &str) -> Result<()> {
+        self.manifests
+            .lock()
+            .unwrap()
+            .remove(image_ref)
+            .map(|_| ())
+            .ok_or_else(|| CuboError::ImageNotFound { reference: image_ref.to_string() })
+    }
+
+    fn tag(&self, source: &str, target: &str) -> Result<()> {
+        let manifest = ImageManifest { reference: target.to_string(), ..self.get_manifest(source)? };
+        self.save_manifest(&manifest)
+    }
+}
+
+/// Lifecycle bookkeeping of `ContainerRuntime` without processes, rootfs or networks
+pub struct MemoryRuntime {
+    store: Box<dyn ContainerStore>,
+}
+
+impl Default for MemoryRuntime {
+    fn default() -> Self {
+        Self::with_store(Box::new(MemoryContainerStore::default()))
+    }
+}
+
+impl MemoryRuntime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_store(store: Box<dyn ContainerStore>) -> Self {
+        Self { store }
+    }
+
+    fn load(&self, container_id: &str) -> Result<Container> {
+        self.store
+            .load_one(container_id)?
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))
+    }
+
+    fn set_status(&self, mut container: Container, status: ContainerStatus) -> Result<()> {
+        container.update_status(status);
+        self.store.save_config(&container)
+    }
+}
+
+impl Runtime for MemoryRuntime {
+    fn create_container(&self, container: Container) -> ApiFuture<'_, String> {
+        Box::pin(async move {
+            if let Some(ref sandbox_id) = container.config.sandbox {
+                self.load(sandbox_id)?;
+            }
+            self.store.save_config(&container)?;
+            Ok(container.id)
+        })
+    }
+
+    fn start_container<'a>(&'a self, container_id: &'a str, detach: bool) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let mut container = self.load(container_id)?;
+            if container.is_running() {
+                return Err(CuboError::SystemError("Container is already running".to_string()));
+            }
+            container.finished_at = None;
+            container.exit_code = None;
+            if detach {
+                return self.set_status(container, ContainerStatus::Running);
+            }
+            container.update_status(ContainerStatus::Running);
+            container.set_exit_code(0);
+            self.set_status(container, ContainerStatus::Stopped)
+        })
+    }
+
+    fn stop_container<'a>(&'a self, container_id: &'a str, _timeout: Option<Duration>) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let container = self.load(container_id)?;
+            if !container.is_running() {
+                return Ok(());
+            }
+            self.set_status(container, ContainerStatus::Stopped)
+        })
+    }
+
+    fn remove_container<'a>(&'a self, container_id: &'a str, force: bool) -> ApiFuture<'a, ()> {
+        Box::pin(async move {
+            let container = self.load(container_id)?;
+            if container.is_running() && !force {
+                return Err(CuboError::SystemError("Container is running. Use --force to remove".to_string()));
+            }
+            self.store.remove(&container.id)
+        })
+    }
+
+    fn list_containers(&self, all: bool) -> ApiFuture<'_, Vec<Container>> {
+        Box::pin(async move {
+            let mut containers: Vec<Container> = self
+                .store
+                .load_all()?
+                .into_values()
+                .filter(|c| all || c.is_running())
+                .collect();
+            containers.sort_by_key(|c| c.created_at);
+            Ok(containers)
+        })
+    }
+
+    fn get_container<'a>(&'a self, container_id: &'a str) -> ApiFuture<'a, Container> {
+        Box::pin(async move { self.load(container_id) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::image_store::ImageConfig;
+
+    #[tokio::test]
+    async fn test_memory_runtime_lifecycle() {
+        let runtime = MemoryRuntime::new();
+        let id = runtime
+            .create_container(Container::new("alpine:latest".to_string(), vec!["true".to_string()]))
+            .await
+            .unwrap();
+
+        runtime.start_container(&id, false).await.unwrap();
+        let container = runtime.get_container(&id).await.unwrap();
+        assert_eq!(container.status, ContainerStatus::Stopped);
+        assert_eq!(container.exit_code, Some(0));
+
+        runtime.start_container(&id, true).await.unwrap();
+        assert_eq!(runtime.list_containers(false).await.unwrap().len(), 1);
+        assert!(runtime.start_container(&id, true).await.is_err());
+        assert!(runtime.remove_container(&id, false).await.is_err());
+
+        runtime.stop_container(&id, None).await.unwrap();
+        assert!(runtime.list_containers(false).await.unwrap().is_empty());
+        runtime.remove_container(&id, false).await.unwrap();
+        assert!(runtime.list_containers(true).await.unwrap().is_empty());
+        assert!(matches!(runtime.get_container(&id).await, Err(CuboError::ContainerNotFound(_))));
+    }
+
+    #[test]
+    fn test_memory_image_repository() {
+        let store = MemoryImageRepository::default();
+        let manifest = ImageManifest {
+            reference: "app:v1".to_string(),
+            parent: None,
+            layers: vec![],
+            config: ImageConfig { cmd: None, env: None, working_dir: None, exposed_ports: None, stop_signal: None, labels: None },
+            annotations: None,
+            repo_digest: None,
+        };
+        store.save_manifest(&manifest).unwrap();
+        assert!(store.has_image("app:v1"));
+        assert_eq!(store.list_images().unwrap(), vec!["app:v1".to_string()]);
+        store.tag("app:v1", "app:latest").unwrap();
+        assert_eq!(store.get_manifest("app:latest").unwrap().reference, "app:latest");
+        store.remove_image("app:v1").unwrap();
+        assert!(matches!(store.get_manifest("app:v1"), Err(CuboError::ImageNotFound { .. })));
+    }
+}
@@ -6,9 +6,57 @@ pub mod rootfs;
 pub mod cubofile;
 pub mod cubofile_toml;
 pub mod builder;
+pub mod build_progress;
 pub mod registry;
+pub mod cgroup;
+pub mod ulimit;
+pub mod sysctl;
+pub mod network;
+pub mod dns;
+pub mod rootless_net;
+pub mod idmap;
+pub mod user;
+pub mod signal;
+pub mod init;
+pub mod checkpoint;
+pub mod logging;
+pub mod events;
+pub mod exec;
+pub mod compose;
+pub mod systemd;
+pub mod health;
+pub mod oci_spec;
+pub mod executor;
+pub mod snapshotter;
+pub mod quota;
+pub mod disk_usage;
+pub mod diagnostics;
+pub mod transport;
+pub mod signature;
+pub mod sbom;
+pub mod lock;
+pub mod audit;
+pub mod api;
+pub mod memory;
+pub mod supervisor;
+pub mod debug;
+pub mod interpreter;
+pub mod secure_join;
+pub mod reference;
+pub mod whiteout;
+pub mod unpack;
+pub mod layer_cache;
+pub mod stats;
+pub mod volume;
+pub mod admission;
+pub mod selinux;
+pub mod apparmor;
+pub mod landlock;
+pub mod sched;
+pub mod build_history;
 
 use std::collections::HashMap;
+use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -40,7 +88,18 @@ pub struct Container {
     pub exit_code: Option<i32>,
     /// PID of the main container process
     pub pid: Option<u32>,
-
+    /// Backend that runs the container's processes, fixed when it is created
+    #[serde(default)]
+    pub executor: executor::ExecutorKind,
+    /// Snapshotter holding the container's rootfs, fixed when it is created
+    #[serde(default)]
+    pub snapshotter: snapshotter::SnapshotterKind,
+    /// Times the supervisor restarted the container under its restart policy
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Ports published when the container last started, with ephemeral host ports resolved
+    #[serde(default)]
+    pub published_ports: Vec<PortMapping>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +120,9 @@ pub struct ContainerConfig {
     pub user: Option<String>,
     // Hostname in the containerdsadsadwq
     pub hostname: Option<String>,
+    /// NIS domain name of the container's UTS namespace
+    #[serde(default)]
+    pub domainname: Option<String>,
     // Whether to allocate TTY
     pub tty: bool,
     // Where to keep the STDIN open
@@ -69,9 +131,251 @@ pub struct ContainerConfig {
     pub network_mode: NetworkMode,
     // Restart policy
     pub restart_policy: RestartPolicy,
+    /// Memory plus swap in bytes, as docker counts it (memory.swap.max is the difference);
+    /// -1 for unlimited swap
+    #[serde(default)]
+    pub memory_swap: Option<i64>,
+    /// Memory in bytes the kernel reclaims from the container last (memory.low)
+    #[serde(default)]
+    pub memory_reservation: Option<u64>,
+    /// Adjustment of the OOM killer score of the container processes (-1000 to 1000)
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
+    /// Nice value of the container processes (-20 to 19)
+    #[serde(default)]
+    pub nice: Option<i32>,
+    /// CPU scheduling policy of the container processes, the host's default if unset
+    #[serde(default)]
+    pub sched_policy: Option<sched::SchedPolicy>,
+    /// Priority of a real-time scheduling policy (1 to 99)
+    #[serde(default)]
+    pub rt_priority: Option<u32>,
+    /// CPU time in microseconds a real-time process may use without blocking (RLIMIT_RTTIME)
+    #[serde(default)]
+    pub rt_runtime: Option<u64>,
+    /// I/O scheduling class and level of the container processes
+    #[serde(default)]
+    pub io_priority: Option<sched::IoPriority>,
+    /// Maximum number of processes (pids.max), None or <= 0 for unlimited
+    #[serde(default)]
+    pub pids_limit: Option<i64>,
+    /// CPUs the container may run on (cpuset.cpus list, e.g. 0-3,6)
+    #[serde(default)]
+    pub cpuset_cpus: Option<String>,
+    /// NUMA memory nodes the container may allocate from (cpuset.mems list)
+    #[serde(default)]
+    pub cpuset_mems: Option<String>,
+    /// Relative block I/O weight (10-1000)
+    #[serde(default)]
+    pub blkio_weight: Option<u16>,
+    /// Read rate limits per device
+    #[serde(default)]
+    pub device_read_bps: Vec<ThrottleDevice>,
+    /// Write rate limits per device
+    #[serde(default)]
+    pub device_write_bps: Vec<ThrottleDevice>,
+    /// Resource limits applied with setrlimit before exec
+    #[serde(default)]
+    pub ulimits: Vec<Ulimit>,
+    /// SELinux labels of the container (`--security-opt label=...`)
+    #[serde(default)]
+    pub label_options: selinux::LabelOptions,
+    /// AppArmor profile of the container (`--security-opt apparmor=...`), cubo-default if unset
+    #[serde(default)]
+    pub apparmor_profile: Option<String>,
+    /// Paths hidden from the container (`--security-opt mask=...`/`unmask=...`)
+    #[serde(default = "namespace::default_masked_paths")]
+    pub masked_paths: Vec<String>,
+    /// Paths mounted read-only in the container (`--security-opt unmask=...`)
+    #[serde(default = "namespace::default_readonly_paths")]
+    pub readonly_paths: Vec<String>,
+    /// Confine the container's processes to its root with Landlock (`--security-opt landlock=on`)
+    #[serde(default)]
+    pub landlock: bool,
+    /// Shifts of the monotonic and boottime clocks, seen through a new time namespace
+    #[serde(default)]
+    pub time_offsets: Vec<TimeOffset>,
+    /// Namespaced kernel parameters (net.*, IPC) set before exec
+    #[serde(default)]
+    pub sysctls: HashMap<String, String>,
+    /// IPC namespace mode (private, host)
+    #[serde(default)]
+    pub ipc_mode: IpcMode,
+    /// DNS servers written to /etc/resolv.conf (host servers if empty)
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// DNS search domains written to /etc/resolv.conf (host domains if empty)
+    #[serde(default)]
+    pub dns_search: Vec<String>,
+    /// Extra /etc/hosts entries
+    #[serde(default)]
+    pub extra_hosts: Vec<HostEntry>,
+    /// Static IPv4 address on the user-defined network
+    #[serde(default)]
+    pub ip_address: Option<String>,
+    /// Static MAC address on the user-defined network
+    #[serde(default)]
+    pub mac_address: Option<String>,
+    /// Additional user-defined networks joined with `cubo network connect`
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// User namespace mode (private, host, keep-id)
+    #[serde(default)]
+    pub userns_mode: UsernsMode,
+    /// Signal sent by `stop` before SIGKILL (SIGTERM if unset)
+    #[serde(default)]
+    pub stop_signal: Option<String>,
+    /// Run an embedded init as pid 1 that forwards signals and reaps zombies
+    #[serde(default)]
+    pub init: bool,
+    /// Where the output of a detached container goes
+    #[serde(default)]
+    pub log_driver: LogDriverKind,
+    /// Log driver options (rotation of the json-file driver)
+    #[serde(default)]
+    pub log_options: LogOptions,
+    /// Sandbox container whose network and IPC namespaces this one joins (pod members)
+    #[serde(default)]
+    pub sandbox: Option<String>,
+    /// Extra hostnames peers on the same networks resolve this container by
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Command that tells whether the service in the container is ready
+    #[serde(default)]
+    pub health_check: Option<health::HealthCheck>,
+    /// Quota in bytes on what the container writes to its rootfs
+    #[serde(default)]
+    pub storage_size: Option<u64>,
+    /// Mount the rootfs read-only; only volumes and tmpfs mounts are writable
+    #[serde(default)]
+    pub read_only_rootfs: bool,
+    /// Program exec'd with the command as its arguments, instead of running the command
+    /// with /bin/sh -c
+    #[serde(default)]
+    pub entrypoint: Option<String>,
+    /// Limits taken from `[defaults]` of the config file because none were given
+    /// (memory, cpus, pids_limit)
+    #[serde(default)]
+    pub inherited_limits: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostEntry {
+    pub hostname: String,
+    pub ip: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpcMode {
+    /// New IPC namespace for the container
+    #[default]
+    Private,
+    /// Share the host IPC namespace
+    Host,
+}
+
+impl std::str::FromStr for IpcMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(IpcMode::Private),
+            "host" => Ok(IpcMode::Host),
+            _ => Err(format!("invalid IPC mode '{}', expected host or private", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UsernsMode {
+    /// New user namespace with container root mapped to the invoking user
+    #[default]
+    Private,
+    /// No user namespace; requires running cubo as root
+    Host,
+    /// New user namespace mapping the invoking user to the same uid/gid inside
+    KeepId,
+}
+
+impl std::str::FromStr for UsernsMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(UsernsMode::Private),
+            "host" => Ok(UsernsMode::Host),
+            "keep-id" => Ok(UsernsMode::KeepId),
+            _ => Err(format!("invalid user namespace mode '{}', expected private, host or keep-id", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogDriverKind {
+    /// JSON lines in the bundle's container.log
+    #[default]
+    JsonFile,
+    /// The systemd journal
+    Journald,
+    /// Kubernetes CRI log format in the file given by the `path` option
+    Cri,
+    /// Discard the output
+    None,
+}
+
+impl std::str::FromStr for LogDriverKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json-file" => Ok(LogDriverKind::JsonFile),
+            "journald" => Ok(LogDriverKind::Journald),
+            "cri" => Ok(LogDriverKind::Cri),
+            "none" => Ok(LogDriverKind::None),
+            _ => Err(format!("invalid log driver '{}', expected json-file, journald, cri or none", s)),
+        }
+    }
+}
+
+/// Options of the json-file and cri log drivers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogOptions {
+    /// Rotate container.log once it would grow past this many bytes
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Number of log files to keep, the live one included (default 1)
+    #[serde(default)]
+    pub max_file: Option<u32>,
+    /// Log file of the cri driver, on the host
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ulimit {
+    /// Limit name as used by `ulimit` (nofile, nproc, core...)
+    pub name: String,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeOffset {
+    /// Clock to shift (monotonic or boottime)
+    pub clock: String,
+    /// Seconds added to the clock, negative to turn it back
+    pub secs: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThrottleDevice {
+    /// Path to the block device on the host (e.g. /dev/sda)
+    pub path: String,
+    /// Rate in bytes per second
+    pub rate: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RestartPolicy {
     No,
     Always,
@@ -79,7 +383,42 @@ pub enum RestartPolicy {
     OnFailure { max_retries: u32 },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "no" => Ok(RestartPolicy::No),
+            None if s == "always" => Ok(RestartPolicy::Always),
+            None if s == "unless-stopped" => Ok(RestartPolicy::UnlessStopped),
+            None if s == "on-failure" => Ok(RestartPolicy::OnFailure { max_retries: 0 }),
+            Some(("on-failure", retries)) => retries
+                .parse()
+                .map(|max_retries| RestartPolicy::OnFailure { max_retries })
+                .map_err(|_| format!("invalid retry count '{}' in restart policy", retries)),
+            _ => Err(format!(
+                "invalid restart policy '{}', expected no, always, unless-stopped or on-failure[:N]",
+                s
+            )),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Whether a container that exited with `exit_code` (None when it couldn't be reaped)
+    /// after `restarts` restarts is started again. A stop through cubo never gets here.
+    pub fn should_restart(&self, exit_code: Option<i32>, restarts: u32) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+            RestartPolicy::OnFailure { max_retries } => {
+                exit_code != Some(0) && (*max_retries == 0 || restarts < *max_retries)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NetworkMode {
     // Default bridge network
     Bridge,
@@ -91,9 +430,9 @@ pub enum NetworkMode {
     Custom(String), 
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PortMapping {
-    /// Port on the host
+    /// Port on the host, 0 for one picked when the container starts
     pub host_port: u16,
     /// Port in the container
     pub container_port: u16,
@@ -103,7 +442,7 @@ pub struct PortMapping {
     pub host_ip: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Protocol {
     Tcp,
     Udp,
@@ -129,6 +468,129 @@ pub struct VolumeMount {
     pub read_only: bool,
     /// Mount type (bind, volume, tmpfs)
     pub mount_type: MountType, 
+    /// Whether mounts made under the bind mount reach the host and the other way around
+    #[serde(default)]
+    pub propagation: Propagation,
+    /// Size, mode and exec of a tmpfs mount
+    #[serde(default)]
+    pub tmpfs_options: TmpfsOptions,
+    /// SELinux label given to the host files of the mount before it is made (`:z`, `:Z`)
+    #[serde(default)]
+    pub relabel: Option<Relabel>,
+}
+
+/// How the host files of a volume are labeled for SELinux
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Relabel {
+    /// Usable by every container (`:z`)
+    Shared,
+    /// Usable by this container only (`:Z`)
+    Private,
+}
+
+impl std::fmt::Display for Relabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Relabel::Shared => "z",
+            Relabel::Private => "Z",
+        })
+    }
+}
+
+/// Options of a tmpfs mount, as `--tmpfs /run:size=64m,mode=755,exec` gives them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TmpfsOptions {
+    /// Size limit in bytes; the kernel's default is half of the RAM
+    pub size: Option<u64>,
+    /// Permissions of the tmpfs root (1777 when unset)
+    pub mode: Option<u32>,
+    /// Allow executing files from it; tmpfs mounts are noexec otherwise
+    pub exec: bool,
+}
+
+impl TmpfsOptions {
+    /// The data argument of mount(2), e.g. `size=67108864,mode=755`
+    pub fn data(&self) -> Option<String> {
+        let mut options = Vec::new();
+        if let Some(size) = self.size {
+            options.push(format!("size={}", size));
+        }
+        if let Some(mode) = self.mode {
+            options.push(format!("mode={:o}", mode));
+        }
+        (!options.is_empty()).then(|| options.join(","))
+    }
+}
+
+/// Mount propagation of a bind mount, as mount(8) names it. The r- variants also apply to
+/// the mounts below it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Propagation {
+    /// Mounts don't propagate in either direction
+    Private,
+    #[default]
+    Rprivate,
+    /// Mounts propagate both ways between the host and the container
+    Shared,
+    Rshared,
+    /// Host mounts propagate into the container, not the other way
+    Slave,
+    Rslave,
+}
+
+impl Propagation {
+    pub fn is_shared(self) -> bool {
+        matches!(self, Propagation::Shared | Propagation::Rshared)
+    }
+
+    pub fn is_slave(self) -> bool {
+        matches!(self, Propagation::Slave | Propagation::Rslave)
+    }
+
+    /// What the container's root has to propagate for every mount to get its own: shared
+    /// when one is shared, slave when one is a slave, else private
+    pub fn of_root(volumes: &[VolumeMount]) -> Self {
+        let binds = || volumes.iter().filter(|v| matches!(v.mount_type, MountType::Bind));
+        if binds().any(|v| v.propagation.is_shared()) {
+            Propagation::Rshared
+        } else if binds().any(|v| v.propagation.is_slave()) {
+            Propagation::Rslave
+        } else {
+            Propagation::Rprivate
+        }
+    }
+}
+
+impl std::str::FromStr for Propagation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "private" => Ok(Propagation::Private),
+            "rprivate" => Ok(Propagation::Rprivate),
+            "shared" => Ok(Propagation::Shared),
+            "rshared" => Ok(Propagation::Rshared),
+            "slave" => Ok(Propagation::Slave),
+            "rslave" => Ok(Propagation::Rslave),
+            _ => Err(format!("invalid propagation '{}', expected [r]private, [r]shared or [r]slave", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Propagation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Propagation::Private => "private",
+            Propagation::Rprivate => "rprivate",
+            Propagation::Shared => "shared",
+            Propagation::Rshared => "rshared",
+            Propagation::Slave => "slave",
+            Propagation::Rslave => "rslave",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -155,6 +617,10 @@ impl Container {
             finished_at: None,
             exit_code: None,
             pid: None,
+            executor: executor::ExecutorKind::default(),
+            snapshotter: snapshotter::SnapshotterKind::default(),
+            restart_count: 0,
+            published_ports: Vec::new(),
         }
     }
 
@@ -168,6 +634,16 @@ impl Container {
         self.id.chars().take(12).collect()
     }
 
+    /// Hostname set in the container: the configured one, else the short ID. None on the
+    /// host network, where the container keeps the host's hostname like it keeps its /etc/hosts
+    pub fn hostname(&self) -> Option<String> {
+        match self.config.hostname {
+            Some(ref hostname) => Some(hostname.clone()),
+            None if self.config.network_mode == NetworkMode::Host => None,
+            None => Some(self.short_id()),
+        }
+    }
+
     // Set container name
     pub fn with_name(mut self, name: String) -> Self {
         self.name = Some(name);
@@ -210,11 +686,248 @@ impl Container {
         self
     }
 
+    // Set memory plus swap limit
+    pub fn with_memory_swap(mut self, limit: i64) -> Self {
+        self.config.memory_swap = Some(limit);
+        self
+    }
+
+    // Set memory reservation
+    pub fn with_memory_reservation(mut self, reservation: u64) -> Self {
+        self.config.memory_reservation = Some(reservation);
+        self
+    }
+
+    // Set OOM score adjustment
+    pub fn with_oom_score_adj(mut self, score: i32) -> Self {
+        self.config.oom_score_adj = Some(score);
+        self
+    }
+
+    // Set nice value
+    pub fn with_nice(mut self, nice: i32) -> Self {
+        self.config.nice = Some(nice);
+        self
+    }
+
+    // Set CPU scheduling policy, with its priority for real-time policies
+    pub fn with_sched_policy(mut self, policy: sched::SchedPolicy, rt_priority: Option<u32>) -> Self {
+        self.config.sched_policy = Some(policy);
+        self.config.rt_priority = rt_priority;
+        self
+    }
+
+    // Set real-time runtime
+    pub fn with_rt_runtime(mut self, runtime: u64) -> Self {
+        self.config.rt_runtime = Some(runtime);
+        self
+    }
+
+    // Set I/O priority
+    pub fn with_io_priority(mut self, priority: sched::IoPriority) -> Self {
+        self.config.io_priority = Some(priority);
+        self
+    }
+
+    // Set PIDs limit
+    pub fn with_pids_limit(mut self, limit: i64) -> Self {
+        self.config.pids_limit = Some(limit);
+        self
+    }
+
+    // Pin to CPUs
+    pub fn with_cpuset_cpus(mut self, cpus: String) -> Self {
+        self.config.cpuset_cpus = Some(cpus);
+        self
+    }
+
+    // Pin to NUMA memory nodes
+    pub fn with_cpuset_mems(mut self, mems: String) -> Self {
+        self.config.cpuset_mems = Some(mems);
+        self
+    }
+
+    // Set block I/O weight
+    pub fn with_blkio_weight(mut self, weight: u16) -> Self {
+        self.config.blkio_weight = Some(weight);
+        self
+    }
+
+    // Add device read rate limit
+    pub fn with_device_read_bps(mut self, device: ThrottleDevice) -> Self {
+        self.config.device_read_bps.push(device);
+        self
+    }
+
+    // Add device write rate limit
+    pub fn with_device_write_bps(mut self, device: ThrottleDevice) -> Self {
+        self.config.device_write_bps.push(device);
+        self
+    }
+
+    // Add ulimit
+    pub fn with_ulimit(mut self, ulimit: Ulimit) -> Self {
+        self.config.ulimits.push(ulimit);
+        self
+    }
+
+    // Add clock offset
+    pub fn with_time_offset(mut self, offset: TimeOffset) -> Self {
+        self.config.time_offsets.push(offset);
+        self
+    }
+
+    // Set sysctl
+    pub fn with_sysctl(mut self, key: String, value: String) -> Self {
+        self.config.sysctls.insert(key, value);
+        self
+    }
+
+    // Set network mode
+    pub fn with_network_mode(mut self, mode: NetworkMode) -> Self {
+        self.config.network_mode = mode;
+        self
+    }
+
+    // Set IPC namespace mode
+    pub fn with_ipc_mode(mut self, mode: IpcMode) -> Self {
+        self.config.ipc_mode = mode;
+        self
+    }
+
+    // Set user namespace mode
+    pub fn with_userns_mode(mut self, mode: UsernsMode) -> Self {
+        self.config.userns_mode = mode;
+        self
+    }
+
+    // Set stop signal
+    pub fn with_stop_signal(mut self, signal: String) -> Self {
+        self.config.stop_signal = Some(signal);
+        self
+    }
+
+    // Set log driver
+    pub fn with_log_driver(mut self, driver: LogDriverKind) -> Self {
+        self.config.log_driver = driver;
+        self
+    }
+
+    // Set log driver options
+    pub fn with_log_options(mut self, options: LogOptions) -> Self {
+        self.config.log_options = options;
+        self
+    }
+
+    // Join the network and IPC namespaces of a sandbox container
+    pub fn with_sandbox(mut self, sandbox_id: String) -> Self {
+        self.config.sandbox = Some(sandbox_id);
+        self
+    }
+
+    // Add a hostname peers on the same networks can reach this container by
+    pub fn with_alias(mut self, alias: String) -> Self {
+        self.config.aliases.push(alias);
+        self
+    }
+
+    // Set the health check
+    pub fn with_health_check(mut self, check: health::HealthCheck) -> Self {
+        self.config.health_check = Some(check);
+        self
+    }
+
+    // Set the rootfs storage quota
+    pub fn with_storage_size(mut self, size: u64) -> Self {
+        self.config.storage_size = Some(size);
+        self
+    }
+
+    // Set the hostname inside the container
+    pub fn with_hostname(mut self, hostname: String) -> Self {
+        self.config.hostname = Some(hostname);
+        self
+    }
+
+    // Set domain name
+    pub fn with_domainname(mut self, domainname: String) -> Self {
+        self.config.domainname = Some(domainname);
+        self
+    }
+
+    // Run the command as user[:group]
+    pub fn with_user(mut self, user: String) -> Self {
+        self.config.user = Some(user);
+        self
+    }
+
+    // Mount the rootfs read-only
+    pub fn with_read_only_rootfs(mut self, read_only: bool) -> Self {
+        self.config.read_only_rootfs = read_only;
+        self
+    }
+
+    pub fn with_entrypoint(mut self, entrypoint: String) -> Self {
+        self.config.entrypoint = Some(entrypoint);
+        self
+    }
+
+    // Set what happens when the container exits
+    pub fn with_restart_policy(mut self, policy: RestartPolicy) -> Self {
+        self.config.restart_policy = policy;
+        self
+    }
+
+    // Run under the embedded init
+    pub fn with_init(mut self, init: bool) -> Self {
+        self.config.init = init;
+        self
+    }
+
+    // Add DNS server
+    pub fn with_dns(mut self, server: String) -> Self {
+        self.config.dns.push(server);
+        self
+    }
+
+    // Add DNS search domain
+    pub fn with_dns_search(mut self, domain: String) -> Self {
+        self.config.dns_search.push(domain);
+        self
+    }
+
+    // Add /etc/hosts entry
+    pub fn with_extra_host(mut self, entry: HostEntry) -> Self {
+        self.config.extra_hosts.push(entry);
+        self
+    }
+
+    // Set static IP address
+    pub fn with_ip_address(mut self, ip: String) -> Self {
+        self.config.ip_address = Some(ip);
+        self
+    }
+
+    // Set static MAC address
+    pub fn with_mac_address(mut self, mac: String) -> Self {
+        self.config.mac_address = Some(mac);
+        self
+    }
+
     // Check if container is running
     pub fn is_running(&self) -> bool {
         matches!(self.status, ContainerStatus::Running)
     }
 
+    /// The host ports the container listens on while running, else the ones it asks for
+    pub fn ports(&self) -> &[PortMapping] {
+        if self.is_running() && !self.published_ports.is_empty() {
+            &self.published_ports
+        } else {
+            &self.config.ports
+        }
+    }
+
     pub fn is_stopped(&self) -> bool {
         matches!(self.status, ContainerStatus::Stopped | ContainerStatus::Error)
     }
@@ -258,10 +971,52 @@ impl Default for ContainerConfig {
             cpu_limit: None,
             user: None,
             hostname: None,
+            domainname: None,
             tty: false,
             stdin: false,
             network_mode: NetworkMode::Bridge,
             restart_policy: RestartPolicy::No,
+            memory_swap: None,
+            memory_reservation: None,
+            oom_score_adj: None,
+            nice: None,
+            sched_policy: None,
+            rt_priority: None,
+            rt_runtime: None,
+            io_priority: None,
+            pids_limit: None,
+            cpuset_cpus: None,
+            cpuset_mems: None,
+            blkio_weight: None,
+            device_read_bps: Vec::new(),
+            device_write_bps: Vec::new(),
+            ulimits: Vec::new(),
+            label_options: selinux::LabelOptions::default(),
+            apparmor_profile: None,
+            masked_paths: namespace::default_masked_paths(),
+            readonly_paths: namespace::default_readonly_paths(),
+            landlock: false,
+            time_offsets: Vec::new(),
+            sysctls: HashMap::new(),
+            ipc_mode: IpcMode::Private,
+            dns: Vec::new(),
+            dns_search: Vec::new(),
+            extra_hosts: Vec::new(),
+            ip_address: None,
+            mac_address: None,
+            networks: Vec::new(),
+            userns_mode: UsernsMode::default(),
+            stop_signal: None,
+            init: false,
+            log_driver: LogDriverKind::default(),
+            log_options: LogOptions::default(),
+            sandbox: None,
+            aliases: Vec::new(),
+            health_check: None,
+            storage_size: None,
+            read_only_rootfs: false,
+            entrypoint: None,
+            inherited_limits: Vec::new(),
         }
     }
 }
@@ -273,6 +1028,9 @@ impl VolumeMount {
             container_path,
             read_only,
             mount_type: MountType::Bind,
+            propagation: Propagation::default(),
+            tmpfs_options: TmpfsOptions::default(),
+            relabel: None,
         }
     }
 
@@ -282,6 +1040,9 @@ impl VolumeMount {
             container_path,
             read_only,
             mount_type: MountType::Volume,
+            propagation: Propagation::default(),
+            tmpfs_options: TmpfsOptions::default(),
+            relabel: None,
         }
     }
 
@@ -291,6 +1052,9 @@ impl VolumeMount {
             container_path,
             read_only: false,
             mount_type: MountType::Tmpfs,
+            propagation: Propagation::default(),
+            tmpfs_options: TmpfsOptions::default(),
+            relabel: None,
         }
     }
 }
@@ -318,6 +1082,35 @@ impl PortMapping {
         self.host_ip = Some(ip);
         self
     }
+
+    /// Whether the host port is picked when the container starts
+    pub fn is_ephemeral(&self) -> bool {
+        self.host_port == 0
+    }
+
+    /// Whether both can't be published at once: the same host port and protocol, on the same
+    /// address or on all addresses for either of them
+    pub fn conflicts_with(&self, other: &PortMapping) -> bool {
+        let all_addresses = |port: &PortMapping| port.host_ip.as_deref().is_none_or(|ip| ip == "0.0.0.0");
+        !self.is_ephemeral()
+            && self.host_port == other.host_port
+            && self.protocol == other.protocol
+            && (all_addresses(self) || all_addresses(other) || self.host_ip == other.host_ip)
+    }
+}
+
+/// `0.0.0.0:8080->80/tcp`, as ps and port show it
+impl std::fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}->{}/{}",
+            self.host_ip.as_deref().unwrap_or("0.0.0.0"),
+            self.host_port,
+            self.container_port,
+            self.protocol
+        )
+    }
 }
 
 impl std::fmt::Display for ContainerStatus {
@@ -342,6 +1135,61 @@ impl std::fmt::Display for Protocol {
     }
 }
 
+impl std::fmt::Display for LogDriverKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogDriverKind::JsonFile => write!(f, "json-file"),
+            LogDriverKind::Journald => write!(f, "journald"),
+            LogDriverKind::Cri => write!(f, "cri"),
+            LogDriverKind::None => write!(f, "none"),
+        }
+    }
+}
+
+impl std::fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestartPolicy::No => write!(f, "no"),
+            RestartPolicy::Always => write!(f, "always"),
+            RestartPolicy::UnlessStopped => write!(f, "unless-stopped"),
+            RestartPolicy::OnFailure { max_retries: 0 } => write!(f, "on-failure"),
+            RestartPolicy::OnFailure { max_retries } => write!(f, "on-failure:{}", max_retries),
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkMode::Bridge => write!(f, "bridge"),
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl std::str::FromStr for NetworkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "bridge" => Ok(NetworkMode::Bridge),
+            "host" => Ok(NetworkMode::Host),
+            "none" => Ok(NetworkMode::None),
+            name => {
+                let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+                if valid {
+                    Ok(NetworkMode::Custom(name.to_string()))
+                } else {
+                    Err(format!("invalid network name '{}'", name))
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -417,6 +1265,16 @@ mod tests {
         assert!(container.id.starts_with(&short_id));
     }
 
+    #[test]
+    fn test_container_hostname_defaults_to_short_id() {
+        let container = Container::new("test:latest".to_string(), vec![]);
+        assert_eq!(container.hostname(), Some(container.short_id()));
+        let container = container.with_network_mode(NetworkMode::Host);
+        assert_eq!(container.hostname(), None);
+        let container = container.with_hostname("web".to_string());
+        assert_eq!(container.hostname().as_deref(), Some("web"));
+    }
+
     #[test]
     fn test_container_is_running() {
         let mut container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
@@ -486,6 +1344,178 @@ mod tests {
         assert_eq!(container.config.cpu_limit, Some(2.5));
     }
 
+    #[test]
+    fn test_container_with_pids_limit() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_pids_limit(256);
+        assert_eq!(container.config.pids_limit, Some(256));
+    }
+
+    #[test]
+    fn test_container_config_without_pids_limit_deserializes() {
+        let mut value = serde_json::to_value(ContainerConfig::default()).unwrap();
+        value.as_object_mut().unwrap().remove("pids_limit");
+        let config: ContainerConfig = serde_json::from_value(value).unwrap();
+        assert!(config.pids_limit.is_none());
+        assert!(config.blkio_weight.is_none());
+        assert!(config.device_read_bps.is_empty());
+        assert!(config.device_write_bps.is_empty());
+        assert!(config.ulimits.is_empty());
+        assert!(config.sysctls.is_empty());
+        assert_eq!(config.ipc_mode, IpcMode::Private);
+        assert!(config.dns.is_empty());
+        assert!(config.dns_search.is_empty());
+        assert!(config.extra_hosts.is_empty());
+        assert!(config.ip_address.is_none());
+        assert!(config.mac_address.is_none());
+        assert!(config.networks.is_empty());
+        assert_eq!(config.userns_mode, UsernsMode::Private);
+        assert!(config.stop_signal.is_none());
+        assert!(!config.init);
+        assert_eq!(config.log_driver, LogDriverKind::JsonFile);
+        assert_eq!(config.log_options, LogOptions::default());
+    }
+
+    #[test]
+    fn test_container_with_dns_options() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_dns("1.1.1.1".to_string())
+            .with_dns_search("example.com".to_string())
+            .with_extra_host(HostEntry { hostname: "db".to_string(), ip: "10.0.0.5".to_string() });
+        assert_eq!(container.config.dns, vec!["1.1.1.1"]);
+        assert_eq!(container.config.dns_search, vec!["example.com"]);
+        assert_eq!(container.config.extra_hosts[0].hostname, "db");
+    }
+
+    #[test]
+    fn test_network_mode_from_str() {
+        assert_eq!("bridge".parse::<NetworkMode>().unwrap(), NetworkMode::Bridge);
+        assert_eq!("host".parse::<NetworkMode>().unwrap(), NetworkMode::Host);
+        assert_eq!("none".parse::<NetworkMode>().unwrap(), NetworkMode::None);
+        assert_eq!(
+            "my-net".parse::<NetworkMode>().unwrap(),
+            NetworkMode::Custom("my-net".to_string())
+        );
+        assert!("-bad".parse::<NetworkMode>().is_err());
+        assert!("".parse::<NetworkMode>().is_err());
+        assert!("a/b".parse::<NetworkMode>().is_err());
+    }
+
+    #[test]
+    fn test_network_mode_display_round_trip() {
+        for mode in ["bridge", "host", "none", "backend"] {
+            assert_eq!(mode.parse::<NetworkMode>().unwrap().to_string(), mode);
+        }
+    }
+
+    #[test]
+    fn test_ipc_mode_from_str() {
+        assert_eq!("private".parse::<IpcMode>().unwrap(), IpcMode::Private);
+        assert_eq!("host".parse::<IpcMode>().unwrap(), IpcMode::Host);
+        assert!("shareable".parse::<IpcMode>().is_err());
+    }
+
+    #[test]
+    fn test_propagation() {
+        assert_eq!("rshared".parse::<Propagation>().unwrap(), Propagation::Rshared);
+        assert_eq!("slave".parse::<Propagation>().unwrap(), Propagation::Slave);
+        assert!("unbindable".parse::<Propagation>().is_err());
+        assert_eq!(Propagation::Rslave.to_string(), "rslave");
+
+        let bind = |propagation| VolumeMount { propagation, ..VolumeMount::bind("/a".to_string(), "/a".to_string(), false) };
+        assert_eq!(Propagation::of_root(&[]), Propagation::Rprivate);
+        assert_eq!(Propagation::of_root(&[bind(Propagation::Slave), bind(Propagation::Private)]), Propagation::Rslave);
+        assert_eq!(Propagation::of_root(&[bind(Propagation::Slave), bind(Propagation::Shared)]), Propagation::Rshared);
+    }
+
+    #[test]
+    fn test_userns_mode_from_str() {
+        assert_eq!("private".parse::<UsernsMode>().unwrap(), UsernsMode::Private);
+        assert_eq!("host".parse::<UsernsMode>().unwrap(), UsernsMode::Host);
+        assert_eq!("keep-id".parse::<UsernsMode>().unwrap(), UsernsMode::KeepId);
+        assert!("auto".parse::<UsernsMode>().is_err());
+    }
+
+    #[test]
+    fn test_log_driver_kind_from_str() {
+        assert_eq!("json-file".parse::<LogDriverKind>().unwrap(), LogDriverKind::JsonFile);
+        assert_eq!("journald".parse::<LogDriverKind>().unwrap(), LogDriverKind::Journald);
+        assert_eq!("none".parse::<LogDriverKind>().unwrap(), LogDriverKind::None);
+        assert_eq!("cri".parse::<LogDriverKind>().unwrap(), LogDriverKind::Cri);
+        assert!("syslog".parse::<LogDriverKind>().is_err());
+        assert_eq!(LogDriverKind::JsonFile.to_string(), "json-file");
+        assert_eq!(LogDriverKind::Cri.to_string(), "cri");
+    }
+
+    #[test]
+    fn test_container_with_log_driver() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_log_driver(LogDriverKind::None);
+        assert_eq!(container.config.log_driver, LogDriverKind::None);
+    }
+
+    #[test]
+    fn test_container_with_log_options() {
+        let options = LogOptions { max_size: Some(1024), max_file: Some(3), ..Default::default() };
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_log_options(options.clone());
+        assert_eq!(container.config.log_options, options);
+    }
+
+    #[test]
+    fn test_container_with_userns_mode() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_userns_mode(UsernsMode::KeepId);
+        assert_eq!(container.config.userns_mode, UsernsMode::KeepId);
+    }
+
+    #[test]
+    fn test_container_with_stop_signal() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_stop_signal("SIGQUIT".to_string());
+        assert_eq!(container.config.stop_signal, Some("SIGQUIT".to_string()));
+    }
+
+    #[test]
+    fn test_container_with_init() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_init(true);
+        assert!(container.config.init);
+    }
+
+    #[test]
+    fn test_container_with_ipc_mode() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_ipc_mode(IpcMode::Host);
+        assert_eq!(container.config.ipc_mode, IpcMode::Host);
+    }
+
+    #[test]
+    fn test_container_with_sysctl() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_sysctl("net.ipv4.ip_forward".to_string(), "1".to_string());
+        assert_eq!(container.config.sysctls.get("net.ipv4.ip_forward"), Some(&"1".to_string()));
+    }
+
+    #[test]
+    fn test_container_with_ulimit() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_ulimit(Ulimit { name: "nofile".to_string(), soft: 1024, hard: 4096 });
+        assert_eq!(container.config.ulimits.len(), 1);
+        assert_eq!(container.config.ulimits[0].name, "nofile");
+    }
+
+    #[test]
+    fn test_container_with_blkio_options() {
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_blkio_weight(300)
+            .with_device_read_bps(ThrottleDevice { path: "/dev/sda".to_string(), rate: 1024 })
+            .with_device_write_bps(ThrottleDevice { path: "/dev/sdb".to_string(), rate: 2048 });
+        assert_eq!(container.config.blkio_weight, Some(300));
+        assert_eq!(container.config.device_read_bps[0].path, "/dev/sda");
+        assert_eq!(container.config.device_write_bps[0].rate, 2048);
+    }
+
     #[test]
     fn test_container_with_volume() {
         let volume = VolumeMount::bind("/host".to_string(), "/container".to_string(), false);
@@ -560,6 +1590,7 @@ mod tests {
         assert!(!config.stdin);
         assert!(matches!(config.network_mode, NetworkMode::Bridge));
         assert!(matches!(config.restart_policy, RestartPolicy::No));
+        assert!(config.pids_limit.is_none());
     }
 
     #[test]
@@ -607,6 +1638,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_restart_policy_from_str() {
+        assert_eq!("no".parse::<RestartPolicy>(), Ok(RestartPolicy::No));
+        assert_eq!("unless-stopped".parse::<RestartPolicy>(), Ok(RestartPolicy::UnlessStopped));
+        assert_eq!("on-failure".parse::<RestartPolicy>(), Ok(RestartPolicy::OnFailure { max_retries: 0 }));
+        assert_eq!("on-failure:3".parse::<RestartPolicy>(), Ok(RestartPolicy::OnFailure { max_retries: 3 }));
+        assert!("on-failure:x".parse::<RestartPolicy>().is_err());
+        assert!("always:2".parse::<RestartPolicy>().is_err());
+        assert!("sometimes".parse::<RestartPolicy>().is_err());
+
+        for policy in ["no", "always", "unless-stopped", "on-failure", "on-failure:5"] {
+            assert_eq!(policy.parse::<RestartPolicy>().unwrap().to_string(), policy);
+        }
+    }
+
+    #[test]
+    fn test_restart_policy_should_restart() {
+        assert!(!RestartPolicy::No.should_restart(Some(1), 0));
+        assert!(RestartPolicy::Always.should_restart(Some(0), 10));
+        assert!(RestartPolicy::UnlessStopped.should_restart(None, 0));
+
+        let policy = RestartPolicy::OnFailure { max_retries: 2 };
+        assert!(!policy.should_restart(Some(0), 0));
+        assert!(policy.should_restart(Some(1), 1));
+        assert!(policy.should_restart(None, 0));
+        assert!(!policy.should_restart(Some(1), 2));
+        assert!(RestartPolicy::OnFailure { max_retries: 0 }.should_restart(Some(137), 100));
+    }
+
     #[test]
     fn test_container_with_multiple_env_vars() {
         let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
@@ -1,12 +1,12 @@
 use std::ffi::CString;
-use nix::sched::{unshare, CloneFlags};
+use nix::sched::{setns, unshare, CloneFlags};
 use nix::unistd::{chdir, getegid, geteuid};
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use std::fs;
 use std::io::ErrorKind;
 use std::os::unix::fs::DirBuilderExt;
-use std::path::Path;
-use crate::container::{NetworkMode};
+use std::path::{Path, PathBuf};
+use crate::container::{idmap, selinux, IpcMode, NetworkMode, Propagation, TimeOffset, UsernsMode};
 use crate::error::{CuboError, Result};
 
 
@@ -17,20 +17,26 @@ pub struct UnshareInfo {
     pub pid: bool,
     pub uts: bool, 
     pub net: bool,
+    pub ipc: bool,
+    pub cgroup: bool,
 }
 
-/// Unshare into a new user namespace, then map container root (0) to current host uid/gid.
-/// Writes /proc/self/setgroups (deny) before gid_map as required by the kernel.
-pub fn unshare_user_then_map_ids() -> Result<()> {
+/// Unshare into a new user namespace, then map container root (0) to current host uid/gid,
+/// or the uid/gid to themselves with keep-id. Writes /proc/self/setgroups (deny) before
+/// gid_map as required by the kernel.
+pub fn unshare_user_then_map_ids(mode: &UsernsMode) -> Result<()> {
     let uid = geteuid().as_raw();
     let gid = getegid().as_raw();
 
+    if *mode == UsernsMode::Host {
+        tracing::debug!("Using the host user namespace");
+        return Ok(());
+    }
     if uid == 0 {
         tracing::debug!("Running as root (uid=0), skipping user namespace creation");
         return Ok(());
     }
-    unshare(CloneFlags::CLONE_NEWUSER)
-        .map_err(|e| CuboError::NamespaceError(format!("Failed to clone user: {}", e)))?;
+    unshare_user()?;
 
     match fs::write("/proc/self/setgroups", b"deny") {
         Ok(_) => {}
@@ -43,70 +49,189 @@ pub fn unshare_user_then_map_ids() -> Result<()> {
         }
     }
 
-    fs::write("/proc/self/uid_map", format!("0 {} 1\n", uid))
+    fs::write("/proc/self/uid_map", idmap::format_map(&idmap::ranges_for(mode, uid, &[])))
         .map_err(|e| CuboError::NamespaceError(format!("Failed to write uid_map: {}", e)))?;
 
-    fs::write("/proc/self/gid_map", format!("0 {} 1\n", gid))
+    fs::write("/proc/self/gid_map", idmap::format_map(&idmap::ranges_for(mode, gid, &[])))
         .map_err(|e| CuboError::NamespaceError(format!("Failed to write gid_map: {}", e)))?;
 
     Ok(())
 }
 
+/// Unshare into a new user namespace and leave writing the id maps to the parent process.
+pub fn unshare_user() -> Result<()> {
+    unshare(CloneFlags::CLONE_NEWUSER)
+        .map_err(|e| CuboError::NamespaceError(format!("Failed to clone user: {}", e)))
+}
 
-/// unshare mount, pid, uts, and optionally net namespaces depeding on the networkmode (host)
-pub fn unshare_mount_pid_net(mode: &NetworkMode) -> Result<UnshareInfo> {
-    let mut flags = CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWUTS;
+/// unshare mount, pid, uts and cgroup namespaces, plus net and ipc unless the host ones are requested.
+/// The caller must already have joined its cgroup so it becomes the root of the new cgroup namespace.
+pub fn unshare_mount_pid_net(mode: &NetworkMode, ipc_mode: &IpcMode) -> Result<UnshareInfo> {
+    let mut flags = CloneFlags::CLONE_NEWNS
+        | CloneFlags::CLONE_NEWPID
+        | CloneFlags::CLONE_NEWUTS
+        | CloneFlags::CLONE_NEWCGROUP;
     let mut net = false;
+    let mut ipc = false;
 
     if !matches!(mode, NetworkMode::Host) {
         flags |= CloneFlags::CLONE_NEWNET;
         net = true;
     }
 
+    if matches!(ipc_mode, IpcMode::Private) {
+        flags |= CloneFlags::CLONE_NEWIPC;
+        ipc = true;
+    }
+
     unshare(flags)
-        .map_err(|e| CuboError::NamespaceError(format!("unshare(mnt, pid, uts, net) failed: {}", e)))?;
+        .map_err(|e| CuboError::NamespaceError(format!("unshare(mnt, pid, uts, cgroup, net, ipc) failed: {}", e)))?;
 
-    Ok(UnshareInfo {user:true, mnt: true, pid: true, uts: true, net})
+    Ok(UnshareInfo {user:true, mnt: true, pid: true, uts: true, net, ipc, cgroup: true})
+}
+
+/// Like `unshare_mount_pid_net`, but join the net and ipc namespaces of the sandbox process
+/// `sandbox_pid` instead of creating new ones, so pod members share its network and IPC.
+pub fn join_sandbox(sandbox_pid: u32) -> Result<UnshareInfo> {
+    // Open the namespaces first so a vanished sandbox fails before anything changes
+    let mut namespaces = Vec::new();
+    for (name, flag) in [("net", CloneFlags::CLONE_NEWNET), ("ipc", CloneFlags::CLONE_NEWIPC)] {
+        let path = format!("/proc/{}/ns/{}", sandbox_pid, name);
+        let file = fs::File::open(&path)
+            .map_err(|e| CuboError::NamespaceError(format!("Failed to open {}: {}", path, e)))?;
+        namespaces.push((name, flag, file));
+    }
+
+    unshare(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWUTS | CloneFlags::CLONE_NEWCGROUP)
+        .map_err(|e| CuboError::NamespaceError(format!("unshare(mnt, pid, uts, cgroup) failed: {}", e)))?;
+
+    for (name, flag, file) in namespaces {
+        setns(&file, flag)
+            .map_err(|e| CuboError::NamespaceError(format!("Failed to join {} namespace of {}: {}", name, sandbox_pid, e)))?;
+    }
+
+    Ok(UnshareInfo { user: false, mnt: true, pid: true, uts: true, net: false, ipc: false, cgroup: true })
+}
+
+/// Clocks a time namespace can shift
+pub const TIME_CLOCKS: [&str; 2] = ["monotonic", "boottime"];
+
+/// Parse "monotonic=86400" or "boottime=-2h": a clock and the seconds to shift it by, or a
+/// count of s, m, h or d
+pub fn parse_time_offset(spec: &str) -> Result<TimeOffset> {
+    let invalid = || {
+        CuboError::InvalidConfiguration(format!(
+            "Invalid time offset '{}', expected <monotonic|boottime>=<offset>[s|m|h|d]",
+            spec
+        ))
+    };
+    let (clock, offset) = spec.split_once('=').ok_or_else(invalid)?;
+    if !TIME_CLOCKS.contains(&clock) {
+        return Err(CuboError::InvalidConfiguration(format!("Unknown clock for a time offset: {}", clock)));
+    }
+
+    let number_end = offset.rfind(|c: char| c.is_ascii_digit()).map_or(0, |i| i + 1);
+    let (number, unit) = offset.split_at(number_end);
+    let unit_seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let secs = number
+        .parse::<i64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit_seconds))
+        .ok_or_else(invalid)?;
+
+    Ok(TimeOffset { clock: clock.to_string(), secs })
+}
+
+/// Content of /proc/self/timens_offsets: "<clock> <secs> <nanosecs>" per clock
+pub fn format_time_offsets(offsets: &[TimeOffset]) -> String {
+    offsets.iter().map(|o| format!("{} {} 0\n", o.clock, o.secs)).collect()
+}
+
+/// Unshare a time namespace whose clocks are shifted by `offsets`. Only the children of the
+/// caller enter it, and its offsets are fixed once the first one does, so this has to run
+/// right before the container init is forked.
+pub fn unshare_time(offsets: &[TimeOffset]) -> Result<()> {
+    unshare(CloneFlags::from_bits_retain(libc::CLONE_NEWTIME))
+        .map_err(|e| CuboError::NamespaceError(format!("unshare(time) failed: {}", e)))?;
+    fs::write("/proc/self/timens_offsets", format_time_offsets(offsets))
+        .map_err(|e| CuboError::NamespaceError(format!("Failed to write timens_offsets: {}", e)))
+}
+
+/// Set the domain name of the UTS namespace, which nix has no wrapper for
+pub fn set_domainname(domainname: &str) -> Result<()> {
+    let rc = unsafe { libc::setdomainname(domainname.as_ptr().cast(), domainname.len()) };
+    if rc != 0 {
+        return Err(CuboError::SystemError(format!(
+            "Failed to set domainname: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
 }
 
 /// Remount the root with privcate propagation to avoid mount leaks back to host.
 pub fn make_mounts_private() -> Result<()> {
+    set_root_propagation(Propagation::Rprivate)
+}
+
+/// Give every mount of the namespace `propagation`. Only what the volumes ask for is
+/// shared with the host; the rest stays private.
+pub fn set_root_propagation(propagation: Propagation) -> Result<()> {
     mount::<str, std::path::Path, str, str>(
         None,
         Path::new("/"),
         None,
-        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        propagation_flags(propagation),
         None::<&str>
     )
-    .map_err(|e| CuboError::NamespaceError(format!("Failed to make mounts private: {}", e)))?;
+    .map_err(|e| CuboError::NamespaceError(format!("Failed to make mounts {}: {}", propagation, e)))?;
 
     Ok(())
 }
 
-
-/// Bind-mount a host path onto the target. Optionally remount read-only.
-pub fn bind_mount(host: &Path, target: &Path, read_only:bool) -> Result<()> {
-    if let Some(parent) = target.parent() {
-        fs::DirBuilder::new()
-            .recursive(true)
-            .mode(0o755)
-            .create(parent)
-            .map_err(|e| CuboError::VolumeError(format!(
-                "Failed to create mount target parent {:?}: {}", 
-                parent, e
-            )))?;
+/// The mount(2) flags that change a mount's propagation
+pub fn propagation_flags(propagation: Propagation) -> MsFlags {
+    match propagation {
+        Propagation::Private => MsFlags::MS_PRIVATE,
+        Propagation::Rprivate => MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        Propagation::Shared => MsFlags::MS_SHARED,
+        Propagation::Rshared => MsFlags::MS_REC | MsFlags::MS_SHARED,
+        Propagation::Slave => MsFlags::MS_SLAVE,
+        Propagation::Rslave => MsFlags::MS_REC | MsFlags::MS_SLAVE,
     }
+}
 
-    if host.is_dir() {
-        if !target.exists() {
-            fs::create_dir_all(target)
-                .map_err(|e| CuboError::VolumeError(format!("Failed to create dir {:?}: {}", target, e)))?;
-        }
-    } else if host.is_file() && !target.exists() {
-        // Create an empty file as the mount point
-        fs::File::create(target)
-            .map_err(|e| CuboError::VolumeError(format!("Failed to create file {:?}: {}", target, e)))?;
-    }
+/// Make the mount holding `path` private, as pivot_root(2) refuses a new root whose parent
+/// mount is shared
+pub fn make_parent_mount_private(path: &Path) -> Result<()> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let parent = mount_point_of(&mountinfo, path)
+        .ok_or_else(|| CuboError::NamespaceError(format!("No mount holds {}", path.display())))?;
+    mount::<str, Path, str, str>(None, &parent, None, MsFlags::MS_PRIVATE, None::<&str>)
+        .map_err(|e| CuboError::NamespaceError(format!("Failed to make {} private: {}", parent.display(), e)))
+}
+
+/// The deepest mount point in `mountinfo` that `path` is under
+fn mount_point_of(mountinfo: &str, path: &Path) -> Option<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(PathBuf::from)
+        .filter(|point| path.starts_with(point))
+        .max_by_key(|point| point.components().count())
+}
+
+
+/// Bind-mount a host path onto the target with the given propagation. Optionally remount
+/// read-only.
+pub fn bind_mount(host: &Path, target: &Path, read_only:bool, propagation: Propagation) -> Result<()> {
+    create_mount_point(host, target)?;
 
     mount::<std::path::Path, std::path::Path, str, str>(
         Some(host),
@@ -134,10 +259,45 @@ pub fn bind_mount(host: &Path, target: &Path, read_only:bool) -> Result<()> {
         )))?;
     }
 
+    if propagation != Propagation::default() {
+        mount::<str, std::path::Path, str, str>(None, target, None, propagation_flags(propagation), None::<&str>)
+            .map_err(|e| CuboError::VolumeError(format!(
+                "Failed to make {:?} {}: {}",
+                target, propagation, e
+            )))?;
+    }
+
 
     Ok(())
 }
 
+/// Create what `host` gets mounted on at `target`: a directory for a directory, else an
+/// empty file, with the missing parents
+pub fn create_mount_point(host: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o755)
+            .create(parent)
+            .map_err(|e| CuboError::VolumeError(format!(
+                "Failed to create mount target parent {:?}: {}", 
+                parent, e
+            )))?;
+    }
+
+    if host.is_dir() {
+        if !target.exists() {
+            fs::create_dir_all(target)
+                .map_err(|e| CuboError::VolumeError(format!("Failed to create dir {:?}: {}", target, e)))?;
+        }
+    } else if host.exists() && !target.exists() {
+        // Create an empty file as the mount point, also for sockets and device nodes
+        fs::File::create(target)
+            .map_err(|e| CuboError::VolumeError(format!("Failed to create file {:?}: {}", target, e)))?;
+    }
+    Ok(())
+}
+
 pub fn pivot_to_rootfs(rootfs: &Path) -> Result<()> {
     mount::<std::path::Path, std::path::Path, str, str>(
         Some(rootfs),
@@ -196,6 +356,222 @@ pub fn mount_proc() -> Result<()> {
     Ok(())
 }
 
+/// Make the container's root read-only. Volumes, tmpfs and the default mounts on top of
+/// it keep their own mode.
+pub fn remount_root_readonly() -> Result<()> {
+    remount_readonly("/")
+}
+
+/// Remount the mount at `path` read-only
+fn remount_readonly(path: &str) -> Result<()> {
+    use nix::sys::statvfs::{statvfs, FsFlags};
+
+    // In a user namespace a remount has to keep the flags locked by the outer mount
+    let current = statvfs(path)
+        .map_err(|e| CuboError::NamespaceError(format!("statvfs({}) failed: {}", path, e)))?
+        .flags();
+    let mut flags = MsFlags::MS_REMOUNT | MsFlags::MS_BIND | MsFlags::MS_RDONLY;
+    for (locked, flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if current.contains(locked) {
+            flags |= flag;
+        }
+    }
+    mount::<str, str, str, str>(None, path, None, flags, None)
+        .map_err(|e| CuboError::NamespaceError(format!("Remounting {} read-only failed: {}", path, e)))
+}
+
+/// Paths of /proc and /sys masked by default, following Docker: they leak host details
+/// or let a container poke at the host's hardware
+pub const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/asound",
+    "/proc/acpi",
+    "/proc/interrupts",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+    "/sys/devices/virtual/powercap",
+];
+
+/// Paths of /proc read-only by default: writing them changes the host's kernel
+pub const DEFAULT_READONLY_PATHS: &[&str] = &["/proc/bus", "/proc/fs", "/proc/irq", "/proc/sys", "/proc/sysrq-trigger"];
+
+pub fn default_masked_paths() -> Vec<String> {
+    DEFAULT_MASKED_PATHS.iter().map(|p| p.to_string()).collect()
+}
+
+pub fn default_readonly_paths() -> Vec<String> {
+    DEFAULT_READONLY_PATHS.iter().map(|p| p.to_string()).collect()
+}
+
+/// The `--security-opt`s turning the default masked and read-only paths into `masked`
+/// and `readonly`: unmask=... for the defaults left out, mask=... for the paths added
+pub fn path_security_opts(masked: &[String], readonly: &[String]) -> Vec<String> {
+    let kept = |p: &&str| masked.iter().chain(readonly).any(|m| m == p);
+    let unmasked: Vec<&str> = DEFAULT_MASKED_PATHS.iter().chain(DEFAULT_READONLY_PATHS).copied().filter(|p| !kept(p)).collect();
+    let added: Vec<&str> = masked.iter().map(String::as_str).filter(|p| !DEFAULT_MASKED_PATHS.contains(p)).collect();
+
+    let mut opts = Vec::new();
+    if unmasked.len() == DEFAULT_MASKED_PATHS.len() + DEFAULT_READONLY_PATHS.len() {
+        opts.push("unmask=ALL".to_string());
+    } else if !unmasked.is_empty() {
+        opts.push(format!("unmask={}", unmasked.join(":")));
+    }
+    if !added.is_empty() {
+        opts.push(format!("mask={}", added.join(":")));
+    }
+    opts
+}
+
+/// Hide `paths` from the container: `devnull` is bound over files and an empty read-only
+/// tmpfs mounted over directories. Paths the container doesn't have are skipped.
+pub fn mask_paths(paths: &[String], devnull: &Path, mount_label: Option<&str>) -> Result<()> {
+    for path in paths {
+        let result = match fs::metadata(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(CuboError::NamespaceError(format!("Failed to stat {}: {}", path, e))),
+            Ok(meta) if meta.is_dir() => mount::<str, str, str, str>(
+                Some("tmpfs"),
+                path,
+                Some("tmpfs"),
+                MsFlags::MS_RDONLY,
+                selinux::with_context(None, mount_label).as_deref(),
+            ),
+            Ok(_) => mount::<Path, str, str, str>(Some(devnull), path, None, MsFlags::MS_BIND, None),
+        };
+        result.map_err(|e| CuboError::NamespaceError(format!("Failed to mask {}: {}", path, e)))?;
+    }
+    Ok(())
+}
+
+/// Bind `paths` onto themselves read-only. Paths the container doesn't have are skipped.
+pub fn make_paths_readonly(paths: &[String]) -> Result<()> {
+    for path in paths {
+        match mount::<str, str, str, str>(Some(path), path, None, MsFlags::MS_BIND | MsFlags::MS_REC, None) {
+            Err(nix::errno::Errno::ENOENT) => continue,
+            result => result.map_err(|e| CuboError::NamespaceError(format!("Failed to bind {}: {}", path, e)))?,
+        }
+        remount_readonly(path)?;
+    }
+    Ok(())
+}
+
+/// A filesystem mounted into every container after pivot_root.
+#[derive(Debug, Clone)]
+pub struct DefaultMount {
+    pub source: &'static str,
+    pub destination: &'static str,
+    pub fstype: &'static str,
+    pub flags: MsFlags,
+    pub data: Option<&'static str>,
+    /// Failure to mount is logged instead of aborting container setup.
+    pub optional: bool,
+}
+
+/// Standard runtime mounts, following the OCI runtime-spec defaults.
+/// /proc is handled separately by `mount_proc`.
+pub fn default_mounts() -> Vec<DefaultMount> {
+    let common = MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV;
+    vec![
+        DefaultMount {
+            source: "sysfs",
+            destination: "/sys",
+            fstype: "sysfs",
+            flags: common | MsFlags::MS_RDONLY,
+            data: None,
+            // sysfs can't be mounted by an unprivileged user without a private net namespace
+            optional: true,
+        },
+        DefaultMount {
+            source: "tmpfs",
+            destination: "/dev/shm",
+            fstype: "tmpfs",
+            flags: common,
+            data: Some("mode=1777,size=65536k"),
+            optional: false,
+        },
+        DefaultMount {
+            source: "devpts",
+            destination: "/dev/pts",
+            fstype: "devpts",
+            flags: MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            data: Some("newinstance,ptmxmode=0666,mode=0620,gid=5"),
+            optional: false,
+        },
+        DefaultMount {
+            source: "mqueue",
+            destination: "/dev/mqueue",
+            fstype: "mqueue",
+            flags: common,
+            data: None,
+            optional: true,
+        },
+    ]
+}
+
+/// Mount /sys (read-only), /dev/shm, /dev/pts and /dev/mqueue inside the current root
+pub fn mount_default_filesystems() -> Result<()> {
+    for m in default_mounts() {
+        match mount_default(&m) {
+            Ok(()) => {}
+            Err(e) if m.optional => {
+                tracing::warn!("Skipping {} mount: {}", m.destination, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Point /dev/ptmx at the new devpts instance
+    let ptmx = Path::new("/dev/ptmx");
+    if Path::new("/dev/pts/ptmx").exists() {
+        let _ = fs::remove_file(ptmx);
+        std::os::unix::fs::symlink("pts/ptmx", ptmx)
+            .map_err(|e| CuboError::NamespaceError(format!("Failed to link /dev/ptmx: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+fn mount_default(m: &DefaultMount) -> Result<()> {
+    fs::create_dir_all(m.destination)
+        .map_err(|e| CuboError::NamespaceError(format!("mkdir {} failed: {}", m.destination, e)))?;
+
+    let result = mount::<str, str, str, str>(Some(m.source), m.destination, Some(m.fstype), m.flags, m.data);
+
+    // gid=5 (tty) is not mapped in a rootless user namespace; retry without it
+    let result = match (result, m.data) {
+        (Err(nix::errno::Errno::EINVAL), Some(data)) if data.contains("gid=") => {
+            let data = strip_mount_option(data, "gid");
+            mount::<str, str, str, str>(Some(m.source), m.destination, Some(m.fstype), m.flags, Some(data.as_str()))
+        }
+        (result, _) => result,
+    };
+
+    result.map_err(|e| CuboError::NamespaceError(format!(
+        "Mount {} on {} failed: {}",
+        m.fstype, m.destination, e
+    )))
+}
+
+/// Remove `key=...` from a comma separated mount option string
+fn strip_mount_option(data: &str, key: &str) -> String {
+    data.split(',')
+        .filter(|opt| opt.split('=').next() != Some(key))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 pub fn setup_loopback() -> Result<()> {
     let try_ip = std::process::Command::new("ip")
         .args(["link", "set", "lo", "up"])
@@ -227,12 +603,16 @@ mod tests {
             pid: true,
             uts: true,
             net: false,
+            ipc: true,
+            cgroup: true,
         };
         assert!(info.user);
         assert!(info.mnt);
         assert!(info.pid);
         assert!(info.uts);
         assert!(!info.net);
+        assert!(info.ipc);
+        assert!(info.cgroup);
     }
 
     #[test]
@@ -243,6 +623,8 @@ mod tests {
             pid: true,
             uts: false,
             net: true,
+            ipc: false,
+            cgroup: false,
         };
         let debug_str = format!("{:?}", info);
         assert!(debug_str.contains("UnshareInfo"));
@@ -257,7 +639,9 @@ mod tests {
             mnt: true,
             pid: false,
             uts: true,
-            net: false
+            net: false,
+            ipc: true,
+            cgroup: true,
         };
         let cloned = info;
         assert_eq!(cloned.user, info.user);
@@ -273,7 +657,7 @@ mod tests {
         let host_dir = temp.path().join("host_dir");
         let target = temp.path().join("deep/nested/target");
         fs::create_dir_all(&host_dir).unwrap();
-        let _result = bind_mount(&host_dir, &target, false);
+        let _result = bind_mount(&host_dir, &target, false, Propagation::default());
         // Parent dirs should be created regardless of mount success/failure
         assert!(target.parent().unwrap().exists());
     }
@@ -284,7 +668,7 @@ mod tests {
         let host_dir = temp.path().join("host_dir");
         let target = temp.path().join("target_dir");
         fs::create_dir_all(&host_dir).unwrap();
-        let _result = bind_mount(&host_dir, &target, false);
+        let _result = bind_mount(&host_dir, &target, false, Propagation::default());
         assert!(target.exists());
         assert!(target.is_dir());
     }
@@ -295,34 +679,80 @@ mod tests {
         let host_file = temp.path().join("host_file");
         let target = temp.path().join("target_file");
         fs::write(&host_file, "content").unwrap();
-        let _result = bind_mount(&host_file, &target, false);
+        let _result = bind_mount(&host_file, &target, false, Propagation::default());
         assert!(target.exists());
         assert!(target.is_file());
     }
 
+    #[test]
+    fn test_bind_mount_creates_file_target_for_socket_host() {
+        let temp = TempDir::new().unwrap();
+        let host_socket = temp.path().join("host.sock");
+        let target = temp.path().join("target_sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&host_socket).unwrap();
+        if bind_mount(&host_socket, &target, false, Propagation::default()).is_ok() {
+            nix::mount::umount(&target).unwrap();
+        }
+        assert!(target.is_file());
+    }
+
     #[test]
     #[ignore]
     fn test_unshare_user_then_map_ids_as_non_root() {
-        let result = unshare_user_then_map_ids();
+        let result = unshare_user_then_map_ids(&UsernsMode::Private);
         println!("unshare_user result: {:?}", result);
     }
 
     #[test]
     #[ignore]
     fn test_unshare_mount_pid_net_bridge_mode() {
-        let result = unshare_mount_pid_net(&NetworkMode::Bridge);
+        let result = unshare_mount_pid_net(&NetworkMode::Bridge, &IpcMode::Private);
         assert!(result.is_ok());
         let info = result.unwrap();
         assert!(info.mnt);
         assert!(info.pid);
         assert!(info.uts);
         assert!(info.net);
+        assert!(info.ipc);
+        assert!(info.cgroup);
+    }
+
+    #[test]
+    fn test_parse_time_offset() {
+        assert_eq!(
+            parse_time_offset("monotonic=86400").unwrap(),
+            TimeOffset { clock: "monotonic".to_string(), secs: 86400 }
+        );
+        assert_eq!(parse_time_offset("boottime=-2h").unwrap().secs, -7200);
+        assert_eq!(parse_time_offset("boottime=30s").unwrap().secs, 30);
+        assert_eq!(parse_time_offset("monotonic=7d").unwrap().secs, 7 * 24 * 60 * 60);
+
+        assert!(parse_time_offset("monotonic").is_err());
+        assert!(parse_time_offset("realtime=10").is_err());
+        assert!(parse_time_offset("monotonic=abc").is_err());
+        assert!(parse_time_offset("monotonic=10y").is_err());
+        assert!(parse_time_offset("monotonic=").is_err());
+    }
+
+    #[test]
+    fn test_format_time_offsets() {
+        let offsets = [
+            TimeOffset { clock: "monotonic".to_string(), secs: 3600 },
+            TimeOffset { clock: "boottime".to_string(), secs: -60 },
+        ];
+        assert_eq!(format_time_offsets(&offsets), "monotonic 3600 0\nboottime -60 0\n");
+    }
+
+    #[test]
+    fn test_join_sandbox_missing_process() {
+        let err = join_sandbox(u32::MAX).unwrap_err();
+        assert!(matches!(err, CuboError::NamespaceError(_)));
     }
 
     #[test]
     #[ignore]
     fn test_unshare_mount_pid_net_host_mode() {
-        let result = unshare_mount_pid_net(&NetworkMode::Host);
+        let result = unshare_mount_pid_net(&NetworkMode::Host, &IpcMode::Host);
         assert!(result.is_ok());
         let info = result.unwrap();
         assert!(info.mnt);
@@ -330,6 +760,19 @@ mod tests {
         assert!(info.uts);
         // Host mode doesn't create net namespace
         assert!(!info.net);
+        assert!(!info.ipc);
+    }
+
+    #[test]
+    fn test_mount_point_of() {
+        let mountinfo = "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+                         40 22 0:35 / /var/lib rw shared:20 - xfs /dev/sdb1 rw\n\
+                         41 40 0:36 / /var/lib/cubo/abc/rootfs rw - overlay overlay rw\n";
+        let point = |path: &str| mount_point_of(mountinfo, Path::new(path));
+        assert_eq!(point("/var/lib/cubo/def/rootfs"), Some(PathBuf::from("/var/lib")));
+        assert_eq!(point("/var/lib/cubo/abc/rootfs"), Some(PathBuf::from("/var/lib/cubo/abc/rootfs")));
+        assert_eq!(point("/var/library"), Some(PathBuf::from("/")));
+        assert_eq!(propagation_flags(Propagation::Rshared), MsFlags::MS_REC | MsFlags::MS_SHARED);
     }
 
     #[test]
@@ -356,6 +799,61 @@ mod tests {
         println!("mount_proc result: {:?}", result);
     }
 
+    #[test]
+    fn test_default_mounts_sysfs_read_only() {
+        let mounts = default_mounts();
+        let sys = mounts.iter().find(|m| m.destination == "/sys").unwrap();
+        assert_eq!(sys.fstype, "sysfs");
+        assert!(sys.flags.contains(MsFlags::MS_RDONLY));
+        assert!(sys.flags.contains(MsFlags::MS_NOSUID));
+    }
+
+    #[test]
+    fn test_default_mounts_devpts_options() {
+        let mounts = default_mounts();
+        let pts = mounts.iter().find(|m| m.destination == "/dev/pts").unwrap();
+        let data = pts.data.unwrap();
+        assert!(data.contains("newinstance"));
+        assert!(data.contains("ptmxmode=0666"));
+        assert!(data.contains("gid=5"));
+        assert!(!pts.optional);
+    }
+
+    #[test]
+    fn test_default_mounts_include_mqueue_and_shm() {
+        let dests: Vec<_> = default_mounts().iter().map(|m| m.destination).collect();
+        assert!(dests.contains(&"/dev/mqueue"));
+        assert!(dests.contains(&"/dev/shm"));
+    }
+
+    #[test]
+    fn test_strip_mount_option() {
+        assert_eq!(
+            strip_mount_option("newinstance,ptmxmode=0666,mode=0620,gid=5", "gid"),
+            "newinstance,ptmxmode=0666,mode=0620"
+        );
+        assert_eq!(strip_mount_option("mode=0620", "gid"), "mode=0620");
+    }
+
+    #[test]
+    fn test_path_security_opts() {
+        let masked = default_masked_paths();
+        let readonly = default_readonly_paths();
+        assert!(path_security_opts(&masked, &readonly).is_empty());
+        assert_eq!(path_security_opts(&[], &[]), ["unmask=ALL"]);
+
+        let masked: Vec<String> = masked.into_iter().filter(|p| p != "/proc/kcore").chain(["/proc/cpuinfo".to_string()]).collect();
+        let readonly: Vec<String> = readonly.into_iter().filter(|p| p != "/proc/sys").collect();
+        assert_eq!(path_security_opts(&masked, &readonly), ["unmask=/proc/kcore:/proc/sys", "mask=/proc/cpuinfo"]);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_mount_default_filesystems() {
+        let result = mount_default_filesystems();
+        println!("mount_default_filesystems result: {:?}", result);
+    }
+
     #[test]
     fn test_setup_loopback_best_effort() {
         let result = setup_loopback();
@@ -0,0 +1,919 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::container::container_store::{atomic_write_json, read_json};
+use crate::container::{Container, ContainerStatus, PortMapping, Protocol};
+use crate::error::{CuboError, Result};
+
+/// Names that select a built-in network mode and can't be used for user networks
+const RESERVED_NAMES: &[&str] = &["bridge", "host", "none"];
+
+/// Pool used to pick a subnet when none is given: 10.89.<n>.0/24
+pub const DEFAULT_POOL: Subnet = Subnet { network: Ipv4Addr::new(10, 89, 0, 0), prefix: 16 };
+
+/// IPv4 subnet in CIDR notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    pub network: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Subnet {
+    pub fn parse(cidr: &str) -> Result<Self> {
+        let invalid = || CuboError::NetworkError(format!("Invalid subnet '{}', expected CIDR like 10.89.0.0/24", cidr));
+
+        let (addr, prefix) = cidr.split_once('/').ok_or_else(invalid)?;
+        let addr: Ipv4Addr = addr.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+
+        // Need room for the network, gateway, broadcast and at least one container
+        if !(8..=30).contains(&prefix) {
+            return Err(CuboError::NetworkError(format!(
+                "Subnet prefix must be between /8 and /30: {}",
+                cidr
+            )));
+        }
+
+        let subnet = Self { network: addr, prefix };
+        if subnet.network_u32() != u32::from(addr) {
+            return Err(CuboError::NetworkError(format!(
+                "{} has host bits set; did you mean {}?",
+                cidr,
+                Subnet { network: Ipv4Addr::from(subnet.network_u32()), prefix }
+            )));
+        }
+        Ok(subnet)
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX << (32 - self.prefix)
+    }
+
+    fn network_u32(&self) -> u32 {
+        u32::from(self.network) & self.mask()
+    }
+
+    fn broadcast_u32(&self) -> u32 {
+        self.network_u32() | !self.mask()
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & self.mask() == self.network_u32()
+    }
+
+    /// True if `ip` can be given to a host (not the network or broadcast address)
+    pub fn is_usable(&self, ip: Ipv4Addr) -> bool {
+        let ip = u32::from(ip);
+        self.contains(Ipv4Addr::from(ip)) && ip != self.network_u32() && ip != self.broadcast_u32()
+    }
+
+    pub fn overlaps(&self, other: &Subnet) -> bool {
+        self.contains(other.network) || other.contains(self.network)
+    }
+
+    pub fn first_host(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.network_u32() + 1)
+    }
+
+    /// Usable host addresses in ascending order
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        (self.network_u32() + 1..self.broadcast_u32()).map(Ipv4Addr::from)
+    }
+}
+
+impl std::fmt::Display for Subnet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// A user-defined bridge network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Network {
+    pub id: String,
+    pub name: String,
+    pub driver: String,
+    pub subnet: String,
+    pub gateway: String,
+    /// Name of the host bridge interface
+    pub bridge: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Connected containers keyed by container ID
+    #[serde(default)]
+    pub endpoints: BTreeMap<String, Endpoint>,
+}
+
+/// A container's attachment to a network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub container_id: String,
+    pub ip: String,
+    pub mac: String,
+    /// Interface name inside the container
+    pub interface: String,
+}
+
+impl Network {
+    pub fn subnet(&self) -> Result<Subnet> {
+        Subnet::parse(&self.subnet)
+    }
+
+    pub fn short_id(&self) -> String {
+        self.id.chars().take(12).collect()
+    }
+
+    /// Validate a requested static address against the subnet and existing endpoints
+    fn reserve_ip(&self, requested: &str) -> Result<Ipv4Addr> {
+        let ip: Ipv4Addr = requested
+            .parse()
+            .map_err(|_| CuboError::NetworkError(format!("Invalid IPv4 address: {}", requested)))?;
+        let subnet = self.subnet()?;
+        if !subnet.is_usable(ip) {
+            return Err(CuboError::NetworkError(format!(
+                "{} is not a usable address in network {} ({})",
+                ip, self.name, self.subnet
+            )));
+        }
+        if ip.to_string() == self.gateway {
+            return Err(CuboError::NetworkError(format!("{} is the gateway of network {}", ip, self.name)));
+        }
+        if let Some(owner) = self.endpoints.values().find(|e| e.ip == ip.to_string()) {
+            return Err(CuboError::NetworkError(format!(
+                "Address {} is already in use by container {}",
+                ip, owner.container_id
+            )));
+        }
+        Ok(ip)
+    }
+
+    fn reserve_mac(&self, requested: &str) -> Result<String> {
+        let mac = normalize_mac(requested)?;
+        if let Some(owner) = self.endpoints.values().find(|e| e.mac == mac) {
+            return Err(CuboError::NetworkError(format!(
+                "MAC address {} is already in use by container {}",
+                mac, owner.container_id
+            )));
+        }
+        Ok(mac)
+    }
+
+    /// Pick the lowest free address that isn't the gateway or already handed out
+    fn next_free_ip(&self) -> Result<Ipv4Addr> {
+        let subnet = self.subnet()?;
+        let used: Vec<&str> = self.endpoints.values().map(|e| e.ip.as_str()).collect();
+        subnet
+            .hosts()
+            .find(|ip| {
+                let ip = ip.to_string();
+                ip != self.gateway && !used.contains(&ip.as_str())
+            })
+            .ok_or_else(|| CuboError::NetworkError(format!("No free addresses left in network {}", self.name)))
+    }
+}
+
+/// Persists networks as JSON files under <root_dir>/networks/<name>.json
+pub struct NetworkStore {
+    root: PathBuf,
+    pool: Subnet,
+}
+
+impl NetworkStore {
+    pub fn new(root: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&root)
+            .map_err(|e| CuboError::NetworkError(format!("Failed to create network store: {}", e)))?;
+        Ok(Self { root, pool: DEFAULT_POOL })
+    }
+
+    /// Take the subnets of networks created without one from `pool` instead of the default
+    pub fn with_pool(mut self, pool: Subnet) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    fn path_for(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.json", name))
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path_for(name).exists()
+    }
+
+    pub fn create(&self, name: &str, subnet: Option<&str>, gateway: Option<&str>) -> Result<Network> {
+        validate_network_name(name)?;
+        if self.exists(name) {
+            return Err(CuboError::NetworkError(format!("Network already exists: {}", name)));
+        }
+
+        let existing = self.list()?;
+        let subnet = match subnet {
+            Some(cidr) => {
+                let subnet = Subnet::parse(cidr)?;
+                for net in &existing {
+                    if net.subnet()?.overlaps(&subnet) {
+                        return Err(CuboError::NetworkError(format!(
+                            "Subnet {} overlaps with network {} ({})",
+                            subnet, net.name, net.subnet
+                        )));
+                    }
+                }
+                subnet
+            }
+            None => next_free_subnet(&existing, &self.pool)?,
+        };
+
+        let gateway = match gateway {
+            Some(gw) => {
+                let ip: Ipv4Addr = gw
+                    .parse()
+                    .map_err(|_| CuboError::NetworkError(format!("Invalid gateway address: {}", gw)))?;
+                if !subnet.is_usable(ip) {
+                    return Err(CuboError::NetworkError(format!(
+                        "Gateway {} is not a usable address in {}",
+                        ip, subnet
+                    )));
+                }
+                ip
+            }
+            None => subnet.first_host(),
+        };
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let network = Network {
+            bridge: format!("cubo-{}", &id[..10]),
+            id,
+            name: name.to_string(),
+            driver: "bridge".to_string(),
+            subnet: subnet.to_string(),
+            gateway: gateway.to_string(),
+            created_at: chrono::Utc::now(),
+            endpoints: BTreeMap::new(),
+        };
+
+        self.save(&network)?;
+        Ok(network)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Network> {
+        let path = self.path_for(name);
+        if !path.exists() {
+            return Err(CuboError::NetworkError(format!("Network not found: {}", name)));
+        }
+        read_json(&path)
+    }
+
+    pub fn list(&self) -> Result<Vec<Network>> {
+        let mut networks = Vec::new();
+        let entries = fs::read_dir(&self.root)
+            .map_err(|e| CuboError::NetworkError(format!("Failed to read network store: {}", e)))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_json::<Network>(&path) {
+                Ok(network) => networks.push(network),
+                Err(e) => warn!("Skipping unreadable network {:?}: {}", path, e),
+            }
+        }
+
+        networks.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(networks)
+    }
+
+    pub fn save(&self, network: &Network) -> Result<()> {
+        atomic_write_json(&self.path_for(&network.name), network)
+    }
+
+    /// Delete a network. Fails while containers are still connected.
+    pub fn remove(&self, name: &str) -> Result<Network> {
+        let network = self.get(name)?;
+        if !network.endpoints.is_empty() {
+            return Err(CuboError::NetworkError(format!(
+                "Network {} has {} connected container(s)",
+                name,
+                network.endpoints.len()
+            )));
+        }
+        fs::remove_file(self.path_for(name))
+            .map_err(|e| CuboError::NetworkError(format!("Failed to remove network {}: {}", name, e)))?;
+        Ok(network)
+    }
+
+    /// Allocate an address for the container and record the endpoint. A requested IP or MAC
+    /// is reserved as-is and rejected if another container already holds it.
+    pub fn connect(&self, name: &str, container_id: &str, ip: Option<&str>, mac: Option<&str>) -> Result<Endpoint> {
+        self.connect_as(name, container_id, "eth0", ip, mac)
+    }
+
+    /// Like `connect`, with the interface name the endpoint gets inside the container
+    pub fn connect_as(
+        &self,
+        name: &str,
+        container_id: &str,
+        interface: &str,
+        ip: Option<&str>,
+        mac: Option<&str>,
+    ) -> Result<Endpoint> {
+        let mut network = self.get(name)?;
+        if let Some(endpoint) = network.endpoints.get(container_id) {
+            return Ok(endpoint.clone());
+        }
+
+        let ip = match ip {
+            Some(requested) => network.reserve_ip(requested)?,
+            None => network.next_free_ip()?,
+        };
+        let mac = match mac {
+            Some(requested) => network.reserve_mac(requested)?,
+            None => mac_for_ip(ip),
+        };
+
+        let endpoint = Endpoint {
+            container_id: container_id.to_string(),
+            ip: ip.to_string(),
+            mac,
+            interface: interface.to_string(),
+        };
+
+        network.endpoints.insert(container_id.to_string(), endpoint.clone());
+        self.save(&network)?;
+        Ok(endpoint)
+    }
+
+    /// Release the container's address. Disconnecting an unknown container is a no-op.
+    pub fn disconnect(&self, name: &str, container_id: &str) -> Result<()> {
+        let mut network = self.get(name)?;
+        if network.endpoints.remove(container_id).is_some() {
+            self.save(&network)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn validate_network_name(name: &str) -> Result<()> {
+    if RESERVED_NAMES.contains(&name) {
+        return Err(CuboError::NetworkError(format!("{} is a reserved network name", name)));
+    }
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+    if !valid {
+        return Err(CuboError::NetworkError(format!(
+            "Invalid network name '{}': use letters, digits, '_', '.' and '-'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// The first /24 of `pool` no network overlaps. A pool holding several leaves its first one alone.
+fn next_free_subnet(existing: &[Network], pool: &Subnet) -> Result<Subnet> {
+    let taken: Vec<Subnet> = existing.iter().filter_map(|n| n.subnet().ok()).collect();
+    let prefix = pool.prefix.max(24);
+    let count = 1u32 << (prefix - pool.prefix);
+    (u32::from(count > 1)..count)
+        .map(|n| Subnet {
+            network: Ipv4Addr::from(pool.network_u32() + (n << (32 - prefix))),
+            prefix,
+        })
+        .find(|candidate| !taken.iter().any(|t| t.overlaps(candidate)))
+        .ok_or_else(|| CuboError::NetworkError(format!("No free subnet left in {}", pool)))
+}
+
+/// Validate a MAC address and return it in lowercase colon notation. Multicast addresses
+/// can't be assigned to an interface.
+pub fn normalize_mac(mac: &str) -> Result<String> {
+    let invalid = || CuboError::NetworkError(format!("Invalid MAC address: {}", mac));
+
+    let octets: Vec<u8> = mac
+        .split(':')
+        .map(|part| {
+            if part.len() == 2 {
+                u8::from_str_radix(part, 16).map_err(|_| invalid())
+            } else {
+                Err(invalid())
+            }
+        })
+        .collect::<Result<_>>()?;
+
+    if octets.len() != 6 {
+        return Err(invalid());
+    }
+    if octets[0] & 0x01 != 0 {
+        return Err(CuboError::NetworkError(format!("{} is a multicast MAC address", mac)));
+    }
+
+    Ok(octets.iter().map(|o| format!("{:02x}", o)).collect::<Vec<_>>().join(":"))
+}
+
+/// Locally administered MAC derived from the IP address (02:42:a:b:c:d)
+pub fn mac_for_ip(ip: Ipv4Addr) -> String {
+    let o = ip.octets();
+    format!("02:42:{:02x}:{:02x}:{:02x}:{:02x}", o[0], o[1], o[2], o[3])
+}
+
+/// Lowest ethN not in `used`. eth0 belongs to the primary network, so extra networks start at eth1.
+pub fn free_interface(used: &[String]) -> String {
+    (1..)
+        .map(|n| format!("eth{}", n))
+        .find(|name| !used.contains(name))
+        .unwrap_or_default()
+}
+
+/// Host ports the containers that are up publish. Nothing binds the ports of a rootful
+/// container on the host, so this is what keeps two containers from publishing the same one.
+#[derive(Debug, Default)]
+pub struct PortRegistry {
+    claims: Vec<(PortMapping, String)>,
+}
+
+impl PortRegistry {
+    /// Ports of the running, paused and restarting containers other than `except`
+    pub fn of<'a>(containers: impl IntoIterator<Item = &'a Container>, except: &str) -> Self {
+        let claims = containers
+            .into_iter()
+            .filter(|c| c.id != except)
+            .filter(|c| matches!(c.status, ContainerStatus::Running | ContainerStatus::Paused | ContainerStatus::Restarting))
+            .flat_map(|c| {
+                let ports = if c.published_ports.is_empty() { &c.config.ports } else { &c.published_ports };
+                ports.iter().map(|port| (port.clone(), c.id.clone()))
+            })
+            .collect();
+        Self { claims }
+    }
+
+    /// ID of the container that publishes a port `port` conflicts with
+    pub fn holder(&self, port: &PortMapping) -> Option<&str> {
+        self.claims
+            .iter()
+            .find(|(claimed, _)| port.conflicts_with(claimed))
+            .map(|(_, id)| id.as_str())
+    }
+
+    /// Refuse `ports` when another container publishes one of them, or when they publish a
+    /// host port twice
+    pub fn check(&self, ports: &[PortMapping]) -> Result<()> {
+        for (i, port) in ports.iter().enumerate() {
+            let address = format!("{}:{}/{}", port.host_ip.as_deref().unwrap_or("0.0.0.0"), port.host_port, port.protocol);
+            if let Some(holder) = self.holder(port) {
+                return Err(CuboError::PortInUse { port: address, container: holder.to_string() });
+            }
+            if ports[..i].iter().any(|other| port.conflicts_with(other)) {
+                return Err(CuboError::InvalidConfiguration(format!("Host port {} is published twice", address)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `ports` with each ephemeral host port replaced by one the kernel hands out as free on
+/// the host address and no container in `registry` publishes. The sockets are held until
+/// every port is picked, so no two collide.
+pub fn publish_ports(ports: &[PortMapping], registry: &PortRegistry) -> Result<Vec<PortMapping>> {
+    let mut held = Vec::new();
+    let mut published = Vec::with_capacity(ports.len());
+    for port in ports {
+        let mut port = port.clone();
+        let ephemeral = port.is_ephemeral();
+        // Held sockets make the kernel hand out another port on the next try
+        while ephemeral && (port.is_ephemeral() || registry.holder(&port).is_some()) {
+            let address = (port.host_ip.as_deref().unwrap_or("0.0.0.0"), 0);
+            let bound = match port.protocol {
+                Protocol::Tcp => std::net::TcpListener::bind(address)
+                    .and_then(|s| Ok((s.local_addr()?.port(), OwnedFd::from(s)))),
+                Protocol::Udp => std::net::UdpSocket::bind(address)
+                    .and_then(|s| Ok((s.local_addr()?.port(), OwnedFd::from(s)))),
+            };
+            let (host_port, socket) = bound.map_err(|e| {
+                CuboError::NetworkError(format!("No free host port for {}/{}: {}", port.container_port, port.protocol, e))
+            })?;
+            held.push(socket);
+            port.host_port = host_port;
+        }
+        published.push(port);
+    }
+    drop(held);
+    Ok(published)
+}
+
+/// Host side veth name, unique per container and network (max 15 chars)
+pub fn veth_name(container_id: &str, network_id: &str) -> String {
+    let cid: String = container_id.chars().filter(|c| *c != '-').take(6).collect();
+    let nid: String = network_id.chars().take(6).collect();
+    format!("vc{}{}", cid, nid)
+}
+
+fn run_ip(args: &[&str]) -> Result<()> {
+    run_cmd("ip", args)
+}
+
+fn run_cmd(program: &str, args: &[&str]) -> Result<()> {
+    debug!("{} {}", program, args.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| CuboError::NetworkError(format!("Failed to run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(CuboError::NetworkError(format!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Create the host bridge for a network if it doesn't exist yet
+pub fn ensure_bridge(network: &Network) -> Result<()> {
+    if Path::new("/sys/class/net").join(&network.bridge).exists() {
+        return Ok(());
+    }
+    let subnet = network.subnet()?;
+    let gateway = format!("{}/{}", network.gateway, subnet.prefix);
+    run_ip(&["link", "add", "name", &network.bridge, "type", "bridge"])?;
+    run_ip(&["addr", "add", &gateway, "dev", &network.bridge])?;
+    run_ip(&["link", "set", &network.bridge, "up"])?;
+    Ok(())
+}
+
+/// Remove the host bridge of a network, if present
+pub fn delete_bridge(network: &Network) -> Result<()> {
+    if !Path::new("/sys/class/net").join(&network.bridge).exists() {
+        return Ok(());
+    }
+    run_ip(&["link", "del", &network.bridge])
+}
+
+/// Wire the network namespace of `pid` to the network's bridge with a veth pair
+/// and configure the endpoint address inside it.
+pub fn attach_endpoint(network: &Network, endpoint: &Endpoint, pid: u32, default_route: bool) -> Result<()> {
+    ensure_bridge(network)?;
+
+    let subnet = network.subnet()?;
+    let host_veth = veth_name(&endpoint.container_id, &network.id);
+    let peer = format!("{}p", host_veth);
+    let pid = pid.to_string();
+    let address = format!("{}/{}", endpoint.ip, subnet.prefix);
+
+    run_ip(&["link", "add", &host_veth, "type", "veth", "peer", "name", &peer])?;
+    let result = (|| {
+        run_ip(&["link", "set", &host_veth, "master", &network.bridge])?;
+        run_ip(&["link", "set", &host_veth, "up"])?;
+        run_ip(&["link", "set", &peer, "netns", &pid])?;
+
+        let in_ns = |args: &[&str]| {
+            let mut full = vec!["-t", pid.as_str(), "-n", "ip"];
+            full.extend_from_slice(args);
+            run_cmd("nsenter", &full)
+        };
+        in_ns(&["link", "set", &peer, "name", &endpoint.interface])?;
+        in_ns(&["link", "set", &endpoint.interface, "address", &endpoint.mac])?;
+        in_ns(&["addr", "add", &address, "dev", &endpoint.interface])?;
+        in_ns(&["link", "set", &endpoint.interface, "up"])?;
+        if default_route {
+            in_ns(&["route", "add", "default", "via", &network.gateway])?;
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = run_ip(&["link", "del", &host_veth]);
+    }
+    result
+}
+
+/// Plug an existing host side veth, e.g. one recreated by a CRIU restore, into the bridge
+pub fn plug_host_veth(network: &Network, container_id: &str) -> Result<()> {
+    ensure_bridge(network)?;
+    let host_veth = veth_name(container_id, &network.id);
+    run_ip(&["link", "set", &host_veth, "master", &network.bridge])?;
+    run_ip(&["link", "set", &host_veth, "up"])
+}
+
+/// Remove the host side of a container's veth pair (the peer goes with it)
+pub fn detach_endpoint(network: &Network, container_id: &str) -> Result<()> {
+    let host_veth = veth_name(container_id, &network.id);
+    if !Path::new("/sys/class/net").join(&host_veth).exists() {
+        return Ok(());
+    }
+    run_ip(&["link", "del", &host_veth])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn store() -> (NetworkStore, TempDir) {
+        let temp = TempDir::new().unwrap();
+        let store = NetworkStore::new(temp.path().join("networks")).unwrap();
+        (store, temp)
+    }
+
+    #[test]
+    fn test_subnet_parse() {
+        let subnet = Subnet::parse("172.20.0.0/16").unwrap();
+        assert_eq!(subnet.network, Ipv4Addr::new(172, 20, 0, 0));
+        assert_eq!(subnet.prefix, 16);
+        assert_eq!(subnet.to_string(), "172.20.0.0/16");
+    }
+
+    #[test]
+    fn test_subnet_parse_invalid() {
+        assert!(Subnet::parse("172.20.0.0").is_err());
+        assert!(Subnet::parse("not-an-ip/24").is_err());
+        assert!(Subnet::parse("10.0.0.0/31").is_err());
+        assert!(Subnet::parse("10.0.0.5/24").is_err());
+    }
+
+    #[test]
+    fn test_subnet_contains_and_usable() {
+        let subnet = Subnet::parse("10.1.2.0/24").unwrap();
+        assert!(subnet.contains(Ipv4Addr::new(10, 1, 2, 77)));
+        assert!(!subnet.contains(Ipv4Addr::new(10, 1, 3, 1)));
+        assert!(!subnet.is_usable(Ipv4Addr::new(10, 1, 2, 0)));
+        assert!(!subnet.is_usable(Ipv4Addr::new(10, 1, 2, 255)));
+        assert!(subnet.is_usable(Ipv4Addr::new(10, 1, 2, 1)));
+    }
+
+    #[test]
+    fn test_subnet_overlaps() {
+        let a = Subnet::parse("10.0.0.0/16").unwrap();
+        let b = Subnet::parse("10.0.5.0/24").unwrap();
+        let c = Subnet::parse("10.1.0.0/24").unwrap();
+        assert!(a.overlaps(&b));
+        assert!(b.overlaps(&a));
+        assert!(!a.overlaps(&c));
+    }
+
+    #[test]
+    fn test_create_network_with_defaults() {
+        let (store, _temp) = store();
+        let network = store.create("mynet", None, None).unwrap();
+        assert_eq!(network.name, "mynet");
+        assert_eq!(network.driver, "bridge");
+        assert_eq!(network.subnet, "10.89.1.0/24");
+        assert_eq!(network.gateway, "10.89.1.1");
+        assert!(network.bridge.len() <= 15);
+        assert!(store.exists("mynet"));
+    }
+
+    #[test]
+    fn test_create_picks_next_free_subnet() {
+        let (store, _temp) = store();
+        store.create("one", None, None).unwrap();
+        let two = store.create("two", None, None).unwrap();
+        assert_eq!(two.subnet, "10.89.2.0/24");
+    }
+
+    #[test]
+    fn test_create_takes_subnets_from_pool() {
+        let (networks, _temp) = store();
+        let networks = networks.with_pool(Subnet::parse("172.30.0.0/23").unwrap());
+        assert_eq!(networks.create("one", None, None).unwrap().subnet, "172.30.1.0/24");
+        assert!(networks.create("two", None, None).is_err());
+
+        let (networks, _temp) = store();
+        let networks = networks.with_pool(Subnet::parse("192.168.7.0/28").unwrap());
+        assert_eq!(networks.create("small", None, None).unwrap().subnet, "192.168.7.0/28");
+    }
+
+    #[test]
+    fn test_create_with_custom_subnet_and_gateway() {
+        let (store, _temp) = store();
+        let network = store.create("custom", Some("192.168.50.0/24"), Some("192.168.50.254")).unwrap();
+        assert_eq!(network.subnet, "192.168.50.0/24");
+        assert_eq!(network.gateway, "192.168.50.254");
+    }
+
+    #[test]
+    fn test_create_rejects_bad_gateway() {
+        let (store, _temp) = store();
+        assert!(store.create("a", Some("192.168.50.0/24"), Some("192.168.51.1")).is_err());
+        assert!(store.create("b", Some("192.168.50.0/24"), Some("192.168.50.255")).is_err());
+    }
+
+    #[test]
+    fn test_create_rejects_overlapping_subnet() {
+        let (store, _temp) = store();
+        store.create("wide", Some("10.0.0.0/16"), None).unwrap();
+        let err = store.create("narrow", Some("10.0.3.0/24"), None).unwrap_err();
+        assert!(err.to_string().contains("overlaps"));
+    }
+
+    #[test]
+    fn test_create_rejects_duplicate_and_reserved_names() {
+        let (store, _temp) = store();
+        store.create("dup", None, None).unwrap();
+        assert!(store.create("dup", None, None).is_err());
+        assert!(store.create("host", None, None).is_err());
+        assert!(store.create("bad/name", None, None).is_err());
+    }
+
+    #[test]
+    fn test_list_networks_sorted() {
+        let (store, _temp) = store();
+        store.create("zeta", None, None).unwrap();
+        store.create("alpha", None, None).unwrap();
+        let names: Vec<_> = store.list().unwrap().into_iter().map(|n| n.name).collect();
+        assert_eq!(names, vec!["alpha", "zeta"]);
+    }
+
+    #[test]
+    fn test_connect_allocates_sequential_ips() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+
+        let a = store.connect("net", "container-a", None, None).unwrap();
+        let b = store.connect("net", "container-b", None, None).unwrap();
+        assert_eq!(a.ip, "10.10.0.2");
+        assert_eq!(b.ip, "10.10.0.3");
+        assert_eq!(a.mac, "02:42:0a:0a:00:02");
+        assert_eq!(a.interface, "eth0");
+
+        // Connecting twice returns the existing endpoint
+        let again = store.connect("net", "container-a", None, None).unwrap();
+        assert_eq!(again, a);
+    }
+
+    #[test]
+    fn test_disconnect_releases_ip() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+        store.connect("net", "container-a", None, None).unwrap();
+        store.disconnect("net", "container-a").unwrap();
+
+        let network = store.get("net").unwrap();
+        assert!(network.endpoints.is_empty());
+        let reused = store.connect("net", "container-b", None, None).unwrap();
+        assert_eq!(reused.ip, "10.10.0.2");
+    }
+
+    #[test]
+    fn test_connect_exhausts_small_subnet() {
+        let (store, _temp) = store();
+        // /30: .1 gateway, .2 the only container address
+        store.create("tiny", Some("10.20.0.0/30"), None).unwrap();
+        store.connect("tiny", "a", None, None).unwrap();
+        assert!(store.connect("tiny", "b", None, None).is_err());
+    }
+
+    #[test]
+    fn test_connect_with_static_ip_and_mac() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+
+        let endpoint = store.connect("net", "a", Some("10.10.0.50"), Some("02:AA:bb:cc:dd:ee")).unwrap();
+        assert_eq!(endpoint.ip, "10.10.0.50");
+        assert_eq!(endpoint.mac, "02:aa:bb:cc:dd:ee");
+
+        // Dynamic allocation still starts at the bottom of the range
+        let dynamic = store.connect("net", "b", None, None).unwrap();
+        assert_eq!(dynamic.ip, "10.10.0.2");
+    }
+
+    #[test]
+    fn test_connect_rejects_conflicting_static_ip() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+        store.connect("net", "a", Some("10.10.0.50"), None).unwrap();
+
+        let err = store.connect("net", "b", Some("10.10.0.50"), None).unwrap_err();
+        assert!(err.to_string().contains("already in use"));
+    }
+
+    #[test]
+    fn test_connect_skips_reserved_static_ip() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+        store.connect("net", "a", Some("10.10.0.2"), None).unwrap();
+        let next = store.connect("net", "b", None, None).unwrap();
+        assert_eq!(next.ip, "10.10.0.3");
+    }
+
+    #[test]
+    fn test_connect_rejects_invalid_static_ip() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+        assert!(store.connect("net", "a", Some("10.11.0.5"), None).is_err());
+        assert!(store.connect("net", "a", Some("10.10.0.1"), None).is_err());
+        assert!(store.connect("net", "a", Some("10.10.0.255"), None).is_err());
+        assert!(store.connect("net", "a", Some("bogus"), None).is_err());
+    }
+
+    #[test]
+    fn test_connect_rejects_conflicting_mac() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+        store.connect("net", "a", None, Some("02:00:00:00:00:01")).unwrap();
+        assert!(store.connect("net", "b", None, Some("02:00:00:00:00:01")).is_err());
+    }
+
+    #[test]
+    fn test_connect_as_sets_interface() {
+        let (store, _temp) = store();
+        store.create("net", Some("10.10.0.0/24"), None).unwrap();
+        let endpoint = store.connect_as("net", "a", "eth2", None, None).unwrap();
+        assert_eq!(endpoint.interface, "eth2");
+        assert_eq!(store.connect("net", "b", None, None).unwrap().interface, "eth0");
+    }
+
+    #[test]
+    fn test_free_interface() {
+        assert_eq!(free_interface(&[]), "eth1");
+        assert_eq!(free_interface(&["eth0".to_string(), "eth1".to_string()]), "eth2");
+        assert_eq!(free_interface(&["eth0".to_string(), "eth2".to_string()]), "eth1");
+    }
+
+    #[test]
+    fn test_publish_ports_resolves_ephemeral_ones() {
+        let ports = [
+            PortMapping::tcp(8080, 80),
+            PortMapping::tcp(0, 443).with_host_ip("127.0.0.1".to_string()),
+            PortMapping::tcp(0, 8443).with_host_ip("127.0.0.1".to_string()),
+            PortMapping::udp(0, 53).with_host_ip("127.0.0.1".to_string()),
+        ];
+        let published = publish_ports(&ports, &PortRegistry::default()).unwrap();
+        assert_eq!(published[0], ports[0]);
+        assert!(published[1..].iter().all(|p| !p.is_ephemeral()));
+        assert_ne!(published[1].host_port, published[2].host_port);
+        assert_eq!(published[3].protocol, Protocol::Udp);
+        assert_eq!(published[3].container_port, 53);
+    }
+
+    #[test]
+    fn test_port_registry() {
+        let mut web = Container::new("nginx".to_string(), vec![]).with_port(PortMapping::tcp(8080, 80));
+        web.id = "web".to_string();
+        web.update_status(ContainerStatus::Running);
+        let mut dns = Container::new("dns".to_string(), vec![]).with_port(PortMapping::udp(53, 53).with_host_ip("127.0.0.1".to_string()));
+        dns.id = "dns".to_string();
+        dns.update_status(ContainerStatus::Paused);
+        let mut stopped = Container::new("db".to_string(), vec![]).with_port(PortMapping::tcp(5432, 5432));
+        stopped.update_status(ContainerStatus::Stopped);
+        let registry = PortRegistry::of([&web, &dns, &stopped], "other");
+
+        assert_eq!(registry.holder(&PortMapping::tcp(8080, 8000)), Some("web"));
+        assert_eq!(registry.holder(&PortMapping::tcp(8080, 8000).with_host_ip("10.0.0.1".to_string())), Some("web"));
+        assert_eq!(registry.holder(&PortMapping::udp(53, 5353)), Some("dns"));
+        assert_eq!(registry.holder(&PortMapping::udp(53, 53).with_host_ip("127.0.0.2".to_string())), None);
+        assert_eq!(registry.holder(&PortMapping::udp(8080, 80)), None);
+        assert_eq!(registry.holder(&PortMapping::tcp(5432, 5432)), None);
+        assert!(PortRegistry::of([&web], "web").check(&[PortMapping::tcp(8080, 80)]).is_ok());
+
+        // Ephemeral ports never land on a port a container publishes
+        let published = publish_ports(&[PortMapping::tcp(0, 80)], &registry).unwrap();
+        assert!(registry.holder(&published[0]).is_none());
+    }
+
+    #[test]
+    fn test_normalize_mac() {
+        assert_eq!(normalize_mac("02:AB:cd:EF:00:11").unwrap(), "02:ab:cd:ef:00:11");
+        assert!(normalize_mac("01:00:5e:00:00:01").is_err());
+        assert!(normalize_mac("02:00:00:00:00").is_err());
+        assert!(normalize_mac("02-00-00-00-00-01").is_err());
+        assert!(normalize_mac("zz:00:00:00:00:01").is_err());
+    }
+
+    #[test]
+    fn test_remove_network() {
+        let (store, _temp) = store();
+        store.create("gone", None, None).unwrap();
+        store.remove("gone").unwrap();
+        assert!(!store.exists("gone"));
+        assert!(store.get("gone").is_err());
+    }
+
+    #[test]
+    fn test_remove_network_with_endpoints_fails() {
+        let (store, _temp) = store();
+        store.create("busy", None, None).unwrap();
+        store.connect("busy", "container", None, None).unwrap();
+        let err = store.remove("busy").unwrap_err();
+        assert!(err.to_string().contains("connected"));
+    }
+
+    #[test]
+    fn test_veth_name_length() {
+        let name = veth_name("3f2b1c4d-aaaa-bbbb-cccc-dddddddddddd", "0123456789abcdef");
+        assert_eq!(name, "vc3f2b1c012345");
+        assert!(name.len() <= 15);
+    }
+
+    #[test]
+    #[ignore] // requires root privileges
+    fn test_ensure_and_delete_bridge() {
+        let (store, _temp) = store();
+        let network = store.create("bridge-test", Some("10.250.0.0/24"), None).unwrap();
+        ensure_bridge(&network).unwrap();
+        delete_bridge(&network).unwrap();
+    }
+}
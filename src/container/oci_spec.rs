@@ -0,0 +1,662 @@
+//! OCI runtime spec (`config.json`) describing how cubo runs a container, for runc/crun
+//! and for auditing its isolation settings
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use nix::mount::MsFlags;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::container::cgroup::{block_device_number, CPU_PERIOD, CUBO_CGROUP_PARENT};
+use crate::container::exec::exec_env;
+use crate::container::idmap::{self, IdRange};
+use crate::container::interpreter;
+use crate::container::namespace::default_mounts;
+use crate::container::selinux::Labels;
+use crate::container::user::lookup;
+use crate::container::{Container, IpcMode, MountType, NetworkMode, Propagation, ThrottleDevice, UsernsMode};
+use crate::error::Result;
+
+/// Version of the runtime spec the generated config follows
+pub const OCI_VERSION: &str = "1.0.2";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spec {
+    pub oci_version: String,
+    pub process: Process,
+    pub root: Root,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domainname: Option<String>,
+    pub mounts: Vec<Mount>,
+    pub linux: Linux,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Process {
+    pub terminal: bool,
+    pub user: User,
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+    pub cwd: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rlimits: Vec<Rlimit>,
+    #[serde(rename = "oomScoreAdj", default, skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<Scheduler>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_priority: Option<IoPriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selinux_label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apparmor_profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_gids: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scheduler {
+    pub policy: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nice: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IoPriority {
+    pub class: String,
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rlimit {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub hard: u64,
+    pub soft: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Root {
+    pub path: String,
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mount {
+    pub destination: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linux {
+    pub namespaces: Vec<Namespace>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uid_mappings: Vec<IdMapping>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gid_mappings: Vec<IdMapping>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sysctl: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    pub cgroups_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rootfs_propagation: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub time_offsets: BTreeMap<String, TimeOffsetSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount_label: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub masked_paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub readonly_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeOffsetSpec {
+    pub secs: i64,
+    pub nanosecs: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Namespace {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Namespace to join instead of creating one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdMapping {
+    #[serde(rename = "containerID")]
+    pub container_id: u32,
+    #[serde(rename = "hostID")]
+    pub host_id: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<MemoryResources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuResources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids: Option<PidsResources>,
+    #[serde(rename = "blockIO", skip_serializing_if = "Option::is_none")]
+    pub block_io: Option<BlockIoResources>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryResources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reservation: Option<i64>,
+    /// Memory plus swap, like docker's --memory-swap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuResources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mems: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PidsResources {
+    pub limit: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockIoResources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_read_bps_device: Vec<ThrottleRate>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub throttle_write_bps_device: Vec<ThrottleRate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThrottleRate {
+    pub major: u64,
+    pub minor: u64,
+    pub rate: u64,
+}
+
+/// Host-side facts the spec depends on besides the container's config
+#[derive(Debug, Clone, Default)]
+pub struct SpecHost {
+    /// uid and gid maps of the container's user namespace; None when it uses the host's
+    pub id_mappings: Option<(Vec<IdRange>, Vec<IdRange>)>,
+    /// Init process of the sandbox whose network and IPC namespaces the container joins
+    pub sandbox_pid: Option<u32>,
+    /// SELinux labels of the container; None when SELinux is off or labeling disabled
+    pub labels: Option<Labels>,
+    /// AppArmor profile of the container; None when AppArmor is off or it is unconfined
+    pub apparmor_profile: Option<String>,
+}
+
+impl SpecHost {
+    /// Mappings cubo would set up for `mode` when run by uid/gid: subordinate ranges when
+    /// newuidmap/newgidmap can use them, else the single invoking id
+    pub fn for_user(uid: u32, gid: u32, mode: &UsernsMode) -> Self {
+        let id_mappings = if uid == 0 || *mode == UsernsMode::Host {
+            None
+        } else {
+            Some(match idmap::detect(uid, gid, mode) {
+                Some(mapping) => (mapping.uids, mapping.gids),
+                None => (idmap::ranges_for(mode, uid, &[]), idmap::ranges_for(mode, gid, &[])),
+            })
+        };
+        Self { id_mappings, sandbox_pid: None, labels: None, apparmor_profile: None }
+    }
+}
+
+/// Build the spec of `container` with its root filesystem at `rootfs`
+pub fn build_spec(container: &Container, rootfs: &Path, host: &SpecHost) -> Result<Spec> {
+    let config = &container.config;
+
+    let mut env = exec_env(container, &HashMap::new());
+    let user = match &config.user {
+        Some(spec) => {
+            let user = lookup(&rootfs.join("etc"), spec)?;
+            env.entry("HOME".to_string()).or_insert(user.home.clone());
+            if let Some(name) = &user.name {
+                env.entry("USER".to_string()).or_insert(name.clone());
+            }
+            User { uid: user.uid, gid: user.gid, additional_gids: user.additional_gids }
+        }
+        None => User { uid: 0, gid: 0, additional_gids: Vec::new() },
+    };
+    let mut env: Vec<String> = env.into_iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    env.sort();
+
+    let mut rlimits: Vec<Rlimit> = config.ulimits.iter()
+        .map(|u| Rlimit { kind: format!("RLIMIT_{}", u.name.to_uppercase()), hard: u.hard, soft: u.soft })
+        .collect();
+    if let Some(runtime) = config.rt_runtime {
+        rlimits.push(Rlimit { kind: "RLIMIT_RTTIME".to_string(), hard: runtime, soft: runtime });
+    }
+
+    // A nice value alone keeps the default policy
+    let scheduler = (config.sched_policy.is_some() || config.nice.is_some()).then(|| Scheduler {
+        policy: config.sched_policy.unwrap_or_default().oci_name().to_string(),
+        nice: config.nice,
+        priority: config.rt_priority.map(|priority| priority as i32),
+    });
+
+    let mut annotations = BTreeMap::new();
+    if let Some(name) = &container.name {
+        annotations.insert("name".to_string(), name.clone());
+    }
+    annotations.insert("blueprint".to_string(), container.blueprint.clone());
+
+    Ok(Spec {
+        oci_version: OCI_VERSION.to_string(),
+        process: Process {
+            terminal: config.tty,
+            user,
+            args: interpreter::command_args(container),
+            env,
+            cwd: config.working_dir.clone().unwrap_or_else(|| "/".to_string()),
+            rlimits,
+            oom_score_adj: config.oom_score_adj,
+            scheduler,
+            io_priority: config.io_priority.map(|p| IoPriority { class: p.class.oci_name().to_string(), priority: p.level as i32 }),
+            selinux_label: host.labels.as_ref().map(|l| l.process.clone()),
+            apparmor_profile: host.apparmor_profile.clone(),
+        },
+        root: Root { path: rootfs.to_string_lossy().to_string(), readonly: config.read_only_rootfs },
+        hostname: container.hostname(),
+        domainname: config.domainname.clone(),
+        mounts: mounts(container),
+        linux: Linux {
+            namespaces: namespaces(container, host),
+            uid_mappings: host.id_mappings.iter().flat_map(|(uids, _)| uids.iter().map(id_mapping)).collect(),
+            gid_mappings: host.id_mappings.iter().flat_map(|(_, gids)| gids.iter().map(id_mapping)).collect(),
+            sysctl: config.sysctls.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            resources: resources(container),
+            cgroups_path: format!("/{}/{}", CUBO_CGROUP_PARENT, container.id),
+            rootfs_propagation: match Propagation::of_root(&config.volume_mounts) {
+                Propagation::Rprivate => None,
+                propagation => Some(propagation.to_string()),
+            },
+            time_offsets: config.time_offsets.iter()
+                .map(|o| (o.clock.clone(), TimeOffsetSpec { secs: o.secs, nanosecs: 0 }))
+                .collect(),
+            mount_label: host.labels.as_ref().map(|l| l.mount.clone()),
+            masked_paths: config.masked_paths.clone(),
+            readonly_paths: config.readonly_paths.clone(),
+        },
+        annotations,
+    })
+}
+
+fn id_mapping(range: &IdRange) -> IdMapping {
+    IdMapping { container_id: range.container_id, host_id: range.host_id, size: range.size }
+}
+
+/// The namespaces cubo unshares, mirroring `unshare_mount_pid_net`, `join_sandbox` and
+/// `unshare_time`
+fn namespaces(container: &Container, host: &SpecHost) -> Vec<Namespace> {
+    let config = &container.config;
+    let new = |kind: &str| Namespace { kind: kind.to_string(), path: None };
+    let mut namespaces = vec![new("pid"), new("mount"), new("uts"), new("cgroup")];
+
+    match host.sandbox_pid {
+        Some(pid) => {
+            for kind in ["network", "ipc"] {
+                let name = if kind == "network" { "net" } else { kind };
+                namespaces.push(Namespace { kind: kind.to_string(), path: Some(format!("/proc/{}/ns/{}", pid, name)) });
+            }
+        }
+        None => {
+            if config.network_mode != NetworkMode::Host {
+                namespaces.push(new("network"));
+            }
+            if config.ipc_mode == IpcMode::Private {
+                namespaces.push(new("ipc"));
+            }
+        }
+    }
+    if !config.time_offsets.is_empty() {
+        namespaces.push(new("time"));
+    }
+    if host.id_mappings.is_some() {
+        namespaces.push(new("user"));
+    }
+    namespaces
+}
+
+/// /proc, the default runtime mounts, then the container's volumes
+fn mounts(container: &Container) -> Vec<Mount> {
+    let mut mounts = vec![Mount {
+        destination: "/proc".to_string(),
+        kind: "proc".to_string(),
+        source: "proc".to_string(),
+        options: Vec::new(),
+    }];
+
+    for m in default_mounts() {
+        let mut options = flag_options(m.flags);
+        options.extend(m.data.iter().flat_map(|d| d.split(',')).map(str::to_string));
+        mounts.push(Mount {
+            destination: m.destination.to_string(),
+            kind: m.fstype.to_string(),
+            source: m.source.to_string(),
+            options,
+        });
+    }
+
+    for volume in &container.config.volume_mounts {
+        let mut mount = match volume.mount_type {
+            MountType::Bind => Mount {
+                destination: volume.container_path.clone(),
+                kind: "bind".to_string(),
+                source: volume.host_path.clone(),
+                options: vec!["rbind".to_string(), volume.propagation.to_string()],
+            },
+            MountType::Tmpfs => {
+                let tmpfs = &volume.tmpfs_options;
+                let noexec = if tmpfs.exec { MsFlags::empty() } else { MsFlags::MS_NOEXEC };
+                let mut options = flag_options(MsFlags::MS_NODEV | MsFlags::MS_NOSUID | noexec);
+                options.extend(tmpfs.data().iter().flat_map(|d| d.split(',')).map(str::to_string));
+                Mount {
+                    destination: volume.container_path.clone(),
+                    kind: "tmpfs".to_string(),
+                    source: "tmpfs".to_string(),
+                    options,
+                }
+            }
+            // Named volumes aren't mounted by the runtime either
+            MountType::Volume => continue,
+        };
+        if volume.read_only {
+            mount.options.push("ro".to_string());
+        }
+        mounts.push(mount);
+    }
+    mounts
+}
+
+fn flag_options(flags: MsFlags) -> Vec<String> {
+    [
+        (MsFlags::MS_NOSUID, "nosuid"),
+        (MsFlags::MS_NOEXEC, "noexec"),
+        (MsFlags::MS_NODEV, "nodev"),
+        (MsFlags::MS_RDONLY, "ro"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// The cgroup limits `CgroupManager::apply` writes. Throttled devices that can't be
+/// resolved on this host are left out.
+fn resources(container: &Container) -> Option<Resources> {
+    let config = &container.config;
+    let throttle = |devices: &[ThrottleDevice]| -> Vec<ThrottleRate> {
+        devices.iter().filter_map(|device| match block_device_number(Path::new(&device.path)) {
+            Ok((major, minor)) => Some(ThrottleRate { major, minor, rate: device.rate }),
+            Err(e) => {
+                warn!("Leaving out the throttle of {}: {}", device.path, e);
+                None
+            }
+        }).collect()
+    };
+
+    let block_io = BlockIoResources {
+        weight: config.blkio_weight,
+        throttle_read_bps_device: throttle(&config.device_read_bps),
+        throttle_write_bps_device: throttle(&config.device_write_bps),
+    };
+    let quota = config.cpu_limit.filter(|cpus| *cpus > 0.0);
+    let cpu = CpuResources {
+        quota: quota.map(|cpus| (cpus as f64 * CPU_PERIOD as f64).round() as i64),
+        period: quota.map(|_| CPU_PERIOD),
+        cpus: config.cpuset_cpus.clone(),
+        mems: config.cpuset_mems.clone(),
+    };
+    let memory = MemoryResources {
+        limit: config.memory_limit.map(|limit| limit as i64),
+        reservation: config.memory_reservation.map(|reservation| reservation as i64),
+        swap: config.memory_swap,
+    };
+    let resources = Resources {
+        memory: (memory != MemoryResources::default()).then_some(memory),
+        cpu: (cpu != CpuResources::default()).then_some(cpu),
+        // Like pids.max, zero or less is unlimited
+        pids: config.pids_limit.map(|limit| PidsResources { limit: if limit <= 0 { -1 } else { limit } }),
+        block_io: (block_io != BlockIoResources::default()).then_some(block_io),
+    };
+    (resources != Resources::default()).then_some(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::sched::{self, IoClass, SchedPolicy};
+    use crate::container::{TimeOffset, TmpfsOptions, Ulimit, VolumeMount};
+    use tempfile::TempDir;
+
+    fn container() -> Container {
+        Container::new("alpine:latest".to_string(), vec!["sleep".to_string(), "60".to_string()])
+            .with_name("web".to_string())
+            .with_env("A".to_string(), "1".to_string())
+            .with_volume(VolumeMount::bind("/srv".to_string(), "/data".to_string(), true))
+            .with_pids_limit(64)
+            .with_ulimit(Ulimit { name: "nofile".to_string(), soft: 1024, hard: 4096 })
+    }
+
+    #[test]
+    fn test_build_spec() {
+        let temp = TempDir::new().unwrap();
+        let spec = build_spec(&container(), temp.path(), &SpecHost::default()).unwrap();
+
+        assert_eq!(spec.oci_version, OCI_VERSION);
+        assert_eq!(spec.process.args, ["/bin/sh", "-c", "sleep 60"]);
+        assert!(spec.process.env.contains(&"A=1".to_string()));
+        assert!(spec.process.env.iter().any(|e| e.starts_with("PATH=")));
+        assert_eq!(spec.process.user, User { uid: 0, gid: 0, additional_gids: vec![] });
+        assert_eq!(spec.process.cwd, "/");
+        assert_eq!(spec.process.rlimits, [Rlimit { kind: "RLIMIT_NOFILE".to_string(), hard: 4096, soft: 1024 }]);
+        assert_eq!(spec.root.path, temp.path().to_string_lossy());
+        assert_eq!(spec.annotations["name"], "web");
+
+        let data = spec.mounts.iter().find(|m| m.destination == "/data").unwrap();
+        assert_eq!((data.kind.as_str(), data.source.as_str()), ("bind", "/srv"));
+        assert_eq!(data.options, ["rbind", "rprivate", "ro"]);
+        assert_eq!(spec.linux.rootfs_propagation, None);
+        let sys = spec.mounts.iter().find(|m| m.destination == "/sys").unwrap();
+        assert_eq!(sys.options, ["nosuid", "noexec", "nodev", "ro"]);
+
+        let kinds: Vec<_> = spec.linux.namespaces.iter().map(|n| n.kind.as_str()).collect();
+        assert_eq!(kinds, ["pid", "mount", "uts", "cgroup", "network", "ipc"]);
+        assert!(spec.linux.uid_mappings.is_empty());
+        assert_eq!(spec.linux.resources.unwrap().pids, Some(PidsResources { limit: 64 }));
+        assert!(spec.linux.masked_paths.contains(&"/proc/kcore".to_string()));
+        assert!(spec.linux.readonly_paths.contains(&"/proc/sys".to_string()));
+    }
+
+    #[test]
+    fn test_shared_mount_makes_root_shared() {
+        let temp = TempDir::new().unwrap();
+        let mut fuse = VolumeMount::bind("/mnt/fuse".to_string(), "/fuse".to_string(), false);
+        fuse.propagation = Propagation::Rshared;
+        let spec = build_spec(&container().with_volume(fuse), temp.path(), &SpecHost::default()).unwrap();
+
+        let mount = spec.mounts.iter().find(|m| m.destination == "/fuse").unwrap();
+        assert_eq!(mount.options, ["rbind", "rshared"]);
+        assert_eq!(spec.linux.rootfs_propagation.as_deref(), Some("rshared"));
+    }
+
+    #[test]
+    fn test_tmpfs_options() {
+        let temp = TempDir::new().unwrap();
+        let mut run = VolumeMount::tmpfs("/run".to_string());
+        run.tmpfs_options = TmpfsOptions { size: Some(1 << 20), mode: Some(0o755), exec: true };
+        let spec = build_spec(&container().with_volume(run), temp.path(), &SpecHost::default()).unwrap();
+
+        let mount = spec.mounts.iter().find(|m| m.destination == "/run").unwrap();
+        assert_eq!(mount.options, ["nosuid", "nodev", "size=1048576", "mode=755"]);
+    }
+
+    #[test]
+    fn test_user_namespace_and_sandbox() {
+        let temp = TempDir::new().unwrap();
+        let host = SpecHost {
+            id_mappings: Some((
+                vec![IdRange { container_id: 0, host_id: 1000, size: 1 }],
+                vec![IdRange { container_id: 0, host_id: 100, size: 1 }],
+            )),
+            sandbox_pid: Some(42),
+            ..Default::default()
+        };
+        let spec = build_spec(&container(), temp.path(), &host).unwrap();
+
+        let net = spec.linux.namespaces.iter().find(|n| n.kind == "network").unwrap();
+        assert_eq!(net.path.as_deref(), Some("/proc/42/ns/net"));
+        assert!(spec.linux.namespaces.iter().any(|n| n.kind == "user"));
+        assert_eq!(spec.linux.uid_mappings, [IdMapping { container_id: 0, host_id: 1000, size: 1 }]);
+        assert_eq!(spec.linux.gid_mappings[0].host_id, 100);
+
+        assert!(SpecHost::for_user(0, 0, &UsernsMode::Private).id_mappings.is_none());
+        assert!(SpecHost::for_user(1000, 1000, &UsernsMode::Host).id_mappings.is_none());
+    }
+
+    #[test]
+    fn test_host_modes_and_user() {
+        let temp = TempDir::new().unwrap();
+        let etc = temp.path().join("etc");
+        std::fs::create_dir_all(&etc).unwrap();
+        std::fs::write(etc.join("passwd"), "app:x:1000:1000:App:/home/app:/bin/sh\n").unwrap();
+        std::fs::write(etc.join("group"), "app:x:1000:\nwheel:x:10:app\n").unwrap();
+
+        let mut container = container()
+            .with_network_mode(NetworkMode::Host)
+            .with_ipc_mode(IpcMode::Host);
+        container.config.user = Some("app".to_string());
+        let spec = build_spec(&container, temp.path(), &SpecHost::default()).unwrap();
+
+        let kinds: Vec<_> = spec.linux.namespaces.iter().map(|n| n.kind.as_str()).collect();
+        assert_eq!(kinds, ["pid", "mount", "uts", "cgroup"]);
+        assert_eq!(spec.process.user, User { uid: 1000, gid: 1000, additional_gids: vec![10] });
+        assert!(spec.process.env.contains(&"HOME=/home/app".to_string()));
+        assert!(spec.process.env.contains(&"USER=app".to_string()));
+    }
+
+    #[test]
+    fn test_spec_cpuset() {
+        let temp = TempDir::new().unwrap();
+        let container = container().with_cpuset_cpus("0-3".to_string()).with_cpuset_mems("1".to_string());
+        let spec = build_spec(&container, temp.path(), &SpecHost::default()).unwrap();
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["linux"]["resources"]["cpu"], serde_json::json!({ "cpus": "0-3", "mems": "1" }));
+    }
+
+    #[test]
+    fn test_spec_memory() {
+        let temp = TempDir::new().unwrap();
+        let container = container().with_memory_reservation(1024).with_memory_swap(-1).with_oom_score_adj(300);
+        let spec = build_spec(&container, temp.path(), &SpecHost::default()).unwrap();
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["linux"]["resources"]["memory"], serde_json::json!({ "reservation": 1024, "swap": -1 }));
+        assert_eq!(json["process"]["oomScoreAdj"], 300);
+    }
+
+    #[test]
+    fn test_spec_scheduling() {
+        let temp = TempDir::new().unwrap();
+        let scheduled = container()
+            .with_nice(-5)
+            .with_sched_policy(SchedPolicy::Fifo, Some(20))
+            .with_rt_runtime(50000)
+            .with_io_priority(sched::IoPriority { class: IoClass::Idle, level: 0 });
+        let spec = build_spec(&scheduled, temp.path(), &SpecHost::default()).unwrap();
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["process"]["scheduler"], serde_json::json!({ "policy": "SCHED_FIFO", "nice": -5, "priority": 20 }));
+        assert_eq!(json["process"]["ioPriority"], serde_json::json!({ "class": "IOPRIO_CLASS_IDLE", "priority": 0 }));
+        assert!(spec.process.rlimits.contains(&Rlimit { kind: "RLIMIT_RTTIME".to_string(), hard: 50000, soft: 50000 }));
+
+        let spec = build_spec(&container().with_nice(10), temp.path(), &SpecHost::default()).unwrap();
+        assert_eq!(spec.process.scheduler.unwrap().policy, "SCHED_OTHER");
+    }
+
+    #[test]
+    fn test_spec_time_offsets() {
+        let temp = TempDir::new().unwrap();
+        let container = container().with_time_offset(TimeOffset { clock: "boottime".to_string(), secs: -60 });
+        let spec = build_spec(&container, temp.path(), &SpecHost::default()).unwrap();
+        assert!(spec.linux.namespaces.iter().any(|n| n.kind == "time"));
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["linux"]["timeOffsets"], serde_json::json!({ "boottime": { "secs": -60, "nanosecs": 0 } }));
+    }
+
+    #[test]
+    fn test_spec_selinux_labels() {
+        let temp = TempDir::new().unwrap();
+        let container = container();
+        let host = SpecHost { labels: crate::container::selinux::labels_for(&container), ..Default::default() };
+        let json = serde_json::to_value(build_spec(&container, temp.path(), &host).unwrap()).unwrap();
+        assert!(json["process"]["selinuxLabel"].as_str().unwrap().starts_with("system_u:system_r:container_t:s0:c"));
+        assert!(json["linux"]["mountLabel"].as_str().unwrap().starts_with("system_u:object_r:container_file_t:s0:c"));
+
+        let host = SpecHost { apparmor_profile: Some("cubo-default".to_string()), ..Default::default() };
+        let json = serde_json::to_value(build_spec(&container, temp.path(), &host).unwrap()).unwrap();
+        assert_eq!(json["process"]["apparmorProfile"], "cubo-default");
+        assert!(json["process"].get("selinuxLabel").is_none());
+        assert!(json["linux"].get("mountLabel").is_none());
+    }
+
+    #[test]
+    fn test_spec_json_field_names() {
+        let temp = TempDir::new().unwrap();
+        let container = container();
+        let spec = build_spec(&container, temp.path(), &SpecHost::default()).unwrap();
+        let json = serde_json::to_value(&spec).unwrap();
+
+        assert_eq!(json["ociVersion"], OCI_VERSION);
+        assert_eq!(json["process"]["rlimits"][0]["type"], "RLIMIT_NOFILE");
+        assert_eq!(json["linux"]["resources"]["pids"]["limit"], 64);
+        assert!(json["linux"]["resources"].get("cpu").is_none());
+        assert!(json["linux"]["resources"].get("memory").is_none());
+        assert!(json["process"].get("oomScoreAdj").is_none());
+        assert!(json["process"].get("scheduler").is_none());
+        assert_eq!(json["linux"]["cgroupsPath"], format!("/cubo/{}", container.id));
+        assert!(json["linux"].get("uidMappings").is_none());
+    }
+}
@@ -0,0 +1,199 @@
+//! Storage quotas on the writable part of a container's rootfs (`--storage-opt size=`)
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use nix::sys::statfs::{statfs, XFS_SUPER_MAGIC};
+use sha2::{Digest, Sha256};
+
+use crate::container::snapshotter::{is_mount_point, run, SnapshotterKind};
+use crate::error::{CuboError, Result};
+
+/// Sparse filesystem image backing the rootfs of a container when the loopback backend is used
+pub const LOOP_IMAGE: &str = "rootfs.img";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaBackend {
+    /// XFS project quota on the directory (needs the `prjquota` mount option)
+    XfsProject,
+    /// btrfs qgroup limit of the rootfs subvolume (needs `btrfs quota enable`)
+    BtrfsQgroup,
+    /// ext4 image of the quota size mounted on the directory
+    Loopback,
+}
+
+/// Backend enforcing a quota on `dir`, the storage of a snapshot of `snapshotter`
+pub fn backend_for(snapshotter: SnapshotterKind, dir: &Path) -> QuotaBackend {
+    if snapshotter == SnapshotterKind::Btrfs {
+        return QuotaBackend::BtrfsQgroup;
+    }
+    let probe = dir.ancestors().find(|p| p.exists()).unwrap_or(Path::new("/"));
+    match statfs(probe) {
+        Ok(stat) if stat.filesystem_type() == XFS_SUPER_MAGIC => QuotaBackend::XfsProject,
+        _ => QuotaBackend::Loopback,
+    }
+}
+
+/// Create an ext4 image of `size` bytes and mount it on `dir`. Has to happen before
+/// anything is written to `dir`.
+pub fn mount_loopback(image: &Path, dir: &Path, size: u64) -> Result<()> {
+    let file = fs::File::create(image)
+        .map_err(|e| CuboError::VolumeError(format!("Failed to create {}: {}", image.display(), e)))?;
+    file.set_len(size)
+        .map_err(|e| CuboError::VolumeError(format!("Failed to size {}: {}", image.display(), e)))?;
+    let mounted = run(Command::new("mkfs.ext4").args(["-q", "-F"]).arg(image))
+        .and_then(|_| {
+            fs::create_dir_all(dir)
+                .map_err(|e| CuboError::VolumeError(format!("Failed to create {}: {}", dir.display(), e)))
+        })
+        .and_then(|_| ensure_loopback(image, dir));
+    if let Err(e) = mounted {
+        let _ = discard_loopback(image, dir);
+        return Err(e);
+    }
+    // The fresh filesystem brings a lost+found the rootfs has no use for
+    let _ = fs::remove_dir(dir.join("lost+found"));
+    Ok(())
+}
+
+/// Mount the loopback image of a container again, e.g. after a reboot
+pub fn ensure_loopback(image: &Path, dir: &Path) -> Result<()> {
+    if !image.exists() || is_mount_point(dir) {
+        return Ok(());
+    }
+    run(Command::new("mount").args(["-o", "loop"]).arg(image).arg(dir))
+}
+
+/// Detach the loopback filesystem from `dir`, if one is mounted there
+pub fn release_loopback(dir: &Path) -> Result<()> {
+    if !is_mount_point(dir) {
+        return Ok(());
+    }
+    run(Command::new("umount").args(["-l", "-d"]).arg(dir))
+}
+
+/// Undo `mount_loopback` when the container it was made for can't be created. `dir` itself
+/// stays, it belongs to the snapshot.
+pub fn discard_loopback(image: &Path, dir: &Path) -> Result<()> {
+    release_loopback(dir)?;
+    match fs::remove_file(image) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(CuboError::VolumeError(format!("Failed to remove {}: {}", image.display(), e)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Limit `dir` to `size` bytes once the snapshot in it is prepared
+pub fn limit(backend: QuotaBackend, dir: &Path, size: u64, container_id: &str) -> Result<()> {
+    match backend {
+        QuotaBackend::XfsProject => {
+            let id = project_id(container_id);
+            run(Command::new("xfs_quota")
+                .arg("-x")
+                .arg("-c")
+                .arg(format!("project -s -p {} {}", dir.display(), id))
+                .arg("-c")
+                .arg(format!("limit -p bhard={} {}", size, id))
+                .arg(mount_point(dir)?))
+        }
+        QuotaBackend::BtrfsQgroup => {
+            run(Command::new("btrfs").args(["qgroup", "limit"]).arg(size.to_string()).arg(dir))
+        }
+        // The size of the image is the limit
+        QuotaBackend::Loopback => Ok(()),
+    }
+}
+
+/// XFS project id of a container, derived from its id
+pub fn project_id(container_id: &str) -> u32 {
+    let digest = Sha256::digest(container_id.as_bytes());
+    let id = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) & 0x7fff_ffff;
+    id.max(1)
+}
+
+/// Mount point of the filesystem holding `path`
+fn mount_point(path: &Path) -> Result<PathBuf> {
+    let dev = fs::metadata(path)?.dev();
+    let mut mount = path;
+    while let Some(parent) = mount.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        mount = parent;
+    }
+    Ok(mount.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_backend_for() {
+        let temp = TempDir::new().unwrap();
+        assert_eq!(backend_for(SnapshotterKind::Btrfs, temp.path()), QuotaBackend::BtrfsQgroup);
+
+        let expected = if statfs(temp.path()).unwrap().filesystem_type() == XFS_SUPER_MAGIC {
+            QuotaBackend::XfsProject
+        } else {
+            QuotaBackend::Loopback
+        };
+        assert_eq!(backend_for(SnapshotterKind::Dir, &temp.path().join("c1/rootfs")), expected);
+    }
+
+    #[test]
+    fn test_project_id() {
+        let id = project_id("2f1c9a7e-container");
+        assert_eq!(id, project_id("2f1c9a7e-container"));
+        assert_ne!(id, project_id("another"));
+        assert!(id > 0 && id <= 0x7fff_ffff);
+    }
+
+    #[test]
+    fn test_mount_point() {
+        let temp = TempDir::new().unwrap();
+        let nested = temp.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        let mount = mount_point(&nested).unwrap();
+        assert!(nested.starts_with(&mount));
+        assert_eq!(fs::metadata(&mount).unwrap().dev(), fs::metadata(&nested).unwrap().dev());
+    }
+
+    #[test]
+    fn test_loopback_release_without_mount() {
+        let temp = TempDir::new().unwrap();
+        release_loopback(temp.path()).unwrap();
+        ensure_loopback(&temp.path().join(LOOP_IMAGE), temp.path()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_loopback_mount_leaves_no_image() {
+        let temp = TempDir::new().unwrap();
+        let image = temp.path().join(LOOP_IMAGE);
+        // A file where the mount point should go makes the mount fail whether or not mkfs runs
+        let blocker = temp.path().join("blocker");
+        fs::write(&blocker, b"").unwrap();
+
+        assert!(mount_loopback(&image, &blocker.join("rootfs"), 16 << 20).is_err());
+        assert!(!image.exists());
+        assert!(blocker.is_file());
+    }
+
+    #[test]
+    fn test_discard_loopback_keeps_the_mount_point() {
+        let temp = TempDir::new().unwrap();
+        let image = temp.path().join(LOOP_IMAGE);
+        let dir = temp.path().join("rootfs");
+        fs::write(&image, b"").unwrap();
+        fs::create_dir(&dir).unwrap();
+
+        discard_loopback(&image, &dir).unwrap();
+        assert!(!image.exists());
+        assert!(dir.is_dir());
+        discard_loopback(&image, &dir).unwrap();
+    }
+}
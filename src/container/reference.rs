@@ -0,0 +1,341 @@
+//! Image references and container names as users give them, checked against Docker's grammar
+//! before they become file names in the image and container stores
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{CuboError, Result};
+
+/// Registry an image without a domain is pulled from
+pub const DEFAULT_REGISTRY: &str = "registry-1.docker.io";
+
+/// Tag of an image referenced without one
+pub const DEFAULT_TAG: &str = "latest";
+
+/// Names Docker Hub goes by in references
+const DOCKER_HUB_DOMAINS: [&str; 3] = ["docker.io", "index.docker.io", DEFAULT_REGISTRY];
+
+/// Docker Hub namespace of the official images, left out of their names
+const OFFICIAL_NAMESPACE: &str = "library/";
+
+/// Longest repository name, domain included, registries accept
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Longest tag
+const MAX_TAG_LENGTH: usize = 128;
+
+/// `[registry/]repository[:tag][@digest]`, e.g. `ghcr.io/owner/app:v1`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReference {
+    /// Domain, with its port if any, when the first component names one
+    pub registry: Option<String>,
+    pub repository: String,
+    pub tag: Option<String>,
+    /// `algorithm:hex`
+    pub digest: Option<String>,
+}
+
+impl ImageReference {
+    pub fn parse(reference: &str) -> Result<Self> {
+        let invalid = |reason: &str| CuboError::InvalidImageReference {
+            reference: reference.to_string(),
+            reason: reason.to_string(),
+        };
+        if reference.is_empty() {
+            return Err(invalid("it is empty"));
+        }
+
+        let (rest, digest) = match reference.split_once('@') {
+            Some((rest, digest)) => {
+                if !is_digest(digest) {
+                    return Err(invalid("the digest must be algorithm:hex, e.g. sha256:<64 hex digits>"));
+                }
+                (rest, Some(digest.to_string()))
+            }
+            None => (reference, None),
+        };
+
+        // A colon after the last slash starts the tag; one before it is a registry port
+        let (name, tag) = match rest.rfind(':') {
+            Some(idx) if !rest[idx..].contains('/') => (&rest[..idx], Some(rest[idx + 1..].to_string())),
+            _ => (rest, None),
+        };
+        if let Some(ref tag) = tag {
+            if !is_tag(tag) {
+                return Err(invalid("tags are up to 128 letters, digits, '_', '.' and '-', not starting with '.' or '-'"));
+            }
+        }
+        if name.len() > MAX_NAME_LENGTH {
+            return Err(invalid("the repository name is longer than 255 characters"));
+        }
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest)) if is_registry(first) => (Some(first.to_string()), rest),
+            _ => (None, name),
+        };
+        if let Some(ref registry) = registry {
+            if !is_domain(registry) {
+                return Err(invalid("the registry must be a host name with an optional port"));
+            }
+        }
+        if !repository.split('/').all(is_path_component) {
+            return Err(invalid(
+                "repository names are lowercase letters and digits, separated by '/', '.', '_', '__' or dashes",
+            ));
+        }
+
+        Ok(Self { registry, repository: repository.to_string(), tag, digest })
+    }
+
+    /// The tag, `latest` when there is none
+    pub fn tag_or_default(&self) -> &str {
+        self.tag.as_deref().unwrap_or(DEFAULT_TAG)
+    }
+
+    /// The form images are stored and shown under, as docker prints them: Docker Hub images
+    /// without the domain or `library/`, and tagged `latest` unless they have a tag or digest.
+    /// `alpine`, `library/alpine` and `docker.io/library/alpine:latest` are all `alpine:latest`.
+    pub fn normalized(&self) -> Self {
+        let hub = self.is_docker_hub();
+        let repository = match self.repository.strip_prefix(OFFICIAL_NAMESPACE) {
+            Some(name) if hub && !name.contains('/') => name.to_string(),
+            _ => self.repository.clone(),
+        };
+        let tag = match (&self.tag, &self.digest) {
+            (None, None) => Some(DEFAULT_TAG.to_string()),
+            (tag, _) => tag.clone(),
+        };
+        Self {
+            registry: if hub { None } else { self.registry.clone() },
+            repository,
+            tag,
+            digest: self.digest.clone(),
+        }
+    }
+
+    /// Registry to pull from and the repository there, with official images in `library/`
+    pub fn remote(&self) -> (String, String) {
+        if !self.is_docker_hub() {
+            return (self.registry.clone().unwrap_or_default(), self.repository.clone());
+        }
+        let repository = if self.repository.contains('/') {
+            self.repository.clone()
+        } else {
+            format!("{}{}", OFFICIAL_NAMESPACE, self.repository)
+        };
+        (DEFAULT_REGISTRY.to_string(), repository)
+    }
+
+    /// What the registry manifest is fetched by: the digest, else the tag
+    pub fn manifest_ref(&self) -> &str {
+        self.digest.as_deref().unwrap_or_else(|| self.tag_or_default())
+    }
+
+    fn is_docker_hub(&self) -> bool {
+        self.registry.as_deref().is_none_or(|registry| DOCKER_HUB_DOMAINS.contains(&registry))
+    }
+
+    /// The reference as a single path component, for the files stored under it
+    pub fn file_name(&self) -> String {
+        self.to_string().replace([':', '/'], "_")
+    }
+}
+
+impl FromStr for ImageReference {
+    type Err = CuboError;
+
+    fn from_str(reference: &str) -> Result<Self> {
+        Self::parse(reference)
+    }
+}
+
+impl fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref registry) = self.registry {
+            write!(f, "{}/", registry)?;
+        }
+        write!(f, "{}", self.repository)?;
+        if let Some(ref tag) = self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(ref digest) = self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// `reference` in the form images are stored and shown under, see `ImageReference::normalized`
+pub fn normalize(reference: &str) -> Result<String> {
+    Ok(ImageReference::parse(reference)?.normalized().to_string())
+}
+
+/// Fail unless `name` is a valid container name: a letter or digit followed by at least one
+/// more of letters, digits, '_', '.' and '-', as docker requires
+pub fn validate_container_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.len() >= 2
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(CuboError::InvalidContainerName(name.to_string()))
+    }
+}
+
+/// Docker takes the first component for a registry when it has a dot, a port or capitals, or
+/// is `localhost`
+fn is_registry(component: &str) -> bool {
+    component.contains(['.', ':']) || component == "localhost" || component.chars().any(|c| c.is_ascii_uppercase())
+}
+
+fn is_domain(domain: &str) -> bool {
+    let (host, port) = match domain.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (domain, None),
+    };
+    let valid_port = port.is_none_or(|port| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()));
+    let valid_host = if let Some(ipv6) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        ipv6.parse::<std::net::Ipv6Addr>().is_ok()
+    } else {
+        host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    };
+    valid_host && valid_port
+}
+
+/// `[a-z0-9]+` runs joined by '.', '_', '__' or any number of dashes
+fn is_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    if bytes.is_empty() || !is_lower_alnum(bytes[0]) || !is_lower_alnum(bytes[bytes.len() - 1]) {
+        return false;
+    }
+    let mut separator = String::new();
+    for &b in bytes {
+        if is_lower_alnum(b) {
+            let valid = matches!(separator.as_str(), "" | "." | "_" | "__") || separator.bytes().all(|s| s == b'-');
+            if !valid {
+                return false;
+            }
+            separator.clear();
+        } else if matches!(b, b'.' | b'_' | b'-') {
+            separator.push(b as char);
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+fn is_lower_alnum(b: u8) -> bool {
+    b.is_ascii_lowercase() || b.is_ascii_digit()
+}
+
+fn is_tag(tag: &str) -> bool {
+    let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    tag.len() <= MAX_TAG_LENGTH
+        && tag.chars().next().is_some_and(word)
+        && tag.chars().all(|c| word(c) || c == '.' || c == '-')
+}
+
+fn is_digest(digest: &str) -> bool {
+    let Some((algorithm, hex)) = digest.split_once(':') else {
+        return false;
+    };
+    let valid_algorithm = algorithm
+        .split(['+', '.', '-', '_'])
+        .all(|part| part.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    valid_algorithm && hex.len() >= 32 && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_parse_image_references() {
+        let reference = ImageReference::parse("alpine").unwrap();
+        assert_eq!((reference.registry, reference.repository.as_str(), reference.tag), (None, "alpine", None));
+
+        let reference = ImageReference::parse("localhost:5000/team/app:v1.2").unwrap();
+        assert_eq!(reference.registry.as_deref(), Some("localhost:5000"));
+        assert_eq!(reference.repository, "team/app");
+        assert_eq!(reference.tag_or_default(), "v1.2");
+
+        let with_digest = format!("ghcr.io/owner/my_app__x@{}", DIGEST);
+        let reference = ImageReference::parse(&with_digest).unwrap();
+        assert_eq!(reference.digest.as_deref(), Some(DIGEST));
+        assert_eq!(reference.tag_or_default(), DEFAULT_TAG);
+        assert_eq!(reference.to_string(), with_digest);
+
+        assert_eq!(ImageReference::parse("user/app:v1").unwrap().file_name(), "user_app_v1");
+    }
+
+    #[test]
+    fn test_normalize() {
+        for reference in ["alpine", "library/alpine", "docker.io/alpine", "docker.io/library/alpine:latest"] {
+            assert_eq!(normalize(reference).unwrap(), "alpine:latest", "{}", reference);
+        }
+        assert_eq!(normalize("index.docker.io/user/theimage").unwrap(), "user/theimage:latest");
+        assert_eq!(normalize("ghcr.io/owner/repo:v1.0").unwrap(), "ghcr.io/owner/repo:v1.0");
+        // Only Docker Hub has official images
+        assert_eq!(normalize("quay.io/library/app").unwrap(), "quay.io/library/app:latest");
+        assert_eq!(normalize("library/team/app:1").unwrap(), "library/team/app:1");
+        let pinned = format!("alpine@{}", DIGEST);
+        assert_eq!(normalize(&format!("docker.io/library/{}", pinned)).unwrap(), pinned);
+    }
+
+    #[test]
+    fn test_remote() {
+        let remote = |reference: &str| ImageReference::parse(reference).unwrap().remote();
+        assert_eq!(remote("alpine"), (DEFAULT_REGISTRY.to_string(), "library/alpine".to_string()));
+        assert_eq!(remote("docker.io/library/nginx:1.25"), (DEFAULT_REGISTRY.to_string(), "library/nginx".to_string()));
+        assert_eq!(remote("user/theimage"), (DEFAULT_REGISTRY.to_string(), "user/theimage".to_string()));
+        assert_eq!(remote("gcr.io/project/image"), ("gcr.io".to_string(), "project/image".to_string()));
+        assert_eq!(remote("quay.io/organization/image:1.0"), ("quay.io".to_string(), "organization/image".to_string()));
+
+        let pinned = ImageReference::parse(&format!("alpine:3.18@{}", DIGEST)).unwrap();
+        assert_eq!(pinned.manifest_ref(), DIGEST);
+        assert_eq!(ImageReference::parse("alpine:3.18").unwrap().manifest_ref(), "3.18");
+    }
+
+    #[test]
+    fn test_invalid_image_references() {
+        for reference in [
+            "",
+            "../../etc",
+            "alpine/../../x",
+            "Alpine",
+            "app:",
+            "app:-v1",
+            "app:v1/x",
+            "app@sha256:abc",
+            "app name",
+            "team//app",
+            "app-",
+            "bad_host-.com/app",
+        ] {
+            let err = ImageReference::parse(reference).unwrap_err();
+            assert!(matches!(err, CuboError::InvalidImageReference { .. }), "{}", reference);
+        }
+        assert!(ImageReference::parse(&"a".repeat(256)).is_err());
+        assert!(ImageReference::parse(&format!("app:{}", "1".repeat(129))).is_err());
+    }
+
+    #[test]
+    fn test_validate_container_name() {
+        for name in ["web", "my-app_1.2", "k8s_POD_x_default_uid_0", "a1"] {
+            validate_container_name(name).unwrap();
+        }
+        for name in ["", "a", "../../etc", "-web", ".hidden", "my app", "web/1", "web:1"] {
+            assert!(matches!(validate_container_name(name), Err(CuboError::InvalidContainerName(_))), "{}", name);
+        }
+    }
+}
@@ -1,24 +1,35 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use flate2::read::GzDecoder;
 use oci_distribution::client::{Client, ClientConfig, ClientProtocol};
-use oci_distribution::Reference;
-use tracing::{info, debug};
+use tracing::{info, debug, warn};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::error::{CuboError, Result};
 use super::image_store::{ImageStore, ImageManifest, ImageConfig};
+use super::reference::{normalize, ImageReference, DEFAULT_REGISTRY};
+use super::runtime::RuntimeConfig;
+use crate::config::ProxyConfig;
+use super::transport::{self, ImageSource};
 
 
 #[derive(Debug, Deserialize, Serialize)]
-struct OciManifest {
+pub(super) struct OciManifest {
     #[serde(rename = "schemaVersion")]
     schema_version: i32,
     #[serde(rename = "mediaType")]
     media_type: Option<String>,
-    config: OciDescriptor,
-    layers: Vec<OciDescriptor>,
+    pub(super) config: OciDescriptor,
+    pub(super) layers: Vec<OciDescriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) annotations: Option<BTreeMap<String, String>>,
+    /// Digest of what the reference resolved to in the registry, the manifest list when there
+    /// is one. Not part of the manifest.
+    #[serde(skip)]
+    pub(super) digest: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -46,15 +57,15 @@ struct Platform {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct OciDescriptor {
+pub(super) struct OciDescriptor {
     #[serde(rename = "mediaType")]
     media_type: String,
     size: i64,
-    digest: String,
+    pub(super) digest: String,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-struct OciImageConfig {
+pub(super) struct OciImageConfig {
     config: Option<OciConfig>,
 }
 
@@ -68,16 +79,74 @@ struct OciConfig {
     working_dir: Option<String>,
     #[serde(rename = "ExposedPorts")]
     exposed_ports: Option<serde_json::Value>,
+    #[serde(rename = "StopSignal", default)]
+    stop_signal: Option<String>,
+    #[serde(rename = "Labels", default)]
+    labels: Option<BTreeMap<String, String>>,
 }
 
 /// client
-pub struct RegistryClient {
+pub struct RegistryClient<'a> {
     client: Client,
-    image_store: ImageStore,
+    image_store: &'a ImageStore,
+    /// Mirror hosts tried before each registry, keyed by registry
+    mirrors: HashMap<String, Vec<String>>,
+    proxy: ProxyConfig,
+    pull_policy: PullPolicy,
+}
+
+/// When images are fetched from a registry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PullPolicy {
+    /// Only when the image isn't stored yet
+    #[default]
+    Missing,
+    /// Every time, even when the image is stored
+    Always,
+    /// Never; images missing from the store are an error. Imports from local transports
+    /// still work.
+    Never,
+}
+
+impl std::str::FromStr for PullPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "missing" => Ok(PullPolicy::Missing),
+            "always" => Ok(PullPolicy::Always),
+            "never" => Ok(PullPolicy::Never),
+            other => Err(format!("unknown pull policy '{}' (expected missing, always or never)", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for PullPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PullPolicy::Missing => "missing",
+            PullPolicy::Always => "always",
+            PullPolicy::Never => "never",
+        })
+    }
+}
+
+/// One step of a pull: `current` of `total` layers are downloaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullProgress {
+    pub status: String,
+    pub current: usize,
+    pub total: usize,
+}
+
+impl PullProgress {
+    fn new(status: &str, current: usize, total: usize) -> Self {
+        Self { status: status.to_string(), current, total }
+    }
 }
 
-impl RegistryClient {
-    pub fn new(image_store: ImageStore) -> Self {
+impl<'a> RegistryClient<'a> {
+    pub fn new(image_store: &'a ImageStore) -> Self {
         let config = ClientConfig {
             protocol: ClientProtocol::Https,
             ..Default::default()
@@ -86,32 +155,58 @@ impl RegistryClient {
         Self {
             client: Client::new(config),
             image_store,
+            mirrors: HashMap::new(),
+            proxy: ProxyConfig::default(),
+            pull_policy: PullPolicy::default(),
         }
     }
 
+    /// Pull through the configured mirrors and proxies
+    pub fn with_config(mut self, config: &RuntimeConfig) -> Self {
+        self.mirrors = config.registry_mirrors.clone();
+        self.proxy = config.proxy.clone();
+        self.pull_policy = config.pull_policy;
+        self
+    }
+
     pub async fn pull(&self, image_ref: &str) -> Result<()> {
+        self.pull_with_progress(image_ref, &|_| {}).await
+    }
+
+    /// Pull an image, reporting each step to `progress` as it happens
+    pub async fn pull_with_progress(
+        &self,
+        image_ref: &str,
+        progress: &(dyn Fn(PullProgress) + Send + Sync),
+    ) -> Result<()> {
+        let image_ref = match ImageSource::parse(image_ref) {
+            ImageSource::Registry(reference) => normalize(&reference)?,
+            source => return self.import(source, progress).await,
+        };
+        let image_ref = image_ref.as_str();
         info!("Pulling image: {}", image_ref);
-        if self.image_store.has_image(image_ref) {
+        if self.image_store.has_image(image_ref) && self.pull_policy != PullPolicy::Always {
             info!("Image {} already exists locally", image_ref);
+            progress(PullProgress::new("Image is up to date", 0, 0));
             return Ok(());
         }
+        if self.pull_policy == PullPolicy::Never {
+            return Err(CuboError::PullDisabled { reference: image_ref.to_string() });
+        }
 
         let (registry, repository, tag ) = Self::parse_image_ref(image_ref)?;
         info!("Registry: {}, Repository: {}, tag: {}", registry, repository, tag);
 
-        let http_client = reqwest::Client::builder()
-            .user_agent("cubo/0.1.0")
-            .redirect(reqwest::redirect::Policy::limited(10))
-            .build()
-            .map_err(|e| CuboError::SystemError(format!("Failed to create http client: {}", e)))?;
-        let token = Self::get_registry_token(&http_client, &registry, &repository).await?;
+        let http_client = self.http_client()?;
         info!("Fetching manifest...");
-        let manifest = Self::fetch_manifest(&http_client, &registry, &repository, &tag, &token).await?;
-        info!("Manifest fetched: {} layers", manifest.layers.len());
+        let (registry, token, manifest) = self.fetch_manifest_from(&http_client, &registry, &repository, &tag).await?;
+        info!("Manifest fetched from {}: {} layers", registry, manifest.layers.len());
+        let total = manifest.layers.len();
+        progress(PullProgress::new("Fetched manifest", 0, total));
         info!("Fetching image config...");
         let config_data = Self::fetch_blob(&http_client, &registry, &repository, &manifest.config.digest, &token).await?;
         let oci_config: OciImageConfig = serde_json::from_slice(&config_data)
-            .map_err(|e| CuboError::SystemError(format!("Failed to parse image config: {}", e)))?;
+            .map_err(|e| Self::registry_error(&registry, None, format!("Failed to parse image config: {}", e)))?;
 
         let temp_dir = tempfile::tempdir()
             .map_err(|e| CuboError::SystemError(format!("Failed to create temp dir: {}", e)))?;
@@ -132,56 +227,144 @@ impl RegistryClient {
             } else {
                 layer_file
             };
-            let safe_name = image_ref.replace(':', "_").replace('/', "_");
-            let blob_path = self
-                .image_store_root()
-                .join("blobs")
-                .join(format!("{}_{}.tar", safe_name, idx));
-
-            fs::create_dir_all(blob_path.parent().unwrap()).map_err(|e| {
-                CuboError::SystemError(format!("Failed to create blobs directoy: {}", e))
-            })?;
-
-            fs::copy(&final_layer, &blob_path)
-                .map_err(|e| CuboError::SystemError(format!("Failed to copy layer: {}", e)))?;
+            let blob_path = self.image_store.write_layer(&final_layer)?;
             layer_paths.push(blob_path.to_string_lossy().to_string());
+            progress(PullProgress::new("Downloaded layer", idx + 1, total));
         }
 
         let image_config = Self::convert_oci_config(&oci_config);
         let manifest_obj = ImageManifest {
             reference: image_ref.to_string(),
+            parent: None,
             layers: layer_paths,
             config: image_config,
+            annotations: manifest.annotations,
+            repo_digest: Some(manifest.digest),
         };
-        self.save_manifest(&manifest_obj)?;
+        self.image_store.save_manifest(&manifest_obj)?;
         info!("Successfully pulled and stored image: {}", image_ref);
+        progress(PullProgress::new("Pull complete", total, total));
         Ok(())
     }
 
-    fn parse_image_ref(image_ref: &str) -> Result<(String, String, String)> {
-        let parts: Vec<&str> = image_ref.split(':').collect();
-        let (image_path, tag) = if parts.len() == 2 {
-            (parts[0], parts[1].to_string())
-        } else {
-            (image_ref, "latest".to_string())
-        };
+    /// Copy an image from a docker daemon, OCI layout or directory into the store
+    async fn import(
+        &self,
+        source: ImageSource,
+        progress: &(dyn Fn(PullProgress) + Send + Sync),
+    ) -> Result<()> {
+        let reference = source.local_reference();
+        info!("Importing {:?} as {}", source, reference);
+        let image = tokio::task::spawn_blocking(move || transport::read(&source))
+            .await
+            .map_err(|e| CuboError::SystemError(format!("Image import failed: {}", e)))??;
+        let total = image.layers.len();
+        progress(PullProgress::new("Read manifest", 0, total));
 
-        let (registry, repository) = if image_path.contains('/') {
-            let path_parts: Vec<&str> = image_path.splitn(2, '/').collect();
-            if path_parts[0].contains('.') || path_parts[0] == "localhost" {
-                (path_parts[0].to_string(), path_parts[1].to_string())
+        let temp_dir = tempfile::tempdir()
+            .map_err(|e| CuboError::SystemError(format!("Failed to create temp dir: {}", e)))?;
+        ImageReference::parse(&reference)?;
+        let mut layer_paths = Vec::new();
+        for (idx, layer) in image.layers.iter().enumerate() {
+            let mut magic = [0u8; 2];
+            let gzipped = fs::File::open(layer)
+                .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut magic))
+                .is_ok()
+                && Self::is_gzipped(&magic);
+            let final_layer = if gzipped {
+                let decompressed_path = temp_dir.path().join(format!("layer_{}.tar", idx));
+                Self::decompress_gzip(layer, &decompressed_path)?;
+                decompressed_path
             } else {
-                ("registry-1.docker.io".to_string(), image_path.to_string())
-            }
-        } else {
-            ("registry-1.docker.io".to_string(), format!("library/{}", image_path))
+                layer.clone()
+            };
+
+            let blob_path = self.image_store.write_layer(&final_layer)?;
+            layer_paths.push(blob_path.to_string_lossy().to_string());
+            progress(PullProgress::new("Copied layer", idx + 1, total));
+        }
+
+        self.image_store.save_manifest(&ImageManifest {
+            reference: reference.clone(),
+            parent: None,
+            layers: layer_paths,
+            config: image.config,
+            annotations: image.annotations,
+            repo_digest: None,
+        })?;
+        info!("Successfully imported image: {}", reference);
+        progress(PullProgress::new("Import complete", total, total));
+        Ok(())
+    }
+
+    fn http_client(&self) -> Result<reqwest::Client> {
+        let no_proxy = self.proxy.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string);
+        let mut builder = reqwest::Client::builder()
+            .user_agent("cubo/0.1.0")
+            .redirect(reqwest::redirect::Policy::limited(10));
+        let invalid = |url: &str, e: reqwest::Error| {
+            CuboError::InvalidConfiguration(format!("Invalid proxy '{}': {}", url, e))
         };
+        if let Some(ref url) = self.proxy.http {
+            let proxy = reqwest::Proxy::http(url).map_err(|e| invalid(url, e))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+        }
+        if let Some(ref url) = self.proxy.https {
+            let proxy = reqwest::Proxy::https(url).map_err(|e| invalid(url, e))?;
+            builder = builder.proxy(proxy.no_proxy(no_proxy));
+        }
+        builder
+            .build()
+            .map_err(|e| CuboError::SystemError(format!("Failed to create http client: {}", e)))
+    }
+
+    /// Mirrors of `registry` in the order they are tried, then the registry itself
+    fn endpoints(&self, registry: &str) -> Vec<String> {
+        let key = if registry == DEFAULT_REGISTRY { "docker.io" } else { registry };
+        let mut endpoints = self
+            .mirrors
+            .get(key)
+            .or_else(|| self.mirrors.get(registry))
+            .cloned()
+            .unwrap_or_default();
+        endpoints.push(registry.to_string());
+        endpoints
+    }
+
+    /// The manifest from the first endpoint serving it, with that endpoint and its token.
+    /// Blobs are fetched from the same endpoint.
+    async fn fetch_manifest_from(
+        &self,
+        client: &reqwest::Client,
+        registry: &str,
+        repository: &str,
+        tag: &str,
+    ) -> Result<(String, String, OciManifest)> {
+        for endpoint in self.endpoints(registry) {
+            let fetched = async {
+                let token = Self::get_registry_token(client, &endpoint, repository).await?;
+                let manifest = Self::fetch_manifest(client, &endpoint, repository, tag, &token).await?;
+                Ok::<_, CuboError>((token, manifest))
+            }
+            .await;
+            match fetched {
+                Ok((token, manifest)) => return Ok((endpoint, token, manifest)),
+                Err(e) if endpoint != registry => warn!("Mirror {} failed, trying the next: {}", endpoint, e),
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!("the registry itself is always the last endpoint")
+    }
 
-        Ok((registry, repository, tag))
+    /// Registry, repository and the tag or digest the manifest is fetched by
+    fn parse_image_ref(image_ref: &str) -> Result<(String, String, String)> {
+        let reference = ImageReference::parse(image_ref)?;
+        let (registry, repository) = reference.remote();
+        Ok((registry, repository, reference.manifest_ref().to_string()))
     }
 
     async fn get_registry_token(client: &reqwest::Client, registry: &str, repository: &str) -> Result<String> {
-        if registry == "registry-1.docker.io" {
+        if registry == DEFAULT_REGISTRY {
             let url = format!(
                 "https://auth.docker.io/token?service=registry.docker.io&scope=repository:{}:pull",
                 repository
@@ -190,13 +373,10 @@ impl RegistryClient {
             let response = client.get(&url)
                 .send()
                 .await
-                .map_err(|e| CuboError::SystemError(format!("Failed to get auth token: {}", e)))?;
+                .map_err(|e| Self::registry_error(registry, None, format!("Failed to get auth token: {}", e)))?;
 
             if !response.status().is_success() {
-                return Err(CuboError::SystemError(format!(
-                    "Failed to get auth token: HTTP {}",
-                    response.status()
-                )));
+                return Err(Self::registry_error(registry, Some(response.status()), "Failed to get auth token"));
             }
 
             #[derive(Deserialize)]
@@ -205,7 +385,7 @@ impl RegistryClient {
             }
 
             let token_res: TokenResponse = response.json().await
-                .map_err(|e| CuboError::SystemError(format!("Failed to parse token response: {}", e)))?;
+                .map_err(|e| Self::registry_error(registry, None, format!("Failed to parse token response: {}", e)))?;
 
             Ok(token_res.token)
         } else {
@@ -237,13 +417,10 @@ impl RegistryClient {
         let response = request
             .send()
             .await
-            .map_err(|e| CuboError::SystemError(format!("Failed to fetch manifest: {}", e)))?;
+            .map_err(|e| Self::registry_error(registry, None, format!("Failed to fetch manifest: {}", e)))?;
 
-            if !response.status().is_success() {
-            return Err(CuboError::SystemError(format!(
-                "Failed to fetch manifest: HTTP {}",
-                response.status()
-            )));
+        if !response.status().is_success() {
+            return Err(Self::registry_error(registry, Some(response.status()), format!("Failed to fetch manifest {}", tag)));
         }
 
         let content_type = response.headers()
@@ -253,12 +430,13 @@ impl RegistryClient {
             .to_string();
 
         let response_text = response.text().await
-            .map_err(|e| CuboError::SystemError(format!("Failed to read response: {}", e)))?;
+            .map_err(|e| Self::registry_error(registry, None, format!("Failed to read response: {}", e)))?;
+        let digest = format!("sha256:{:x}", Sha256::digest(response_text.as_bytes()));
 
         if content_type.contains("manifest.list") || content_type.contains("image.index") {
             debug!("Received manifest list, selecting platform-specific manifest");
             let manifest_list: ManifestList = serde_json::from_str(&response_text)
-                .map_err(|e| CuboError::SystemError(format!("Failed to parse manifest list: {}", e)))?;
+                .map_err(|e| Self::registry_error(registry, None, format!("Failed to parse manifest list: {}", e)))?;
 
             let platform_manifest = manifest_list.manifests.iter()
                 .find(|m| {
@@ -267,15 +445,16 @@ impl RegistryClient {
                         .unwrap_or(false)
                 })
                 .or_else(|| manifest_list.manifests.first())
-                .ok_or_else(|| CuboError::SystemError("No suitable manifest found in list".to_string()))?;
+                .ok_or_else(|| Self::registry_error(registry, None, "No suitable manifest found in list"))?;
 
             info!("Selected manifest for platform: linux/amd64");
 
-            Self::fetch_manifest_by_digest(client, registry, repository, &platform_manifest.digest, token).await
+            let manifest = Self::fetch_manifest_by_digest(client, registry, repository, &platform_manifest.digest, token).await?;
+            Ok(OciManifest { digest, ..manifest })
         } else {
             let manifest: OciManifest = serde_json::from_str(&response_text)
-                .map_err(|e| CuboError::SystemError(format!("Failed to parse manifest: {}", e)))?;
-            Ok(manifest)
+                .map_err(|e| Self::registry_error(registry, None, format!("Failed to parse manifest: {}", e)))?;
+            Ok(OciManifest { digest, ..manifest })
         }
     }
 
@@ -302,17 +481,14 @@ impl RegistryClient {
         let response = request
             .send()
             .await
-            .map_err(|e| CuboError::SystemError(format!("Failed to fetch manifest by digest: {}", e)))?;
+            .map_err(|e| Self::registry_error(registry, None, format!("Failed to fetch manifest by digest: {}", e)))?;
 
         if !response.status().is_success() {
-            return Err(CuboError::SystemError(format!(
-                "Failed to fetch manifest: HTTP {}",
-                response.status()
-            )));
+            return Err(Self::registry_error(registry, Some(response.status()), format!("Failed to fetch manifest {}", digest)));
         }
 
         let manifest: OciManifest = response.json().await
-            .map_err(|e| CuboError::SystemError(format!("Failed to parse manifest: {}", e)))?;
+            .map_err(|e| Self::registry_error(registry, None, format!("Failed to parse manifest: {}", e)))?;
 
         Ok(manifest)
     }
@@ -334,24 +510,20 @@ impl RegistryClient {
         let response = request
             .send()
             .await
-            .map_err(|e| CuboError::SystemError(format!("Fialed to fetch blob: {}: {}", digest, e)))?;
+            .map_err(|e| Self::registry_error(registry, None, format!("Failed to fetch blob {}: {}", digest, e)))?;
 
         if !response.status().is_success() {
-            return Err(CuboError::SystemError(format!(
-                "Failed to fetch blob {}: HTTP {}",
-                digest,
-                response.status()
-            )));
+            return Err(Self::registry_error(registry, Some(response.status()), format!("Failed to fetch blob {}", digest)));
         }
 
         let data = response.bytes().await
-            .map_err(|e| CuboError::SystemError(format!("Failed to read blob data: {}", e)))?
+            .map_err(|e| Self::registry_error(registry, None, format!("Failed to read blob data: {}", e)))?
             .to_vec();
 
         Ok(data)
     }
 
-    fn convert_oci_config(oci_config: &OciImageConfig) -> ImageConfig {
+    pub(super) fn convert_oci_config(oci_config: &OciImageConfig) -> ImageConfig {
         let config = oci_config.config.as_ref();
 
         ImageConfig {
@@ -366,25 +538,11 @@ impl RegistryClient {
                         None
                     }
                 })
-            })
+            }),
+            stop_signal: config.and_then(|c| c.stop_signal.clone()),
+            labels: config.and_then(|c| c.labels.clone()),
         }
     }
- 
-    fn save_manifest(&self, manifest: &ImageManifest) -> Result<()> {
-        let safe_name = manifest.reference.replace(':', "_");
-        let manifest_path = self
-            .image_store_root()
-            .join("manifests")
-            .join(format!("{}.json", safe_name));
-        fs::create_dir_all(manifest_path.parent().unwrap()).map_err(|e| {
-            CuboError::SystemError(format!("Failed to create manifests dir: {}", e))
-        })?;
-        let json = serde_json::to_string_pretty(manifest)
-            .map_err(|e| CuboError::SystemError(format!("Failed to serialize manifest: {}", e)))?;
-        fs::write(&manifest_path, json)
-            .map_err(|e| CuboError::SystemError(format!("Failed to write manifest: {}", e)))?;
-        Ok(())
-    }
 
     fn parse_image_config(_config_data: &oci_distribution::client::Config) -> Result<ImageConfig> {
         Ok(ImageConfig {
@@ -392,9 +550,19 @@ impl RegistryClient {
             env: Some(vec!["PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string()]),
             working_dir: Some("/".to_string()),
             exposed_ports: None,
+            stop_signal: None,
+            labels: None,
         })
     } 
 
+    fn registry_error(registry: &str, status: Option<reqwest::StatusCode>, message: impl Into<String>) -> CuboError {
+        CuboError::RegistryError {
+            registry: registry.to_string(),
+            status: status.map(|s| s.as_u16()),
+            message: message.into(),
+        }
+    }
+
     fn is_gzipped(data: &[u8]) -> bool {
         data.len() >= 2 && data[0] == 0x1f && data[1] == 0x8b
     }
@@ -407,69 +575,20 @@ impl RegistryClient {
         let mut output_file = fs::File::create(output)
             .map_err(|e| CuboError::SystemError(format!("Failed to create output file: {}", e)))?;
 
-        std::io::copy(&mut decoder, &mut output_file)
-            .map_err(|e| CuboError::SystemError(format!("Failed to decompress gzip: {}", e)))?;
+        std::io::copy(&mut decoder, &mut output_file).map_err(|e| CuboError::LayerExtractionError {
+            layer: input.to_path_buf(),
+            message: format!("Failed to decompress gzip: {}", e),
+        })?;
 
         Ok(())
     }
 
-    fn parse_reference(image_ref: &str) -> Result<Reference> {
-        let full_ref = if !image_ref.contains('/') {
-            format!("docker.io/library/{}", image_ref)
-        } else if !image_ref.starts_with("docker.io") && !image_ref.contains('.') {
-            format!("docker.io/{}", image_ref)
-        } else {
-            image_ref.to_string()
-        };
-
-        let full_ref = if !full_ref.contains(':') && !full_ref.contains('@') {
-            format!("{}:latest", full_ref)
-        } else {
-            full_ref
-        };
-
-        Reference::try_from(full_ref.as_str()).map_err(|e| {
-            CuboError::InvalidConfiguration(format!("Invalid image reference '{}': {}", image_ref, e))
-        })
-    }
-
-    fn image_store_root(&self) -> PathBuf {
-        std::env::var("CUBO_ROOT")
-            .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("/var/lib/cubo"))
-            .join("images")
-    }
-
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_parse_reference_short() {
-        let ref_str = RegistryClient::parse_reference("alpine")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("docker.io/library/alpine:latest"));
-    }
-
-    #[test]
-    fn test_parse_reference_with_tag() {
-        let ref_str = RegistryClient::parse_reference("alpine:3.18")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("alpine:3.18"));
-    }
-
-    #[test]
-    fn test_parse_reference_user_image() {
-        let ref_str = RegistryClient::parse_reference("user/theimage")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("docker.io/user/theimage:latest"));
-    }
-
     #[test]
     fn test_is_gzipped() {
         let gzip_magic = vec![0x1f, 0x8b, 0x08, 0x00];
@@ -491,47 +610,12 @@ mod tests {
         assert!(!RegistryClient::is_gzipped(&single));
     }
 
-    #[test]
-    fn test_parse_reference_ghcr() {
-        let ref_str = RegistryClient::parse_reference("ghcr.io/owner/repo:v1.0")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("ghcr.io"));
-        assert!(ref_str.contains("owner/repo"));
-        assert!(ref_str.contains("v1.0"));
-    }
-
-    #[test]
-    fn test_parase_reference_gcr() {
-        let ref_str = RegistryClient::parse_reference("gcr.io/project/image:latest")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("gcr.io"));
-    }
-
-    #[test]
-    fn test_parse_reference_quay() {
-        let ref_str = RegistryClient::parse_reference("quay.io/organization/image:1.0")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("quay.io"));
-    }
-
-    #[test]
-    fn test_parse_reference_docker_io_explicit() {
-        let ref_str = RegistryClient::parse_reference("docker.io/library/nginx:1.25")
-            .unwrap()
-            .to_string();
-        assert!(ref_str.contains("nginx"));
-        assert!(ref_str.contains("1.25"));
-    }
-
     #[test]
     fn test_registry_client_creation() {
         use tempfile::TempDir;
         let tmp = TempDir::new().unwrap();
         let store = crate::container::image_store::ImageStore::new(tmp.path().to_path_buf()).unwrap();
-        let _client = RegistryClient::new(store);
+        let _client = RegistryClient::new(&store);
     }
     
     #[test]
@@ -540,13 +624,18 @@ mod tests {
 
         let manifest = ImageManifest {
             reference: "test:latest".to_string(),
+            parent: None,
             layers: vec!["layer1.tar".to_string(), "layer2.tar".to_string()],
             config: ImageConfig {
                 cmd: Some(vec!["/bin/bash".to_string()]),
                 env: Some(vec!["PATH=/bin".to_string()]),
                 working_dir: Some("/app".to_string()),
                 exposed_ports: Some(vec!["80/tcp".to_string()]),
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
 
         let json = serde_json::to_string(&manifest).unwrap();
@@ -622,6 +711,8 @@ mod tests {
                 cmd: Some(vec!["/bin/sh".to_string()]),
                 working_dir: Some("/app".to_string()),
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             }),
         };
         let config = RegistryClient::convert_oci_config(&oci_config);
@@ -631,6 +722,27 @@ mod tests {
         assert_eq!(config.env.unwrap().len(), 2);
     }
 
+    #[test]
+    fn test_convert_oci_config_stop_signal() {
+        let oci_config: OciImageConfig =
+            serde_json::from_str(r#"{"config": {"Cmd": ["nginx"], "StopSignal": "SIGQUIT"}}"#).unwrap();
+        let config = RegistryClient::convert_oci_config(&oci_config);
+        assert_eq!(config.stop_signal, Some("SIGQUIT".to_string()));
+    }
+
+    #[test]
+    fn test_convert_oci_config_labels() {
+        let oci_config: OciImageConfig = serde_json::from_str(
+            r#"{"config": {"Labels": {"org.opencontainers.image.source": "https://github.com/nginx/docker-nginx"}}}"#,
+        )
+        .unwrap();
+        let labels = RegistryClient::convert_oci_config(&oci_config).labels.unwrap();
+        assert_eq!(labels["org.opencontainers.image.source"], "https://github.com/nginx/docker-nginx");
+
+        let oci_config: OciImageConfig = serde_json::from_str(r#"{"config": {"Labels": null}}"#).unwrap();
+        assert!(RegistryClient::convert_oci_config(&oci_config).labels.is_none());
+    }
+
     #[test]
     fn test_convert_oci_config_with_exposed_ports() {
         let mut ports_map = serde_json::Map::new();
@@ -643,6 +755,8 @@ mod tests {
                 cmd: None,
                 working_dir: None,
                 exposed_ports: Some(serde_json::Value::Object(ports_map)),
+                stop_signal: None,
+                labels: None,
             }),
         };
         let config = RegistryClient::convert_oci_config(&oci_config);
@@ -682,33 +796,55 @@ mod tests {
     }
 
     #[test]
-    #[serial_test::serial]
-    fn test_image_store_root_from_env() {
-        use tempfile::TempDir;
-        let tmp = TempDir::new().unwrap();
-        std::env::set_var("CUBO_ROOT", tmp.path());
-
-        let store = crate::container::image_store::ImageStore::new(tmp.path().join("images")).unwrap();
-        let client = RegistryClient::new(store);
+    fn test_pull_policy_parse() {
+        for policy in [PullPolicy::Missing, PullPolicy::Always, PullPolicy::Never] {
+            assert_eq!(policy.to_string().parse::<PullPolicy>().unwrap(), policy);
+        }
+        assert!("offline".parse::<PullPolicy>().is_err());
+    }
 
-        let root = client.image_store_root();
-        assert_eq!(root, tmp.path().join("images"));
+    #[tokio::test]
+    async fn test_never_pull_policy_fails_without_network() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let store = ImageStore::new(tmp.path().to_path_buf()).unwrap();
+        let config = RuntimeConfig { pull_policy: PullPolicy::Never, ..RuntimeConfig::default() };
+        let client = RegistryClient::new(&store).with_config(&config);
 
-        std::env::remove_var("CUBO_ROOT");
+        // A registry that can't resolve would hang or fail differently if it were contacted
+        let err = client.pull("registry.invalid/app:1").await.unwrap_err();
+        assert!(matches!(err, CuboError::PullDisabled { ref reference } if reference == "registry.invalid/app:1"));
     }
 
-    #[test]
+    #[tokio::test]
     #[serial_test::serial]
-    fn test_image_store_root_default() {
-        std::env::remove_var("CUBO_ROOT");
-
+    async fn test_import_writes_to_the_given_store() {
+        use sha2::{Digest, Sha256};
         use tempfile::TempDir;
         let tmp = TempDir::new().unwrap();
-        let store = crate::container::image_store::ImageStore::new(tmp.path().to_path_buf()).unwrap();
-        let client = RegistryClient::new(store);
+        // CUBO_ROOT names another root: the image goes to the store the client was given
+        std::env::set_var("CUBO_ROOT", tmp.path().join("elsewhere"));
+        let image_dir = tmp.path().join("img");
+        fs::create_dir_all(&image_dir).unwrap();
+        let config = r#"{"config":{"Cmd":["sh"]}}"#;
+        let config_hex = format!("{:x}", Sha256::digest(config));
+        fs::write(image_dir.join(&config_hex), config).unwrap();
+        let layer_hex = format!("{:x}", Sha256::digest(b"layer"));
+        fs::write(image_dir.join(&layer_hex), b"layer").unwrap();
+        let manifest = format!(
+            r#"{{"schemaVersion":2,"config":{{"mediaType":"application/vnd.oci.image.config.v1+json","size":1,"digest":"sha256:{}"}},"layers":[{{"mediaType":"application/vnd.oci.image.layer.v1.tar","size":1,"digest":"sha256:{}"}}]}}"#,
+            config_hex, layer_hex
+        );
+        fs::write(image_dir.join("manifest.json"), manifest).unwrap();
 
-        let root = client.image_store_root();
-        assert_eq!(root, PathBuf::from("/var/lib/cubo/images"));
+        let store = ImageStore::new(tmp.path().join("store")).unwrap();
+        RegistryClient::new(&store).pull(&format!("dir:{}", image_dir.display())).await.unwrap();
+
+        let store = ImageStore::new(tmp.path().join("store")).unwrap();
+        let layers = store.get_manifest("img:latest").unwrap().layers;
+        let blob = format!("store/blobs/sha256_{}.tar", layer_hex);
+        assert_eq!(layers, [tmp.path().join(blob).to_string_lossy()]);
+        assert!(!tmp.path().join("elsewhere").exists());
+        std::env::remove_var("CUBO_ROOT");
     }
 
     #[test]
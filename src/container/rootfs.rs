@@ -1,11 +1,15 @@
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 
 use tracing::{debug, error, info, warn};
 
 use crate::error::{CuboError, Result};
 use super::image_store::ImageStore;
+use super::layer_cache::LayerCache;
+use super::secure_join::secure_join;
+use super::snapshotter::{self, Snapshotter};
+use super::unpack::{self, ScannedLayer};
+use super::whiteout::WhiteoutFormat;
 
 pub struct RootfsBuilder<'a> {
     image_store: &'a ImageStore,
@@ -19,21 +23,19 @@ impl<'a> RootfsBuilder<'a> {
     pub fn build_from_image(&self, image_ref: &str, target: &Path) -> Result<()> {
         info!("Building rootfs for {} at {}", image_ref, target.display());
 
-        fs::create_dir_all(target)
-            .map_err(|e| CuboError::SystemError(format!("Failed to create rootfs directory: {}", e)))?;
-
         let layers  = self.image_store.get_layers(image_ref)?;
 
         if layers.is_empty() {
             return Err(CuboError::SystemError(format!("Image {} has no layers", image_ref)));
         }
 
-        debug!("Extrac ting {} layers for {}", layers.len(), image_ref);
-
-        for (idx, layer_path ) in layers .iter().enumerate() {
-            debug!("Extracting layer {}/{}: {}", idx + 1, layers.len(), layer_path.display());
-            self.extract_layer(layer_path, target)?;
-        }
+        debug!("Copying {} layers of {} from the layer cache", layers.len(), image_ref);
+        LayerCache::new(self.image_store).materialize(
+            &layers,
+            target,
+            |layer| self.scan_layer(layer),
+            |layer, dir| self.apply_layer(&layer, dir, WhiteoutFormat::Delete),
+        )?;
 
         self.ensure_essential_dirs(target)?;
 
@@ -41,9 +43,50 @@ impl<'a> RootfsBuilder<'a> {
         Ok(())
     }
 
-    fn extract_layer(&self, layer_path: &Path, target: &Path) -> Result<()> {
+    /// Prepare `target` as a snapshot of the image, unpacking and committing the layers
+    /// that aren't snapshots yet
+    pub fn prepare_from_image(&self, snapshotter: &dyn Snapshotter, image_ref: &str, target: &Path) -> Result<()> {
+        info!("Preparing rootfs for {} at {}", image_ref, target.display());
+
+        let layers = self.image_store.get_layers(image_ref)?;
+        if layers.is_empty() {
+            return Err(CuboError::SystemError(format!("Image {} has no layers", image_ref)));
+        }
+
+        let mut names: Vec<String> = Vec::with_capacity(layers.len());
+        for layer_path in &layers {
+            names.push(snapshotter::chain_id(names.last().map(String::as_str), layer_path)?);
+        }
+        let missing: Vec<usize> = (0..layers.len()).filter(|&idx| !snapshotter.exists(&names[idx])).collect();
+        let missing_layers: Vec<PathBuf> = missing.iter().map(|&idx| layers[idx].clone()).collect();
+
+        // The next layers are scanned while one is unpacked; each still goes over its parent
+        unpack::pipelined(&missing_layers, unpack::scan_workers(), |layer| self.scan_layer(layer), |n, layer| {
+            let idx = missing[n];
+            debug!("Unpacking layer {}/{}: {}", idx + 1, layers.len(), layer.path.display());
+            let parent = idx.checked_sub(1).map(|parent| names[parent].as_str());
+            let scratch = snapshotter.root().join(format!("tmp-{}", uuid::Uuid::new_v4()));
+            snapshotter.prepare(&scratch, parent)?;
+            let committed = self
+                .apply_layer(&layer, &snapshotter.upper_dir(&scratch), snapshotter.whiteouts())
+                .and_then(|_| snapshotter.commit(&names[idx], &scratch));
+            if committed.is_err() {
+                let _ = snapshotter.remove(&scratch);
+            }
+            committed
+        })?;
+
+        snapshotter.prepare(target, names.last().map(String::as_str))?;
+        self.ensure_essential_dirs(&snapshotter.upper_dir(target))
+    }
+
+    /// Read the whiteouts of a layer blob, which may be gzipped
+    fn scan_layer(&self, layer_path: &Path) -> Result<ScannedLayer> {
         if !layer_path.exists() {
-            return Err(CuboError::SystemError(format!("Layer file does not exist: {}", layer_path.display())));
+            return Err(CuboError::LayerExtractionError {
+                layer: layer_path.to_path_buf(),
+                message: "Layer file does not exist".to_string(),
+            });
         }
 
         let is_gzip = layer_path
@@ -52,34 +95,20 @@ impl<'a> RootfsBuilder<'a> {
             .map(|s| s == "gz" || s == "tgz")
             .unwrap_or(false);
 
-        let mut cmd = Command::new("tar");
-
-        if is_gzip {
-            cmd.arg("-xzf");
-        } else {
-            cmd.arg("-xf");
-        }
-
-        cmd.arg(layer_path)
-            .arg("-C")
-            .arg(target)
-            .arg("--no-same-owner")
-            .arg("--no-same-permissions");
-
-        debug!("Running: {:?}", cmd);
-
-        let output = cmd.output()
-            .map_err(|e| CuboError::SystemError(format!("Failed to execute tar command: {}", e)))?;
+        unpack::scan_layer(layer_path, is_gzip)
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(CuboError::SystemError(format!(
-                "Failed to extract layer {}: {}",
-                layer_path.display(),
-                stderr
-            )));
+    /// Unpack a scanned layer over `target`, applying its whiteouts as `whiteouts` says, and
+    /// warn about the entries that couldn't be
+    fn apply_layer(&self, layer: &ScannedLayer, target: &Path, whiteouts: WhiteoutFormat) -> Result<()> {
+        debug!("Unpacking {} into {}", layer.path.display(), target.display());
+        let report = unpack::unpack_scanned(layer, target, whiteouts)?;
+        if !report.skipped.is_empty() {
+            warn!("Skipped {} entries of layer {}:", report.skipped.len(), layer.path.display());
+            for entry in &report.skipped {
+                warn!("  /{}: {}", entry.path.display(), entry.reason);
+            }
         }
-
         Ok(())
     }
 
@@ -143,7 +172,7 @@ impl<'a> RootfsBuilder<'a> {
         for binary in &essential_binaries {
             let binary_path = Path::new(binary);
             if binary_path.exists() {
-                let dest_path = rootfs.join(binary.trim_start_matches('/'));
+                let dest_path = secure_join(rootfs, binary)?;
                 if let Some(parent) = dest_path.parent() {
                     fs::create_dir_all(parent)
                         .map_err(|e| CuboError::SystemError(format!(
@@ -168,10 +197,15 @@ impl<'a> RootfsBuilder<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::process::Command;
     use tempfile::TempDir;
     use std::fs::File;
     use std::io::Write;
 
+    fn extract(builder: &RootfsBuilder, layer: &Path, target: &Path, whiteouts: WhiteoutFormat) -> Result<()> {
+        builder.apply_layer(&builder.scan_layer(layer)?, target, whiteouts)
+    }
+
     fn create_test_tar(path: &Path, content: &str) -> Result<()> {
         // Create a simple tar file for testing
         let temp_dir = TempDir::new().unwrap();
@@ -241,7 +275,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = RootfsBuilder::new(&image_store);
 
-        builder.extract_layer(&tar_path, &rootfs).unwrap();
+        extract(&builder, &tar_path, &rootfs, WhiteoutFormat::Delete).unwrap();
 
         let extracted_file = rootfs.join("test.txt");
         assert!(extracted_file.exists());
@@ -257,7 +291,7 @@ mod tests {
         fs::create_dir_all(&rootfs).unwrap();
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = RootfsBuilder::new(&image_store);
-        let result = builder.extract_layer(Path::new("/nonexistent/layer.tar"), &rootfs);
+        let result = extract(&builder, Path::new("/nonexistent/layer.tar"), &rootfs, WhiteoutFormat::Delete);
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("does not exist"));
     }
@@ -323,13 +357,18 @@ mod tests {
 
         let manifest = ImageManifest {
             reference: "test:latest".to_string(),
+            parent: None,
             layers: vec![layer_path.to_string_lossy().to_string()],
             config: ImageConfig {
                 cmd: Some(vec!["/bin/sh".to_string()]),
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         image_store.save_manifest(&manifest).unwrap();
 
@@ -353,13 +392,18 @@ mod tests {
 
         let manifest = ImageManifest {
             reference: "empty:latest".to_string(),
+            parent: None,
             layers: vec![],
             config: ImageConfig {
                 cmd: None,
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         image_store.save_manifest(&manifest).unwrap();
 
@@ -392,7 +436,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = RootfsBuilder::new(&image_store);
 
-        let result = builder.extract_layer(&gz_path, &rootfs);
+        let result = extract(&builder, &gz_path, &rootfs, WhiteoutFormat::Delete);
         assert!(result.is_ok());
 
         let extracted_file = rootfs.join("test.txt");
@@ -420,7 +464,7 @@ mod tests {
         let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
         let builder = RootfsBuilder::new(&image_store);
 
-        let result = builder.extract_layer(&tgz_path, &rootfs);
+        let result = extract(&builder, &tgz_path, &rootfs, WhiteoutFormat::Delete);
         assert!(result.is_ok());
     }
 
@@ -452,6 +496,7 @@ mod tests {
 
         let manifest = ImageManifest {
             reference: "multi:latest".to_string(),
+            parent: None,
             layers: vec![
                 layer1_path.to_string_lossy().to_string(),
                 layer2_path.to_string_lossy().to_string(),
@@ -461,7 +506,11 @@ mod tests {
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         image_store.save_manifest(&manifest).unwrap();
 
@@ -495,4 +544,112 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_prepare_from_image_reuses_layer_snapshots() {
+        use crate::container::image_store::{ImageManifest, ImageConfig};
+        use crate::container::snapshotter::{snapshotter_for, ProvisionMode, SnapshotterKind};
+
+        let tmp = TempDir::new().unwrap();
+        let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
+        let layer_path = tmp.path().join("images/blobs/layer.tar");
+        create_test_tar(&layer_path, "test content").unwrap();
+        image_store.save_manifest(&ImageManifest {
+            reference: "test:latest".to_string(),
+            parent: None,
+            layers: vec![layer_path.to_string_lossy().to_string()],
+            config: ImageConfig {
+                cmd: None,
+                env: None,
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        }).unwrap();
+
+        let snapshotter = snapshotter_for(SnapshotterKind::Dir, tmp.path(), ProvisionMode::Auto);
+        let builder = RootfsBuilder::new(&image_store);
+        let first = tmp.path().join("c1/rootfs");
+        builder.prepare_from_image(snapshotter.as_ref(), "test:latest", &first).unwrap();
+        assert_eq!(fs::read_to_string(first.join("test.txt")).unwrap(), "test content");
+        assert!(first.join("proc").exists());
+
+        let second = tmp.path().join("c2/rootfs");
+        builder.prepare_from_image(snapshotter.as_ref(), "test:latest", &second).unwrap();
+        assert!(second.join("test.txt").exists());
+
+        let name = snapshotter::chain_id(None, &layer_path).unwrap();
+        let snapshots: Vec<_> = fs::read_dir(snapshotter.root()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(snapshots, [std::ffi::OsString::from(name)]);
+    }
+
+    /// A layer tar of `files`, given as (path, content) pairs
+    fn create_layer_tar(path: &Path, files: &[(&str, &str)]) {
+        let dir = TempDir::new().unwrap();
+        for (file, content) in files {
+            let file = dir.path().join(file);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, content).unwrap();
+        }
+        let status = Command::new("tar").arg("-cf").arg(path).arg("-C").arg(dir.path()).arg(".").status().unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_whiteouts_hide_lower_layers() {
+        use crate::container::image_store::{ImageManifest, ImageConfig};
+        use crate::container::snapshotter::{snapshotter_for, ProvisionMode, SnapshotterKind};
+
+        let tmp = TempDir::new().unwrap();
+        let image_store = ImageStore::new(tmp.path().join("images")).unwrap();
+        let base = tmp.path().join("images/blobs/base.tar");
+        let top = tmp.path().join("images/blobs/top.tar");
+        create_layer_tar(&base, &[
+            ("etc/motd", "welcome"),
+            ("etc/hostname", "box"),
+            ("var/cache/old", "stale"),
+            ("opt/app/bin", "app"),
+        ]);
+        create_layer_tar(&top, &[
+            ("etc/.wh.motd", ""),
+            ("var/cache/.wh..wh..opq", ""),
+            ("var/cache/new", "fresh"),
+            (".wh.opt", ""),
+        ]);
+        image_store.save_manifest(&ImageManifest {
+            reference: "layered:latest".to_string(),
+            parent: None,
+            layers: vec![base.to_string_lossy().to_string(), top.to_string_lossy().to_string()],
+            config: ImageConfig {
+                cmd: None,
+                env: None,
+                working_dir: None,
+                exposed_ports: None,
+                stop_signal: None,
+                labels: None,
+            },
+            annotations: None,
+            repo_digest: None,
+        }).unwrap();
+
+        let builder = RootfsBuilder::new(&image_store);
+        let snapshotter = snapshotter_for(SnapshotterKind::Dir, tmp.path(), ProvisionMode::Auto);
+        let built = tmp.path().join("built");
+        let prepared = tmp.path().join("c1/rootfs");
+        builder.build_from_image("layered:latest", &built).unwrap();
+        builder.prepare_from_image(snapshotter.as_ref(), "layered:latest", &prepared).unwrap();
+
+        for rootfs in [built, prepared] {
+            assert!(!rootfs.join("etc/motd").exists());
+            assert!(!rootfs.join("etc/.wh.motd").exists());
+            assert_eq!(fs::read_to_string(rootfs.join("etc/hostname")).unwrap(), "box");
+            assert!(!rootfs.join("var/cache/old").exists());
+            assert_eq!(fs::read_to_string(rootfs.join("var/cache/new")).unwrap(), "fresh");
+            assert!(!rootfs.join("var/cache/.wh..wh..opq").exists());
+            assert!(!rootfs.join("opt").exists());
+        }
+    }
+
 }
@@ -0,0 +1,290 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use tracing::{debug, info, warn};
+
+use crate::container::{PortMapping, Protocol};
+use crate::error::{CuboError, Result};
+
+/// File in the container bundle holding the pid of a long-running network helper
+const HELPER_PID_FILE: &str = "network-helper.pid";
+
+/// slirp4netns API socket, used to add port forwards after startup
+const SLIRP_API_SOCKET: &str = "slirp4netns.sock";
+
+/// Usermode network stacks that can give a rootless container egress
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsermodeDriver {
+    Pasta(PathBuf),
+    Slirp4netns(PathBuf),
+}
+
+impl UsermodeDriver {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UsermodeDriver::Pasta(_) => "pasta",
+            UsermodeDriver::Slirp4netns(_) => "slirp4netns",
+        }
+    }
+}
+
+/// Look for pasta first (faster, native port forwarding), then slirp4netns
+pub fn detect_driver() -> Option<UsermodeDriver> {
+    let path = std::env::var("PATH").unwrap_or_default();
+    if let Some(pasta) = find_in_path("pasta", &path) {
+        return Some(UsermodeDriver::Pasta(pasta));
+    }
+    find_in_path("slirp4netns", &path).map(UsermodeDriver::Slirp4netns)
+}
+
+pub fn find_in_path(binary: &str, path_var: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    path_var
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(dir).join(binary))
+        .find(|candidate| {
+            fs::metadata(candidate)
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+pub fn missing_driver_message() -> String {
+    "Rootless bridge networking needs pasta (passt) or slirp4netns; neither was found in PATH. \
+     The container only has a loopback interface. Install one of them, or use --network host."
+        .to_string()
+}
+
+pub fn pasta_args(pid: u32, ports: &[PortMapping]) -> Vec<String> {
+    let mut args = vec!["--config-net".to_string(), "--quiet".to_string()];
+    for port in ports {
+        let flag = match port.protocol {
+            Protocol::Tcp => "-t",
+            Protocol::Udp => "-u",
+        };
+        let spec = match port.host_ip {
+            Some(ref ip) => format!("{}/{}:{}", ip, port.host_port, port.container_port),
+            None => format!("{}:{}", port.host_port, port.container_port),
+        };
+        args.push(flag.to_string());
+        args.push(spec);
+    }
+    args.push(pid.to_string());
+    args
+}
+
+pub fn slirp4netns_args(pid: u32, api_socket: &Path) -> Vec<String> {
+    vec![
+        "--configure".to_string(),
+        "--mtu=65520".to_string(),
+        "--disable-host-loopback".to_string(),
+        format!("--api-socket={}", api_socket.display()),
+        pid.to_string(),
+        "tap0".to_string(),
+    ]
+}
+
+/// JSON request asking slirp4netns to forward a host port into the container
+pub fn slirp_hostfwd_request(port: &PortMapping) -> serde_json::Value {
+    serde_json::json!({
+        "execute": "add_hostfwd",
+        "arguments": {
+            "proto": port.protocol.to_string(),
+            "host_addr": port.host_ip.clone().unwrap_or_else(|| "0.0.0.0".to_string()),
+            "host_port": port.host_port,
+            "guest_port": port.container_port,
+        }
+    })
+}
+
+/// Connect the network namespace of `pid` to the outside world and set up port forwarding.
+pub fn start(driver: &UsermodeDriver, pid: u32, ports: &[PortMapping], bundle: &Path) -> Result<()> {
+    match driver {
+        UsermodeDriver::Pasta(binary) => {
+            // pasta daemonizes once the namespace is configured and exits with it
+            let output = Command::new(binary)
+                .args(pasta_args(pid, ports))
+                .output()
+                .map_err(|e| CuboError::NetworkError(format!("Failed to run pasta: {}", e)))?;
+            if !output.status.success() {
+                return Err(CuboError::NetworkError(format!(
+                    "pasta failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+        }
+        UsermodeDriver::Slirp4netns(binary) => {
+            let api_socket = bundle.join(SLIRP_API_SOCKET);
+            let _ = fs::remove_file(&api_socket);
+
+            let child = Command::new(binary)
+                .args(slirp4netns_args(pid, &api_socket))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| CuboError::NetworkError(format!("Failed to run slirp4netns: {}", e)))?;
+            fs::write(bundle.join(HELPER_PID_FILE), child.id().to_string())
+                .map_err(|e| CuboError::NetworkError(format!("Failed to record slirp4netns pid: {}", e)))?;
+
+            wait_for_socket(&api_socket, Duration::from_secs(5))?;
+            for port in ports {
+                slirp_api_call(&api_socket, &slirp_hostfwd_request(port))?;
+            }
+        }
+    }
+
+    info!("Rootless networking for pid {} provided by {}", pid, driver.name());
+    Ok(())
+}
+
+fn wait_for_socket(path: &Path, timeout: Duration) -> Result<()> {
+    let start = Instant::now();
+    while !path.exists() {
+        if start.elapsed() > timeout {
+            return Err(CuboError::NetworkError(format!(
+                "slirp4netns did not create its API socket {:?}",
+                path
+            )));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+fn slirp_api_call(socket: &Path, request: &serde_json::Value) -> Result<()> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| CuboError::NetworkError(format!("Failed to connect to slirp4netns: {}", e)))?;
+    stream
+        .write_all(request.to_string().as_bytes())
+        .map_err(|e| CuboError::NetworkError(format!("slirp4netns request failed: {}", e)))?;
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(|e| CuboError::NetworkError(format!("slirp4netns response failed: {}", e)))?;
+    debug!("slirp4netns: {}", response.trim());
+
+    if response.contains("\"error\"") {
+        return Err(CuboError::NetworkError(format!("slirp4netns: {}", response.trim())));
+    }
+    Ok(())
+}
+
+/// Terminate the network helper recorded in the bundle, if any
+pub fn stop_helper(bundle: &Path) {
+    let pid_file = bundle.join(HELPER_PID_FILE);
+    if let Ok(content) = fs::read_to_string(&pid_file) {
+        if let Ok(pid) = content.trim().parse::<i32>() {
+            if let Err(e) = kill(Pid::from_raw(pid), Signal::SIGTERM) {
+                debug!("Network helper {} already gone: {}", pid, e);
+            }
+        } else {
+            warn!("Ignoring malformed helper pid file {:?}", pid_file);
+        }
+        let _ = fs::remove_file(&pid_file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn make_executable(path: &Path) {
+        fs::write(path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_find_in_path() {
+        let temp = TempDir::new().unwrap();
+        let bin = temp.path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        make_executable(&bin.join("pasta"));
+
+        let path_var = format!("/nonexistent:{}", bin.display());
+        assert_eq!(find_in_path("pasta", &path_var), Some(bin.join("pasta")));
+        assert_eq!(find_in_path("slirp4netns", &path_var), None);
+    }
+
+    #[test]
+    fn test_find_in_path_skips_non_executable() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("pasta"), "").unwrap();
+        assert_eq!(find_in_path("pasta", &temp.path().display().to_string()), None);
+    }
+
+    #[test]
+    fn test_pasta_args_with_ports() {
+        let ports = vec![
+            PortMapping::tcp(8080, 80),
+            PortMapping::udp(5353, 53).with_host_ip("127.0.0.1".to_string()),
+        ];
+        let args = pasta_args(1234, &ports);
+        assert_eq!(
+            args,
+            vec!["--config-net", "--quiet", "-t", "8080:80", "-u", "127.0.0.1/5353:53", "1234"]
+        );
+    }
+
+    #[test]
+    fn test_slirp4netns_args() {
+        let args = slirp4netns_args(42, Path::new("/run/x.sock"));
+        assert!(args.contains(&"--configure".to_string()));
+        assert!(args.contains(&"--api-socket=/run/x.sock".to_string()));
+        assert_eq!(args[args.len() - 2], "42");
+        assert_eq!(args[args.len() - 1], "tap0");
+    }
+
+    #[test]
+    fn test_slirp_hostfwd_request() {
+        let request = slirp_hostfwd_request(&PortMapping::tcp(8080, 80));
+        assert_eq!(request["execute"], "add_hostfwd");
+        assert_eq!(request["arguments"]["proto"], "tcp");
+        assert_eq!(request["arguments"]["host_addr"], "0.0.0.0");
+        assert_eq!(request["arguments"]["host_port"], 8080);
+        assert_eq!(request["arguments"]["guest_port"], 80);
+    }
+
+    #[test]
+    fn test_driver_name() {
+        assert_eq!(UsermodeDriver::Pasta(PathBuf::from("/usr/bin/pasta")).name(), "pasta");
+        assert_eq!(UsermodeDriver::Slirp4netns(PathBuf::from("/usr/bin/slirp4netns")).name(), "slirp4netns");
+    }
+
+    #[test]
+    fn test_missing_driver_message_mentions_both() {
+        let msg = missing_driver_message();
+        assert!(msg.contains("pasta"));
+        assert!(msg.contains("slirp4netns"));
+    }
+
+    #[test]
+    fn test_stop_helper_without_pid_file() {
+        let temp = TempDir::new().unwrap();
+        stop_helper(temp.path());
+    }
+
+    #[test]
+    fn test_stop_helper_removes_pid_file() {
+        let temp = TempDir::new().unwrap();
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        fs::write(temp.path().join(HELPER_PID_FILE), child.id().to_string()).unwrap();
+
+        stop_helper(temp.path());
+        assert!(!temp.path().join(HELPER_PID_FILE).exists());
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+    }
+}
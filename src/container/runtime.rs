@@ -6,24 +6,48 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use nix::sys::signal::{kill, Signal};
-use nix::unistd::{chdir, execv, fork, setgid, sethostname, setuid, ForkResult, Gid, Pid, Uid};
+use nix::unistd::{chdir, execv, fork, setgid, setgroups, sethostname, setuid, ForkResult, Gid, Pid, Uid};
 use nix::sys::wait::WaitStatus as NixWaitStatus;
 use nix::sys::wait::waitpid as nix_waitpid;
+use nix::sys::wait::{waitid, Id, WaitPidFlag};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 use tracing::{debug, error, info, warn};
 
-use super::{Container, ContainerStatus, NetworkMode};
+use super::{Container, ContainerStatus, HostEntry, MountType, NetworkMode, Propagation, VolumeMount};
 use crate::container::container_store as store;
 use crate::container::image_store::ImageStore;
 use crate::container::rootfs::RootfsBuilder;
-use crate::error::{CuboError, Result};
+use crate::error::{CuboError, Result, EXIT_FAILURE};
 use crate::container::namespace as ns;
+use crate::container::cgroup::{self, CgroupDriver, CgroupManager, ResourceUpdate};
+use crate::container::{apparmor, checkpoint, dns, idmap, init, interpreter, landlock, logging, reference, sched, stats, selinux, sysctl, ulimit, user};
+use crate::container::events::{EventBus, EventKind};
+use crate::container::user::ExecUser;
+use crate::container::signal::{self as signals, parse_signal};
+use crate::container::network::{self, Endpoint, Network, NetworkStore};
+use crate::container::rootless_net::{self, UsermodeDriver};
+use crate::container::volume::VolumeStore;
+use crate::container::admission::{self, AdmissionHook};
+use crate::container::secure_join::{fd_path, open_in_root, secure_join};
+use crate::container::executor::{executor_for, Executor, ExecutorKind};
+use crate::container::snapshotter::{self, snapshotter_for, ProvisionMode, Snapshotter, SnapshotterKind};
+use crate::container::registry::PullPolicy;
+use crate::container::quota::{self, QuotaBackend, LOOP_IMAGE};
+use crate::container::lock::ContainerLock;
+use crate::container::audit::{AuditLog, AuditOperation, AuditRecord};
+use crate::container::api::{ContainerStore, FsContainerStore};
+use crate::container::LogDriverKind;
+use crate::config::{self, ConfigFile, DefaultLimits, ProxyConfig};
 
 pub struct ContainerRuntime {
     containers: Arc<Mutex<HashMap<String, Container>>>,
+    store: Arc<dyn ContainerStore>,
     root_dir: PathBuf,
     config: RuntimeConfig,
+    events: EventBus,
+    audit: AuditLog,
+    admission: Vec<Arc<dyn AdmissionHook>>,
 }
 
 #[derive(Debug, Clone)]
@@ -32,6 +56,106 @@ pub struct RuntimeConfig {
     pub default_network_mode: NetworkMode,
     pub debug: bool,
     pub container_timeout: u64,
+    pub cgroup_root: PathBuf,
+    /// Who creates container cgroups: cubo itself, or systemd as transient scopes
+    pub cgroup_driver: CgroupDriver,
+    /// Backend for containers created from now on; existing ones keep theirs
+    pub executor: ExecutorKind,
+    /// Snapshotter for the rootfs of containers created from now on
+    pub snapshotter: SnapshotterKind,
+    /// How the `dir` snapshotter copies image trees into new snapshots
+    pub provision: ProvisionMode,
+    /// Range networks created without a subnet get theirs from
+    pub network_pool: Option<String>,
+    /// Mirrors tried before each registry, keyed by registry host
+    pub registry_mirrors: HashMap<String, Vec<String>>,
+    /// When pulls and builds fetch images from a registry
+    pub pull_policy: PullPolicy,
+    /// Limits of containers created without their own
+    pub default_limits: DefaultLimits,
+    /// Log driver of containers run without --log-driver
+    pub log_driver: Option<LogDriverKind>,
+    /// --log-opt values applied before the container's own
+    pub log_opts: Vec<String>,
+    pub proxy: ProxyConfig,
+    /// How long exited containers are kept before garbage collection removes them
+    pub exited_ttl: Option<chrono::TimeDelta>,
+    /// How often cubod samples the resource usage of running containers; never when unset
+    pub stats_interval: Option<chrono::TimeDelta>,
+    /// Samples kept in each container's stats history
+    pub stats_history: usize,
+}
+
+/// Pipes used to hold the container process until the parent has finished its part of the
+/// setup (id mapping, network wiring)
+struct SyncPipes {
+    ready_r: fs::File,
+    ready_w: fs::File,
+    go_r: fs::File,
+    go_w: fs::File,
+}
+
+impl SyncPipes {
+    fn new() -> Result<Self> {
+        let (ready_r, ready_w) = nix::unistd::pipe()
+            .map_err(|e| CuboError::SystemError(format!("Failed to create sync pipe: {}", e)))?;
+        let (go_r, go_w) = nix::unistd::pipe()
+            .map_err(|e| CuboError::SystemError(format!("Failed to create sync pipe: {}", e)))?;
+        Ok(Self {
+            ready_r: ready_r.into(),
+            ready_w: ready_w.into(),
+            go_r: go_r.into(),
+            go_w: go_w.into(),
+        })
+    }
+
+    /// The parent's ends, right after fork. Closing the child's ends here is what lets the
+    /// parent see EOF when the child exits before the handshake.
+    fn into_parent(self) -> ParentSync {
+        ParentSync { ready_r: self.ready_r, go_w: self.go_w }
+    }
+
+    /// The child's ends, right after fork
+    fn into_child(self) -> ChildSync {
+        ChildSync { ready_w: self.ready_w, go_r: self.go_r }
+    }
+}
+
+struct ParentSync {
+    ready_r: fs::File,
+    go_w: fs::File,
+}
+
+impl ParentSync {
+    /// Wait for the child to be ready, run `step`, then release the child
+    async fn handshake(self, step: impl FnOnce() -> Result<()>) -> Result<()> {
+        use std::io::{Read, Write};
+
+        let ParentSync { mut ready_r, mut go_w } = self;
+        tokio::task::spawn_blocking(move || ready_r.read_exact(&mut [0u8; 1]))
+            .await
+            .map_err(|e| CuboError::SystemError(format!("Failed to wait for container setup: {}", e)))?
+            .map_err(|e| CuboError::SystemError(format!("Container exited during setup: {}", e)))?;
+        step()?;
+        go_w.write_all(&[1])
+            .map_err(|e| CuboError::SystemError(format!("Failed to release container: {}", e)))
+    }
+}
+
+struct ChildSync {
+    ready_w: fs::File,
+    go_r: fs::File,
+}
+
+impl ChildSync {
+    /// Tell the parent we're ready and wait until it's done
+    fn handshake(self) -> std::io::Result<()> {
+        use std::io::{Read, Write};
+
+        let ChildSync { mut ready_w, mut go_r } = self;
+        ready_w.write_all(&[1])?;
+        go_r.read_exact(&mut [0u8; 1])
+    }
 }
 
 #[derive(Debug)]
@@ -43,71 +167,172 @@ pub struct ExecutionContext {
 
 impl ContainerRuntime {
     pub fn new(config: RuntimeConfig) -> Result<Self> {
+        let store = Arc::new(FsContainerStore::new(&config.root_dir));
+        Self::with_store(config, store)
+    }
+
+    /// A runtime keeping its containers in `store` instead of the bundles under the root
+    /// directory, which still holds their rootfs, logs and locks
+    pub fn with_store(config: RuntimeConfig, store: Arc<dyn ContainerStore>) -> Result<Self> {
         if !config.root_dir.exists() {
             fs::create_dir_all(&config.root_dir)
                 .map_err(|e| CuboError::SystemError(format!("Failed to create root directory: {}", e)))?;
         }
 
-        let mut loaded: HashMap<String, Container> = store::load_all(&config.root_dir)?;
+        let mut loaded: HashMap<String, Container> = store.load_all()?;
 
         for container in loaded.values_mut() {
-            if matches!(container.status, ContainerStatus::Running) {
-                if !store::pid_is_alive(container.pid) {
-                    container.update_status(ContainerStatus::Stopped);
-                    let _ = store::save_state(&config.root_dir, container);
-                }
+            if container.is_running() && !process_alive(&config, container) {
+                mark_dead(container, &config.root_dir);
+                let _ = store.save_config(container);
+                let _ = store.save_state(container);
             }
         }
 
         Ok(Self {
             containers: Arc::new(Mutex::new(loaded)),
+            store,
             root_dir: config.root_dir.clone(),
+            audit: AuditLog::new(&config.root_dir),
             config,
+            events: EventBus::default(),
+            admission: Vec::new(),
         })
     }
 
+    /// Consult `hook` before pulling images and creating containers, after the hooks added
+    /// before it
+    pub fn with_admission_hook(mut self, hook: Arc<dyn AdmissionHook>) -> Self {
+        self.admission.push(hook);
+        self
+    }
+
+    /// Check with the admission hooks that `reference` may be pulled
+    pub fn admit_pull(&self, reference: &str) -> Result<()> {
+        admission::admit_pull(&self.admission, reference)
+    }
+
+    /// Lifecycle events of the containers managed by this runtime
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn root_dir(&self) -> &Path {
+        &self.root_dir
+    }
+
+    pub fn config(&self) -> &RuntimeConfig {
+        &self.config
+    }
+
+    fn executor(&self, container: &Container) -> Box<dyn Executor> {
+        executor_for(&container.executor, &self.root_dir)
+    }
+
+    fn snapshotter(&self, container: &Container) -> Box<dyn Snapshotter> {
+        snapshotter_for(container.snapshotter, &self.root_dir, self.config.provision)
+    }
+
+    /// Lock a container against other cubo processes, then pick up what they changed in its
+    /// bundle since it was loaded
+    async fn lock_container(&self, container_id: &str) -> Result<ContainerLock> {
+        let (root_dir, id) = (self.root_dir.clone(), container_id.to_string());
+        let lock = tokio::task::spawn_blocking(move || ContainerLock::acquire(&root_dir, &id))
+            .await
+            .map_err(|e| CuboError::SystemError(format!("Failed to lock container {}: {}", container_id, e)))??;
+
+        let fresh = self.store.load_one(container_id)?;
+        let mut containers = self.containers.lock().await;
+        match fresh {
+            Some(mut fresh) => {
+                // Exit codes and times are only saved with the config; keep ours while the
+                // status hasn't changed behind our back
+                if let Some(current) = containers.get(container_id).filter(|c| c.status == fresh.status) {
+                    fresh.started_at = current.started_at;
+                    fresh.finished_at = current.finished_at;
+                    fresh.exit_code = current.exit_code;
+                }
+                containers.insert(container_id.to_string(), fresh);
+            }
+            None => {
+                containers.remove(container_id);
+            }
+        }
+        Ok(lock)
+    }
+
+    /// Record a create/start/stop/rm of a container of `image` in the audit log
+    fn audit<T>(&self, operation: AuditOperation, container_id: &str, image: Option<&str>, result: &Result<T>) {
+        let mut record = AuditRecord::new(operation, result).with_container(container_id);
+        if let Some(image) = image {
+            record = record.with_image(image);
+        }
+        self.audit.record(record);
+    }
+
+    async fn blueprint_of(&self, container_id: &str) -> Option<String> {
+        self.containers.lock().await.get(container_id).map(|c| c.blueprint.clone())
+    }
+
     pub async fn create_container(&self, container: Container) -> Result<String> {
+        let (container_id, image) = (container.id.clone(), container.blueprint.clone());
+        let result = self.create(container).await;
+        self.audit(AuditOperation::Create, &container_id, Some(&image), &result);
+        result
+    }
+
+    async fn create(&self, mut container: Container) -> Result<String> {
+        // Shown by ps and inspect as the image was stored
+        container.blueprint = reference::normalize(&container.blueprint)?;
+        container.executor = self.config.executor.clone();
+        container.snapshotter = self.config.snapshotter;
+        self.config.default_limits.apply_to(&mut container.config);
+        admission::admit_run(&self.admission, &mut container)?;
         let container_id = container.id.clone();
+        if let Some(ref name) = container.name {
+            reference::validate_container_name(name)?;
+        }
+        let _lock = self.lock_container(&container_id).await?;
+        network::PortRegistry::of(self.containers.lock().await.values(), &container_id).check(&container.config.ports)?;
+
+        if let Some(ref sandbox_id) = container.config.sandbox {
+            if !self.containers.lock().await.contains_key(sandbox_id) {
+                return Err(CuboError::ContainerNotFound(sandbox_id.clone()));
+            }
+        }
+
+        if let NetworkMode::Custom(ref name) = container.config.network_mode {
+            self.network_store()?.connect(
+                name,
+                &container_id,
+                container.config.ip_address.as_deref(),
+                container.config.mac_address.as_deref(),
+            )?;
+        }
 
         let container_dir = self.root_dir.join(&container_id);
         fs::create_dir_all(&container_dir)
             .map_err(|e| CuboError::SystemError(format!("Failed to create container directory: {}", e)))?;
 
         let rootfs_dir = container_dir.join("rootfs");
-        fs::create_dir_all(&rootfs_dir)
-            .map_err(|e| CuboError::SystemError(format!("Failed to create rootfs directory: {}", e)))?;
-
         self.setup_rootfs(&container, &rootfs_dir)?;
 
-        store::save_config(&self.root_dir, &container)?;
-        store::save_state(&self.root_dir, &container)?;
+        self.store.save_config(&container)?;
+        self.store.save_state(&container)?;
 
         let mut containers = self.containers.lock().await;
         containers.insert(container_id.clone(), container);
 
         info!("Created container: {}", container_id);
+        self.events.publish(EventKind::Create, &container_id);
         Ok(container_id)
     }
 
     pub async fn start_container(&self, container_id: &str, detach: bool) -> Result<()> {
-        let mut containers = self.containers.lock().await;
-        let container = containers.get_mut(container_id)
-            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
-
-        if container.is_running() {
-            return Err(CuboError::SystemError("Container is already running".to_string()));
-        }
-
-        container.update_status(ContainerStatus::Running);
-        let container_snapshot = container.clone();
-        drop(containers);
-        store::save_state(&self.root_dir, &container_snapshot)?;
-
-        let exec_ctx = ExecutionContext {
-            container: container_snapshot.clone(),
-            rootfs_path: self.root_dir.join(container_id).join("rootfs"),
-            detach,
-        };
+        let image = self.blueprint_of(container_id).await;
+        let prepared = self.prepare_start(container_id, detach).await;
+        self.audit(AuditOperation::Start, container_id, image.as_deref(), &prepared);
+        let exec_ctx = prepared?;
 
         let container_id_clone = container_id.to_string();
         let runtime = self.clone();
@@ -126,41 +351,151 @@ impl ContainerRuntime {
         Ok(())
     }
 
+    /// Mount the rootfs and mark the container running, up to spawning its process
+    async fn prepare_start(&self, container_id: &str, detach: bool) -> Result<ExecutionContext> {
+        // Only held while the state changes, the container itself runs unlocked
+        let lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let registry = network::PortRegistry::of(containers.values(), container_id);
+        let container = containers.get_mut(container_id)
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
+
+        if container.is_running() {
+            return Err(CuboError::SystemError("Container is already running".to_string()));
+        }
+
+        // Another container may have taken a port since this one was created. Ephemeral host
+        // ports are picked anew on every start.
+        registry.check(&container.config.ports)?;
+        container.published_ports = network::publish_ports(&container.config.ports, &registry)?;
+
+        let mounts = self.mount_volumes(container)?;
+        let labels = selinux::container_labels(container);
+        if let Some(ref labels) = labels {
+            selinux::relabel_volumes(&mounts, labels)?;
+        }
+        if container.config.apparmor_profile.is_none() {
+            apparmor::ensure_default_profile(&self.root_dir.join("apparmor"))?;
+        }
+        let rootfs_path = self.root_dir.join(container_id).join("rootfs");
+        let snapshotter = self.snapshotter(container);
+        quota::ensure_loopback(&self.root_dir.join(container_id).join(LOOP_IMAGE), &snapshotter.storage_dir(&rootfs_path))?;
+        snapshotter::mount_snapshot(snapshotter.as_ref(), &rootfs_path, labels.as_ref().map(|l| l.mount.as_str()))?;
+
+        if container.status == ContainerStatus::Restarting {
+            container.started_at = None;
+        }
+        // The pid of an earlier run is gone, the new one is set once the process is forked
+        container.pid = None;
+        container.update_status(ContainerStatus::Running);
+        let mut container_snapshot = container.clone();
+        drop(containers);
+        self.store.save_config(&container_snapshot)?;
+        self.store.save_state(&container_snapshot)?;
+        drop(lock);
+        self.events.publish(EventKind::Start, container_id);
+        // Only the process sees the named volumes as the bind mounts of their mountpoints
+        container_snapshot.config.volume_mounts = mounts;
+
+        Ok(ExecutionContext {
+            container: container_snapshot,
+            rootfs_path,
+            detach,
+        })
+    }
+
     pub async fn stop_container(&self, container_id: &str, timeout: Option<Duration>) -> Result<()> {
+        let image = self.blueprint_of(container_id).await;
+        let result = self.stop(container_id, timeout).await;
+        self.audit(AuditOperation::Stop, container_id, image.as_deref(), &result);
+        result
+    }
+
+    async fn stop(&self, container_id: &str, timeout: Option<Duration>) -> Result<()> {
+        let _lock = self.lock_container(container_id).await?;
         let mut containers = self.containers.lock().await;
         let container = containers.get_mut(container_id)
             .ok_or_else(|| CuboError::ContainerNotRunning(container_id.to_string()))?;
 
+        if container.status == ContainerStatus::Restarting {
+            // Waiting out the supervisor's backoff, there is no process to signal
+            container.update_status(ContainerStatus::Stopped);
+            let snapshot = container.clone();
+            drop(containers);
+            self.store.save_config(&snapshot)?;
+            self.store.save_state(&snapshot)?;
+            self.events.publish(EventKind::Stop, container_id);
+            return Ok(());
+        }
         if !container.is_running() {
             return Ok(());
         }
 
         if let Some(pid) = container.pid {
             let timeout = timeout.unwrap_or(Duration::from_secs(10));
+            let stop_signal = Self::stop_signal(container);
+            let executor = self.executor(container);
 
-            if let Err(e) = kill(Pid::from_raw(pid as i32), Signal::SIGTERM) {
-                warn!("Failed to send SIGTERM to container {}: {}", container_id, e);
+            if let Err(e) = executor.signal(container, stop_signal) {
+                warn!("{}", e);
             }
 
             sleep(timeout).await;
 
-            if let Err(e) = kill(Pid::from_raw(pid as i32), Signal::SIGKILL) {
-                warn!("Failed to send SIGKILL to container {}: {}", container_id, e);
+            let mut killed_by = stop_signal;
+            if store::pid_is_alive(Some(pid)) {
+                if let Err(e) = executor.signal(container, Signal::SIGKILL) {
+                    warn!("{}", e);
+                }
+                killed_by = Signal::SIGKILL;
             }
+            // The waiting process may have left the real exit code, if the signal was handled
+            let bundle = self.root_dir.join(container_id);
+            container.set_exit_code(store::read_exit_code(&bundle).unwrap_or(128 + killed_by as i32));
         }
 
+        rootless_net::stop_helper(&self.root_dir.join(container_id));
+        self.unmount_volumes(container);
+
         container.update_status(ContainerStatus::Stopped);
         let snapshot = container.clone();
         info!("Stopped container: {}", container_id);
         drop(containers);
-        store::save_state(&self.root_dir, &snapshot)?;
+        self.store.save_config(&snapshot)?;
+        self.store.save_state(&snapshot)?;
+        self.events.publish(EventKind::Stop, container_id);
         Ok(())
     }
 
+    /// Replace a container with a new one of the same name and configuration, created from
+    /// the current content of its image. The new one is left created; returns its ID.
+    pub async fn recreate_container(&self, container_id: &str) -> Result<String> {
+        let old = self.get_container(container_id).await?;
+        self.stop_container(container_id, None).await?;
+        self.remove_container(container_id, true).await?;
+
+        let mut container = Container::new(old.blueprint, old.command);
+        container.name = old.name;
+        container.config = old.config;
+        container.executor = old.executor;
+        container.snapshotter = old.snapshotter;
+        self.create_container(container).await
+    }
+
     pub async fn remove_container(&self, container_id: &str, force: bool) -> Result<()> {
+        let image = self.blueprint_of(container_id).await;
+        let result = self.remove(container_id, force).await;
+        self.audit(AuditOperation::Remove, container_id, image.as_deref(), &result);
+        result
+    }
+
+    async fn remove(&self, container_id: &str, force: bool) -> Result<()> {
+        let mut lock = self.lock_container(container_id).await?;
         let mut containers = self.containers.lock().await;
         let container = containers.get(container_id)
             .ok_or_else(|| CuboError::ContainerNotRunning(container_id.to_string()))?;
+        let networks = Self::attached_networks(container);
+        let removed = container.clone();
 
         if container.is_running() && !force {
             return Err(CuboError::SystemError("Container is running. Use --force to remove".to_string()));
@@ -168,23 +503,327 @@ impl ContainerRuntime {
 
         if container.is_running() {
             drop(containers);
+            drop(lock);
             self.stop_container(container_id, Some(Duration::from_secs(5))).await?;
+            lock = self.lock_container(container_id).await?;
             containers = self.containers.lock().await;
         }
 
+        if let Err(e) = self.executor(&removed).delete(&removed) {
+            warn!("Failed to delete container {} from its executor: {}", container_id, e);
+        }
+
+        if let Err(e) = self.config.cgroup(container_id).destroy() {
+            warn!("Failed to remove cgroup for container {}: {}", container_id, e);
+        }
+
+        for name in &networks {
+            if let Err(e) = self.release_network(name, container_id) {
+                warn!("Failed to disconnect container {} from network {}: {}", container_id, name, e);
+            }
+        }
+
         let container_dir = self.root_dir.join(container_id);
+        rootless_net::stop_helper(&container_dir);
+        self.unmount_volumes(&removed);
+        let snapshotter = self.snapshotter(&removed);
+        let rootfs = container_dir.join("rootfs");
+        if let Err(e) = quota::release_loopback(&snapshotter.storage_dir(&rootfs)) {
+            warn!("Failed to unmount the rootfs quota of container {}: {}", container_id, e);
+        }
+        if let Err(e) = snapshotter.remove(&rootfs) {
+            warn!("Failed to remove the rootfs snapshot of container {}: {}", container_id, e);
+        }
+        self.store.remove(container_id)?;
         if container_dir.exists() {
             fs::remove_dir_all(&container_dir)
                 .map_err(|e| CuboError::SystemError(format!("Failed to remove container directory: {}", e)))?;
         }
 
         containers.remove(container_id);
+        drop(lock);
 
         info!("Removed container: {}", container_id);
+        self.events.publish(EventKind::Remove, container_id);
+        Ok(())
+    }
+
+    /// Connect a container to another user-defined network. A running container gets the
+    /// new interface right away; the hosts files of the network's members are refreshed.
+    pub async fn connect_network(
+        &self,
+        container_id: &str,
+        name: &str,
+        ip: Option<&str>,
+        mac: Option<&str>,
+    ) -> Result<Endpoint> {
+        let _lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let container = containers.get_mut(container_id)
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
+
+        if matches!(container.config.network_mode, NetworkMode::Host | NetworkMode::None) {
+            return Err(CuboError::NetworkError(format!(
+                "Container {} uses the {} network and can't join other networks",
+                container_id, container.config.network_mode
+            )));
+        }
+
+        let attached = Self::attached_networks(container);
+        if attached.iter().any(|n| n == name) {
+            return Err(CuboError::NetworkError(format!(
+                "Container {} is already connected to network {}",
+                container_id, name
+            )));
+        }
+
+        let store = self.network_store()?;
+        let used: Vec<String> = attached
+            .iter()
+            .filter_map(|n| store.get(n).ok())
+            .filter_map(|net| net.endpoints.get(container_id).map(|e| e.interface.clone()))
+            .collect();
+        let endpoint = store.connect_as(name, container_id, &network::free_interface(&used), ip, mac)?;
+
+        if let (true, Some(pid)) = (container.is_running(), container.pid) {
+            let wired = store.get(name).and_then(|net| network::attach_endpoint(&net, &endpoint, pid, false));
+            if let Err(e) = wired {
+                let _ = store.disconnect(name, container_id);
+                return Err(e);
+            }
+        }
+
+        container.config.networks.push(name.to_string());
+        let snapshot = container.clone();
+        drop(containers);
+        self.store.save_config(&snapshot)?;
+
+        self.refresh_hosts(name, container_id).await;
+        info!("Connected container {} to network {} ({})", container_id, name, endpoint.ip);
+        Ok(endpoint)
+    }
+
+    /// Disconnect a container from a network it joined with `connect_network`
+    pub async fn disconnect_network(&self, container_id: &str, name: &str) -> Result<()> {
+        let _lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let container = containers.get_mut(container_id)
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
+
+        if container.config.network_mode == NetworkMode::Custom(name.to_string()) {
+            return Err(CuboError::NetworkError(format!(
+                "Network {} is the primary network of container {}; remove the container instead",
+                name, container_id
+            )));
+        }
+        if !container.config.networks.iter().any(|n| n == name) {
+            return Err(CuboError::NetworkError(format!(
+                "Container {} is not connected to network {}",
+                container_id, name
+            )));
+        }
+
+        if container.is_running() {
+            self.release_network(name, container_id)?;
+        } else {
+            self.network_store()?.disconnect(name, container_id)?;
+        }
+
+        container.config.networks.retain(|n| n != name);
+        let snapshot = container.clone();
+        drop(containers);
+        self.store.save_config(&snapshot)?;
+
+        self.refresh_hosts(name, container_id).await;
+        info!("Disconnected container {} from network {}", container_id, name);
+        Ok(())
+    }
+
+    /// Change the resource limits of a container. A running container's cgroup is rewritten
+    /// in place; the new limits are saved to its config either way.
+    pub async fn update_container(&self, container_id: &str, update: &ResourceUpdate) -> Result<()> {
+        if update.is_empty() {
+            return Err(CuboError::InvalidConfiguration("No resource limits to update".to_string()));
+        }
+
+        let _lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let container = containers.get_mut(container_id)
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
+
+        if container.is_running() {
+            let manager = self.config.cgroup(container_id);
+            if !manager.exists() {
+                return Err(CuboError::CgroupError {
+                    path: manager.path().to_path_buf(),
+                    message: format!("Container {} runs without a cgroup; restart it to apply limits", container_id),
+                });
+            }
+            manager.update(update)?;
+        }
+
+        update.apply_to(&mut container.config);
+        let snapshot = container.clone();
+        drop(containers);
+        self.store.save_config(&snapshot)?;
+
+        info!("Updated resource limits of container {}", container_id);
+        self.events.publish(EventKind::Update, container_id);
+        Ok(())
+    }
+
+    /// Dump a running container with CRIU into its bundle. The container is stopped
+    /// afterwards unless `leave_running` is set.
+    pub async fn checkpoint_container(&self, container_id: &str, leave_running: bool) -> Result<()> {
+        let _lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let container = containers.get_mut(container_id)
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
+
+        let pid = match (container.is_running(), container.pid) {
+            (true, Some(pid)) => pid,
+            _ => return Err(CuboError::ContainerNotRunning(container_id.to_string())),
+        };
+        Self::require_root("checkpoint")?;
+        let criu = checkpoint::find_criu()?;
+
+        let bundle = self.root_dir.join(container_id);
+        let init_pid = checkpoint::container_init_pid(pid)?;
+        checkpoint::dump(&criu, &bundle, init_pid, leave_running)?;
+
+        if !leave_running {
+            rootless_net::stop_helper(&bundle);
+            container.update_status(ContainerStatus::Stopped);
+            let snapshot = container.clone();
+            drop(containers);
+            self.store.save_state(&snapshot)?;
+        }
+
+        info!("Checkpointed container {} to {:?}", container_id, checkpoint::checkpoint_dir(&bundle));
+        self.events.publish(EventKind::Checkpoint, container_id);
+        Ok(())
+    }
+
+    /// Bring a checkpointed container back with the same rootfs, addresses and cgroup
+    pub async fn restore_container(&self, container_id: &str) -> Result<()> {
+        let _lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let container = containers.get_mut(container_id)
+            .ok_or_else(|| CuboError::ContainerNotFound(container_id.to_string()))?;
+
+        if container.is_running() {
+            return Err(CuboError::ContainerAlreadyRunning(container_id.to_string()));
+        }
+        let bundle = self.root_dir.join(container_id);
+        if !checkpoint::has_checkpoint(&bundle) {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "Container {} has no checkpoint",
+                container_id
+            )));
+        }
+        Self::require_root("restore")?;
+        let criu = checkpoint::find_criu()?;
+
+        // CRIU recreates the veth pairs under their old host names; we plug them back in
+        let attachments = self.network_attachments(container)?;
+        let veth_pairs: Vec<(String, String)> = attachments
+            .iter()
+            .map(|(network, endpoint)| (endpoint.interface.clone(), network::veth_name(container_id, &network.id)))
+            .collect();
+
+        let pid = checkpoint::restore(&criu, &bundle, &veth_pairs)?;
+        for (network, _) in &attachments {
+            if let Err(e) = network::plug_host_veth(network, container_id) {
+                warn!("Failed to reconnect container {} to network {}: {}", container_id, network.name, e);
+            }
+        }
+
+        container.set_pid(pid);
+        container.exit_code = None;
+        container.finished_at = None;
+        container.update_status(ContainerStatus::Running);
+        let snapshot = container.clone();
+        drop(containers);
+        self.store.save_state(&snapshot)?;
+
+        info!("Restored container {} with PID {}", container_id, pid);
+        self.events.publish(EventKind::Restore, container_id);
+        Ok(())
+    }
+
+    fn require_root(action: &str) -> Result<()> {
+        if !nix::unistd::geteuid().is_root() {
+            return Err(CuboError::PermissionDenied(format!("{} requires root (CRIU)", action)));
+        }
         Ok(())
     }
 
+    /// Stop the running containers whose processes died without anyone recording it, and
+    /// publish a die event for each
+    async fn reconcile(&self) {
+        let stale: Vec<String> = self
+            .containers
+            .lock()
+            .await
+            .values()
+            .filter(|c| c.is_running() && !process_alive(&self.config, c))
+            .map(|c| c.id.clone())
+            .collect();
+
+        for container_id in stale {
+            let Ok(_lock) = self.lock_container(&container_id).await else {
+                continue;
+            };
+            let mut containers = self.containers.lock().await;
+            let Some(container) = containers
+                .get_mut(&container_id)
+                .filter(|c| c.is_running() && !process_alive(&self.config, c))
+            else {
+                continue;
+            };
+            mark_dead(container, &self.root_dir);
+            let snapshot = container.clone();
+            drop(containers);
+            if let Err(e) = self.store.save_config(&snapshot).and_then(|_| self.store.save_state(&snapshot)) {
+                warn!("Failed to save the state of container {}: {}", container_id, e);
+            }
+            info!("Container {} died without its exit being recorded", container_id);
+            self.events.publish(EventKind::Die, &container_id);
+        }
+    }
+
+    /// Remove the exited containers that finished before `until`, or all of them, returning
+    /// the IDs of those removed. One that can't be removed doesn't stop the others.
+    pub async fn prune_containers(&self, until: Option<chrono::DateTime<chrono::Utc>>) -> Result<Vec<String>> {
+        let expired: Vec<String> = self
+            .list_containers(true)
+            .await?
+            .into_iter()
+            .filter(|c| c.is_stopped())
+            .filter(|c| until.is_none_or(|until| c.finished_at.unwrap_or(c.created_at) < until))
+            .map(|c| c.id)
+            .collect();
+
+        let mut removed = Vec::new();
+        for container_id in expired {
+            match self.remove_container(&container_id, false).await {
+                Ok(()) => removed.push(container_id),
+                Err(e) => warn!("Failed to remove exited container {}: {}", container_id, e),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove the exited containers older than the configured `cleanup.exited_ttl`, if any
+    pub async fn collect_garbage(&self) -> Result<Vec<String>> {
+        match self.config.exited_ttl {
+            Some(ttl) => self.prune_containers(Some(chrono::Utc::now() - ttl)).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
     pub async fn list_containers(&self, all: bool) -> Result<Vec<Container>> {
+        self.reconcile().await;
         let containers = self.containers.lock().await;
         let mut result = Vec::new();
 
@@ -204,13 +843,132 @@ impl ContainerRuntime {
             .ok_or_else(|| CuboError::ContainerNotRunning(container_id.to_string()))
     }
 
+    /// The ID of the container `identifier` names: its full ID, its name or a prefix of its
+    /// ID no other container shares
+    pub async fn resolve(&self, identifier: &str) -> Result<String> {
+        resolve_identifier(&self.list_containers(true).await?, identifier)
+    }
+
+    /// A container as it is on disk, with what other cubo processes changed since it was loaded
+    pub async fn reload_container(&self, container_id: &str) -> Result<Container> {
+        let _lock = self.lock_container(container_id).await?;
+        self.get_container(container_id).await
+    }
+
+    /// Pid of a started container's process once it has one. Detached containers get
+    /// theirs shortly after `start_container` returns.
+    pub async fn wait_for_pid(&self, container_id: &str, timeout: Duration) -> Result<u32> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let container = self.get_container(container_id).await?;
+            if let Some(pid) = container.pid.filter(|_| container.is_running()) {
+                return Ok(pid);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(CuboError::ContainerNotRunning(container_id.to_string()));
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Wait for the process of a detached container to exit, returning its exit code. Only
+    /// the process that started the container can wait for it; anyone else (or an OCI
+    /// runtime's container) is polled until it is gone, without an exit code. The process
+    /// is left a zombie until `reap`, so `ps` doesn't take it for dead before the exit is
+    /// recorded.
+    pub async fn wait_for_exit(&self, pid: u32) -> Option<i32> {
+        let child = Pid::from_raw(pid as i32);
+        let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+        loop {
+            match waitid(Id::Pid(child), flags) {
+                Ok(NixWaitStatus::Exited(_, code)) => return Some(code),
+                Ok(NixWaitStatus::Signaled(_, signal, _)) => return Some(128 + signal as i32),
+                Ok(_) => {}
+                Err(_) if !store::pid_is_alive(Some(pid)) => return None,
+                Err(_) => {}
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Wait until a started container has stopped for good, riding out restarts, and return
+    /// its exit code
+    pub async fn wait_container(&self, container_id: &str) -> Result<i32> {
+        loop {
+            self.reconcile().await;
+            let container = self.reload_container(container_id).await?;
+            match container.status {
+                ContainerStatus::Running | ContainerStatus::Paused | ContainerStatus::Restarting => {}
+                ContainerStatus::Created => {
+                    return Err(CuboError::InvalidConfiguration(format!(
+                        "Container {} has not been started",
+                        container_id
+                    )))
+                }
+                _ => {
+                    return container.exit_code.filter(|code| *code >= 0).ok_or_else(|| {
+                        CuboError::SystemError(format!("The exit code of container {} is unknown", container_id))
+                    })
+                }
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Release the zombie left by `wait_for_exit`
+    pub fn reap(&self, pid: u32) {
+        let _ = nix_waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG));
+    }
+
+    /// Record that a supervised container exited and move it on as its restart policy says:
+    /// Restarting, with the restart counted, or Stopped. Returns the restart count when it
+    /// is to be started again, None when it was stopped or removed in the meantime.
+    pub async fn record_exit(&self, container_id: &str, exit_code: Option<i32>) -> Result<Option<u32>> {
+        let _lock = self.lock_container(container_id).await?;
+        let mut containers = self.containers.lock().await;
+        let Some(container) = containers.get_mut(container_id).filter(|c| c.is_running()) else {
+            return Ok(None);
+        };
+
+        container.exit_code = exit_code;
+        container.finished_at = Some(chrono::Utc::now());
+        let restart = container
+            .config
+            .restart_policy
+            .should_restart(exit_code, container.restart_count);
+        if restart {
+            container.restart_count += 1;
+            container.status = ContainerStatus::Restarting;
+        } else {
+            container.update_status(ContainerStatus::Stopped);
+        }
+        let snapshot = container.clone();
+        drop(containers);
+        self.unmount_volumes(&snapshot);
+        self.store.save_config(&snapshot)?;
+        self.store.save_state(&snapshot)?;
+        if !restart {
+            self.events.publish(EventKind::Stop, container_id);
+        }
+        Ok(restart.then_some(snapshot.restart_count))
+    }
+
+    /// Whether a container the supervisor is about to restart is still waiting for it
+    pub async fn restart_pending(&self, container_id: &str) -> Result<bool> {
+        let _lock = self.lock_container(container_id).await?;
+        let containers = self.containers.lock().await;
+        Ok(containers
+            .get(container_id)
+            .is_some_and(|c| c.status == ContainerStatus::Restarting))
+    }
+
     async fn run_container_process(&self, exec_ctx: ExecutionContext) -> Result<()> {
         let container_id = exec_ctx.container.id.clone();
         let detach = exec_ctx.detach;
 
         info!("Starting the container process: {}", container_id);
 
-        let result = self.create_isolated_process(&exec_ctx).await;
+        let result = self.executor(&exec_ctx.container).start(self, &exec_ctx).await;
 
         if detach {
             info!("Container {} running in background", container_id);
@@ -233,30 +991,99 @@ impl ContainerRuntime {
         Ok(())
     }
 
-    async fn create_isolated_process(&self, exec_ctx: &ExecutionContext) -> Result<i32> {
+    pub(super) async fn create_isolated_process(&self, exec_ctx: &ExecutionContext) -> Result<i32> {
         let container = &exec_ctx.container;
         let detach = exec_ctx.detach;
 
-        let program = CString::new("/bin/sh")
+        // Checked now, since a failed exec in the container only leaves an errno behind
+        let args = interpreter::check_command(&exec_ctx.rootfs_path, container)?
+            .into_iter()
+            .map(CString::new)
+            .collect::<std::result::Result<Vec<_>, _>>()
             .map_err(|e| CuboError::SystemError(format!("Invalid command: {}", e)))?;
+        let program = args[0].clone();
+
+        let cgroup = self.prepare_cgroup(container)?;
+        // Sandbox members live in the sandbox's network, which is already wired up
+        let sandbox_pid = self.sandbox_pid(container).await?;
+        let attachments = match sandbox_pid {
+            Some(_) => Vec::new(),
+            None => self.network_attachments(container)?,
+        };
 
-        let shell_command = container.command.join(" ");
-        let args = vec![
-            CString::new("/bin/sh").unwrap(),
-            CString::new("-c").unwrap(),
-            CString::new(shell_command)
-                .map_err(|e| CuboError::SystemError(format!("Invalid command: {}", e)))?,
-        ];
+        if !matches!(container.config.network_mode, NetworkMode::Host) {
+            let ip = attachments.first().map(|(_, endpoint)| endpoint.ip.as_str());
+            let peers = self.peer_hosts(container).await;
+            dns::write_network_files(&exec_ctx.rootfs_path, container, ip, &peers)?;
+        }
+        let primary_custom = matches!(container.config.network_mode, NetworkMode::Custom(_));
+        let usermode = match sandbox_pid {
+            Some(_) => None,
+            None => Self::usermode_driver(container),
+        };
+        let sync = if attachments.is_empty() && usermode.is_none() {
+            None
+        } else {
+            Some(SyncPipes::new()?)
+        };
 
-        match unsafe { fork() } {
+        // With subordinate ids the parent maps the user namespace through newuidmap/newgidmap
+        let (host_uid, host_gid) = (nix::unistd::geteuid().as_raw(), nix::unistd::getegid().as_raw());
+        let userns_mode = container.config.userns_mode.clone();
+        let id_mapping = idmap::detect(host_uid, host_gid, &userns_mode);
+        let userns_sync = match id_mapping {
+            Some(_) => Some(SyncPipes::new()?),
+            None => None,
+        };
+
+        let bundle = self.root_dir.join(&container.id);
+        store::clear_exit_code(&bundle);
+
+        match unsafe { fork() } {
             Ok(ForkResult::Parent { child }) => {
+                let userns_sync = userns_sync.map(SyncPipes::into_parent);
+                let sync = sync.map(SyncPipes::into_parent);
                 self.set_container_pid(&container.id, child.as_raw() as u32).await;
+                let pid = child.as_raw() as u32;
+
+                let mut wired = match (userns_sync, id_mapping) {
+                    (Some(userns_sync), Some(mapping)) => userns_sync.handshake(|| {
+                        idmap::apply(&mapping, pid).or_else(|e| {
+                            warn!("{}; mapping a single uid/gid instead", e);
+                            idmap::write_single_mapping(pid, host_uid, host_gid, &userns_mode)
+                        })
+                    }).await,
+                    _ => Ok(()),
+                };
+                if let Some(sync) = sync.filter(|_| wired.is_ok()) {
+                    wired = sync.handshake(|| {
+                        for (i, (network, endpoint)) in attachments.iter().enumerate() {
+                            network::attach_endpoint(network, endpoint, pid, primary_custom && i == 0)?;
+                        }
+                        if let Some(ref driver) = usermode {
+                            let bundle = self.root_dir.join(&container.id);
+                            rootless_net::start(driver, pid, &container.published_ports, &bundle)?;
+                        }
+                        Ok(())
+                    }).await;
+                }
+                if let Err(e) = wired {
+                    let _ = kill(child, Signal::SIGKILL);
+                    let _ = nix_waitpid(child, None);
+                    return Err(e);
+                }
 
                 if detach {
                     info!("Container {} detached with PID {}", container.id, child.as_raw());
                     Ok(0)
                 } else {
-                    match nix_waitpid(child, None) {
+                    // Like docker run, Ctrl-C and friends go to the container instead of killing the CLI
+                    if let Err(e) = signals::forward_signals(child.as_raw()) {
+                        warn!("{}", e);
+                    }
+                    let status = nix_waitpid(child, None);
+                    signals::stop_forwarding();
+                    match status {
                         Ok(NixWaitStatus::Exited(_, exit_code )) => Ok(exit_code),
                         Ok(NixWaitStatus::Signaled(_, signal, _)) => {
                             warn!("Container {} killed by signal: {:?}", container.id, signal);
@@ -271,27 +1098,17 @@ impl ContainerRuntime {
                 }
             }
             Ok(ForkResult::Child) => {
+                let userns_sync = userns_sync.map(SyncPipes::into_child);
+                let sync = sync.map(SyncPipes::into_child);
                 if detach {
                     use std::os::unix::io::IntoRawFd;
                     use std::fs::OpenOptions;
 
-                    let log_path = self.root_dir.join(&container.id).join("container.log");
-
-                    if let Ok(log_file) = OpenOptions::new()
-                        .create(true)
-                        .append(true)
-                        .open(&log_path)
-                        {
-                            let log_fd = log_file.into_raw_fd();
-                            unsafe {
-                                libc::dup2(log_fd, 1);
-                                libc::dup2(log_fd, 2);
-                                if log_fd > 2 {
-                                    libc::close(log_fd);
-                                }
-                            }
-                        }
-                    
+                    if let Err(e) = logging::redirect_output(container, &bundle) {
+                        error!("log driver setup failed: {}", e);
+                        std::process::exit(EXIT_FAILURE);
+                    }
+
                     if let Ok(devnull) = OpenOptions::new().read(true).open("/dev/null") {
                         let null_fd = devnull.into_raw_fd();
                         unsafe {
@@ -302,27 +1119,73 @@ impl ContainerRuntime {
                         }
                     }
                 }
-                if let Err(e) = ns::unshare_user_then_map_ids() {
+                // Join the cgroup before forking so every container process inherits it
+                if let Some(ref cgroup) = cgroup {
+                    if let Err(e) = cgroup.join(std::process::id(), &container.config) {
+                        error!("joining cgroup failed: {}", e);
+                        std::process::exit(EXIT_FAILURE);
+                    }
+                }
+
+                let userns = match userns_sync {
+                    Some(userns_sync) => ns::unshare_user().and_then(|_| {
+                        userns_sync.handshake().map_err(|e| {
+                            CuboError::NamespaceError(format!("id mapping was aborted: {}", e))
+                        })
+                    }),
+                    None => ns::unshare_user_then_map_ids(&userns_mode),
+                };
+                if let Err(e) = userns {
                     error!("userns setup failed: {}", e);
-                    std::process::exit(1);
+                    std::process::exit(EXIT_FAILURE);
                 }
 
-                if let Err(e) = ns::unshare_mount_pid_net(&container.config.network_mode) {
+                let unshared = match sandbox_pid {
+                    Some(pid) => ns::join_sandbox(pid),
+                    None => ns::unshare_mount_pid_net(&container.config.network_mode, &container.config.ipc_mode),
+                };
+                if let Err(e) = unshared {
                     error!("unshare mount/pid/net failed: {}", e);
-                    std::process::exit(1);
+                    std::process::exit(EXIT_FAILURE);
+                }
+
+                // Let the parent plug the new network namespace into its networks, then wait for it
+                if let Some(sync) = sync {
+                    if sync.handshake().is_err() {
+                        error!("network setup was aborted");
+                        std::process::exit(EXIT_FAILURE);
+                    }
+                }
+
+                if !container.config.time_offsets.is_empty() {
+                    if let Err(e) = ns::unshare_time(&container.config.time_offsets) {
+                        error!("time namespace setup failed: {}", e);
+                        std::process::exit(EXIT_FAILURE);
+                    }
                 }
 
                 match unsafe { fork() } {
                     Ok(ForkResult::Parent { child }) => {
+                        // Pass signals on to the container init, which only sees handled ones
+                        if let Err(e) = signals::forward_signals(child.as_raw()) {
+                            warn!("{}", e);
+                        }
+                        let exit = |code: i32| -> ! {
+                            // Nobody may be waiting for this process once the container is detached
+                            if let Err(e) = store::write_exit_code(&bundle, code) {
+                                warn!("{}", e);
+                            }
+                            std::process::exit(code)
+                        };
                         loop {
                             match nix_waitpid(child, None) {
-                                Ok(NixWaitStatus::Exited(_, code)) => std::process::exit(code),
-                                Ok(NixWaitStatus::Signaled(_, sig, _)) => std::process::exit(128 + sig as i32),
+                                Ok(NixWaitStatus::Exited(_, code)) => exit(code),
+                                Ok(NixWaitStatus::Signaled(_, sig, _)) => exit(128 + sig as i32),
                                 Ok(NixWaitStatus::StillAlive) => continue,
                                 Ok(_) => continue,
                                 Err(e) => {
                                     error!("waitpid failed: {}", e);
-                                    std::process::exit(1);
+                                    std::process::exit(EXIT_FAILURE);
                                 }
                             }
                         }
@@ -330,13 +1193,13 @@ impl ContainerRuntime {
                     Ok(ForkResult::Child) => {
                         if let Err(e) = self.setup_namespaced_container(exec_ctx, &program, &args) {
                             error!("Container setup failed: {}", e);
-                            std::process::exit(1);
+                            std::process::exit(e.exit_code());
                         }
-                        std::process::exit(1);
+                        std::process::exit(EXIT_FAILURE);
                     }
                     Err(e) => {
                         error!("fork into pid namespace failed: {}", e);
-                        std::process::exit(1);
+                        std::process::exit(EXIT_FAILURE);
                     }
                 }
             }
@@ -344,24 +1207,220 @@ impl ContainerRuntime {
         }
     }
 
+    /// PID whose namespaces a sandbox member joins; the sandbox has to be running
+    pub(super) async fn sandbox_pid(&self, container: &Container) -> Result<Option<u32>> {
+        let Some(ref sandbox_id) = container.config.sandbox else {
+            return Ok(None);
+        };
+        let sandbox = self.get_container(sandbox_id).await?;
+        match (sandbox.is_running(), sandbox.pid) {
+            (true, Some(pid)) => Ok(Some(pid)),
+            _ => Err(CuboError::ContainerNotRunning(sandbox_id.clone())),
+        }
+    }
+
+    /// The configured stop signal, falling back to SIGTERM
+    fn stop_signal(container: &Container) -> Signal {
+        match container.config.stop_signal.as_deref().map(parse_signal) {
+            Some(Ok(signal)) => signal,
+            Some(Err(e)) => {
+                warn!("{}; stopping container {} with SIGTERM", e, container.id);
+                Signal::SIGTERM
+            }
+            None => Signal::SIGTERM,
+        }
+    }
+
+    fn network_store(&self) -> Result<NetworkStore> {
+        self.config.network_store()
+    }
+
+    /// The mounts of a container with each named volume mounted by its driver and replaced
+    /// by a bind mount of its mountpoint
+    fn mount_volumes(&self, container: &Container) -> Result<Vec<VolumeMount>> {
+        let mut mounts = container.config.volume_mounts.clone();
+        if !mounts.iter().any(|m| matches!(m.mount_type, MountType::Volume)) {
+            return Ok(mounts);
+        }
+        let volumes = VolumeStore::new(&self.root_dir)?;
+        for mount in mounts.iter_mut().filter(|m| matches!(m.mount_type, MountType::Volume)) {
+            let mountpoint = volumes.mount(&mount.host_path, &container.id)?;
+            mount.host_path = mountpoint.to_string_lossy().to_string();
+            mount.mount_type = MountType::Bind;
+        }
+        Ok(mounts)
+    }
+
+    /// Tell the drivers of the container's named volumes it no longer uses them
+    fn unmount_volumes(&self, container: &Container) {
+        let names: Vec<&str> = container
+            .config
+            .volume_mounts
+            .iter()
+            .filter(|m| matches!(m.mount_type, MountType::Volume))
+            .map(|m| m.host_path.as_str())
+            .collect();
+        if names.is_empty() {
+            return;
+        }
+        let volumes = match VolumeStore::new(&self.root_dir) {
+            Ok(volumes) => volumes,
+            Err(e) => return warn!("Failed to unmount the volumes of container {}: {}", container.id, e),
+        };
+        for name in names {
+            if let Err(e) = volumes.unmount(name, &container.id) {
+                warn!("Failed to unmount volume {} of container {}: {}", name, container.id, e);
+            }
+        }
+    }
+
+    /// User-defined networks the container is on, primary network first
+    fn attached_networks(container: &Container) -> Vec<String> {
+        let mut names = Vec::new();
+        if let NetworkMode::Custom(ref name) = container.config.network_mode {
+            names.push(name.clone());
+        }
+        names.extend(container.config.networks.iter().cloned());
+        names
+    }
+
+    /// Networks the container must be wired to when it starts, primary network first
+    pub(super) fn network_attachments(&self, container: &Container) -> Result<Vec<(Network, Endpoint)>> {
+        let names = Self::attached_networks(container);
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let store = self.network_store()?;
+        names
+            .iter()
+            .map(|name| {
+                let network = store.get(name)?;
+                let endpoint = network.endpoints.get(&container.id).cloned().ok_or_else(|| {
+                    CuboError::NetworkError(format!(
+                        "Container {} has no endpoint on network {}",
+                        container.id, name
+                    ))
+                })?;
+                Ok((network, endpoint))
+            })
+            .collect()
+    }
+
+    /// Hosts entries for the named containers sharing a network with `container`
+    pub(super) async fn peer_hosts(&self, container: &Container) -> Vec<HostEntry> {
+        let names = Self::attached_networks(container);
+        let Ok(store) = self.network_store() else {
+            return Vec::new();
+        };
+        let containers = self.containers.lock().await;
+
+        let mut peers = Vec::new();
+        for network in names.iter().filter_map(|name| store.get(name).ok()) {
+            for endpoint in network.endpoints.values().filter(|e| e.container_id != container.id) {
+                let Some(peer) = containers.get(&endpoint.container_id) else {
+                    continue;
+                };
+                for hostname in peer.name.iter().chain(&peer.config.aliases) {
+                    peers.push(HostEntry { hostname: hostname.clone(), ip: endpoint.ip.clone() });
+                }
+            }
+        }
+        peers
+    }
+
+    /// Rewrite /etc/hosts of the running members of a network, plus `changed`, which may
+    /// have just left it
+    async fn refresh_hosts(&self, network_name: &str, changed: &str) {
+        let mut ids: Vec<String> = self
+            .network_store()
+            .and_then(|store| store.get(network_name))
+            .map(|network| network.endpoints.into_keys().collect())
+            .unwrap_or_default();
+        if !ids.iter().any(|id| id == changed) {
+            ids.push(changed.to_string());
+        }
+
+        for id in ids {
+            let Ok(container) = self.get_container(&id).await else { continue };
+            if !container.is_running() || container.config.network_mode == NetworkMode::Host {
+                continue;
+            }
+            let ip = self
+                .network_attachments(&container)
+                .ok()
+                .and_then(|a| a.into_iter().next())
+                .map(|(_, endpoint)| endpoint.ip);
+            let peers = self.peer_hosts(&container).await;
+            let rootfs = self.root_dir.join(&id).join("rootfs");
+            if let Err(e) = dns::write_hosts(&rootfs, &container, ip.as_deref(), &peers) {
+                warn!("Failed to update hosts file of container {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Rootless containers on the default bridge get egress through pasta or slirp4netns
+    pub(super) fn usermode_driver(container: &Container) -> Option<UsermodeDriver> {
+        if nix::unistd::geteuid().is_root() || !matches!(container.config.network_mode, NetworkMode::Bridge) {
+            return None;
+        }
+        let driver = rootless_net::detect_driver();
+        if driver.is_none() {
+            warn!("{}", rootless_net::missing_driver_message());
+        }
+        driver
+    }
+
+    fn release_network(&self, name: &str, container_id: &str) -> Result<()> {
+        let store = self.network_store()?;
+        let network = store.get(name)?;
+        network::detach_endpoint(&network, container_id)?;
+        store.disconnect(name, container_id)
+    }
+
+    /// Create the container cgroup and write its limits. Without explicit limits a
+    /// missing cgroup (e.g. rootless without delegation) is not an error. With the systemd
+    /// driver the scope is only started once the container process joins it.
+    fn prepare_cgroup(&self, container: &Container) -> Result<Option<CgroupManager>> {
+        let manager = self.config.cgroup(&container.id);
+        if manager.driver() != self.config.cgroup_driver {
+            debug!("The systemd cgroup driver needs systemd and cgroup v2; using cgroupfs");
+        }
+        if manager.driver() == CgroupDriver::Systemd {
+            return Ok(Some(manager));
+        }
+        match manager.create().and_then(|_| manager.apply(&container.config)) {
+            Ok(()) => Ok(Some(manager)),
+            Err(e) if cgroup::has_limits(&container.config) => Err(e),
+            Err(e) => {
+                debug!("Running container {} without a cgroup: {}", container.id, e);
+                Ok(None)
+            }
+        }
+    }
+
     fn setup_namespaced_container(&self, exec_ctx: &ExecutionContext, program: &CString, args: &[CString]) -> Result<()> {
         let container = &exec_ctx.container;
-        ns::make_mounts_private()?;
+        // selinuxfs and securityfs are out of reach once the root is pivoted
+        let labels = selinux::container_labels(container);
+        let apparmor_profile = apparmor::profile_for(container);
+        let root_propagation = Propagation::of_root(&container.config.volume_mounts);
+        ns::set_root_propagation(root_propagation)?;
 
         for volume in &container.config.volume_mounts {
             match volume.mount_type {
                 super::MountType::Bind => {
-                    let target = exec_ctx
-                        .rootfs_path
-                        .join(volume.container_path.trim_start_matches('/'));
+                    // Mount points are created along the resolved path, then mounted on
+                    // through a descriptor the kernel opened inside the rootfs
+                    let rootfs = &exec_ctx.rootfs_path;
                     let host = std::path::Path::new(&volume.host_path);
-                    ns::bind_mount(host, &target, volume.read_only)?;
+                    ns::create_mount_point(host, &secure_join(rootfs, &volume.container_path)?)?;
+                    let target = open_in_root(rootfs, &volume.container_path)?;
+                    ns::bind_mount(host, &fd_path(&target), volume.read_only, volume.propagation)?;
                 }
                 super::MountType::Tmpfs => {
                     use nix::mount::{mount, MsFlags};
-                    let target = exec_ctx
-                        .rootfs_path
-                        .join(volume.container_path.trim_start_matches('/'));
+                    let target = secure_join(&exec_ctx.rootfs_path, &volume.container_path)?;
                     if let Some(parent) = target.parent() {
                         fs::create_dir_all(parent)
                             .map_err(|e| CuboError::NamespaceError(format!(
@@ -374,37 +1433,65 @@ impl ContainerRuntime {
                             "Failed to create tmpfs dir {:?}: {}",
                             target, e
                         )))?;
+                    let target = open_in_root(&exec_ctx.rootfs_path, &volume.container_path)?;
+                    let options = &volume.tmpfs_options;
+                    let mut flags = MsFlags::MS_NODEV | MsFlags::MS_NOSUID;
+                    if !options.exec {
+                        flags |= MsFlags::MS_NOEXEC;
+                    }
                     mount::<str, std::path::Path, str, str>(
                         Some("tmpfs"),
-                        &target,
+                        &fd_path(&target),
                         Some("tmpfs"),
-                        MsFlags::MS_NODEV | MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
-                        None,
+                        flags,
+                        selinux::with_context(options.data(), labels.as_ref().map(|l| l.mount.as_str())).as_deref(),
                     )
                     .map_err(|e| CuboError::NamespaceError(format!(
-                        "Failed to mount tmpfs at {:?}: {}",
-                        target, e
+                        "Failed to mount tmpfs at {}: {}",
+                        volume.container_path, e
                     )))?;
                 }
                 super::MountType::Volume => {
-                    debug!("Named volumes not implemented; skipping mount for {}", volume.container_path);
+                    // prepare_start replaces them by bind mounts of their driver's mountpoint
+                    warn!("Named volume {} was not mounted by its driver; skipping {}", volume.host_path, volume.container_path);
                 }
             }
         }
 
+        // The image's /dev/null may be missing or a plain file; mask with the host's
+        let devnull = std::os::fd::OwnedFd::from(
+            fs::File::open("/dev/null").map_err(|e| CuboError::NamespaceError(format!("Failed to open /dev/null: {}", e)))?,
+        );
+
+        if root_propagation.is_shared() {
+            ns::make_parent_mount_private(&exec_ctx.rootfs_path)?;
+        }
         ns::pivot_to_rootfs(&exec_ctx.rootfs_path)?;
 
-        if let Some(ref hostname) = container.config.hostname {
+        if let Some(hostname) = container.hostname() {
             sethostname(hostname)
                 .map_err(|e| CuboError::SystemError(format!("Failed to set hostname: {}", e)))?;
         }
+        if let Some(ref domainname) = container.config.domainname {
+            ns::set_domainname(domainname)?;
+        }
 
         ns::mount_proc()?;
+        ns::mount_default_filesystems()?;
+        sysctl::apply_sysctls(&container.config.sysctls, Path::new("/proc/sys"))?;
+        // After the sysctls, which write to /proc/sys
+        ns::mask_paths(&container.config.masked_paths, &fd_path(&devnull), labels.as_ref().map(|l| l.mount.as_str()))?;
+        ns::make_paths_readonly(&container.config.readonly_paths)?;
+        drop(devnull);
 
         if !matches!(container.config.network_mode, NetworkMode::Host) {
             let _ = ns::setup_loopback();
         }
 
+        if container.config.read_only_rootfs {
+            ns::remount_root_readonly()?;
+        }
+
         if let Some(ref workdir) = container.config.working_dir {
             chdir(workdir.as_str())
                 .map_err(|e| CuboError::SystemError(format!("Failed to change directory: {}", e)))?;
@@ -414,15 +1501,40 @@ impl ContainerRuntime {
             std::env::set_var(key, value);
         }
 
-        if let Some(ref user) = container.config.user {
-            self.setup_user(user)?;
+        // While still privileged in the user namespace, which spares setting no_new_privs
+        if container.config.landlock {
+            landlock::restrict_to("/")?;
+        }
+
+        // Raise limits before dropping privileges
+        ulimit::apply_ulimits(&container.config.ulimits)?;
+        if let Some(score) = container.config.oom_score_adj {
+            fs::write("/proc/self/oom_score_adj", score.to_string())
+                .map_err(|e| CuboError::ProcessError(format!("Failed to set the OOM score adjustment: {}", e)))?;
         }
+        sched::apply(&container.config)?;
+
         if let Some(ref user) = container.config.user {
-            self.setup_user(user)?;
+            let exec_user = self.setup_user(user)?;
+            if !container.config.env_vars.contains_key("HOME") {
+                std::env::set_var("HOME", &exec_user.home);
+            }
+            if let (Some(name), false) = (exec_user.name, container.config.env_vars.contains_key("USER")) {
+                std::env::set_var("USER", name);
+            }
         }
 
+        // The embedded init waits for signals synchronously, so block them before forking
+        let saved_mask = if container.config.init { Some(init::block_signals()?) } else { None };
+
         match unsafe { fork() } {
             Ok(ForkResult::Parent { child }) => {
+                if saved_mask.is_some() {
+                    init::supervise(child);
+                }
+                if let Err(e) = signals::forward_signals(child.as_raw()) {
+                    warn!("{}", e);
+                }
                 loop {
                     match nix_waitpid(Pid::from_raw(-1), None) {
                         Ok(NixWaitStatus::Exited(pid, code)) => {
@@ -445,9 +1557,28 @@ impl ContainerRuntime {
                 }
             }
             Ok(ForkResult::Child) => {
+                if let Some(ref mask) = saved_mask {
+                    if let Err(e) = init::restore_signals(mask) {
+                        error!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+                if let Some(ref labels) = labels {
+                    if let Err(e) = selinux::set_exec_label(&labels.process) {
+                        error!("{}", e);
+                        std::process::exit(e.exit_code());
+                    }
+                }
+                if let Some(ref profile) = apparmor_profile {
+                    if let Err(e) = apparmor::change_onexec(profile) {
+                        error!("{}", e);
+                        std::process::exit(e.exit_code());
+                    }
+                }
                 if let Err(e) = execv(program, args) {
-                    error!("Failed to execute command: {}", e);
-                    std::process::exit(1);
+                    let e = exec_error(program, e);
+                    error!("{}", e);
+                    std::process::exit(e.exit_code());
                 }
                 unreachable!();
             }
@@ -455,18 +1586,18 @@ impl ContainerRuntime {
         }
     }
     
-    fn resolve_mount_paths(rootfs_path: &Path, volume: &super::VolumeMount) -> (PathBuf, Option<PathBuf>) {
-        let container_path = rootfs_path.join(volume.container_path.trim_start_matches('/'));
+    fn resolve_mount_paths(rootfs_path: &Path, volume: &super::VolumeMount) -> Result<(PathBuf, Option<PathBuf>)> {
+        let container_path = secure_join(rootfs_path, &volume.container_path)?;
         let host_path = if !volume.host_path.is_empty() {
             Some(PathBuf::from(&volume.host_path))
         } else {
             None
         };
-        (container_path, host_path)
+        Ok((container_path, host_path))
     }
 
     fn mount_volume(&self, rootfs_path: &Path, volume: &super::VolumeMount) -> Result<()> {
-        let (container_path, host_path) = Self::resolve_mount_paths(rootfs_path, volume);
+        let (container_path, host_path) = Self::resolve_mount_paths(rootfs_path, volume)?;
         
         if let Some(parent) = container_path.parent() {
             fs::create_dir_all(parent)
@@ -531,100 +1662,188 @@ impl ContainerRuntime {
         Ok(())
     }
 
-    fn parse_user_spec(user_spec: &str) -> Result<(u32, Option<u32>)> {
-        let parts: Vec<&str> = user_spec.split(':').collect();
+    /// Switch to the container user, resolved against the image's /etc/passwd and /etc/group
+    fn setup_user(&self, user_spec: &str) -> Result<ExecUser> {
+        let user = user::lookup(Path::new("/etc"), user_spec)?;
 
-        match parts.len() {
-            1 => {
-                let uid = parts[0].parse()
-                    .map_err(|e| CuboError::SystemError(format!("Invalid UID: {}", e)))?;
-                Ok((uid, None))
+        let groups: Vec<Gid> = user.additional_gids.iter().map(|g| Gid::from_raw(*g)).collect();
+        if let Err(e) = setgroups(&groups) {
+            // Single-id user namespaces deny setgroups; that only matters if there are groups to set
+            if !groups.is_empty() {
+                warn!("Supplementary groups not applied: {}", e);
             }
-            2 => {
-                let uid = parts[0].parse()
-                    .map_err(|e| CuboError::SystemError(format!("Invalid UID: {}", e)))?;
-                let gid = parts[1].parse()
-                    .map_err(|e| CuboError::SystemError(format!("Invalid GID: {}", e)))?;
-                Ok((uid, Some(gid)))
-            }
-            _ => Err(CuboError::SystemError("Invalid user specification".to_string())),
         }
-    }
-
-    fn setup_user(&self, user_spec: &str) -> Result<()> {
-        let (uid, gid) = Self::parse_user_spec(user_spec)?;
-        if let Some(gid) = gid {
-            setgid(Gid::from_raw(gid))
-                .map_err(|e| CuboError::SystemError(format!("Failed to set GID: {}", e)))?;
-        }
-        setuid(Uid::from_raw(uid))
+        setgid(Gid::from_raw(user.gid))
+            .map_err(|e| CuboError::SystemError(format!("Failed to set GID: {}", e)))?;
+        setuid(Uid::from_raw(user.uid))
             .map_err(|e| CuboError::SystemError(format!("Failed to set UID: {}", e)))?;
-        Ok(())
+        Ok(user)
     }
 
     fn setup_rootfs(&self, container: &Container, rootfs_path: &Path) -> Result<()> {
         let image_store = ImageStore::new(self.root_dir.join("images"))?;
         let builder = RootfsBuilder::new(&image_store);
+        let snapshotter = self.snapshotter(container);
+        let storage = snapshotter.storage_dir(rootfs_path);
+        let quota = container.config.storage_size.map(|size| {
+            (size, quota::backend_for(container.snapshotter, rootfs_path.parent().unwrap_or(rootfs_path)))
+        });
+        let image = rootfs_path.with_file_name(LOOP_IMAGE);
+        let loopback = matches!(quota, Some((_, QuotaBackend::Loopback)));
+        if let Some((size, QuotaBackend::Loopback)) = quota {
+            quota::mount_loopback(&image, &storage, size)?;
+        }
 
-        match builder.build_from_image(&container.blueprint, rootfs_path) {
+        let populated = self.populate_rootfs(&builder, snapshotter.as_ref(), container, rootfs_path).and_then(|_| {
+            match quota {
+                Some((size, backend)) => quota::limit(backend, &storage, size, &container.id),
+                None => Ok(()),
+            }
+        });
+        if populated.is_err() && loopback {
+            if let Err(e) = quota::discard_loopback(&image, &storage) {
+                warn!("Failed to unmount the rootfs quota of container {}: {}", container.id, e);
+            }
+        }
+        populated
+    }
+
+    /// Fill the snapshot at `rootfs_path` from the image of `container`, or with a minimal
+    /// rootfs if that fails. A quota filesystem mounted in the snapshot is emptied, not removed.
+    fn populate_rootfs(
+        &self,
+        builder: &RootfsBuilder,
+        snapshotter: &dyn Snapshotter,
+        container: &Container,
+        rootfs_path: &Path,
+    ) -> Result<()> {
+        match builder.prepare_from_image(snapshotter, &container.blueprint, rootfs_path) {
             Ok(_) => {
                 info!("Successfully built rootfs from image: {}", container.blueprint);
-                Ok(())
-            }
-            Err(CuboError::BlueprintNotFound(_)) => {
-                warn!(
-                    "Image {} not found, creating minimal rootfs. Import the image using image_store.import_tar()",
-                    container.blueprint
-                );
-                builder.create_minimal_rootfs(rootfs_path)
             }
             Err(e) => {
-                warn!("Failed to build rootfs from image: {}, falling back to minimal rootfs", e);
-                builder.create_minimal_rootfs(rootfs_path)
+                if let CuboError::ImageNotFound { .. } = e {
+                    warn!(
+                        "Image {} not found, creating minimal rootfs. Import the image using image_store.import_tar()",
+                        container.blueprint
+                    );
+                } else {
+                    warn!("Failed to build rootfs from image: {}, falling back to minimal rootfs", e);
+                }
+                snapshotter.remove(rootfs_path)?;
+                snapshotter.prepare(rootfs_path, None)?;
+                builder.create_minimal_rootfs(&snapshotter.upper_dir(rootfs_path))?;
             }
         }
+        Ok(())
     }
 
     async fn set_container_status(&self, container_id: &str, status: ContainerStatus) {
+        let Ok(_lock) = self.lock_container(container_id).await else {
+            warn!("Failed to lock container {}, its state is not saved", container_id);
+            return;
+        };
         let mut containers = self.containers.lock().await;
         if let Some(container) = containers.get_mut(container_id) {
             container.update_status(status);
             let snapshot = container.clone();
             drop(containers);
-            let _ = store::save_state(&self.root_dir, &snapshot);
+            let _ = self.store.save_state(&snapshot);
             return;
         }
     }
 
-    async fn set_container_pid(&self, container_id: &str, pid: u32) {
+    pub(super) async fn set_container_pid(&self, container_id: &str, pid: u32) {
+        let Ok(_lock) = self.lock_container(container_id).await else {
+            warn!("Failed to lock container {}, its state is not saved", container_id);
+            return;
+        };
         let mut containers = self.containers.lock().await;
         if let Some(container) = containers.get_mut(container_id) {
             container.set_pid(pid);
             let snapshot = container.clone();
             drop(containers);
-            let _ = store::save_state(&self.root_dir, &snapshot);
+            let _ = self.store.save_state(&snapshot);
             return;
         }
     }
 
     async fn set_container_exit_code(&self, container_id: &str, exit_code: i32) {
+        let Ok(_lock) = self.lock_container(container_id).await else {
+            warn!("Failed to lock container {}, its state is not saved", container_id);
+            return;
+        };
         let mut containers = self.containers.lock().await;
         if let Some(container) = containers.get_mut(container_id) {
             container.set_exit_code(exit_code);
             let snapshot = container.clone();
             drop(containers);
-            let _ = store::save_state(&self.root_dir, &snapshot);
+            let _ = self.store.save_state(&snapshot);
             return;
         }
     }
 }
 
+/// Whether anything of a running container is left: its process, or whatever is still in
+/// its cgroup
+fn process_alive(config: &RuntimeConfig, container: &Container) -> bool {
+    store::pid_is_alive(container.pid) || config.cgroup(&container.id).is_populated()
+}
+
+/// The error of exec'ing `program` failing with `errno`: not found, or found but not runnable
+fn exec_error(program: &CString, errno: nix::errno::Errno) -> CuboError {
+    let command = program.to_string_lossy().to_string();
+    match errno {
+        nix::errno::Errno::ENOENT => CuboError::CommandNotFound(command),
+        _ => CuboError::CommandNotInvocable { command, reason: errno.to_string() },
+    }
+}
+
+/// Stop a container whose process is gone. Nobody reaped it, so its exit code is the one its
+/// waiting process left in the bundle, or unknown.
+fn mark_dead(container: &mut Container, root_dir: &Path) {
+    container.set_exit_code(store::read_exit_code(&root_dir.join(&container.id)).unwrap_or(-1));
+    container.finished_at = Some(chrono::Utc::now());
+    container.update_status(ContainerStatus::Stopped);
+}
+
+/// The container of `containers` that `identifier` names, as `ContainerRuntime::resolve`.
+/// A prefix of several IDs is an error listing them rather than a guess.
+pub fn resolve_identifier(containers: &[Container], identifier: &str) -> Result<String> {
+    if let Some(container) = containers.iter().find(|c| c.id == identifier || c.name.as_deref() == Some(identifier)) {
+        return Ok(container.id.clone());
+    }
+    let matches: Vec<&Container> = match identifier.is_empty() {
+        true => Vec::new(),
+        false => containers.iter().filter(|c| c.id.starts_with(identifier)).collect(),
+    };
+    match matches.as_slice() {
+        [] => Err(CuboError::ContainerNotFound(identifier.to_string())),
+        [container] => Ok(container.id.clone()),
+        _ => {
+            let mut candidates: Vec<String> = matches
+                .iter()
+                .map(|c| match c.name {
+                    Some(ref name) => format!("{} ({})", &c.id[..12.min(c.id.len())], name),
+                    None => c.id[..12.min(c.id.len())].to_string(),
+                })
+                .collect();
+            candidates.sort();
+            Err(CuboError::AmbiguousIdentifier { identifier: identifier.to_string(), candidates })
+        }
+    }
+}
+
 impl Clone for ContainerRuntime {
     fn clone(&self) -> Self {
         Self {
             containers: Arc::clone(&self.containers),
+            store: Arc::clone(&self.store),
             root_dir: self.root_dir.clone(),
             config: self.config.clone(),
+            events: self.events.clone(),
+            audit: self.audit.clone(),
+            admission: self.admission.clone(),
         }
     }
 }
@@ -636,146 +1855,785 @@ impl Default for RuntimeConfig {
             default_network_mode: NetworkMode::Bridge,
             debug: false,
             container_timeout: 300,
+            cgroup_root: PathBuf::from(cgroup::DEFAULT_CGROUP_ROOT),
+            cgroup_driver: CgroupDriver::default(),
+            executor: ExecutorKind::default(),
+            snapshotter: SnapshotterKind::default(),
+            provision: ProvisionMode::default(),
+            network_pool: None,
+            registry_mirrors: HashMap::new(),
+            pull_policy: PullPolicy::default(),
+            default_limits: DefaultLimits::default(),
+            log_driver: None,
+            log_opts: Vec::new(),
+            proxy: ProxyConfig::default(),
+            exited_ttl: None,
+            stats_interval: None,
+            stats_history: stats::DEFAULT_HISTORY,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// The config file, then environment variables over it
+    pub fn from_env() -> Self {
+        Self::load(config::config_path())
+    }
+
+    /// The config file at `config_file` when it exists, then environment variables over it
+    pub fn load(config_file: Option<PathBuf>) -> Self {
+        let mut cfg = Self::default();
+        if let Some(path) = config_file.filter(|p| p.exists()) {
+            if let Err(e) = ConfigFile::load(&path).and_then(|file| file.apply(&mut cfg)) {
+                warn!("Ignoring {}: {}", path.display(), e);
+            }
+        }
+        if let Ok(root) = std::env::var("CUBO_ROOT") {
+            if !root.is_empty() {
+                cfg.root_dir = PathBuf::from(root);
+            }
+        }
+        if let Ok(executor) = std::env::var("CUBO_EXECUTOR") {
+            match executor.parse() {
+                Ok(kind) => cfg.executor = kind,
+                Err(e) => warn!("Ignoring CUBO_EXECUTOR: {}", e),
+            }
+        }
+        if let Ok(snapshotter) = std::env::var("CUBO_SNAPSHOTTER") {
+            match snapshotter.parse() {
+                Ok(kind) => cfg.snapshotter = kind,
+                Err(e) => warn!("Ignoring CUBO_SNAPSHOTTER: {}", e),
+            }
         }
+        if let Ok(provision) = std::env::var("CUBO_PROVISION") {
+            match provision.parse() {
+                Ok(mode) => cfg.provision = mode,
+                Err(e) => warn!("Ignoring CUBO_PROVISION: {}", e),
+            }
+        }
+        if let Ok(policy) = std::env::var("CUBO_PULL_POLICY") {
+            match policy.parse() {
+                Ok(policy) => cfg.pull_policy = policy,
+                Err(e) => warn!("Ignoring CUBO_PULL_POLICY: {}", e),
+            }
+        }
+        config::proxy_from_env(&mut cfg.proxy);
+        cfg
+    }
+
+    /// The cgroup of a container, created by the configured driver
+    pub fn cgroup(&self, container_id: &str) -> CgroupManager {
+        CgroupManager::with_driver(&self.cgroup_root, self.cgroup_driver, container_id)
+    }
+
+    /// The network store under the root, handing out subnets from the configured pool
+    pub fn network_store(&self) -> Result<NetworkStore> {
+        let store = NetworkStore::new(self.root_dir.join("networks"))?;
+        match self.network_pool {
+            Some(ref pool) => Ok(store.with_pool(network::Subnet::parse(pool)?)),
+            None => Ok(store),
+        }
+    }
+}
+
+
+fn default_root_dir() -> PathBuf {
+    fn with_leaf(base: PathBuf) -> PathBuf { base.join("cubo") }
+
+    if let Ok(state_home) = std::env::var("XDG_STATE_HOME") {
+        if !state_home.is_empty() {
+            return with_leaf(PathBuf::from(state_home));
+        }
+    }
+
+    if let Ok(data_home) = std::env::var("XDG_DATA_HOME") {
+        if !data_home.is_empty() {
+            return with_leaf(PathBuf::from(data_home));
+        }
+    }
+
+    if let Ok(home) = std::env::var("HOME") {
+        if !home.is_empty() {
+            let home_path = PathBuf::from(home);
+            let state_default = home_path.join(".local").join("state");
+            return with_leaf(state_default);
+        }
+    }
+
+    PathBuf::from("/tmp/cubo")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::{Container, VolumeMount, MountType, PortMapping};
+    use crate::container::container_store as store;
+    use tempfile::TempDir;
+
+    /// Changes reach other commands through the bundle, not this runtime's memory
+    async fn set_status_on_disk(runtime: &ContainerRuntime, id: &str, status: ContainerStatus) {
+        let mut containers = runtime.containers.lock().await;
+        let container = containers.get_mut(id).unwrap();
+        container.update_status(status);
+        store::save_state(&runtime.root_dir, container).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_parent_handshake_fails_when_the_child_exits_first() {
+        let pipes = SyncPipes::new().unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                // Like a child failing to join its cgroup before the handshake
+                let _sync = pipes.into_child();
+                unsafe { libc::_exit(EXIT_FAILURE) }
+            }
+            ForkResult::Parent { child } => {
+                let sync = pipes.into_parent();
+                let mut stepped = false;
+                let handshake = sync.handshake(|| {
+                    stepped = true;
+                    Ok(())
+                });
+                let result = tokio::time::timeout(Duration::from_secs(10), handshake).await;
+                let _ = nix_waitpid(child, None);
+                assert!(matches!(result.expect("the handshake hung"), Err(CuboError::SystemError(_))));
+                assert!(!stepped);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_network_handshake_fails_when_the_child_exits_after_id_mapping() {
+        let (userns_pipes, net_pipes) = (SyncPipes::new().unwrap(), SyncPipes::new().unwrap());
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                let (userns_sync, _net_sync) = (userns_pipes.into_child(), net_pipes.into_child());
+                // Mapped, then failing to unshare the other namespaces
+                let code = if userns_sync.handshake().is_ok() { EXIT_FAILURE } else { 0 };
+                unsafe { libc::_exit(code) }
+            }
+            ForkResult::Parent { child } => {
+                let (userns_sync, net_sync) = (userns_pipes.into_parent(), net_pipes.into_parent());
+                let handshakes = async {
+                    userns_sync.handshake(|| Ok(())).await?;
+                    net_sync.handshake(|| Ok(())).await
+                };
+                let result = tokio::time::timeout(Duration::from_secs(10), handshakes).await;
+                let status = nix_waitpid(child, None).unwrap();
+                assert!(matches!(result.expect("the handshake hung"), Err(CuboError::SystemError(_))));
+                assert_eq!(status, NixWaitStatus::Exited(child, EXIT_FAILURE));
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_identifier() {
+        let container = |id: &str, name: Option<&str>| {
+            let mut container = Container::new("test:latest".to_string(), vec!["true".to_string()]);
+            container.id = id.to_string();
+            container.name = name.map(str::to_string);
+            container
+        };
+        let containers = vec![
+            container("3f2a1b9c0d1e4f5a", Some("web")),
+            container("3f9d8e7c6b5a4f3e", None),
+            container("7c1d2e3f4a5b6c7d", Some("3f")),
+        ];
+        assert_eq!(resolve_identifier(&containers, "3f2a1b9c0d1e4f5a").unwrap(), "3f2a1b9c0d1e4f5a");
+        assert_eq!(resolve_identifier(&containers, "web").unwrap(), "3f2a1b9c0d1e4f5a");
+        assert_eq!(resolve_identifier(&containers, "3f9").unwrap(), "3f9d8e7c6b5a4f3e");
+        // A name wins over the IDs it prefixes
+        assert_eq!(resolve_identifier(&containers, "3f").unwrap(), "7c1d2e3f4a5b6c7d");
+        match resolve_identifier(&containers, "3") {
+            Err(CuboError::AmbiguousIdentifier { identifier, candidates }) => {
+                assert_eq!(identifier, "3");
+                assert_eq!(candidates, ["3f2a1b9c0d1e (web)", "3f9d8e7c6b5a"]);
+            }
+            other => panic!("expected an ambiguous identifier, got {:?}", other),
+        }
+        assert!(matches!(resolve_identifier(&containers, "9"), Err(CuboError::ContainerNotFound(_))));
+        assert!(matches!(resolve_identifier(&containers, ""), Err(CuboError::ContainerNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_host_ports_another_container_publishes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = |port: PortMapping| Container::new("test:latest".to_string(), vec!["true".to_string()]).with_port(port);
+        let web = runtime.create_container(container(PortMapping::tcp(8080, 80))).await.unwrap();
+        // Both only created, the first to start gets the port
+        let other = runtime.create_container(container(PortMapping::tcp(8080, 8080))).await.unwrap();
+        set_status_on_disk(&runtime, &web, ContainerStatus::Running).await;
+
+        let err = runtime.create_container(container(PortMapping::tcp(8080, 80))).await.unwrap_err();
+        assert!(matches!(err, CuboError::PortInUse { ref container, .. } if *container == web));
+        let err = runtime.start_container(&other, true).await.unwrap_err();
+        assert!(matches!(err, CuboError::PortInUse { ref port, .. } if port == "0.0.0.0:8080/tcp"));
+
+        // Another protocol, or another port, doesn't conflict
+        runtime.create_container(container(PortMapping::udp(8080, 53))).await.unwrap();
+        runtime.create_container(container(PortMapping::tcp(8081, 80))).await.unwrap();
+        let twice = container(PortMapping::tcp(9090, 80)).with_port(PortMapping::tcp(9090, 443));
+        assert!(matches!(runtime.create_container(twice).await, Err(CuboError::InvalidConfiguration(_))));
+    }
+
+    #[tokio::test]
+    async fn test_runtimes_sharing_a_root_see_each_others_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let first = ContainerRuntime::new(config.clone()).unwrap();
+        let container_id = first
+            .create_container(Container::new("test:latest".to_string(), vec!["true".to_string()]))
+            .await
+            .unwrap();
+
+        // Another cubo process removes the container this one still knows about
+        let second = ContainerRuntime::new(config).unwrap();
+        second.remove_container(&container_id, false).await.unwrap();
+
+        assert!(first.remove_container(&container_id, false).await.is_err());
+        assert!(first.get_container(&container_id).await.is_err());
+        assert!(temp_dir.path().join("locks").join(format!("{}.lock", container_id)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_runtime_with_memory_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let store = Arc::new(crate::container::memory::MemoryContainerStore::default());
+        let runtime = ContainerRuntime::with_store(config.clone(), store.clone()).unwrap();
+        let id = runtime
+            .create_container(Container::new("test:latest".to_string(), vec!["true".to_string()]))
+            .await
+            .unwrap();
+
+        assert!(!temp_dir.path().join(&id).join("config.json").exists());
+        let reloaded = ContainerRuntime::with_store(config, store.clone()).unwrap();
+        assert_eq!(reloaded.get_container(&id).await.unwrap().id, id);
+
+        reloaded.remove_container(&id, false).await.unwrap();
+        assert!(store.load_one(&id).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_create_container() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new(
+            "test:latest".to_string(),
+            vec!["echo".to_string(), "hello".to_string()],
+        );
+
+        let container_id = runtime.create_container(container).await.unwrap();
+        assert!(!container_id.is_empty());
+
+        let containers = runtime.list_containers(true).await.unwrap();
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].id, container_id);
+
+        let bundle = temp_dir.path().join(&container_id);
+        assert!(bundle.exists());
+        assert!(bundle.join("config.json").exists());
+        assert!(bundle.join("state.json").exists());
+    }
+
+    #[tokio::test]
+    async fn test_container_lifecycle() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new(
+            "test:latest".to_string(),
+            vec!["echo".to_string(), "hello".to_string()],
+        );
+
+        let container_id = runtime.create_container(container).await.unwrap();
+        
+        let container = runtime.get_container(&container_id).await.unwrap();
+        assert_eq!(container.status, ContainerStatus::Created);
+
+        runtime.remove_container(&container_id, false).await.unwrap();
+        
+        assert!(runtime.get_container(&container_id).await.is_err());
+
+        assert!(!temp_dir.path().join(&container_id).exists());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_dead_pid_to_stopped() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut c = Container::new("demo:latest".into(), vec!["/bin/echo".into(), "hi".into()]);
+        c.set_pid(999_999);
+        c.update_status(ContainerStatus::Running);
+        store::save_config(temp_dir.path(), &c).unwrap();
+        store::save_state(temp_dir.path(), &c).unwrap();
+
+        let config = RuntimeConfig { root_dir: temp_dir.path().to_path_buf(), ..Default::default() };
+        let rt = ContainerRuntime::new(config).unwrap();
+
+        let loaded = rt.get_container(&c.id).await.unwrap();
+        assert_eq!(loaded.status, ContainerStatus::Stopped);
+        assert!(loaded.finished_at.is_some());
+        assert_eq!(loaded.exit_code, Some(-1));
+
+        let st_path = temp_dir.path().join(&c.id).join("state.json");
+        let st: store::OciState = store::read_json(&st_path).unwrap();
+        assert_eq!(st.status, "stopped");
+    }
+
+    #[test]
+    #[serial_test::serial]
+    fn test_runtime_config_from_env() {
+        let tmp = TempDir::new().unwrap();
+        std::env::set_var("CUBO_ROOT", tmp.path());
+        let cfg = RuntimeConfig::from_env();
+        assert_eq!(cfg.root_dir, tmp.path());
+        std::env::remove_var("CUBO_ROOT");
+    }
+
+    #[test]
+    fn test_runtime_config_default() {
+        let cfg = RuntimeConfig::default();
+        assert!(!cfg.debug);
+        assert_eq!(cfg.container_timeout, 300);
+        assert!(matches!(cfg.default_network_mode, NetworkMode::Bridge));
+        assert_eq!(cfg.cgroup_root, PathBuf::from("/sys/fs/cgroup"));
+    }
+
+    #[test]
+    fn test_stop_signal_from_config() {
+        let container = Container::new("test:latest".to_string(), vec!["nginx".to_string()]);
+        assert_eq!(ContainerRuntime::stop_signal(&container), Signal::SIGTERM);
+
+        let container = container.with_stop_signal("SIGQUIT".to_string());
+        assert_eq!(ContainerRuntime::stop_signal(&container), Signal::SIGQUIT);
+
+        let container = container.with_stop_signal("bogus".to_string());
+        assert_eq!(ContainerRuntime::stop_signal(&container), Signal::SIGTERM);
+    }
+
+    #[tokio::test]
+    async fn test_container_on_user_network_gets_endpoint() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let store = NetworkStore::new(temp_dir.path().join("networks")).unwrap();
+        store.create("appnet", Some("10.99.0.0/24"), None).unwrap();
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_network_mode(NetworkMode::Custom("appnet".to_string()));
+        let id = runtime.create_container(container.clone()).await.unwrap();
+
+        let attachments = runtime.network_attachments(&runtime.get_container(&id).await.unwrap()).unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].1.ip, "10.99.0.2");
+
+        runtime.remove_container(&id, false).await.unwrap();
+        assert!(store.get("appnet").unwrap().endpoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_peer_hosts_include_aliases() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let store = NetworkStore::new(temp_dir.path().join("networks")).unwrap();
+        store.create("appnet", Some("10.99.0.0/24"), None).unwrap();
+
+        let db = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_name("shop-db".to_string())
+            .with_alias("db".to_string())
+            .with_network_mode(NetworkMode::Custom("appnet".to_string()));
+        runtime.create_container(db).await.unwrap();
+        let web = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_network_mode(NetworkMode::Custom("appnet".to_string()));
+        let web = runtime.get_container(&runtime.create_container(web).await.unwrap()).await.unwrap();
+
+        let hostnames: Vec<String> = runtime.peer_hosts(&web).await.into_iter().map(|p| p.hostname).collect();
+        assert_eq!(hostnames, ["shop-db", "db"]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_and_disconnect_network() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let store = NetworkStore::new(temp_dir.path().join("networks")).unwrap();
+        store.create("front", Some("10.98.0.0/24"), None).unwrap();
+        store.create("back", Some("10.97.0.0/24"), None).unwrap();
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_network_mode(NetworkMode::Custom("front".to_string()));
+        let id = runtime.create_container(container).await.unwrap();
+
+        let endpoint = runtime.connect_network(&id, "back", Some("10.97.0.20"), None).await.unwrap();
+        assert_eq!(endpoint.ip, "10.97.0.20");
+        assert_eq!(endpoint.interface, "eth1");
+
+        // The extra network survives a runtime reload and is wired on start
+        let reloaded = ContainerRuntime::new(runtime.config.clone()).unwrap();
+        let loaded = reloaded.get_container(&id).await.unwrap();
+        assert_eq!(loaded.config.networks, vec!["back".to_string()]);
+        let attachments = reloaded.network_attachments(&loaded).unwrap();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].0.name, "front");
+
+        assert!(runtime.connect_network(&id, "back", None, None).await.is_err());
+        assert!(runtime.disconnect_network(&id, "front").await.is_err());
+
+        runtime.disconnect_network(&id, "back").await.unwrap();
+        assert!(store.get("back").unwrap().endpoints.is_empty());
+        assert!(runtime.get_container(&id).await.unwrap().config.networks.is_empty());
+        assert!(runtime.disconnect_network(&id, "back").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_network_rejected_for_host_network() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let store = NetworkStore::new(temp_dir.path().join("networks")).unwrap();
+        store.create("back", Some("10.97.0.0/24"), None).unwrap();
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_network_mode(NetworkMode::Host);
+        let id = runtime.create_container(container).await.unwrap();
+
+        assert!(runtime.connect_network(&id, "back", None, None).await.is_err());
+        assert!(store.get("back").unwrap().endpoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remove_container_releases_extra_networks() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let store = NetworkStore::new(temp_dir.path().join("networks")).unwrap();
+        store.create("back", Some("10.97.0.0/24"), None).unwrap();
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+        runtime.connect_network(&id, "back", None, None).await.unwrap();
+
+        runtime.remove_container(&id, false).await.unwrap();
+        assert!(store.get("back").unwrap().endpoints.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_create_container_on_missing_network_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_network_mode(NetworkMode::Custom("nope".to_string()));
+        let result = runtime.create_container(container).await;
+        assert!(result.is_err());
+        assert!(runtime.list_containers(true).await.unwrap().is_empty());
     }
-}
 
-impl RuntimeConfig {
-    pub fn from_env() -> Self {
-        let mut cfg = Self::default();
-        if let Ok(root) = std::env::var("CUBO_ROOT") {
-            if !root.is_empty() {
-                cfg.root_dir = PathBuf::from(root);
-            }
-        }
-        cfg
+    #[tokio::test]
+    async fn test_sandbox_member_requires_sandbox() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let orphan = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_sandbox("missing".to_string());
+        let err = runtime.create_container(orphan).await.unwrap_err();
+        assert!(matches!(err, CuboError::ContainerNotFound(_)));
+
+        let sandbox = Container::new("test:latest".to_string(), vec!["sleep".to_string()]);
+        let sandbox_id = runtime.create_container(sandbox).await.unwrap();
+        let member = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_sandbox(sandbox_id.clone());
+        let member = runtime.get_container(&runtime.create_container(member).await.unwrap()).await.unwrap();
+        let err = runtime.sandbox_pid(&member).await.unwrap_err();
+        assert!(matches!(err, CuboError::ContainerNotRunning(id) if id == sandbox_id));
     }
-}
 
+    #[tokio::test]
+    async fn test_remove_container_removes_cgroup() {
+        let temp_dir = TempDir::new().unwrap();
+        let cgroup_root = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            cgroup_root: cgroup_root.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container.clone()).await.unwrap();
 
-fn default_root_dir() -> PathBuf {
-    fn with_leaf(base: PathBuf) -> PathBuf { base.join("cubo") }
+        // A plain directory stands in for cgroupfs, so keep it free of limit files
+        let cgroup = runtime.prepare_cgroup(&container).unwrap().unwrap();
+        assert!(cgroup.exists());
 
-    if let Ok(state_home) = std::env::var("XDG_STATE_HOME") {
-        if !state_home.is_empty() {
-            return with_leaf(PathBuf::from(state_home));
-        }
+        runtime.remove_container(&id, false).await.unwrap();
+        assert!(!cgroup.exists());
     }
 
-    if let Ok(data_home) = std::env::var("XDG_DATA_HOME") {
-        if !data_home.is_empty() {
-            return with_leaf(PathBuf::from(data_home));
-        }
+    #[tokio::test]
+    async fn test_lifecycle_publishes_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let mut events = runtime.events().subscribe();
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+        runtime.remove_container(&id, false).await.unwrap();
+
+        let created = events.recv().await.unwrap();
+        assert_eq!((created.kind, created.id.as_str()), (EventKind::Create, id.as_str()));
+        let removed = events.recv().await.unwrap();
+        assert_eq!((removed.kind, removed.id.as_str()), (EventKind::Remove, id.as_str()));
     }
 
-    if let Ok(home) = std::env::var("HOME") {
-        if !home.is_empty() {
-            let home_path = PathBuf::from(home);
-            let state_default = home_path.join(".local").join("state");
-            return with_leaf(state_default);
-        }
+    #[tokio::test]
+    async fn test_list_containers_reconciles_dead_processes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig { root_dir: temp_dir.path().to_path_buf(), ..Default::default() };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+        runtime.set_container_pid(&id, 999_999).await;
+        set_status_on_disk(&runtime, &id, ContainerStatus::Running).await;
+        let mut events = runtime.events().subscribe();
+
+        assert!(runtime.list_containers(false).await.unwrap().is_empty());
+        let container = runtime.get_container(&id).await.unwrap();
+        assert_eq!(container.status, ContainerStatus::Stopped);
+        assert_eq!(container.exit_code, Some(-1));
+        let died = events.recv().await.unwrap();
+        assert_eq!((died.kind, died.id.as_str()), (EventKind::Die, id.as_str()));
+
+        // Another runtime reading the bundle sees the same
+        let config = RuntimeConfig { root_dir: temp_dir.path().to_path_buf(), ..Default::default() };
+        let other = ContainerRuntime::new(config).unwrap();
+        assert_eq!(other.get_container(&id).await.unwrap().exit_code, Some(-1));
     }
 
-    PathBuf::from("/tmp/cubo")
-}
+    #[tokio::test]
+    async fn test_wait_container_reads_the_left_exit_code() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig { root_dir: temp_dir.path().to_path_buf(), ..Default::default() };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+        assert!(runtime.wait_container(&id).await.is_err());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::container::{Container, VolumeMount, MountType};
-    use crate::container::container_store as store;
-    use tempfile::TempDir;
+        // A detached container whose process died, leaving its exit code behind
+        runtime.set_container_pid(&id, 999_999).await;
+        set_status_on_disk(&runtime, &id, ContainerStatus::Running).await;
+        store::write_exit_code(&temp_dir.path().join(&id), 137).unwrap();
+        assert_eq!(runtime.wait_container(&id).await.unwrap(), 137);
+    }
 
     #[tokio::test]
-    async fn test_create_container() {
+    async fn test_prune_containers_by_age() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
+            exited_ttl: Some(chrono::TimeDelta::hours(1)),
             ..Default::default()
         };
-
         let runtime = ContainerRuntime::new(config).unwrap();
-        let container = Container::new(
-            "test:latest".to_string(),
-            vec!["echo".to_string(), "hello".to_string()],
-        );
-
-        let container_id = runtime.create_container(container).await.unwrap();
-        assert!(!container_id.is_empty());
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+            ids.push(runtime.create_container(container).await.unwrap());
+        }
+        let now = chrono::Utc::now();
+        for (id, finished) in [(&ids[0], now - chrono::TimeDelta::hours(2)), (&ids[1], now)] {
+            let mut containers = runtime.containers.lock().await;
+            let container = containers.get_mut(id).unwrap();
+            container.update_status(ContainerStatus::Stopped);
+            container.finished_at = Some(finished);
+            store::save_config(&runtime.root_dir, container).unwrap();
+            store::save_state(&runtime.root_dir, container).unwrap();
+        }
 
-        let containers = runtime.list_containers(true).await.unwrap();
-        assert_eq!(containers.len(), 1);
-        assert_eq!(containers[0].id, container_id);
+        assert_eq!(runtime.collect_garbage().await.unwrap(), vec![ids[0].clone()]);
+        assert!(!temp_dir.path().join(&ids[0]).exists());
 
-        let bundle = temp_dir.path().join(&container_id);
-        assert!(bundle.exists());
-        assert!(bundle.join("config.json").exists());
-        assert!(bundle.join("state.json").exists());
+        // Without a cutoff every exited container goes, created ones stay
+        assert_eq!(runtime.prune_containers(None).await.unwrap(), vec![ids[1].clone()]);
+        assert!(runtime.get_container(&ids[2]).await.is_ok());
     }
 
     #[tokio::test]
-    async fn test_container_lifecycle() {
+    async fn test_create_container_inherits_default_limits() {
         let temp_dir = TempDir::new().unwrap();
         let config = RuntimeConfig {
             root_dir: temp_dir.path().to_path_buf(),
+            default_limits: DefaultLimits { memory: Some(256 << 20), cpus: Some(1.0), pids_limit: Some(128) },
             ..Default::default()
         };
-
         let runtime = ContainerRuntime::new(config).unwrap();
-        let container = Container::new(
-            "test:latest".to_string(),
-            vec!["echo".to_string(), "hello".to_string()],
-        );
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]).with_pids_limit(16);
+        let id = runtime.create_container(container).await.unwrap();
 
-        let container_id = runtime.create_container(container).await.unwrap();
-        
-        let container = runtime.get_container(&container_id).await.unwrap();
+        let config = runtime.get_container(&id).await.unwrap().config;
+        assert_eq!(config.memory_limit, Some(256 << 20));
+        assert_eq!(config.cpu_limit, Some(1.0));
+        assert_eq!(config.pids_limit, Some(16));
+        assert_eq!(config.inherited_limits, ["memory", "cpus"]);
+    }
+
+    #[tokio::test]
+    async fn test_recreate_container_keeps_name_and_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig { root_dir: temp_dir.path().to_path_buf(), ..Default::default() };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("app:latest".to_string(), vec!["serve".to_string()])
+            .with_name("web".to_string())
+            .with_env("MODE".to_string(), "dev".to_string());
+        let old = runtime.create_container(container).await.unwrap();
+
+        let new = runtime.recreate_container(&old).await.unwrap();
+        assert_ne!(new, old);
+        assert!(runtime.get_container(&old).await.is_err());
+        let container = runtime.get_container(&new).await.unwrap();
+        assert_eq!(container.name.as_deref(), Some("web"));
+        assert_eq!(container.command, ["serve"]);
+        assert_eq!(container.config.env_vars["MODE"], "dev");
         assert_eq!(container.status, ContainerStatus::Created);
+    }
 
-        runtime.remove_container(&container_id, false).await.unwrap();
-        
-        assert!(runtime.get_container(&container_id).await.is_err());
+    #[tokio::test]
+    async fn test_update_stopped_container_saves_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
 
-        assert!(!temp_dir.path().join(&container_id).exists());
+        let update = ResourceUpdate { memory_limit: Some(64 * 1024 * 1024), ..Default::default() };
+        runtime.update_container(&id, &update).await.unwrap();
+
+        assert_eq!(runtime.get_container(&id).await.unwrap().config.memory_limit, Some(64 * 1024 * 1024));
+        let saved = store::load_all(temp_dir.path()).unwrap();
+        assert_eq!(saved[&id].config.memory_limit, Some(64 * 1024 * 1024));
     }
 
     #[tokio::test]
-    async fn test_reconcile_dead_pid_to_stopped() {
+    async fn test_update_running_container_rewrites_cgroup() {
         let temp_dir = TempDir::new().unwrap();
-        let mut c = Container::new("demo:latest".into(), vec!["/bin/echo".into(), "hi".into()]);
-        c.set_pid(999_999);
-        c.update_status(ContainerStatus::Running);
-        store::save_config(temp_dir.path(), &c).unwrap();
-        store::save_state(temp_dir.path(), &c).unwrap();
+        let cgroup_root = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            cgroup_root: cgroup_root.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container.clone()).await.unwrap();
+        let update = ResourceUpdate { pids_limit: Some(32), cpu_limit: Some(0.5), ..Default::default() };
 
-        let config = RuntimeConfig { root_dir: temp_dir.path().to_path_buf(), ..Default::default() };
-        let rt = ContainerRuntime::new(config).unwrap();
+        set_status_on_disk(&runtime, &id, ContainerStatus::Running).await;
+        let err = runtime.update_container(&id, &update).await.unwrap_err();
+        assert!(err.to_string().contains("without a cgroup"));
 
-        let loaded = rt.get_container(&c.id).await.unwrap();
-        assert_eq!(loaded.status, ContainerStatus::Stopped);
-        assert!(loaded.finished_at.is_some());
+        let cgroup = runtime.prepare_cgroup(&container).unwrap().unwrap();
+        runtime.update_container(&id, &update).await.unwrap();
+        assert_eq!(cgroup.read("pids.max").unwrap(), "32");
+        assert_eq!(cgroup.read("cpu.max").unwrap(), "50000 100000");
+        assert_eq!(runtime.get_container(&id).await.unwrap().config.pids_limit, Some(32));
+    }
 
-        let st_path = temp_dir.path().join(&c.id).join("state.json");
-        let st: store::OciState = store::read_json(&st_path).unwrap();
-        assert_eq!(st.status, "stopped");
+    #[tokio::test]
+    async fn test_update_container_requires_limits() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+
+        assert!(runtime.update_container(&id, &ResourceUpdate::default()).await.is_err());
+        assert!(runtime.update_container("missing", &ResourceUpdate { pids_limit: Some(1), ..Default::default() }).await.is_err());
     }
 
-    #[test]
-    #[serial_test::serial]
-    fn test_runtime_config_from_env() {
-        let tmp = TempDir::new().unwrap();
-        std::env::set_var("CUBO_ROOT", tmp.path());
-        let cfg = RuntimeConfig::from_env();
-        assert_eq!(cfg.root_dir, tmp.path());
-        std::env::remove_var("CUBO_ROOT");
+    #[tokio::test]
+    async fn test_checkpoint_requires_running_container() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+
+        let err = runtime.checkpoint_container(&id, false).await.unwrap_err();
+        assert!(matches!(err, CuboError::ContainerNotRunning(_)));
+        assert!(runtime.checkpoint_container("missing", false).await.is_err());
     }
 
-    #[test]
-    fn test_runtime_config_default() {
-        let cfg = RuntimeConfig::default();
-        assert!(!cfg.debug);
-        assert_eq!(cfg.container_timeout, 300);
-        assert!(matches!(cfg.default_network_mode, NetworkMode::Bridge));
+    #[tokio::test]
+    async fn test_restore_requires_checkpoint() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = runtime.create_container(container).await.unwrap();
+
+        let err = runtime.restore_container(&id).await.unwrap_err();
+        assert!(err.to_string().contains("no checkpoint"));
+
+        set_status_on_disk(&runtime, &id, ContainerStatus::Running).await;
+        let err = runtime.restore_container(&id).await.unwrap_err();
+        assert!(matches!(err, CuboError::ContainerAlreadyRunning(_)));
     }
 
     #[tokio::test]
@@ -871,6 +2729,77 @@ mod tests {
         assert_eq!(retrieved.name, Some("my-test-container".to_string()))
     }
 
+    #[tokio::test]
+    async fn test_create_container_normalizes_blueprint() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("docker.io/library/alpine".to_string(), vec!["echo".to_string()]);
+        let container_id = runtime.create_container(container).await.unwrap();
+        assert_eq!(runtime.get_container(&container_id).await.unwrap().blueprint, "alpine:latest");
+    }
+
+    #[tokio::test]
+    async fn test_create_container_rejects_invalid_name() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap();
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_name("../../etc".to_string());
+        let result = runtime.create_container(container).await;
+        assert!(matches!(result, Err(CuboError::InvalidContainerName(_))));
+        assert!(runtime.list_containers(true).await.unwrap().is_empty());
+    }
+
+    /// Denies `latest` images and pins the memory of the others
+    struct PinnedOnly;
+
+    impl AdmissionHook for PinnedOnly {
+        fn name(&self) -> &str {
+            "pinned-only"
+        }
+
+        fn admit_pull(&self, reference: &str) -> std::result::Result<(), String> {
+            match reference.ends_with(":latest") {
+                true => Err("latest is not pinned".to_string()),
+                false => Ok(()),
+            }
+        }
+
+        fn admit_run(&self, container: &mut Container) -> std::result::Result<(), String> {
+            self.admit_pull(&container.blueprint)?;
+            container.config.memory_limit = Some(64 * 1024 * 1024);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admission_hooks_veto_and_mutate_containers() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig {
+            root_dir: temp.path().to_path_buf(),
+            ..Default::default()
+        };
+        let runtime = ContainerRuntime::new(config).unwrap().with_admission_hook(Arc::new(PinnedOnly));
+
+        // The hook sees the normalized reference
+        let result = runtime.create_container(Container::new("alpine".to_string(), vec![])).await;
+        assert!(matches!(result, Err(CuboError::AdmissionDenied { ref hook, .. }) if hook == "pinned-only"));
+        assert!(runtime.list_containers(true).await.unwrap().is_empty());
+        assert!(runtime.admit_pull("alpine:latest").is_err());
+        assert!(runtime.admit_pull("alpine:3.20").is_ok());
+
+        let container_id = runtime.create_container(Container::new("alpine:3.20".to_string(), vec![])).await.unwrap();
+        let container = runtime.get_container(&container_id).await.unwrap();
+        assert_eq!(container.config.memory_limit, Some(64 * 1024 * 1024));
+    }
+
     #[tokio::test]
     async fn test_multiple_containers() {
         let temp = TempDir::new().unwrap();
@@ -983,12 +2912,20 @@ mod tests {
             default_network_mode: NetworkMode::Host,
             debug: true,
             container_timeout: 600,
+            cgroup_root: PathBuf::from("/test/cgroup"),
+            executor: ExecutorKind::Oci("crun".to_string()),
+            snapshotter: SnapshotterKind::Overlayfs,
+            provision: ProvisionMode::Hardlink,
+            ..Default::default()
         };
         let cloned = config.clone();
         assert_eq!(cloned.root_dir, PathBuf::from("/test/path"));
         assert!(matches!(cloned.default_network_mode, NetworkMode::Host));
         assert!(cloned.debug);
         assert_eq!(cloned.container_timeout, 600);
+        assert_eq!(cloned.executor, ExecutorKind::Oci("crun".to_string()));
+        assert_eq!(cloned.snapshotter, SnapshotterKind::Overlayfs);
+        assert_eq!(cloned.provision, ProvisionMode::Hardlink);
     }
 
     #[test]
@@ -1056,6 +2993,9 @@ mod tests {
             container_path: "/data".to_string(),
             read_only: false,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
         let container = Container::new(
             "test:latest".to_string(),
@@ -1089,6 +3029,9 @@ mod tests {
             container_path: "/data".to_string(),
             read_only: false,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
         let result = runtime.mount_volume(&rootfs, &volume);
@@ -1116,6 +3059,9 @@ mod tests {
             container_path: "/etc/config.json".to_string(),
             read_only: true,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
         let result = runtime.mount_volume(&rootfs, &volume);
@@ -1139,6 +3085,9 @@ mod tests {
             container_path: "/tmp".to_string(),
             read_only: false,
             mount_type: MountType::Tmpfs,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
         let result = runtime.mount_volume(&rootfs, &volume);
@@ -1163,6 +3112,9 @@ mod tests {
             container_path: "/data".to_string(),
             read_only: false,
             mount_type: MountType::Volume,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
         let result = runtime.mount_volume(&rootfs, &volume);
@@ -1187,6 +3139,9 @@ mod tests {
             container_path: "/data".to_string(),
             read_only: false,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
         let result = runtime.mount_volume(&rootfs, &volume);
@@ -1317,52 +3272,6 @@ mod tests {
         assert!(result.is_err());
     }
 
-    #[test]
-    fn test_parse_user_spec_uid_only() {
-        let result = ContainerRuntime::parse_user_spec("1000");
-        assert!(result.is_ok());
-        let (uid, gid) = result.unwrap();
-        assert_eq!(uid, 1000);
-        assert_eq!(gid, None);
-    }
-
-    #[test]
-    fn test_parse_user_spec_uid_gid() {
-        let result = ContainerRuntime::parse_user_spec("1000:1001");
-        assert!(result.is_ok());
-        let (uid, gid) = result.unwrap();
-        assert_eq!(uid, 1000);
-        assert_eq!(gid, Some(1001));
-    }
-
-    #[test]
-    fn test_parse_user_spec_invalid_uid() {
-        let result = ContainerRuntime::parse_user_spec("notanumber");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Invalid UID"));
-    }
-
-    #[test]
-    fn test_parse_user_spec_invalid_gid() {
-        let result = ContainerRuntime::parse_user_spec("1000:notanumber");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Invalid GID"));
-    }
-
-    #[test]
-    fn test_parse_user_spec_too_many_parts() {
-        let result = ContainerRuntime::parse_user_spec("1000:1001:extra");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Invalid user specification"));
-    }
-
-    #[test]
-    fn test_parse_user_spec_empty_string() {
-        let result = ContainerRuntime::parse_user_spec("");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Invalid UID"));
-    }
-
     #[test]
     fn test_resolve_mount_paths_with_host() {
         let rootfs = PathBuf::from("/var/run/container/rootfs");
@@ -1371,9 +3280,12 @@ mod tests {
             container_path: "/data".to_string(),
             read_only: false,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
-        let (container_path, host_path) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume);
+        let (container_path, host_path) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume).unwrap();
         assert_eq!(container_path, PathBuf::from("/var/run/container/rootfs/data"));
         assert_eq!(host_path, Some(PathBuf::from("/tmp/data")));
     }
@@ -1386,9 +3298,12 @@ mod tests {
             container_path: "/tmp".to_string(),
             read_only: false,
             mount_type: MountType::Tmpfs,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
-        let (container_path, host_path) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume);
+        let (container_path, host_path) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume).unwrap();
         assert_eq!(container_path, PathBuf::from("/var/run/container/rootfs/tmp"));
         assert_eq!(host_path, None);
     }
@@ -1401,9 +3316,12 @@ mod tests {
             container_path: "/container/path".to_string(),
             read_only: false,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
-        let (container_path, _) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume);
+        let (container_path, _) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume).unwrap();
         assert_eq!(container_path, PathBuf::from("/rootfs/container/path"));
     }
 
@@ -1415,9 +3333,36 @@ mod tests {
             container_path: "container/path".to_string(),
             read_only: false,
             mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
         };
 
-        let (container_path, _) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume);
+        let (container_path, _) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume).unwrap();
         assert_eq!(container_path, PathBuf::from("/rootfs/container/path"));
     }
+
+    #[test]
+    fn test_resolve_mount_paths_stays_inside_rootfs() {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        fs::create_dir_all(&rootfs).unwrap();
+        std::os::unix::fs::symlink("/", rootfs.join("host")).unwrap();
+        let mut volume = VolumeMount {
+            host_path: "/data".to_string(),
+            container_path: "../../etc".to_string(),
+            read_only: false,
+            mount_type: MountType::Bind,
+            propagation: Default::default(),
+            tmpfs_options: Default::default(),
+            relabel: None,
+        };
+
+        let (container_path, _) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume).unwrap();
+        assert_eq!(container_path, rootfs.join("etc"));
+
+        volume.container_path = "/host/etc".to_string();
+        let (container_path, _) = ContainerRuntime::resolve_mount_paths(&rootfs, &volume).unwrap();
+        assert_eq!(container_path, rootfs.join("etc"));
+    }
 }
@@ -0,0 +1,513 @@
+//! Software bill of materials and build provenance of built images
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::container::reference::ImageReference;
+use crate::error::{CuboError, Result};
+
+const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SbomFormat {
+    #[default]
+    CycloneDx,
+    Spdx,
+}
+
+impl std::str::FromStr for SbomFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cyclonedx" => Ok(SbomFormat::CycloneDx),
+            "spdx" => Ok(SbomFormat::Spdx),
+            _ => Err(format!("invalid SBOM format '{}', expected cyclonedx or spdx", s)),
+        }
+    }
+}
+
+/// Package manager database a package was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageKind {
+    Apk,
+    Deb,
+    Rpm,
+}
+
+impl PackageKind {
+    fn purl_type(self) -> &'static str {
+        match self {
+            PackageKind::Apk => "apk",
+            PackageKind::Deb => "deb",
+            PackageKind::Rpm => "rpm",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Package {
+    pub kind: PackageKind,
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub arch: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+}
+
+impl Package {
+    /// Package URL, e.g. `pkg:apk/alpine/musl@1.2.4-r2?arch=x86_64`
+    pub fn purl(&self, distro: &str) -> String {
+        let mut purl = format!("pkg:{}/{}/{}@{}", self.kind.purl_type(), distro, self.name, self.version);
+        if let Some(ref arch) = self.arch {
+            purl.push_str(&format!("?arch={}", arch));
+        }
+        purl
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// Path inside the image, starting with `/`
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// What an image is made of, as stored next to its manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sbom {
+    pub image: String,
+    pub created: DateTime<Utc>,
+    /// `ID` of the image's /etc/os-release, used in package URLs
+    pub distro: String,
+    pub packages: Vec<Package>,
+    pub files: Vec<FileEntry>,
+}
+
+impl Sbom {
+    /// Inventory the files of `rootfs` and the packages of its apk, dpkg and rpm databases
+    pub fn scan(image: &str, rootfs: &Path) -> Result<Self> {
+        let mut packages = Vec::new();
+        if let Ok(db) = fs::read_to_string(rootfs.join("lib/apk/db/installed")) {
+            packages.extend(parse_apk_db(&db));
+        }
+        if let Ok(status) = fs::read_to_string(rootfs.join("var/lib/dpkg/status")) {
+            packages.extend(parse_dpkg_status(&status));
+        }
+        packages.extend(query_rpm_db(rootfs));
+        packages.sort_by(|a, b| (a.name.as_str(), a.version.as_str()).cmp(&(b.name.as_str(), b.version.as_str())));
+
+        let mut files = Vec::new();
+        inventory(rootfs, rootfs, &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(Self {
+            image: image.to_string(),
+            created: Utc::now(),
+            distro: distro_id(rootfs),
+            packages,
+            files,
+        })
+    }
+
+    pub fn export(&self, format: SbomFormat) -> Value {
+        match format {
+            SbomFormat::CycloneDx => self.cyclonedx(),
+            SbomFormat::Spdx => self.spdx(),
+        }
+    }
+
+    fn cyclonedx(&self) -> Value {
+        let packages = self.packages.iter().map(|p| {
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": p.purl(&self.distro),
+                "name": p.name,
+                "version": p.version,
+                "purl": p.purl(&self.distro),
+            });
+            if let Some(ref license) = p.license {
+                component["licenses"] = json!([{ "license": { "name": license } }]);
+            }
+            component
+        });
+        let files = self.files.iter().map(|f| {
+            json!({
+                "type": "file",
+                "name": f.path,
+                "hashes": [{ "alg": "SHA-256", "content": f.sha256 }],
+            })
+        });
+
+        json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "serialNumber": format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+            "version": 1,
+            "metadata": {
+                "timestamp": self.created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "tools": [{ "name": "cubo", "version": TOOL_VERSION }],
+                "component": { "type": "container", "name": self.image },
+            },
+            "components": packages.chain(files).collect::<Vec<_>>(),
+        })
+    }
+
+    fn spdx(&self) -> Value {
+        let packages: Vec<Value> = self
+            .packages
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| {
+                json!({
+                    "name": p.name,
+                    "SPDXID": format!("SPDXRef-Package-{}", idx + 1),
+                    "versionInfo": p.version,
+                    "downloadLocation": "NOASSERTION",
+                    "licenseConcluded": "NOASSERTION",
+                    "licenseDeclared": p.license.as_deref().unwrap_or("NOASSERTION"),
+                    "externalRefs": [{
+                        "referenceCategory": "PACKAGE-MANAGER",
+                        "referenceType": "purl",
+                        "referenceLocator": p.purl(&self.distro),
+                    }],
+                })
+            })
+            .collect();
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(idx, f)| {
+                json!({
+                    "fileName": format!(".{}", f.path),
+                    "SPDXID": format!("SPDXRef-File-{}", idx + 1),
+                    "checksums": [{ "algorithm": "SHA256", "checksumValue": f.sha256 }],
+                })
+            })
+            .collect();
+
+        json!({
+            "spdxVersion": "SPDX-2.3",
+            "dataLicense": "CC0-1.0",
+            "SPDXID": "SPDXRef-DOCUMENT",
+            "name": self.image,
+            "documentNamespace": format!("urn:uuid:{}", uuid::Uuid::new_v4()),
+            "creationInfo": {
+                "created": self.created.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "creators": [format!("Tool: cubo-{}", TOOL_VERSION)],
+            },
+            "packages": packages,
+            "files": files,
+        })
+    }
+}
+
+/// Packages of an apk database: blank line separated records of `X:value` lines
+pub fn parse_apk_db(db: &str) -> Vec<Package> {
+    db.split("\n\n")
+        .filter_map(|record| {
+            let field = |key: &str| {
+                record
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key).and_then(|l| l.strip_prefix(':')))
+                    .map(str::to_string)
+            };
+            Some(Package {
+                kind: PackageKind::Apk,
+                name: field("P")?,
+                version: field("V")?,
+                arch: field("A"),
+                license: field("L"),
+            })
+        })
+        .collect()
+}
+
+/// Installed packages of a dpkg status file
+pub fn parse_dpkg_status(status: &str) -> Vec<Package> {
+    status
+        .split("\n\n")
+        .filter_map(|paragraph| {
+            let field = |key: &str| {
+                paragraph
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key).and_then(|l| l.strip_prefix(": ")))
+                    .map(|value| value.trim().to_string())
+            };
+            if !field("Status")?.ends_with(" installed") {
+                return None;
+            }
+            Some(Package {
+                kind: PackageKind::Deb,
+                name: field("Package")?,
+                version: field("Version")?,
+                arch: field("Architecture"),
+                license: None,
+            })
+        })
+        .collect()
+}
+
+/// Packages of an rpm database, read with the host's `rpm` when the image has one
+fn query_rpm_db(rootfs: &Path) -> Vec<Package> {
+    let has_db = ["var/lib/rpm", "usr/lib/sysimage/rpm"]
+        .iter()
+        .any(|dir| rootfs.join(dir).read_dir().is_ok_and(|mut entries| entries.next().is_some()));
+    if !has_db {
+        return Vec::new();
+    }
+
+    let output = Command::new("rpm")
+        .arg("--root")
+        .arg(rootfs)
+        .args(["-qa", "--qf", "%{NAME}\\t%{VERSION}-%{RELEASE}\\t%{ARCH}\\t%{LICENSE}\\n"])
+        .output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                Some(Package {
+                    kind: PackageKind::Rpm,
+                    name: fields.next()?.to_string(),
+                    version: fields.next()?.to_string(),
+                    arch: fields.next().filter(|a| *a != "(none)").map(str::to_string),
+                    license: fields.next().filter(|l| !l.is_empty()).map(str::to_string),
+                })
+            })
+            .collect(),
+        Ok(output) => {
+            warn!("rpm could not read the image's database: {}", String::from_utf8_lossy(&output.stderr).trim());
+            Vec::new()
+        }
+        Err(e) => {
+            warn!("The image has an rpm database but rpm is not available: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn distro_id(rootfs: &Path) -> String {
+    ["etc/os-release", "usr/lib/os-release"]
+        .iter()
+        .find_map(|path| fs::read_to_string(rootfs.join(path)).ok())
+        .and_then(|release| {
+            release
+                .lines()
+                .find_map(|line| line.strip_prefix("ID="))
+                .map(|id| id.trim_matches('"').to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn inventory(rootfs: &Path, dir: &Path, files: &mut Vec<FileEntry>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", dir.display(), e)))?;
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            inventory(rootfs, &path, files)?;
+        } else if file_type.is_file() {
+            let mut file = fs::File::open(&path)?;
+            let mut hasher = Sha256::new();
+            let size = std::io::copy(&mut file, &mut hasher)?;
+            files.push(FileEntry {
+                path: format!("/{}", path.strip_prefix(rootfs).unwrap_or(&path).display()),
+                size,
+                sha256: format!("{:x}", hasher.finalize()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// How an image was built: an in-toto statement with a SLSA provenance predicate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub image: String,
+    /// Digest of the built image, see `signature::image_digest`
+    pub digest: String,
+    pub base_image: String,
+    pub base_digest: String,
+    /// Steps as written in the build file
+    pub instructions: Vec<String>,
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+}
+
+impl Provenance {
+    pub fn statement(&self) -> Value {
+        let sha256 = |digest: &str| digest.trim_start_matches("sha256:").to_string();
+        json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{ "name": self.image, "digest": { "sha256": sha256(&self.digest) } }],
+            "predicateType": "https://slsa.dev/provenance/v1",
+            "predicate": {
+                "buildDefinition": {
+                    "buildType": "cubo-build/v1",
+                    "externalParameters": {
+                        "instructions": self.instructions,
+                    },
+                    "resolvedDependencies": [{
+                        "uri": self.base_image,
+                        "digest": { "sha256": sha256(&self.base_digest) },
+                    }],
+                },
+                "runDetails": {
+                    "builder": { "id": format!("cubo/{}", TOOL_VERSION) },
+                    "metadata": {
+                        "startedOn": self.started.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        "finishedOn": self.finished.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    },
+                },
+            },
+        })
+    }
+}
+
+/// SBOMs and provenance of built images, under `<image store>/attestations`
+pub struct AttestationStore {
+    dir: PathBuf,
+}
+
+impl AttestationStore {
+    pub fn new(image_store_root: &Path) -> Self {
+        Self { dir: image_store_root.join("attestations") }
+    }
+
+    fn path(&self, image: &str, kind: &str) -> Result<PathBuf> {
+        let reference = ImageReference::parse(image)?.normalized();
+        Ok(self.dir.join(format!("{}.{}.json", reference.file_name(), kind)))
+    }
+
+    pub fn save(&self, sbom: &Sbom, provenance: &Provenance) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        for (kind, value) in [
+            ("sbom", serde_json::to_string_pretty(sbom)),
+            ("provenance", serde_json::to_string_pretty(provenance)),
+        ] {
+            let json = value.map_err(|e| CuboError::SystemError(format!("Failed to serialize {}: {}", kind, e)))?;
+            fs::write(self.path(&sbom.image, kind)?, json)?;
+        }
+        debug!("Stored SBOM and provenance of {}", sbom.image);
+        Ok(())
+    }
+
+    pub fn sbom(&self, image: &str) -> Result<Sbom> {
+        self.load(image, "sbom")
+    }
+
+    pub fn provenance(&self, image: &str) -> Result<Provenance> {
+        self.load(image, "provenance")
+    }
+
+    fn load<T: serde::de::DeserializeOwned>(&self, image: &str, kind: &str) -> Result<T> {
+        let path = self.path(image, kind)?;
+        let data = fs::read(&path).map_err(|_| {
+            CuboError::InvalidConfiguration(format!("{} has no {} (only images built by cubo do)", image, kind))
+        })?;
+        serde_json::from_slice(&data)
+            .map_err(|e| CuboError::SystemError(format!("Failed to parse {}: {}", path.display(), e)))
+    }
+
+    pub fn remove(&self, image: &str) {
+        for path in ["sbom", "provenance"].iter().filter_map(|kind| self.path(image, kind).ok()) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const APK_DB: &str = "C:Q1abc=\nP:musl\nV:1.2.4-r2\nA:x86_64\nL:MIT\n\nC:Q1def=\nP:busybox\nV:1.36.1-r5\nA:x86_64\nL:GPL-2.0-only\n";
+    const DPKG_STATUS: &str = "Package: libc6\nStatus: install ok installed\nArchitecture: amd64\nVersion: 2.36-9\n\nPackage: removed\nStatus: deinstall ok config-files\nVersion: 1.0\n";
+
+    #[test]
+    fn test_parse_package_databases() {
+        let apk = parse_apk_db(APK_DB);
+        assert_eq!(apk.len(), 2);
+        assert_eq!(apk[0].name, "musl");
+        assert_eq!(apk[0].license.as_deref(), Some("MIT"));
+        assert_eq!(apk[0].purl("alpine"), "pkg:apk/alpine/musl@1.2.4-r2?arch=x86_64");
+
+        let deb = parse_dpkg_status(DPKG_STATUS);
+        assert_eq!(deb.len(), 1);
+        assert_eq!(deb[0].version, "2.36-9");
+        assert_eq!(deb[0].purl("debian"), "pkg:deb/debian/libc6@2.36-9?arch=amd64");
+    }
+
+    #[test]
+    fn test_scan_and_export() {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path();
+        fs::create_dir_all(rootfs.join("lib/apk/db")).unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("lib/apk/db/installed"), APK_DB).unwrap();
+        fs::write(rootfs.join("etc/os-release"), "NAME=\"Alpine Linux\"\nID=alpine\n").unwrap();
+
+        let sbom = Sbom::scan("myapp:latest", rootfs).unwrap();
+        assert_eq!(sbom.distro, "alpine");
+        assert_eq!(sbom.packages.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["busybox", "musl"]);
+        assert_eq!(sbom.files.len(), 2);
+        assert_eq!(sbom.files[0].path, "/etc/os-release");
+
+        let cyclonedx = sbom.export(SbomFormat::CycloneDx);
+        assert_eq!(cyclonedx["bomFormat"], "CycloneDX");
+        assert_eq!(cyclonedx["components"].as_array().unwrap().len(), 4);
+        assert_eq!(cyclonedx["components"][1]["purl"], "pkg:apk/alpine/musl@1.2.4-r2?arch=x86_64");
+
+        let spdx = sbom.export(SbomFormat::Spdx);
+        assert_eq!(spdx["spdxVersion"], "SPDX-2.3");
+        assert_eq!(spdx["packages"][0]["licenseDeclared"], "GPL-2.0-only");
+        assert_eq!(spdx["files"][1]["fileName"], "./lib/apk/db/installed");
+    }
+
+    #[test]
+    fn test_attestation_store() {
+        let temp = TempDir::new().unwrap();
+        let store = AttestationStore::new(temp.path());
+        let sbom = Sbom::scan("myapp:v1", temp.path()).unwrap();
+        let provenance = Provenance {
+            image: "myapp:v1".to_string(),
+            digest: "sha256:aa".to_string(),
+            base_image: "alpine:3.18".to_string(),
+            base_digest: "sha256:bb".to_string(),
+            instructions: vec!["BASE alpine:3.18".to_string(), "RUN apk add curl".to_string()],
+            started: Utc::now(),
+            finished: Utc::now(),
+        };
+        store.save(&sbom, &provenance).unwrap();
+        assert_eq!(store.sbom("myapp:v1").unwrap(), sbom);
+
+        let statement = store.provenance("myapp:v1").unwrap().statement();
+        assert_eq!(statement["subject"][0]["digest"]["sha256"], "aa");
+        assert_eq!(statement["predicate"]["buildDefinition"]["resolvedDependencies"][0]["uri"], "alpine:3.18");
+
+        store.remove("myapp:v1");
+        assert!(matches!(store.sbom("myapp:v1"), Err(CuboError::InvalidConfiguration(_))));
+    }
+
+    #[test]
+    fn test_sbom_format_from_str() {
+        assert_eq!("spdx".parse::<SbomFormat>().unwrap(), SbomFormat::Spdx);
+        assert_eq!("cyclonedx".parse::<SbomFormat>().unwrap(), SbomFormat::CycloneDx);
+        assert!("syft".parse::<SbomFormat>().is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! CPU and I/O scheduling of the container processes (`--nice`, `--sched-policy`,
+//! `--rt-priority`, `--rt-runtime` and `--ionice`), set on the container's init before
+//! exec and inherited by everything it starts
+
+use std::fmt;
+
+use nix::errno::Errno;
+use nix::sys::resource::{setrlimit, Resource};
+use serde::{Deserialize, Serialize};
+
+use crate::container::ContainerConfig;
+use crate::error::{CuboError, Result};
+
+/// ioprio_set target: a single process, the caller when the id is 0
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+/// The I/O class sits above the 13 bits of the priority level
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// Highest real-time priority of sched_setscheduler
+pub const MAX_RT_PRIORITY: u32 = 99;
+
+/// Scheduling policy of the container processes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchedPolicy {
+    /// Default time-sharing scheduling (SCHED_OTHER)
+    #[default]
+    Other,
+    /// CPU-bound batch work, preempted by interactive processes (SCHED_BATCH)
+    Batch,
+    /// Runs only when the CPU has nothing else to do (SCHED_IDLE)
+    Idle,
+    /// Real-time first in, first out (SCHED_FIFO)
+    Fifo,
+    /// Real-time round robin (SCHED_RR)
+    Rr,
+}
+
+impl SchedPolicy {
+    /// Whether the policy is real-time, and so takes a priority
+    pub fn is_realtime(self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::Rr)
+    }
+
+    fn to_libc(self) -> libc::c_int {
+        match self {
+            SchedPolicy::Other => libc::SCHED_OTHER,
+            SchedPolicy::Batch => libc::SCHED_BATCH,
+            SchedPolicy::Idle => libc::SCHED_IDLE,
+            SchedPolicy::Fifo => libc::SCHED_FIFO,
+            SchedPolicy::Rr => libc::SCHED_RR,
+        }
+    }
+
+    /// Name of the policy in the OCI runtime spec
+    pub fn oci_name(self) -> &'static str {
+        match self {
+            SchedPolicy::Other => "SCHED_OTHER",
+            SchedPolicy::Batch => "SCHED_BATCH",
+            SchedPolicy::Idle => "SCHED_IDLE",
+            SchedPolicy::Fifo => "SCHED_FIFO",
+            SchedPolicy::Rr => "SCHED_RR",
+        }
+    }
+}
+
+impl std::str::FromStr for SchedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "other" => Ok(SchedPolicy::Other),
+            "batch" => Ok(SchedPolicy::Batch),
+            "idle" => Ok(SchedPolicy::Idle),
+            "fifo" => Ok(SchedPolicy::Fifo),
+            "rr" => Ok(SchedPolicy::Rr),
+            _ => Err(format!("invalid scheduling policy '{}', expected other, batch, idle, fifo or rr", s)),
+        }
+    }
+}
+
+impl fmt::Display for SchedPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SchedPolicy::Other => "other",
+            SchedPolicy::Batch => "batch",
+            SchedPolicy::Idle => "idle",
+            SchedPolicy::Fifo => "fifo",
+            SchedPolicy::Rr => "rr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// I/O scheduling class, as `ionice -c` names them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    /// Served before everything else
+    Realtime,
+    /// Default class, ordered by level
+    #[default]
+    BestEffort,
+    /// Served only when no other process needs the disk
+    Idle,
+}
+
+impl IoClass {
+    fn to_kernel(self) -> u32 {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+
+    /// Name of the class in the OCI runtime spec
+    pub fn oci_name(self) -> &'static str {
+        match self {
+            IoClass::Realtime => "IOPRIO_CLASS_RT",
+            IoClass::BestEffort => "IOPRIO_CLASS_BE",
+            IoClass::Idle => "IOPRIO_CLASS_IDLE",
+        }
+    }
+}
+
+impl fmt::Display for IoClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IoClass::Realtime => "realtime",
+            IoClass::BestEffort => "best-effort",
+            IoClass::Idle => "idle",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// I/O priority of the container processes: a class and, except for idle, a level from
+/// 0 (highest) to 7
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoPriority {
+    pub class: IoClass,
+    pub level: u8,
+}
+
+/// Parse "best-effort:2", "realtime" (level 4, like ionice) or "idle"
+impl std::str::FromStr for IoPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s, None),
+        };
+        let class = match class {
+            "realtime" | "rt" => IoClass::Realtime,
+            "best-effort" | "be" => IoClass::BestEffort,
+            "idle" => IoClass::Idle,
+            _ => return Err(format!("invalid I/O class '{}', expected realtime, best-effort or idle", class)),
+        };
+        let level = match (class, level) {
+            (IoClass::Idle, Some(_)) => return Err("the idle I/O class takes no level".to_string()),
+            (IoClass::Idle, None) => 0,
+            (_, None) => 4,
+            (_, Some(level)) => level.parse::<u8>().ok().filter(|l| *l <= 7)
+                .ok_or_else(|| format!("invalid I/O priority level '{}', expected 0 to 7", level))?,
+        };
+        Ok(IoPriority { class, level })
+    }
+}
+
+impl fmt::Display for IoPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.class {
+            IoClass::Idle => write!(f, "{}", self.class),
+            _ => write!(f, "{}:{}", self.class, self.level),
+        }
+    }
+}
+
+impl IoPriority {
+    /// Value of ioprio_set, the class above the level
+    pub fn to_kernel(self) -> libc::c_int {
+        ((self.class.to_kernel() << IOPRIO_CLASS_SHIFT) | self.level as u32) as libc::c_int
+    }
+}
+
+/// Set the scheduling policy, nice value, real-time budget and I/O priority of the
+/// calling process. Called before dropping privileges: raising priorities or picking a
+/// real-time policy needs CAP_SYS_NICE.
+pub fn apply(config: &ContainerConfig) -> Result<()> {
+    if let Some(policy) = config.sched_policy {
+        let param = libc::sched_param {
+            sched_priority: if policy.is_realtime() { config.rt_priority.unwrap_or(1) as libc::c_int } else { 0 },
+        };
+        let rc = unsafe { libc::sched_setscheduler(0, policy.to_libc(), &param) };
+        Errno::result(rc).map_err(|e| {
+            CuboError::ProcessError(format!("Failed to set the {} scheduling policy: {}", policy, e))
+        })?;
+    }
+
+    if let Some(runtime) = config.rt_runtime {
+        setrlimit(Resource::RLIMIT_RTTIME, runtime, runtime)
+            .map_err(|e| CuboError::ProcessError(format!("Failed to set the real-time runtime: {}", e)))?;
+    }
+
+    if let Some(nice) = config.nice {
+        let rc = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+        Errno::result(rc).map_err(|e| CuboError::ProcessError(format!("Failed to set nice value {}: {}", nice, e)))?;
+    }
+
+    if let Some(priority) = config.io_priority {
+        let rc = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority.to_kernel()) };
+        Errno::result(rc).map_err(|e| {
+            CuboError::ProcessError(format!("Failed to set the I/O priority {}: {}", priority, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_io_priority() {
+        let priority: IoPriority = "best-effort:2".parse().unwrap();
+        assert_eq!(priority, IoPriority { class: IoClass::BestEffort, level: 2 });
+        assert_eq!(priority.to_kernel(), (2 << 13) | 2);
+        assert_eq!("rt".parse::<IoPriority>().unwrap(), IoPriority { class: IoClass::Realtime, level: 4 });
+        assert_eq!("idle".parse::<IoPriority>().unwrap().to_string(), "idle");
+        assert!("idle:3".parse::<IoPriority>().is_err());
+        assert!("best-effort:8".parse::<IoPriority>().is_err());
+        assert!("bulk".parse::<IoPriority>().is_err());
+    }
+
+    #[test]
+    fn test_sched_policy() {
+        assert_eq!("batch".parse::<SchedPolicy>().unwrap(), SchedPolicy::Batch);
+        assert!("rr".parse::<SchedPolicy>().unwrap().is_realtime());
+        assert!(!SchedPolicy::Idle.is_realtime());
+        assert_eq!(SchedPolicy::Fifo.oci_name(), "SCHED_FIFO");
+        assert!("deadline".parse::<SchedPolicy>().is_err());
+    }
+}
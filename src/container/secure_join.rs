@@ -0,0 +1,146 @@
+//! Paths inside a rootfs or build context made from names the image or the user chose:
+//! `..` and symlinks resolve as they would with the root as `/`, never out of it
+
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::{open, openat2, OFlag, OpenHow, ResolveFlag};
+use nix::sys::stat::Mode;
+
+use crate::error::{CuboError, Result};
+
+/// Symlinks followed before giving up on a path, as the kernel does
+const MAX_SYMLINKS: usize = 40;
+
+/// `path` inside `root`, with the symlinks on the way followed as if `root` were `/`.
+/// Components that don't exist yet are taken as plain directories, and `..` stops at the
+/// root, so the result is always under `root`.
+pub fn secure_join(root: &Path, path: &str) -> Result<PathBuf> {
+    let mut resolved = PathBuf::from("/");
+    let mut pending: Vec<String> = path.split('/').rev().map(str::to_string).collect();
+    let mut followed = 0;
+
+    while let Some(part) = pending.pop() {
+        match part.as_str() {
+            "" | "." => continue,
+            ".." => {
+                resolved.pop();
+                continue;
+            }
+            _ => {}
+        }
+        let candidate = resolved.join(&part);
+        let host = host_path(root, &candidate);
+        let is_symlink = match fs::symlink_metadata(&host) {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(CuboError::SystemError(format!("Failed to resolve {}: {}", host.display(), e))),
+        };
+        if !is_symlink {
+            resolved = candidate;
+            continue;
+        }
+
+        followed += 1;
+        if followed > MAX_SYMLINKS {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "Too many levels of symbolic links in {}",
+                path
+            )));
+        }
+        let target = fs::read_link(&host)
+            .map_err(|e| CuboError::SystemError(format!("Failed to read link {}: {}", host.display(), e)))?;
+        let target = target.to_string_lossy();
+        if target.starts_with('/') {
+            resolved = PathBuf::from("/");
+        }
+        pending.extend(target.split('/').rev().map(str::to_string));
+    }
+    Ok(host_path(root, &resolved))
+}
+
+/// Open the existing `path` inside `root` as an O_PATH descriptor. With openat2 the kernel
+/// resolves it with `root` as `/`, so a symlink swapped in after `secure_join` still can't
+/// lead out; older kernels get `secure_join` and O_NOFOLLOW.
+pub fn open_in_root(root: &Path, path: &str) -> Result<OwnedFd> {
+    let flags = OFlag::O_PATH | OFlag::O_CLOEXEC;
+    let failed = |e: Errno| CuboError::SystemError(format!("Failed to open {} in {}: {}", path, root.display(), e));
+
+    let dir = open(root, OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC, Mode::empty()).map_err(failed)?;
+    let how = OpenHow::new()
+        .flags(flags)
+        .resolve(ResolveFlag::RESOLVE_IN_ROOT | ResolveFlag::RESOLVE_NO_MAGICLINKS);
+    match openat2(&dir, path, how) {
+        Ok(fd) => Ok(fd),
+        // Not in this kernel, or filtered by seccomp
+        Err(Errno::ENOSYS | Errno::EPERM | Errno::E2BIG) => {
+            open(&secure_join(root, path)?, flags | OFlag::O_NOFOLLOW, Mode::empty()).map_err(failed)
+        }
+        Err(e) => Err(failed(e)),
+    }
+}
+
+/// The magic link through which mount(2) and friends reach what `fd` refers to
+pub fn fd_path(fd: &OwnedFd) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+}
+
+fn host_path(root: &Path, path: &Path) -> PathBuf {
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_secure_join_clamps_dot_dot() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        assert_eq!(secure_join(root, "/data").unwrap(), root.join("data"));
+        assert_eq!(secure_join(root, "data/../logs").unwrap(), root.join("logs"));
+        assert_eq!(secure_join(root, "/../../etc/passwd").unwrap(), root.join("etc/passwd"));
+        assert_eq!(secure_join(root, "/a/../../../b").unwrap(), root.join("b"));
+        assert_eq!(secure_join(root, "/").unwrap(), root.to_path_buf());
+    }
+
+    #[test]
+    fn test_secure_join_follows_symlinks_inside() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("usr/lib")).unwrap();
+        symlink("usr/lib", root.join("lib")).unwrap();
+        symlink("/etc", root.join("usr/lib/absolute")).unwrap();
+        symlink("../../../../../../etc", root.join("usr/lib/relative")).unwrap();
+
+        assert_eq!(secure_join(root, "/lib/x.so").unwrap(), root.join("usr/lib/x.so"));
+        assert_eq!(secure_join(root, "/lib/absolute/shadow").unwrap(), root.join("etc/shadow"));
+        assert_eq!(secure_join(root, "/lib/relative/shadow").unwrap(), root.join("etc/shadow"));
+        // A dangling last component resolves to where the link points
+        symlink("/missing", root.join("dangling")).unwrap();
+        assert_eq!(secure_join(root, "/dangling").unwrap(), root.join("missing"));
+
+        symlink("loop", root.join("loop")).unwrap();
+        assert!(matches!(secure_join(root, "/loop"), Err(CuboError::InvalidConfiguration(_))));
+    }
+
+    #[test]
+    fn test_open_in_root_stays_inside() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("rootfs");
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/hostname"), "inside").unwrap();
+        fs::write(temp.path().join("hostname"), "outside").unwrap();
+        symlink("/etc", root.join("link")).unwrap();
+
+        let fd = open_in_root(&root, "/link/hostname").unwrap();
+        assert_eq!(fs::read_link(fd_path(&fd)).unwrap(), root.join("etc/hostname"));
+        let fd = open_in_root(&root, "/../hostname");
+        assert!(fd.is_err());
+    }
+}
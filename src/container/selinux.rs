@@ -0,0 +1,319 @@
+//! SELinux labels of container processes and of the files they use, on hosts where
+//! SELinux is enabled
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::container::{Container, MountType, Relabel, VolumeMount};
+use crate::error::{CuboError, Result};
+
+/// Where selinuxfs is mounted when SELinux is enabled
+pub const SELINUXFS: &str = "/sys/fs/selinux";
+
+/// Extended attribute holding the label of a file
+const XATTR: &str = "security.selinux";
+
+/// Type of container processes, and of the files they may write
+const PROCESS_TYPE: &str = "container_t";
+const FILE_TYPE: &str = "container_file_t";
+
+/// Label of files every container may use (`:z`): no categories
+pub const SHARED_FILE_LABEL: &str = "system_u:object_r:container_file_t:s0";
+
+/// Number of MCS categories the policy defines (c0 to c1023)
+const CATEGORIES: u16 = 1024;
+
+/// Host directories whose relabeling would lock the host out of its own files
+const PROTECTED_PATHS: &[&str] = &[
+    "/", "/bin", "/boot", "/dev", "/etc", "/home", "/lib", "/lib64", "/proc", "/root", "/run",
+    "/sbin", "/sys", "/tmp", "/usr", "/var",
+];
+
+/// SELinux settings given with `--security-opt label=...`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelOptions {
+    /// Run the container with the label of cubo and leave the labels of its files alone
+    #[serde(default)]
+    pub disable: bool,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub role: Option<String>,
+    /// Type of the process (container_t by default)
+    #[serde(default)]
+    pub kind: Option<String>,
+    /// MCS level, e.g. s0:c1,c2; picked from the container ID by default
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+impl LabelOptions {
+    /// The `--security-opt`s that give these options
+    pub fn security_opts(&self) -> Vec<String> {
+        let mut opts = Vec::new();
+        if self.disable {
+            opts.push("label=disable".to_string());
+        }
+        for (key, value) in [("user", &self.user), ("role", &self.role), ("type", &self.kind), ("level", &self.level)] {
+            if let Some(value) = value {
+                opts.push(format!("label={}:{}", key, value));
+            }
+        }
+        opts
+    }
+}
+
+/// Labels a container runs with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Labels {
+    /// Label of the container processes
+    pub process: String,
+    /// Label of the files only this container may use (rootfs, tmpfs, `:Z` volumes)
+    pub mount: String,
+}
+
+/// Whether SELinux is enabled on the host, enforcing or permissive
+pub fn enabled() -> bool {
+    Path::new(SELINUXFS).join("enforce").exists()
+}
+
+/// Apply "disable", "user:U", "role:R", "type:T" or "level:L" to `options`
+pub fn parse_label_opt(value: &str, options: &mut LabelOptions) -> Result<()> {
+    if value == "disable" {
+        options.disable = true;
+        return Ok(());
+    }
+    let (key, label) = value
+        .split_once(':')
+        .filter(|(_, label)| !label.is_empty())
+        .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid label option '{}'", value)))?;
+    let field = match key {
+        "user" => &mut options.user,
+        "role" => &mut options.role,
+        "type" => &mut options.kind,
+        "level" => &mut options.level,
+        _ => return Err(CuboError::InvalidConfiguration(format!("Unknown label option '{}'", key))),
+    };
+    *field = Some(label.to_string());
+    Ok(())
+}
+
+/// MCS level of two categories derived from the container ID, so containers can't use
+/// each other's files while their labels stay the same across restarts
+pub fn level_for(id: &str) -> String {
+    let hash = Sha256::digest(id.as_bytes());
+    let first = u16::from_be_bytes([hash[0], hash[1]]) % CATEGORIES;
+    let mut second = u16::from_be_bytes([hash[2], hash[3]]) % CATEGORIES;
+    if second == first {
+        second = (first + 1) % CATEGORIES;
+    }
+    format!("s0:c{},c{}", first.min(second), first.max(second))
+}
+
+/// The labels of `container` from its options, None with label=disable
+pub fn labels_for(container: &Container) -> Option<Labels> {
+    let options = &container.config.label_options;
+    if options.disable {
+        return None;
+    }
+    let user = options.user.as_deref().unwrap_or("system_u");
+    let level = options.level.clone().unwrap_or_else(|| level_for(&container.id));
+    Some(Labels {
+        process: format!(
+            "{}:{}:{}:{}",
+            user,
+            options.role.as_deref().unwrap_or("system_r"),
+            options.kind.as_deref().unwrap_or(PROCESS_TYPE),
+            level
+        ),
+        mount: format!("{}:object_r:{}:{}", user, FILE_TYPE, level),
+    })
+}
+
+/// `labels_for` when SELinux is enabled on the host; without it there is nothing to label
+pub fn container_labels(container: &Container) -> Option<Labels> {
+    if !enabled() {
+        return None;
+    }
+    labels_for(container)
+}
+
+/// Mount data with the `context=` option that labels every file of the mount
+pub fn with_context(data: Option<String>, label: Option<&str>) -> Option<String> {
+    match (data, label) {
+        (data, None) => data,
+        (None, Some(label)) => Some(format!("context=\"{}\"", label)),
+        (Some(data), Some(label)) => Some(format!("{},context=\"{}\"", data, label)),
+    }
+}
+
+/// Refuse relabeling system directories: containers could then use them, the host no longer
+pub fn check_relabel(path: &Path) -> Result<()> {
+    let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let home = std::env::var_os("HOME").map(std::path::PathBuf::from);
+    if PROTECTED_PATHS.iter().any(|p| resolved == Path::new(p)) || home.is_some_and(|h| resolved == h) {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "Relabeling {} would make it unusable for the host; use a subdirectory",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Label the host files of the bind mounts asking for it with `:z` or `:Z`
+pub fn relabel_volumes(mounts: &[VolumeMount], labels: &Labels) -> Result<()> {
+    for mount in mounts.iter().filter(|m| matches!(m.mount_type, MountType::Bind)) {
+        let label = match mount.relabel {
+            Some(Relabel::Shared) => SHARED_FILE_LABEL,
+            Some(Relabel::Private) => labels.mount.as_str(),
+            None => continue,
+        };
+        relabel(Path::new(&mount.host_path), label)?;
+    }
+    Ok(())
+}
+
+/// Label the tree at `path` with `label`, unless its root already has it
+pub fn relabel(path: &Path, label: &str) -> Result<()> {
+    if file_label(path).as_deref() == Some(label) {
+        return Ok(());
+    }
+    relabel_tree(path, label)
+}
+
+fn relabel_tree(path: &Path, label: &str) -> Result<()> {
+    set_file_label(path, label)?;
+    let meta = fs::symlink_metadata(path)
+        .map_err(|e| CuboError::SystemError(format!("Failed to stat {}: {}", path.display(), e)))?;
+    if meta.is_dir() {
+        let entries = fs::read_dir(path)
+            .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", path.display(), e)))?;
+        for entry in entries.flatten() {
+            relabel_tree(&entry.path(), label)?;
+        }
+    }
+    Ok(())
+}
+
+/// The label of `path` itself, not of what a symlink points to
+pub fn file_label(path: &Path) -> Option<String> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let name = CString::new(XATTR).ok()?;
+    let mut buf = vec![0u8; 256];
+    let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), buf.len()) };
+    if len < 0 {
+        return None;
+    }
+    buf.truncate(len as usize);
+    // The kernel counts the trailing NUL
+    while buf.last() == Some(&0) {
+        buf.pop();
+    }
+    String::from_utf8(buf).ok()
+}
+
+fn set_file_label(path: &Path, label: &str) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| CuboError::SystemError(format!("Invalid path {}: {}", path.display(), e)))?;
+    let name = CString::new(XATTR).unwrap();
+    let rc = unsafe { libc::lsetxattr(c_path.as_ptr(), name.as_ptr(), label.as_ptr().cast(), label.len(), 0) };
+    if rc != 0 {
+        return Err(CuboError::SystemError(format!(
+            "Failed to label {} {}: {}",
+            path.display(),
+            label,
+            std::io::Error::last_os_error()
+        )));
+    }
+    Ok(())
+}
+
+/// Have the next execve of the calling thread run with `label`, like setexeccon(3)
+pub fn set_exec_label(label: &str) -> Result<()> {
+    fs::write("/proc/thread-self/attr/exec", label)
+        .map_err(|e| CuboError::SystemError(format!("Failed to set the SELinux label {}: {}", label, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container() -> Container {
+        Container::new("alpine:latest".to_string(), vec!["sh".to_string()])
+    }
+
+    #[test]
+    fn test_parse_label_opt() {
+        let mut options = LabelOptions::default();
+        parse_label_opt("type:spc_t", &mut options).unwrap();
+        parse_label_opt("level:s0:c100,c200", &mut options).unwrap();
+        assert_eq!(options.kind.as_deref(), Some("spc_t"));
+        assert_eq!(options.level.as_deref(), Some("s0:c100,c200"));
+        assert!(!options.disable);
+
+        parse_label_opt("disable", &mut options).unwrap();
+        assert!(options.disable);
+
+        assert!(parse_label_opt("type:", &mut options).is_err());
+        assert!(parse_label_opt("range:s0", &mut options).is_err());
+        assert!(parse_label_opt("enable", &mut options).is_err());
+        assert_eq!(options.security_opts(), ["label=disable", "label=type:spc_t", "label=level:s0:c100,c200"]);
+    }
+
+    #[test]
+    fn test_level_for_is_stable_and_has_two_categories() {
+        let level = level_for("abc");
+        assert_eq!(level, level_for("abc"));
+        let categories: Vec<u16> = level
+            .strip_prefix("s0:")
+            .unwrap()
+            .split(',')
+            .map(|c| c.strip_prefix('c').unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(categories.len(), 2);
+        assert!(categories[0] < categories[1] && categories[1] < CATEGORIES);
+    }
+
+    #[test]
+    fn test_labels_for() {
+        let c = container();
+        let labels = labels_for(&c).unwrap();
+        let level = level_for(&c.id);
+        assert_eq!(labels.process, format!("system_u:system_r:container_t:{}", level));
+        assert_eq!(labels.mount, format!("system_u:object_r:container_file_t:{}", level));
+
+        let mut c = container();
+        c.config.label_options.kind = Some("spc_t".to_string());
+        c.config.label_options.level = Some("s0".to_string());
+        let labels = labels_for(&c).unwrap();
+        assert_eq!(labels.process, "system_u:system_r:spc_t:s0");
+        assert_eq!(labels.mount, "system_u:object_r:container_file_t:s0");
+
+        c.config.label_options.disable = true;
+        assert_eq!(labels_for(&c), None);
+    }
+
+    #[test]
+    fn test_with_context() {
+        assert_eq!(with_context(Some("size=1024".to_string()), None).as_deref(), Some("size=1024"));
+        assert_eq!(
+            with_context(Some("size=1024".to_string()), Some("system_u:object_r:container_file_t:s0")).as_deref(),
+            Some("size=1024,context=\"system_u:object_r:container_file_t:s0\"")
+        );
+        assert_eq!(with_context(None, Some("l")).as_deref(), Some("context=\"l\""));
+    }
+
+    #[test]
+    fn test_check_relabel() {
+        assert!(check_relabel(Path::new("/")).is_err());
+        assert!(check_relabel(Path::new("/usr")).is_err());
+        assert!(check_relabel(Path::new("/etc/../usr")).is_err());
+        let temp = tempfile::TempDir::new().unwrap();
+        assert!(check_relabel(temp.path()).is_ok());
+    }
+}
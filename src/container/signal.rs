@@ -0,0 +1,98 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+use crate::error::{CuboError, Result};
+
+/// Parse a signal given by name ("SIGQUIT", "quit") or number ("3")
+pub fn parse_signal(spec: &str) -> Result<Signal> {
+    let invalid = || CuboError::InvalidConfiguration(format!("Invalid signal: {}", spec));
+
+    let spec = spec.trim();
+    if let Ok(number) = spec.parse::<i32>() {
+        return Signal::try_from(number).map_err(|_| invalid());
+    }
+
+    let upper = spec.to_ascii_uppercase();
+    let name = if upper.starts_with("SIG") { upper } else { format!("SIG{}", upper) };
+    Signal::from_str(&name).map_err(|_| invalid())
+}
+
+/// Signals relayed from the CLI through the container's intermediate processes to its payload
+pub const FORWARDED_SIGNALS: [Signal; 4] = [Signal::SIGINT, Signal::SIGTERM, Signal::SIGQUIT, Signal::SIGHUP];
+
+// Process that receives forwarded signals; 0 while forwarding is off
+static FORWARD_TARGET: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let target = FORWARD_TARGET.load(Ordering::SeqCst);
+    if target > 0 {
+        // kill(2) is async-signal-safe
+        unsafe {
+            libc::kill(target, signal);
+        }
+    }
+}
+
+/// Relay FORWARDED_SIGNALS received by this process to `pid` until `stop_forwarding`.
+/// Handlers use SA_RESTART so a blocking waitpid on `pid` keeps waiting.
+pub fn forward_signals(pid: i32) -> Result<()> {
+    FORWARD_TARGET.store(pid, Ordering::SeqCst);
+    let action = SigAction::new(SigHandler::Handler(forward_signal), SaFlags::SA_RESTART, SigSet::empty());
+    for signal in FORWARDED_SIGNALS {
+        unsafe { sigaction(signal, &action) }
+            .map_err(|e| CuboError::SystemError(format!("Failed to install {} handler: {}", signal, e)))?;
+    }
+    Ok(())
+}
+
+/// Restore the default disposition of FORWARDED_SIGNALS
+pub fn stop_forwarding() {
+    let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    for signal in FORWARDED_SIGNALS {
+        let _ = unsafe { sigaction(signal, &action) };
+    }
+    FORWARD_TARGET.store(0, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_parse_signal_names() {
+        assert_eq!(parse_signal("SIGQUIT").unwrap(), Signal::SIGQUIT);
+        assert_eq!(parse_signal("quit").unwrap(), Signal::SIGQUIT);
+        assert_eq!(parse_signal("SigInt").unwrap(), Signal::SIGINT);
+    }
+
+    #[test]
+    fn test_parse_signal_numbers() {
+        assert_eq!(parse_signal("15").unwrap(), Signal::SIGTERM);
+        assert_eq!(parse_signal("9").unwrap(), Signal::SIGKILL);
+    }
+
+    #[test]
+    fn test_parse_signal_invalid() {
+        assert!(parse_signal("SIGNOPE").is_err());
+        assert!(parse_signal("0").is_err());
+        assert!(parse_signal("999").is_err());
+        assert!(parse_signal("").is_err());
+    }
+
+    #[test]
+    fn test_forward_signals_reaches_target() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        forward_signals(child.id() as i32).unwrap();
+
+        nix::sys::signal::raise(Signal::SIGHUP).unwrap();
+        let status = child.wait().unwrap();
+        stop_forwarding();
+
+        assert_eq!(status.signal(), Some(Signal::SIGHUP as i32));
+        assert_eq!(FORWARD_TARGET.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,536 @@
+//! Image signatures and the trust policy enforced by `cubo pull` and `cubo run --verify`
+
+use std::fs;
+use std::os::unix::fs::OpenOptionsExt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ECDSA_P256_SHA256_ASN1_SIGNING};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+
+use crate::error::{CuboError, Result};
+use super::image_store::{ImageManifest, ImageStore};
+use super::transport::ImageSource;
+
+/// SubjectPublicKeyInfo header of a P-256 public key, followed by the 65 byte point
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+const SIGNATURE_TYPE: &str = "cosign container image signature";
+
+/// Trust policy file, `CUBO_POLICY` or `<root>/policy.toml`
+pub fn policy_path(root_dir: &Path) -> PathBuf {
+    std::env::var("CUBO_POLICY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| root_dir.join("policy.toml"))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultAction {
+    #[default]
+    Accept,
+    Reject,
+}
+
+/// A sigstore keyless signer: the OIDC issuer and the subject of its certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+}
+
+/// Signers accepted for the images under `scope`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyRule {
+    /// Registry, repository or reference prefix, e.g. `ghcr.io/acme`
+    pub scope: String,
+    /// PEM public keys, any of which may have signed the image
+    #[serde(default)]
+    pub keys: Vec<PathBuf>,
+    /// Keyless identities, checked with `cosign verify`
+    #[serde(default)]
+    pub identities: Vec<Identity>,
+}
+
+impl PolicyRule {
+    fn matches(&self, reference: &str) -> bool {
+        let scope = self.scope.trim_end_matches('/');
+        reference == scope
+            || reference
+                .strip_prefix(scope)
+                .is_some_and(|rest| rest.starts_with('/') || rest.starts_with(':') || rest.starts_with('@'))
+    }
+
+    fn is_unrestricted(&self) -> bool {
+        self.keys.is_empty() && self.identities.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustPolicy {
+    /// What happens to images no rule matches
+    #[serde(default)]
+    pub default: DefaultAction,
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+}
+
+impl TrustPolicy {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read_to_string(path).map_err(|e| {
+            CuboError::InvalidConfiguration(format!("Failed to read trust policy {}: {}", path.display(), e))
+        })?;
+        toml::from_str(&data).map_err(|e| {
+            CuboError::InvalidConfiguration(format!("Invalid trust policy {}: {}", path.display(), e))
+        })
+    }
+
+    /// The policy at `path`, or one accepting everything when there is no file
+    pub fn load_or_default(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// The most specific rule covering `reference`
+    pub fn rule_for(&self, reference: &str) -> Option<&PolicyRule> {
+        let reference = normalize_reference(reference);
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(&reference))
+            .max_by_key(|rule| rule.scope.trim_end_matches('/').len())
+    }
+}
+
+/// Fully qualified form of a reference: `alpine` is `docker.io/library/alpine:latest`
+pub fn normalize_reference(reference: &str) -> String {
+    let (domain, rest) = match reference.split_once('/') {
+        Some((first, rest)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            (first.to_string(), rest.to_string())
+        }
+        Some(_) => ("docker.io".to_string(), reference.to_string()),
+        None => ("docker.io".to_string(), format!("library/{}", reference)),
+    };
+    let last = rest.rsplit('/').next().unwrap_or(&rest);
+    if last.contains(':') || last.contains('@') {
+        format!("{}/{}", domain, rest)
+    } else {
+        format!("{}/{}:latest", domain, rest)
+    }
+}
+
+/// Digest of a stored image: its config and the sha256 of each layer, in order
+pub fn image_digest(image_store: &ImageStore, reference: &str) -> Result<String> {
+    let manifest = image_store.get_manifest(reference)?;
+    let mut hasher = Sha256::new();
+    let config = serde_json::to_vec(&manifest.config)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize image config: {}", e)))?;
+    hasher.update(&config);
+    for layer in &manifest.layers {
+        let mut file = fs::File::open(layer)
+            .map_err(|e| CuboError::SystemError(format!("Failed to open layer {}: {}", layer, e)))?;
+        let mut layer_hasher = Sha256::new();
+        std::io::copy(&mut file, &mut layer_hasher)?;
+        hasher.update(layer_hasher.finalize());
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Signature of an image digest, in the simple signing format cosign uses
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageSignature {
+    /// Base64 of the signed JSON payload
+    pub payload: String,
+    /// Base64 of the ASN.1 ECDSA signature of the payload
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Payload {
+    critical: Critical,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Critical {
+    identity: PayloadIdentity,
+    image: PayloadImage,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PayloadIdentity {
+    #[serde(rename = "docker-reference")]
+    docker_reference: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PayloadImage {
+    #[serde(rename = "docker-manifest-digest")]
+    digest: String,
+}
+
+/// Signatures of local images, one file per image digest
+pub struct SignatureStore {
+    dir: PathBuf,
+}
+
+impl SignatureStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, digest: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", digest.trim_start_matches("sha256:")))
+    }
+
+    pub fn signatures(&self, digest: &str) -> Result<Vec<ImageSignature>> {
+        let path = self.path(digest);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read(&path)?;
+        serde_json::from_slice(&data)
+            .map_err(|e| CuboError::SystemError(format!("Failed to parse {}: {}", path.display(), e)))
+    }
+
+    /// Sign `digest` as the image `reference` with the PKCS#8 key at `key`
+    pub fn sign(&self, key: &Path, reference: &str, digest: &str) -> Result<ImageSignature> {
+        let key_pair = load_private_key(key)?;
+        let payload = serde_json::to_vec(&Payload {
+            critical: Critical {
+                identity: PayloadIdentity { docker_reference: normalize_reference(reference) },
+                image: PayloadImage { digest: digest.to_string() },
+                kind: SIGNATURE_TYPE.to_string(),
+            },
+        })
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize signature payload: {}", e)))?;
+        let signature = key_pair
+            .sign(&SystemRandom::new(), &payload)
+            .map_err(|_| CuboError::SystemError("Failed to sign the image".to_string()))?;
+        let signature = ImageSignature {
+            payload: STANDARD.encode(&payload),
+            signature: STANDARD.encode(signature.as_ref()),
+        };
+
+        let mut signatures = self.signatures(digest)?;
+        if !signatures.contains(&signature) {
+            signatures.push(signature.clone());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_string_pretty(&signatures)
+            .map_err(|e| CuboError::SystemError(format!("Failed to serialize signatures: {}", e)))?;
+        fs::write(self.path(digest), json)?;
+        Ok(signature)
+    }
+
+    /// Whether one of the stored signatures of `digest` for `reference` verifies with the key at `key`
+    pub fn verify(&self, key: &Path, reference: &str, digest: &str) -> Result<bool> {
+        let public_key = load_public_key(key)?;
+        let reference = normalize_reference(reference);
+        for signature in self.signatures(digest)? {
+            let (Ok(payload), Ok(sig)) = (STANDARD.decode(&signature.payload), STANDARD.decode(&signature.signature)) else {
+                continue;
+            };
+            if UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &public_key).verify(&payload, &sig).is_err() {
+                continue;
+            }
+            let Ok(payload) = serde_json::from_slice::<Payload>(&payload) else {
+                continue;
+            };
+            if payload.critical.image.digest == digest && payload.critical.identity.docker_reference == reference {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// What `cosign verify` checks for the stored image `reference`: the registry manifest it was
+/// pulled by, pinned by digest so a tag moved since can't stand in for it. None for images that
+/// didn't come from a registry, which have no registry signatures to check.
+fn cosign_reference(reference: &str, manifest: &ImageManifest) -> Option<String> {
+    if !matches!(ImageSource::parse(reference), ImageSource::Registry(_)) {
+        return None;
+    }
+    let digest = manifest.repo_digest.as_deref()?;
+    let normalized = normalize_reference(reference);
+    let name = match normalized.split_once('@') {
+        Some((name, _)) => name,
+        None => normalized.rsplit_once(':').filter(|(_, tag)| !tag.contains('/')).map_or(normalized.as_str(), |(name, _)| name),
+    };
+    Some(format!("{}@{}", name, digest))
+}
+
+/// Check `reference` against the policy. Keys are tried against local signatures first; what
+/// they don't settle is asked of `cosign verify` against the manifest the image was pulled by.
+pub fn enforce(policy: &TrustPolicy, image_store: &ImageStore, signatures: &SignatureStore, reference: &str) -> Result<()> {
+    let Some(rule) = policy.rule_for(reference) else {
+        return match policy.default {
+            DefaultAction::Accept => Ok(()),
+            DefaultAction::Reject => Err(CuboError::PermissionDenied(format!(
+                "{} is not covered by the trust policy",
+                reference
+            ))),
+        };
+    };
+    if rule.is_unrestricted() {
+        debug!("Trust policy scope {} accepts {} unsigned", rule.scope, reference);
+        return Ok(());
+    }
+
+    let digest = image_digest(image_store, reference)?;
+    for key in &rule.keys {
+        if signatures.verify(key, reference, &digest)? {
+            info!("{} is signed with {}", reference, key.display());
+            return Ok(());
+        }
+    }
+
+    let attempts: Vec<Vec<String>> = rule
+        .keys
+        .iter()
+        .map(|key| vec!["--key".to_string(), key.to_string_lossy().to_string()])
+        .chain(rule.identities.iter().map(|identity| {
+            vec![
+                "--certificate-identity".to_string(),
+                identity.subject.clone(),
+                "--certificate-oidc-issuer".to_string(),
+                identity.issuer.clone(),
+            ]
+        }))
+        .collect();
+    match cosign_reference(reference, &image_store.get_manifest(reference)?) {
+        Some(pinned) => {
+            for args in attempts {
+                if cosign_verify(&args, &pinned)? {
+                    info!("{} verified by cosign as {}", reference, pinned);
+                    return Ok(());
+                }
+            }
+        }
+        None => debug!("{} was not pulled from a registry, cosign is not asked", reference),
+    }
+
+    Err(CuboError::PermissionDenied(format!(
+        "{} has no valid signature for trust policy scope {}",
+        reference, rule.scope
+    )))
+}
+
+/// Run `cosign verify` for a registry image pinned by digest; false when cosign is missing or
+/// rejects it
+fn cosign_verify(args: &[String], reference: &str) -> Result<bool> {
+    let output = match Command::new("cosign").arg("verify").args(args).arg(reference).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!("cosign is not installed, registry signatures of {} are not checked", reference);
+            return Ok(false);
+        }
+        Err(e) => return Err(CuboError::SystemError(format!("Failed to run cosign: {}", e))),
+    };
+    if !output.status.success() {
+        debug!("cosign verify {}: {}", reference, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(output.status.success())
+}
+
+/// Create a P-256 key pair as `<prefix>.key` (PKCS#8) and `<prefix>.pub`
+pub fn generate_key_pair(prefix: &Path) -> Result<(PathBuf, PathBuf)> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+        .map_err(|_| CuboError::SystemError("Failed to generate a key pair".to_string()))?;
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+        .map_err(|_| CuboError::SystemError("Failed to load the generated key".to_string()))?;
+    let mut spki = P256_SPKI_PREFIX.to_vec();
+    spki.extend_from_slice(key_pair.public_key().as_ref());
+
+    let private = prefix.with_extension("key");
+    let public = prefix.with_extension("pub");
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&private)
+        .and_then(|mut f| f.write_all(to_pem("PRIVATE KEY", pkcs8.as_ref()).as_bytes()))
+        .map_err(|e| CuboError::SystemError(format!("Failed to write {}: {}", private.display(), e)))?;
+    fs::write(&public, to_pem("PUBLIC KEY", &spki))?;
+    Ok((private, public))
+}
+
+fn load_private_key(path: &Path) -> Result<EcdsaKeyPair> {
+    let der = read_pem(path, "PRIVATE KEY")?;
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &der, &SystemRandom::new()).map_err(|_| {
+        CuboError::InvalidConfiguration(format!("{} is not an unencrypted PKCS#8 P-256 key", path.display()))
+    })
+}
+
+/// The uncompressed point of a P-256 public key in a PEM SubjectPublicKeyInfo
+fn load_public_key(path: &Path) -> Result<Vec<u8>> {
+    let der = read_pem(path, "PUBLIC KEY")?;
+    der.strip_prefix(&P256_SPKI_PREFIX[..])
+        .filter(|point| point.len() == 65)
+        .map(|point| point.to_vec())
+        .ok_or_else(|| CuboError::InvalidConfiguration(format!("{} is not a P-256 public key", path.display())))
+}
+
+fn read_pem(path: &Path, label: &str) -> Result<Vec<u8>> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| CuboError::InvalidConfiguration(format!("Failed to read key {}: {}", path.display(), e)))?;
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let body = data
+        .split_once(&begin)
+        .and_then(|(_, rest)| rest.split_once(&end))
+        .map(|(body, _)| body.split_whitespace().collect::<String>())
+        .ok_or_else(|| CuboError::InvalidConfiguration(format!("{} holds no {}", path.display(), label)))?;
+    STANDARD
+        .decode(body)
+        .map_err(|e| CuboError::InvalidConfiguration(format!("Invalid PEM in {}: {}", path.display(), e)))
+}
+
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let lines: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(64)
+        .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+        .collect();
+    format!("-----BEGIN {label}-----\n{}\n-----END {label}-----\n", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::image_store::ImageConfig;
+    use tempfile::TempDir;
+
+    fn store_image(temp: &Path, reference: &str, layer: &[u8]) -> ImageStore {
+        let store = ImageStore::new(temp.join("images")).unwrap();
+        let blob = temp.join("images/blobs/layer.tar");
+        fs::write(&blob, layer).unwrap();
+        store
+            .save_manifest(&ImageManifest {
+                reference: reference.to_string(),
+                parent: None,
+                layers: vec![blob.to_string_lossy().to_string()],
+                config: ImageConfig { cmd: None, env: None, working_dir: None, exposed_ports: None, stop_signal: None, labels: None },
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+        store
+    }
+
+    #[test]
+    fn test_normalize_reference() {
+        assert_eq!(normalize_reference("alpine"), "docker.io/library/alpine:latest");
+        assert_eq!(normalize_reference("acme/app:v1"), "docker.io/acme/app:v1");
+        assert_eq!(normalize_reference("ghcr.io/acme/app"), "ghcr.io/acme/app:latest");
+        assert_eq!(normalize_reference("localhost:5000/app@sha256:ab"), "localhost:5000/app@sha256:ab");
+    }
+
+    #[test]
+    fn test_cosign_reference_pins_the_pulled_digest() {
+        let temp = TempDir::new().unwrap();
+        let store = store_image(temp.path(), "alpine:3.19", b"layer");
+        let pulled = |repo_digest: Option<&str>| ImageManifest {
+            repo_digest: repo_digest.map(str::to_string),
+            ..store.get_manifest("alpine:3.19").unwrap()
+        };
+        let digest = format!("sha256:{}", "a".repeat(64));
+
+        assert_eq!(
+            cosign_reference("alpine:3.19", &pulled(Some(&digest))),
+            Some(format!("docker.io/library/alpine@{}", digest))
+        );
+        assert_eq!(
+            cosign_reference("localhost:5000/team/app", &pulled(Some(&digest))),
+            Some(format!("localhost:5000/team/app@{}", digest))
+        );
+        assert_eq!(
+            cosign_reference(&format!("ghcr.io/acme/app@{}", "sha256:".to_string() + &"b".repeat(64)), &pulled(Some(&digest))),
+            Some(format!("ghcr.io/acme/app@{}", digest))
+        );
+        // Built or imported images have no registry manifest to check
+        assert_eq!(cosign_reference("alpine:3.19", &pulled(None)), None);
+        for reference in ["docker-daemon:alpine:3.19", "oci:/srv/layouts/alpine:3.19", "dir:/srv/images/alpine"] {
+            assert_eq!(cosign_reference(reference, &pulled(Some(&digest))), None);
+        }
+    }
+
+    #[test]
+    fn test_policy_rule_for() {
+        let policy: TrustPolicy = toml::from_str(
+            r#"
+            default = "reject"
+
+            [[rules]]
+            scope = "ghcr.io/acme"
+            keys = ["/etc/cubo/acme.pub"]
+
+            [[rules]]
+            scope = "ghcr.io/acme/tools"
+
+            [[rules]]
+            scope = "docker.io/library"
+            identities = [{ issuer = "https://accounts.example", subject = "ci@example.com" }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.default, DefaultAction::Reject);
+        assert_eq!(policy.rule_for("ghcr.io/acme/app:v1").unwrap().scope, "ghcr.io/acme");
+        assert!(policy.rule_for("ghcr.io/acme/tools:v1").unwrap().is_unrestricted());
+        assert_eq!(policy.rule_for("alpine").unwrap().identities.len(), 1);
+        assert!(policy.rule_for("ghcr.io/acmecorp/app").is_none());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let temp = TempDir::new().unwrap();
+        let store = store_image(temp.path(), "myapp:latest", b"layer");
+        let signatures = SignatureStore::new(temp.path().join("images/signatures"));
+        let (key, public) = generate_key_pair(&temp.path().join("cubo")).unwrap();
+        let (_, other) = generate_key_pair(&temp.path().join("other")).unwrap();
+
+        let digest = image_digest(&store, "myapp:latest").unwrap();
+        signatures.sign(&key, "myapp:latest", &digest).unwrap();
+        assert!(signatures.verify(&public, "myapp:latest", &digest).unwrap());
+        assert!(!signatures.verify(&other, "myapp:latest", &digest).unwrap());
+        assert!(!signatures.verify(&public, "myapp:other", &digest).unwrap());
+
+        let policy = TrustPolicy {
+            default: DefaultAction::Reject,
+            rules: vec![PolicyRule { scope: "docker.io/library/myapp".to_string(), keys: vec![public], ..Default::default() }],
+        };
+        enforce(&policy, &store, &signatures, "myapp:latest").unwrap();
+        assert!(matches!(
+            enforce(&policy, &store, &signatures, "unlisted:latest"),
+            Err(CuboError::PermissionDenied(_))
+        ));
+
+        // Changing the image invalidates the signature
+        fs::write(temp.path().join("images/blobs/layer.tar"), b"tampered").unwrap();
+        assert!(enforce(&policy, &store, &signatures, "myapp:latest").is_err());
+    }
+
+    #[test]
+    fn test_generate_key_pair_keeps_existing_key() {
+        let temp = TempDir::new().unwrap();
+        let prefix = temp.path().join("cubo");
+        generate_key_pair(&prefix).unwrap();
+        assert!(generate_key_pair(&prefix).is_err());
+        assert!(load_private_key(&prefix.with_extension("key")).is_ok());
+        assert_eq!(load_public_key(&prefix.with_extension("pub")).unwrap().len(), 65);
+    }
+}
@@ -0,0 +1,627 @@
+//! Snapshotters keep unpacked image layers as committed snapshots and give each container
+//! a writable snapshot of its image as rootfs
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::container::selinux;
+use crate::container::whiteout::WhiteoutFormat;
+use crate::error::{CuboError, Result};
+
+/// How snapshots are stored on disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotterKind {
+    /// Plain directories, each a copy of its parent
+    #[default]
+    Dir,
+    /// overlayfs mounts with the parent snapshots as lower layers
+    Overlayfs,
+    /// btrfs subvolume snapshots; the root directory has to be on btrfs
+    Btrfs,
+}
+
+impl std::str::FromStr for SnapshotterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(SnapshotterKind::Dir),
+            "overlayfs" | "overlay" => Ok(SnapshotterKind::Overlayfs),
+            "btrfs" => Ok(SnapshotterKind::Btrfs),
+            other => Err(format!("unknown snapshotter '{}' (expected dir, overlayfs or btrfs)", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for SnapshotterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotterKind::Dir => write!(f, "dir"),
+            SnapshotterKind::Overlayfs => write!(f, "overlayfs"),
+            SnapshotterKind::Btrfs => write!(f, "btrfs"),
+        }
+    }
+}
+
+/// How the `dir` snapshotter fills a snapshot from its parent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvisionMode {
+    /// Reflink where the filesystem supports it (XFS, Btrfs), copy elsewhere
+    #[default]
+    Auto,
+    /// Always reflink (FICLONE); fails on filesystems without support
+    Reflink,
+    /// Hard link the files. Nothing is copied, but a file modified in place changes
+    /// for every container of the image.
+    Hardlink,
+    /// Full copies
+    Copy,
+}
+
+impl ProvisionMode {
+    /// Arguments of `cp` that copy a tree this way
+    pub(crate) fn cp_args(self) -> &'static [&'static str] {
+        match self {
+            ProvisionMode::Auto => &["-a", "--reflink=auto"],
+            ProvisionMode::Reflink => &["-a", "--reflink=always"],
+            ProvisionMode::Hardlink => &["-a", "--link"],
+            ProvisionMode::Copy => &["-a", "--reflink=never"],
+        }
+    }
+}
+
+impl std::str::FromStr for ProvisionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ProvisionMode::Auto),
+            "reflink" => Ok(ProvisionMode::Reflink),
+            "hardlink" => Ok(ProvisionMode::Hardlink),
+            "copy" => Ok(ProvisionMode::Copy),
+            other => Err(format!("unknown provisioning mode '{}' (expected auto, reflink, hardlink or copy)", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for ProvisionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProvisionMode::Auto => write!(f, "auto"),
+            ProvisionMode::Reflink => write!(f, "reflink"),
+            ProvisionMode::Hardlink => write!(f, "hardlink"),
+            ProvisionMode::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+/// A mount to make on a snapshot's target before it shows the snapshot's contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMount {
+    pub fstype: String,
+    pub source: String,
+    pub options: Vec<String>,
+}
+
+/// Active snapshots are identified by the directory they are prepared at, committed ones
+/// by name under `root()`
+pub trait Snapshotter: Send + Sync {
+    /// Directory holding the committed snapshots
+    fn root(&self) -> &Path;
+
+    /// Set up `target` as a writable snapshot of the committed snapshot `parent`, or as an
+    /// empty one without a parent
+    fn prepare(&self, target: &Path, parent: Option<&str>) -> Result<()>;
+
+    /// Directory that content written to the snapshot at `target` goes to while it isn't mounted
+    fn upper_dir(&self, target: &Path) -> PathBuf;
+
+    /// Directory holding everything the snapshot at `target` stores; quotas apply to it
+    fn storage_dir(&self, target: &Path) -> PathBuf;
+
+    /// Turn the snapshot prepared at `target` into the read-only snapshot `name`. The active
+    /// snapshot is consumed; if `name` already exists it is simply dropped.
+    fn commit(&self, name: &str, target: &Path) -> Result<()>;
+
+    /// Delete the snapshot prepared at `target`
+    fn remove(&self, target: &Path) -> Result<()>;
+
+    /// Mounts needed on `target`; none when the snapshot is a plain directory there
+    fn mounts(&self, target: &Path) -> Result<Vec<SnapshotMount>>;
+
+    /// How layers unpacked into `upper_dir` keep their whiteouts. Snapshots prepared as a copy
+    /// of their parent have the hidden files deleted.
+    fn whiteouts(&self) -> WhiteoutFormat {
+        WhiteoutFormat::Delete
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.root().join(name).exists()
+    }
+}
+
+/// The snapshotter of `kind`, keeping its committed snapshots under `root_dir/snapshots`.
+/// `provision` only matters to the `dir` snapshotter.
+pub fn snapshotter_for(kind: SnapshotterKind, root_dir: &Path, provision: ProvisionMode) -> Box<dyn Snapshotter> {
+    let root = root_dir.join("snapshots").join(kind.to_string());
+    match kind {
+        SnapshotterKind::Dir => Box::new(DirSnapshotter { root, provision }),
+        SnapshotterKind::Overlayfs => Box::new(OverlaySnapshotter { root }),
+        SnapshotterKind::Btrfs => Box::new(BtrfsSnapshotter { root }),
+    }
+}
+
+/// Name of the snapshot of `layer` applied on top of `parent`. The size and modification
+/// time of the blob are part of it, so a blob written again gets fresh snapshots.
+pub fn chain_id(parent: Option<&str>, layer: &Path) -> Result<String> {
+    let meta = fs::metadata(layer).map_err(|e| {
+        CuboError::SystemError(format!("Layer file does not exist: {}: {}", layer.display(), e))
+    })?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+
+    let mut hasher = Sha256::new();
+    hasher.update(parent.unwrap_or("").as_bytes());
+    hasher.update(b"\n");
+    hasher.update(layer.to_string_lossy().as_bytes());
+    hasher.update(format!("\n{}\n{}", meta.len(), mtime).as_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Make the mounts of the snapshot at `target`, unless it is mounted already. With a
+/// `mount_label` every file of the mounts gets that SELinux label.
+pub fn mount_snapshot(snapshotter: &dyn Snapshotter, target: &Path, mount_label: Option<&str>) -> Result<()> {
+    let mounts = snapshotter.mounts(target)?;
+    if mounts.is_empty() || is_mount_point(target) {
+        return Ok(());
+    }
+    for m in &mounts {
+        let data = selinux::with_context(Some(m.options.join(",")), mount_label).unwrap_or_default();
+        debug!("Mounting {} snapshot at {}: {}", m.fstype, target.display(), data);
+        mount(
+            Some(m.source.as_str()),
+            target,
+            Some(m.fstype.as_str()),
+            MsFlags::empty(),
+            Some(data.as_str()),
+        )
+        .map_err(|e| CuboError::SystemError(format!(
+            "Failed to mount {} rootfs at {}: {}",
+            m.fstype,
+            target.display(),
+            e
+        )))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn is_mount_point(path: &Path) -> bool {
+    match (fs::metadata(path), path.parent().map(fs::metadata)) {
+        (Ok(meta), Some(Ok(parent))) => meta.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+pub(crate) fn run(cmd: &mut Command) -> Result<()> {
+    debug!("Running: {:?}", cmd);
+    let output = cmd.output().map_err(|e| {
+        CuboError::SystemError(format!("Failed to run {:?}: {}", cmd.get_program(), e))
+    })?;
+    if !output.status.success() {
+        return Err(CuboError::SystemError(format!(
+            "{:?} failed: {}",
+            cmd.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Delete `dir` and everything in it. Only the contents go when a filesystem is mounted
+/// there, such as the loopback image of a storage quota.
+fn remove_tree(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let removed = if is_mount_point(dir) {
+        fs::read_dir(dir).and_then(|entries| {
+            entries.into_iter().try_for_each(|entry| {
+                let path = entry?.path();
+                if path.is_dir() && !path.is_symlink() { fs::remove_dir_all(path) } else { fs::remove_file(path) }
+            })
+        })
+    } else {
+        fs::remove_dir_all(dir)
+    };
+    removed.map_err(|e| CuboError::SystemError(format!("Failed to remove snapshot {}: {}", dir.display(), e)))
+}
+
+fn create_parent(target: &Path) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| CuboError::SystemError(format!("Failed to create directory {}: {}", parent.display(), e)))?;
+    }
+    Ok(())
+}
+
+/// Every snapshot is a copy of its parent, which works on any filesystem. Reflinks or
+/// hard links make the copy cheap where possible.
+pub struct DirSnapshotter {
+    root: PathBuf,
+    provision: ProvisionMode,
+}
+
+impl Snapshotter for DirSnapshotter {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn prepare(&self, target: &Path, parent: Option<&str>) -> Result<()> {
+        fs::create_dir_all(target)
+            .map_err(|e| CuboError::SystemError(format!("Failed to create rootfs directory: {}", e)))?;
+        if let Some(parent) = parent {
+            let source = self.root.join(parent).join(".");
+            run(Command::new("cp").args(self.provision.cp_args()).arg(&source).arg(target))?;
+        }
+        Ok(())
+    }
+
+    fn upper_dir(&self, target: &Path) -> PathBuf {
+        target.to_path_buf()
+    }
+
+    fn storage_dir(&self, target: &Path) -> PathBuf {
+        target.to_path_buf()
+    }
+
+    fn commit(&self, name: &str, target: &Path) -> Result<()> {
+        if self.exists(name) {
+            return self.remove(target);
+        }
+        create_parent(&self.root.join(name))?;
+        if let Err(e) = fs::rename(target, self.root.join(name)) {
+            // Someone else committed the same layer in the meantime
+            if self.exists(name) {
+                return self.remove(target);
+            }
+            return Err(CuboError::SystemError(format!("Failed to commit snapshot {}: {}", name, e)));
+        }
+        Ok(())
+    }
+
+    fn remove(&self, target: &Path) -> Result<()> {
+        remove_tree(target)
+    }
+
+    fn mounts(&self, _target: &Path) -> Result<Vec<SnapshotMount>> {
+        Ok(Vec::new())
+    }
+}
+
+/// Committed snapshots hold only the files their layer changed and are stacked as the
+/// lower directories of an overlay mount. The upper and work directories of a snapshot
+/// prepared at `target` live next to it in `<target>.overlay`.
+pub struct OverlaySnapshotter {
+    root: PathBuf,
+}
+
+impl OverlaySnapshotter {
+    fn state_dir(target: &Path) -> PathBuf {
+        let name = target.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        target.with_file_name(format!("{}.overlay", name))
+    }
+
+    /// The `fs` directories of `name` and its ancestors, topmost first
+    fn lower_dirs(&self, name: &str) -> Result<Vec<PathBuf>> {
+        let mut lowers = Vec::new();
+        let mut next = Some(name.to_string());
+        while let Some(name) = next {
+            let dir = self.root.join(&name);
+            if !dir.exists() {
+                return Err(CuboError::SystemError(format!("Snapshot {} does not exist", name)));
+            }
+            lowers.push(dir.join("fs"));
+            next = fs::read_to_string(dir.join("parent")).ok().map(|p| p.trim().to_string());
+        }
+        Ok(lowers)
+    }
+}
+
+impl Snapshotter for OverlaySnapshotter {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn prepare(&self, target: &Path, parent: Option<&str>) -> Result<()> {
+        let state = Self::state_dir(target);
+        for dir in [target.to_path_buf(), state.join("upper"), state.join("work")] {
+            fs::create_dir_all(&dir)
+                .map_err(|e| CuboError::SystemError(format!("Failed to create directory {}: {}", dir.display(), e)))?;
+        }
+        if let Some(parent) = parent {
+            self.lower_dirs(parent)?;
+            fs::write(state.join("parent"), parent)?;
+        }
+        Ok(())
+    }
+
+    fn upper_dir(&self, target: &Path) -> PathBuf {
+        Self::state_dir(target).join("upper")
+    }
+
+    fn storage_dir(&self, target: &Path) -> PathBuf {
+        Self::state_dir(target)
+    }
+
+    fn commit(&self, name: &str, target: &Path) -> Result<()> {
+        if self.exists(name) {
+            return self.remove(target);
+        }
+        let state = Self::state_dir(target);
+        let _ = fs::remove_dir_all(state.join("work"));
+        fs::rename(state.join("upper"), state.join("fs"))?;
+        create_parent(&self.root.join(name))?;
+        if let Err(e) = fs::rename(&state, self.root.join(name)) {
+            if self.exists(name) {
+                return self.remove(target);
+            }
+            return Err(CuboError::SystemError(format!("Failed to commit snapshot {}: {}", name, e)));
+        }
+        let _ = fs::remove_dir(target);
+        Ok(())
+    }
+
+    fn remove(&self, target: &Path) -> Result<()> {
+        if is_mount_point(target) {
+            umount2(target, MntFlags::MNT_DETACH).map_err(|e| {
+                CuboError::SystemError(format!("Failed to unmount {}: {}", target.display(), e))
+            })?;
+        }
+        remove_tree(target)?;
+        remove_tree(&Self::state_dir(target))
+    }
+
+    fn mounts(&self, target: &Path) -> Result<Vec<SnapshotMount>> {
+        let state = Self::state_dir(target);
+        let lowers = match fs::read_to_string(state.join("parent")) {
+            Ok(parent) => self.lower_dirs(parent.trim())?,
+            Err(_) => {
+                // overlayfs wants at least one lower directory
+                let empty = self.root.join("empty");
+                fs::create_dir_all(&empty)?;
+                vec![empty]
+            }
+        };
+        let lowerdir = lowers.iter().map(|d| d.to_string_lossy()).collect::<Vec<_>>().join(":");
+        Ok(vec![SnapshotMount {
+            fstype: "overlay".to_string(),
+            source: "overlay".to_string(),
+            options: vec![
+                format!("lowerdir={}", lowerdir),
+                format!("upperdir={}", state.join("upper").display()),
+                format!("workdir={}", state.join("work").display()),
+            ],
+        }])
+    }
+
+    /// A layer's upper directory is unpacked on its own and becomes a lower directory, so its
+    /// whiteouts are kept for overlayfs to apply
+    fn whiteouts(&self) -> WhiteoutFormat {
+        WhiteoutFormat::Overlay
+    }
+}
+
+/// Snapshots are btrfs subvolumes, so preparing one from its parent costs no copy
+pub struct BtrfsSnapshotter {
+    root: PathBuf,
+}
+
+impl Snapshotter for BtrfsSnapshotter {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn prepare(&self, target: &Path, parent: Option<&str>) -> Result<()> {
+        create_parent(target)?;
+        // `subvolume create` and `snapshot` want to create the target themselves
+        let _ = fs::remove_dir(target);
+        match parent {
+            Some(parent) => run(Command::new("btrfs")
+                .args(["subvolume", "snapshot"])
+                .arg(self.root.join(parent))
+                .arg(target)),
+            None => run(Command::new("btrfs").args(["subvolume", "create"]).arg(target)),
+        }
+    }
+
+    fn upper_dir(&self, target: &Path) -> PathBuf {
+        target.to_path_buf()
+    }
+
+    fn storage_dir(&self, target: &Path) -> PathBuf {
+        target.to_path_buf()
+    }
+
+    fn commit(&self, name: &str, target: &Path) -> Result<()> {
+        if !self.exists(name) {
+            create_parent(&self.root.join(name))?;
+            run(Command::new("btrfs")
+                .args(["subvolume", "snapshot", "-r"])
+                .arg(target)
+                .arg(self.root.join(name)))?;
+        }
+        self.remove(target)
+    }
+
+    fn remove(&self, target: &Path) -> Result<()> {
+        if target.exists() {
+            run(Command::new("btrfs").args(["subvolume", "delete"]).arg(target))?;
+        }
+        Ok(())
+    }
+
+    fn mounts(&self, _target: &Path) -> Result<Vec<SnapshotMount>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_snapshotter_kind() {
+        assert_eq!("dir".parse::<SnapshotterKind>(), Ok(SnapshotterKind::Dir));
+        assert_eq!("overlay".parse::<SnapshotterKind>(), Ok(SnapshotterKind::Overlayfs));
+        assert_eq!("btrfs".parse::<SnapshotterKind>(), Ok(SnapshotterKind::Btrfs));
+        assert!("zfs".parse::<SnapshotterKind>().is_err());
+        assert_eq!(SnapshotterKind::Overlayfs.to_string(), "overlayfs");
+        assert_eq!(SnapshotterKind::default(), SnapshotterKind::Dir);
+    }
+
+    #[test]
+    fn test_chain_id() {
+        let temp = TempDir::new().unwrap();
+        let layer = temp.path().join("layer.tar");
+        fs::write(&layer, "one").unwrap();
+
+        let base = chain_id(None, &layer).unwrap();
+        assert_eq!(base, chain_id(None, &layer).unwrap());
+        assert_ne!(base, chain_id(Some(&base), &layer).unwrap());
+
+        fs::write(&layer, "changed").unwrap();
+        assert_ne!(base, chain_id(None, &layer).unwrap());
+        assert!(chain_id(None, &temp.path().join("missing.tar")).is_err());
+    }
+
+    #[test]
+    fn test_dir_snapshots() {
+        let temp = TempDir::new().unwrap();
+        let snapshotter = snapshotter_for(SnapshotterKind::Dir, temp.path(), ProvisionMode::Auto);
+
+        let scratch = snapshotter.root().join("tmp-base");
+        snapshotter.prepare(&scratch, None).unwrap();
+        fs::write(snapshotter.upper_dir(&scratch).join("base"), "1").unwrap();
+        snapshotter.commit("base", &scratch).unwrap();
+        assert!(snapshotter.exists("base"));
+        assert!(!scratch.exists());
+
+        let rootfs = temp.path().join("c1").join("rootfs");
+        snapshotter.prepare(&rootfs, Some("base")).unwrap();
+        assert_eq!(fs::read_to_string(rootfs.join("base")).unwrap(), "1");
+        fs::write(rootfs.join("base"), "2").unwrap();
+        assert_eq!(fs::read_to_string(snapshotter.root().join("base").join("base")).unwrap(), "1");
+        assert!(snapshotter.mounts(&rootfs).unwrap().is_empty());
+
+        snapshotter.remove(&rootfs).unwrap();
+        assert!(!rootfs.exists());
+    }
+
+    #[test]
+    fn test_dir_provision_modes() {
+        let temp = TempDir::new().unwrap();
+        let base = temp.path().join("snapshots/dir/base");
+        fs::create_dir_all(base.join("bin")).unwrap();
+        fs::write(base.join("bin/tool"), "elf").unwrap();
+        std::os::unix::fs::symlink("tool", base.join("bin/link")).unwrap();
+        let ino = |p: &Path| fs::symlink_metadata(p).unwrap().ino();
+
+        for mode in [ProvisionMode::Auto, ProvisionMode::Copy, ProvisionMode::Hardlink] {
+            let snapshotter = snapshotter_for(SnapshotterKind::Dir, temp.path(), mode);
+            let rootfs = temp.path().join(mode.to_string()).join("rootfs");
+            snapshotter.prepare(&rootfs, Some("base")).unwrap();
+
+            assert_eq!(fs::read_to_string(rootfs.join("bin/tool")).unwrap(), "elf");
+            assert_eq!(fs::read_link(rootfs.join("bin/link")).unwrap(), Path::new("tool"));
+            assert_eq!(ino(&rootfs.join("bin/tool")) == ino(&base.join("bin/tool")), mode == ProvisionMode::Hardlink);
+            assert_ne!(ino(&rootfs.join("bin")), ino(&base.join("bin")));
+        }
+        assert_eq!("hardlink".parse::<ProvisionMode>(), Ok(ProvisionMode::Hardlink));
+        assert!("symlink".parse::<ProvisionMode>().is_err());
+    }
+
+    #[test]
+    fn test_commit_existing_drops_snapshot() {
+        let temp = TempDir::new().unwrap();
+        let snapshotter = snapshotter_for(SnapshotterKind::Dir, temp.path(), ProvisionMode::Auto);
+        for scratch in ["tmp-a", "tmp-b"] {
+            let scratch = snapshotter.root().join(scratch);
+            snapshotter.prepare(&scratch, None).unwrap();
+            snapshotter.commit("layer", &scratch).unwrap();
+            assert!(!scratch.exists());
+        }
+        assert!(snapshotter.exists("layer"));
+    }
+
+    #[test]
+    fn test_remove_keeps_mounted_storage() {
+        let temp = TempDir::new().unwrap();
+        let snapshotter = snapshotter_for(SnapshotterKind::Dir, temp.path(), ProvisionMode::Auto);
+        let target = temp.path().join("rootfs");
+        fs::create_dir(&target).unwrap();
+        // Stands in for the loopback filesystem of a quota; needs CAP_SYS_ADMIN
+        if mount(Some("tmpfs"), &target, Some("tmpfs"), MsFlags::empty(), None::<&str>).is_err() {
+            return;
+        }
+        fs::create_dir_all(target.join("etc/conf.d")).unwrap();
+        fs::write(target.join("etc/hostname"), b"cubo").unwrap();
+
+        let removed = snapshotter.remove(&target);
+        let mounted = is_mount_point(&target);
+        let left = fs::read_dir(&target).map(|entries| entries.count());
+        umount2(&target, MntFlags::MNT_DETACH).unwrap();
+        removed.unwrap();
+        assert!(mounted);
+        assert_eq!(left.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_overlay_mounts_stack_parents() {
+        let temp = TempDir::new().unwrap();
+        let snapshotter = snapshotter_for(SnapshotterKind::Overlayfs, temp.path(), ProvisionMode::Auto);
+        let root = snapshotter.root().to_path_buf();
+
+        let mut parent: Option<&str> = None;
+        for name in ["base", "top"] {
+            let scratch = root.join(format!("tmp-{}", name));
+            snapshotter.prepare(&scratch, parent).unwrap();
+            fs::write(snapshotter.upper_dir(&scratch).join(name), name).unwrap();
+            snapshotter.commit(name, &scratch).unwrap();
+            parent = Some(name);
+        }
+        assert!(root.join("top").join("fs").join("top").exists());
+
+        let rootfs = temp.path().join("c1").join("rootfs");
+        snapshotter.prepare(&rootfs, Some("top")).unwrap();
+        let state = temp.path().join("c1").join("rootfs.overlay");
+        assert_eq!(snapshotter.upper_dir(&rootfs), state.join("upper"));
+
+        let mounts = snapshotter.mounts(&rootfs).unwrap();
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].fstype, "overlay");
+        assert_eq!(mounts[0].options, [
+            format!("lowerdir={}:{}", root.join("top/fs").display(), root.join("base/fs").display()),
+            format!("upperdir={}", state.join("upper").display()),
+            format!("workdir={}", state.join("work").display()),
+        ]);
+
+        snapshotter.remove(&rootfs).unwrap();
+        assert!(!rootfs.exists() && !state.exists());
+        assert!(snapshotter.prepare(&rootfs, Some("missing")).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+//! Resource usage of containers read from their cgroups, and the history of samples cubod
+//! records for each container
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::container::cgroup::CgroupManager;
+use crate::container::container_store::atomic_write_json;
+use crate::container::runtime::ContainerRuntime;
+use crate::error::{CuboError, Result};
+
+/// File of the recorded samples in the container directory
+pub const HISTORY_FILE: &str = "stats.json";
+
+/// Samples kept per container when the config file doesn't say
+pub const DEFAULT_HISTORY: usize = 1440;
+
+/// Resource usage of a container at one point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsSample {
+    pub timestamp: DateTime<Utc>,
+    /// CPU time used by the container so far, in microseconds
+    pub cpu_usec: u64,
+    /// CPU used since the previous sample, in percent of one CPU
+    pub cpu_percent: Option<f64>,
+    pub memory_bytes: u64,
+    pub memory_limit: Option<u64>,
+    pub pids: u64,
+    pub io_read_bytes: u64,
+    pub io_write_bytes: u64,
+}
+
+impl StatsSample {
+    /// Read the usage of the container whose cgroup is `cgroup`. Counters of controllers
+    /// that aren't enabled read as 0.
+    pub fn read(cgroup: &CgroupManager) -> Result<Self> {
+        if !cgroup.exists() {
+            return Err(CuboError::CgroupError {
+                path: cgroup.path().to_path_buf(),
+                message: "No cgroup, the container isn't running".to_string(),
+            });
+        }
+        let number = |file: &str| cgroup.read(file).ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        let cpu_usec = cgroup
+            .read("cpu.stat")
+            .ok()
+            .and_then(|stat| stat_field(&stat, "usage_usec"))
+            .unwrap_or(0);
+        let (io_read_bytes, io_write_bytes) = cgroup
+            .read("io.stat")
+            .map(|stat| {
+                stat.lines().fold((0, 0), |(read, written), device| {
+                    (read + stat_field(device, "rbytes").unwrap_or(0), written + stat_field(device, "wbytes").unwrap_or(0))
+                })
+            })
+            .unwrap_or((0, 0));
+
+        Ok(Self {
+            timestamp: Utc::now(),
+            cpu_usec,
+            cpu_percent: None,
+            memory_bytes: number("memory.current"),
+            memory_limit: cgroup.read("memory.max").ok().and_then(|max| max.parse().ok()),
+            pids: number("pids.current"),
+            io_read_bytes,
+            io_write_bytes,
+        })
+    }
+
+    /// This sample with the CPU used since `previous`
+    pub fn since(mut self, previous: &StatsSample) -> Self {
+        let elapsed = (self.timestamp - previous.timestamp).num_microseconds().unwrap_or(0);
+        if elapsed > 0 && self.cpu_usec >= previous.cpu_usec {
+            self.cpu_percent = Some((self.cpu_usec - previous.cpu_usec) as f64 * 100.0 / elapsed as f64);
+        }
+        self
+    }
+}
+
+/// A `key value` field of a cgroup stat file, or `key=value` of an io.stat line
+fn stat_field(stat: &str, key: &str) -> Option<u64> {
+    stat.split_whitespace()
+        .flat_map(|word| word.split('='))
+        .collect::<Vec<_>>()
+        .windows(2)
+        .find(|pair| pair[0] == key)
+        .and_then(|pair| pair[1].parse().ok())
+}
+
+/// The last samples of a container, oldest first, kept in its directory
+pub struct StatsHistory {
+    path: PathBuf,
+    capacity: usize,
+}
+
+impl StatsHistory {
+    pub fn new(container_dir: &Path, capacity: usize) -> Self {
+        Self { path: container_dir.join(HISTORY_FILE), capacity }
+    }
+
+    /// The recorded samples; none when nothing was recorded yet
+    pub fn load(&self) -> Result<Vec<StatsSample>> {
+        match fs::read_to_string(&self.path) {
+            Ok(data) => serde_json::from_str(&data).map_err(|e| {
+                CuboError::SystemError(format!("Failed to parse {}: {}", self.path.display(), e))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add a sample, with its CPU use since the previous one, dropping the oldest samples
+    /// past the capacity
+    pub fn record(&self, sample: StatsSample) -> Result<StatsSample> {
+        let mut samples = self.load().unwrap_or_else(|e| {
+            warn!("Starting a new stats history: {}", e);
+            Vec::new()
+        });
+        let sample = match samples.last() {
+            Some(previous) => sample.since(previous),
+            None => sample,
+        };
+        samples.push(sample.clone());
+        let excess = samples.len().saturating_sub(self.capacity);
+        samples.drain(..excess);
+        atomic_write_json(&self.path, &samples)?;
+        Ok(sample)
+    }
+}
+
+/// Record a sample of every running container. Returns how many were recorded.
+pub async fn record_running(runtime: &ContainerRuntime) -> Result<usize> {
+    let config = runtime.config();
+    let mut recorded = 0;
+    for container in runtime.list_containers(false).await? {
+        let cgroup = config.cgroup(&container.id);
+        let history = StatsHistory::new(&runtime.root_dir().join(&container.id), config.stats_history);
+        match StatsSample::read(&cgroup).and_then(|sample| history.record(sample)) {
+            Ok(_) => recorded += 1,
+            Err(e) => debug!("No stats sample of {}: {}", container.id, e),
+        }
+    }
+    Ok(recorded)
+}
+
+/// Record samples of the running containers every `interval`, until the task is dropped
+pub async fn record_every(runtime: ContainerRuntime, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        if let Err(e) = record_running(&runtime).await {
+            warn!("Failed to record container stats: {}", e);
+        }
+    }
+}
+
+/// How `cubo stats --export` writes the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(StatsFormat::Csv),
+            "json" => Ok(StatsFormat::Json),
+            other => Err(format!("unknown export format '{}' (expected csv or json)", other)),
+        }
+    }
+}
+
+/// A sample with the container it belongs to
+#[derive(Debug, Serialize)]
+pub struct ExportRow<'a> {
+    pub container: &'a str,
+    #[serde(flatten)]
+    pub sample: &'a StatsSample,
+}
+
+/// Write `rows` as CSV with a header line, or as a JSON array
+pub fn export(rows: &[ExportRow<'_>], format: StatsFormat) -> Result<String> {
+    match format {
+        StatsFormat::Json => serde_json::to_string_pretty(rows)
+            .map_err(|e| CuboError::SystemError(format!("Failed to serialize stats: {}", e))),
+        StatsFormat::Csv => {
+            let mut csv = String::from(
+                "container,timestamp,cpu_usec,cpu_percent,memory_bytes,memory_limit,pids,io_read_bytes,io_write_bytes\n",
+            );
+            let optional = |value: Option<String>| value.unwrap_or_default();
+            for row in rows {
+                let sample = row.sample;
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{},{},{},{},{}",
+                    row.container,
+                    sample.timestamp.to_rfc3339(),
+                    sample.cpu_usec,
+                    optional(sample.cpu_percent.map(|p| format!("{:.2}", p))),
+                    sample.memory_bytes,
+                    optional(sample.memory_limit.map(|l| l.to_string())),
+                    sample.pids,
+                    sample.io_read_bytes,
+                    sample.io_write_bytes,
+                );
+            }
+            Ok(csv)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use tempfile::TempDir;
+
+    fn sample(seconds: i64, cpu_usec: u64) -> StatsSample {
+        StatsSample {
+            timestamp: DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap(),
+            cpu_usec,
+            cpu_percent: None,
+            memory_bytes: 64 << 20,
+            memory_limit: Some(512 << 20),
+            pids: 3,
+            io_read_bytes: 4096,
+            io_write_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn test_read_cgroup_files() {
+        let temp = TempDir::new().unwrap();
+        let cgroup = CgroupManager::new(temp.path(), "abc");
+        fs::create_dir_all(cgroup.path()).unwrap();
+        fs::write(cgroup.path().join("cpu.stat"), "usage_usec 250000\nuser_usec 200000\n").unwrap();
+        fs::write(cgroup.path().join("memory.current"), "1048576\n").unwrap();
+        fs::write(cgroup.path().join("memory.max"), "max\n").unwrap();
+        fs::write(cgroup.path().join("io.stat"), "8:0 rbytes=100 wbytes=20 rios=1\n259:0 rbytes=5 wbytes=1\n").unwrap();
+
+        let sample = StatsSample::read(&cgroup).unwrap();
+        assert_eq!(sample.cpu_usec, 250000);
+        assert_eq!(sample.memory_bytes, 1048576);
+        assert_eq!(sample.memory_limit, None);
+        assert_eq!(sample.pids, 0);
+        assert_eq!((sample.io_read_bytes, sample.io_write_bytes), (105, 21));
+
+        assert!(StatsSample::read(&CgroupManager::new(temp.path(), "gone")).is_err());
+    }
+
+    #[test]
+    fn test_cpu_percent_since_previous() {
+        let second = sample(10, 1_500_000).since(&sample(0, 500_000));
+        assert_eq!(second.cpu_percent, Some(10.0));
+        // A restarted container's counter starts over
+        assert_eq!(sample(10, 0).since(&sample(0, 500_000)).cpu_percent, None);
+    }
+
+    #[test]
+    fn test_history_is_a_ring_buffer() {
+        let temp = TempDir::new().unwrap();
+        let history = StatsHistory::new(temp.path(), 3);
+        assert!(history.load().unwrap().is_empty());
+        for i in 0..5 {
+            history.record(sample(i * 10, i as u64 * 1_000_000)).unwrap();
+        }
+
+        let samples = history.load().unwrap();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].timestamp, sample(20, 0).timestamp);
+        assert_eq!(samples[2].cpu_percent, Some(10.0));
+        assert!(samples[2].timestamp - samples[0].timestamp == TimeDelta::seconds(20));
+    }
+
+    #[test]
+    fn test_export() {
+        let first = sample(0, 0);
+        let second = sample(10, 1_000_000).since(&first);
+        let rows = [ExportRow { container: "web", sample: &first }, ExportRow { container: "web", sample: &second }];
+
+        let csv = export(&rows, StatsFormat::Csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("container,timestamp,cpu_usec,cpu_percent"));
+        assert_eq!(lines[1], "web,2023-11-14T22:13:20+00:00,0,,67108864,536870912,3,4096,0");
+        assert!(lines[2].contains(",1000000,10.00,"));
+
+        let json: serde_json::Value = serde_json::from_str(&export(&rows, StatsFormat::Json).unwrap()).unwrap();
+        assert_eq!(json[1]["container"], "web");
+        assert_eq!(json[1]["cpu_percent"], 10.0);
+        assert!("xml".parse::<StatsFormat>().is_err());
+    }
+}
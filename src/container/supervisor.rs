@@ -0,0 +1,153 @@
+//! Restart policies of detached containers. `cubo run --restart` hands the container to a
+//! `cubo supervise` process, which starts it, waits for it to exit and starts it again for
+//! as long as the policy says.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+use tracing::info;
+
+use crate::container::runtime::ContainerRuntime;
+use crate::error::{CuboError, Result};
+
+/// Output of the supervisor process, in the container's bundle
+pub const SUPERVISOR_LOG: &str = "supervisor.log";
+
+/// How long a (re)started container may take to get a process
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Delay before the `restarts`th restart, doubling from 100ms up to a minute so a container
+/// that keeps crashing doesn't spin
+pub fn backoff(restarts: u32) -> Duration {
+    let doublings = restarts.saturating_sub(1).min(16);
+    (MIN_BACKOFF * 2u32.pow(doublings)).min(MAX_BACKOFF)
+}
+
+/// Start a created container and keep restarting it under its restart policy. Returns
+/// once the policy lets it stay down, or it was stopped or removed.
+pub async fn supervise(runtime: &ContainerRuntime, container_id: &str) -> Result<()> {
+    runtime.start_container(container_id, true).await?;
+    loop {
+        let pid = runtime.wait_for_pid(container_id, START_TIMEOUT).await?;
+        let exit_code = runtime.wait_for_exit(pid).await;
+        let recorded = runtime.record_exit(container_id, exit_code).await;
+        runtime.reap(pid);
+        let Some(restarts) = recorded? else {
+            info!("Container {} is down, leaving it stopped", container_id);
+            return Ok(());
+        };
+
+        let delay = backoff(restarts);
+        info!(
+            "Container {} exited with {:?}, restart {} in {:?}",
+            container_id, exit_code, restarts, delay
+        );
+        sleep(delay).await;
+        if !runtime.restart_pending(container_id).await? {
+            return Ok(());
+        }
+        runtime.start_container(container_id, true).await?;
+    }
+}
+
+/// Hand a created container to a new `cubo supervise` process, in a session of its own so
+/// it outlives the terminal, and wait until the container runs. `global_args` give the
+/// supervisor the configuration of this process.
+pub async fn launch(runtime: &ContainerRuntime, container_id: &str, global_args: &[OsString]) -> Result<()> {
+    let log_path = runtime.root_dir().join(container_id).join(SUPERVISOR_LOG);
+    let log = File::create(&log_path)
+        .map_err(|e| CuboError::SystemError(format!("Failed to create {}: {}", log_path.display(), e)))?;
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .args(global_args)
+        .arg("supervise")
+        .arg(container_id)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log);
+    unsafe {
+        command.pre_exec(|| nix::unistd::setsid().map(|_| ()).map_err(std::io::Error::from));
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| CuboError::SystemError(format!("Failed to start the supervisor: {}", e)))?;
+
+    let deadline = Instant::now() + START_TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let output = fs::read_to_string(&log_path).unwrap_or_default();
+            let reason = output.lines().last().unwrap_or("no output");
+            return Err(CuboError::SystemError(format!(
+                "Supervisor of container {} exited ({}): {}",
+                container_id, status, reason
+            )));
+        }
+        let container = runtime.reload_container(container_id).await?;
+        if container.is_running() && container.pid.is_some() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            return Err(CuboError::ContainerNotRunning(container_id.to_string()));
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::container::{Container, ContainerStatus, RestartPolicy};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_backoff_doubles_up_to_a_minute() {
+        assert_eq!(backoff(1), Duration::from_millis(100));
+        assert_eq!(backoff(2), Duration::from_millis(200));
+        assert_eq!(backoff(4), Duration::from_millis(800));
+        assert_eq!(backoff(12), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    async fn running_container(runtime: &ContainerRuntime, policy: RestartPolicy) -> String {
+        let container = Container::new("test:latest".to_string(), vec!["true".to_string()])
+            .with_restart_policy(policy);
+        let id = runtime.create_container(container).await.unwrap();
+        let mut container = runtime.get_container(&id).await.unwrap();
+        container.update_status(ContainerStatus::Running);
+        crate::container::container_store::save_state(runtime.root_dir(), &container).unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_record_exit_follows_the_policy() {
+        let temp = TempDir::new().unwrap();
+        let config = RuntimeConfig { root_dir: temp.path().to_path_buf(), ..Default::default() };
+        let runtime = ContainerRuntime::new(config).unwrap();
+
+        let id = running_container(&runtime, RestartPolicy::OnFailure { max_retries: 1 }).await;
+        assert_eq!(runtime.record_exit(&id, Some(3)).await.unwrap(), Some(1));
+        let container = runtime.reload_container(&id).await.unwrap();
+        assert_eq!(container.status, ContainerStatus::Restarting);
+        assert_eq!(container.exit_code, Some(3));
+        assert!(runtime.restart_pending(&id).await.unwrap());
+
+        // Stopping it during the backoff keeps the supervisor from starting it again
+        runtime.stop_container(&id, None).await.unwrap();
+        assert!(!runtime.restart_pending(&id).await.unwrap());
+        assert_eq!(runtime.record_exit(&id, Some(3)).await.unwrap(), None);
+
+        let id = running_container(&runtime, RestartPolicy::No).await;
+        assert_eq!(runtime.record_exit(&id, Some(1)).await.unwrap(), None);
+        assert_eq!(runtime.reload_container(&id).await.unwrap().status, ContainerStatus::Stopped);
+    }
+}
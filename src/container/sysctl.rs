@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::container::{ContainerConfig, IpcMode, NetworkMode};
+use crate::error::{CuboError, Result};
+
+/// IPC sysctls that are namespaced by the kernel
+const IPC_SYSCTLS: &[&str] = &[
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+
+/// Namespace a sysctl belongs to, as far as the allowlist is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysctlKind {
+    Net,
+    Ipc,
+}
+
+/// Returns which namespace a sysctl lives in, or None if it is not safe to set
+pub fn classify(key: &str) -> Option<SysctlKind> {
+    if key.starts_with("net.") {
+        Some(SysctlKind::Net)
+    } else if IPC_SYSCTLS.contains(&key) || key.starts_with("fs.mqueue.") {
+        Some(SysctlKind::Ipc)
+    } else {
+        None
+    }
+}
+
+/// Parse "net.ipv4.ip_forward=1" into key and value
+pub fn parse_sysctl(spec: &str) -> Result<(String, String)> {
+    match spec.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains('/') && !key.contains("..") => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(CuboError::InvalidConfiguration(format!(
+            "Invalid sysctl '{}', expected <key>=<value>",
+            spec
+        ))),
+    }
+}
+
+/// Check the sysctls against the allowlist and the namespaces the container gets.
+pub fn validate_sysctls(config: &ContainerConfig) -> Result<()> {
+    for key in config.sysctls.keys() {
+        match classify(key) {
+            Some(SysctlKind::Net) => {
+                if matches!(config.network_mode, NetworkMode::Host) {
+                    return Err(CuboError::InvalidConfiguration(format!(
+                        "sysctl {} is not allowed with the host network",
+                        key
+                    )));
+                }
+            }
+            Some(SysctlKind::Ipc) => {
+                if matches!(config.ipc_mode, IpcMode::Host) {
+                    return Err(CuboError::InvalidConfiguration(format!(
+                        "sysctl {} is not allowed with the host IPC namespace",
+                        key
+                    )));
+                }
+            }
+            None => {
+                return Err(CuboError::InvalidConfiguration(format!(
+                    "sysctl {} is not allowed; only net.* and IPC sysctls can be set",
+                    key
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the sysctls below `proc_sys` (normally /proc/sys inside the container)
+pub fn apply_sysctls(sysctls: &HashMap<String, String>, proc_sys: &Path) -> Result<()> {
+    for (key, value) in sysctls {
+        let path = proc_sys.join(key.replace('.', "/"));
+        fs::write(&path, value).map_err(|e| {
+            CuboError::SystemError(format!("Failed to set sysctl {}={}: {}", key, value, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config_with(key: &str, network_mode: NetworkMode) -> ContainerConfig {
+        let mut config = ContainerConfig {
+            network_mode,
+            ..Default::default()
+        };
+        config.sysctls.insert(key.to_string(), "1".to_string());
+        config
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify("net.ipv4.ip_forward"), Some(SysctlKind::Net));
+        assert_eq!(classify("kernel.shmmax"), Some(SysctlKind::Ipc));
+        assert_eq!(classify("fs.mqueue.msg_max"), Some(SysctlKind::Ipc));
+        assert_eq!(classify("kernel.hostname"), None);
+        assert_eq!(classify("vm.swappiness"), None);
+    }
+
+    #[test]
+    fn test_parse_sysctl() {
+        let (key, value) = parse_sysctl("net.ipv4.ip_unprivileged_port_start=0").unwrap();
+        assert_eq!(key, "net.ipv4.ip_unprivileged_port_start");
+        assert_eq!(value, "0");
+        assert!(parse_sysctl("net.ipv4.ip_forward").is_err());
+        assert!(parse_sysctl("=1").is_err());
+        assert!(parse_sysctl("net/../../x=1").is_err());
+    }
+
+    #[test]
+    fn test_validate_net_sysctl() {
+        assert!(validate_sysctls(&config_with("net.ipv4.ip_forward", NetworkMode::Bridge)).is_ok());
+        assert!(validate_sysctls(&config_with("net.ipv4.ip_forward", NetworkMode::Host)).is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_non_namespaced_sysctl() {
+        let err = validate_sysctls(&config_with("vm.swappiness", NetworkMode::Bridge)).unwrap_err();
+        assert!(err.to_string().contains("not allowed"));
+    }
+
+    #[test]
+    fn test_validate_ipc_sysctl() {
+        let mut config = config_with("kernel.shmmax", NetworkMode::Bridge);
+        assert!(validate_sysctls(&config).is_ok());
+        config.ipc_mode = IpcMode::Host;
+        assert!(validate_sysctls(&config).is_err());
+    }
+
+    #[test]
+    fn test_apply_sysctls() {
+        let temp = TempDir::new().unwrap();
+        fs::create_dir_all(temp.path().join("net/ipv4")).unwrap();
+
+        let mut sysctls = HashMap::new();
+        sysctls.insert("net.ipv4.ip_forward".to_string(), "1".to_string());
+        apply_sysctls(&sysctls, temp.path()).unwrap();
+
+        let written = fs::read_to_string(temp.path().join("net/ipv4/ip_forward")).unwrap();
+        assert_eq!(written, "1");
+    }
+
+    #[test]
+    fn test_apply_sysctls_missing_key() {
+        let temp = TempDir::new().unwrap();
+        let mut sysctls = HashMap::new();
+        sysctls.insert("net.ipv4.nonexistent".to_string(), "1".to_string());
+        assert!(apply_sysctls(&sysctls, temp.path()).is_err());
+    }
+}
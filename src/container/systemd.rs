@@ -0,0 +1,777 @@
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::container::{namespace, Container, IpcMode, LogDriverKind, MountType, NetworkMode, RestartPolicy, UsernsMode, VolumeMount};
+use crate::error::{CuboError, Result};
+
+/// How systemd learns that the container of a generated unit is up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServiceType {
+    /// `cubo run` reports readiness and the container's PID over $NOTIFY_SOCKET
+    #[default]
+    Notify,
+    /// The service is up once `cubo run` has started the container and exited
+    Forking,
+}
+
+impl std::str::FromStr for ServiceType {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "notify" => Ok(ServiceType::Notify),
+            "forking" => Ok(ServiceType::Forking),
+            _ => Err(format!("invalid service type '{}', expected notify or forking", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ServiceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceType::Notify => write!(f, "notify"),
+            ServiceType::Forking => write!(f, "forking"),
+        }
+    }
+}
+
+/// Where the notify socket appears in containers run with `--sdnotify container`
+pub const CONTAINER_NOTIFY_SOCKET: &str = "/run/notify/notify.sock";
+
+/// Who tells systemd that a container started by `cubo run` is ready
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SdNotifyMode {
+    /// `cubo run` sends READY once the container is started (and healthy with --wait-healthy)
+    #[default]
+    Cubo,
+    /// The socket is forwarded into the container, whose service sends READY itself
+    Container,
+    /// Nothing is sent
+    Ignore,
+}
+
+impl std::str::FromStr for SdNotifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cubo" => Ok(SdNotifyMode::Cubo),
+            "container" => Ok(SdNotifyMode::Container),
+            "ignore" => Ok(SdNotifyMode::Ignore),
+            _ => Err(format!("invalid sdnotify mode '{}', expected cubo, container or ignore", s)),
+        }
+    }
+}
+
+/// Message `cubo run` sends once the container is started. MAINPID lets systemd supervise
+/// the container process after `cubo run` exits.
+pub fn started_state(mode: SdNotifyMode, pid: Option<u32>) -> Option<String> {
+    match (mode, pid) {
+        (SdNotifyMode::Cubo, Some(pid)) => Some(format!("READY=1\nMAINPID={}", pid)),
+        (SdNotifyMode::Cubo, None) => Some("READY=1".to_string()),
+        (SdNotifyMode::Container, Some(pid)) => Some(format!("MAINPID={}", pid)),
+        (SdNotifyMode::Container, None) | (SdNotifyMode::Ignore, _) => None,
+    }
+}
+
+/// Bind mount of `$NOTIFY_SOCKET` at CONTAINER_NOTIFY_SOCKET. Abstract sockets live in the
+/// host network namespace and can't be reached from a container.
+pub fn notify_socket_mount(socket: &str) -> Result<VolumeMount> {
+    if socket.starts_with('@') {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "The abstract notify socket {} can't be forwarded into a container",
+            socket
+        )));
+    }
+    Ok(VolumeMount::bind(socket.to_string(), CONTAINER_NOTIFY_SOCKET.to_string(), false))
+}
+
+/// Settings of a generated unit that the container itself doesn't record
+#[derive(Debug, Clone)]
+pub struct UnitOptions {
+    pub service_type: ServiceType,
+    /// Policy to map instead of the container's own
+    pub restart_policy: Option<RestartPolicy>,
+    /// Seconds systemd waits before restarting the service
+    pub restart_sec: Option<u64>,
+    /// cubo binary the unit runs
+    pub cubo: PathBuf,
+    /// State directory handed to cubo as CUBO_ROOT
+    pub root_dir: PathBuf,
+}
+
+/// Name the unit runs the container under; unnamed containers keep their short id
+pub fn service_container_name(container: &Container) -> String {
+    container.name.clone().unwrap_or_else(|| container.id.chars().take(12).collect())
+}
+
+pub fn unit_file_name(container: &Container) -> String {
+    format!("container-{}.service", service_container_name(container))
+}
+
+/// Render a service unit that recreates `container` with `cubo run` on every start and
+/// removes it on stop, so it comes back after a reboot
+pub fn render_unit(container: &Container, options: &UnitOptions) -> Result<String> {
+    let name = service_container_name(container);
+    let cubo = quote_arg(&options.cubo.to_string_lossy());
+    let restart_policy = options.restart_policy.as_ref().unwrap_or(&container.config.restart_policy);
+
+    let mut run = vec!["run".to_string(), "--name".to_string(), name.clone()];
+    if options.service_type == ServiceType::Notify && container.config.health_check.is_some() {
+        // Only report the service ready once the container is healthy
+        run.push("--wait-healthy".to_string());
+    }
+    run.extend(run_args(container)?);
+    let start = run.iter().map(|a| quote_arg(a)).collect::<Vec<_>>().join(" ");
+
+    let mut unit = String::new();
+    unit.push_str(&format!("# {}\n", unit_file_name(container)));
+    unit.push_str("# Generated by cubo generate systemd\n\n");
+
+    unit.push_str("[Unit]\n");
+    unit.push_str(&format!("Description=cubo container {}\n", name));
+    unit.push_str("Wants=network-online.target\n");
+    unit.push_str("After=network-online.target\n");
+    unit.push_str(&format!("RequiresMountsFor={}\n", quote_arg(&options.root_dir.to_string_lossy())));
+    if let RestartPolicy::OnFailure { max_retries } = restart_policy {
+        if *max_retries > 0 {
+            // The first start counts against the burst too
+            unit.push_str("StartLimitIntervalSec=infinity\n");
+            unit.push_str(&format!("StartLimitBurst={}\n", max_retries + 1));
+        }
+    }
+
+    unit.push_str("\n[Service]\n");
+    unit.push_str(&format!(
+        "Environment={}\n",
+        quote_arg(&format!("CUBO_ROOT={}", options.root_dir.to_string_lossy()))
+    ));
+    unit.push_str(&format!("Type={}\n", options.service_type));
+    if options.service_type == ServiceType::Notify {
+        // READY comes from cubo run, which isn't the main process once it reports MAINPID
+        unit.push_str("NotifyAccess=all\n");
+    }
+    unit.push_str(&format!("Restart={}\n", systemd_restart(restart_policy)));
+    if let Some(secs) = options.restart_sec {
+        unit.push_str(&format!("RestartSec={}\n", secs));
+    }
+    unit.push_str("TimeoutStopSec=70\n");
+    unit.push_str(&format!("ExecStartPre=-{} rm -f {}\n", cubo, quote_arg(&name)));
+    unit.push_str(&format!("ExecStart={} {}\n", cubo, start));
+    unit.push_str(&format!("ExecStop={} stop -f {}\n", cubo, quote_arg(&name)));
+    unit.push_str(&format!("ExecStopPost=-{} rm -f {}\n", cubo, quote_arg(&name)));
+
+    unit.push_str("\n[Install]\n");
+    unit.push_str("WantedBy=default.target\n");
+    Ok(unit)
+}
+
+/// systemd has no notion of a stop the user asked for, so unless-stopped behaves like always
+fn systemd_restart(policy: &RestartPolicy) -> &'static str {
+    match policy {
+        RestartPolicy::No => "no",
+        RestartPolicy::Always | RestartPolicy::UnlessStopped => "always",
+        RestartPolicy::OnFailure { .. } => "on-failure",
+    }
+}
+
+/// `cubo run` flags, blueprint and command that recreate the container's config.
+/// Settings `cubo run` has no flag for are dropped with a warning.
+pub fn run_args(container: &Container) -> Result<Vec<String>> {
+    let config = &container.config;
+    let mut args = Vec::new();
+    let mut flag = |name: &str, value: String| {
+        args.push(name.to_string());
+        args.push(value);
+    };
+
+    let mut env: Vec<_> = config.env_vars.iter().collect();
+    env.sort();
+    for (key, value) in env {
+        flag("-e", format!("{}={}", key, value));
+    }
+
+    for mount in &config.volume_mounts {
+        if matches!(mount.mount_type, MountType::Tmpfs) {
+            warn!("Skipping {:?} mount on {}: cubo run -v only creates bind mounts and named volumes", mount.mount_type, mount.container_path);
+            continue;
+        }
+        let mut options = Vec::new();
+        if mount.read_only {
+            options.push("ro".to_string());
+        }
+        options.extend(mount.relabel.map(|relabel| relabel.to_string()));
+        let suffix = if options.is_empty() { String::new() } else { format!(":{}", options.join(",")) };
+        flag("-v", format!("{}:{}{}", mount.host_path, mount.container_path, suffix));
+    }
+
+    for port in &config.ports {
+        if let Some(ip) = &port.host_ip {
+            warn!("Publishing port {} on all addresses instead of {}", port.host_port, ip);
+        }
+        flag("-p", format!("{}:{}/{}", port.host_port, port.container_port, port.protocol));
+    }
+
+    if let Some(workdir) = &config.working_dir {
+        flag("-w", workdir.clone());
+    }
+    if let Some(limit) = config.pids_limit {
+        flag("--pids-limit", limit.to_string());
+    }
+    if let Some(limit) = config.memory_limit {
+        flag("--memory", limit.to_string());
+    }
+    if let Some(limit) = config.memory_swap {
+        flag("--memory-swap", limit.to_string());
+    }
+    if let Some(reservation) = config.memory_reservation {
+        flag("--memory-reservation", reservation.to_string());
+    }
+    if let Some(score) = config.oom_score_adj {
+        flag("--oom-score-adj", score.to_string());
+    }
+    if let Some(nice) = config.nice {
+        flag("--nice", nice.to_string());
+    }
+    if let Some(policy) = config.sched_policy {
+        flag("--sched-policy", policy.to_string());
+    }
+    if let Some(priority) = config.rt_priority {
+        flag("--rt-priority", priority.to_string());
+    }
+    if let Some(runtime) = config.rt_runtime {
+        flag("--rt-runtime", runtime.to_string());
+    }
+    if let Some(priority) = config.io_priority {
+        flag("--ionice", priority.to_string());
+    }
+    if let Some(weight) = config.blkio_weight {
+        flag("--blkio-weight", weight.to_string());
+    }
+    if let Some(cpus) = &config.cpuset_cpus {
+        flag("--cpuset-cpus", cpus.clone());
+    }
+    if let Some(mems) = &config.cpuset_mems {
+        flag("--cpuset-mems", mems.clone());
+    }
+    for device in &config.device_read_bps {
+        flag("--device-read-bps", format!("{}:{}", device.path, device.rate));
+    }
+    for device in &config.device_write_bps {
+        flag("--device-write-bps", format!("{}:{}", device.path, device.rate));
+    }
+    for ulimit in &config.ulimits {
+        flag("--ulimit", format!("{}={}:{}", ulimit.name, ulimit.soft, ulimit.hard));
+    }
+    for opt in config.label_options.security_opts() {
+        flag("--security-opt", opt);
+    }
+    if let Some(profile) = &config.apparmor_profile {
+        flag("--security-opt", format!("apparmor={}", profile));
+    }
+    if config.landlock {
+        flag("--security-opt", "landlock=on".to_string());
+    }
+    for opt in namespace::path_security_opts(&config.masked_paths, &config.readonly_paths) {
+        flag("--security-opt", opt);
+    }
+    for offset in &config.time_offsets {
+        flag("--time-offset", format!("{}={}", offset.clock, offset.secs));
+    }
+    if let Some(size) = config.storage_size {
+        flag("--storage-opt", format!("size={}", size));
+    }
+
+    let mut sysctls: Vec<_> = config.sysctls.iter().collect();
+    sysctls.sort();
+    for (key, value) in sysctls {
+        flag("--sysctl", format!("{}={}", key, value));
+    }
+
+    if let Some(hostname) = &config.hostname {
+        flag("--hostname", hostname.clone());
+    }
+    if let Some(domainname) = &config.domainname {
+        flag("--domainname", domainname.clone());
+    }
+    if config.network_mode != NetworkMode::Bridge {
+        flag("--network", config.network_mode.to_string());
+    }
+    if let Some(ip) = &config.ip_address {
+        flag("--ip", ip.clone());
+    }
+    if let Some(mac) = &config.mac_address {
+        flag("--mac-address", mac.clone());
+    }
+    for server in &config.dns {
+        flag("--dns", server.clone());
+    }
+    for domain in &config.dns_search {
+        flag("--dns-search", domain.clone());
+    }
+    for host in &config.extra_hosts {
+        flag("--add-host", format!("{}:{}", host.hostname, host.ip));
+    }
+
+    if config.ipc_mode == IpcMode::Host {
+        flag("--ipc", "host".to_string());
+    }
+    match config.userns_mode {
+        UsernsMode::Private => {}
+        UsernsMode::Host => flag("--userns", "host".to_string()),
+        UsernsMode::KeepId => flag("--userns", "keep-id".to_string()),
+    }
+    if let Some(signal) = &config.stop_signal {
+        flag("--stop-signal", signal.clone());
+    }
+
+    if config.log_driver != LogDriverKind::JsonFile {
+        flag("--log-driver", config.log_driver.to_string());
+    }
+    let log = &config.log_options;
+    if let Some(size) = log.max_size {
+        flag("--log-opt", format!("max-size={}", size));
+    }
+    if let Some(count) = log.max_file {
+        flag("--log-opt", format!("max-file={}", count));
+    }
+    if let Some(path) = &log.path {
+        flag("--log-opt", format!("path={}", path.display()));
+    }
+
+    if let Some(check) = &config.health_check {
+        flag("--health-cmd", check.command.clone());
+        flag("--health-interval", check.interval.to_string());
+        flag("--health-timeout", check.timeout.to_string());
+        flag("--health-retries", check.retries.to_string());
+    }
+
+    if config.init {
+        args.push("--init".to_string());
+    }
+
+    let dropped = [
+        ("CPU limit", config.cpu_limit.is_some()),
+        ("user", config.user.is_some()),
+        ("extra networks", !config.networks.is_empty()),
+        ("network aliases", !config.aliases.is_empty()),
+    ];
+    for (setting, _) in dropped.iter().filter(|(_, set)| *set) {
+        warn!("The {} of container {} can't be passed to cubo run and is left out of the unit", setting, container.id);
+    }
+    if config.sandbox.is_some() {
+        return Err(CuboError::InvalidConfiguration(
+            "Containers joining a sandbox can't be recreated by a systemd unit".to_string()
+        ));
+    }
+
+    // Everything after the blueprint is the command, even if it looks like a flag
+    args.push("--".to_string());
+    args.push(container.blueprint.clone());
+    args.extend(container.command.iter().cloned());
+    Ok(args)
+}
+
+/// Quote an argument for ExecStart= and friends. systemd expands `%` specifiers and `$`
+/// variables even inside quotes, so those are doubled unconditionally.
+pub fn quote_arg(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    let needs_quotes = escaped.is_empty()
+        || escaped == ";"
+        || escaped.chars().any(|c| c.is_whitespace() || "\"'\\".contains(c));
+    if !needs_quotes {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Send `state` to the service manager over $NOTIFY_SOCKET, including abstract sockets
+/// ("@name"). Returns false when cubo isn't running under a Type=notify unit.
+pub fn notify(state: &str) -> Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Client cubo reaches the service manager's D-Bus API with
+pub const BUSCTL: &str = "busctl";
+
+/// Slice holding the scopes of containers run with the systemd cgroup driver
+pub const CUBO_SLICE: &str = "cubo.slice";
+
+/// How long a process may take to show up in the scope started for it
+const SCOPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether systemd is the init system, like sd_booted(3)
+pub fn booted() -> bool {
+    Path::new("/run/systemd/system").is_dir()
+}
+
+/// Transient scope unit of a container run with the systemd cgroup driver
+pub fn scope_unit(container_id: &str) -> String {
+    format!("cubo-{}.scope", container_id)
+}
+
+/// cgroup of a container's scope below the cgroup root: in cubo.slice of the system
+/// manager for root, of the user's manager otherwise
+pub fn scope_cgroup(container_id: &str, uid: u32) -> PathBuf {
+    let slice = match uid {
+        0 => PathBuf::from(CUBO_SLICE),
+        _ => PathBuf::from(format!("user.slice/user-{0}.slice/user@{0}.service", uid)).join(CUBO_SLICE),
+    };
+    slice.join(scope_unit(container_id))
+}
+
+/// Value of a unit property, with its D-Bus type
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Str(String),
+    Bool(bool),
+    U64(u64),
+    U32s(Vec<u32>),
+    Bytes(Vec<u8>),
+    /// (device path, bytes per second) pairs
+    DeviceLimits(Vec<(String, u64)>),
+}
+
+/// A property of a transient unit, like `MemoryMax`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitProperty {
+    pub name: &'static str,
+    pub value: PropertyValue,
+}
+
+impl UnitProperty {
+    pub fn new(name: &'static str, value: PropertyValue) -> Self {
+        Self { name, value }
+    }
+
+    /// The property in busctl's notation: name, signature, then the values
+    fn busctl_args(&self) -> Vec<String> {
+        let mut args = vec![self.name.to_string()];
+        match &self.value {
+            PropertyValue::Str(s) => args.extend(["s".to_string(), s.clone()]),
+            PropertyValue::Bool(b) => args.extend(["b".to_string(), b.to_string()]),
+            PropertyValue::U64(n) => args.extend(["t".to_string(), n.to_string()]),
+            PropertyValue::U32s(list) => {
+                args.extend(["au".to_string(), list.len().to_string()]);
+                args.extend(list.iter().map(u32::to_string));
+            }
+            PropertyValue::Bytes(bytes) => {
+                args.extend(["ay".to_string(), bytes.len().to_string()]);
+                args.extend(bytes.iter().map(u8::to_string));
+            }
+            PropertyValue::DeviceLimits(limits) => {
+                args.extend(["a(st)".to_string(), limits.len().to_string()]);
+                for (path, rate) in limits {
+                    args.extend([path.clone(), rate.to_string()]);
+                }
+            }
+        }
+        args
+    }
+}
+
+/// An array of properties in busctl's notation
+fn properties_args(properties: &[UnitProperty]) -> Vec<String> {
+    let mut args = vec![properties.len().to_string()];
+    args.extend(properties.iter().flat_map(UnitProperty::busctl_args));
+    args
+}
+
+/// Call a method of the service manager; the user's manager unless running as root
+fn call_manager(method: &str, signature: &str, args: Vec<String>) -> Result<()> {
+    let mut command = Command::new(BUSCTL);
+    if !nix::unistd::geteuid().is_root() {
+        command.arg("--user");
+    }
+    command
+        .args(["call", "org.freedesktop.systemd1", "/org/freedesktop/systemd1", "org.freedesktop.systemd1.Manager"])
+        .arg(method)
+        .arg(signature)
+        .args(args);
+    debug!("Calling systemd {}", method);
+    let output = command
+        .output()
+        .map_err(|e| CuboError::SystemError(format!("Failed to run {}: {}", BUSCTL, e)))?;
+    if !output.status.success() {
+        return Err(CuboError::SystemError(format!(
+            "systemd {} failed: {}",
+            method,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Arguments of StartTransientUnit for a scope holding `pid`, with its cgroup delegated
+fn start_scope_args(unit: &str, pid: u32, limits: &[UnitProperty]) -> Vec<String> {
+    let mut properties = vec![
+        UnitProperty::new("Description", PropertyValue::Str(format!("cubo container {}", unit))),
+        UnitProperty::new("Slice", PropertyValue::Str(CUBO_SLICE.to_string())),
+        UnitProperty::new("Delegate", PropertyValue::Bool(true)),
+        UnitProperty::new("PIDs", PropertyValue::U32s(vec![pid])),
+    ];
+    properties.extend(limits.iter().cloned());
+
+    let mut args = vec![unit.to_string(), "fail".to_string()];
+    args.extend(properties_args(&properties));
+    // No auxiliary units
+    args.push("0".to_string());
+    args
+}
+
+/// Start the transient scope `unit` with `limits` and move `pid` into it. systemd moves the
+/// process once the start job runs, so this waits until it has.
+pub fn start_scope(unit: &str, pid: u32, limits: &[UnitProperty]) -> Result<()> {
+    call_manager("StartTransientUnit", "ssa(sv)a(sa(sv))", start_scope_args(unit, pid, limits))?;
+
+    let suffix = format!("/{}", unit);
+    let deadline = Instant::now() + SCOPE_TIMEOUT;
+    loop {
+        let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+        if cgroup.lines().any(|line| line.trim_end().ends_with(&suffix)) {
+            return Ok(());
+        }
+        if Instant::now() > deadline {
+            return Err(CuboError::SystemError(format!("Process {} did not join the scope {}", pid, unit)));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Change properties of a running unit until it stops
+pub fn set_unit_properties(unit: &str, properties: &[UnitProperty]) -> Result<()> {
+    let mut args = vec![unit.to_string(), "true".to_string()];
+    args.extend(properties_args(properties));
+    call_manager("SetUnitProperties", "sba(sv)", args)
+}
+
+/// Stop a unit, killing whatever is left in it
+pub fn stop_unit(unit: &str) -> Result<()> {
+    call_manager("StopUnit", "ss", vec![unit.to_string(), "fail".to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::health::HealthCheck;
+    use crate::container::sched::{IoClass, IoPriority, SchedPolicy};
+    use crate::container::{HostEntry, PortMapping, Relabel, TimeOffset};
+    use serial_test::serial;
+
+    fn options() -> UnitOptions {
+        UnitOptions {
+            service_type: ServiceType::Notify,
+            restart_policy: None,
+            restart_sec: None,
+            cubo: PathBuf::from("/usr/bin/cubo"),
+            root_dir: PathBuf::from("/var/lib/cubo"),
+        }
+    }
+
+    #[test]
+    fn test_run_args() {
+        let container = Container::new("nginx:latest".to_string(), vec!["nginx".to_string(), "-g".to_string()])
+            .with_env("B".to_string(), "2".to_string())
+            .with_env("A".to_string(), "1".to_string())
+            .with_volume(VolumeMount::bind("/srv".to_string(), "/data".to_string(), true))
+            .with_volume(VolumeMount {
+                relabel: Some(Relabel::Private),
+                ..VolumeMount::volume("cache".to_string(), "/cache".to_string(), false)
+            })
+            .with_port(PortMapping::tcp(8080, 80))
+            .with_network_mode(NetworkMode::Host)
+            .with_extra_host(HostEntry { hostname: "db".to_string(), ip: "10.0.0.2".to_string() })
+            .with_init(true);
+
+        let args = run_args(&container).unwrap();
+        assert_eq!(args, vec![
+            "-e", "A=1", "-e", "B=2",
+            "-v", "/srv:/data:ro",
+            "-v", "cache:/cache:Z",
+            "-p", "8080:80/tcp",
+            "--network", "host",
+            "--add-host", "db:10.0.0.2",
+            "--init",
+            "--", "nginx:latest", "nginx", "-g",
+        ]);
+    }
+
+    #[test]
+    fn test_run_args_resources() {
+        let container = Container::new("nginx:latest".to_string(), vec![])
+            .with_memory_limit(256 * 1024 * 1024)
+            .with_memory_swap(-1)
+            .with_oom_score_adj(-500)
+            .with_nice(10)
+            .with_sched_policy(SchedPolicy::Batch, None)
+            .with_io_priority(IoPriority { class: IoClass::BestEffort, level: 7 })
+            .with_cpuset_cpus("0-1".to_string())
+            .with_time_offset(TimeOffset { clock: "boottime".to_string(), secs: -3600 });
+        let mut container = container;
+        container.config.label_options.kind = Some("spc_t".to_string());
+        container.config.apparmor_profile = Some("unconfined".to_string());
+        container.config.masked_paths.retain(|p| p != "/proc/kcore");
+        container.config.landlock = true;
+
+        let args = run_args(&container).unwrap();
+        assert_eq!(args, vec![
+            "--memory", "268435456",
+            "--memory-swap", "-1",
+            "--oom-score-adj", "-500",
+            "--nice", "10",
+            "--sched-policy", "batch",
+            "--ionice", "best-effort:7",
+            "--cpuset-cpus", "0-1",
+            "--security-opt", "label=type:spc_t",
+            "--security-opt", "apparmor=unconfined",
+            "--security-opt", "landlock=on",
+            "--security-opt", "unmask=/proc/kcore",
+            "--time-offset", "boottime=-3600",
+            "--", "nginx:latest",
+        ]);
+    }
+
+    #[test]
+    fn test_scope_cgroup() {
+        assert_eq!(scope_cgroup("abc", 0), Path::new("cubo.slice/cubo-abc.scope"));
+        assert_eq!(
+            scope_cgroup("abc", 1000),
+            Path::new("user.slice/user-1000.slice/user@1000.service/cubo.slice/cubo-abc.scope")
+        );
+    }
+
+    #[test]
+    fn test_start_scope_args() {
+        let limits = [
+            UnitProperty::new("MemoryMax", PropertyValue::U64(1024)),
+            UnitProperty::new("AllowedCPUs", PropertyValue::Bytes(vec![0b1011])),
+            UnitProperty::new("IOReadBandwidthMax", PropertyValue::DeviceLimits(vec![("/dev/sda".to_string(), 1024)])),
+        ];
+        let args = start_scope_args("cubo-abc.scope", 42, &limits);
+        assert_eq!(args, vec![
+            "cubo-abc.scope", "fail", "7",
+            "Description", "s", "cubo container cubo-abc.scope",
+            "Slice", "s", "cubo.slice",
+            "Delegate", "b", "true",
+            "PIDs", "au", "1", "42",
+            "MemoryMax", "t", "1024",
+            "AllowedCPUs", "ay", "1", "11",
+            "IOReadBandwidthMax", "a(st)", "1", "/dev/sda", "1024",
+            "0",
+        ]);
+    }
+
+    #[test]
+    fn test_render_unit() {
+        let container = Container::new("alpine".to_string(), vec!["sleep".to_string(), "1d".to_string()])
+            .with_name("web".to_string())
+            .with_env("GREETING".to_string(), "hello world".to_string());
+
+        let unit = render_unit(&container, &options()).unwrap();
+        assert!(unit.contains("Type=notify\nNotifyAccess=all\n"));
+        assert!(unit.contains("Restart=no\n"));
+        assert!(unit.contains("Environment=CUBO_ROOT=/var/lib/cubo\n"));
+        assert!(unit.contains("ExecStartPre=-/usr/bin/cubo rm -f web\n"));
+        assert!(unit.contains("ExecStart=/usr/bin/cubo run --name web -e \"GREETING=hello world\" -- alpine sleep 1d\n"));
+        assert!(unit.contains("ExecStop=/usr/bin/cubo stop -f web\n"));
+        assert!(unit.contains("[Install]\nWantedBy=default.target\n"));
+        assert!(!unit.contains("StartLimitBurst"));
+        assert_eq!(unit_file_name(&container), "container-web.service");
+
+        let mut forking = options();
+        forking.service_type = ServiceType::Forking;
+        forking.restart_policy = Some(RestartPolicy::OnFailure { max_retries: 3 });
+        forking.restart_sec = Some(5);
+        let unit = render_unit(&container, &forking).unwrap();
+        assert!(unit.contains("Type=forking\nRestart=on-failure\nRestartSec=5\n"));
+        assert!(unit.contains("StartLimitBurst=4\n"));
+        assert!(!unit.contains("NotifyAccess"));
+    }
+
+    #[test]
+    fn test_health_check_waits_before_ready() {
+        let container = Container::new("alpine".to_string(), vec![])
+            .with_name("db".to_string())
+            .with_health_check(HealthCheck::new("pg_isready".to_string()));
+
+        let unit = render_unit(&container, &options()).unwrap();
+        assert!(unit.contains(
+            "run --name db --wait-healthy --health-cmd pg_isready --health-interval 30 --health-timeout 30 --health-retries 3 -- alpine\n"
+        ));
+
+        let mut forking = options();
+        forking.service_type = ServiceType::Forking;
+        assert!(!render_unit(&container, &forking).unwrap().contains("--wait-healthy"));
+    }
+
+    #[test]
+    fn test_started_state() {
+        assert_eq!(started_state(SdNotifyMode::Cubo, Some(7)).as_deref(), Some("READY=1\nMAINPID=7"));
+        assert_eq!(started_state(SdNotifyMode::Cubo, None).as_deref(), Some("READY=1"));
+        assert_eq!(started_state(SdNotifyMode::Container, Some(7)).as_deref(), Some("MAINPID=7"));
+        assert_eq!(started_state(SdNotifyMode::Container, None), None);
+        assert_eq!(started_state(SdNotifyMode::Ignore, Some(7)), None);
+    }
+
+    #[test]
+    fn test_notify_socket_mount() {
+        let mount = notify_socket_mount("/run/systemd/notify").unwrap();
+        assert_eq!(mount.host_path, "/run/systemd/notify");
+        assert_eq!(mount.container_path, CONTAINER_NOTIFY_SOCKET);
+        assert!(notify_socket_mount("@/org/freedesktop/systemd1/notify").is_err());
+    }
+
+    #[test]
+    fn test_unnamed_container_uses_short_id() {
+        let container = Container::new("alpine".to_string(), vec![]);
+        assert_eq!(service_container_name(&container), &container.id[..12]);
+    }
+
+    #[test]
+    fn test_quote_arg() {
+        assert_eq!(quote_arg("plain"), "plain");
+        assert_eq!(quote_arg(""), "\"\"");
+        assert_eq!(quote_arg("a b"), "\"a b\"");
+        assert_eq!(quote_arg("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote_arg("$HOME/100%"), "$$HOME/100%%");
+    }
+
+    #[test]
+    #[serial]
+    fn test_notify() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("notify.sock");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        std::env::remove_var("NOTIFY_SOCKET");
+        assert!(!notify("READY=1").unwrap());
+
+        std::env::set_var("NOTIFY_SOCKET", &path);
+        assert!(notify("READY=1\nMAINPID=42").unwrap());
+        std::env::remove_var("NOTIFY_SOCKET");
+
+        let mut buf = [0u8; 64];
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nMAINPID=42");
+    }
+}
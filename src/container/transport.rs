@@ -0,0 +1,475 @@
+//! Image sources other than a registry: `docker-daemon:`, `oci:` and `dir:` references
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tempfile::TempDir;
+use tracing::{debug, info};
+
+use crate::error::{CuboError, Result};
+use super::image_store::ImageConfig;
+use super::reference;
+use super::registry::{OciImageConfig, OciManifest, RegistryClient};
+
+const OCI_REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// Where `cubo pull` reads an image from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageSource {
+    /// A registry reference, optionally written as `docker://ref`
+    Registry(String),
+    /// `docker-daemon:name[:tag]`, exported by the local docker daemon
+    DockerDaemon(String),
+    /// `oci:path[:tag]`, an OCI image layout directory
+    OciLayout { path: PathBuf, tag: Option<String> },
+    /// `dir:path`, a directory with manifest.json and the blobs named by digest
+    Dir(PathBuf),
+}
+
+impl ImageSource {
+    pub fn parse(reference: &str) -> Self {
+        if let Some(name) = reference.strip_prefix("docker://") {
+            return ImageSource::Registry(name.to_string());
+        }
+        if let Some(name) = reference.strip_prefix("docker-daemon:") {
+            return ImageSource::DockerDaemon(with_default_tag(name));
+        }
+        // `oci:` and `dir:` need a path, so images named oci or dir keep working
+        if let Some(rest) = reference.strip_prefix("oci:").filter(|r| r.contains('/')) {
+            let (path, tag) = match rest.rsplit_once(':') {
+                Some((path, tag)) if !tag.contains('/') && !tag.is_empty() => (path, Some(tag.to_string())),
+                _ => (rest, None),
+            };
+            return ImageSource::OciLayout { path: PathBuf::from(path), tag };
+        }
+        if let Some(path) = reference.strip_prefix("dir:").filter(|r| r.contains('/')) {
+            return ImageSource::Dir(PathBuf::from(path));
+        }
+        ImageSource::Registry(reference.to_string())
+    }
+
+    /// Reference the image is stored under in cubo
+    pub fn local_reference(&self) -> String {
+        match self {
+            ImageSource::Registry(name) | ImageSource::DockerDaemon(name) => {
+                reference::normalize(name).unwrap_or_else(|_| name.clone())
+            }
+            ImageSource::OciLayout { path, tag } => {
+                format!("{}:{}", base_name(path), tag.as_deref().unwrap_or("latest"))
+            }
+            ImageSource::Dir(path) => format!("{}:latest", base_name(path)),
+        }
+    }
+}
+
+fn with_default_tag(name: &str) -> String {
+    let last = name.rsplit('/').next().unwrap_or(name);
+    if last.contains(':') || last.contains('@') {
+        name.to_string()
+    } else {
+        format!("{}:latest", name)
+    }
+}
+
+fn base_name(path: &Path) -> String {
+    path.canonicalize()
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .or_else(|| path.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "image".to_string())
+        .to_lowercase()
+}
+
+/// An image read from a local source, with its layers as (possibly compressed) tar files
+#[derive(Debug)]
+pub struct LocalImage {
+    pub config: ImageConfig,
+    pub layers: Vec<PathBuf>,
+    /// Annotations of the OCI manifest, when the source has one
+    pub annotations: Option<BTreeMap<String, String>>,
+    /// Keeps the files of an export alive until the layers are stored
+    _workdir: Option<TempDir>,
+}
+
+/// Read the image of a non-registry source
+pub fn read(source: &ImageSource) -> Result<LocalImage> {
+    match source {
+        ImageSource::Registry(name) => Err(CuboError::InvalidConfiguration(format!(
+            "{} is a registry reference",
+            name
+        ))),
+        ImageSource::DockerDaemon(name) => {
+            let workdir = tempfile::tempdir()?;
+            docker_export(&docker_socket(), name, workdir.path())?;
+            let mut image = read_docker_archive(workdir.path())?;
+            image._workdir = Some(workdir);
+            Ok(image)
+        }
+        ImageSource::OciLayout { path, tag } => read_oci_layout(path, tag.as_deref()),
+        ImageSource::Dir(path) => read_dir_image(path),
+    }
+}
+
+fn docker_socket() -> PathBuf {
+    std::env::var("DOCKER_HOST")
+        .ok()
+        .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("/var/run/docker.sock"))
+}
+
+/// Fetch `docker save` of `name` through the daemon socket and unpack it into `dir`
+fn docker_export(socket: &Path, name: &str, dir: &Path) -> Result<()> {
+    info!("Exporting {} from the docker daemon at {}", name, socket.display());
+    let mut stream = UnixStream::connect(socket).map_err(|e| {
+        CuboError::SystemError(format!("Failed to connect to the docker daemon at {}: {}", socket.display(), e))
+    })?;
+    // HTTP/1.0 so the archive comes unchunked, up to the end of the stream. Sent in one write,
+    // a daemon answering early must not find the request half written.
+    stream.write_all(format!("GET /images/{}/get HTTP/1.0\r\nHost: docker\r\n\r\n", name).as_bytes())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+    match status.split_whitespace().nth(1) {
+        Some("200") => {}
+        Some("404") => return Err(CuboError::ImageNotFound { reference: format!("docker-daemon:{}", name) }),
+        _ => {
+            let mut body = String::new();
+            let _ = reader.read_to_string(&mut body);
+            return Err(CuboError::SystemError(format!(
+                "The docker daemon failed to export {}: {}",
+                name,
+                body.trim().trim_start_matches("{\"message\":\"").trim_end_matches("\"}")
+            )));
+        }
+    }
+
+    tar::Archive::new(reader)
+        .unpack(dir)
+        .map_err(|e| CuboError::SystemError(format!("Failed to unpack the export of {}: {}", name, e)))
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchiveEntry {
+    #[serde(rename = "Config")]
+    config: String,
+    #[serde(rename = "Layers")]
+    layers: Vec<String>,
+}
+
+/// Read an unpacked `docker save` archive (manifest.json listing config and layer files)
+fn read_docker_archive(dir: &Path) -> Result<LocalImage> {
+    let entries: Vec<ArchiveEntry> = read_json(&dir.join("manifest.json"))?;
+    let entry = entries.into_iter().next().ok_or_else(|| {
+        CuboError::SystemError("The docker archive holds no image".to_string())
+    })?;
+    let config: OciImageConfig = read_json(&dir.join(&entry.config))?;
+
+    Ok(LocalImage {
+        config: RegistryClient::convert_oci_config(&config),
+        layers: entry.layers.iter().map(|layer| dir.join(layer)).collect(),
+        annotations: None,
+        _workdir: None,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct OciIndex {
+    manifests: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    #[serde(rename = "mediaType", default)]
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    platform: Option<IndexPlatform>,
+    #[serde(default)]
+    annotations: std::collections::HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexPlatform {
+    architecture: String,
+    os: String,
+}
+
+/// The hex part of a `sha256:<64 hex>` digest. Digests come from the image and name the blob
+/// files, so anything else, such as a path, is refused.
+fn digest_hex(digest: &str) -> Result<&str> {
+    digest
+        .strip_prefix("sha256:")
+        .filter(|hex| hex.len() == 64 && hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')))
+        .ok_or_else(|| CuboError::SystemError(format!("Invalid digest '{}'", digest)))
+}
+
+fn oci_blob(layout: &Path, digest: &str) -> Result<PathBuf> {
+    Ok(layout.join("blobs").join("sha256").join(digest_hex(digest)?))
+}
+
+/// Read the image tagged `tag` (or the only one) from an OCI image layout
+fn read_oci_layout(layout: &Path, tag: Option<&str>) -> Result<LocalImage> {
+    let index: OciIndex = read_json(&layout.join("index.json"))?;
+    let entry = match tag {
+        Some(tag) => index.manifests.iter().find(|m| {
+            m.annotations.get(OCI_REF_NAME).is_some_and(|name| name == tag || name.ends_with(&format!(":{}", tag)))
+        }),
+        None if index.manifests.len() == 1 => index.manifests.first(),
+        None => {
+            return Err(CuboError::InvalidConfiguration(format!(
+                "{} holds {} images, name one with oci:{}:TAG",
+                layout.display(),
+                index.manifests.len(),
+                layout.display()
+            )));
+        }
+    }
+    .ok_or_else(|| CuboError::ImageNotFound { reference: format!("oci:{}:{}", layout.display(), tag.unwrap_or("")) })?;
+
+    let mut digest = entry.digest.clone();
+    if entry.media_type.contains("image.index") || entry.media_type.contains("manifest.list") {
+        let nested: OciIndex = read_json(&oci_blob(layout, &digest)?)?;
+        let platform = nested
+            .manifests
+            .iter()
+            .find(|m| m.platform.as_ref().is_some_and(|p| p.os == "linux" && p.architecture == "amd64"))
+            .or_else(|| nested.manifests.first())
+            .ok_or_else(|| CuboError::SystemError("No suitable manifest found in index".to_string()))?;
+        debug!("Selected manifest {} from the image index", platform.digest);
+        digest = platform.digest.clone();
+    }
+
+    let manifest: OciManifest = read_json(&oci_blob(layout, &digest)?)?;
+    let config: OciImageConfig = read_json(&oci_blob(layout, &manifest.config.digest)?)?;
+    let layers = manifest
+        .layers
+        .iter()
+        .map(|layer| oci_blob(layout, &layer.digest))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(LocalImage {
+        config: RegistryClient::convert_oci_config(&config),
+        layers,
+        annotations: manifest.annotations,
+        _workdir: None,
+    })
+}
+
+/// Read a `dir:` image: manifest.json next to blobs named by the hex part of their digest
+fn read_dir_image(dir: &Path) -> Result<LocalImage> {
+    let blob = |digest: &str| digest_hex(digest).map(|hex| dir.join(hex));
+
+    let manifest: OciManifest = read_json(&dir.join("manifest.json"))?;
+    let config: OciImageConfig = read_json(&blob(&manifest.config.digest)?)?;
+    Ok(LocalImage {
+        config: RegistryClient::convert_oci_config(&config),
+        layers: manifest.layers.iter().map(|layer| blob(&layer.digest)).collect::<Result<Vec<_>>>()?,
+        annotations: manifest.annotations,
+        _workdir: None,
+    })
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let data = fs::read(path)
+        .map_err(|e| CuboError::SystemError(format!("Failed to read {}: {}", path.display(), e)))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| CuboError::SystemError(format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    const CONFIG: &str = r#"{"config":{"Cmd":["nginx"],"Env":["PATH=/usr/bin"],"WorkingDir":"/srv"}}"#;
+
+    /// Store `data` in an OCI layout and return its digest
+    fn put_blob(layout: &Path, data: &[u8]) -> String {
+        let hex = format!("{:x}", Sha256::digest(data));
+        let dir = layout.join("blobs/sha256");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(&hex), data).unwrap();
+        format!("sha256:{}", hex)
+    }
+
+    /// The request head the client sent, read up to the blank line ending it
+    fn read_request(conn: &mut std::os::unix::net::UnixStream) -> String {
+        let mut request = Vec::new();
+        let mut byte = [0u8; 1];
+        while !request.ends_with(b"\r\n\r\n") && conn.read(&mut byte).unwrap() == 1 {
+            request.push(byte[0]);
+        }
+        String::from_utf8_lossy(&request).to_string()
+    }
+
+    fn manifest_json(config: &str, layers: &[&str]) -> String {
+        let layers: Vec<String> = layers
+            .iter()
+            .map(|d| format!(r#"{{"mediaType":"application/vnd.oci.image.layer.v1.tar","size":1,"digest":"{}"}}"#, d))
+            .collect();
+        format!(
+            r#"{{"schemaVersion":2,"config":{{"mediaType":"application/vnd.oci.image.config.v1+json","size":1,"digest":"{}"}},"layers":[{}]}}"#,
+            config,
+            layers.join(",")
+        )
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        assert_eq!(ImageSource::parse("alpine:3.18"), ImageSource::Registry("alpine:3.18".to_string()));
+        assert_eq!(ImageSource::parse("docker://ghcr.io/o/r:v1"), ImageSource::Registry("ghcr.io/o/r:v1".to_string()));
+        assert_eq!(ImageSource::parse("docker-daemon:nginx"), ImageSource::DockerDaemon("nginx:latest".to_string()));
+        assert_eq!(
+            ImageSource::parse("docker-daemon:localhost:5000/app:v2"),
+            ImageSource::DockerDaemon("localhost:5000/app:v2".to_string())
+        );
+        assert_eq!(
+            ImageSource::parse("oci:/srv/layouts/app:v1"),
+            ImageSource::OciLayout { path: PathBuf::from("/srv/layouts/app"), tag: Some("v1".to_string()) }
+        );
+        assert_eq!(
+            ImageSource::parse("oci:./app"),
+            ImageSource::OciLayout { path: PathBuf::from("./app"), tag: None }
+        );
+        assert_eq!(ImageSource::parse("dir:/tmp/img"), ImageSource::Dir(PathBuf::from("/tmp/img")));
+        // Without a path these are plain image names
+        assert_eq!(ImageSource::parse("oci:latest"), ImageSource::Registry("oci:latest".to_string()));
+    }
+
+    #[test]
+    fn test_local_reference() {
+        let oci = ImageSource::OciLayout { path: PathBuf::from("/nonexistent/My-App"), tag: Some("v1".to_string()) };
+        assert_eq!(oci.local_reference(), "my-app:v1");
+        assert_eq!(ImageSource::Dir(PathBuf::from("/nonexistent/img")).local_reference(), "img:latest");
+        assert_eq!(ImageSource::DockerDaemon("nginx:latest".to_string()).local_reference(), "nginx:latest");
+    }
+
+    #[test]
+    fn test_read_oci_layout() {
+        let temp = TempDir::new().unwrap();
+        let layout = temp.path();
+        let config = put_blob(layout, CONFIG.as_bytes());
+        let layer = put_blob(layout, b"layer");
+        let manifest = put_blob(layout, manifest_json(&config, &[&layer]).as_bytes());
+        let index = format!(
+            r#"{{"schemaVersion":2,"manifests":[{{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"{}","size":1,"annotations":{{"{}":"v1"}}}}]}}"#,
+            manifest, OCI_REF_NAME
+        );
+        fs::write(layout.join("index.json"), index).unwrap();
+
+        for tag in [Some("v1"), None] {
+            let image = read_oci_layout(layout, tag).unwrap();
+            assert_eq!(image.config.cmd, Some(vec!["nginx".to_string()]));
+            assert_eq!(image.config.working_dir.as_deref(), Some("/srv"));
+            assert_eq!(image.layers, [oci_blob(layout, &layer).unwrap()]);
+        }
+        assert!(matches!(read_oci_layout(layout, Some("v2")), Err(CuboError::ImageNotFound { .. })));
+    }
+
+    #[test]
+    fn test_read_dir_image() {
+        let temp = TempDir::new().unwrap();
+        let dir = temp.path();
+        let config = format!("sha256:{:x}", Sha256::digest(CONFIG));
+        fs::write(dir.join(config.trim_start_matches("sha256:")), CONFIG).unwrap();
+        let layer = format!("{:x}", Sha256::digest(b"layer"));
+        fs::write(dir.join("manifest.json"), manifest_json(&config, &[&format!("sha256:{}", layer)])).unwrap();
+
+        let image = read(&ImageSource::Dir(dir.to_path_buf())).unwrap();
+        assert_eq!(image.config.env, Some(vec!["PATH=/usr/bin".to_string()]));
+        assert_eq!(image.layers, [dir.join(layer)]);
+
+        fs::write(dir.join("manifest.json"), manifest_json(&config, &["sha256:../../etc/shadow"])).unwrap();
+        assert!(read(&ImageSource::Dir(dir.to_path_buf())).is_err());
+    }
+
+    #[test]
+    fn test_oci_layout_refuses_malicious_digests() {
+        let temp = TempDir::new().unwrap();
+        let layout = temp.path();
+        let config = put_blob(layout, CONFIG.as_bytes());
+        fs::write(temp.path().join("secret"), CONFIG).unwrap();
+
+        for digest in [
+            "sha256:../../secret".to_string(),
+            "../secret".to_string(),
+            format!("sha512:{}", "a".repeat(64)),
+            format!("sha256:{}", "A".repeat(64)),
+            format!("{}/x", config),
+        ] {
+            assert!(oci_blob(layout, &digest).is_err(), "{}", digest);
+            let manifest = put_blob(layout, manifest_json(&digest, &[]).as_bytes());
+            let index = format!(r#"{{"manifests":[{{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"{}"}}]}}"#, manifest);
+            fs::write(layout.join("index.json"), index).unwrap();
+            assert!(read_oci_layout(layout, None).is_err(), "{}", digest);
+        }
+        assert_eq!(oci_blob(layout, &config).unwrap(), layout.join("blobs/sha256").join(&config[7..]));
+    }
+
+    #[test]
+    fn test_docker_export_and_archive() {
+        let temp = TempDir::new().unwrap();
+        let socket = temp.path().join("docker.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        // `docker save` output: manifest.json, the config and a layer directory
+        let mut archive = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            ("manifest.json", r#"[{"Config":"cfg.json","RepoTags":["nginx:latest"],"Layers":["l1/layer.tar"]}]"#),
+            ("cfg.json", CONFIG),
+            ("l1/layer.tar", "layer"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, path, data.as_bytes()).unwrap();
+        }
+        let body = archive.into_inner().unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let request = read_request(&mut conn);
+            conn.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: application/x-tar\r\n\r\n").unwrap();
+            conn.write_all(&body).unwrap();
+            request
+        });
+
+        let out = temp.path().join("export");
+        fs::create_dir_all(&out).unwrap();
+        docker_export(&socket, "nginx:latest", &out).unwrap();
+        assert!(server.join().unwrap().starts_with("GET /images/nginx:latest/get HTTP/1.0\r\n"));
+
+        let image = read_docker_archive(&out).unwrap();
+        assert_eq!(image.config.cmd, Some(vec!["nginx".to_string()]));
+        assert_eq!(image.layers, [out.join("l1/layer.tar")]);
+        assert_eq!(fs::read_to_string(&image.layers[0]).unwrap(), "layer");
+    }
+
+    #[test]
+    fn test_docker_export_missing_image() {
+        let temp = TempDir::new().unwrap();
+        let socket = temp.path().join("docker.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            read_request(&mut conn);
+            conn.write_all(b"HTTP/1.0 404 Not Found\r\n\r\n{\"message\":\"reference does not exist\"}").unwrap();
+        });
+
+        let err = docker_export(&socket, "missing:latest", temp.path()).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(err, CuboError::ImageNotFound { ref reference } if reference == "docker-daemon:missing:latest"));
+    }
+}
@@ -0,0 +1,133 @@
+use nix::sys::resource::{setrlimit, Resource, RLIM_INFINITY};
+
+use crate::container::Ulimit;
+use crate::error::{CuboError, Result};
+
+/// Map a ulimit name as accepted by `--ulimit` (e.g. "nofile") to its rlimit resource
+pub fn resource_for(name: &str) -> Option<Resource> {
+    let resource = match name {
+        "as" => Resource::RLIMIT_AS,
+        "core" => Resource::RLIMIT_CORE,
+        "cpu" => Resource::RLIMIT_CPU,
+        "data" => Resource::RLIMIT_DATA,
+        "fsize" => Resource::RLIMIT_FSIZE,
+        "locks" => Resource::RLIMIT_LOCKS,
+        "memlock" => Resource::RLIMIT_MEMLOCK,
+        "msgqueue" => Resource::RLIMIT_MSGQUEUE,
+        "nice" => Resource::RLIMIT_NICE,
+        "nofile" => Resource::RLIMIT_NOFILE,
+        "nproc" => Resource::RLIMIT_NPROC,
+        "rss" => Resource::RLIMIT_RSS,
+        "rtprio" => Resource::RLIMIT_RTPRIO,
+        "rttime" => Resource::RLIMIT_RTTIME,
+        "sigpending" => Resource::RLIMIT_SIGPENDING,
+        "stack" => Resource::RLIMIT_STACK,
+        _ => return None,
+    };
+    Some(resource)
+}
+
+/// Parse "nofile=1024:4096" or "nofile=4096" (soft = hard). "unlimited" or -1 lift the limit.
+pub fn parse_ulimit(spec: &str) -> Result<Ulimit> {
+    let (name, values) = spec.split_once('=').ok_or_else(|| {
+        CuboError::InvalidConfiguration(format!(
+            "Invalid ulimit '{}', expected <name>=<soft>[:<hard>]",
+            spec
+        ))
+    })?;
+
+    if resource_for(name).is_none() {
+        return Err(CuboError::InvalidConfiguration(format!("Unknown ulimit: {}", name)));
+    }
+
+    let (soft, hard) = match values.split_once(':') {
+        Some((soft, hard)) => (parse_limit(soft)?, parse_limit(hard)?),
+        None => {
+            let value = parse_limit(values)?;
+            (value, value)
+        }
+    };
+
+    if soft > hard {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "ulimit {}: soft limit {} exceeds hard limit {}",
+            name, soft, hard
+        )));
+    }
+
+    Ok(Ulimit { name: name.to_string(), soft, hard })
+}
+
+fn parse_limit(value: &str) -> Result<u64> {
+    match value {
+        "unlimited" | "-1" => Ok(RLIM_INFINITY),
+        _ => value
+            .parse::<u64>()
+            .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid ulimit value: {}", value))),
+    }
+}
+
+/// Apply the ulimits to the current process; they are inherited across exec.
+pub fn apply_ulimits(ulimits: &[Ulimit]) -> Result<()> {
+    for ulimit in ulimits {
+        let resource = resource_for(&ulimit.name).ok_or_else(|| {
+            CuboError::InvalidConfiguration(format!("Unknown ulimit: {}", ulimit.name))
+        })?;
+        setrlimit(resource, ulimit.soft, ulimit.hard).map_err(|e| {
+            CuboError::SystemError(format!("Failed to set ulimit {}: {}", ulimit.name, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ulimit_soft_and_hard() {
+        let ulimit = parse_ulimit("nofile=1024:65535").unwrap();
+        assert_eq!(ulimit.name, "nofile");
+        assert_eq!(ulimit.soft, 1024);
+        assert_eq!(ulimit.hard, 65535);
+    }
+
+    #[test]
+    fn test_parse_ulimit_single_value() {
+        let ulimit = parse_ulimit("nproc=512").unwrap();
+        assert_eq!(ulimit.soft, 512);
+        assert_eq!(ulimit.hard, 512);
+    }
+
+    #[test]
+    fn test_parse_ulimit_unlimited() {
+        let ulimit = parse_ulimit("core=-1").unwrap();
+        assert_eq!(ulimit.soft, RLIM_INFINITY);
+        let ulimit = parse_ulimit("memlock=unlimited").unwrap();
+        assert_eq!(ulimit.hard, RLIM_INFINITY);
+    }
+
+    #[test]
+    fn test_parse_ulimit_invalid() {
+        assert!(parse_ulimit("nofile").is_err());
+        assert!(parse_ulimit("bogus=10").is_err());
+        assert!(parse_ulimit("nofile=abc").is_err());
+        assert!(parse_ulimit("nofile=2048:1024").is_err());
+    }
+
+    #[test]
+    fn test_resource_for_known_names() {
+        assert!(resource_for("nofile").is_some());
+        assert!(resource_for("stack").is_some());
+        assert!(resource_for("NOFILE").is_none());
+    }
+
+    #[test]
+    fn test_apply_ulimits_lowering_soft_limit() {
+        let (_, hard) = nix::sys::resource::getrlimit(Resource::RLIMIT_CORE).unwrap();
+        let ulimit = Ulimit { name: "core".to_string(), soft: 0, hard };
+        assert!(apply_ulimits(&[ulimit]).is_ok());
+        let (soft, _) = nix::sys::resource::getrlimit(Resource::RLIMIT_CORE).unwrap();
+        assert_eq!(soft, 0);
+    }
+}
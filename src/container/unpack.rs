@@ -0,0 +1,554 @@
+//! Unpacking image layers: regular files, links, device nodes and xattrs as the image has them,
+//! with a report of what this process wasn't allowed to recreate
+
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use flate2::read::GzDecoder;
+use nix::errno::Errno;
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use tar::EntryType;
+use tracing::debug;
+
+use crate::container::secure_join::secure_join;
+use crate::container::whiteout::{self, Whiteout, WhiteoutFormat, WHITEOUT_PREFIX};
+use crate::error::{CuboError, Result};
+
+/// PAX records holding extended attributes, as GNU tar and docker write them
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Size of the pieces a gzip layer is decompressed in, and how many may wait to be unpacked
+const INFLATE_CHUNK: usize = 256 * 1024;
+const INFLATE_AHEAD: usize = 8;
+
+/// Most layers scanned at once ahead of the one being unpacked
+const MAX_SCAN_WORKERS: usize = 4;
+
+/// An entry of a layer, or an attribute of one, that couldn't be unpacked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    /// Path in the layer
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// What unpacking a layer left out
+#[derive(Debug, Default)]
+pub struct UnpackReport {
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// A layer tar whose whiteouts are read. Scanning needs nothing from the other layers, so
+/// layers can be scanned in parallel and unpacked in order afterwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedLayer {
+    pub path: PathBuf,
+    pub gzip: bool,
+    pub whiteouts: Vec<Whiteout>,
+}
+
+/// Read the whiteouts of the layer tar at `layer`
+pub fn scan_layer(layer: &Path, gzip: bool) -> Result<ScannedLayer> {
+    Ok(ScannedLayer {
+        path: layer.to_path_buf(),
+        gzip,
+        whiteouts: whiteout::read_whiteouts(layer, gzip)?,
+    })
+}
+
+/// Unpack the layer tar at `layer` over `target`, applying its whiteouts as `whiteouts` says.
+/// Run as root, ownership aside, the files come out as in the image: setuid bits, file
+/// capabilities and other xattrs, hard links and device nodes. Otherwise setuid and setgid bits
+/// are dropped, since the files would belong to the unpacking user, and device nodes and
+/// privileged xattrs are skipped and reported.
+pub fn unpack_layer(layer: &Path, target: &Path, gzip: bool, whiteouts: WhiteoutFormat) -> Result<UnpackReport> {
+    unpack_scanned(&scan_layer(layer, gzip)?, target, whiteouts)
+}
+
+/// Unpack a scanned layer over `target`, as `unpack_layer` does
+pub fn unpack_scanned(layer: &ScannedLayer, target: &Path, whiteouts: WhiteoutFormat) -> Result<UnpackReport> {
+    let failed = |e: io::Error| CuboError::LayerExtractionError {
+        layer: layer.path.clone(),
+        message: e.to_string(),
+    };
+
+    // Whiteouts hide files of the layers below, not the ones their own layer adds
+    let hidden = &layer.whiteouts;
+    if whiteouts == WhiteoutFormat::Delete {
+        whiteout::delete_hidden(target, hidden)?;
+    }
+
+    let privileged = nix::unistd::geteuid().is_root();
+    let mut archive = tar::Archive::new(open_layer(&layer.path, layer.gzip).map_err(failed)?);
+    let mut report = UnpackReport::default();
+    // Directories are made last, as `tar` does, so a read-only one can still be filled
+    let mut directories = Vec::new();
+
+    for entry in archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        let path = layer_path(&entry.path().map_err(failed)?);
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if name.to_string_lossy().starts_with(WHITEOUT_PREFIX) {
+            continue;
+        }
+        let skip = |reason: String| SkippedEntry { path: path.clone(), reason };
+
+        let entry_type = entry.header().entry_type();
+        if matches!(entry_type, EntryType::Char | EntryType::Block | EntryType::Fifo) {
+            if let Err(reason) = make_node(target, &path, entry.header(), privileged) {
+                report.skipped.push(skip(reason));
+            }
+            continue;
+        }
+        if entry_type == EntryType::Link && !link_source_exists(target, &entry) {
+            let source = entry.link_name().ok().flatten().map(|l| l.display().to_string()).unwrap_or_default();
+            report.skipped.push(skip(format!("hard link to {}, which isn't unpacked in this directory", source)));
+            continue;
+        }
+
+        let xattrs = xattrs(&mut entry).map_err(failed)?;
+        entry.set_preserve_permissions(privileged);
+        entry.set_unpack_xattrs(false);
+        if entry_type == EntryType::Directory {
+            directories.push((entry, path, xattrs));
+            continue;
+        }
+        if !entry.unpack_in(target).map_err(failed)? {
+            report.skipped.push(skip("path leads out of the layer".to_string()));
+            continue;
+        }
+        set_xattrs(target, &path, &xattrs, &mut report);
+    }
+
+    for (mut entry, path, xattrs) in directories.into_iter().rev() {
+        entry.unpack_in(target).map_err(failed)?;
+        set_xattrs(target, &path, &xattrs, &mut report);
+    }
+
+    if whiteouts == WhiteoutFormat::Overlay {
+        whiteout::write_overlay(target, hidden)?;
+    }
+    Ok(report)
+}
+
+/// How many layers `pipelined` prepares at once: one core is left to the layer being unpacked
+pub fn scan_workers() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .saturating_sub(1)
+        .clamp(1, MAX_SCAN_WORKERS)
+}
+
+/// Run `prepare` on `layers` on up to `workers` threads, ahead of `apply`, which gets the
+/// results in layer order on this thread. With no workers each layer is prepared right before
+/// it is applied. Stops at the first error of either.
+pub fn pipelined<T: Send>(
+    layers: &[PathBuf],
+    workers: usize,
+    prepare: impl Fn(&Path) -> Result<T> + Sync,
+    mut apply: impl FnMut(usize, T) -> Result<()>,
+) -> Result<()> {
+    if workers == 0 {
+        for (idx, layer) in layers.iter().enumerate() {
+            apply(idx, prepare(layer)?)?;
+        }
+        return Ok(());
+    }
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..workers.min(layers.len()) {
+            let (sender, next, stop, prepare) = (sender.clone(), &next, &stop, &prepare);
+            scope.spawn(move || loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                if idx >= layers.len() || stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                if sender.send((idx, prepare(&layers[idx]))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let applied = apply_in_order(layers.len(), &receiver, &mut apply);
+        stop.store(true, Ordering::SeqCst);
+        applied
+    })
+}
+
+/// Hand the prepared layers arriving on `receiver` in any order to `apply` in layer order
+fn apply_in_order<T>(
+    count: usize,
+    receiver: &mpsc::Receiver<(usize, Result<T>)>,
+    apply: &mut impl FnMut(usize, T) -> Result<()>,
+) -> Result<()> {
+    let mut ready = BTreeMap::new();
+    for idx in 0..count {
+        let prepared = loop {
+            if let Some(prepared) = ready.remove(&idx) {
+                break prepared;
+            }
+            let (done, prepared) = receiver
+                .recv()
+                .map_err(|_| CuboError::SystemError("Layer preparation stopped early".to_string()))?;
+            ready.insert(done, prepared);
+        };
+        apply(idx, prepared?)?;
+    }
+    Ok(())
+}
+
+/// The tar stream of a layer. A gzip layer is decompressed on a thread of its own, a few
+/// chunks ahead of the reader, so inflating overlaps with writing the files out.
+fn open_layer(layer: &Path, gzip: bool) -> io::Result<Box<dyn Read>> {
+    let file = File::open(layer)?;
+    if !gzip {
+        return Ok(Box::new(file));
+    }
+    let (sender, receiver) = mpsc::sync_channel(INFLATE_AHEAD);
+    std::thread::spawn(move || {
+        let mut decoder = GzDecoder::new(file);
+        loop {
+            let mut chunk = vec![0; INFLATE_CHUNK];
+            let read = match decoder.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    Ok(chunk)
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = read.is_err();
+            // The reader is gone when unpacking stopped early
+            if sender.send(read).is_err() || failed {
+                break;
+            }
+        }
+    });
+    Ok(Box::new(InflatedReader { receiver, chunk: Vec::new(), pos: 0 }))
+}
+
+/// Reads what the decompressing thread of `open_layer` sends
+struct InflatedReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for InflatedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // Everything was decompressed
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Set an extended attribute of `path` itself, not of what it links to
+pub(crate) fn set_xattr(path: &Path, name: &OsStr, value: &[u8]) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name.as_bytes())?;
+    // SAFETY: both strings are NUL-terminated and value outlives the call
+    let rc = unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// An archive path with `.`, `/` and `..` dropped
+fn layer_path(path: &Path) -> PathBuf {
+    path.components().filter(|c| matches!(c, Component::Normal(_))).collect()
+}
+
+/// The (name, value) xattrs of an entry
+fn xattrs<R: Read>(entry: &mut tar::Entry<'_, R>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let Some(extensions) = entry.pax_extensions()? else {
+        return Ok(Vec::new());
+    };
+    let mut xattrs = Vec::new();
+    for extension in extensions {
+        let extension = extension?;
+        if let Some(name) = extension.key_bytes().strip_prefix(PAX_XATTR_PREFIX.as_bytes()) {
+            xattrs.push((name.to_vec(), extension.value_bytes().to_vec()));
+        }
+    }
+    Ok(xattrs)
+}
+
+fn set_xattrs(target: &Path, path: &Path, xattrs: &[(Vec<u8>, Vec<u8>)], report: &mut UnpackReport) {
+    for (name, value) in xattrs {
+        let name = OsStr::from_bytes(name);
+        if let Err(e) = set_xattr(&target.join(path), name, value) {
+            report.skipped.push(SkippedEntry {
+                path: path.to_path_buf(),
+                reason: format!("xattr {}: {}", name.to_string_lossy(), e),
+            });
+        }
+    }
+}
+
+/// Whether the file a hard link entry points to is in `target` to link to. It isn't when the
+/// layer is unpacked on its own and the file comes from a layer below.
+fn link_source_exists<R: Read>(target: &Path, entry: &tar::Entry<'_, R>) -> bool {
+    let Ok(Some(source)) = entry.link_name() else {
+        return false;
+    };
+    let source = layer_path(&source);
+    let parent = source.parent().unwrap_or(Path::new("")).to_string_lossy().to_string();
+    match (secure_join(target, &parent), source.file_name()) {
+        (Ok(parent), Some(name)) => fs::symlink_metadata(parent.join(name)).is_ok(),
+        _ => false,
+    }
+}
+
+/// Create a device node or FIFO entry, or say why it can't be
+fn make_node(target: &Path, path: &Path, header: &tar::Header, privileged: bool) -> std::result::Result<(), String> {
+    let (kind, device) = match header.entry_type() {
+        EntryType::Char => (SFlag::S_IFCHR, true),
+        EntryType::Block => (SFlag::S_IFBLK, true),
+        _ => (SFlag::S_IFIFO, false),
+    };
+    if device && !privileged {
+        return Err("device node, which only root can create".to_string());
+    }
+
+    let parent = secure_join(target, &path.parent().unwrap_or(Path::new("")).to_string_lossy())
+        .map_err(|e| e.to_string())?;
+    fs::create_dir_all(&parent).map_err(|e| e.to_string())?;
+    let Some(name) = path.file_name() else {
+        return Ok(());
+    };
+    let node = parent.join(name);
+    if fs::symlink_metadata(&node).is_ok_and(|meta| !meta.is_dir()) {
+        fs::remove_file(&node).map_err(|e| e.to_string())?;
+    }
+
+    let mask = if privileged { 0o7777 } else { 0o777 };
+    let mode = Mode::from_bits_truncate(header.mode().unwrap_or(0o644) & mask);
+    let major = header.device_major().ok().flatten().unwrap_or(0);
+    let minor = header.device_minor().ok().flatten().unwrap_or(0);
+    match mknod(&node, kind, mode, makedev(major.into(), minor.into())) {
+        Ok(()) => {
+            debug!("Created {:?} node {}", kind, node.display());
+            Ok(())
+        }
+        // Root in a user namespace can't make devices either
+        Err(Errno::EPERM) if device => Err("device node, which this process isn't allowed to create".to_string()),
+        Err(e) => Err(format!("mknod failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tempfile::TempDir;
+
+    fn header(entry_type: EntryType, mode: u32, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header
+    }
+
+    #[test]
+    fn test_unpack_layer_keeps_links_and_modes() {
+        let temp = TempDir::new().unwrap();
+        let layer = temp.path().join("layer.tar");
+        let mut builder = tar::Builder::new(File::create(&layer).unwrap());
+        builder.append_data(&mut header(EntryType::Directory, 0o555, 0), "usr/bin", io::empty()).unwrap();
+        builder.append_data(&mut header(EntryType::Regular, 0o4755, 3), "usr/bin/su", &b"bin"[..]).unwrap();
+        builder.append_link(&mut header(EntryType::Link, 0o4755, 0), "usr/bin/su-link", "usr/bin/su").unwrap();
+        builder.append_link(&mut header(EntryType::Link, 0o644, 0), "usr/bin/lower", "usr/lib/missing").unwrap();
+        builder.append_data(&mut header(EntryType::Fifo, 0o600, 0), "run/pipe", io::empty()).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let target = temp.path().join("rootfs");
+        fs::create_dir_all(&target).unwrap();
+        let report = unpack_layer(&layer, &target, false, WhiteoutFormat::Delete).unwrap();
+
+        let su = fs::metadata(target.join("usr/bin/su")).unwrap();
+        let expected_mode = if nix::unistd::geteuid().is_root() { 0o4755 } else { 0o755 };
+        assert_eq!(su.permissions().mode() & 0o7777, expected_mode);
+        assert_eq!(fs::metadata(target.join("usr/bin/su-link")).unwrap().ino(), su.ino());
+        assert_eq!(fs::metadata(target.join("usr/bin")).unwrap().permissions().mode() & 0o777, 0o555);
+        assert!(fs::symlink_metadata(target.join("run/pipe")).is_ok());
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, PathBuf::from("usr/bin/lower"));
+        fs::set_permissions(target.join("usr/bin"), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_layer_devices_and_xattrs() {
+        let temp = TempDir::new().unwrap();
+        let layer = temp.path().join("layer.tar");
+        let mut builder = tar::Builder::new(File::create(&layer).unwrap());
+        let mut null = header(EntryType::Char, 0o666, 0);
+        null.set_device_major(1).unwrap();
+        null.set_device_minor(3).unwrap();
+        builder.append_data(&mut null, "dev/null", io::empty()).unwrap();
+        builder
+            .append_pax_extensions([("SCHILY.xattr.user.cubo-test", &b"yes"[..])])
+            .unwrap();
+        builder.append_data(&mut header(EntryType::Regular, 0o755, 0), "bin/ping", io::empty()).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let target = temp.path().join("rootfs");
+        fs::create_dir_all(&target).unwrap();
+        let report = unpack_layer(&layer, &target, false, WhiteoutFormat::Delete).unwrap();
+
+        // Whatever this process may do, each entry is either unpacked or reported
+        let skipped = |path: &str| report.skipped.iter().any(|s| s.path == Path::new(path));
+        assert!(fs::symlink_metadata(target.join("dev/null")).is_ok() || skipped("dev/null"));
+        assert!(target.join("bin/ping").exists());
+        let xattr = xattr_value(&target.join("bin/ping"), "user.cubo-test");
+        assert!(xattr.as_deref() == Some(&b"yes"[..]) || skipped("bin/ping"));
+    }
+
+    #[test]
+    fn test_pipelined_applies_in_order() {
+        let layers: Vec<PathBuf> = (0..8).map(|i| PathBuf::from(format!("layer{}", i))).collect();
+        // Early layers take longest to prepare, so they finish out of order
+        let prepare = |layer: &Path| {
+            let idx: u64 = layer.to_string_lossy().trim_start_matches("layer").parse().unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(5 * (8 - idx)));
+            Ok(idx)
+        };
+        for workers in [0, 1, 3] {
+            let mut applied = Vec::new();
+            pipelined(&layers, workers, prepare, |n, idx| {
+                assert_eq!(n as u64, idx);
+                applied.push(idx);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(applied, (0..8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_pipelined_stops_at_first_error() {
+        let layers: Vec<PathBuf> = (0..6).map(|i| PathBuf::from(i.to_string())).collect();
+        let prepare = |layer: &Path| match layer.to_str() {
+            Some("3") => Err(CuboError::SystemError("corrupt layer 3".to_string())),
+            _ => Ok(()),
+        };
+        let mut applied = 0;
+        let err = pipelined(&layers, 2, prepare, |_, _| {
+            applied += 1;
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("corrupt layer 3"));
+        assert_eq!(applied, 3);
+    }
+
+    /// A gzip layer of `files` files of `size` bytes each, compressible like binaries are
+    fn gzip_layer(path: &Path, layer: usize, files: usize, size: usize) {
+        let encoder = flate2::write::GzEncoder::new(File::create(path).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let mut seed = layer as u64 + 1;
+        for file in 0..files {
+            let data: Vec<u8> = (0..size)
+                .map(|i| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                    if i % 4 == 0 { (seed >> 56) as u8 } else { b'a' + (i % 16) as u8 }
+                })
+                .collect();
+            let name = format!("layer{}/file{}", layer, file);
+            builder.append_data(&mut header(EntryType::Regular, 0o644, size as u64), name, &data[..]).unwrap();
+        }
+        if layer > 0 {
+            // Hides a file of the layer below
+            let whiteout = format!("layer{}/{}file0", layer - 1, WHITEOUT_PREFIX);
+            builder.append_data(&mut header(EntryType::Regular, 0o644, 0), whiteout, io::empty()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn unpack_all(layers: &[PathBuf], target: &Path, workers: usize) -> std::time::Duration {
+        fs::create_dir_all(target).unwrap();
+        let started = std::time::Instant::now();
+        pipelined(layers, workers, |layer| scan_layer(layer, true), |_, layer| {
+            unpack_scanned(&layer, target, WhiteoutFormat::Delete).map(|_| ())
+        })
+        .unwrap();
+        started.elapsed()
+    }
+
+    #[test]
+    fn test_pipelined_gzip_layers() {
+        let temp = TempDir::new().unwrap();
+        let layers: Vec<PathBuf> = (0..3).map(|i| temp.path().join(format!("{}.tar.gz", i))).collect();
+        for (idx, layer) in layers.iter().enumerate() {
+            gzip_layer(layer, idx, 4, 3 * INFLATE_CHUNK / 2);
+        }
+        let target = temp.path().join("rootfs");
+        unpack_all(&layers, &target, 2);
+
+        assert!(!target.join("layer0/file0").exists());
+        assert!(!target.join("layer1/file0").exists());
+        assert_eq!(fs::metadata(target.join("layer2/file0")).unwrap().len(), 3 * INFLATE_CHUNK as u64 / 2);
+        assert_eq!(fs::read_dir(target.join("layer1")).unwrap().count(), 3);
+    }
+
+    /// Benchmark: `cargo test --release bench_parallel_extraction -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_parallel_extraction() {
+        let temp = TempDir::new().unwrap();
+        let layers: Vec<PathBuf> = (0..16).map(|i| temp.path().join(format!("{}.tar.gz", i))).collect();
+        for (idx, layer) in layers.iter().enumerate() {
+            gzip_layer(layer, idx, 32, 256 * 1024);
+        }
+
+        let sequential = unpack_all(&layers, &temp.path().join("sequential"), 0);
+        let parallel = unpack_all(&layers, &temp.path().join("parallel"), scan_workers());
+        println!(
+            "16 layers: {:?} one at a time, {:?} with {} scan workers ({:.2}x)",
+            sequential,
+            parallel,
+            scan_workers(),
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+        if std::thread::available_parallelism().map_or(1, |n| n.get()) > 1 {
+            assert!(parallel < sequential);
+        }
+    }
+
+    fn xattr_value(path: &Path, name: &str) -> Option<Vec<u8>> {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = CString::new(name).unwrap();
+        let mut value = [0u8; 64];
+        // SAFETY: the buffer is as long as the size passed
+        let len = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        (len >= 0).then(|| value[..len as usize].to_vec())
+    }
+}
@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::{CuboError, Result};
+
+/// An /etc/passwd entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+}
+
+/// An /etc/group entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// Identity the container process runs as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecUser {
+    pub uid: u32,
+    pub gid: u32,
+    pub additional_gids: Vec<u32>,
+    /// User name, if the uid has a passwd entry
+    pub name: Option<String>,
+    pub home: String,
+}
+
+pub fn parse_passwd(content: &str) -> Vec<PasswdEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            Some(PasswdEntry {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: fields[5].to_string(),
+            })
+        })
+        .collect()
+}
+
+pub fn parse_group(content: &str) -> Vec<GroupEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 3 {
+                return None;
+            }
+            Some(GroupEntry {
+                name: fields[0].to_string(),
+                gid: fields[2].parse().ok()?,
+                members: fields
+                    .get(3)
+                    .map(|m| m.split(',').filter(|s| !s.is_empty()).map(String::from).collect())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+/// Split "user[:group]"; both parts must be non-empty
+pub fn parse_user_spec(spec: &str) -> Result<(&str, Option<&str>)> {
+    let invalid = || CuboError::InvalidConfiguration(format!("Invalid user specification '{}'", spec));
+
+    let mut parts = spec.split(':');
+    let user = parts.next().filter(|u| !u.is_empty()).ok_or_else(invalid)?;
+    let group = match parts.next() {
+        Some("") => return Err(invalid()),
+        other => other,
+    };
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok((user, group))
+}
+
+/// Resolve "user[:group]" against the image's passwd and group databases. Numeric ids
+/// don't need an entry; names do. The primary group defaults to the passwd entry's, or 0.
+pub fn resolve_user(spec: &str, passwd: &[PasswdEntry], groups: &[GroupEntry]) -> Result<ExecUser> {
+    let (user, group) = parse_user_spec(spec)?;
+
+    let entry = match user.parse::<u32>() {
+        Ok(uid) => passwd.iter().find(|e| e.uid == uid),
+        Err(_) => Some(
+            passwd
+                .iter()
+                .find(|e| e.name == user)
+                .ok_or_else(|| CuboError::InvalidConfiguration(format!("Unknown user '{}' in image", user)))?,
+        ),
+    };
+    let uid = match entry {
+        Some(e) => e.uid,
+        None => user.parse().map_err(|_| CuboError::InvalidConfiguration(format!("Invalid UID: {}", user)))?,
+    };
+
+    let gid = match group {
+        Some(group) => match group.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => groups
+                .iter()
+                .find(|g| g.name == group)
+                .map(|g| g.gid)
+                .ok_or_else(|| CuboError::InvalidConfiguration(format!("Unknown group '{}' in image", group)))?,
+        },
+        None => entry.map(|e| e.gid).unwrap_or(0),
+    };
+
+    let mut additional_gids: Vec<u32> = match entry {
+        Some(e) => groups
+            .iter()
+            .filter(|g| g.members.contains(&e.name))
+            .map(|g| g.gid)
+            .collect(),
+        None => Vec::new(),
+    };
+    additional_gids.retain(|g| *g != gid);
+    additional_gids.dedup();
+
+    Ok(ExecUser {
+        uid,
+        gid,
+        additional_gids,
+        name: entry.map(|e| e.name.clone()),
+        home: entry.map(|e| e.home.clone()).unwrap_or_else(|| "/".to_string()),
+    })
+}
+
+/// Resolve a user spec against `<etc>/passwd` and `<etc>/group`; missing files count as empty
+pub fn lookup(etc: &Path, spec: &str) -> Result<ExecUser> {
+    let passwd = parse_passwd(&fs::read_to_string(etc.join("passwd")).unwrap_or_default());
+    let groups = parse_group(&fs::read_to_string(etc.join("group")).unwrap_or_default());
+    resolve_user(spec, &passwd, &groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh\napp:x:1000:1000:App:/home/app:/bin/sh\n# comment\nbroken:x\n";
+    const GROUP: &str = "root:x:0:\napp:x:1000:\nwheel:x:10:app,other\naudio:x:29:app\nstaff:x:50:\n";
+
+    fn resolve(spec: &str) -> Result<ExecUser> {
+        resolve_user(spec, &parse_passwd(PASSWD), &parse_group(GROUP))
+    }
+
+    #[test]
+    fn test_parse_passwd_and_group() {
+        let passwd = parse_passwd(PASSWD);
+        assert_eq!(passwd.len(), 2);
+        assert_eq!(passwd[1], PasswdEntry { name: "app".into(), uid: 1000, gid: 1000, home: "/home/app".into() });
+
+        let groups = parse_group(GROUP);
+        assert_eq!(groups.len(), 5);
+        assert_eq!(groups[2].members, vec!["app", "other"]);
+        assert!(groups[0].members.is_empty());
+    }
+
+    #[test]
+    fn test_parse_user_spec() {
+        assert_eq!(parse_user_spec("app").unwrap(), ("app", None));
+        assert_eq!(parse_user_spec("1000:staff").unwrap(), ("1000", Some("staff")));
+        assert!(parse_user_spec("").is_err());
+        assert!(parse_user_spec("app:").is_err());
+        assert!(parse_user_spec("1000:1000:extra").is_err());
+    }
+
+    #[test]
+    fn test_resolve_user_by_name() {
+        let user = resolve("app").unwrap();
+        assert_eq!(user.uid, 1000);
+        assert_eq!(user.gid, 1000);
+        assert_eq!(user.additional_gids, vec![10, 29]);
+        assert_eq!(user.name.as_deref(), Some("app"));
+        assert_eq!(user.home, "/home/app");
+    }
+
+    #[test]
+    fn test_resolve_user_with_group_name() {
+        let user = resolve("app:staff").unwrap();
+        assert_eq!(user.gid, 50);
+        assert_eq!(user.additional_gids, vec![10, 29]);
+    }
+
+    #[test]
+    fn test_resolve_numeric_user_without_entry() {
+        let user = resolve("4242:4242").unwrap();
+        assert_eq!((user.uid, user.gid), (4242, 4242));
+        assert!(user.additional_gids.is_empty());
+        assert!(user.name.is_none());
+        assert_eq!(user.home, "/");
+
+        assert_eq!(resolve("4242").unwrap().gid, 0);
+    }
+
+    #[test]
+    fn test_resolve_numeric_user_with_entry() {
+        let user = resolve("1000").unwrap();
+        assert_eq!(user.name.as_deref(), Some("app"));
+        assert_eq!(user.gid, 1000);
+    }
+
+    #[test]
+    fn test_resolve_unknown_names() {
+        assert!(resolve("nobody-here").unwrap_err().to_string().contains("Unknown user"));
+        assert!(resolve("app:nogroup").unwrap_err().to_string().contains("Unknown group"));
+    }
+
+    #[test]
+    fn test_lookup_reads_files() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("passwd"), PASSWD).unwrap();
+        fs::write(temp.path().join("group"), GROUP).unwrap();
+        assert_eq!(lookup(temp.path(), "app").unwrap().uid, 1000);
+
+        let empty = TempDir::new().unwrap();
+        assert!(lookup(empty.path(), "app").is_err());
+        assert_eq!(lookup(empty.path(), "7").unwrap().uid, 7);
+    }
+}
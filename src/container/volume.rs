@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::container::container_store::{atomic_write_json, read_json};
+use crate::error::{CuboError, Result};
+
+/// Driver of volumes created without `--driver`, keeping their data under the root directory
+pub const LOCAL_DRIVER: &str = "local";
+
+/// Executables named `cubo-volume-<driver>` provide the drivers other than local
+const PLUGIN_PREFIX: &str = "cubo-volume-";
+
+/// A named volume, stored as volumes/<name>.json under the root directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Volume {
+    pub name: String,
+    pub driver: String,
+    /// Driver options from `cubo volume create -o KEY=VALUE`
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// What provides the storage of volumes: creates and removes it, and mounts it for a
+/// container on a host path that's then bind mounted into the container
+pub trait VolumeDriver: Send + Sync {
+    fn create(&self, volume: &Volume) -> Result<()>;
+
+    fn remove(&self, volume: &Volume) -> Result<()>;
+
+    /// Make the volume available to the container; returns the host path holding it
+    fn mount(&self, volume: &Volume, container_id: &str) -> Result<PathBuf>;
+
+    /// Release the volume once the container stopped. May be called more than once.
+    fn unmount(&self, volume: &Volume, container_id: &str) -> Result<()>;
+}
+
+/// Keeps the data of a volume in volumes/<name>/_data
+pub struct LocalDriver {
+    root: PathBuf,
+}
+
+impl LocalDriver {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn data_dir(&self, volume: &Volume) -> PathBuf {
+        self.root.join(&volume.name).join("_data")
+    }
+}
+
+impl VolumeDriver for LocalDriver {
+    fn create(&self, volume: &Volume) -> Result<()> {
+        if let Some(option) = volume.options.keys().next() {
+            return Err(CuboError::VolumeError(format!("The local driver has no option '{}'", option)));
+        }
+        let dir = self.data_dir(volume);
+        fs::create_dir_all(&dir)
+            .map_err(|e| CuboError::VolumeError(format!("Failed to create {}: {}", dir.display(), e)))
+    }
+
+    fn remove(&self, volume: &Volume) -> Result<()> {
+        let dir = self.root.join(&volume.name);
+        match fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(CuboError::VolumeError(format!("Failed to remove {}: {}", dir.display(), e)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn mount(&self, volume: &Volume, _container_id: &str) -> Result<PathBuf> {
+        let dir = self.data_dir(volume);
+        fs::create_dir_all(&dir)
+            .map_err(|e| CuboError::VolumeError(format!("Failed to create {}: {}", dir.display(), e)))?;
+        Ok(dir)
+    }
+
+    fn unmount(&self, _volume: &Volume, _container_id: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Request written to a plugin's stdin
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PluginRequest<'a> {
+    name: &'a str,
+    opts: &'a BTreeMap<String, String>,
+    #[serde(rename = "ID", skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+}
+
+/// Response a plugin prints on stdout; a non-empty `Err` fails the call
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct PluginResponse {
+    mountpoint: Option<PathBuf>,
+    err: String,
+}
+
+/// A driver provided by an external program. Each call runs `<program> <Method>` with the
+/// method one of Create, Remove, Mount and Unmount, the request as JSON on stdin
+/// (`{"Name": ..., "Opts": {...}, "ID": ...}`) and the response as JSON on stdout
+/// (`{"Mountpoint": ..., "Err": ...}`), like the requests of Docker volume plugins.
+pub struct PluginDriver {
+    program: PathBuf,
+}
+
+impl PluginDriver {
+    pub fn new(program: PathBuf) -> Self {
+        Self { program }
+    }
+
+    /// The `cubo-volume-<driver>` program in `plugin_dir`, or else on the PATH
+    pub fn find(driver: &str, plugin_dir: &Path) -> Result<Self> {
+        let name = format!("{}{}", PLUGIN_PREFIX, driver);
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        std::iter::once(plugin_dir.to_path_buf())
+            .chain(std::env::split_paths(&path))
+            .map(|dir| dir.join(&name))
+            .find(|candidate| is_executable(candidate))
+            .map(Self::new)
+            .ok_or_else(|| CuboError::VolumeError(format!(
+                "Volume driver '{}' not found: no {} in {} or on the PATH",
+                driver,
+                name,
+                plugin_dir.display()
+            )))
+    }
+
+    fn call(&self, method: &str, volume: &Volume, container_id: Option<&str>) -> Result<PluginResponse> {
+        let request = PluginRequest { name: &volume.name, opts: &volume.options, id: container_id };
+        let request = serde_json::to_vec(&request)
+            .map_err(|e| CuboError::VolumeError(format!("Failed to encode {} request: {}", method, e)))?;
+        let failed = |reason: String| {
+            CuboError::VolumeError(format!(
+                "Volume driver {} failed to {} {}: {}",
+                self.program.display(),
+                method.to_lowercase(),
+                volume.name,
+                reason
+            ))
+        };
+        debug!("Calling {} {} for volume {}", self.program.display(), method, volume.name);
+
+        let mut child = Command::new(&self.program)
+            .arg(method)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| failed(e.to_string()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A plugin that doesn't need the request may exit without reading it
+            match stdin.write_all(&request) {
+                Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(failed(e.to_string())),
+                _ => {}
+            }
+        }
+        let output = child.wait_with_output().map_err(|e| failed(e.to_string()))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(failed(format!("{}: {}", output.status, stderr.trim())));
+        }
+
+        let response: PluginResponse = if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            PluginResponse::default()
+        } else {
+            serde_json::from_slice(&output.stdout).map_err(|e| failed(format!("invalid response: {}", e)))?
+        };
+        if !response.err.is_empty() {
+            return Err(failed(response.err));
+        }
+        Ok(response)
+    }
+}
+
+impl VolumeDriver for PluginDriver {
+    fn create(&self, volume: &Volume) -> Result<()> {
+        self.call("Create", volume, None).map(drop)
+    }
+
+    fn remove(&self, volume: &Volume) -> Result<()> {
+        self.call("Remove", volume, None).map(drop)
+    }
+
+    fn mount(&self, volume: &Volume, container_id: &str) -> Result<PathBuf> {
+        let mountpoint = self.call("Mount", volume, Some(container_id))?.mountpoint.ok_or_else(|| {
+            CuboError::VolumeError(format!("Volume driver {} gave no Mountpoint for {}", volume.driver, volume.name))
+        })?;
+        if !mountpoint.is_absolute() {
+            return Err(CuboError::VolumeError(format!(
+                "Volume driver {} gave the relative Mountpoint {} for {}",
+                volume.driver,
+                mountpoint.display(),
+                volume.name
+            )));
+        }
+        Ok(mountpoint)
+    }
+
+    fn unmount(&self, volume: &Volume, container_id: &str) -> Result<()> {
+        self.call("Unmount", volume, Some(container_id)).map(drop)
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+/// Named volumes under the root directory, each handled by its driver
+pub struct VolumeStore {
+    root: PathBuf,
+    plugin_dir: PathBuf,
+}
+
+impl VolumeStore {
+    /// The store of volumes under `root_dir`, finding driver plugins in its plugins directory
+    pub fn new(root_dir: &Path) -> Result<Self> {
+        let root = root_dir.join("volumes");
+        fs::create_dir_all(&root)
+            .map_err(|e| CuboError::VolumeError(format!("Failed to create volume store: {}", e)))?;
+        Ok(Self { root, plugin_dir: root_dir.join("plugins") })
+    }
+
+    fn path_for(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.json", name))
+    }
+
+    pub fn driver(&self, name: &str) -> Result<Box<dyn VolumeDriver>> {
+        match name {
+            LOCAL_DRIVER => Ok(Box::new(LocalDriver::new(self.root.clone()))),
+            plugin => Ok(Box::new(PluginDriver::find(plugin, &self.plugin_dir)?)),
+        }
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.path_for(name).exists()
+    }
+
+    pub fn create(&self, name: &str, driver: &str, options: BTreeMap<String, String>) -> Result<Volume> {
+        validate_volume_name(name)?;
+        if self.exists(name) {
+            return Err(CuboError::VolumeError(format!("Volume already exists: {}", name)));
+        }
+
+        let volume = Volume {
+            name: name.to_string(),
+            driver: driver.to_string(),
+            options,
+            created_at: chrono::Utc::now(),
+        };
+        self.driver(driver)?.create(&volume)?;
+        self.save(&volume)?;
+        Ok(volume)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Volume> {
+        let path = self.path_for(name);
+        if !path.exists() {
+            return Err(CuboError::VolumeError(format!("Volume not found: {}", name)));
+        }
+        read_json(&path)
+    }
+
+    pub fn list(&self) -> Result<Vec<Volume>> {
+        let mut volumes = Vec::new();
+        let entries = fs::read_dir(&self.root)
+            .map_err(|e| CuboError::VolumeError(format!("Failed to read volume store: {}", e)))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_json::<Volume>(&path) {
+                Ok(volume) => volumes.push(volume),
+                Err(e) => warn!("Skipping unreadable volume {:?}: {}", path, e),
+            }
+        }
+
+        volumes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(volumes)
+    }
+
+    pub fn save(&self, volume: &Volume) -> Result<()> {
+        atomic_write_json(&self.path_for(&volume.name), volume)
+    }
+
+    /// Delete a volume and, through its driver, its data
+    pub fn remove(&self, name: &str) -> Result<Volume> {
+        let volume = self.get(name)?;
+        self.driver(&volume.driver)?.remove(&volume)?;
+        fs::remove_file(self.path_for(name))
+            .map_err(|e| CuboError::VolumeError(format!("Failed to remove volume {}: {}", name, e)))?;
+        Ok(volume)
+    }
+
+    /// Mount a volume for a container, creating it with the local driver when there's no
+    /// volume of that name yet. Returns the host path to bind mount.
+    pub fn mount(&self, name: &str, container_id: &str) -> Result<PathBuf> {
+        let volume = match self.exists(name) {
+            true => self.get(name)?,
+            false => self.create(name, LOCAL_DRIVER, BTreeMap::new())?,
+        };
+        self.driver(&volume.driver)?.mount(&volume, container_id)
+    }
+
+    pub fn unmount(&self, name: &str, container_id: &str) -> Result<()> {
+        let volume = self.get(name)?;
+        self.driver(&volume.driver)?.unmount(&volume, container_id)
+    }
+}
+
+pub fn validate_volume_name(name: &str) -> Result<()> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c));
+    if !valid {
+        return Err(CuboError::VolumeError(format!(
+            "Invalid volume name '{}': use letters, digits, '_', '.' and '-'",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// `-o KEY=VALUE` driver options
+pub fn parse_options(options: &[String]) -> Result<BTreeMap<String, String>> {
+    options
+        .iter()
+        .map(|option| {
+            option
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid volume option '{}', expected KEY=VALUE", option)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_plugin(dir: &Path, driver: &str, script: &str) {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(format!("{}{}", PLUGIN_PREFIX, driver));
+        fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_local_volume_lifecycle() {
+        let temp = TempDir::new().unwrap();
+        let store = VolumeStore::new(temp.path()).unwrap();
+
+        let volume = store.create("data", LOCAL_DRIVER, BTreeMap::new()).unwrap();
+        assert_eq!(volume.driver, "local");
+        assert!(store.create("data", LOCAL_DRIVER, BTreeMap::new()).is_err());
+
+        let mountpoint = store.mount("data", "c1").unwrap();
+        assert_eq!(mountpoint, temp.path().join("volumes/data/_data"));
+        assert!(mountpoint.is_dir());
+        store.unmount("data", "c1").unwrap();
+
+        assert_eq!(store.list().unwrap(), vec![volume]);
+        store.remove("data").unwrap();
+        assert!(!mountpoint.exists());
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mount_creates_missing_local_volume() {
+        let temp = TempDir::new().unwrap();
+        let store = VolumeStore::new(temp.path()).unwrap();
+
+        store.mount("cache", "c1").unwrap();
+        assert_eq!(store.get("cache").unwrap().driver, LOCAL_DRIVER);
+    }
+
+    #[test]
+    fn test_local_driver_rejects_options() {
+        let temp = TempDir::new().unwrap();
+        let store = VolumeStore::new(temp.path()).unwrap();
+        let options = parse_options(&["size=1g".to_string()]).unwrap();
+        assert!(matches!(store.create("data", LOCAL_DRIVER, options), Err(CuboError::VolumeError(_))));
+        assert!(!store.exists("data"));
+    }
+
+    #[test]
+    fn test_invalid_names_and_options() {
+        assert!(validate_volume_name("my-vol_1.0").is_ok());
+        assert!(validate_volume_name("-vol").is_err());
+        assert!(validate_volume_name("a/b").is_err());
+        assert!(parse_options(&["=x".to_string()]).is_err());
+        assert!(parse_options(&["novalue".to_string()]).is_err());
+        assert_eq!(parse_options(&["a=b=c".to_string()]).unwrap()["a"], "b=c");
+    }
+
+    #[test]
+    fn test_plugin_driver_protocol() {
+        let temp = TempDir::new().unwrap();
+        let log = temp.path().join("calls");
+        let script = format!(
+            "read -r request\necho \"$1 $request\" >> {}\n[ \"$1\" = Mount ] && echo '{{\"Mountpoint\": \"/mnt/nfs\"}}'\nexit 0\n",
+            log.display()
+        );
+        write_plugin(&temp.path().join("plugins"), "nfs", &script);
+        let store = VolumeStore::new(temp.path()).unwrap();
+
+        let options = parse_options(&["server=10.0.0.1".to_string()]).unwrap();
+        store.create("shared", "nfs", options).unwrap();
+        assert_eq!(store.mount("shared", "abc").unwrap(), PathBuf::from("/mnt/nfs"));
+        store.unmount("shared", "abc").unwrap();
+        store.remove("shared").unwrap();
+
+        let calls = fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = calls.lines().collect();
+        assert_eq!(lines[0], r#"Create {"Name":"shared","Opts":{"server":"10.0.0.1"}}"#);
+        assert_eq!(lines[1], r#"Mount {"Name":"shared","Opts":{"server":"10.0.0.1"},"ID":"abc"}"#);
+        assert!(lines[2].starts_with("Unmount "));
+        assert!(lines[3].starts_with("Remove "));
+    }
+
+    #[test]
+    fn test_plugin_errors() {
+        let temp = TempDir::new().unwrap();
+        write_plugin(&temp.path().join("plugins"), "broken", "echo '{\"Err\": \"no quota left\"}'\n");
+        let store = VolumeStore::new(temp.path()).unwrap();
+
+        let err = store.create("v", "broken", BTreeMap::new()).unwrap_err();
+        assert!(err.to_string().contains("no quota left"), "{}", err);
+        assert!(!store.exists("v"));
+        assert!(matches!(store.create("v", "missing", BTreeMap::new()), Err(CuboError::VolumeError(_))));
+    }
+}
@@ -0,0 +1,216 @@
+//! OCI whiteouts: a `.wh.NAME` entry in a layer deletes NAME from the layers below it, and
+//! `.wh..wh..opq` in a directory hides everything the layers below have in that directory
+
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use tracing::debug;
+
+use crate::container::secure_join::secure_join;
+use crate::container::unpack::set_xattr;
+use crate::error::{CuboError, Result};
+
+/// Prefix of the file names that mark a whiteout
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// File name that makes its directory opaque
+pub const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Extended attribute overlayfs marks opaque directories with
+const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// What a whiteout in a layer hides, as a path relative to the rootfs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Whiteout {
+    /// This file or directory is deleted
+    Path(PathBuf),
+    /// What the layers below have in this directory is deleted
+    Opaque(PathBuf),
+}
+
+/// How the whiteouts of a layer are unpacked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WhiteoutFormat {
+    /// Delete what they hide from the layers already unpacked to the same directory
+    Delete,
+    /// Write them as overlayfs does, for a layer that becomes an overlay lower directory
+    Overlay,
+}
+
+/// The whiteouts of a layer tar, in archive order
+pub fn read_whiteouts(layer: &Path, gzip: bool) -> Result<Vec<Whiteout>> {
+    let failed = |e: io::Error| CuboError::LayerExtractionError {
+        layer: layer.to_path_buf(),
+        message: format!("Failed to read whiteouts: {}", e),
+    };
+    let file = File::open(layer).map_err(failed)?;
+    let reader: Box<dyn Read> = if gzip { Box::new(GzDecoder::new(file)) } else { Box::new(file) };
+
+    let mut whiteouts = Vec::new();
+    for entry in tar::Archive::new(reader).entries().map_err(failed)? {
+        let entry = entry.map_err(failed)?;
+        let path: PathBuf = entry
+            .path()
+            .map_err(failed)?
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if name == OPAQUE_WHITEOUT {
+            whiteouts.push(Whiteout::Opaque(dir));
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            whiteouts.push(Whiteout::Path(dir.join(hidden)));
+        }
+    }
+    Ok(whiteouts)
+}
+
+/// Delete what `whiteouts` hide from `rootfs`, before their layer is unpacked over it
+pub fn delete_hidden(rootfs: &Path, whiteouts: &[Whiteout]) -> Result<()> {
+    for whiteout in whiteouts {
+        match whiteout {
+            Whiteout::Path(path) => {
+                // The hidden entry itself goes, not what it links to
+                let parent = secure_join(rootfs, &path.parent().unwrap_or(Path::new("")).to_string_lossy())?;
+                if let Some(name) = path.file_name() {
+                    remove_entry(&parent.join(name))?;
+                }
+            }
+            Whiteout::Opaque(dir) => {
+                let dir = secure_join(rootfs, &dir.to_string_lossy())?;
+                if !fs::symlink_metadata(&dir).is_ok_and(|meta| meta.is_dir()) {
+                    continue;
+                }
+                for entry in fs::read_dir(&dir)? {
+                    remove_entry(&entry?.path())?;
+                }
+            }
+        }
+        debug!("Applied whiteout {:?}", whiteout);
+    }
+    Ok(())
+}
+
+/// Write `whiteouts` into `upper` the way overlayfs keeps them: a 0/0 character device for a
+/// deleted path and an xattr on an opaque directory
+pub fn write_overlay(upper: &Path, whiteouts: &[Whiteout]) -> Result<()> {
+    let failed = |path: &Path, e: &dyn std::fmt::Display| {
+        CuboError::SystemError(format!("Failed to write whiteout {}: {}", path.display(), e))
+    };
+    for whiteout in whiteouts {
+        match whiteout {
+            Whiteout::Path(path) => {
+                let parent = secure_join(upper, &path.parent().unwrap_or(Path::new("")).to_string_lossy())?;
+                fs::create_dir_all(&parent)?;
+                let Some(name) = path.file_name() else {
+                    continue;
+                };
+                let target = parent.join(name);
+                remove_entry(&target)?;
+                mknod(&target, SFlag::S_IFCHR, Mode::empty(), makedev(0, 0)).map_err(|e| failed(&target, &e))?;
+            }
+            Whiteout::Opaque(dir) => {
+                let dir = secure_join(upper, &dir.to_string_lossy())?;
+                fs::create_dir_all(&dir)?;
+                set_xattr(&dir, OsStr::new(OVERLAY_OPAQUE_XATTR), b"y").map_err(|e| failed(&dir, &e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remove_entry(path: &Path) -> Result<()> {
+    let removed = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    };
+    removed.map_err(|e| CuboError::SystemError(format!("Failed to remove {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, FileTypeExt};
+    use tempfile::TempDir;
+
+    /// A layer tar with the given files, written with empty content
+    fn layer(dir: &Path, files: &[&str]) -> PathBuf {
+        let path = dir.join("layer.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        for file in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(0);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, file, io::empty()).unwrap();
+        }
+        builder.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_read_whiteouts() {
+        let temp = TempDir::new().unwrap();
+        let layer = layer(temp.path(), &["./etc/.wh.motd", "var/cache/.wh..wh..opq", "usr/bin/app", ".wh.opt"]);
+        assert_eq!(
+            read_whiteouts(&layer, false).unwrap(),
+            vec![
+                Whiteout::Path(PathBuf::from("etc/motd")),
+                Whiteout::Opaque(PathBuf::from("var/cache")),
+                Whiteout::Path(PathBuf::from("opt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_delete_hidden() {
+        let temp = TempDir::new().unwrap();
+        let rootfs = temp.path().join("rootfs");
+        for dir in ["etc", "opt/app", "var/cache/apt", "outside"] {
+            fs::create_dir_all(rootfs.join(dir)).unwrap();
+        }
+        fs::write(rootfs.join("etc/motd"), "hi").unwrap();
+        fs::write(rootfs.join("etc/hostname"), "box").unwrap();
+        fs::write(rootfs.join("var/cache/index"), "").unwrap();
+        fs::write(temp.path().join("host-file"), "host").unwrap();
+        // A link that resolves out of the rootfs must be removed, not followed
+        symlink(temp.path(), rootfs.join("escape")).unwrap();
+
+        let whiteouts = [
+            Whiteout::Path(PathBuf::from("etc/motd")),
+            Whiteout::Path(PathBuf::from("opt")),
+            Whiteout::Opaque(PathBuf::from("var/cache")),
+            Whiteout::Path(PathBuf::from("missing/file")),
+            Whiteout::Path(PathBuf::from("escape")),
+        ];
+        delete_hidden(&rootfs, &whiteouts).unwrap();
+
+        assert!(!rootfs.join("etc/motd").exists());
+        assert!(rootfs.join("etc/hostname").exists());
+        assert!(!rootfs.join("opt").exists());
+        assert!(rootfs.join("var/cache").is_dir());
+        assert_eq!(fs::read_dir(rootfs.join("var/cache")).unwrap().count(), 0);
+        assert!(fs::symlink_metadata(rootfs.join("escape")).is_err());
+        assert!(temp.path().join("host-file").exists());
+    }
+
+    #[test]
+    #[ignore] // Needs CAP_MKNOD and trusted xattrs
+    fn test_write_overlay() {
+        let temp = TempDir::new().unwrap();
+        let whiteouts = [Whiteout::Path(PathBuf::from("etc/motd")), Whiteout::Opaque(PathBuf::from("var/cache"))];
+        write_overlay(temp.path(), &whiteouts).unwrap();
+
+        let meta = fs::symlink_metadata(temp.path().join("etc/motd")).unwrap();
+        assert!(meta.file_type().is_char_device());
+        assert!(temp.path().join("var/cache").is_dir());
+    }
+}
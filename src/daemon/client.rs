@@ -0,0 +1,173 @@
+use super::http::{self, Request};
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use tokio::net::UnixStream;
+
+/// Talks to a running `cubod` over its unix socket
+#[derive(Debug, Clone)]
+pub struct DaemonClient {
+    socket: PathBuf,
+}
+
+impl DaemonClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self { socket: socket.into() }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Send one request and decode the JSON reply; error statuses become `DaemonError`
+    async fn send<T: DeserializeOwned>(&self, request: Request) -> Result<T> {
+        let stream = UnixStream::connect(&self.socket).await.map_err(|e| {
+            CuboError::DaemonError(format!(
+                "Cannot connect to the daemon at {}: {} (is cubod running?)",
+                self.socket.display(),
+                e
+            ))
+        })?;
+        let (read, mut write) = stream.into_split();
+        http::write_request(&mut write, &request).await?;
+        let response = http::read_response(read).await?;
+
+        if !(200..300).contains(&response.status) {
+            let message = serde_json::from_slice::<serde_json::Value>(&response.body)
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| format!("request failed with status {}", response.status));
+            return Err(CuboError::DaemonError(message));
+        }
+        serde_json::from_slice(&response.body)
+            .map_err(|e| CuboError::DaemonError(format!("Invalid response from daemon: {}", e)))
+    }
+
+    fn path(route: &str) -> String {
+        format!("/{}/{}", super::API_VERSION, route)
+    }
+
+    pub async fn version(&self) -> Result<serde_json::Value> {
+        self.send(Request::new("GET", &Self::path("version"))).await
+    }
+
+    pub async fn list_containers(&self, all: bool) -> Result<Vec<Container>> {
+        self.send(Request::new("GET", &Self::path(&format!("containers?all={}", all)))).await
+    }
+
+    pub async fn get_container(&self, identifier: &str) -> Result<Container> {
+        self.send(Request::new("GET", &Self::path(&format!("containers/{}", identifier)))).await
+    }
+
+    /// Create a container, returning its ID
+    pub async fn create_container(&self, container: &Container) -> Result<String> {
+        let body = serde_json::to_vec(container)
+            .map_err(|e| CuboError::SystemError(format!("Failed to serialize container: {}", e)))?;
+        let reply: serde_json::Value = self
+            .send(Request::new("POST", &Self::path("containers")).with_body(body))
+            .await?;
+        id_of(reply)
+    }
+
+    /// Start a container in the background
+    pub async fn start_container(&self, identifier: &str) -> Result<String> {
+        id_of(self.send(Request::new("POST", &Self::path(&format!("containers/{}/start", identifier)))).await?)
+    }
+
+    pub async fn stop_container(&self, identifier: &str, timeout: Option<u64>) -> Result<String> {
+        let mut route = format!("containers/{}/stop", identifier);
+        if let Some(secs) = timeout {
+            route.push_str(&format!("?timeout={}", secs));
+        }
+        id_of(self.send(Request::new("POST", &Self::path(&route))).await?)
+    }
+
+    pub async fn remove_container(&self, identifier: &str, force: bool) -> Result<String> {
+        let route = format!("containers/{}?force={}", identifier, force);
+        id_of(self.send(Request::new("DELETE", &Self::path(&route))).await?)
+    }
+
+    pub async fn list_images(&self) -> Result<Vec<String>> {
+        self.send(Request::new("GET", &Self::path("images"))).await
+    }
+
+    pub async fn pull(&self, reference: &str) -> Result<()> {
+        let _: serde_json::Value = self
+            .send(Request::new("POST", &Self::path(&format!("images/pull?reference={}", reference))))
+            .await?;
+        Ok(())
+    }
+}
+
+fn id_of(reply: serde_json::Value) -> Result<String> {
+    reply["id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| CuboError::DaemonError("Daemon reply has no container id".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::daemon::Daemon;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    async fn start_daemon(temp_dir: &TempDir) -> (DaemonClient, oneshot::Sender<()>, JoinHandle<Result<()>>) {
+        let socket = temp_dir.path().join("cubo.sock");
+        let daemon = Daemon::new(RuntimeConfig {
+            root_dir: temp_dir.path().join("root"),
+            ..Default::default()
+        })
+        .unwrap();
+        let served = socket.clone();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            daemon.serve_until(&served, None, async { let _ = stopped.await; }).await
+        });
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        (DaemonClient::new(socket), stop, handle)
+    }
+
+    #[tokio::test]
+    async fn test_client_against_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        let (client, stop, handle) = start_daemon(&temp_dir).await;
+
+        let version = client.version().await.unwrap();
+        assert_eq!(version["api_version"], crate::daemon::API_VERSION);
+
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_name("remote".to_string());
+        let id = client.create_container(&container).await.unwrap();
+        assert_eq!(id, container.id);
+        assert_eq!(client.get_container("remote").await.unwrap().id, id);
+        assert_eq!(client.list_containers(true).await.unwrap().len(), 1);
+
+        client.remove_container("remote", false).await.unwrap();
+        let err = client.get_container("remote").await.unwrap_err();
+        assert!(err.to_string().contains("Container not found: remote"));
+
+        stop.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!client.socket().exists());
+    }
+
+    #[tokio::test]
+    async fn test_client_without_daemon() {
+        let temp_dir = TempDir::new().unwrap();
+        let client = DaemonClient::new(temp_dir.path().join("missing.sock"));
+        let err = client.version().await.unwrap_err();
+        assert!(matches!(err, CuboError::DaemonError(_)));
+        assert!(err.to_string().contains("is cubod running?"));
+    }
+}
@@ -0,0 +1,1318 @@
+//! Kubernetes Container Runtime Interface (proto/runtime/v1/api.proto) so a kubelet can use
+//! cubo as its container runtime. A pod sandbox is an infra container running the pause
+//! image that holds the pod's network and IPC namespaces; the pod's containers join them.
+//! CRI metadata (pod names, labels, log paths) is kept next to the runtime's own state.
+
+pub mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("runtime.v1");
+}
+
+use super::docker::image_id;
+use super::grpc::to_status;
+use super::Daemon;
+use crate::commands::run::validate_config;
+use crate::container::container_store::{atomic_write_json, read_json};
+use crate::container::exec::exec_command;
+use crate::container::image_store::{ImageConfig, ImageStore};
+use crate::container::network::NetworkStore;
+use crate::container::{
+    Container, ContainerStatus, LogDriverKind, LogOptions, NetworkMode, PortMapping, Protocol, Relabel,
+    UsernsMode, VolumeMount,
+};
+use crate::error::{CuboError, Result};
+use proto::image_service_server::{ImageService, ImageServiceServer};
+use proto::runtime_service_server::{RuntimeService, RuntimeServiceServer};
+use proto::{ContainerState, NamespaceMode, PodSandboxState};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+/// Image the infra container of every pod sandbox runs
+pub const DEFAULT_PAUSE_IMAGE: &str = "registry.k8s.io/pause:3.9";
+/// User-defined network pod sandboxes get their IP on
+pub const POD_NETWORK: &str = "cubo-pods";
+
+const RUNTIME_NAME: &str = "cubo";
+const CRI_API_VERSION: &str = "v1";
+/// How long RunPodSandbox waits for the infra container to come up
+const SANDBOX_START_TIMEOUT: Duration = Duration::from_secs(5);
+/// Grace period when stopping infra containers, which only run pause
+const SANDBOX_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+type GrpcResult<T> = std::result::Result<Response<T>, Status>;
+
+/// Settings of the CRI server
+#[derive(Debug, Clone)]
+pub struct CriConfig {
+    /// Socket the kubelet connects to
+    pub socket: PathBuf,
+    /// Image of the pod sandbox infra containers
+    pub pause_image: String,
+}
+
+/// Serve the CRI runtime and image services on `listener` until `shutdown` completes
+pub async fn serve(
+    daemon: Daemon,
+    listener: UnixListener,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<()> {
+    let pause_image = daemon
+        .cri
+        .as_ref()
+        .map(|config| config.pause_image.clone())
+        .unwrap_or_else(|| DEFAULT_PAUSE_IMAGE.to_string());
+    let service = CriService {
+        store: CriStore::new(daemon.root_dir.join("cri"))?,
+        daemon,
+        pause_image,
+        pod_cidr: Arc::new(Mutex::new(None)),
+    };
+    Server::builder()
+        .add_service(RuntimeServiceServer::new(service.clone()))
+        .add_service(ImageServiceServer::new(service))
+        .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+        .await
+        .map_err(|e| CuboError::DaemonError(format!("CRI server failed: {}", e)))
+}
+
+/// CRI metadata persisted per record kind under `<root>/cri/<kind>/<id>.json`
+trait Record: Serialize + DeserializeOwned {
+    const KIND: &'static str;
+    fn id(&self) -> &str;
+}
+
+/// A pod sandbox; its ID is the ID of its infra container
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SandboxRecord {
+    id: String,
+    name: String,
+    uid: String,
+    namespace: String,
+    attempt: u32,
+    labels: HashMap<String, String>,
+    annotations: HashMap<String, String>,
+    log_directory: String,
+    hostname: String,
+    host_network: bool,
+    created_at: i64,
+    ready: bool,
+}
+
+impl Record for SandboxRecord {
+    const KIND: &'static str = "sandboxes";
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl SandboxRecord {
+    fn metadata(&self) -> proto::PodSandboxMetadata {
+        proto::PodSandboxMetadata {
+            name: self.name.clone(),
+            uid: self.uid.clone(),
+            namespace: self.namespace.clone(),
+            attempt: self.attempt,
+        }
+    }
+}
+
+/// A container of a pod sandbox
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ContainerRecord {
+    id: String,
+    sandbox_id: String,
+    name: String,
+    attempt: u32,
+    labels: HashMap<String, String>,
+    annotations: HashMap<String, String>,
+    /// Image as the kubelet asked for it
+    image: String,
+    /// Stored image reference it resolved to
+    image_ref: String,
+    /// Absolute path of the CRI log file, empty when the kubelet asked for none
+    log_path: String,
+}
+
+impl Record for ContainerRecord {
+    const KIND: &'static str = "containers";
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl ContainerRecord {
+    fn metadata(&self) -> proto::ContainerMetadata {
+        proto::ContainerMetadata { name: self.name.clone(), attempt: self.attempt }
+    }
+
+    fn image_spec(&self) -> proto::ImageSpec {
+        proto::ImageSpec { image: self.image.clone(), ..Default::default() }
+    }
+}
+
+#[derive(Clone)]
+struct CriStore {
+    root: PathBuf,
+}
+
+impl CriStore {
+    fn new(root: PathBuf) -> Result<Self> {
+        for kind in [SandboxRecord::KIND, ContainerRecord::KIND] {
+            fs::create_dir_all(root.join(kind))
+                .map_err(|e| CuboError::SystemError(format!("Failed to create CRI state directory: {}", e)))?;
+        }
+        Ok(Self { root })
+    }
+
+    fn path<T: Record>(&self, id: &str) -> PathBuf {
+        self.root.join(T::KIND).join(format!("{}.json", id))
+    }
+
+    fn save<T: Record>(&self, record: &T) -> Result<()> {
+        atomic_write_json(&self.path::<T>(record.id()), record)
+    }
+
+    fn remove<T: Record>(&self, id: &str) {
+        let _ = fs::remove_file(self.path::<T>(id));
+    }
+
+    fn list<T: Record>(&self) -> Result<Vec<T>> {
+        let entries = fs::read_dir(self.root.join(T::KIND))
+            .map_err(|e| CuboError::SystemError(format!("Failed to read CRI state: {}", e)))?;
+        let mut records = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                match read_json(&path) {
+                    Ok(record) => records.push(record),
+                    Err(e) => warn!("Skipping {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Find a record by exact ID, or by an unambiguous ID prefix
+    fn find<T: Record>(&self, id: &str) -> Result<Option<T>> {
+        if id.is_empty() {
+            return Ok(None);
+        }
+        let mut matches: Vec<T> = self.list::<T>()?.into_iter().filter(|r| r.id().starts_with(id)).collect();
+        if let Some(exact) = matches.iter().position(|r| r.id() == id) {
+            return Ok(Some(matches.swap_remove(exact)));
+        }
+        Ok(if matches.len() == 1 { matches.pop() } else { None })
+    }
+}
+
+#[derive(Clone)]
+struct CriService {
+    daemon: Daemon,
+    store: CriStore,
+    pause_image: String,
+    /// Pod CIDR handed out by the kubelet, used when the pod network is created
+    pod_cidr: Arc<Mutex<Option<String>>>,
+}
+
+impl CriService {
+    fn sandbox(&self, id: &str) -> std::result::Result<SandboxRecord, Status> {
+        self.store
+            .find(id)
+            .map_err(to_status)?
+            .ok_or_else(|| Status::not_found(format!("Pod sandbox not found: {}", id)))
+    }
+
+    fn container(&self, id: &str) -> std::result::Result<ContainerRecord, Status> {
+        self.store
+            .find(id)
+            .map_err(to_status)?
+            .ok_or_else(|| Status::not_found(format!("Container not found: {}", id)))
+    }
+
+    /// Runtime state of a container, None once the runtime no longer knows it
+    async fn runtime_container(&self, id: &str) -> Option<Container> {
+        self.daemon.runtime.get_container(id).await.ok()
+    }
+
+    /// Reference of `image`, pulling it when it isn't stored yet
+    async fn ensure_image(&self, image: &str) -> Result<String> {
+        let store = self.daemon.image_store()?;
+        if let Some(reference) = resolve_image(&store, image)? {
+            return Ok(reference);
+        }
+        self.daemon.pull(image, &|_| {}).await?;
+        Ok(image.to_string())
+    }
+
+    fn ensure_pod_network(&self) -> Result<()> {
+        let networks = NetworkStore::new(self.daemon.root_dir.join("networks"))?;
+        if !networks.exists(POD_NETWORK) {
+            let cidr = self.pod_cidr.lock().unwrap().clone();
+            networks.create(POD_NETWORK, cidr.as_deref(), None)?;
+            info!("Created pod network {}", POD_NETWORK);
+        }
+        Ok(())
+    }
+
+    /// Start the infra container and wait until it has a process members can join
+    async fn start_sandbox(&self, id: &str) -> Result<()> {
+        self.daemon.runtime.start_container(id, true).await?;
+        self.daemon.runtime.wait_for_pid(id, SANDBOX_START_TIMEOUT).await?;
+        Ok(())
+    }
+
+    async fn sandbox_ready(&self, record: &SandboxRecord) -> bool {
+        record.ready && self.runtime_container(&record.id).await.is_some_and(|c| c.is_running())
+    }
+
+    fn sandbox_ip(&self, record: &SandboxRecord) -> String {
+        if record.host_network {
+            return String::new();
+        }
+        NetworkStore::new(self.daemon.root_dir.join("networks"))
+            .and_then(|networks| networks.get(POD_NETWORK))
+            .ok()
+            .and_then(|network| network.endpoints.get(&record.id).map(|endpoint| endpoint.ip.clone()))
+            .unwrap_or_default()
+    }
+
+    fn members(&self, sandbox_id: &str) -> Result<Vec<ContainerRecord>> {
+        Ok(self
+            .store
+            .list::<ContainerRecord>()?
+            .into_iter()
+            .filter(|record| record.sandbox_id == sandbox_id)
+            .collect())
+    }
+
+    async fn stop(&self, id: &str, timeout: Duration) -> Result<()> {
+        match self.daemon.runtime.stop_container(id, Some(timeout)).await {
+            Err(CuboError::ContainerNotRunning(_)) => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn remove(&self, id: &str) -> Result<()> {
+        match self.daemon.runtime.remove_container(id, true).await {
+            Err(CuboError::ContainerNotRunning(_)) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Image ID of `reference` as ListImages reports it
+    fn image_id(&self, reference: &str) -> String {
+        self.daemon.image_store().map(|store| image_id(&store, reference)).unwrap_or_default()
+    }
+
+    async fn to_proto_container(&self, record: &ContainerRecord) -> proto::Container {
+        let container = self.runtime_container(&record.id).await;
+        proto::Container {
+            id: record.id.clone(),
+            pod_sandbox_id: record.sandbox_id.clone(),
+            metadata: Some(record.metadata()),
+            image: Some(record.image_spec()),
+            image_ref: record.image_ref.clone(),
+            state: container_state(container.as_ref()) as i32,
+            created_at: container.as_ref().map(|c| nanos(Some(c.created_at))).unwrap_or_default(),
+            labels: record.labels.clone(),
+            annotations: record.annotations.clone(),
+            image_id: self.image_id(&record.image_ref),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl RuntimeService for CriService {
+    async fn version(&self, _request: Request<proto::VersionRequest>) -> GrpcResult<proto::VersionResponse> {
+        Ok(Response::new(proto::VersionResponse {
+            version: "0.1.0".to_string(),
+            runtime_name: RUNTIME_NAME.to_string(),
+            runtime_version: env!("CARGO_PKG_VERSION").to_string(),
+            runtime_api_version: CRI_API_VERSION.to_string(),
+        }))
+    }
+
+    async fn run_pod_sandbox(
+        &self,
+        request: Request<proto::RunPodSandboxRequest>,
+    ) -> GrpcResult<proto::RunPodSandboxResponse> {
+        let config = request
+            .into_inner()
+            .config
+            .ok_or_else(|| Status::invalid_argument("Missing pod sandbox config"))?;
+        let image = self.ensure_image(&self.pause_image).await.map_err(to_status)?;
+        let image_config = self.daemon.image_store().and_then(|s| s.get_config(&image)).map_err(to_status)?;
+        let host_network = host_network(&config);
+        if !host_network {
+            self.ensure_pod_network().map_err(to_status)?;
+        }
+
+        let container = infra_container(&config, &image, &image_config).map_err(to_status)?;
+        let metadata = config.metadata.unwrap_or_default();
+        let mut record = SandboxRecord {
+            id: container.id.clone(),
+            name: metadata.name,
+            uid: metadata.uid,
+            namespace: metadata.namespace,
+            attempt: metadata.attempt,
+            labels: config.labels,
+            annotations: config.annotations,
+            log_directory: config.log_directory,
+            hostname: config.hostname,
+            host_network,
+            created_at: nanos(Some(container.created_at)),
+            ready: false,
+        };
+        let id = self.daemon.runtime.create_container(container).await.map_err(to_status)?;
+        self.store.save(&record).map_err(to_status)?;
+
+        if let Err(e) = self.start_sandbox(&id).await {
+            warn!("Pod sandbox {} failed to start: {}", id, e);
+            let _ = self.remove(&id).await;
+            self.store.remove::<SandboxRecord>(&id);
+            return Err(to_status(e));
+        }
+        record.ready = true;
+        self.store.save(&record).map_err(to_status)?;
+        info!("Started pod sandbox {} ({}/{})", id, record.namespace, record.name);
+        Ok(Response::new(proto::RunPodSandboxResponse { pod_sandbox_id: id }))
+    }
+
+    async fn stop_pod_sandbox(
+        &self,
+        request: Request<proto::StopPodSandboxRequest>,
+    ) -> GrpcResult<proto::StopPodSandboxResponse> {
+        // Stopping is idempotent: the kubelet retries it for sandboxes that are already gone
+        let Some(mut record) = self.store.find::<SandboxRecord>(&request.into_inner().pod_sandbox_id).map_err(to_status)?
+        else {
+            return Ok(Response::new(proto::StopPodSandboxResponse {}));
+        };
+        for member in self.members(&record.id).map_err(to_status)? {
+            self.stop(&member.id, SANDBOX_STOP_TIMEOUT).await.map_err(to_status)?;
+        }
+        self.stop(&record.id, SANDBOX_STOP_TIMEOUT).await.map_err(to_status)?;
+        record.ready = false;
+        self.store.save(&record).map_err(to_status)?;
+        Ok(Response::new(proto::StopPodSandboxResponse {}))
+    }
+
+    async fn remove_pod_sandbox(
+        &self,
+        request: Request<proto::RemovePodSandboxRequest>,
+    ) -> GrpcResult<proto::RemovePodSandboxResponse> {
+        let Some(record) = self.store.find::<SandboxRecord>(&request.into_inner().pod_sandbox_id).map_err(to_status)?
+        else {
+            return Ok(Response::new(proto::RemovePodSandboxResponse {}));
+        };
+        for member in self.members(&record.id).map_err(to_status)? {
+            self.remove(&member.id).await.map_err(to_status)?;
+            self.store.remove::<ContainerRecord>(&member.id);
+        }
+        self.remove(&record.id).await.map_err(to_status)?;
+        self.store.remove::<SandboxRecord>(&record.id);
+        Ok(Response::new(proto::RemovePodSandboxResponse {}))
+    }
+
+    async fn pod_sandbox_status(
+        &self,
+        request: Request<proto::PodSandboxStatusRequest>,
+    ) -> GrpcResult<proto::PodSandboxStatusResponse> {
+        let record = self.sandbox(&request.into_inner().pod_sandbox_id)?;
+        let state = match self.sandbox_ready(&record).await {
+            true => PodSandboxState::SandboxReady,
+            false => PodSandboxState::SandboxNotready,
+        };
+        let network = match record.host_network {
+            true => NamespaceMode::Node,
+            false => NamespaceMode::Pod,
+        };
+        let status = proto::PodSandboxStatus {
+            id: record.id.clone(),
+            metadata: Some(record.metadata()),
+            state: state as i32,
+            created_at: record.created_at,
+            network: Some(proto::PodSandboxNetworkStatus { ip: self.sandbox_ip(&record), additional_ips: Vec::new() }),
+            linux: Some(proto::LinuxPodSandboxStatus {
+                namespaces: Some(proto::Namespace {
+                    options: Some(proto::NamespaceOption {
+                        network: network as i32,
+                        pid: NamespaceMode::Container as i32,
+                        ipc: NamespaceMode::Pod as i32,
+                        target_id: String::new(),
+                    }),
+                }),
+            }),
+            labels: record.labels,
+            annotations: record.annotations,
+            runtime_handler: String::new(),
+        };
+        Ok(Response::new(proto::PodSandboxStatusResponse { status: Some(status), info: HashMap::new() }))
+    }
+
+    async fn list_pod_sandbox(
+        &self,
+        request: Request<proto::ListPodSandboxRequest>,
+    ) -> GrpcResult<proto::ListPodSandboxResponse> {
+        let filter = request.into_inner().filter.unwrap_or_default();
+        let mut items = Vec::new();
+        for record in self.store.list::<SandboxRecord>().map_err(to_status)? {
+            let state = match self.sandbox_ready(&record).await {
+                true => PodSandboxState::SandboxReady,
+                false => PodSandboxState::SandboxNotready,
+            };
+            if !record.id.starts_with(&filter.id)
+                || filter.state.as_ref().is_some_and(|wanted| wanted.state != state as i32)
+                || !labels_match(&record.labels, &filter.label_selector)
+            {
+                continue;
+            }
+            items.push(proto::PodSandbox {
+                id: record.id.clone(),
+                metadata: Some(record.metadata()),
+                state: state as i32,
+                created_at: record.created_at,
+                labels: record.labels,
+                annotations: record.annotations,
+                runtime_handler: String::new(),
+            });
+        }
+        Ok(Response::new(proto::ListPodSandboxResponse { items }))
+    }
+
+    async fn create_container(
+        &self,
+        request: Request<proto::CreateContainerRequest>,
+    ) -> GrpcResult<proto::CreateContainerResponse> {
+        let request = request.into_inner();
+        let sandbox = self.sandbox(&request.pod_sandbox_id)?;
+        let config = request.config.ok_or_else(|| Status::invalid_argument("Missing container config"))?;
+        let image = config.image.as_ref().map(|spec| spec.image.clone()).unwrap_or_default();
+        let store = self.daemon.image_store().map_err(to_status)?;
+        let image_ref = resolve_image(&store, &image)
+            .map_err(to_status)?
+            .ok_or_else(|| Status::not_found(format!("Image not found: {}", image)))?;
+        let image_config = store.get_config(&image_ref).map_err(to_status)?;
+
+        let container = member_container(&sandbox, &config, &image_ref, &image_config).map_err(to_status)?;
+        let metadata = config.metadata.unwrap_or_default();
+        let record = ContainerRecord {
+            id: container.id.clone(),
+            sandbox_id: sandbox.id,
+            name: metadata.name,
+            attempt: metadata.attempt,
+            labels: config.labels,
+            annotations: config.annotations,
+            image,
+            image_ref,
+            log_path: container
+                .config
+                .log_options
+                .path
+                .as_ref()
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        };
+        let id = self.daemon.runtime.create_container(container).await.map_err(to_status)?;
+        self.store.save(&record).map_err(to_status)?;
+        Ok(Response::new(proto::CreateContainerResponse { container_id: id }))
+    }
+
+    async fn start_container(
+        &self,
+        request: Request<proto::StartContainerRequest>,
+    ) -> GrpcResult<proto::StartContainerResponse> {
+        let record = self.container(&request.into_inner().container_id)?;
+        self.daemon.runtime.start_container(&record.id, true).await.map_err(to_status)?;
+        Ok(Response::new(proto::StartContainerResponse {}))
+    }
+
+    async fn stop_container(
+        &self,
+        request: Request<proto::StopContainerRequest>,
+    ) -> GrpcResult<proto::StopContainerResponse> {
+        let request = request.into_inner();
+        let Some(record) = self.store.find::<ContainerRecord>(&request.container_id).map_err(to_status)? else {
+            return Ok(Response::new(proto::StopContainerResponse {}));
+        };
+        let timeout = Duration::from_secs(request.timeout.max(0) as u64);
+        self.stop(&record.id, timeout).await.map_err(to_status)?;
+        Ok(Response::new(proto::StopContainerResponse {}))
+    }
+
+    async fn remove_container(
+        &self,
+        request: Request<proto::RemoveContainerRequest>,
+    ) -> GrpcResult<proto::RemoveContainerResponse> {
+        let Some(record) =
+            self.store.find::<ContainerRecord>(&request.into_inner().container_id).map_err(to_status)?
+        else {
+            return Ok(Response::new(proto::RemoveContainerResponse {}));
+        };
+        self.remove(&record.id).await.map_err(to_status)?;
+        self.store.remove::<ContainerRecord>(&record.id);
+        Ok(Response::new(proto::RemoveContainerResponse {}))
+    }
+
+    async fn list_containers(
+        &self,
+        request: Request<proto::ListContainersRequest>,
+    ) -> GrpcResult<proto::ListContainersResponse> {
+        let filter = request.into_inner().filter.unwrap_or_default();
+        let mut containers = Vec::new();
+        for record in self.store.list::<ContainerRecord>().map_err(to_status)? {
+            if !record.id.starts_with(&filter.id)
+                || !record.sandbox_id.starts_with(&filter.pod_sandbox_id)
+                || !labels_match(&record.labels, &filter.label_selector)
+            {
+                continue;
+            }
+            let container = self.to_proto_container(&record).await;
+            if filter.state.as_ref().is_some_and(|wanted| wanted.state != container.state) {
+                continue;
+            }
+            containers.push(container);
+        }
+        Ok(Response::new(proto::ListContainersResponse { containers }))
+    }
+
+    async fn container_status(
+        &self,
+        request: Request<proto::ContainerStatusRequest>,
+    ) -> GrpcResult<proto::ContainerStatusResponse> {
+        let record = self.container(&request.into_inner().container_id)?;
+        let container = self.runtime_container(&record.id).await;
+        let state = container_state(container.as_ref());
+        let exit_code = container.as_ref().and_then(|c| c.exit_code).unwrap_or_default();
+        let reason = match (state, exit_code) {
+            (ContainerState::ContainerExited, 0) => "Completed",
+            (ContainerState::ContainerExited, _) => "Error",
+            _ => "",
+        };
+        let mounts = container
+            .as_ref()
+            .map(|c| {
+                c.config
+                    .volume_mounts
+                    .iter()
+                    .map(|mount| proto::Mount {
+                        container_path: mount.container_path.clone(),
+                        host_path: mount.host_path.clone(),
+                        readonly: mount.read_only,
+                        selinux_relabel: mount.relabel.is_some(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let status = proto::ContainerStatus {
+            id: record.id.clone(),
+            metadata: Some(record.metadata()),
+            state: state as i32,
+            created_at: container.as_ref().map(|c| nanos(Some(c.created_at))).unwrap_or_default(),
+            started_at: container.as_ref().map(|c| nanos(c.started_at)).unwrap_or_default(),
+            finished_at: container.as_ref().map(|c| nanos(c.finished_at)).unwrap_or_default(),
+            exit_code,
+            image: Some(record.image_spec()),
+            image_ref: record.image_ref.clone(),
+            reason: reason.to_string(),
+            message: String::new(),
+            labels: record.labels.clone(),
+            annotations: record.annotations.clone(),
+            mounts,
+            log_path: record.log_path.clone(),
+            image_id: self.image_id(&record.image_ref),
+        };
+        Ok(Response::new(proto::ContainerStatusResponse { status: Some(status), info: HashMap::new() }))
+    }
+
+    async fn reopen_container_log(
+        &self,
+        request: Request<proto::ReopenContainerLogRequest>,
+    ) -> GrpcResult<proto::ReopenContainerLogResponse> {
+        // The cri log driver reopens its file on its own once the kubelet has rotated it
+        self.container(&request.into_inner().container_id)?;
+        Ok(Response::new(proto::ReopenContainerLogResponse {}))
+    }
+
+    async fn exec_sync(&self, request: Request<proto::ExecSyncRequest>) -> GrpcResult<proto::ExecSyncResponse> {
+        let request = request.into_inner();
+        let record = self.container(&request.container_id)?;
+        let container = self.daemon.runtime.get_container(&record.id).await.map_err(to_status)?;
+        let mut command = exec_command(&container, &request.cmd, &HashMap::new()).map_err(to_status)?;
+        command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        let output = command.output();
+        let output = match request.timeout {
+            secs if secs > 0 => tokio::time::timeout(Duration::from_secs(secs as u64), output)
+                .await
+                .map_err(|_| Status::deadline_exceeded(format!("Exec timed out after {}s", secs)))?,
+            _ => output.await,
+        }
+        .map_err(|e| Status::internal(format!("Failed to run exec process: {}", e)))?;
+
+        let exit_code = output
+            .status
+            .code()
+            .or_else(|| output.status.signal().map(|signal| 128 + signal))
+            .unwrap_or(-1);
+        Ok(Response::new(proto::ExecSyncResponse { stdout: output.stdout, stderr: output.stderr, exit_code }))
+    }
+
+    async fn update_runtime_config(
+        &self,
+        request: Request<proto::UpdateRuntimeConfigRequest>,
+    ) -> GrpcResult<proto::UpdateRuntimeConfigResponse> {
+        let pod_cidr = request
+            .into_inner()
+            .runtime_config
+            .and_then(|config| config.network_config)
+            .map(|network| network.pod_cidr)
+            .filter(|cidr| !cidr.is_empty());
+        if let Some(ref cidr) = pod_cidr {
+            info!("Pod CIDR set to {}", cidr);
+        }
+        *self.pod_cidr.lock().unwrap() = pod_cidr;
+        Ok(Response::new(proto::UpdateRuntimeConfigResponse {}))
+    }
+
+    async fn status(&self, _request: Request<proto::StatusRequest>) -> GrpcResult<proto::StatusResponse> {
+        let condition = |kind: &str| proto::RuntimeCondition { r#type: kind.to_string(), status: true, ..Default::default() };
+        Ok(Response::new(proto::StatusResponse {
+            status: Some(proto::RuntimeStatus {
+                conditions: vec![condition("RuntimeReady"), condition("NetworkReady")],
+            }),
+            info: HashMap::new(),
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl ImageService for CriService {
+    async fn list_images(&self, request: Request<proto::ListImagesRequest>) -> GrpcResult<proto::ListImagesResponse> {
+        let wanted = request.into_inner().filter.and_then(|filter| filter.image).map(|spec| spec.image);
+        let store = self.daemon.image_store().map_err(to_status)?;
+        let mut images = Vec::new();
+        for reference in store.list_images().map_err(to_status)? {
+            if wanted.as_ref().is_some_and(|image| !image.is_empty() && *image != reference) {
+                continue;
+            }
+            images.push(image_of(&store, &reference));
+        }
+        Ok(Response::new(proto::ListImagesResponse { images }))
+    }
+
+    async fn image_status(
+        &self,
+        request: Request<proto::ImageStatusRequest>,
+    ) -> GrpcResult<proto::ImageStatusResponse> {
+        let image = request.into_inner().image.map(|spec| spec.image).unwrap_or_default();
+        let store = self.daemon.image_store().map_err(to_status)?;
+        // A missing image is not an error: the kubelet checks this before pulling
+        let image = resolve_image(&store, &image).map_err(to_status)?.map(|reference| image_of(&store, &reference));
+        Ok(Response::new(proto::ImageStatusResponse { image, info: HashMap::new() }))
+    }
+
+    async fn pull_image(&self, request: Request<proto::PullImageRequest>) -> GrpcResult<proto::PullImageResponse> {
+        let image = request.into_inner().image.map(|spec| spec.image).unwrap_or_default();
+        if image.is_empty() {
+            return Err(Status::invalid_argument("Missing image to pull"));
+        }
+        self.daemon.pull(&image, &|_| {}).await.map_err(to_status)?;
+        Ok(Response::new(proto::PullImageResponse { image_ref: self.image_id(&image) }))
+    }
+
+    async fn remove_image(
+        &self,
+        request: Request<proto::RemoveImageRequest>,
+    ) -> GrpcResult<proto::RemoveImageResponse> {
+        let image = request.into_inner().image.map(|spec| spec.image).unwrap_or_default();
+        let store = self.daemon.image_store().map_err(to_status)?;
+        if let Some(reference) = resolve_image(&store, &image).map_err(to_status)? {
+            store.remove_image(&reference).map_err(to_status)?;
+        }
+        Ok(Response::new(proto::RemoveImageResponse {}))
+    }
+
+    async fn image_fs_info(
+        &self,
+        _request: Request<proto::ImageFsInfoRequest>,
+    ) -> GrpcResult<proto::ImageFsInfoResponse> {
+        let images = self.daemon.root_dir.join("images");
+        let (bytes, inodes) = disk_usage(&images);
+        let usage = proto::FilesystemUsage {
+            timestamp: nanos(Some(chrono::Utc::now())),
+            fs_id: Some(proto::FilesystemIdentifier { mountpoint: images.to_string_lossy().to_string() }),
+            used_bytes: Some(proto::UInt64Value { value: bytes }),
+            inodes_used: Some(proto::UInt64Value { value: inodes }),
+        };
+        Ok(Response::new(proto::ImageFsInfoResponse {
+            image_filesystems: vec![usage],
+            container_filesystems: Vec::new(),
+        }))
+    }
+}
+
+/// The kubelet's runtime/default, unconfined or localhost/<profile> as a cubo profile
+fn apparmor_profile(profile: &str) -> Result<Option<String>> {
+    match profile {
+        "" | "runtime/default" => Ok(None),
+        "unconfined" => Ok(Some(profile.to_string())),
+        _ => match profile.strip_prefix("localhost/") {
+            Some(name) if !name.is_empty() => Ok(Some(name.to_string())),
+            _ => Err(CuboError::InvalidConfiguration(format!("Invalid AppArmor profile '{}'", profile))),
+        },
+    }
+}
+
+fn host_network(config: &proto::PodSandboxConfig) -> bool {
+    config
+        .linux
+        .as_ref()
+        .and_then(|linux| linux.security_context.as_ref())
+        .and_then(|context| context.namespace_options.as_ref())
+        .is_some_and(|options| options.network() == NamespaceMode::Node)
+}
+
+/// The infra container of a pod sandbox: it runs pause and owns the pod's namespaces
+fn infra_container(config: &proto::PodSandboxConfig, image: &str, image_config: &ImageConfig) -> Result<Container> {
+    let metadata = config
+        .metadata
+        .as_ref()
+        .ok_or_else(|| CuboError::InvalidConfiguration("Missing pod sandbox metadata".to_string()))?;
+    let command = image_config.cmd.clone().unwrap_or_else(|| vec!["/pause".to_string()]);
+    let host_network = host_network(config);
+    let network_mode = match host_network {
+        true => NetworkMode::Host,
+        false => NetworkMode::Custom(POD_NETWORK.to_string()),
+    };
+
+    let mut container = Container::new(image.to_string(), command)
+        .with_name(format!("k8s_POD_{}_{}_{}_{}", metadata.name, metadata.namespace, metadata.uid, metadata.attempt))
+        .with_network_mode(network_mode)
+        .with_userns_mode(UsernsMode::Host)
+        .with_log_driver(LogDriverKind::None);
+    if !config.hostname.is_empty() {
+        container.config.hostname = Some(config.hostname.clone());
+    }
+    // Host-network pods listen on the host already
+    let published = config.port_mappings.iter().filter(|port| port.host_port > 0 && !host_network);
+    for port in published {
+        container = container.with_port(port_mapping(port)?);
+    }
+    if let Some(ref dns) = config.dns_config {
+        for server in &dns.servers {
+            container = container.with_dns(server.clone());
+        }
+        for domain in &dns.searches {
+            container = container.with_dns_search(domain.clone());
+        }
+    }
+    if let Some(ref linux) = config.linux {
+        for (key, value) in &linux.sysctls {
+            container = container.with_sysctl(key.clone(), value.clone());
+        }
+    }
+
+    validate_config(&container.config)?;
+    Ok(container)
+}
+
+/// A container of the pod: it joins the infra container's network and IPC namespaces
+fn member_container(
+    sandbox: &SandboxRecord,
+    config: &proto::ContainerConfig,
+    image: &str,
+    image_config: &ImageConfig,
+) -> Result<Container> {
+    let metadata = config
+        .metadata
+        .as_ref()
+        .ok_or_else(|| CuboError::InvalidConfiguration("Missing container metadata".to_string()))?;
+    // Like Docker: command overrides the entrypoint, args the image's cmd
+    let command = match (config.command.is_empty(), config.args.is_empty()) {
+        (true, true) => image_config.cmd.clone().unwrap_or_else(|| vec!["/bin/sh".to_string()]),
+        (true, false) => config.args.clone(),
+        (false, _) => config.command.iter().chain(&config.args).cloned().collect(),
+    };
+
+    let mut container = Container::new(image.to_string(), command)
+        .with_name(format!(
+            "k8s_{}_{}_{}_{}_{}",
+            metadata.name, sandbox.name, sandbox.namespace, sandbox.uid, metadata.attempt
+        ))
+        .with_sandbox(sandbox.id.clone())
+        .with_userns_mode(UsernsMode::Host);
+    if sandbox.host_network {
+        container = container.with_network_mode(NetworkMode::Host);
+    }
+    if !sandbox.hostname.is_empty() {
+        container.config.hostname = Some(sandbox.hostname.clone());
+    }
+
+    let image_env = image_config.env.iter().flatten().filter_map(|var| var.split_once('='));
+    for (name, value) in image_env {
+        container = container.with_env(name.to_string(), value.to_string());
+    }
+    for var in &config.envs {
+        container = container.with_env(var.key.clone(), var.value.clone());
+    }
+    let working_dir = match config.working_dir.as_str() {
+        "" => image_config.working_dir.clone(),
+        dir => Some(dir.to_string()),
+    };
+    if let Some(dir) = working_dir {
+        container = container.with_workdir(dir);
+    }
+    for mount in &config.mounts {
+        // Other containers of the pod may share the volume, so it gets the shared label
+        let mut volume = VolumeMount::bind(mount.host_path.clone(), mount.container_path.clone(), mount.readonly);
+        volume.relabel = mount.selinux_relabel.then_some(Relabel::Shared);
+        container = container.with_volume(volume);
+    }
+    container.config.tty = config.tty;
+    container.config.stdin = config.stdin;
+
+    if let Some(ref linux) = config.linux {
+        if let Some(ref resources) = linux.resources {
+            if resources.memory_limit_in_bytes > 0 {
+                container = container.with_memory_limit(resources.memory_limit_in_bytes as u64);
+            }
+            if resources.cpu_quota > 0 && resources.cpu_period > 0 {
+                container = container.with_cpu_limit(resources.cpu_quota as f32 / resources.cpu_period as f32);
+            }
+            if resources.memory_swap_limit_in_bytes != 0 {
+                container = container.with_memory_swap(resources.memory_swap_limit_in_bytes);
+            }
+            if resources.oom_score_adj != 0 {
+                container = container.with_oom_score_adj(resources.oom_score_adj.clamp(-1000, 1000) as i32);
+            }
+            if !resources.cpuset_cpus.is_empty() {
+                container = container.with_cpuset_cpus(resources.cpuset_cpus.clone());
+            }
+            if !resources.cpuset_mems.is_empty() {
+                container = container.with_cpuset_mems(resources.cpuset_mems.clone());
+            }
+        }
+        if let Some(ref context) = linux.security_context {
+            container.config.apparmor_profile = apparmor_profile(&context.apparmor_profile)?;
+            if let Some(ref selinux) = context.selinux_options {
+                let options = &mut container.config.label_options;
+                let set = |value: &String| (!value.is_empty()).then(|| value.clone());
+                options.user = set(&selinux.user);
+                options.role = set(&selinux.role);
+                options.kind = set(&selinux.r#type);
+                options.level = set(&selinux.level);
+            }
+            container.config.user = match (&context.run_as_user, &context.run_as_group) {
+                (Some(uid), Some(gid)) => Some(format!("{}:{}", uid.value, gid.value)),
+                (Some(uid), None) => Some(uid.value.to_string()),
+                (None, _) if !context.run_as_username.is_empty() => Some(context.run_as_username.clone()),
+                (None, _) => None,
+            };
+        }
+    }
+
+    // The kubelet reads the logs itself, from a file in the pod's log directory
+    if !config.log_path.is_empty() {
+        container = container.with_log_driver(LogDriverKind::Cri).with_log_options(LogOptions {
+            path: Some(Path::new(&sandbox.log_directory).join(&config.log_path)),
+            ..Default::default()
+        });
+    }
+
+    validate_config(&container.config)?;
+    Ok(container)
+}
+
+fn port_mapping(port: &proto::PortMapping) -> Result<PortMapping> {
+    let protocol = match port.protocol() {
+        proto::Protocol::Tcp => Protocol::Tcp,
+        proto::Protocol::Udp => Protocol::Udp,
+        proto::Protocol::Sctp => {
+            return Err(CuboError::InvalidConfiguration("SCTP port mappings are not supported".to_string()))
+        }
+    };
+    let port_number = |value: i32| {
+        u16::try_from(value).map_err(|_| CuboError::InvalidConfiguration(format!("Invalid port: {}", value)))
+    };
+    Ok(PortMapping {
+        host_port: port_number(port.host_port)?,
+        container_port: port_number(port.container_port)?,
+        protocol,
+        host_ip: Some(port.host_ip.clone()).filter(|ip| !ip.is_empty()),
+    })
+}
+
+fn container_state(container: Option<&Container>) -> ContainerState {
+    match container.map(|c| &c.status) {
+        Some(ContainerStatus::Created) => ContainerState::ContainerCreated,
+        Some(ContainerStatus::Running) => ContainerState::ContainerRunning,
+        Some(ContainerStatus::Stopped) | Some(ContainerStatus::Error) => ContainerState::ContainerExited,
+        _ => ContainerState::ContainerUnknown,
+    }
+}
+
+/// Every label of the selector has to be present with the same value
+fn labels_match(labels: &HashMap<String, String>, selector: &HashMap<String, String>) -> bool {
+    selector.iter().all(|(key, value)| labels.get(key) == Some(value))
+}
+
+/// Stored reference of `image`, which is either a reference or an image ID (with or without
+/// the `sha256:` prefix) as handed out by ListImages and PullImage
+fn resolve_image(store: &ImageStore, image: &str) -> Result<Option<String>> {
+    if image.is_empty() {
+        return Ok(None);
+    }
+    let Ok(digest) = store.resolve(image) else {
+        return Ok(None);
+    };
+    // An ID stands for the image under one of its names, if it has any
+    let names = store.list_images()?;
+    Ok(Some(match names.iter().any(|name| name == image) {
+        true => image.to_string(),
+        false => names.into_iter().find(|name| store.resolve(name).ok() == Some(digest.clone())).unwrap_or(digest),
+    }))
+}
+
+fn image_of(store: &ImageStore, reference: &str) -> proto::Image {
+    let size = store
+        .get_layers(reference)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|layer| fs::metadata(layer).ok())
+        .map(|meta| meta.len())
+        .sum();
+    proto::Image {
+        id: image_id(store, reference),
+        repo_tags: vec![reference.to_string()],
+        size,
+        spec: Some(proto::ImageSpec { image: reference.to_string(), ..Default::default() }),
+        ..Default::default()
+    }
+}
+
+/// Bytes and inodes used below `path`
+fn disk_usage(path: &Path) -> (u64, u64) {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return (0, 0);
+    };
+    let mut usage = (meta.len(), 1);
+    if meta.is_dir() {
+        for entry in fs::read_dir(path).into_iter().flatten().flatten() {
+            let (bytes, inodes) = disk_usage(&entry.path());
+            usage = (usage.0 + bytes, usage.1 + inodes);
+        }
+    }
+    usage
+}
+
+fn nanos(time: Option<chrono::DateTime<chrono::Utc>>) -> i64 {
+    time.and_then(|t| t.timestamp_nanos_opt()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::image_store::ImageManifest;
+    use crate::container::runtime::RuntimeConfig;
+    use proto::image_service_client::ImageServiceClient;
+    use proto::runtime_service_client::RuntimeServiceClient;
+    use tempfile::TempDir;
+    use tonic::Code;
+
+    fn pod_config(host_network: bool) -> proto::PodSandboxConfig {
+        let mode = if host_network { NamespaceMode::Node } else { NamespaceMode::Pod };
+        proto::PodSandboxConfig {
+            metadata: Some(proto::PodSandboxMetadata {
+                name: "web".to_string(),
+                uid: "1234".to_string(),
+                namespace: "default".to_string(),
+                attempt: 0,
+            }),
+            hostname: "web".to_string(),
+            log_directory: "/var/log/pods/default_web_1234".to_string(),
+            dns_config: Some(proto::DnsConfig {
+                servers: vec!["10.96.0.10".to_string()],
+                searches: vec!["default.svc.cluster.local".to_string()],
+                options: Vec::new(),
+            }),
+            port_mappings: vec![
+                proto::PortMapping { container_port: 80, host_port: 8080, ..Default::default() },
+                proto::PortMapping { container_port: 443, ..Default::default() },
+            ],
+            linux: Some(proto::LinuxPodSandboxConfig {
+                security_context: Some(proto::LinuxSandboxSecurityContext {
+                    namespace_options: Some(proto::NamespaceOption { network: mode as i32, ..Default::default() }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn sandbox_record() -> SandboxRecord {
+        SandboxRecord {
+            id: "abc123".to_string(),
+            name: "web".to_string(),
+            uid: "1234".to_string(),
+            namespace: "default".to_string(),
+            attempt: 0,
+            labels: HashMap::new(),
+            annotations: HashMap::new(),
+            log_directory: "/var/log/pods/default_web_1234".to_string(),
+            hostname: "web".to_string(),
+            host_network: false,
+            created_at: 0,
+            ready: true,
+        }
+    }
+
+    fn image_config() -> ImageConfig {
+        ImageConfig {
+            cmd: Some(vec!["nginx".to_string()]),
+            env: Some(vec!["PATH=/usr/bin".to_string()]),
+            working_dir: Some("/srv".to_string()),
+            exposed_ports: None,
+            stop_signal: None,
+            labels: None,
+        }
+    }
+
+    #[test]
+    fn test_infra_container() {
+        let pause = ImageConfig { cmd: None, env: None, working_dir: None, ..image_config() };
+        let container = infra_container(&pod_config(false), "pause:3.9", &pause).unwrap();
+        assert_eq!(container.name.as_deref(), Some("k8s_POD_web_default_1234_0"));
+        assert_eq!(container.command, ["/pause"]);
+        assert_eq!(container.config.network_mode, NetworkMode::Custom(POD_NETWORK.to_string()));
+        assert_eq!(container.config.hostname.as_deref(), Some("web"));
+        assert_eq!(container.config.dns, ["10.96.0.10"]);
+        assert_eq!(container.config.dns_search, ["default.svc.cluster.local"]);
+        // Only ports with a host port are published
+        assert_eq!(container.config.ports.len(), 1);
+        assert_eq!(container.config.ports[0].host_port, 8080);
+
+        let container = infra_container(&pod_config(true), "pause:3.9", &pause).unwrap();
+        assert_eq!(container.config.network_mode, NetworkMode::Host);
+        assert!(container.config.ports.is_empty());
+
+        let err = infra_container(&proto::PodSandboxConfig::default(), "pause:3.9", &pause).unwrap_err();
+        assert!(matches!(err, CuboError::InvalidConfiguration(_)));
+    }
+
+    #[test]
+    fn test_apparmor_profile() {
+        assert_eq!(apparmor_profile("").unwrap(), None);
+        assert_eq!(apparmor_profile("runtime/default").unwrap(), None);
+        assert_eq!(apparmor_profile("unconfined").unwrap().as_deref(), Some("unconfined"));
+        assert_eq!(apparmor_profile("localhost/nginx").unwrap().as_deref(), Some("nginx"));
+        assert!(apparmor_profile("localhost/").is_err());
+        assert!(apparmor_profile("nginx").is_err());
+    }
+
+    #[test]
+    fn test_member_container() {
+        let config = proto::ContainerConfig {
+            metadata: Some(proto::ContainerMetadata { name: "nginx".to_string(), attempt: 2 }),
+            args: vec!["-g".to_string(), "daemon off;".to_string()],
+            envs: vec![proto::KeyValue { key: "MODE".to_string(), value: "prod".to_string() }],
+            mounts: vec![proto::Mount {
+                container_path: "/data".to_string(),
+                host_path: "/var/lib/kubelet/data".to_string(),
+                readonly: true,
+                selinux_relabel: true,
+            }],
+            log_path: "nginx/2.log".to_string(),
+            linux: Some(proto::LinuxContainerConfig {
+                resources: Some(proto::LinuxContainerResources {
+                    cpu_period: 100000,
+                    cpu_quota: 50000,
+                    memory_limit_in_bytes: 64 * 1024 * 1024,
+                    cpuset_cpus: "0-1".to_string(),
+                    oom_score_adj: 1000,
+                    ..Default::default()
+                }),
+                security_context: Some(proto::LinuxContainerSecurityContext {
+                    run_as_user: Some(proto::Int64Value { value: 1000 }),
+                    run_as_group: Some(proto::Int64Value { value: 1000 }),
+                    selinux_options: Some(proto::SeLinuxOption {
+                        level: "s0:c123,c456".to_string(),
+                        ..Default::default()
+                    }),
+                    apparmor_profile: "localhost/nginx".to_string(),
+                    ..Default::default()
+                }),
+            }),
+            ..Default::default()
+        };
+        let container = member_container(&sandbox_record(), &config, "nginx:latest", &image_config()).unwrap();
+        assert_eq!(container.name.as_deref(), Some("k8s_nginx_web_default_1234_2"));
+        assert_eq!(container.command, ["-g", "daemon off;"]);
+        assert_eq!(container.config.sandbox.as_deref(), Some("abc123"));
+        assert_eq!(container.config.userns_mode, UsernsMode::Host);
+        assert_eq!(container.config.env_vars["MODE"], "prod");
+        assert_eq!(container.config.env_vars["PATH"], "/usr/bin");
+        assert_eq!(container.config.working_dir.as_deref(), Some("/srv"));
+        assert!(container.config.volume_mounts[0].read_only);
+        assert_eq!(container.config.volume_mounts[0].relabel, Some(Relabel::Shared));
+        assert_eq!(container.config.label_options.level.as_deref(), Some("s0:c123,c456"));
+        assert!(container.config.label_options.kind.is_none());
+        assert_eq!(container.config.apparmor_profile.as_deref(), Some("nginx"));
+        assert_eq!(container.config.memory_limit, Some(64 * 1024 * 1024));
+        assert_eq!(container.config.cpu_limit, Some(0.5));
+        assert_eq!(container.config.cpuset_cpus.as_deref(), Some("0-1"));
+        assert!(container.config.cpuset_mems.is_none());
+        assert_eq!(container.config.oom_score_adj, Some(1000));
+        assert!(container.config.memory_swap.is_none());
+        assert_eq!(container.config.user.as_deref(), Some("1000:1000"));
+        assert_eq!(container.config.log_driver, LogDriverKind::Cri);
+        assert_eq!(
+            container.config.log_options.path,
+            Some(PathBuf::from("/var/log/pods/default_web_1234/nginx/2.log"))
+        );
+
+        let bare = proto::ContainerConfig {
+            metadata: Some(proto::ContainerMetadata { name: "app".to_string(), attempt: 0 }),
+            command: vec!["sleep".to_string()],
+            args: vec!["60".to_string()],
+            ..Default::default()
+        };
+        let container = member_container(&sandbox_record(), &bare, "nginx:latest", &image_config()).unwrap();
+        assert_eq!(container.command, ["sleep", "60"]);
+        assert_eq!(container.config.log_driver, LogDriverKind::default());
+    }
+
+    #[test]
+    fn test_labels_and_states() {
+        let labels = HashMap::from([("app".to_string(), "web".to_string()), ("tier".to_string(), "front".to_string())]);
+        assert!(labels_match(&labels, &HashMap::new()));
+        assert!(labels_match(&labels, &HashMap::from([("app".to_string(), "web".to_string())])));
+        assert!(!labels_match(&labels, &HashMap::from([("app".to_string(), "db".to_string())])));
+
+        let mut container = Container::new("test:latest".to_string(), vec!["true".to_string()]);
+        assert_eq!(container_state(Some(&container)), ContainerState::ContainerCreated);
+        container.update_status(ContainerStatus::Stopped);
+        assert_eq!(container_state(Some(&container)), ContainerState::ContainerExited);
+        assert_eq!(container_state(None), ContainerState::ContainerUnknown);
+    }
+
+    #[test]
+    fn test_store_find_by_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = CriStore::new(temp_dir.path().to_path_buf()).unwrap();
+        store.save(&sandbox_record()).unwrap();
+        store.save(&SandboxRecord { id: "abd456".to_string(), ..sandbox_record() }).unwrap();
+
+        assert_eq!(store.find::<SandboxRecord>("abc123").unwrap(), Some(sandbox_record()));
+        assert_eq!(store.find::<SandboxRecord>("abc").unwrap(), Some(sandbox_record()));
+        assert_eq!(store.find::<SandboxRecord>("ab").unwrap(), None);
+        assert_eq!(store.find::<SandboxRecord>("").unwrap(), None);
+
+        store.remove::<SandboxRecord>("abc123");
+        assert_eq!(store.list::<SandboxRecord>().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_runtime_and_image_services() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("cubo.sock");
+        let cri_socket = temp_dir.path().join("cubo-cri.sock");
+        let root = temp_dir.path().join("root");
+        ImageStore::new(root.join("images"))
+            .unwrap()
+            .save_manifest(&ImageManifest {
+                reference: "nginx:latest".to_string(),
+                parent: None,
+                layers: Vec::new(),
+                config: image_config(),
+                annotations: None,
+                repo_digest: None,
+            })
+            .unwrap();
+        let daemon = Daemon::new(RuntimeConfig { root_dir: root, ..Default::default() })
+            .unwrap()
+            .with_cri(CriConfig { socket: cri_socket.clone(), pause_image: DEFAULT_PAUSE_IMAGE.to_string() });
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            daemon.serve_until(&socket, None, async { let _ = stopped.await; }).await
+        });
+        for _ in 0..50 {
+            if cri_socket.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let channel = super::super::grpc::channel(&cri_socket).await.unwrap();
+        let mut runtime = RuntimeServiceClient::new(channel.clone());
+        let mut images = ImageServiceClient::new(channel);
+
+        let version = runtime.version(proto::VersionRequest::default()).await.unwrap().into_inner();
+        assert_eq!((version.runtime_name.as_str(), version.runtime_api_version.as_str()), ("cubo", "v1"));
+        let status = runtime.status(proto::StatusRequest::default()).await.unwrap().into_inner();
+        assert!(status.status.unwrap().conditions.iter().all(|condition| condition.status));
+
+        let listed = images.list_images(proto::ListImagesRequest::default()).await.unwrap().into_inner().images;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].repo_tags, ["nginx:latest"]);
+        // Images resolve by ID as well as by reference
+        let spec = |image: &str| Some(proto::ImageSpec { image: image.to_string(), ..Default::default() });
+        let found = images
+            .image_status(proto::ImageStatusRequest { image: spec(&listed[0].id), verbose: false })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(found.image.unwrap().repo_tags, ["nginx:latest"]);
+        let missing = images
+            .image_status(proto::ImageStatusRequest { image: spec("redis:7"), verbose: false })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(missing.image.is_none());
+
+        let err = runtime
+            .pod_sandbox_status(proto::PodSandboxStatusRequest { pod_sandbox_id: "nope".to_string(), verbose: false })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+        runtime
+            .stop_pod_sandbox(proto::StopPodSandboxRequest { pod_sandbox_id: "nope".to_string() })
+            .await
+            .unwrap();
+        runtime
+            .remove_pod_sandbox(proto::RemovePodSandboxRequest { pod_sandbox_id: "nope".to_string() })
+            .await
+            .unwrap();
+        let err = runtime
+            .create_container(proto::CreateContainerRequest { pod_sandbox_id: "nope".to_string(), ..Default::default() })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        images
+            .remove_image(proto::RemoveImageRequest { image: spec("nginx:latest") })
+            .await
+            .unwrap();
+        images
+            .remove_image(proto::RemoveImageRequest { image: spec("nginx:latest") })
+            .await
+            .unwrap();
+        let listed = images.list_images(proto::ListImagesRequest::default()).await.unwrap().into_inner().images;
+        assert!(listed.is_empty());
+
+        stop.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!cri_socket.exists());
+    }
+}
@@ -0,0 +1,947 @@
+//! The commonly used subset of the Docker Engine API, so Docker tooling can point at the
+//! daemon socket. Paths may carry a Docker version prefix (`/v1.43/containers/json`).
+
+use super::http::{Request, Response};
+use super::Daemon;
+use crate::commands::build::detect_build_file;
+use crate::commands::run::{parse_env_var, parse_security_opt, parse_volume, validate_config};
+use crate::container::audit::{AuditLog, AuditOperation, AuditRecord};
+use crate::container::builder::ImageBuilder;
+use crate::container::cubofile::Cubofile;
+use crate::container::cubofile_toml::CubofileToml;
+use crate::container::image_store::{ImageFilter, ImageStore};
+use crate::container::logging::{log_segments, LogEntry, LogStream, LOG_FILE};
+use crate::container::secure_join::secure_join;
+use crate::container::{namespace, Container, ContainerConfig, ContainerStatus, LogDriverKind, PortMapping, Protocol, RestartPolicy};
+use crate::error::{CuboError, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_humanize::{Accuracy, HumanTime, Tense};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Docker API version reported by `/version`
+pub const DOCKER_API_VERSION: &str = "1.43";
+const MIN_DOCKER_API_VERSION: &str = "1.24";
+
+/// Body of `POST /containers/create`; Docker sends `null` for unset fields
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct CreateRequest {
+    image: String,
+    cmd: Option<Vec<String>>,
+    entrypoint: Option<Vec<String>>,
+    env: Option<Vec<String>>,
+    working_dir: Option<String>,
+    stop_signal: Option<String>,
+    hostname: Option<String>,
+    domainname: Option<String>,
+    host_config: Option<HostConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+struct HostConfig {
+    binds: Option<Vec<String>>,
+    port_bindings: Option<HashMap<String, Option<Vec<PortBinding>>>>,
+    memory: Option<u64>,
+    memory_swap: Option<i64>,
+    memory_reservation: Option<u64>,
+    oom_score_adj: Option<i32>,
+    nano_cpus: Option<u64>,
+    cpuset_cpus: Option<String>,
+    cpuset_mems: Option<String>,
+    pids_limit: Option<i64>,
+    security_opt: Option<Vec<String>>,
+    network_mode: Option<String>,
+    init: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PortBinding {
+    #[serde(rename = "HostIp")]
+    host_ip: Option<String>,
+    #[serde(rename = "HostPort")]
+    host_port: Option<String>,
+}
+
+/// Serve a Docker API request; None if the path isn't part of the supported subset
+pub(super) async fn route(daemon: &Daemon, request: &Request) -> Option<Result<Response>> {
+    let mut segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.first().is_some_and(|s| s.starts_with("v1.")) {
+        segments.remove(0);
+    }
+
+    let response = match (request.method.as_str(), segments.as_slice()) {
+        ("GET" | "HEAD", ["_ping"]) => Ok(Response::new(200, "text/plain", b"OK".to_vec())),
+        ("GET", ["version"]) => Ok(Response::json(200, &version())),
+        ("GET", ["containers", "json"]) => list_containers(daemon, request).await,
+        ("POST", ["containers", "create"]) => create_container(daemon, request).await,
+        ("GET", ["containers", identifier, "json"]) => inspect_container(daemon, identifier).await,
+        ("POST", ["containers", identifier, "start"]) => start_container(daemon, identifier).await,
+        ("POST", ["containers", identifier, "stop"]) => stop_container(daemon, identifier, request).await,
+        ("DELETE", ["containers", identifier]) => remove_container(daemon, identifier, request).await,
+        ("GET", ["containers", identifier, "logs"]) => container_logs(daemon, identifier, request).await,
+        ("GET", ["images", "json"]) => list_images(daemon, request),
+        ("POST", ["images", "create"]) => pull_image(daemon, request).await,
+        ("POST", ["build"]) => build_image(daemon, request).await,
+        _ => return None,
+    };
+    Some(response)
+}
+
+fn version() -> Value {
+    json!({
+        "Version": env!("CARGO_PKG_VERSION"),
+        "ApiVersion": DOCKER_API_VERSION,
+        "MinAPIVersion": MIN_DOCKER_API_VERSION,
+        "Os": std::env::consts::OS,
+        "Arch": docker_arch(),
+        "Components": [{ "Name": "cubo", "Version": env!("CARGO_PKG_VERSION") }],
+    })
+}
+
+/// Docker names architectures after Go's GOARCH
+fn docker_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
+
+fn state_name(status: &ContainerStatus) -> &'static str {
+    match status {
+        ContainerStatus::Created => "created",
+        ContainerStatus::Running => "running",
+        ContainerStatus::Stopped => "exited",
+        ContainerStatus::Paused => "paused",
+        ContainerStatus::Error => "dead",
+        ContainerStatus::Restarting => "restarting",
+    }
+}
+
+fn ago(time: DateTime<Utc>) -> String {
+    HumanTime::from(Utc::now() - time).to_text_en(Accuracy::Rough, Tense::Present)
+}
+
+/// The human readable `Status` column of `docker ps`
+fn status_text(container: &Container) -> String {
+    match container.status {
+        ContainerStatus::Running => format!("Up {}", ago(container.started_at.unwrap_or(container.created_at))),
+        ContainerStatus::Stopped => format!(
+            "Exited ({}) {} ago",
+            container.exit_code.unwrap_or(0),
+            ago(container.finished_at.unwrap_or(container.created_at))
+        ),
+        ContainerStatus::Created => "Created".to_string(),
+        ref status => status.to_string(),
+    }
+}
+
+/// Docker names always start with a slash; unnamed containers get their short ID
+fn docker_name(container: &Container) -> String {
+    format!("/{}", container.name.clone().unwrap_or_else(|| container.id[..12].to_string()))
+}
+
+fn docker_ports(container: &Container) -> Vec<Value> {
+    container
+        .ports()
+        .iter()
+        .map(|port| json!({
+            "IP": port.host_ip.clone().unwrap_or_else(|| "0.0.0.0".to_string()),
+            "PrivatePort": port.container_port,
+            "PublicPort": port.host_port,
+            "Type": port.protocol.to_string(),
+        }))
+        .collect()
+}
+
+fn summary(store: &ImageStore, container: &Container) -> Value {
+    json!({
+        "Id": container.id,
+        "Names": [docker_name(container)],
+        "Image": container.blueprint,
+        "ImageID": image_id(store, &container.blueprint),
+        "Command": container.command.join(" "),
+        "Created": container.created_at.timestamp(),
+        "State": state_name(&container.status),
+        "Status": status_text(container),
+        "Ports": docker_ports(container),
+        "Labels": {},
+    })
+}
+
+async fn list_containers(daemon: &Daemon, request: &Request) -> Result<Response> {
+    let containers = daemon.runtime.list_containers(request.flag("all")).await?;
+    let mut containers: Vec<&Container> = containers.iter().collect();
+    containers.sort_by_key(|c| std::cmp::Reverse(c.created_at));
+    let store = daemon.image_store()?;
+    let list: Vec<Value> = containers.into_iter().map(|container| summary(&store, container)).collect();
+    Ok(Response::json(200, &json!(list)))
+}
+
+fn rfc3339(time: Option<DateTime<Utc>>) -> String {
+    time.map(|t| t.to_rfc3339_opts(SecondsFormat::Nanos, true))
+        .unwrap_or_else(|| "0001-01-01T00:00:00Z".to_string())
+}
+
+fn restart_policy(policy: &RestartPolicy) -> Value {
+    let (name, max_retries) = match policy {
+        RestartPolicy::No => ("no", 0),
+        RestartPolicy::Always => ("always", 0),
+        RestartPolicy::UnlessStopped => ("unless-stopped", 0),
+        RestartPolicy::OnFailure { max_retries } => ("on-failure", *max_retries),
+    };
+    json!({ "Name": name, "MaximumRetryCount": max_retries })
+}
+
+/// The `--security-opt`s of the container, as Docker lists them
+fn security_opts(config: &ContainerConfig) -> Vec<String> {
+    let mut opts = config.label_options.security_opts();
+    opts.extend(config.apparmor_profile.iter().map(|profile| format!("apparmor={}", profile)));
+    if config.landlock {
+        opts.push("landlock=on".to_string());
+    }
+    opts.extend(namespace::path_security_opts(&config.masked_paths, &config.readonly_paths));
+    opts
+}
+
+fn inspect(store: &ImageStore, container: &Container) -> Value {
+    let config = &container.config;
+    let env: Vec<String> = config.env_vars.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    let binds: Vec<String> = config
+        .volume_mounts
+        .iter()
+        .map(|m| {
+            let mut options: Vec<String> = m.read_only.then(|| "ro".to_string()).into_iter().collect();
+            options.extend(m.relabel.map(|relabel| relabel.to_string()));
+            match options.is_empty() {
+                true => format!("{}:{}", m.host_path, m.container_path),
+                false => format!("{}:{}:{}", m.host_path, m.container_path, options.join(",")),
+            }
+        })
+        .collect();
+    let mut ports = serde_json::Map::new();
+    for port in container.ports() {
+        ports.insert(
+            format!("{}/{}", port.container_port, port.protocol),
+            json!([{ "HostIp": port.host_ip.clone().unwrap_or_default(), "HostPort": port.host_port.to_string() }]),
+        );
+    }
+
+    json!({
+        "Id": container.id,
+        "Created": rfc3339(Some(container.created_at)),
+        "Path": container.command.first().cloned().unwrap_or_default(),
+        "Args": container.command.iter().skip(1).collect::<Vec<_>>(),
+        "State": {
+            "Status": state_name(&container.status),
+            "Running": container.is_running(),
+            "Paused": matches!(container.status, ContainerStatus::Paused),
+            "Restarting": matches!(container.status, ContainerStatus::Restarting),
+            "Dead": matches!(container.status, ContainerStatus::Error),
+            "Pid": container.pid.unwrap_or(0),
+            "ExitCode": container.exit_code.unwrap_or(0),
+            "StartedAt": rfc3339(container.started_at),
+            "FinishedAt": rfc3339(container.finished_at),
+        },
+        "Image": image_id(store, &container.blueprint),
+        "Name": docker_name(container),
+        "RestartCount": container.restart_count,
+        "AppArmorProfile": config.apparmor_profile.clone().unwrap_or_default(),
+        "Config": {
+            "Image": container.blueprint,
+            "Cmd": container.command,
+            "Env": env,
+            "WorkingDir": config.working_dir.clone().unwrap_or_default(),
+            "StopSignal": config.stop_signal,
+            "Hostname": container.hostname().unwrap_or_default(),
+            "Domainname": config.domainname.clone().unwrap_or_default(),
+            "Labels": {},
+        },
+        "HostConfig": {
+            "Binds": binds,
+            "Memory": config.memory_limit.unwrap_or(0),
+            "MemorySwap": config.memory_swap.unwrap_or(0),
+            "MemoryReservation": config.memory_reservation.unwrap_or(0),
+            "OomScoreAdj": config.oom_score_adj.unwrap_or(0),
+            "NanoCpus": config.cpu_limit.map(|cpus| (cpus as f64 * 1e9) as u64).unwrap_or(0),
+            "CpusetCpus": config.cpuset_cpus.clone().unwrap_or_default(),
+            "CpusetMems": config.cpuset_mems.clone().unwrap_or_default(),
+            "PidsLimit": config.pids_limit,
+            "SecurityOpt": security_opts(config),
+            "NetworkMode": config.network_mode.to_string(),
+            "Init": config.init,
+            "RestartPolicy": restart_policy(&config.restart_policy),
+        },
+        "NetworkSettings": { "Ports": ports },
+    })
+}
+
+async fn inspect_container(daemon: &Daemon, identifier: &str) -> Result<Response> {
+    let id = daemon.runtime.resolve(identifier).await?;
+    let container = daemon.runtime.get_container(&id).await?;
+    Ok(Response::json(200, &inspect(&daemon.image_store()?, &container)))
+}
+
+async fn start_container(daemon: &Daemon, identifier: &str) -> Result<Response> {
+    let id = daemon.runtime.resolve(identifier).await?;
+    daemon.runtime.start_container(&id, true).await?;
+    Ok(Response::empty(204))
+}
+
+async fn remove_container(daemon: &Daemon, identifier: &str, request: &Request) -> Result<Response> {
+    let id = daemon.runtime.resolve(identifier).await?;
+    daemon.runtime.remove_container(&id, request.flag("force")).await?;
+    Ok(Response::empty(204))
+}
+
+async fn stop_container(daemon: &Daemon, identifier: &str, request: &Request) -> Result<Response> {
+    let id = daemon.runtime.resolve(identifier).await?;
+    let timeout = match request.query.get("t") {
+        Some(secs) => Some(Duration::from_secs(secs.parse().map_err(|_| {
+            CuboError::InvalidConfiguration(format!("Invalid timeout '{}'", secs))
+        })?)),
+        None => None,
+    };
+    daemon.runtime.stop_container(&id, timeout).await?;
+    Ok(Response::empty(204))
+}
+
+fn parse_port_bindings(bindings: HashMap<String, Option<Vec<PortBinding>>>) -> Result<Vec<PortMapping>> {
+    let mut ports = Vec::new();
+    for (key, hosts) in bindings {
+        let (container_port, protocol) = key.split_once('/').unwrap_or((key.as_str(), "tcp"));
+        let container_port: u16 = container_port
+            .parse()
+            .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid port '{}'", key)))?;
+        let protocol = match protocol {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            other => return Err(CuboError::InvalidConfiguration(format!("Unsupported protocol '{}'", other))),
+        };
+        for host in hosts.unwrap_or_default() {
+            // An empty host port asks for a random one, picked when the container starts
+            let host_port = match host.host_port.as_deref() {
+                Some(port) if !port.is_empty() => port
+                    .parse()
+                    .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid host port '{}'", port)))?,
+                _ => 0,
+            };
+            ports.push(PortMapping {
+                host_port,
+                container_port,
+                protocol: protocol.clone(),
+                host_ip: host.host_ip.filter(|ip| !ip.is_empty()),
+            });
+        }
+    }
+    Ok(ports)
+}
+
+/// Map a Docker create request onto a cubo container, with image defaults like `cubo run`
+fn build_container(daemon: &Daemon, name: Option<&String>, create: CreateRequest) -> Result<Container> {
+    if create.image.is_empty() {
+        return Err(CuboError::InvalidConfiguration("Image is required".to_string()));
+    }
+    let image_config = daemon.image_store()?.get_config(&create.image).ok();
+
+    let mut command = create.entrypoint.unwrap_or_default();
+    command.extend(create.cmd.unwrap_or_default());
+    if command.is_empty() {
+        command = image_config
+            .as_ref()
+            .and_then(|c| c.cmd.clone())
+            .unwrap_or_else(|| vec!["/bin/sh".to_string()]);
+    }
+
+    let mut container = Container::new(create.image.clone(), command);
+    if let Some(name) = name.filter(|n| !n.is_empty()) {
+        container = container.with_name(name.clone());
+    }
+    if let Some(workdir) = create.working_dir.filter(|w| !w.is_empty()) {
+        container = container.with_workdir(workdir);
+    }
+    if let Some(hostname) = create.hostname.filter(|h| !h.is_empty()) {
+        container = container.with_hostname(hostname);
+    }
+    if let Some(domainname) = create.domainname.filter(|d| !d.is_empty()) {
+        container = container.with_domainname(domainname);
+    }
+    for env_var in create.env.unwrap_or_default() {
+        if let Some((key, value)) = parse_env_var(&env_var) {
+            container = container.with_env(key, value);
+        }
+    }
+    if let Some(signal) = create.stop_signal.or_else(|| image_config.and_then(|c| c.stop_signal)) {
+        container = container.with_stop_signal(signal);
+    }
+
+    let host_config = create.host_config.unwrap_or_default();
+    for bind in host_config.binds.unwrap_or_default() {
+        let volume = parse_volume(&bind)
+            .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid bind '{}'", bind)))?;
+        container = container.with_volume(volume);
+    }
+    for port in parse_port_bindings(host_config.port_bindings.unwrap_or_default())? {
+        container = container.with_port(port);
+    }
+    for opt in host_config.security_opt.unwrap_or_default() {
+        parse_security_opt(&opt, &mut container.config)?;
+    }
+    if let Some(memory) = host_config.memory.filter(|m| *m > 0) {
+        container = container.with_memory_limit(memory);
+    }
+    // Docker clients send 0 for unset, and -1 for unlimited swap
+    if let Some(swap) = host_config.memory_swap.filter(|s| *s != 0) {
+        container = container.with_memory_swap(swap);
+    }
+    if let Some(reservation) = host_config.memory_reservation.filter(|r| *r > 0) {
+        container = container.with_memory_reservation(reservation);
+    }
+    if let Some(score) = host_config.oom_score_adj.filter(|s| *s != 0) {
+        container = container.with_oom_score_adj(score);
+    }
+    if let Some(nano_cpus) = host_config.nano_cpus.filter(|n| *n > 0) {
+        container = container.with_cpu_limit((nano_cpus as f64 / 1e9) as f32);
+    }
+    if let Some(cpus) = host_config.cpuset_cpus.filter(|c| !c.is_empty()) {
+        container = container.with_cpuset_cpus(cpus);
+    }
+    if let Some(mems) = host_config.cpuset_mems.filter(|m| !m.is_empty()) {
+        container = container.with_cpuset_mems(mems);
+    }
+    if let Some(limit) = host_config.pids_limit.filter(|l| *l > 0) {
+        container = container.with_pids_limit(limit);
+    }
+    if let Some(mode) = host_config.network_mode.filter(|m| !m.is_empty() && m != "default") {
+        container = container.with_network_mode(mode.parse().map_err(CuboError::InvalidConfiguration)?);
+    }
+    if let Some(init) = host_config.init {
+        container = container.with_init(init);
+    }
+
+    validate_config(&container.config)?;
+    Ok(container)
+}
+
+async fn create_container(daemon: &Daemon, request: &Request) -> Result<Response> {
+    let create: CreateRequest = serde_json::from_slice(&request.body)
+        .map_err(|e| CuboError::InvalidConfiguration(format!("Invalid create request: {}", e)))?;
+    let image = create.image.clone();
+    let container = build_container(daemon, request.query.get("name"), create)?;
+
+    let mut warnings = Vec::new();
+    if !daemon.image_store()?.has_image(&image) {
+        warnings.push(format!("Image {} is not in the image store; pull it first", image));
+    }
+    let id = daemon.runtime.create_container(container).await?;
+    Ok(Response::json(201, &json!({ "Id": id, "Warnings": warnings })))
+}
+
+/// Frame `payload` the way Docker multiplexes stdout and stderr on a non-TTY stream
+fn frame(stream: &LogStream, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(match stream {
+        LogStream::Stdout => 1,
+        LogStream::Stderr => 2,
+    });
+    out.extend_from_slice(&[0, 0, 0]);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// A stored log line: stream, timestamp (if known) and message
+type LogLine = (LogStream, Option<DateTime<Utc>>, String);
+
+/// Lines written before log drivers existed have no stream or timestamp: treat them as stdout
+fn read_log_entries(container: &Container, root_dir: &std::path::Path) -> Result<Vec<LogLine>> {
+    let log_path = root_dir.join(&container.id).join(LOG_FILE);
+    let mut entries = Vec::new();
+    for segment in log_segments(&log_path) {
+        let file = File::open(&segment)?;
+        for line in BufReader::new(file).lines().map_while(|l| l.ok()) {
+            entries.push(match LogEntry::parse(&line) {
+                Some(entry) => (entry.stream, Some(entry.timestamp), entry.message),
+                None => (LogStream::Stdout, None, line),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// `GET /containers/{id}/logs`: stdout, stderr, timestamps, since and tail are honoured.
+/// `follow` isn't streamed; the logs written so far are returned.
+async fn container_logs(daemon: &Daemon, identifier: &str, request: &Request) -> Result<Response> {
+    let id = daemon.runtime.resolve(identifier).await?;
+    let container = daemon.runtime.get_container(&id).await?;
+    if container.config.log_driver != LogDriverKind::JsonFile {
+        return Err(CuboError::InvalidConfiguration(format!(
+            "Container {} uses the {} log driver, which can't be read back",
+            identifier, container.config.log_driver
+        )));
+    }
+
+    let since = match request.query.get("since").map(String::as_str) {
+        None | Some("") | Some("0") => None,
+        Some(secs) => {
+            let secs: f64 = secs
+                .parse()
+                .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid since '{}'", secs)))?;
+            DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+        }
+    };
+    let tail = match request.query.get("tail").map(String::as_str) {
+        None | Some("all") | Some("") => None,
+        Some(n) => Some(n.parse::<usize>().map_err(|_| {
+            CuboError::InvalidConfiguration(format!("Invalid tail '{}'", n))
+        })?),
+    };
+    let (stdout, stderr) = (request.flag("stdout"), request.flag("stderr"));
+
+    let entries: Vec<_> = read_log_entries(&container, &daemon.root_dir)?
+        .into_iter()
+        .filter(|(stream, _, _)| match stream {
+            LogStream::Stdout => stdout,
+            LogStream::Stderr => stderr,
+        })
+        .filter(|(_, timestamp, _)| match (since, timestamp) {
+            (Some(since), Some(t)) => *t >= since,
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .collect();
+    let skip = tail.map_or(0, |n| entries.len().saturating_sub(n));
+
+    let mut body = Vec::new();
+    for (stream, timestamp, message) in &entries[skip..] {
+        let line = match (request.flag("timestamps"), timestamp) {
+            (true, Some(t)) => format!("{} {}\n", t.to_rfc3339_opts(SecondsFormat::Nanos, true), message),
+            _ => format!("{}\n", message),
+        };
+        frame(stream, line.as_bytes(), &mut body);
+    }
+    Ok(Response::new(200, "application/vnd.docker.raw-stream", body))
+}
+
+/// Docker image IDs are content digests: the digest of the manifest, or a hash of the
+/// reference for an image that isn't in the store
+pub(super) fn image_id(store: &ImageStore, reference: &str) -> String {
+    store
+        .resolve(reference)
+        .unwrap_or_else(|_| format!("sha256:{:x}", Sha256::digest(reference.as_bytes())))
+}
+
+fn list_images(daemon: &Daemon, request: &Request) -> Result<Response> {
+    let store = daemon.image_store()?;
+    let mut images = Vec::new();
+    for manifest in store.find_images(&image_filters(request)?)? {
+        let size: u64 = manifest
+            .layers
+            .iter()
+            .filter_map(|layer| fs::metadata(layer).ok())
+            .map(|meta| meta.len())
+            .sum();
+        let created = manifest
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get("org.opencontainers.image.created"))
+            .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+            .map(|created| created.timestamp())
+            .unwrap_or(0);
+        images.push(json!({
+            "Id": image_id(&store, &manifest.reference),
+            "ParentId": "",
+            "RepoTags": [manifest.reference],
+            "RepoDigests": [],
+            "Created": created,
+            "Size": size,
+            "SharedSize": -1,
+            "Labels": manifest.config.labels.unwrap_or_default(),
+            "Containers": -1,
+        }));
+    }
+    Ok(Response::json(200, &json!(images)))
+}
+
+/// The `label` filters of `GET /images/json`. Docker sends `filters` as JSON, a list of
+/// values per filter (`{"label":["team=payments"]}`), or a map of them to `true` from
+/// older clients.
+fn image_filters(request: &Request) -> Result<Vec<ImageFilter>> {
+    let Some(filters) = request.query.get("filters").filter(|f| !f.is_empty()) else {
+        return Ok(Vec::new());
+    };
+    let invalid = |reason: String| CuboError::InvalidConfiguration(format!("Invalid filters: {}", reason));
+    let filters: HashMap<String, Value> = serde_json::from_str(filters).map_err(|e| invalid(e.to_string()))?;
+
+    let mut parsed = Vec::new();
+    for (name, values) in filters {
+        let values: Vec<String> = match values {
+            Value::Array(values) => values.iter().filter_map(|v| v.as_str().map(str::to_string)).collect(),
+            Value::Object(values) => values.keys().cloned().collect(),
+            _ => return Err(invalid(format!("{} must be a list", name))),
+        };
+        for value in values {
+            parsed.push(format!("{}={}", name, value).parse().map_err(invalid)?);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Docker streams progress as JSON lines; cubo reports the outcome in one line
+fn progress(lines: &[Value]) -> Response {
+    let mut body = Vec::new();
+    for line in lines {
+        body.extend_from_slice(line.to_string().as_bytes());
+        body.push(b'\n');
+    }
+    Response::new(200, "application/json", body)
+}
+
+async fn pull_image(daemon: &Daemon, request: &Request) -> Result<Response> {
+    let image = request
+        .query
+        .get("fromImage")
+        .filter(|i| !i.is_empty())
+        .ok_or_else(|| CuboError::InvalidConfiguration("fromImage is required".to_string()))?;
+    let reference = match request.query.get("tag").filter(|t| !t.is_empty()) {
+        Some(tag) if tag.starts_with("sha256:") => format!("{}@{}", image, tag),
+        Some(tag) => format!("{}:{}", image, tag),
+        None => image.clone(),
+    };
+
+    daemon.pull(&reference, &|_| {}).await?;
+    Ok(progress(&[
+        json!({ "status": format!("Pulling from {}", image) }),
+        json!({ "status": format!("Downloaded newer image for {}", reference) }),
+    ]))
+}
+
+/// The build file `dockerfile` names in the unpacked context, or else the Cubofile there. The
+/// name is resolved inside the context: `..`, absolute paths and symlinks can't leave it.
+fn context_build_file(context: &Path, dockerfile: &str) -> Result<(PathBuf, bool)> {
+    let named = secure_join(context, dockerfile)?;
+    if named.is_file() {
+        return Ok((named, dockerfile.ends_with(".toml")));
+    }
+    detect_build_file(&context.to_path_buf(), None).map_err(|_| {
+        CuboError::InvalidConfiguration(format!("No {} or Cubofile in the build context", dockerfile))
+    })
+}
+
+/// `POST /build`: the body is a tar (optionally gzipped) of the build context, built from
+/// `dockerfile` (default `Dockerfile`) or else the Cubofile it contains
+async fn build_image(daemon: &Daemon, request: &Request) -> Result<Response> {
+    let context = tempfile::tempdir()?;
+    let body = request.body.as_slice();
+    let unpacked = if body.starts_with(&[0x1f, 0x8b]) {
+        tar::Archive::new(flate2::read::GzDecoder::new(body)).unpack(context.path())
+    } else {
+        tar::Archive::new(body).unpack(context.path())
+    };
+    unpacked.map_err(|e| CuboError::InvalidConfiguration(format!("Invalid build context: {}", e)))?;
+
+    let context_path = context.path().to_path_buf();
+    let dockerfile = request.query.get("dockerfile").map_or("Dockerfile", String::as_str);
+    let (build_file, is_toml) = context_build_file(&context_path, dockerfile)?;
+
+    let tag = match request.query.get("t").filter(|t| !t.is_empty()) {
+        Some(tag) if tag.contains(':') => tag.clone(),
+        Some(tag) => format!("{}:latest", tag),
+        None => format!("build-{}:latest", &uuid::Uuid::new_v4().simple().to_string()[..12]),
+    };
+
+    // Docker sends the labels as a JSON object
+    let labels = match request.query.get("labels").filter(|l| !l.is_empty()) {
+        Some(labels) => serde_json::from_str(labels)
+            .map_err(|e| CuboError::InvalidConfiguration(format!("Invalid labels: {}", e)))?,
+        None => BTreeMap::new(),
+    };
+
+    let store = daemon.image_store()?;
+    let builder = ImageBuilder::new(&store, context_path).with_labels(labels);
+    let result = if is_toml {
+        match CubofileToml::from_file(&build_file) {
+            Ok(cubofile) => builder.build_from_toml(&cubofile, &tag).await,
+            Err(e) => Err(e),
+        }
+    } else {
+        match Cubofile::from_file(&build_file) {
+            Ok(cubofile) => builder.build(&cubofile, &tag).await,
+            Err(e) => Err(e),
+        }
+    };
+    AuditLog::new(&daemon.root_dir).record(AuditRecord::new(AuditOperation::Build, &result).with_image(&tag));
+    result?;
+
+    let id = image_id(&daemon.image_store()?, &tag);
+    Ok(progress(&[
+        json!({ "aux": { "ID": id } }),
+        json!({ "stream": format!("Successfully built {}\n", id) }),
+        json!({ "stream": format!("Successfully tagged {}\n", tag) }),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::runtime::RuntimeConfig;
+    use crate::container::image_store::{ImageConfig, ImageManifest};
+    use tempfile::TempDir;
+
+    fn daemon(temp_dir: &TempDir) -> Daemon {
+        Daemon::new(RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut out = Vec::new();
+        frame(&LogStream::Stderr, b"oops\n", &mut out);
+        assert_eq!(out, [2, 0, 0, 0, 0, 0, 0, 5, b'o', b'o', b'p', b's', b'\n']);
+    }
+
+    #[test]
+    fn test_parse_port_bindings() {
+        let bindings: HashMap<String, Option<Vec<PortBinding>>> = serde_json::from_value(json!({
+            "80/tcp": [{ "HostIp": "", "HostPort": "8080" }],
+            "53/udp": [{ "HostIp": "127.0.0.1", "HostPort": "" }],
+            "9000/tcp": null,
+        }))
+        .unwrap();
+        let mut ports = parse_port_bindings(bindings).unwrap();
+        ports.sort_by_key(|p| p.container_port);
+        assert_eq!(ports.len(), 2);
+        assert_eq!((ports[0].host_port, ports[0].container_port), (0, 53));
+        assert_eq!(ports[0].host_ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!((ports[1].host_port, ports[1].container_port), (8080, 80));
+        assert_eq!(ports[1].host_ip, None);
+    }
+
+    #[test]
+    fn test_parse_port_bindings_invalid() {
+        let bindings = HashMap::from([("http/tcp".to_string(), None)]);
+        assert!(parse_port_bindings(bindings).is_err());
+        let bindings = HashMap::from([("80/sctp".to_string(), None)]);
+        assert!(parse_port_bindings(bindings).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ping_and_version_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        let response = daemon.handle(Request::new("GET", "/_ping")).await;
+        assert_eq!((response.status, response.body.as_slice()), (200, &b"OK"[..]));
+
+        let response = daemon.handle(Request::new("GET", "/v1.43/version")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response)["ApiVersion"], DOCKER_API_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_create_list_inspect_and_delete() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        let create = json!({
+            "Image": "alpine:latest",
+            "Cmd": ["echo", "hi"],
+            "Env": ["A=1", "B=two"],
+            "WorkingDir": "/srv",
+            "Domainname": "example.com",
+            "HostConfig": {
+                "Binds": ["/srv/data:/data:ro,Z"],
+                "SecurityOpt": ["label=level:s0:c1,c2", "apparmor=unconfined"],
+                "PortBindings": { "80/tcp": [{ "HostPort": "8080" }] },
+                "Memory": 268435456,
+                "NanoCpus": 1500000000,
+                "PidsLimit": 0,
+            },
+        });
+        let request = Request::new("POST", "/v1.43/containers/create?name=web")
+            .with_body(serde_json::to_vec(&create).unwrap());
+        let response = daemon.handle(request).await;
+        assert_eq!(response.status, 201);
+        let id = body(&response)["Id"].as_str().unwrap().to_string();
+        assert_eq!(body(&response)["Warnings"].as_array().unwrap().len(), 1);
+
+        let response = daemon.handle(Request::new("GET", "/containers/json?all=1")).await;
+        let list = body(&response);
+        assert_eq!(list[0]["Id"], id.as_str());
+        assert_eq!(list[0]["Names"][0], "/web");
+        assert_eq!(list[0]["State"], "created");
+        assert_eq!(list[0]["Ports"][0]["PublicPort"], 8080);
+        let response = daemon.handle(Request::new("GET", "/containers/json")).await;
+        assert!(body(&response).as_array().unwrap().is_empty());
+
+        let response = daemon.handle(Request::new("GET", "/containers/web/json")).await;
+        let inspected = body(&response);
+        assert_eq!(inspected["Path"], "echo");
+        assert_eq!(inspected["Config"]["WorkingDir"], "/srv");
+        assert_eq!(inspected["Config"]["Hostname"], &id[..12]);
+        assert_eq!(inspected["Config"]["Domainname"], "example.com");
+        assert_eq!(inspected["HostConfig"]["Memory"], 268435456);
+        assert_eq!(inspected["HostConfig"]["NanoCpus"], 1500000000);
+        assert_eq!(inspected["HostConfig"]["Binds"][0], "/srv/data:/data:ro,Z");
+        assert_eq!(inspected["HostConfig"]["SecurityOpt"], json!(["label=level:s0:c1,c2", "apparmor=unconfined"]));
+        assert_eq!(inspected["AppArmorProfile"], "unconfined");
+        assert_eq!(inspected["NetworkSettings"]["Ports"]["80/tcp"][0]["HostPort"], "8080");
+        assert_eq!(inspected["HostConfig"]["RestartPolicy"]["Name"], "no");
+        assert_eq!(inspected["RestartCount"], 0);
+
+        let response = daemon.handle(Request::new("DELETE", "/containers/web?force=1")).await;
+        assert_eq!(response.status, 204);
+        let response = daemon.handle(Request::new("GET", "/containers/web/json")).await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_create_requires_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let request = Request::new("POST", "/containers/create").with_body(b"{\"Cmd\":null}".to_vec());
+        let response = daemon(&temp_dir).handle(request).await;
+        assert_eq!(response.status, 400);
+        assert!(body(&response)["message"].as_str().unwrap().contains("Image is required"));
+    }
+
+    #[tokio::test]
+    async fn test_container_logs_multiplexed() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        let container = Container::new("alpine:latest".to_string(), vec!["echo".to_string()]);
+        let id = daemon.runtime.create_container(container).await.unwrap();
+        let lines = [
+            r#"{"stream":"stdout","timestamp":"2025-11-24T20:30:00Z","message":"out"}"#,
+            r#"{"stream":"stderr","timestamp":"2025-11-24T20:30:01Z","message":"err"}"#,
+            r#"{"stream":"stdout","timestamp":"2025-11-24T20:30:02Z","message":"late"}"#,
+        ];
+        fs::write(temp_dir.path().join(&id).join(LOG_FILE), lines.join("\n") + "\n").unwrap();
+
+        let request = Request::new("GET", &format!("/containers/{}/logs?stdout=1&stderr=1&tail=2", id));
+        let response = daemon.handle(request).await;
+        assert_eq!(response.content_type, "application/vnd.docker.raw-stream");
+        let mut expected = Vec::new();
+        frame(&LogStream::Stderr, b"err\n", &mut expected);
+        frame(&LogStream::Stdout, b"late\n", &mut expected);
+        assert_eq!(response.body, expected);
+
+        // since is a unix timestamp: 2025-11-24T20:30:01Z
+        let request = Request::new("GET", &format!("/containers/{}/logs?stdout=1&since=1764016201", id));
+        let mut expected = Vec::new();
+        frame(&LogStream::Stdout, b"late\n", &mut expected);
+        assert_eq!(daemon.handle(request).await.body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_pull_requires_from_image() {
+        let temp_dir = TempDir::new().unwrap();
+        let response = daemon(&temp_dir).handle(Request::new("POST", "/images/create")).await;
+        assert_eq!(response.status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_build_rejects_invalid_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let request = Request::new("POST", "/build?t=app").with_body(b"not a tar".to_vec());
+        let response = daemon(&temp_dir).handle(request).await;
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn test_build_file_stays_in_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = temp_dir.path().join("context");
+        fs::create_dir_all(context.join("docker")).unwrap();
+        fs::write(context.join("docker/Dockerfile.dev"), "FROM alpine\n").unwrap();
+        fs::write(temp_dir.path().join("Dockerfile.evil"), "FROM alpine\n").unwrap();
+
+        let (path, is_toml) = context_build_file(&context, "docker/Dockerfile.dev").unwrap();
+        assert_eq!(path, context.join("docker/Dockerfile.dev"));
+        assert!(!is_toml);
+
+        // Neither `..` nor an absolute path reaches the file next to the context
+        let outside = temp_dir.path().join("Dockerfile.evil");
+        for dockerfile in ["../Dockerfile.evil".to_string(), outside.to_string_lossy().to_string()] {
+            let result = context_build_file(&context, &dockerfile);
+            assert!(matches!(result, Err(CuboError::InvalidConfiguration(_))), "{}", dockerfile);
+        }
+    }
+
+    #[test]
+    fn test_build_file_symlink_stays_in_context() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = temp_dir.path().join("context");
+        fs::create_dir_all(&context).unwrap();
+        fs::write(temp_dir.path().join("secret"), "FROM alpine\n").unwrap();
+        std::os::unix::fs::symlink(temp_dir.path().join("secret"), context.join("Dockerfile")).unwrap();
+
+        let result = context_build_file(&context, "Dockerfile");
+        assert!(matches!(result, Err(CuboError::InvalidConfiguration(_))));
+    }
+
+    #[tokio::test]
+    async fn test_list_images_with_labels() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        let store = daemon.image_store().unwrap();
+        for (reference, team) in [("web:1", Some("payments")), ("alpine:latest", None)] {
+            store
+                .save_manifest(&ImageManifest {
+                    reference: reference.to_string(),
+                    parent: None,
+                    layers: vec![],
+                    config: ImageConfig {
+                        cmd: None,
+                        env: None,
+                        working_dir: None,
+                        exposed_ports: None,
+                        stop_signal: None,
+                        labels: team.map(|team| BTreeMap::from([("team".to_string(), team.to_string())])),
+                    },
+                    annotations: Some(BTreeMap::from([(
+                        "org.opencontainers.image.created".to_string(),
+                        "2026-10-17T09:00:00Z".to_string(),
+                    )])),
+                    repo_digest: None,
+                })
+                .unwrap();
+        }
+
+        let images = body(&daemon.handle(Request::new("GET", "/images/json")).await);
+        assert_eq!(images.as_array().unwrap().len(), 2);
+        assert_eq!(images[1]["RepoTags"][0], "web:1");
+        assert_eq!(images[1]["Labels"]["team"], "payments");
+        assert_eq!(images[1]["Created"], 1792227600);
+
+        // filters={"label":["team=payments"]}
+        let request = Request::new("GET", "/images/json?filters=%7B%22label%22%3A%5B%22team%3Dpayments%22%5D%7D");
+        let images = body(&daemon.handle(request).await);
+        assert_eq!(images.as_array().unwrap().len(), 1);
+        assert_eq!(images[0]["RepoTags"][0], "web:1");
+
+        let request = Request::new("GET", "/images/json?filters=%7B%22dangling%22%3A%5B%22true%22%5D%7D");
+        assert_eq!(daemon.handle(request).await.status, 400);
+    }
+
+    #[test]
+    fn test_image_id_is_stable_digest() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = ImageStore::new(temp_dir.path().to_path_buf()).unwrap();
+        let id = image_id(&store, "alpine:latest");
+        assert!(id.starts_with("sha256:"));
+        assert_eq!(id.len(), "sha256:".len() + 64);
+        assert_eq!(id, image_id(&store, "alpine:latest"));
+    }
+}
@@ -0,0 +1,657 @@
+//! gRPC control API (proto/cubo/v1/cubo.proto) for programmatic orchestration: container
+//! lifecycle, exec with bidirectional streams, followed logs, runtime events and pull progress.
+
+pub mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("cubo.v1");
+}
+
+pub use proto::cubo_client::CuboClient;
+
+use super::Daemon;
+use crate::commands::logs::StoredLine;
+use crate::commands::run::{parse_port, parse_volume, validate_config};
+use crate::container::container_store::pid_is_alive;
+use crate::container::events::RuntimeEvent;
+use crate::container::exec::{exec_command, NSENTER};
+use crate::container::logging::{log_segments, was_rotated, LogStream, LOG_FILE};
+use crate::container::registry::PullProgress;
+use crate::container::{Container, LogDriverKind};
+use crate::error::{CuboError, Result};
+use hyper_util::rt::TokioIo;
+use proto::cubo_server::{Cubo, CuboServer};
+use proto::{exec_request, exec_response};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
+use tonic::transport::{Channel, Endpoint, Server, Uri};
+use tonic::{Request, Response, Status, Streaming};
+use tower::service_fn;
+use tracing::warn;
+
+/// Messages a stream buffers before the producer waits for the client
+const STREAM_BUFFER: usize = 64;
+
+type GrpcResult<T> = std::result::Result<Response<T>, Status>;
+type ResponseStream<T> = ReceiverStream<std::result::Result<T, Status>>;
+
+/// Serve the gRPC API on `listener` until `shutdown` completes
+pub async fn serve(
+    daemon: Daemon,
+    listener: UnixListener,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<()> {
+    Server::builder()
+        .add_service(CuboServer::new(CuboService { daemon }))
+        .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+        .await
+        .map_err(|e| CuboError::DaemonError(format!("gRPC server failed: {}", e)))
+}
+
+/// Connect to the gRPC API of a running `cubod`
+pub async fn connect(socket: impl AsRef<Path>) -> Result<CuboClient<Channel>> {
+    Ok(CuboClient::new(channel(socket.as_ref()).await?))
+}
+
+/// A gRPC channel over a unix socket
+pub(super) async fn channel(socket: &Path) -> Result<Channel> {
+    let socket = socket.to_path_buf();
+    let target = socket.clone();
+    // The URI is required but unused: every connection goes to the socket
+    let channel = Endpoint::from_static("http://localhost")
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let target = target.clone();
+            async move { Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(target).await?)) }
+        }))
+        .await
+        .map_err(|e| {
+            CuboError::DaemonError(format!(
+                "Cannot connect to the daemon at {}: {} (is cubod running?)",
+                socket.display(),
+                e
+            ))
+        })?;
+    Ok(channel)
+}
+
+pub struct CuboService {
+    daemon: Daemon,
+}
+
+#[tonic::async_trait]
+impl Cubo for CuboService {
+    async fn create_container(
+        &self,
+        request: Request<proto::CreateContainerRequest>,
+    ) -> GrpcResult<proto::CreateContainerResponse> {
+        let container = build_container(&self.daemon, request.into_inner()).map_err(to_status)?;
+        let id = self.daemon.runtime.create_container(container).await.map_err(to_status)?;
+        Ok(Response::new(proto::CreateContainerResponse { id }))
+    }
+
+    async fn start_container(
+        &self,
+        request: Request<proto::StartContainerRequest>,
+    ) -> GrpcResult<proto::StartContainerResponse> {
+        let id = self.find_container_id(&request.into_inner().container).await?;
+        self.daemon.runtime.start_container(&id, true).await.map_err(to_status)?;
+        Ok(Response::new(proto::StartContainerResponse { id }))
+    }
+
+    async fn stop_container(
+        &self,
+        request: Request<proto::StopContainerRequest>,
+    ) -> GrpcResult<proto::StopContainerResponse> {
+        let request = request.into_inner();
+        let id = self.find_container_id(&request.container).await?;
+        let timeout = request.timeout_seconds.map(|secs| Duration::from_secs(secs.into()));
+        self.daemon.runtime.stop_container(&id, timeout).await.map_err(to_status)?;
+        Ok(Response::new(proto::StopContainerResponse { id }))
+    }
+
+    async fn remove_container(
+        &self,
+        request: Request<proto::RemoveContainerRequest>,
+    ) -> GrpcResult<proto::RemoveContainerResponse> {
+        let request = request.into_inner();
+        let id = self.find_container_id(&request.container).await?;
+        self.daemon.runtime.remove_container(&id, request.force).await.map_err(to_status)?;
+        Ok(Response::new(proto::RemoveContainerResponse { id }))
+    }
+
+    async fn list_containers(
+        &self,
+        request: Request<proto::ListContainersRequest>,
+    ) -> GrpcResult<proto::ListContainersResponse> {
+        let containers = self
+            .daemon
+            .runtime
+            .list_containers(request.into_inner().all)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::ListContainersResponse {
+            containers: containers.into_iter().map(to_proto_container).collect(),
+        }))
+    }
+
+    type ExecStream = ResponseStream<proto::ExecResponse>;
+
+    async fn exec(&self, request: Request<Streaming<proto::ExecRequest>>) -> GrpcResult<Self::ExecStream> {
+        let mut inbound = request.into_inner();
+        let start = match inbound.message().await?.and_then(|message| message.payload) {
+            Some(exec_request::Payload::Start(start)) => start,
+            _ => return Err(Status::invalid_argument("The first exec message must be ExecStart")),
+        };
+        let id = self.find_container_id(&start.container).await?;
+        let container = self.daemon.runtime.get_container(&id).await.map_err(to_status)?;
+
+        let mut command = exec_command(&container, &start.command, &start.env).map_err(to_status)?;
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    Status::failed_precondition(format!("{} not found; install util-linux", NSENTER))
+                }
+                _ => Status::internal(format!("Failed to start exec process: {}", e)),
+            })?;
+
+        // Client messages after ExecStart feed stdin; dropping it closes the process's stdin
+        let mut stdin = child.stdin.take();
+        tokio::spawn(async move {
+            while let Ok(Some(message)) = inbound.message().await {
+                match message.payload {
+                    Some(exec_request::Payload::Stdin(data)) => {
+                        if let Some(pipe) = stdin.as_mut() {
+                            if pipe.write_all(&data).await.is_err() {
+                                stdin = None;
+                            }
+                        }
+                    }
+                    Some(exec_request::Payload::CloseStdin(true)) => stdin = None,
+                    _ => {}
+                }
+            }
+        });
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        tokio::spawn(async move {
+            let (out, err) = tokio::join!(
+                forward_output(stdout, &tx, exec_response::Payload::Stdout),
+                forward_output(stderr, &tx, exec_response::Payload::Stderr),
+            );
+            // The client went away: don't leave the process running unattended
+            if !(out && err) {
+                let _ = child.start_kill();
+            }
+            let reply = match child.wait().await {
+                Ok(status) => Ok(proto::ExecResponse {
+                    payload: Some(exec_response::Payload::ExitCode(
+                        status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+                    )),
+                }),
+                Err(e) => Err(Status::internal(format!("Failed to wait for exec process: {}", e))),
+            };
+            let _ = tx.send(reply).await;
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type LogsStream = ResponseStream<proto::LogLine>;
+
+    async fn logs(&self, request: Request<proto::LogsRequest>) -> GrpcResult<Self::LogsStream> {
+        let request = request.into_inner();
+        let id = self.find_container_id(&request.container).await?;
+        let container = self.daemon.runtime.get_container(&id).await.map_err(to_status)?;
+        if container.config.log_driver != LogDriverKind::JsonFile {
+            return Err(Status::failed_precondition(format!(
+                "Container {} does not use the json-file log driver",
+                request.container
+            )));
+        }
+
+        let path = self.daemon.root_dir.join(&id).join(LOG_FILE);
+        let pid = if request.follow && container.is_running() { container.pid } else { None };
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            if let Err(e) = stream_logs(&path, request.tail, request.follow, pid, &tx).await {
+                let _ = tx.send(Err(to_status(e))).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type WatchEventsStream = ResponseStream<proto::Event>;
+
+    async fn watch_events(&self, request: Request<proto::WatchEventsRequest>) -> GrpcResult<Self::WatchEventsStream> {
+        let ids = request.into_inner().ids;
+        let mut events = self.daemon.runtime.events().subscribe();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    received = events.recv() => match received {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("Event watcher fell behind and missed {} events", missed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = tx.closed() => break,
+                };
+                if !ids.is_empty() && !ids.contains(&event.id) {
+                    continue;
+                }
+                if tx.send(Ok(to_proto_event(event))).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type PullImageStream = ResponseStream<proto::PullProgress>;
+
+    async fn pull_image(&self, request: Request<proto::PullImageRequest>) -> GrpcResult<Self::PullImageStream> {
+        let reference = request.into_inner().reference;
+        if reference.is_empty() {
+            return Err(Status::invalid_argument("Image reference is required"));
+        }
+
+        let daemon = self.daemon.clone();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let updates = tx.clone();
+            // Progress is advisory: drop updates rather than stall the pull on a slow client
+            let progress = move |step: PullProgress| {
+                let _ = updates.try_send(Ok(proto::PullProgress {
+                    status: step.status,
+                    current: step.current as u32,
+                    total: step.total as u32,
+                }));
+            };
+            if let Err(e) = daemon.pull(&reference, &progress).await {
+                let _ = tx.send(Err(to_status(e))).await;
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+impl CuboService {
+    async fn find_container_id(&self, identifier: &str) -> std::result::Result<String, Status> {
+        self.daemon.runtime.resolve(identifier).await.map_err(to_status)
+    }
+}
+
+fn build_container(daemon: &Daemon, request: proto::CreateContainerRequest) -> Result<Container> {
+    if request.image.is_empty() {
+        return Err(CuboError::InvalidConfiguration("Image is required".to_string()));
+    }
+    let image_config = daemon.image_store()?.get_config(&request.image).ok();
+
+    let command = if request.command.is_empty() {
+        image_config
+            .as_ref()
+            .and_then(|c| c.cmd.clone())
+            .unwrap_or_else(|| vec!["/bin/sh".to_string()])
+    } else {
+        request.command
+    };
+
+    let mut container = Container::new(request.image, command);
+    if !request.name.is_empty() {
+        container = container.with_name(request.name);
+    }
+    if !request.working_dir.is_empty() {
+        container = container.with_workdir(request.working_dir);
+    }
+    for (key, value) in request.env {
+        container = container.with_env(key, value);
+    }
+    for volume in request.volumes {
+        let mount = parse_volume(&volume)
+            .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid volume '{}'", volume)))?;
+        container = container.with_volume(mount);
+    }
+    for port in request.ports {
+        let mapping = parse_port(&port)
+            .ok_or_else(|| CuboError::InvalidConfiguration(format!("Invalid port '{}'", port)))?;
+        container = container.with_port(mapping);
+    }
+    if let Some(memory) = request.memory_limit {
+        container = container.with_memory_limit(memory);
+    }
+    if let Some(cpus) = request.cpu_limit {
+        container = container.with_cpu_limit(cpus);
+    }
+    if let Some(limit) = request.pids_limit {
+        container = container.with_pids_limit(limit);
+    }
+    if !request.network.is_empty() {
+        container = container.with_network_mode(request.network.parse().map_err(CuboError::InvalidConfiguration)?);
+    }
+    container = container.with_init(request.init);
+    if let Some(signal) = image_config.and_then(|c| c.stop_signal) {
+        container = container.with_stop_signal(signal);
+    }
+
+    validate_config(&container.config)?;
+    Ok(container)
+}
+
+/// Send chunks of `output` until it closes; false when the client stopped listening
+async fn forward_output(
+    output: Option<impl AsyncRead + Unpin>,
+    tx: &mpsc::Sender<std::result::Result<proto::ExecResponse, Status>>,
+    payload: fn(Vec<u8>) -> exec_response::Payload,
+) -> bool {
+    let Some(mut output) = output else {
+        return true;
+    };
+    let mut buffer = vec![0u8; 8192];
+    loop {
+        match output.read(&mut buffer).await {
+            Ok(0) | Err(_) => return true,
+            Ok(read) => {
+                let message = proto::ExecResponse { payload: Some(payload(buffer[..read].to_vec())) };
+                if tx.send(Ok(message)).await.is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Send the log's history (only the last `tail` lines if given), then, when following, new
+/// lines until the container exits or the client goes away
+async fn stream_logs(
+    path: &Path,
+    tail: Option<u32>,
+    follow: bool,
+    pid: Option<u32>,
+    tx: &mpsc::Sender<std::result::Result<proto::LogLine, Status>>,
+) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let mut reader = BufReader::new(File::open(path)?);
+
+    // Rotated segments, then the live file through the reader we keep following
+    let mut segments: Vec<PathBuf> = log_segments(path);
+    segments.pop();
+    let mut history = Vec::new();
+    for segment in segments {
+        history.extend(BufReader::new(File::open(segment)?).lines().map_while(|l| l.ok()));
+    }
+    history.extend((&mut reader).lines().map_while(|l| l.ok()));
+
+    let skip = tail.map_or(0, |tail| history.len().saturating_sub(tail as usize));
+    for line in &history[skip..] {
+        if tx.send(Ok(to_proto_line(line))).await.is_err() {
+            return Ok(());
+        }
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut line = String::new();
+    let mut exiting = false;
+    while !tx.is_closed() {
+        if reader.read_line(&mut line)? == 0 {
+            // The driver rotated the log: carry on with the new file from its start
+            if was_rotated(path, reader.get_ref()) {
+                if let Ok(file) = File::open(path) {
+                    reader = BufReader::new(file);
+                    continue;
+                }
+            }
+            if exiting {
+                break;
+            }
+            // Give the logger a moment to flush the last lines once the container is gone
+            exiting = !pid_is_alive(pid);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            continue;
+        }
+        if tx.send(Ok(to_proto_line(line.trim_end_matches('\n')))).await.is_err() {
+            break;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+fn to_proto_container(container: Container) -> proto::Container {
+    proto::Container {
+        status: container.status.to_string().to_lowercase(),
+        created_at_unix: container.created_at.timestamp(),
+        name: container.name.unwrap_or_default(),
+        id: container.id,
+        image: container.blueprint,
+        command: container.command,
+        pid: container.pid,
+        exit_code: container.exit_code,
+    }
+}
+
+fn to_proto_line(line: &str) -> proto::LogLine {
+    let stored = StoredLine::parse(line);
+    let stream = match stored.stream {
+        LogStream::Stdout => proto::LogStream::Stdout,
+        LogStream::Stderr => proto::LogStream::Stderr,
+    };
+    proto::LogLine {
+        stream: stream.into(),
+        timestamp_unix_nanos: stored.timestamp.and_then(|t| t.timestamp_nanos_opt()),
+        message: stored.message,
+    }
+}
+
+fn to_proto_event(event: RuntimeEvent) -> proto::Event {
+    proto::Event {
+        kind: event.kind.to_string(),
+        id: event.id,
+        time_unix_nanos: event.time.timestamp_nanos_opt().unwrap_or_default(),
+    }
+}
+
+pub(super) fn to_status(error: CuboError) -> Status {
+    let message = error.to_string();
+    match error {
+        CuboError::ContainerNotFound(_) | CuboError::ImageNotFound { .. } => Status::not_found(message),
+        CuboError::RegistryError { .. } => Status::unavailable(message),
+        CuboError::ContainerAlreadyExists(_) => Status::already_exists(message),
+        CuboError::ContainerAlreadyRunning(_) | CuboError::ContainerNotRunning(_) | CuboError::PortInUse { .. } => {
+            Status::failed_precondition(message)
+        }
+        CuboError::InvalidConfiguration(_) | CuboError::DaemonError(_) | CuboError::AmbiguousIdentifier { .. } => {
+            Status::invalid_argument(message)
+        }
+        CuboError::PermissionDenied(_) => Status::permission_denied(message),
+        _ => Status::internal(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::logging::LogEntry;
+    use crate::container::runtime::RuntimeConfig;
+    use tempfile::TempDir;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+    use tonic::Code;
+
+    async fn start_daemon(temp_dir: &TempDir) -> (CuboClient<Channel>, oneshot::Sender<()>, JoinHandle<Result<()>>) {
+        let socket = temp_dir.path().join("cubo.sock");
+        let grpc_socket = temp_dir.path().join("cubo-grpc.sock");
+        let daemon = Daemon::new(RuntimeConfig {
+            root_dir: temp_dir.path().join("root"),
+            ..Default::default()
+        })
+        .unwrap();
+        let served = grpc_socket.clone();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let handle = tokio::spawn(async move {
+            daemon.serve_until(&socket, Some(&served), async { let _ = stopped.await; }).await
+        });
+        for _ in 0..50 {
+            if grpc_socket.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        (connect(&grpc_socket).await.unwrap(), stop, handle)
+    }
+
+    fn create_request(name: &str) -> proto::CreateContainerRequest {
+        proto::CreateContainerRequest {
+            image: "test:latest".to_string(),
+            command: vec!["echo".to_string(), "hi".to_string()],
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_and_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut client, stop, handle) = start_daemon(&temp_dir).await;
+
+        let mut events = client
+            .watch_events(proto::WatchEventsRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+
+        let id = client.create_container(create_request("grpc")).await.unwrap().into_inner().id;
+        let listed = client
+            .list_containers(proto::ListContainersRequest { all: true })
+            .await
+            .unwrap()
+            .into_inner()
+            .containers;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "grpc");
+        assert_eq!(listed[0].status, "created");
+        assert_eq!(listed[0].command, ["echo", "hi"]);
+
+        let removed = client
+            .remove_container(proto::RemoveContainerRequest { container: "grpc".to_string(), force: false })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(removed.id, id);
+
+        let created = events.message().await.unwrap().unwrap();
+        assert_eq!((created.kind.as_str(), created.id.as_str()), ("create", id.as_str()));
+        let removed = events.message().await.unwrap().unwrap();
+        assert_eq!(removed.kind, "remove");
+
+        drop(events);
+        stop.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+        assert!(!temp_dir.path().join("cubo-grpc.sock").exists());
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_status_codes() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut client, stop, handle) = start_daemon(&temp_dir).await;
+
+        let err = client
+            .start_container(proto::StartContainerRequest { container: "missing".to_string() })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        let mut bad_port = create_request("bad");
+        bad_port.ports = vec!["nope".to_string()];
+        let err = client.create_container(bad_port).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        let err = client.pull_image(proto::PullImageRequest::default()).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        client.create_container(create_request("idle")).await.unwrap();
+        let start = proto::ExecRequest {
+            payload: Some(exec_request::Payload::Start(proto::ExecStart {
+                container: "idle".to_string(),
+                command: vec!["ls".to_string()],
+                ..Default::default()
+            })),
+        };
+        let err = client.exec(tokio_stream::iter(vec![start])).await.unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition);
+
+        let err = client.exec(tokio_stream::iter(Vec::new())).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        stop.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_logs_stream_history() {
+        let temp_dir = TempDir::new().unwrap();
+        let (mut client, stop, handle) = start_daemon(&temp_dir).await;
+
+        let id = client.create_container(create_request("logger")).await.unwrap().into_inner().id;
+        let lines: Vec<String> = ["one", "two", "three"]
+            .iter()
+            .map(|message| {
+                let stream = if *message == "two" { LogStream::Stderr } else { LogStream::Stdout };
+                serde_json::to_string(&LogEntry::new(stream, message.to_string())).unwrap()
+            })
+            .collect();
+        std::fs::write(temp_dir.path().join("root").join(&id).join(LOG_FILE), lines.join("\n") + "\n").unwrap();
+
+        let request = proto::LogsRequest { container: "logger".to_string(), follow: false, tail: Some(2) };
+        let mut stream = client.logs(request).await.unwrap().into_inner();
+        let mut received = Vec::new();
+        while let Some(line) = stream.message().await.unwrap() {
+            assert!(line.timestamp_unix_nanos.is_some());
+            received.push((line.stream(), line.message));
+        }
+        assert_eq!(
+            received,
+            [(proto::LogStream::Stderr, "two".to_string()), (proto::LogStream::Stdout, "three".to_string())]
+        );
+
+        stop.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_to_status() {
+        assert_eq!(to_status(CuboError::ContainerNotFound("x".into())).code(), Code::NotFound);
+        assert_eq!(to_status(CuboError::ContainerAlreadyExists("x".into())).code(), Code::AlreadyExists);
+        assert_eq!(to_status(CuboError::ContainerNotRunning("x".into())).code(), Code::FailedPrecondition);
+        assert_eq!(to_status(CuboError::PermissionDenied("x".into())).code(), Code::PermissionDenied);
+        let ambiguous = CuboError::AmbiguousIdentifier { identifier: "x".into(), candidates: vec![] };
+        assert_eq!(to_status(ambiguous).code(), Code::InvalidArgument);
+        assert_eq!(to_status(CuboError::SystemError("x".into())).code(), Code::Internal);
+    }
+
+    #[test]
+    fn test_to_proto_line_legacy() {
+        let line = to_proto_line("plain output");
+        assert_eq!(line.stream(), proto::LogStream::Stdout);
+        assert_eq!(line.timestamp_unix_nanos, None);
+        assert_eq!(line.message, "plain output");
+    }
+}
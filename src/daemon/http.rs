@@ -0,0 +1,319 @@
+//! Just enough HTTP/1.1 for the daemon API: one request and response per connection.
+
+use crate::error::{CuboError, Result};
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Requests larger than this are refused rather than buffered (build contexts included)
+const MAX_BODY: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, target: &str) -> Self {
+        let (path, query) = split_target(target);
+        Self { method: method.to_string(), path, query, body: Vec::new() }
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// True when the query parameter is present and set to `1` or `true`
+    pub fn flag(&self, name: &str) -> bool {
+        matches!(self.query.get(name).map(String::as_str), Some("1") | Some("true"))
+    }
+
+    fn target(&self) -> String {
+        if self.query.is_empty() {
+            return self.path.clone();
+        }
+        let mut pairs: Vec<String> = self.query.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+        pairs.sort();
+        format!("{}?{}", self.path, pairs.join("&"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Self { status, content_type: content_type.to_string(), body }
+    }
+
+    pub fn json(status: u16, value: &serde_json::Value) -> Self {
+        Self::new(status, "application/json", value.to_string().into_bytes())
+    }
+
+    pub fn empty(status: u16) -> Self {
+        Self::new(status, "text/plain", Vec::new())
+    }
+}
+
+fn split_target(target: &str) -> (String, HashMap<String, String>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((k, v)) => (percent_decode(k), percent_decode(v)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect();
+    (path.to_string(), query)
+}
+
+/// Decode `%XX` escapes and `+` in a query component; invalid escapes are kept verbatim
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Read the header block, returning the start line and the headers with lowercased names
+async fn read_head<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<(String, HashMap<String, String>)> {
+    let mut start = String::new();
+    reader.read_line(&mut start).await?;
+    if start.is_empty() {
+        return Err(CuboError::DaemonError("Connection closed before a request".to_string()));
+    }
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Ok((start.trim_end().to_string(), headers))
+}
+
+fn content_length(headers: &HashMap<String, String>) -> Result<usize> {
+    let length = match headers.get("content-length") {
+        Some(value) => value
+            .parse()
+            .map_err(|_| CuboError::DaemonError(format!("Invalid Content-Length '{}'", value)))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(CuboError::DaemonError(format!("Body of {} bytes is too large", length)));
+    }
+    Ok(length)
+}
+
+pub async fn read_request<R: AsyncRead + Unpin>(stream: R) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let (start, headers) = read_head(&mut reader).await?;
+
+    let mut parts = start.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method, target),
+        _ => return Err(CuboError::DaemonError(format!("Malformed request line '{}'", start))),
+    };
+
+    let chunked = headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    let body = if chunked {
+        read_chunked(&mut reader).await?
+    } else {
+        let mut body = vec![0u8; content_length(&headers)?];
+        reader.read_exact(&mut body).await?;
+        body
+    };
+    Ok(Request::new(method, target).with_body(body))
+}
+
+/// Read a `Transfer-Encoding: chunked` body (Docker clients send build contexts this way)
+async fn read_chunked<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let size = line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| CuboError::DaemonError(format!("Invalid chunk size '{}'", line.trim())))?;
+        if body.len() + size > MAX_BODY {
+            return Err(CuboError::DaemonError("Chunked body is too large".to_string()));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        // CRLF after the chunk, or the (empty) trailer section after the last one
+        let mut end = String::new();
+        reader.read_line(&mut end).await?;
+        if size == 0 {
+            while !end.trim_end().is_empty() {
+                end.clear();
+                if reader.read_line(&mut end).await? == 0 {
+                    break;
+                }
+            }
+            return Ok(body);
+        }
+    }
+}
+
+pub async fn write_request<W: AsyncWrite + Unpin>(stream: &mut W, request: &Request) -> Result<()> {
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        request.method,
+        request.target(),
+        request.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&request.body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+pub async fn read_response<R: AsyncRead + Unpin>(stream: R) -> Result<Response> {
+    let mut reader = BufReader::new(stream);
+    let (start, headers) = read_head(&mut reader).await?;
+
+    let status = start
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| CuboError::DaemonError(format!("Malformed status line '{}'", start)))?;
+
+    let content_type = headers
+        .get("content-type")
+        .cloned()
+        .unwrap_or_else(|| "application/json".to_string());
+    let mut body = vec![0u8; content_length(&headers)?];
+    reader.read_exact(&mut body).await?;
+    Ok(Response { status, content_type, body })
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(stream: &mut W, response: &Response) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_target() {
+        let (path, query) = split_target("/v1/containers?all=true&force");
+        assert_eq!(path, "/v1/containers");
+        assert_eq!(query.get("all").map(String::as_str), Some("true"));
+        assert_eq!(query.get("force").map(String::as_str), Some(""));
+
+        let (path, query) = split_target("/v1/version");
+        assert_eq!(path, "/v1/version");
+        assert!(query.is_empty());
+    }
+
+    #[test]
+    fn test_split_target_decodes_query() {
+        let (_, query) = split_target("/images/create?fromImage=ghcr.io%2Fowner%2Fapp&tag=1.0+rc&bad=%zz");
+        assert_eq!(query.get("fromImage").map(String::as_str), Some("ghcr.io/owner/app"));
+        assert_eq!(query.get("tag").map(String::as_str), Some("1.0 rc"));
+        assert_eq!(query.get("bad").map(String::as_str), Some("%zz"));
+    }
+
+    #[tokio::test]
+    async fn test_read_request_chunked() {
+        let wire = b"POST /build HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n";
+        let request = read_request(&wire[..]).await.unwrap();
+        assert_eq!(request.body, b"hello world");
+    }
+
+    #[test]
+    fn test_request_flag() {
+        let request = Request::new("GET", "/v1/containers?all=true&force=0");
+        assert!(request.flag("all"));
+        assert!(!request.flag("force"));
+        assert!(!request.flag("missing"));
+    }
+
+    #[tokio::test]
+    async fn test_request_round_trip() {
+        let request = Request::new("POST", "/v1/containers/abc/stop?timeout=5").with_body(b"{}".to_vec());
+        let mut wire = Vec::new();
+        write_request(&mut wire, &request).await.unwrap();
+        assert!(wire.starts_with(b"POST /v1/containers/abc/stop?timeout=5 HTTP/1.1\r\n"));
+
+        let parsed = read_request(wire.as_slice()).await.unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[tokio::test]
+    async fn test_response_round_trip() {
+        let response = Response::json(404, &serde_json::json!({ "message": "gone" }));
+        let mut wire = Vec::new();
+        write_response(&mut wire, &response).await.unwrap();
+        assert!(wire.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+        let parsed = read_response(wire.as_slice()).await.unwrap();
+        assert_eq!(parsed, response);
+    }
+
+    #[tokio::test]
+    async fn test_read_request_malformed() {
+        assert!(read_request(&b"GET /\r\n\r\n"[..]).await.is_err());
+        assert!(read_request(&b""[..]).await.is_err());
+        assert!(read_request(&b"GET / HTTP/1.1\r\nContent-Length: nope\r\n\r\n"[..]).await.is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! `cubod`: a long-running daemon serving container and image operations over a versioned
+//! HTTP API on a unix socket, so every client shares one runtime and its state. A gRPC
+//! API with streaming exec, logs and events is served next to it on a second socket, and
+//! optionally the Kubernetes CRI on a third.
+
+pub mod client;
+pub mod cri;
+pub mod docker;
+pub mod grpc;
+pub mod http;
+
+use crate::commands::run::validate_config;
+use crate::container::admission::AdmissionHook;
+use crate::container::audit::{AuditLog, AuditOperation, AuditRecord};
+use crate::container::image_store::ImageStore;
+use crate::container::events::EventKind;
+use crate::container::layer_cache::LayerCache;
+use crate::container::registry::{PullProgress, RegistryClient};
+use crate::container::stats;
+use crate::container::runtime::{ContainerRuntime, RuntimeConfig};
+use crate::container::Container;
+use crate::error::{CuboError, Result};
+use http::{Request, Response};
+use serde_json::json;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// Every route is prefixed with the API version, e.g. `/v1/containers`
+pub const API_VERSION: &str = "v1";
+pub const SOCKET_NAME: &str = "cubo.sock";
+pub const GRPC_SOCKET_NAME: &str = "cubo-grpc.sock";
+pub const CRI_SOCKET_NAME: &str = "cubo-cri.sock";
+
+/// Grace period for open gRPC streams (event watchers, followed logs) when shutting down
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// `$XDG_RUNTIME_DIR/cubo.sock`, or `/run/cubo.sock` when there is no runtime dir (root)
+pub fn default_socket_path() -> PathBuf {
+    runtime_dir().join(SOCKET_NAME)
+}
+
+/// `$XDG_RUNTIME_DIR/cubo-grpc.sock`, or `/run/cubo-grpc.sock` when there is no runtime dir
+pub fn default_grpc_socket_path() -> PathBuf {
+    runtime_dir().join(GRPC_SOCKET_NAME)
+}
+
+/// `$XDG_RUNTIME_DIR/cubo-cri.sock`, or `/run/cubo-cri.sock` when there is no runtime dir
+pub fn default_cri_socket_path() -> PathBuf {
+    runtime_dir().join(CRI_SOCKET_NAME)
+}
+
+fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/run"))
+}
+
+#[derive(Clone)]
+pub struct Daemon {
+    runtime: ContainerRuntime,
+    root_dir: PathBuf,
+    cri: Option<cri::CriConfig>,
+}
+
+impl Daemon {
+    pub fn new(config: RuntimeConfig) -> Result<Self> {
+        Ok(Self {
+            runtime: ContainerRuntime::new(config.clone())?,
+            root_dir: config.root_dir,
+            cri: None,
+        })
+    }
+
+    // Also serve the Kubernetes CRI
+    pub fn with_cri(mut self, config: cri::CriConfig) -> Self {
+        self.cri = Some(config);
+        self
+    }
+
+    /// Consult `hook` before the pulls and container creations of every API
+    pub fn with_admission_hook(mut self, hook: Arc<dyn AdmissionHook>) -> Self {
+        self.runtime = self.runtime.with_admission_hook(hook);
+        self
+    }
+
+    /// Serve requests on `socket` (and gRPC on `grpc_socket`) until SIGINT or SIGTERM
+    pub async fn serve(self, socket: &Path, grpc_socket: Option<&Path>) -> Result<()> {
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let shutdown = async move {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+        };
+        self.serve_until(socket, grpc_socket, shutdown).await
+    }
+
+    /// Serve requests on `socket` (gRPC on `grpc_socket`, and the CRI if configured) until
+    /// `shutdown` completes, then remove the sockets
+    pub async fn serve_until(
+        self,
+        socket: &Path,
+        grpc_socket: Option<&Path>,
+        shutdown: impl std::future::Future<Output = ()>,
+    ) -> Result<()> {
+        match self.runtime.collect_garbage().await {
+            Ok(removed) if !removed.is_empty() => {
+                info!("Removed {} exited containers past cleanup.exited_ttl", removed.len())
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to collect exited containers: {}", e),
+        }
+        match self.image_store().and_then(|store| LayerCache::new(&store).prune(&store)) {
+            Ok(removed) if removed > 0 => info!("Removed {} extracted layers no image uses", removed),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to collect extracted layers: {}", e),
+        }
+
+        let listener = bind(socket)?;
+        info!("Listening on {}", socket.display());
+
+        // The gRPC servers share one shutdown signal; dropping the sender stops them too
+        let (stop_servers, _) = watch::channel(false);
+        let stopped = || {
+            let mut receiver = stop_servers.subscribe();
+            async move {
+                let _ = receiver.changed().await;
+            }
+        };
+        let mut servers = Vec::new();
+        if let Some(grpc_socket) = grpc_socket {
+            let listener = bind(grpc_socket)?;
+            info!("Serving gRPC on {}", grpc_socket.display());
+            let server = tokio::spawn(grpc::serve(self.clone(), listener, stopped()));
+            servers.push((grpc_socket.to_path_buf(), server));
+        }
+        if let Some(ref config) = self.cri {
+            let listener = bind(&config.socket)?;
+            info!("Serving the CRI on {}", config.socket.display());
+            let server = tokio::spawn(cri::serve(self.clone(), listener, stopped()));
+            servers.push((config.socket.clone(), server));
+        }
+
+        let recorder = self.runtime.config().stats_interval.and_then(|interval| interval.to_std().ok()).map(|interval| {
+            info!("Recording container stats every {:?}", interval);
+            tokio::spawn(stats::record_every(self.runtime.clone(), interval))
+        });
+
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        let daemon = self.clone();
+                        tokio::spawn(async move { daemon.handle_connection(stream).await });
+                    }
+                    Err(e) => warn!("Failed to accept connection: {}", e),
+                },
+                _ = &mut shutdown => break,
+            }
+        }
+
+        info!("Shutting down");
+        if let Some(recorder) = recorder {
+            recorder.abort();
+        }
+        let _ = fs::remove_file(socket);
+        let _ = stop_servers.send(true);
+        for (server_socket, mut server) in servers {
+            match tokio::time::timeout(SHUTDOWN_GRACE, &mut server).await {
+                Ok(Ok(result)) => result?,
+                Ok(Err(e)) => warn!("gRPC server task failed: {}", e),
+                Err(_) => {
+                    warn!("gRPC streams still open after {:?}; closing them", SHUTDOWN_GRACE);
+                    server.abort();
+                }
+            }
+            let _ = fs::remove_file(server_socket);
+        }
+        Ok(())
+    }
+
+    async fn handle_connection(&self, stream: UnixStream) {
+        let (read, mut write) = stream.into_split();
+        let response = match http::read_request(read).await {
+            Ok(request) => self.handle(request).await,
+            Err(e) => error_response(&e),
+        };
+        if let Err(e) = http::write_response(&mut write, &response).await {
+            warn!("Failed to send response: {}", e);
+        }
+    }
+
+    pub async fn handle(&self, request: Request) -> Response {
+        match self.route(&request).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("{} {} failed: {}", request.method, request.path, e);
+                error_response(&e)
+            }
+        }
+    }
+
+    async fn route(&self, request: &Request) -> Result<Response> {
+        let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+        let route = match segments.split_first() {
+            Some((version, rest)) if *version == API_VERSION => rest,
+            _ => return docker::route(self, request).await.unwrap_or_else(|| Ok(not_found(request))),
+        };
+
+        match (request.method.as_str(), route) {
+            ("GET", ["version"]) => Ok(Response::json(200, &json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "api_version": API_VERSION,
+            }))),
+            ("GET", ["containers"]) => {
+                let containers = self.runtime.list_containers(request.flag("all")).await?;
+                Ok(Response::json(200, &to_json(&containers)?))
+            }
+            ("POST", ["containers"]) => self.create_container(&request.body).await,
+            ("GET", ["containers", identifier]) => {
+                let id = self.runtime.resolve(identifier).await?;
+                let container = self.runtime.get_container(&id).await?;
+                Ok(Response::json(200, &to_json(&container)?))
+            }
+            ("DELETE", ["containers", identifier]) => {
+                let id = self.runtime.resolve(identifier).await?;
+                self.runtime.remove_container(&id, request.flag("force")).await?;
+                Ok(Response::json(200, &json!({ "id": id })))
+            }
+            ("POST", ["containers", identifier, "start"]) => {
+                let id = self.runtime.resolve(identifier).await?;
+                self.runtime.start_container(&id, true).await?;
+                Ok(Response::json(200, &json!({ "id": id })))
+            }
+            ("POST", ["containers", identifier, "stop"]) => {
+                let id = self.runtime.resolve(identifier).await?;
+                self.runtime.stop_container(&id, parse_timeout(request)?).await?;
+                Ok(Response::json(200, &json!({ "id": id })))
+            }
+            ("GET", ["images"]) => {
+                let images = self.image_store()?.list_images()?;
+                Ok(Response::json(200, &json!(images)))
+            }
+            ("POST", ["images", "pull"]) => {
+                let reference = request.query.get("reference").ok_or_else(|| {
+                    CuboError::InvalidConfiguration("Missing 'reference' query parameter".to_string())
+                })?;
+                self.pull(reference, &|_| {}).await?;
+                Ok(Response::json(200, &json!({ "reference": reference })))
+            }
+            _ => Ok(not_found(request)),
+        }
+    }
+
+    async fn create_container(&self, body: &[u8]) -> Result<Response> {
+        let container: Container = serde_json::from_slice(body)
+            .map_err(|e| CuboError::InvalidConfiguration(format!("Invalid container: {}", e)))?;
+        validate_config(&container.config)?;
+        if self.runtime.get_container(&container.id).await.is_ok() {
+            return Err(CuboError::ContainerAlreadyExists(container.id));
+        }
+
+        let id = self.runtime.create_container(container).await?;
+        Ok(Response::json(201, &json!({ "id": id })))
+    }
+
+    /// Pull an image and tell event watchers about it
+    async fn pull(&self, reference: &str, progress: &(dyn Fn(PullProgress) + Send + Sync)) -> Result<()> {
+        let result = match self.runtime.admit_pull(reference).and_then(|_| self.image_store()) {
+            Ok(store) => RegistryClient::new(&store)
+                .with_config(self.runtime.config())
+                .pull_with_progress(reference, progress).await,
+            Err(e) => Err(e),
+        };
+        AuditLog::new(&self.root_dir).record(AuditRecord::new(AuditOperation::Pull, &result).with_image(reference));
+        result?;
+        self.runtime.events().publish(EventKind::Pull, reference);
+        Ok(())
+    }
+
+    fn image_store(&self) -> Result<ImageStore> {
+        ImageStore::new(self.root_dir.join("images"))
+    }
+}
+
+/// Bind the socket, replacing a stale one left by a daemon that didn't shut down cleanly
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            return Err(CuboError::DaemonError(format!(
+                "Another daemon is already listening on {}",
+                socket.display()
+            )));
+        }
+        fs::remove_file(socket)?;
+    }
+    if let Some(parent) = socket.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let listener = UnixListener::bind(socket)
+        .map_err(|e| CuboError::DaemonError(format!("Failed to bind {}: {}", socket.display(), e)))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+fn parse_timeout(request: &Request) -> Result<Option<Duration>> {
+    request
+        .query
+        .get("timeout")
+        .map(|secs| {
+            secs.parse()
+                .map(Duration::from_secs)
+                .map_err(|_| CuboError::InvalidConfiguration(format!("Invalid timeout '{}'", secs)))
+        })
+        .transpose()
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<serde_json::Value> {
+    serde_json::to_value(value)
+        .map_err(|e| CuboError::SystemError(format!("Failed to serialize response: {}", e)))
+}
+
+fn not_found(request: &Request) -> Response {
+    Response::json(404, &json!({
+        "message": format!("No route for {} {}", request.method, request.path),
+    }))
+}
+
+fn status_for(error: &CuboError) -> u16 {
+    match error {
+        CuboError::ContainerNotFound(_) | CuboError::ImageNotFound { .. } => 404,
+        CuboError::RegistryError { .. } => 502,
+        CuboError::ContainerAlreadyExists(_)
+        | CuboError::ContainerAlreadyRunning(_)
+        | CuboError::ContainerNotRunning(_)
+        | CuboError::PortInUse { .. } => 409,
+        CuboError::InvalidConfiguration(_) | CuboError::DaemonError(_) | CuboError::AmbiguousIdentifier { .. } => 400,
+        CuboError::PermissionDenied(_) => 403,
+        _ => 500,
+    }
+}
+
+fn error_response(error: &CuboError) -> Response {
+    Response::json(status_for(error), &json!({ "message": error.to_string() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn daemon(temp_dir: &TempDir) -> Daemon {
+        Daemon::new(RuntimeConfig {
+            root_dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn body(response: &Response) -> serde_json::Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_default_socket_path_ends_with_socket_name() {
+        assert!(default_socket_path().ends_with(SOCKET_NAME));
+        assert!(default_grpc_socket_path().ends_with(GRPC_SOCKET_NAME));
+        assert!(default_cri_socket_path().ends_with(CRI_SOCKET_NAME));
+    }
+
+    #[test]
+    fn test_status_for() {
+        assert_eq!(status_for(&CuboError::ContainerNotFound("x".into())), 404);
+        assert_eq!(status_for(&CuboError::ContainerAlreadyRunning("x".into())), 409);
+        assert_eq!(status_for(&CuboError::PortInUse { port: "0.0.0.0:80/tcp".into(), container: "x".into() }), 409);
+        assert_eq!(status_for(&CuboError::InvalidConfiguration("x".into())), 400);
+        assert_eq!(status_for(&CuboError::AmbiguousIdentifier { identifier: "x".into(), candidates: vec![] }), 400);
+        assert_eq!(status_for(&CuboError::PermissionDenied("x".into())), 403);
+        assert_eq!(status_for(&CuboError::SystemError("x".into())), 500);
+    }
+
+    #[tokio::test]
+    async fn test_version() {
+        let temp_dir = TempDir::new().unwrap();
+        let response = daemon(&temp_dir).handle(Request::new("GET", "/v1/version")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response)["api_version"], API_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_route() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        assert_eq!(daemon.handle(Request::new("GET", "/v2/containers")).await.status, 404);
+        assert_eq!(daemon.handle(Request::new("PUT", "/v1/containers")).await.status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_create_inspect_and_remove_container() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()])
+            .with_name("api-test".to_string());
+        let request = Request::new("POST", "/v1/containers")
+            .with_body(serde_json::to_vec(&container).unwrap());
+
+        let response = daemon.handle(request.clone()).await;
+        assert_eq!(response.status, 201);
+        assert_eq!(body(&response)["id"], container.id.as_str());
+        assert_eq!(daemon.handle(request).await.status, 409);
+
+        let response = daemon.handle(Request::new("GET", "/v1/containers/api-test")).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response)["id"], container.id.as_str());
+
+        let response = daemon.handle(Request::new("GET", "/v1/containers?all=true")).await;
+        assert_eq!(body(&response).as_array().unwrap().len(), 1);
+        let response = daemon.handle(Request::new("GET", "/v1/containers")).await;
+        assert!(body(&response).as_array().unwrap().is_empty());
+
+        let response = daemon.handle(Request::new("DELETE", "/v1/containers/api-test")).await;
+        assert_eq!(response.status, 200);
+        let response = daemon.handle(Request::new("GET", "/v1/containers/api-test")).await;
+        assert_eq!(response.status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_create_container_invalid_body() {
+        let temp_dir = TempDir::new().unwrap();
+        let request = Request::new("POST", "/v1/containers").with_body(b"{\"image\":".to_vec());
+        let response = daemon(&temp_dir).handle(request).await;
+        assert_eq!(response.status, 400);
+        assert!(body(&response)["message"].as_str().unwrap().contains("Invalid container"));
+    }
+
+    #[tokio::test]
+    async fn test_stop_invalid_timeout() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(&temp_dir);
+        let container = Container::new("test:latest".to_string(), vec!["echo".to_string()]);
+        let id = daemon.runtime.create_container(container).await.unwrap();
+
+        let request = Request::new("POST", &format!("/v1/containers/{}/stop?timeout=soon", id));
+        assert_eq!(daemon.handle(request).await.status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_pull_requires_reference() {
+        let temp_dir = TempDir::new().unwrap();
+        let response = daemon(&temp_dir).handle(Request::new("POST", "/v1/images/pull")).await;
+        assert_eq!(response.status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_list_images_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let response = daemon(&temp_dir).handle(Request::new("GET", "/v1/images")).await;
+        assert_eq!(response.status, 200);
+        assert!(body(&response).as_array().unwrap().is_empty());
+    }
+}
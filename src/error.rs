@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 
 #[allow(dead_code)]
@@ -6,8 +8,25 @@ pub enum CuboError {
     #[error("Container not found: {0}")]
     ContainerNotFound(String),
 
-    #[error("Blueprint not found: {0}")]
-    BlueprintNotFound(String),
+    #[error("{identifier} matches several containers: {}", candidates.join(", "))]
+    AmbiguousIdentifier { identifier: String, candidates: Vec<String> },
+
+    #[error("Image not found: {reference}")]
+    ImageNotFound { reference: String },
+
+    #[error("Registry {registry} failed{}: {message}", status.map(|s| format!(" with HTTP {}", s)).unwrap_or_default())]
+    RegistryError {
+        registry: String,
+        /// HTTP status of the response, None when there was no response
+        status: Option<u16>,
+        message: String,
+    },
+
+    #[error("Image {reference} is not in the local store and pulling is disabled (--offline or pull_policy = \"never\")")]
+    PullDisabled { reference: String },
+
+    #[error("Failed to extract layer {}: {message}", layer.display())]
+    LayerExtractionError { layer: PathBuf, message: String },
 
     #[error("Container already exists: {0}")]
     ContainerAlreadyExists(String),
@@ -18,12 +37,24 @@ pub enum CuboError {
     #[error("Container is already running: {0}")]
     ContainerAlreadyRunning(String),
 
+    #[error("Host port {port} is already published by container {container}")]
+    PortInUse { port: String, container: String },
+
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
 
+    #[error("Admission hook {hook} denied the {operation}: {reason}")]
+    AdmissionDenied { hook: String, operation: String, reason: String },
+
     #[error("Invalid configuration: {0}")]
     InvalidConfiguration(String),
 
+    #[error("Invalid image reference \"{reference}\": {reason}")]
+    InvalidImageReference { reference: String, reason: String },
+
+    #[error("Invalid container name \"{0}\": only [a-zA-Z0-9][a-zA-Z0-9_.-]+ is allowed")]
+    InvalidContainerName(String),
+
     #[error("System error: {0}")]
     SystemError(String),
 
@@ -39,6 +70,21 @@ pub enum CuboError {
     #[error("Process error: {0}")]
     ProcessError(String),
 
+    #[error("Cgroup error at {}: {message}", path.display())]
+    CgroupError { path: PathBuf, message: String },
+
+    #[error("Daemon error: {0}")]
+    DaemonError(String),
+
+    #[error("Command not found: {0}")]
+    CommandNotFound(String),
+
+    #[error("Command cannot be invoked: {command}: {reason}")]
+    CommandNotInvocable { command: String, reason: String },
+
+    #[error("{path} not found in the container, so {command} can't run (set --entrypoint to a program the image has)")]
+    MissingInterpreter { path: String, command: String },
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -46,6 +92,28 @@ pub enum CuboError {
     UuidError(#[from] uuid::Error),
 }
 
+impl CuboError {
+    /// Exit status of a cubo command failing with this error, as docker has it: 127 when the
+    /// container's command doesn't exist, 126 when it exists but can't be run, 125 otherwise
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CuboError::CommandNotFound(_) | CuboError::MissingInterpreter { .. } => 127,
+            CuboError::CommandNotInvocable { .. } => 126,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+/// Exit status of cubo itself failing, as opposed to the container's command
+pub const EXIT_FAILURE: i32 = 125;
+
+/// Exit status a shell would give a process that ended with `status`: its exit code, or 128
+/// plus the signal that killed it
+pub fn exit_status_code(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status.code().or_else(|| status.signal().map(|signal| 128 + signal)).unwrap_or(EXIT_FAILURE)
+}
+
 #[allow(dead_code)]
 pub type Result<T> = std::result::Result<T, CuboError>;
 
@@ -61,9 +129,44 @@ mod tests {
     }
 
     #[test]
-    fn test_blueprint_not_found_display() {
-        let err = CuboError::BlueprintNotFound("alpine:latest".to_string());
-        assert_eq!(err.to_string(), "Blueprint not found: alpine:latest");
+    fn test_ambiguous_identifier_display() {
+        let err = CuboError::AmbiguousIdentifier {
+            identifier: "3f".to_string(),
+            candidates: vec!["3f2a1b9c0d1e (web)".to_string(), "3f9d8e7c6b5a".to_string()],
+        };
+        assert_eq!(err.to_string(), "3f matches several containers: 3f2a1b9c0d1e (web), 3f9d8e7c6b5a");
+    }
+
+    #[test]
+    fn test_image_not_found_display() {
+        let err = CuboError::ImageNotFound { reference: "alpine:latest".to_string() };
+        assert_eq!(err.to_string(), "Image not found: alpine:latest");
+    }
+
+    #[test]
+    fn test_registry_error_display() {
+        let err = CuboError::RegistryError {
+            registry: "ghcr.io".to_string(),
+            status: Some(401),
+            message: "Failed to fetch manifest".to_string(),
+        };
+        assert_eq!(err.to_string(), "Registry ghcr.io failed with HTTP 401: Failed to fetch manifest");
+
+        let err = CuboError::RegistryError {
+            registry: "ghcr.io".to_string(),
+            status: None,
+            message: "connection refused".to_string(),
+        };
+        assert_eq!(err.to_string(), "Registry ghcr.io failed: connection refused");
+    }
+
+    #[test]
+    fn test_layer_extraction_error_display() {
+        let err = CuboError::LayerExtractionError {
+            layer: PathBuf::from("/blobs/0.tar"),
+            message: "unexpected EOF".to_string(),
+        };
+        assert_eq!(err.to_string(), "Failed to extract layer /blobs/0.tar: unexpected EOF");
     }
 
     #[test]
@@ -120,6 +223,43 @@ mod tests {
         assert_eq!(err.to_string(), "Process error: exec failed");
     }
 
+    #[test]
+    fn test_cgroup_error_display() {
+        let err = CuboError::CgroupError {
+            path: PathBuf::from("/sys/fs/cgroup/cubo/c1/pids.max"),
+            message: "Failed to write: Permission denied".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Cgroup error at /sys/fs/cgroup/cubo/c1/pids.max: Failed to write: Permission denied"
+        );
+    }
+
+    #[test]
+    fn test_daemon_error_display() {
+        let err = CuboError::DaemonError("connection refused".to_string());
+        assert_eq!(err.to_string(), "Daemon error: connection refused");
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(CuboError::CommandNotFound("nope".to_string()).exit_code(), 127);
+        let err = CuboError::CommandNotInvocable { command: "/etc/passwd".to_string(), reason: "not executable".to_string() };
+        assert_eq!(err.exit_code(), 126);
+        let err = CuboError::MissingInterpreter { path: "/bin/sh".to_string(), command: "app".to_string() };
+        assert_eq!(err.exit_code(), 127);
+        assert_eq!(CuboError::ImageNotFound { reference: "x".to_string() }.exit_code(), 125);
+        assert_eq!(CuboError::SystemError("x".to_string()).exit_code(), 125);
+    }
+
+    #[test]
+    fn test_exit_status_code() {
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(exit_status_code(std::process::ExitStatus::from_raw(3 << 8)), 3);
+        // Killed by SIGKILL
+        assert_eq!(exit_status_code(std::process::ExitStatus::from_raw(9)), 137);
+    }
+
     #[test]
     fn test_io_error_from_conversion() {
         let io_err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
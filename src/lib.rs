@@ -6,6 +6,8 @@
 pub mod error;
 pub mod commands;
 pub mod cli;
+pub mod config;
 pub mod container;
+pub mod daemon;
 
 pub use error::{CuboError, Result};
\ No newline at end of file
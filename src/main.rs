@@ -1,31 +1,96 @@
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+use tracing_subscriber::EnvFilter;
 
 use cubo::cli::{self, Cli};
 use cubo::commands;
+use cubo::commands::context::CuboContext;
+use cubo::container::build_progress::ProgressMode;
+use cubo::error::Result;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Logging
-    tracing_subscriber::fmt::init();
+async fn main() {
+    // Shells call back in with $COMPLETE set to get candidates; nothing may be printed before
+    CompleteEnv::with_factory(Cli::command).var(commands::completion::COMPLETE_VAR).complete();
+
+    // Logging, on stderr so stdout only carries command output
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
 
     let cli: Cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-    if let Some(ref root) = cli.root_dir {
-        std::env::set_var("CUBO_ROOT", root);
+async fn run(cli: Cli) -> Result<()> {
+    let ctx = CuboContext::init(&cli);
+
+    // Completion scripts, man pages, inspect output, generated units, an SBOM on stdout, audit
+    // records as JSON, exported stats, JSON build progress and the ID of a detached container
+    // are read by other programs
+    let machine_output = match cli.command {
+        cli::Commands::Completion(_)
+        | cli::Commands::Man(_)
+        | cli::Commands::Inspect(_)
+        | cli::Commands::Generate(_) => true,
+        cli::Commands::Image(cli::ImageArgs { command: cli::ImageCommands::Sbom(ref args) }) => {
+            args.output.is_none()
+        }
+        cli::Commands::Audit(ref args) => args.json,
+        cli::Commands::Stats(ref args) => args.export.is_some(),
+        cli::Commands::Build(ref args) => args.progress == ProgressMode::Json,
+        cli::Commands::Run(ref args) => !args.interactive,
+        _ => false,
+    };
+    if !machine_output {
+        println!("Cubo containerization tool");
     }
 
-    println!("Cubo containerization tool");
+    if cli.remote {
+        commands::remote::execute(&ctx, cli.socket.as_deref(), cli.command).await?;
+        return Ok(());
+    }
     
     match cli.command {
-        cli::Commands::Run(args) => commands::run::execute(args).await?,
-        cli::Commands::Build(args) => commands::build::execute(args).await?,
-        cli::Commands::Ps(args) => commands::ps::execute(args).await?,
+        cli::Commands::Run(args) => commands::run::execute(&ctx, *args).await?,
+        cli::Commands::Build(args) => commands::build::execute(&ctx, args).await?,
+        cli::Commands::Ps(args) => commands::ps::execute(&ctx, args).await?,
         cli::Commands::Blueprint(args) => commands::blueprints::execute(args).await?,
-        cli::Commands::Stop(args) => commands::stop::execute(args).await?,
-        cli::Commands::Rm(args) => commands::rm::execute(args).await?,
-        cli::Commands::Pull(args) => commands::pull::execute(args).await?,
-        cli::Commands::Logs(args) => commands::logs::execute(args).await?,
+        cli::Commands::Stop(args) => commands::stop::execute(&ctx, args).await?,
+        cli::Commands::Update(args) => commands::update::execute(&ctx, args).await?,
+        cli::Commands::Checkpoint(args) => commands::checkpoint::execute(&ctx, args).await?,
+        cli::Commands::Restore(args) => commands::restore::execute(&ctx, args).await?,
+        cli::Commands::Rm(args) => commands::rm::execute(&ctx, args).await?,
+        cli::Commands::Pull(args) => commands::pull::execute(&ctx, args).await?,
+        cli::Commands::Logs(args) => commands::logs::execute(&ctx, args).await?,
+        cli::Commands::Network(args) => commands::network::execute(&ctx, args).await?,
+        cli::Commands::Volume(args) => commands::volume::execute(&ctx, args).await?,
+        cli::Commands::Compose(args) => commands::compose::execute(&ctx, args).await?,
+        cli::Commands::Generate(args) => commands::generate::execute(&ctx, args).await?,
+        cli::Commands::ExportBundle(args) => commands::export_bundle::execute(&ctx, args).await?,
+        cli::Commands::System(args) => commands::system::execute(&ctx, args).await?,
+        cli::Commands::Image(args) => commands::image::execute(&ctx, args).await?,
+        cli::Commands::Images(args) => commands::image::list(&ctx, args)?,
+        cli::Commands::Tag(args) => commands::image::tag(&ctx, args)?,
+        cli::Commands::Doctor => commands::doctor::execute(&ctx).await?,
+        cli::Commands::Completion(args) => commands::completion::execute(args).await?,
+        cli::Commands::Man(args) => commands::completion::man(args).await?,
+        cli::Commands::Audit(args) => commands::audit::execute(&ctx, args).await?,
+        cli::Commands::Inspect(args) => commands::inspect::execute(&ctx, args).await?,
+        cli::Commands::Container(args) => commands::container::execute(&ctx, args).await?,
+        cli::Commands::Gc => commands::container::gc(&ctx).await?,
+        cli::Commands::Debug(args) => commands::debug::execute(&ctx, args).await?,
+        cli::Commands::Enter(args) => commands::enter::execute(&ctx, args).await?,
+        cli::Commands::Dev(args) => commands::dev::execute(&ctx, args).await?,
+        cli::Commands::Wait(args) => commands::wait::execute(&ctx, args).await?,
+        cli::Commands::Stats(args) => commands::stats::execute(&ctx, args).await?,
+        cli::Commands::Port(args) => commands::port::execute(&ctx, args).await?,
+        cli::Commands::Supervise(args) => commands::supervise::execute(&ctx, args).await?,
     }
 
     Ok(())
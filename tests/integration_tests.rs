@@ -90,13 +90,18 @@ fn test_image_store_import_and_retrieve() {
     // save manifest
     let manifest = ImageManifest {
         reference: "integration:test".to_string(),
+        parent: None,
         layers: vec!["layer1.tar".to_string()],
         config: ImageConfig {
             cmd: Some(vec!["/bin/sh".to_string()]),
             env: Some(vec!["PATH=/bin".to_string()]),
             working_dir: Some("/".to_string()),
             exposed_ports: None,
+            stop_signal: None,
+            labels: None,
         },
+        annotations: None,
+        repo_digest: None,
     };
 
     store.save_manifest(&manifest).unwrap();
@@ -128,13 +133,18 @@ fn test_image_store_list_multiple() {
     for img in &images {
         let manifest = ImageManifest {
             reference: img.to_string(),
+            parent: None,
             layers: vec![],
             config: ImageConfig {
                 cmd: None,
                 env: None,
                 working_dir: None,
                 exposed_ports: None,
+                stop_signal: None,
+                labels: None,
             },
+            annotations: None,
+            repo_digest: None,
         };
         store.save_manifest(&manifest).unwrap();
     }